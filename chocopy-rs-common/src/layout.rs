@@ -0,0 +1,151 @@
+// The layout constants are baked into generated code by the compiler, while the runtime uses the
+// structs directly. `Layout` gathers both views so that tests can check that they agree, within
+// one build and between the compiler and the runtime it links with.
+use super::*;
+use std::mem::{offset_of, size_of};
+
+// Number of members of a struct, which are all named so that a new one fails to build until listed
+macro_rules! member_count {
+    ($struct:ident { $($member:ident),* }) => {{
+        let _ = |value: &$struct| {
+            let $struct { $($member: _),* } = value;
+        };
+        [$(stringify!($member)),*].len()
+    }};
+}
+
+macro_rules! layout {
+    ($($field:ident: $constant:ident = $actual:expr,)*) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(C)]
+        pub struct Layout {
+            $(pub $field: u32,)*
+        }
+
+        impl Layout {
+            // The layout as the compiler sees it
+            pub const fn constants() -> Layout {
+                Layout {
+                    $($field: $constant,)*
+                }
+            }
+
+            // The layout of the structs in this build
+            pub fn structs() -> Layout {
+                Layout {
+                    $($field: ($actual) as u32,)*
+                }
+            }
+
+            // The layout from its values in the order of the fields, if there are as many
+            pub fn from_values(values: &[u32]) -> Option<Layout> {
+                let mut values = values.iter().copied();
+                let layout = Layout {
+                    $($field: values.next()?,)*
+                };
+                match values.next() {
+                    None => Some(layout),
+                    Some(_) => None,
+                }
+            }
+
+            // Constants that have another value in the other layout
+            pub fn mismatches(&self, other: &Layout) -> Vec<String> {
+                let mut mismatches = vec![];
+                $(if self.$field != other.$field {
+                    mismatches.push(format!(
+                        "{} is {} but {} in the other layout",
+                        stringify!($constant),
+                        self.$field,
+                        other.$field
+                    ));
+                })*
+                mismatches
+            }
+        }
+    };
+}
+
+layout! {
+    pointer_size: POINTER_SIZE = size_of::<*const u8>(),
+    function_pointer_size: FUNCTION_POINTER_SIZE = size_of::<extern "C" fn()>(),
+
+    prototype_size_offset: PROTOTYPE_SIZE_OFFSET = offset_of!(Prototype, size),
+    prototype_tag_offset: PROTOTYPE_TAG_OFFSET = offset_of!(Prototype, tag),
+    prototype_map_offset: PROTOTYPE_MAP_OFFSET = offset_of!(Prototype, map),
+    // Method pointers follow the members of the struct
+    prototype_init_offset: PROTOTYPE_INIT_OFFSET = size_of::<Prototype>(),
+    prototype_str_offset: PROTOTYPE_STR_OFFSET =
+        size_of::<Prototype>() + size_of::<extern "C" fn()>(),
+    object_prototype_size: OBJECT_PROTOTYPE_SIZE =
+        size_of::<Prototype>() + 2 * size_of::<extern "C" fn()>(),
+    prototype_header_member_count: PROTOTYPE_HEADER_MEMBER_COUNT =
+        member_count!(Prototype { size, tag, map }),
+
+    object_prototype_offset: OBJECT_PROTOTYPE_OFFSET = offset_of!(Object, prototype),
+    object_gc_count_offset: OBJECT_GC_COUNT_OFFSET = offset_of!(Object, gc_count),
+    object_gc_next_offset: OBJECT_GC_NEXT_OFFSET = offset_of!(Object, gc_next),
+    object_attribute_offset: OBJECT_ATTRIBUTE_OFFSET = size_of::<Object>(),
+    object_header_member_count: OBJECT_HEADER_MEMBER_COUNT =
+        member_count!(Object { prototype, gc_count, gc_next }),
+
+    array_len_offset: ARRAY_LEN_OFFSET = offset_of!(ArrayObject, len),
+    array_element_offset: ARRAY_ELEMENT_OFFSET = size_of::<ArrayObject>(),
+
+    bottom_frame_offset: BOTTOM_FRAME_OFFSET = offset_of!(InitParam, bottom_frame),
+    global_section_offset: GLOBAL_SECTION_OFFSET = offset_of!(InitParam, global_section),
+    global_size_offset: GLOBAL_SIZE_OFFSET = offset_of!(InitParam, global_size),
+    global_map_offset: GLOBAL_MAP_OFFSET = offset_of!(InitParam, global_map),
+    str_prototype_offset: STR_PROTOTYPE_OFFSET = offset_of!(InitParam, str_prototype),
+    init_param_size: INIT_PARAM_SIZE = size_of::<InitParam>(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::align_of;
+
+    #[test]
+    fn constants_match_structs() {
+        let mismatches = Layout::constants().mismatches(&Layout::structs());
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn alignment() {
+        // Generated code reads and writes whole words at these offsets
+        for &(name, offset) in &[
+            ("PROTOTYPE_MAP_OFFSET", PROTOTYPE_MAP_OFFSET),
+            ("PROTOTYPE_INIT_OFFSET", PROTOTYPE_INIT_OFFSET),
+            ("OBJECT_ATTRIBUTE_OFFSET", OBJECT_ATTRIBUTE_OFFSET),
+            ("ARRAY_LEN_OFFSET", ARRAY_LEN_OFFSET),
+            ("ARRAY_ELEMENT_OFFSET", ARRAY_ELEMENT_OFFSET),
+        ] {
+            assert_eq!(offset % 8, 0, "{} is not aligned to 8 bytes", name);
+        }
+        assert_eq!(align_of::<Prototype>(), 8, "Prototype");
+        assert_eq!(align_of::<Object>(), 8, "Object");
+        assert_eq!(align_of::<ArrayObject>(), 8, "ArrayObject");
+        assert_eq!(size_of::<TypeTag>(), 4, "TypeTag");
+    }
+
+    #[test]
+    fn mismatch_names_constant() {
+        let constants = Layout::constants();
+        let values = [constants.pointer_size, constants.function_pointer_size];
+        assert_eq!(Layout::from_values(&values), None);
+
+        let drifted = Layout {
+            array_element_offset: ARRAY_ELEMENT_OFFSET + 8,
+            ..constants
+        };
+        assert_eq!(
+            constants.mismatches(&drifted),
+            [format!(
+                "ARRAY_ELEMENT_OFFSET is {} but {} in the other layout",
+                ARRAY_ELEMENT_OFFSET,
+                ARRAY_ELEMENT_OFFSET + 8
+            )]
+        );
+    }
+}
@@ -1,5 +1,9 @@
 use std::ptr::*;
 
+pub mod layout;
+pub mod random;
+pub mod temp;
+
 pub const POINTER_SIZE: u32 = 8;
 pub const FUNCTION_POINTER_SIZE: u32 = 8;
 
@@ -27,7 +31,9 @@ pub const PROTOTYPE_SIZE_OFFSET: u32 = 0;
 pub const PROTOTYPE_TAG_OFFSET: u32 = PROTOTYPE_SIZE_OFFSET + 4;
 pub const PROTOTYPE_MAP_OFFSET: u32 = PROTOTYPE_TAG_OFFSET + 4;
 pub const PROTOTYPE_INIT_OFFSET: u32 = PROTOTYPE_MAP_OFFSET + FUNCTION_POINTER_SIZE;
-pub const OBJECT_PROTOTYPE_SIZE: u32 = PROTOTYPE_INIT_OFFSET + FUNCTION_POINTER_SIZE;
+// Reserved for `__str__`, which is called by `print`. Null if the class doesn't define it
+pub const PROTOTYPE_STR_OFFSET: u32 = PROTOTYPE_INIT_OFFSET + FUNCTION_POINTER_SIZE;
+pub const OBJECT_PROTOTYPE_SIZE: u32 = PROTOTYPE_STR_OFFSET + FUNCTION_POINTER_SIZE;
 pub const PROTOTYPE_HEADER_MEMBER_COUNT: u32 = 3;
 
 #[repr(C)]
@@ -68,3 +74,7 @@ pub const GLOBAL_SIZE_OFFSET: u32 = GLOBAL_SECTION_OFFSET + POINTER_SIZE;
 pub const GLOBAL_MAP_OFFSET: u32 = GLOBAL_SIZE_OFFSET + 8;
 pub const STR_PROTOTYPE_OFFSET: u32 = GLOBAL_MAP_OFFSET + POINTER_SIZE;
 pub const INIT_PARAM_SIZE: u32 = std::mem::size_of::<InitParam>() as u32;
+
+// Objects nested deeper than this are compared by identity in the class equality extension,
+// so that comparing cycles of objects ends
+pub const OBJ_EQ_MAX_DEPTH: u32 = 64;
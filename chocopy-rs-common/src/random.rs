@@ -0,0 +1,47 @@
+// Random numbers for the built-in functions `seed` and `randint`, shared by the runtime and the
+// interpreter so that both give the same numbers
+
+// A program that never calls `seed` gets the same numbers as after `seed(0)`
+pub const DEFAULT_SEED: i32 = 0;
+
+// xorshift64* generator
+#[derive(Clone, Copy, Debug)]
+pub struct Random {
+    state: u64, // Never zero
+}
+
+impl Random {
+    pub const fn new(seed: i32) -> Random {
+        // SplitMix64 spreads close seeds apart
+        let mut z = (seed as i64 as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Random {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // A uniformly distributed integer in [lo, hi]. lo must not be greater than hi.
+    pub fn int(&mut self, lo: i32, hi: i32) -> i32 {
+        assert!(lo <= hi);
+        let range = (hi as i64 - lo as i64 + 1) as u64;
+        // Values at the top that would make some results more likely are drawn again
+        let zone = u64::MAX / range * range;
+        loop {
+            let value = self.next();
+            if value < zone {
+                return (lo as i64 + (value % range) as i64) as i32;
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Paths of temporary files.
+//
+// Names come from a small deterministic generator, so that the same seed reproduces the same names.
+// The seed is given with `seed`, or in the CHOCOPY_SEED environment variable. Without either,
+// it is taken from the time and the process ID. Files are made with `create`, which skips names
+// that already exist, so that concurrent runs with the same seed don't share files either.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const SEED_VAR: &str = "CHOCOPY_SEED";
+
+// Sequence of file names from the SplitMix64 generator
+pub struct TempNames {
+    state: u64,
+}
+
+impl TempNames {
+    pub fn new(seed: u64) -> TempNames {
+        TempNames { state: seed }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) >> 32) as u32
+    }
+
+    // "chocopy-N" followed by the extension
+    pub fn next_name(&mut self, extension: &str) -> String {
+        format!("chocopy-{}{}", self.next_u32(), extension)
+    }
+}
+
+// The seed from the environment variable if it is set to a number, or from the time otherwise
+fn default_seed() -> u64 {
+    if let Some(seed) = std::env::var(SEED_VAR).ok().and_then(|s| s.parse().ok()) {
+        return seed;
+    }
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    time ^ (u64::from(std::process::id()) << 32)
+}
+
+static NAMES: Mutex<Option<TempNames>> = Mutex::new(None);
+
+// Restart the names returned by `temp_path` from the seed
+pub fn seed(seed: u64) {
+    *NAMES.lock().unwrap() = Some(TempNames::new(seed));
+}
+
+fn next_name(extension: &str) -> String {
+    NAMES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| TempNames::new(default_seed()))
+        .next_name(extension)
+}
+
+// Path in the temporary directory with the next name
+pub fn temp_path(extension: &str) -> PathBuf {
+    std::env::temp_dir().join(next_name(extension))
+}
+
+// Names tried before giving up, in case the directory can't be written at all
+const CREATE_ATTEMPTS: u32 = 100;
+
+// Create a new empty file in the directory with the next name that is not taken yet. The name
+// stays reserved for the caller as long as the file exists
+pub fn create_in(dir: &Path, extension: &str) -> std::io::Result<(PathBuf, File)> {
+    let mut attempts = 0;
+    loop {
+        let path = dir.join(next_name(extension));
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                attempts += 1;
+                if attempts == CREATE_ATTEMPTS {
+                    return Err(e);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Create a new empty file in the temporary directory
+pub fn create(extension: &str) -> std::io::Result<(PathBuf, File)> {
+    create_in(&std::env::temp_dir(), extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(seed: u64) -> Vec<String> {
+        let mut names = TempNames::new(seed);
+        (0..4).map(|_| names.next_name(".o")).collect()
+    }
+
+    #[test]
+    fn same_seed() {
+        assert_eq!(names(42), names(42));
+        assert_ne!(names(42), names(43));
+
+        let names = names(0);
+        assert!(names
+            .iter()
+            .all(|n| n.starts_with("chocopy-") && n.ends_with(".o")));
+        for (i, name) in names.iter().enumerate() {
+            assert!(!names[..i].contains(name));
+        }
+    }
+
+    #[test]
+    fn create_skips_taken_names() {
+        let dir = temp_path("");
+        std::fs::create_dir(&dir).unwrap();
+
+        // A second process with the same seed gets the names in the same order
+        seed(7);
+        let (first, _) = create_in(&dir, ".o").unwrap();
+        seed(7);
+        let (second, _) = create_in(&dir, ".o").unwrap();
+        assert_ne!(first, second);
+        assert!(first.exists() && second.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
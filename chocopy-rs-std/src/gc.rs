@@ -66,6 +66,11 @@ pub unsafe fn collect(rbp: *const u64, rsp: *const u64) {
         current_frame = *current_frame as *const u64;
     }
 
+    mark_globals(init_param);
+    sweep();
+}
+
+unsafe fn mark_globals(init_param: &InitParam) {
     for index in 0..init_param.global_size / 8 {
         let index = index as usize;
         let flag = *init_param.global_map.add(index / 8) & (1 << (index % 8));
@@ -73,10 +78,15 @@ pub unsafe fn collect(rbp: *const u64, rsp: *const u64) {
             walk(init_param.global_section.add(index));
         }
     }
+}
 
+// Frees unmarked objects in the chain and clears the marks of the others
+unsafe fn sweep() {
+    let final_check = GC_FINAL_CHECK.with(|final_check| final_check.get());
     let mut head = GC_HEAD.with(|gc_head| gc_head.get());
     let mut cur = &mut head;
     let mut collect_space = 0;
+    let mut object_ids = OBJECT_IDS.with(|object_ids| object_ids.take());
     while let Some(object) = *cur {
         let object = object.as_ptr();
         if (*object).gc_count == 1 {
@@ -85,11 +95,19 @@ pub unsafe fn collect(rbp: *const u64, rsp: *const u64) {
         } else {
             *cur = (*object).gc_next;
 
+            // The address may be reused by a new object, which should get a new identity
+            if !object_ids.is_empty() {
+                object_ids.remove(&(object as usize));
+            }
+            if final_check {
+                ALLOCATED.with(|allocated| allocated.borrow_mut().remove(&(object as usize)));
+            }
+
             let size = calculate_size((*object).prototype, || unsafe {
                 (*(object as *mut ArrayObject)).len
             });
 
-            drop(Box::from_raw(std::slice::from_raw_parts_mut(
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
                 object as *mut AllocUnit,
                 size,
             )));
@@ -97,6 +115,98 @@ pub unsafe fn collect(rbp: *const u64, rsp: *const u64) {
         }
     }
 
+    OBJECT_IDS.with(|ids| ids.replace(object_ids));
     GC_HEAD.with(|gc_head| gc_head.set(head));
     CURRENT_SPACE.with(|current_space| current_space.set(current_space.get() - collect_space));
 }
+
+fn tag_name(tag: &TypeTag) -> &'static str {
+    match tag {
+        TypeTag::Other => "object",
+        TypeTag::Int => "int",
+        TypeTag::Bool => "bool",
+        TypeTag::Str => "str",
+        TypeTag::PlainList => "plain list",
+        TypeTag::RefList => "reference list",
+    }
+}
+
+/// Runs a final collection with only the globals as roots, after which every object
+/// not freed should be in the chain. Prints the objects that are neither,
+/// which the collector has lost track of, and returns how many there are.
+///
+/// # Safety
+///  - `init` is already called with `CHOCOPY_GC_FINAL_CHECK` set.
+///  - No ChocoPy function is running.
+pub unsafe fn final_check() -> usize {
+    let init_param = INIT_PARAM.with(|init_param| &*init_param.get());
+    mark_globals(init_param);
+    sweep();
+
+    let mut lost = ALLOCATED.with(|allocated| allocated.borrow().clone());
+    let mut cur = GC_HEAD.with(|gc_head| gc_head.get());
+    while let Some(object) = cur {
+        lost.remove(&(object.as_ptr() as usize));
+        cur = (*object.as_ptr()).gc_next;
+    }
+
+    let mut lost = lost.into_iter().collect::<Vec<_>>();
+    lost.sort_unstable();
+    for &address in &lost {
+        let object = address as *mut Object;
+        let prototype = (*object).prototype;
+        let size = calculate_size(prototype, || unsafe { (*(object as *mut ArrayObject)).len });
+        println!(
+            "Lost object at {:#x}: {}, {} bytes",
+            address,
+            tag_name(&(*prototype).tag),
+            size * size_of::<AllocUnit>()
+        );
+    }
+    lost.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn final_check_finds_broken_chain() {
+        unsafe {
+            // Objects with a single reference attribute
+            let map = [1u8];
+            let prototype = Prototype {
+                size: 8,
+                tag: TypeTag::Other,
+                map: map.as_ptr(),
+            };
+            let mut globals = vec![0u64];
+            let global_section = globals.as_mut_ptr();
+            let global_map = [1u8];
+            let init_param = InitParam {
+                bottom_frame: null(),
+                global_section,
+                global_size: 8,
+                global_map: global_map.as_ptr(),
+                str_prototype: null(),
+            };
+            INIT_PARAM.with(|i| i.set(&init_param));
+            GC_FINAL_CHECK.with(|final_check| final_check.set(true));
+
+            // The global references a, which references b. c is garbage
+            let a = alloc_obj(&prototype, 0, null(), null());
+            let b = alloc_obj(&prototype, 0, null(), null());
+            alloc_obj(&prototype, 0, null(), null());
+            *(a.add(1) as *mut u64) = b as u64;
+            *global_section = a as u64;
+            assert_eq!(final_check(), 0);
+            assert_eq!(ALLOCATED.with(|allocated| allocated.borrow().len()), 2);
+
+            // Cut the chain e -> b -> a to just a, losing both a reachable object
+            // and an unreachable one
+            alloc_obj(&prototype, 0, null(), null());
+            GC_HEAD.with(|gc_head| gc_head.set(NonNull::new(a)));
+            assert_eq!(final_check(), 2);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// Ctrl-C handling for programs compiled with --interruptible.
+//
+// The first Ctrl-C only sets a flag, which the program checks at the end of each loop iteration
+// to exit through `$interrupted`. A program waiting elsewhere, such as for input, is still ended
+// by a second Ctrl-C as if there were no handler.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Read by generated code as a byte
+#[export_name = "$interrupt_flag"]
+pub static INTERRUPT_FLAG: AtomicBool = AtomicBool::new(false);
+
+// Exit code of interrupted programs, as shells report for processes ended by SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+#[cfg(unix)]
+mod platform {
+    use super::*;
+
+    const SIGINT: i32 = 2;
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn _exit(status: i32) -> !;
+    }
+
+    extern "C" fn handler(_: i32) {
+        if INTERRUPT_FLAG.swap(true, Ordering::Relaxed) {
+            // Only async-signal-safe functions can be called here
+            unsafe { _exit(EXIT_CODE) }
+        }
+    }
+
+    pub fn install() {
+        unsafe {
+            signal(SIGINT, handler);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::*;
+
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+
+    // Runs on a thread of its own. Returning 0 passes the event on to the default handler,
+    // which ends the process
+    extern "system" fn handler(event: u32) -> i32 {
+        let handled = (event == CTRL_C_EVENT || event == CTRL_BREAK_EVENT)
+            && !INTERRUPT_FLAG.swap(true, Ordering::Relaxed);
+        handled as i32
+    }
+
+    pub fn install() {
+        unsafe {
+            SetConsoleCtrlHandler(Some(handler), 1);
+        }
+    }
+}
+
+pub use platform::install;
@@ -1,20 +1,31 @@
 use chocopy_rs_common::*;
 use std::cell::*;
+use std::collections::{HashMap, HashSet};
 use std::mem::*;
 use std::process::{abort, exit};
 use std::ptr::*;
 
 mod gc;
+mod interrupt;
 
 #[repr(transparent)]
 #[derive(Clone, Copy)]
 struct AllocUnit(u64);
 
 thread_local! {
-    static INIT_PARAM: Cell<*const InitParam> = Cell::new(std::ptr::null());
-    static GC_HEAD: Cell<Option<NonNull<Object>>> = Cell::new(None);
-    static CURRENT_SPACE: Cell<usize> = Cell::new(0);
-    static THRESHOLD_SPACE: Cell<usize> = Cell::new(1024);
+    static INIT_PARAM: Cell<*const InitParam> = const { Cell::new(std::ptr::null()) };
+    static GC_HEAD: Cell<Option<NonNull<Object>>> = const { Cell::new(None) };
+    static CURRENT_SPACE: Cell<usize> = const { Cell::new(0) };
+    static THRESHOLD_SPACE: Cell<usize> = const { Cell::new(1024) };
+    static GC_STRESS: Cell<bool> = const { Cell::new(false) };
+    static GC_FINAL_CHECK: Cell<bool> = const { Cell::new(false) };
+    // Addresses of all objects not freed yet, only tracked for the final check
+    static ALLOCATED: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+    // Identities handed out by `obj_id`, keyed by object address. Freed objects are removed.
+    static OBJECT_IDS: RefCell<HashMap<usize, i32>> = RefCell::new(HashMap::new());
+    static NEXT_OBJECT_ID: Cell<i32> = const { Cell::new(1) };
+    static RANDOM: Cell<random::Random> =
+        const { Cell::new(random::Random::new(random::DEFAULT_SEED)) };
 }
 
 fn divide_up(value: usize) -> usize {
@@ -41,6 +52,16 @@ pub(crate) unsafe fn calculate_size<F: FnOnce() -> u64>(
     })
 }
 
+/// # Safety
+///  - `init` already called.
+///  - `rbp` and `rsp` points to the bottom and the top of the top stack frame.
+unsafe fn collect_and_update_threshold(rbp: *const u64, rsp: *const u64) {
+    gc::collect(rbp, rsp);
+    let current = CURRENT_SPACE.with(|current_space| current_space.get());
+    let threshold = std::cmp::max(1024, current * 2);
+    THRESHOLD_SPACE.with(|threshold_space| threshold_space.set(threshold));
+}
+
 /// Allocates a ChocoPy object
 ///
 /// # Safety
@@ -60,13 +81,11 @@ pub unsafe extern "C" fn alloc_obj(
     rbp: *const u64,
     rsp: *const u64,
 ) -> *mut Object {
-    if CURRENT_SPACE.with(|current_space| current_space.get())
-        >= THRESHOLD_SPACE.with(|threshold_space| threshold_space.get())
+    if GC_STRESS.with(|gc_stress| gc_stress.get())
+        || CURRENT_SPACE.with(|current_space| current_space.get())
+            >= THRESHOLD_SPACE.with(|threshold_space| threshold_space.get())
     {
-        gc::collect(rbp, rsp);
-        let current = CURRENT_SPACE.with(|current_space| current_space.get());
-        let threshold = std::cmp::max(1024, current * 2);
-        THRESHOLD_SPACE.with(|threshold_space| threshold_space.set(threshold));
+        collect_and_update_threshold(rbp, rsp);
     }
 
     let size = calculate_size(prototype, || len);
@@ -75,6 +94,9 @@ pub unsafe extern "C" fn alloc_obj(
         Box::into_raw(vec![AllocUnit(0); size].into_boxed_slice()) as *mut AllocUnit as *mut Object;
 
     CURRENT_SPACE.with(|current_space| current_space.set(current_space.get() + size));
+    if GC_FINAL_CHECK.with(|final_check| final_check.get()) {
+        ALLOCATED.with(|allocated| allocated.borrow_mut().insert(pointer as usize));
+    }
 
     let gc_next = GC_HEAD.with(|gc_next| gc_next.replace(NonNull::new(pointer)));
 
@@ -94,6 +116,19 @@ pub unsafe extern "C" fn alloc_obj(
     pointer
 }
 
+/// Collects garbage now, for the built-in function `collect`. Returns None.
+///
+/// Calling it is never required for correctness, as allocation collects garbage when needed.
+///
+/// # Safety
+///  - `init` is already called.
+///  - `rbp` and `rsp` points to the bottom and the top of the top stack frame.
+#[export_name = "$collect"]
+pub unsafe extern "C" fn collect(rbp: *const u64, rsp: *const u64) -> *mut u8 {
+    collect_and_update_threshold(rbp, rsp);
+    std::ptr::null_mut()
+}
+
 /// Gets the array length of a ChocoPy object
 ///
 /// # Safety
@@ -115,6 +150,164 @@ pub unsafe extern "C" fn len(pointer: *mut Object) -> i32 {
     (*object).len as i32
 }
 
+/// Creates a new list with the elements of `pointer` followed by `value`.
+/// This is the `append` extension. The original list is not changed.
+///
+/// # Safety
+///  - `init` is already called.
+///  - `pointer` must be null or previously returned by `alloc_obj` for a list.
+///  - `prototype` is a list prototype with the same element size as the list.
+///  - `value` holds an element in its low bytes, according to the element size.
+///  - `rbp` and `rsp` points to the bottom and the top of the top stack frame,
+///    which keeps `pointer` and `value` alive during garbage collection.
+#[export_name = "$list_append"]
+pub unsafe extern "C" fn list_append(
+    pointer: *mut Object,
+    value: u64,
+    prototype: *const Prototype,
+    rbp: *const u64,
+    rsp: *const u64,
+) -> *mut Object {
+    if pointer.is_null() {
+        none_op();
+    }
+    let len = (*(pointer as *mut ArrayObject)).len;
+    let result = alloc_obj(prototype, len + 1, rbp, rsp);
+
+    let element_size = -(*prototype).size as usize;
+    let source = (pointer as *const u8).add(size_of::<ArrayObject>());
+    let target = (result as *mut u8).add(size_of::<ArrayObject>());
+    std::ptr::copy_nonoverlapping(source, target, len as usize * element_size);
+    std::ptr::copy_nonoverlapping(
+        value.to_le_bytes().as_ptr(),
+        target.add(len as usize * element_size),
+        element_size,
+    );
+    result
+}
+
+/// Tests a ChocoPy object for truthiness like Python. Used by the `and` / `or` extension.
+///
+/// Called without a reference map, so this must never allocate ChocoPy objects.
+///
+/// # Safety
+///  - `init` is already called.
+///  - `pointer` must be null or previously returned by `alloc_obj`.
+#[export_name = "$truthy"]
+pub unsafe extern "C" fn truthy(pointer: *mut Object) -> bool {
+    if pointer.is_null() {
+        return false;
+    }
+    match (*(*pointer).prototype).tag {
+        TypeTag::Int => *(pointer.offset(1) as *const i32) != 0,
+        TypeTag::Bool => *(pointer.offset(1) as *const bool),
+        TypeTag::Str | TypeTag::PlainList | TypeTag::RefList => {
+            (*(pointer as *mut ArrayObject)).len != 0
+        }
+        TypeTag::Other => true,
+    }
+}
+
+/// Compares two ChocoPy objects like `==`, for the class equality extension.
+/// Objects of the same class are equal if their attributes are. `int`, `bool` and `str` are
+/// compared by value, lists by identity, and other objects recursively. Beyond
+/// `OBJ_EQ_MAX_DEPTH` levels of nesting, objects are compared by identity.
+///
+/// Called without a reference map, so this must never allocate ChocoPy objects.
+///
+/// # Safety
+///  - `init` is already called.
+///  - `a` and `b` must be null or previously returned by `alloc_obj`.
+#[export_name = "$obj_eq"]
+pub unsafe extern "C" fn obj_eq(a: *mut Object, b: *mut Object) -> bool {
+    obj_eq_nested(a, b, 0)
+}
+
+unsafe fn obj_eq_nested(a: *mut Object, b: *mut Object, depth: u32) -> bool {
+    if a == b {
+        return true;
+    }
+    if a.is_null() || b.is_null() {
+        return false;
+    }
+    let (a_prototype, b_prototype) = ((*a).prototype, (*b).prototype);
+    // Each object file has its own prototypes for the built-in types, so only their tags match
+    match (&(*a_prototype).tag, &(*b_prototype).tag) {
+        (TypeTag::Int, TypeTag::Int) => *(a.add(1) as *const i32) == *(b.add(1) as *const i32),
+        (TypeTag::Bool, TypeTag::Bool) => *(a.add(1) as *const bool) == *(b.add(1) as *const bool),
+        (TypeTag::Str, TypeTag::Str) => {
+            let (a, b) = (a as *mut ArrayObject, b as *mut ArrayObject);
+            let len = (*a).len as usize;
+            len == (*b).len as usize
+                && std::slice::from_raw_parts(a.add(1) as *const u8, len)
+                    == std::slice::from_raw_parts(b.add(1) as *const u8, len)
+        }
+        (TypeTag::Other, TypeTag::Other) if a_prototype == b_prototype => {
+            if depth >= OBJ_EQ_MAX_DEPTH {
+                return false;
+            }
+            let size = (*a_prototype).size as usize;
+            let ref_map = (*a_prototype).map;
+            let (a, b) = (a.add(1) as *const u8, b.add(1) as *const u8);
+            (0..size.div_ceil(8)).all(|i| {
+                if *ref_map.add(i / 8) & (1 << (i % 8)) != 0 {
+                    let a = *(a as *const *mut Object).add(i);
+                    let b = *(b as *const *mut Object).add(i);
+                    obj_eq_nested(a, b, depth + 1)
+                } else {
+                    // Scalar attributes and the zeroed padding between them
+                    let len = std::cmp::min(8, size - i * 8);
+                    std::slice::from_raw_parts(a.add(i * 8), len)
+                        == std::slice::from_raw_parts(b.add(i * 8), len)
+                }
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Gets a number identifying a ChocoPy object while it is alive.
+/// Numbers are assigned in the order first requested, and None is 0.
+///
+/// # Safety
+///  - `init` is already called.
+///  - `pointer` must be null or previously returned by `alloc_obj`.
+#[export_name = "$obj_id"]
+pub unsafe extern "C" fn obj_id(pointer: *mut Object) -> i32 {
+    if pointer.is_null() {
+        return 0;
+    }
+    OBJECT_IDS.with(|object_ids| {
+        *object_ids
+            .borrow_mut()
+            .entry(pointer as usize)
+            .or_insert_with(|| NEXT_OBJECT_ID.with(|next| next.replace(next.get() + 1)))
+    })
+}
+
+/// Restarts the numbers from `randint`, for the built-in function `seed`. Returns None.
+///
+/// Without calling it, the numbers are the same as after `seed(0)`.
+#[export_name = "$seed"]
+pub extern "C" fn seed(seed: i32) -> *mut u8 {
+    RANDOM.with(|random| random.set(random::Random::new(seed)));
+    std::ptr::null_mut()
+}
+
+/// Gets a uniformly distributed random integer in [lo, hi], for the built-in function `randint`
+#[export_name = "$randint"]
+pub extern "C" fn randint(lo: i32, hi: i32) -> i32 {
+    if lo > hi {
+        invalid_arg();
+    }
+    RANDOM.with(|random| {
+        let mut state = random.get();
+        let value = state.int(lo, hi);
+        random.set(state);
+        value
+    })
+}
+
 /// Prints a ChocoPy object
 ///
 /// # Safety
@@ -141,13 +334,12 @@ pub unsafe extern "C" fn print(pointer: *mut Object) -> *mut u8 {
             );
         }
         TypeTag::Str => {
+            // Indexing counts bytes, so it can split a multibyte character from `input`.
+            // The pieces are printed as U+FFFD, as in the WebAssembly runtime
             let object = pointer as *mut ArrayObject;
-            let slice = std::str::from_utf8(std::slice::from_raw_parts(
-                object.offset(1) as *const u8,
-                (*object).len as usize,
-            ))
-            .unwrap_or_else(|e| fatal(&e.to_string()));
-            println!("{}", slice);
+            let bytes =
+                std::slice::from_raw_parts(object.offset(1) as *const u8, (*object).len as usize);
+            println!("{}", String::from_utf8_lossy(bytes));
         }
         _ => {
             invalid_arg();
@@ -193,6 +385,11 @@ pub unsafe extern "C" fn input(rbp: *const u64, rsp: *const u64) -> *mut Object
 #[export_name = "$init"]
 pub unsafe extern "C" fn init(init_param: *const InitParam) {
     INIT_PARAM.with(|i| i.set(init_param));
+    // Collect garbage on every allocation, to find missing references in tests
+    GC_STRESS.with(|gc_stress| gc_stress.set(std::env::var_os("CHOCOPY_GC_STRESS").is_some()));
+    // Check at exit that the collector hasn't lost track of any object
+    GC_FINAL_CHECK
+        .with(|final_check| final_check.set(std::env::var_os("CHOCOPY_GC_FINAL_CHECK").is_some()));
 }
 
 pub(crate) fn fatal(message: &str) -> ! {
@@ -200,34 +397,159 @@ pub(crate) fn fatal(message: &str) -> ! {
     abort();
 }
 
+// Runtime errors go to stderr, to keep the output of the program apart
 fn exit_code(code: i32) -> ! {
-    println!("Exited with error code {}", code);
+    // CHOCOPY_QUIET=1 leaves out the message, for programs expected to fail
+    if std::env::var_os("CHOCOPY_QUIET").is_none_or(|quiet| quiet != "1") {
+        eprintln!("Exited with error code {}", code);
+    }
     exit(code);
 }
 
-fn invalid_arg() -> ! {
-    println!("Invalid argument");
+#[export_name = "$invalid_arg"]
+pub extern "C" fn invalid_arg() -> ! {
+    eprintln!("Invalid argument");
     exit_code(1)
 }
 
 #[export_name = "$div_zero"]
 pub extern "C" fn div_zero() -> ! {
-    println!("Division by zero");
+    eprintln!("Division by zero");
     exit_code(2)
 }
 
 #[export_name = "$out_of_bound"]
 pub extern "C" fn out_of_bound() -> ! {
-    println!("Index out of bounds");
+    eprintln!("Index out of bounds");
     exit_code(3)
 }
 
 #[export_name = "$none_op"]
 pub extern "C" fn none_op() -> ! {
-    println!("Operation on None");
+    eprintln!("Operation on None");
     exit_code(4)
 }
 
+/// Installs the Ctrl-C handler, for programs compiled with `--interruptible`
+#[export_name = "$catch_interrupt"]
+pub extern "C" fn catch_interrupt() {
+    interrupt::install();
+}
+
+/// Exits the program after Ctrl-C, called by the program itself once it sees the flag
+#[export_name = "$interrupted"]
+pub extern "C" fn interrupted() -> ! {
+    use std::io::Write;
+    let _ = std::io::stdout().flush();
+    eprintln!("Interrupted");
+    exit_code(interrupt::EXIT_CODE)
+}
+
+/// Prints the source line about to be executed, for programs compiled with `--trace-exec`.
+///
+/// Called without a reference map, so this must never allocate ChocoPy objects.
+/// It does not allocate on the Rust heap either, to keep the overhead low.
+#[export_name = "$trace_line"]
+pub extern "C" fn trace_line(line: u32) {
+    use std::io::Write;
+    const PREFIX: &[u8] = b"TRACE: line ";
+    let mut buffer = [0; PREFIX.len() + 11];
+    buffer[..PREFIX.len()].copy_from_slice(PREFIX);
+    let mut digits = [0; 10];
+    let mut digit_count = 0;
+    let mut value = line;
+    loop {
+        digits[digit_count] = b'0' + (value % 10) as u8;
+        digit_count += 1;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    let mut len = PREFIX.len();
+    for digit in digits[..digit_count].iter().rev() {
+        buffer[len] = *digit;
+        len += 1;
+    }
+    buffer[len] = b'\n';
+    let _ = std::io::stderr().write_all(&buffer[..=len]);
+}
+
+/// Prints all arguments. Used by the compiler to test the calling convention
+#[doc(hidden)]
+#[export_name = "$test_args6"]
+pub extern "C" fn test_args6(a0: i64, a1: i64, a2: i64, a3: i64, a4: i64, a5: i64) {
+    println!("{} {} {} {} {} {}", a0, a1, a2, a3, a4, a5);
+}
+
+/// Prints all arguments, dereferencing the pointers.
+/// Used by the compiler to test the calling convention
+///
+/// # Safety
+///  - `p6` and `p7` are valid pointers.
+#[doc(hidden)]
+#[export_name = "$test_args8"]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn test_args8(
+    a0: i64,
+    a1: i64,
+    a2: i64,
+    a3: i64,
+    a4: i64,
+    a5: i64,
+    p6: *const i64,
+    p7: *const i64,
+) {
+    println!("{} {} {} {} {} {} {} {}", a0, a1, a2, a3, a4, a5, *p6, *p7);
+}
+
+// `$test_stack_alignment` passes the stack pointer at its entry on to this function.
+// A call following the system ABI leaves rsp + 8 a multiple of 16 there
+extern "C" fn check_stack_alignment(rsp: u64) {
+    if !(rsp + 8).is_multiple_of(16) {
+        fatal(&format!("Stack is misaligned at a call, rsp = {:#x}", rsp));
+    }
+    println!("aligned");
+}
+
+// The stack pointer is only available to assembly before a Rust prologue changes it.
+// The check runs on a realigned stack, so that it can report a misaligned one
+macro_rules! stack_alignment_stub {
+    ($symbol:literal, $arg:literal) => {
+        std::arch::global_asm!(
+            concat!(".globl ", $symbol),
+            concat!($symbol, ":"),
+            "push rbp",
+            "mov rbp, rsp",
+            concat!("lea ", $arg, ", [rsp+8]"),
+            "and rsp, -16",
+            // Shadow space for Windows
+            "sub rsp, 32",
+            "call {check}",
+            "leave",
+            "ret",
+            check = sym check_stack_alignment,
+        );
+    };
+}
+
+// Prints "aligned", or aborts if the stack isn't aligned as it should be at a call.
+// Used by the compiler to test the alignment at its call sites
+#[cfg(windows)]
+stack_alignment_stub!("\"$test_stack_alignment\"", "rcx");
+#[cfg(target_os = "macos")]
+stack_alignment_stub!("\"_$test_stack_alignment\"", "rdi");
+#[cfg(all(unix, not(target_os = "macos")))]
+stack_alignment_stub!("\"$test_stack_alignment\"", "rdi");
+
+/// Gets the layout of the structs as this library is built.
+/// Used by the compiler to test that its constants agree with the library it links with
+#[doc(hidden)]
+#[export_name = "$layout"]
+pub extern "C" fn layout() -> layout::Layout {
+    layout::Layout::structs()
+}
+
 #[cfg(not(test))]
 pub mod crt0_glue {
     extern "C" {
@@ -240,6 +562,132 @@ pub mod crt0_glue {
     #[export_name = "main"]
     pub unsafe extern "C" fn entry_point() -> i32 {
         chocopy_main();
+        if super::GC_FINAL_CHECK.with(|final_check| final_check.get())
+            && super::gc::final_check() != 0
+        {
+            return 1;
+        }
         0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn randint_range() {
+        seed(1);
+        let mut counts = [0; 6];
+        for _ in 0..60000 {
+            let value = randint(1, 6);
+            assert!((1..=6).contains(&value));
+            counts[value as usize - 1] += 1;
+        }
+        // Each count is 10000 on average with a standard deviation of about 91
+        for &count in &counts {
+            assert!((9500..=10500).contains(&count), "{:?}", counts);
+        }
+
+        assert_eq!(randint(-7, -7), -7);
+        let mut ends = [false; 2];
+        for _ in 0..100 {
+            ends[randint(0, 1) as usize] = true;
+        }
+        assert_eq!(ends, [true, true]);
+        for _ in 0..100 {
+            let value = randint(i32::MAX - 1, i32::MAX);
+            assert!(value >= i32::MAX - 1);
+            randint(i32::MIN, i32::MAX);
+        }
+    }
+
+    #[test]
+    fn obj_eq_graphs() {
+        unsafe {
+            // Never collect, as there is no ChocoPy stack to walk
+            THRESHOLD_SPACE.with(|threshold_space| threshold_space.set(usize::MAX));
+
+            // Objects with an int, a bool and a reference attribute
+            let map = [0b10u8];
+            let node = Prototype {
+                size: 16,
+                tag: TypeTag::Other,
+                map: map.as_ptr(),
+            };
+            let same_layout = Prototype {
+                size: 16,
+                tag: TypeTag::Other,
+                map: map.as_ptr(),
+            };
+            let str_prototype = Prototype {
+                size: -1,
+                tag: TypeTag::Str,
+                map: null(),
+            };
+            let new_node = |prototype: &Prototype, value: i32, flag: bool, next: *mut Object| {
+                let object = alloc_obj(prototype, 0, null(), null());
+                let attributes = object.add(1) as *mut u8;
+                *(attributes as *mut i32) = value;
+                *attributes.add(4) = flag as u8;
+                *(attributes.add(8) as *mut *mut Object) = next;
+                object
+            };
+            let new_str = |s: &str| {
+                let object = alloc_obj(&str_prototype, s.len() as u64, null(), null());
+                let elements = (object as *mut ArrayObject).add(1) as *mut u8;
+                std::ptr::copy_nonoverlapping(s.as_ptr(), elements, s.len());
+                object
+            };
+            let chain = |len: usize| {
+                (0..len).fold(null_mut(), |next, i| new_node(&node, i as i32, true, next))
+            };
+
+            let a = new_node(&node, 1, true, null_mut());
+            assert!(obj_eq(a, a));
+            assert!(obj_eq(null_mut(), null_mut()));
+            assert!(!obj_eq(a, null_mut()));
+            assert!(!obj_eq(null_mut(), a));
+            assert!(obj_eq(a, new_node(&node, 1, true, null_mut())));
+            assert!(!obj_eq(a, new_node(&node, 2, true, null_mut())));
+            assert!(!obj_eq(a, new_node(&node, 1, false, null_mut())));
+            assert!(!obj_eq(a, new_node(&node, 1, true, a)));
+            // Objects of another class are unequal, even with the same attributes
+            assert!(!obj_eq(a, new_node(&same_layout, 1, true, null_mut())));
+
+            // Strings are compared by value
+            let b = new_node(&node, 1, true, new_str("ab"));
+            assert!(obj_eq(b, new_node(&node, 1, true, new_str("ab"))));
+            assert!(!obj_eq(b, new_node(&node, 1, true, new_str("ac"))));
+            assert!(!obj_eq(b, new_node(&node, 1, true, new_str("abc"))));
+            assert!(!obj_eq(b, new_node(&node, 1, true, new_str(""))));
+
+            // Nested objects are compared recursively, up to the depth limit
+            let nested = |inner: i32| new_node(&node, 0, true, new_node(&node, inner, true, b));
+            assert!(obj_eq(nested(1), nested(1)));
+            assert!(!obj_eq(nested(1), nested(2)));
+            let depth = OBJ_EQ_MAX_DEPTH as usize;
+            assert!(obj_eq(chain(depth), chain(depth)));
+            assert!(!obj_eq(chain(depth + 1), chain(depth + 1)));
+
+            // Distinct cycles are unequal, but each is equal to itself
+            let x = new_node(&node, 1, true, null_mut());
+            *(x.add(1) as *mut u8).add(8).cast::<*mut Object>() = x;
+            let y = new_node(&node, 1, true, x);
+            *(y.add(1) as *mut u8).add(8).cast::<*mut Object>() = y;
+            assert!(obj_eq(x, x));
+            assert!(!obj_eq(x, y));
+        }
+    }
+
+    #[test]
+    fn seed_repeats() {
+        let draw = || (0..10).map(|_| randint(0, 1000)).collect::<Vec<_>>();
+        seed(42);
+        let seeded = draw();
+        seed(42);
+        assert_eq!(draw(), seeded);
+        seed(43);
+        assert_ne!(draw(), seeded);
+    }
+}
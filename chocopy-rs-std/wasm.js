@@ -0,0 +1,112 @@
+// Runtime for ChocoPy programs compiled to WebAssembly with `--platform wasm`.
+//
+// In a browser, call `run(bytes, io)` with the module bytes, where `io.write(text)` receives the
+// output and `io.readLine()` returns the next line of input, or null at the end of it. Runtime
+// errors go to `io.writeError(text)` if there is one, and to `io.write(text)` otherwise, and
+// `io.quiet` leaves out the "Exited with error code N" line.
+// With Node.js, `node wasm.js program.wasm` runs the program with stdin, stdout and stderr, and
+// exits with the same code as a natively compiled program. CHOCOPY_QUIET=1 sets `io.quiet`.
+'use strict';
+
+const MESSAGES = {
+  1: 'Invalid argument',
+  2: 'Division by zero',
+  3: 'Index out of bounds',
+  4: 'Operation on None',
+};
+
+class ChocoPyExit extends Error {
+  constructor(code) {
+    super(`Exited with error code ${code}`);
+    this.code = code;
+  }
+}
+
+// Runs the program and returns its exit code
+async function run(bytes, io) {
+  let exports = null;
+  const writeError = io.writeError ?? io.write;
+
+  // A str is its length followed by its bytes
+  const readStr = (address) => {
+    const length = new DataView(exports.memory.buffer).getInt32(address, true);
+    return new TextDecoder().decode(new Uint8Array(exports.memory.buffer, address + 4, length));
+  };
+  const newStr = (text) => {
+    const bytes = new TextEncoder().encode(text);
+    const address = exports.alloc(4 + bytes.length);
+    new DataView(exports.memory.buffer).setInt32(address, bytes.length, true);
+    new Uint8Array(exports.memory.buffer, address + 4, bytes.length).set(bytes);
+    return address;
+  };
+
+  const env = {
+    print_int: (value) => io.write(`${value}\n`),
+    print_bool: (value) => io.write(value ? 'True\n' : 'False\n'),
+    print_str: (address) => io.write(`${readStr(address)}\n`),
+    input: () => newStr(io.readLine() ?? ''),
+    error: (code) => {
+      writeError(`${MESSAGES[code]}\n`);
+      throw new ChocoPyExit(code);
+    },
+  };
+
+  const { instance } = await WebAssembly.instantiate(bytes, { env });
+  exports = instance.exports;
+  try {
+    exports.main();
+  } catch (e) {
+    if (e instanceof ChocoPyExit) {
+      if (!io.quiet) {
+        writeError(`${e.message}\n`);
+      }
+      return e.code;
+    }
+    throw e;
+  }
+  return 0;
+}
+
+if (typeof module !== 'undefined') {
+  module.exports = { run };
+}
+
+if (typeof require !== 'undefined' && require.main === module) {
+  const fs = require('fs');
+
+  // Input is read all at once at the first call to input()
+  let input = null;
+  let position = 0;
+  const io = {
+    write: (text) => fs.writeSync(1, text),
+    writeError: (text) => fs.writeSync(2, text),
+    quiet: process.env.CHOCOPY_QUIET === '1',
+    readLine: () => {
+      if (input === null) {
+        try {
+          input = fs.readFileSync(0, 'utf8');
+        } catch (e) {
+          input = '';
+        }
+      }
+      if (position >= input.length) {
+        return null;
+      }
+      let end = input.indexOf('\n', position);
+      if (end < 0) {
+        end = input.length;
+      }
+      const line = input.slice(position, end).replace(/\r$/, '');
+      position = end + 1;
+      return line;
+    },
+  };
+
+  run(fs.readFileSync(process.argv[2]), io).then(
+    (code) => process.exit(code),
+    (e) => {
+      console.error(`Fatal error: ${e.message}`);
+      process.exit(134);
+    },
+  );
+}
@@ -0,0 +1,267 @@
+// Reports the difference between expected and actual output of a failed case.
+// Text output is shown as a unified diff of lines, and anything else by the first differing byte.
+
+const CONTEXT: usize = 3;
+
+// Changed parts larger than this many pairs of lines are not searched for common lines,
+// so that huge outputs don't take quadratic time
+const MAX_LCS_CELLS: usize = 1 << 22;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Edit {
+    Keep,
+    Remove,
+    Add,
+}
+
+// Edits turning `expected` into `actual`, using the longest common subsequence of lines
+fn edits(expected: &[&str], actual: &[&str]) -> Vec<Edit> {
+    let prefix = expected
+        .iter()
+        .zip(actual)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &expected[prefix..expected.len() - suffix];
+    let new = &actual[prefix..actual.len() - suffix];
+
+    let mut result = vec![Edit::Keep; prefix];
+    let (mut i, mut j) = (0, 0);
+    if old.len() * new.len() <= MAX_LCS_CELLS {
+        // lengths[i * width + j] is the length of the LCS of old[i..] and new[j..]
+        let width = new.len() + 1;
+        let mut lengths = vec![0usize; (old.len() + 1) * width];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lengths[i * width + j] = if old[i] == new[j] {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    std::cmp::max(lengths[(i + 1) * width + j], lengths[i * width + j + 1])
+                };
+            }
+        }
+        while i < old.len() && j < new.len() {
+            if old[i] == new[j] {
+                result.push(Edit::Keep);
+                i += 1;
+                j += 1;
+            } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+                result.push(Edit::Remove);
+                i += 1;
+            } else {
+                result.push(Edit::Add);
+                j += 1;
+            }
+        }
+    }
+    result.extend(std::iter::repeat_n(Edit::Remove, old.len() - i));
+    result.extend(std::iter::repeat_n(Edit::Add, new.len() - j));
+    result.extend(std::iter::repeat_n(Edit::Keep, suffix));
+    result
+}
+
+// Line range in a hunk header, where an empty range starts at the line before it
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+fn write_line(out: &mut String, sign: char, line: &str) {
+    out.push(sign);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+fn unified_diff(expected: &str, actual: &str) -> String {
+    let old: Vec<_> = expected.split_inclusive('\n').collect();
+    let new: Vec<_> = actual.split_inclusive('\n').collect();
+    let edits = edits(&old, &new);
+
+    // Line numbers in both outputs before each edit
+    let mut positions = vec![];
+    let (mut i, mut j) = (0, 0);
+    for edit in &edits {
+        positions.push((i, j));
+        match edit {
+            Edit::Keep => {
+                i += 1;
+                j += 1;
+            }
+            Edit::Remove => i += 1,
+            Edit::Add => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let mut out = "--- expected\n+++ actual\n".to_owned();
+    let changes: Vec<_> = (0..edits.len())
+        .filter(|&k| edits[k] != Edit::Keep)
+        .collect();
+    let mut k = 0;
+    while k < changes.len() {
+        // Changes separated by little enough context go into the same hunk
+        let mut last = k;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= 2 * CONTEXT + 1 {
+            last += 1;
+        }
+        let begin = changes[k].saturating_sub(CONTEXT);
+        let end = std::cmp::min(changes[last] + 1 + CONTEXT, edits.len());
+        let (old_begin, new_begin) = positions[begin];
+        let (old_end, new_end) = positions[end];
+        out += &format!(
+            "@@ -{} +{} @@\n",
+            range(old_begin, old_end - old_begin),
+            range(new_begin, new_end - new_begin)
+        );
+        for (edit, &(i, j)) in edits[begin..end].iter().zip(&positions[begin..end]) {
+            match edit {
+                Edit::Keep => write_line(&mut out, ' ', old[i]),
+                Edit::Remove => write_line(&mut out, '-', old[i]),
+                Edit::Add => write_line(&mut out, '+', new[j]),
+            }
+        }
+        k = last + 1;
+    }
+    out
+}
+
+// Output that can be shown as lines of text
+fn as_text(output: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(output).ok()?;
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+    {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn hex_around(output: &[u8], offset: usize) -> String {
+    let begin = offset.saturating_sub(8);
+    let end = std::cmp::min(offset + 8, output.len());
+    if begin >= end {
+        return "(end of output)".to_owned();
+    }
+    output[begin..end]
+        .iter()
+        .enumerate()
+        .map(|(k, byte)| {
+            if begin + k == offset {
+                format!("[{:02x}]", byte)
+            } else {
+                format!("{:02x}", byte)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn byte_diff(expected: &[u8], actual: &[u8]) -> String {
+    let offset = expected
+        .iter()
+        .zip(actual)
+        .take_while(|(a, b)| a == b)
+        .count();
+    format!(
+        "Output differs at byte {} (expected {} bytes, actual {} bytes)\n\
+         expected: {}\n\
+         actual:   {}\n",
+        offset,
+        expected.len(),
+        actual.len(),
+        hex_around(expected, offset),
+        hex_around(actual, offset)
+    )
+}
+
+pub fn format_diff(expected: &[u8], actual: &[u8]) -> String {
+    match (as_text(expected), as_text(actual)) {
+        (Some(expected), Some(actual)) => unified_diff(expected, actual),
+        _ => byte_diff(expected, actual),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(expected: &str, actual: &str) -> String {
+        format_diff(expected.as_bytes(), actual.as_bytes())
+    }
+
+    #[test]
+    fn changed_line() {
+        assert_eq!(
+            diff(
+                "1\n2\n3\n4\n5\n6\n7\n8\n9\n",
+                "1\n2\n3\n4\nfive\n6\n7\n8\n9\n"
+            ),
+            "--- expected\n+++ actual\n\
+             @@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn separate_hunks() {
+        let expected: String = (0..20).map(|i| format!("{}\n", i)).collect();
+        let actual = expected.replacen("2\n", "two\n", 1).replace("17\n", "");
+        assert_eq!(
+            diff(&expected, &actual),
+            "--- expected\n+++ actual\n\
+             @@ -1,6 +1,6 @@\n 0\n 1\n-2\n+two\n 3\n 4\n 5\n\
+             @@ -15,6 +15,5 @@\n 14\n 15\n 16\n-17\n 18\n 19\n"
+        );
+    }
+
+    #[test]
+    fn added_and_removed() {
+        assert_eq!(
+            diff("a\nb\n", "a\nb\nc\n"),
+            "--- expected\n+++ actual\n@@ -1,2 +1,3 @@\n a\n b\n+c\n"
+        );
+        assert_eq!(
+            diff("a\n", ""),
+            "--- expected\n+++ actual\n@@ -1 +0,0 @@\n-a\n"
+        );
+        assert_eq!(
+            diff("x\ny\nz\n", "y\nw\n"),
+            "--- expected\n+++ actual\n@@ -1,3 +1,2 @@\n-x\n y\n-z\n+w\n"
+        );
+    }
+
+    #[test]
+    fn missing_newline() {
+        assert_eq!(
+            diff("a\nb\n", "a\nb"),
+            "--- expected\n+++ actual\n@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn binary() {
+        assert_eq!(
+            format_diff(b"abc\n", b"ab\0\n"),
+            "Output differs at byte 2 (expected 4 bytes, actual 4 bytes)\n\
+             expected: 61 62 [63] 0a\n\
+             actual:   61 62 [00] 0a\n"
+        );
+        assert_eq!(
+            format_diff(b"\xff", b"\xff\x01"),
+            "Output differs at byte 1 (expected 1 bytes, actual 2 bytes)\n\
+             expected: ff\n\
+             actual:   ff [01]\n"
+        );
+    }
+}
@@ -1,5 +1,13 @@
+mod diff;
+
+use chocopy_rs_common::temp;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{BufRead, BufReader, Write};
+
+// Process exit codes, so that CI can tell failed tests apart from a broken test run
+const EXIT_PASSED: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+const EXIT_HARNESS_ERROR: i32 = 2;
 
 fn fixup_newline(s: &mut String) {
     if s.ends_with("\r\n") {
@@ -9,91 +17,199 @@ fn fixup_newline(s: &mut String) {
     }
 }
 
-struct IntegratedCases {
-    file: BufReader<File>,
+// Stdin and expected stdout of one run
+type Case = (Vec<u8>, Vec<u8>);
+
+#[derive(Debug)]
+struct CaseError {
+    path: std::path::PathBuf,
+    line: usize,
+    message: String,
 }
 
-impl Iterator for IntegratedCases {
-    type Item = (Vec<u8>, Vec<u8>);
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let mut line = "".to_owned();
-            if self.file.read_line(&mut line).unwrap() == 0 {
-                return None;
-            }
-            fixup_newline(&mut line);
-            if line == "#!\n" {
-                break;
-            }
+impl std::fmt::Display for CaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+impl std::error::Error for CaseError {}
+
+// Line reader for case files that tracks the line number for errors
+struct CaseReader<'a> {
+    path: &'a std::path::Path,
+    file: Box<dyn BufRead + 'a>,
+    line: usize,
+}
+
+impl<'a> CaseReader<'a> {
+    fn open(path: &'a std::path::Path) -> Result<CaseReader<'a>, CaseError> {
+        let file = File::open(path).map_err(|e| CaseError {
+            path: path.to_owned(),
+            line: 0,
+            message: e.to_string(),
+        })?;
+        Ok(CaseReader {
+            path,
+            file: Box::new(BufReader::new(file)),
+            line: 0,
+        })
+    }
+
+    fn error(&self, message: &str) -> CaseError {
+        CaseError {
+            path: self.path.to_owned(),
+            line: self.line,
+            message: message.to_owned(),
         }
+    }
 
-        let mut input = vec![];
-        loop {
-            let mut line = "".to_owned();
-            self.file.read_line(&mut line).unwrap();
-            fixup_newline(&mut line);
-            if line == "#<->#\n" {
-                break;
+    // Next line with its newline normalized to "\n", or None at the end of file
+    fn next_line(&mut self) -> Result<Option<String>, CaseError> {
+        let mut line = "".to_owned();
+        match self.file.read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                self.line += 1;
+                fixup_newline(&mut line);
+                Ok(Some(line))
             }
-            let bytes = line.as_bytes();
-            assert!(bytes[0] == b'#');
-            input.extend(bytes.iter().skip(1));
+            Err(e) => Err(self.error(&e.to_string())),
         }
+    }
 
-        let mut expected_output = vec![];
+    // Read lines prefixed with '#' until "#<->#", and join them without the prefix
+    fn section(&mut self) -> Result<Vec<u8>, CaseError> {
+        let mut content = vec![];
         loop {
-            let mut line = "".to_owned();
-            self.file.read_line(&mut line).unwrap();
-            fixup_newline(&mut line);
+            let line = self
+                .next_line()?
+                .ok_or_else(|| self.error("Section is not terminated by #<->#"))?;
             if line == "#<->#\n" {
-                break;
+                return Ok(content);
             }
-            let bytes = line.as_bytes();
-            assert!(bytes[0] == b'#');
-            expected_output.extend(bytes.iter().skip(1));
+            if line == "\n" {
+                return Err(self.error("Blank line in section; an empty line is written as #"));
+            }
+            let text = line
+                .strip_prefix('#')
+                .ok_or_else(|| self.error("Line in section doesn't start with #"))?;
+            content.extend(text.as_bytes());
         }
+    }
 
-        Some((input, expected_output))
+    // Read the remaining lines as they are
+    fn rest(&mut self) -> Result<Vec<u8>, CaseError> {
+        let mut content = vec![];
+        while let Some(line) = self.next_line()? {
+            content.extend(line.as_bytes());
+        }
+        Ok(content)
     }
 }
 
-fn get_cases(file_path: &std::path::Path) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)>> {
-    let mut ref_path = file_path.to_path_buf();
-    ref_path.set_file_name(
-        ref_path.file_name().unwrap().to_str().unwrap().to_owned() + ".ast.typed.s.result",
-    );
+// Cases embedded in comments of the source file:
+// "#!", then the input section, then the expected output section
+fn integrated_cases(path: &std::path::Path) -> Result<Vec<Case>, CaseError> {
+    let mut reader = CaseReader::open(path)?;
+    let mut cases = vec![];
+    while let Some(line) = reader.next_line()? {
+        if line == "#!\n" {
+            let input = reader.section()?;
+            let expected_output = reader.section()?;
+            cases.push((input, expected_output));
+        }
+    }
+    Ok(cases)
+}
 
-    if let Ok(file) = std::fs::File::open(ref_path) {
-        let mut file = BufReader::new(file);
-        let mut expected_output = vec![];
-        loop {
-            let mut line = "".to_owned();
-            if file.read_line(&mut line).unwrap() == 0 {
-                break;
-            }
-            fixup_newline(&mut line);
-            let bytes = line.as_bytes();
-            expected_output.extend(bytes.iter());
+// A reference result file holds the expected output as is.
+// The input is either in an optional header, which is "#!" followed by the input section,
+// or in a sibling input file.
+fn reference_case(
+    result_path: &std::path::Path,
+    input_path: &std::path::Path,
+) -> Result<Case, CaseError> {
+    let mut reader = CaseReader::open(result_path)?;
+    let mut expected_output = vec![];
+    let mut input = None;
+    if let Some(line) = reader.next_line()? {
+        if line == "#!\n" {
+            input = Some(reader.section()?);
+        } else {
+            expected_output.extend(line.as_bytes());
         }
+    }
+    expected_output.extend(reader.rest()?);
 
-        return Box::new(std::iter::once((vec![], expected_output)));
+    if input_path.exists() {
+        if input.is_some() {
+            return Err(CaseError {
+                path: result_path.to_owned(),
+                line: 1,
+                message: format!(
+                    "Input is given both in the header and in {}",
+                    input_path.display()
+                ),
+            });
+        }
+        input = Some(std::fs::read(input_path).map_err(|e| CaseError {
+            path: input_path.to_owned(),
+            line: 0,
+            message: e.to_string(),
+        })?);
     }
+    Ok((input.unwrap_or_default(), expected_output))
+}
 
-    Box::new(IntegratedCases {
-        file: BufReader::new(std::fs::File::open(file_path).unwrap()),
-    })
+// Cases of a program are either reference results next to it,
+// in name.py.ast.typed.s.result (input in name.py.input) and
+// in name.py.N.result (input in name.py.N.input) for N = 1, 2, ...,
+// or integrated in the program itself if there is no reference result
+fn get_cases(file_path: &std::path::Path) -> Result<Vec<Case>, CaseError> {
+    let sibling = |suffix: &str| {
+        let mut path = file_path.to_path_buf();
+        path.set_file_name(file_path.file_name().unwrap().to_str().unwrap().to_owned() + suffix);
+        path
+    };
+
+    let mut cases = vec![];
+    let result_path = sibling(".ast.typed.s.result");
+    if result_path.exists() {
+        cases.push(reference_case(&result_path, &sibling(".input"))?);
+    }
+    for n in 1.. {
+        let result_path = sibling(&format!(".{}.result", n));
+        if !result_path.exists() {
+            break;
+        }
+        cases.push(reference_case(
+            &result_path,
+            &sibling(&format!(".{}.input", n)),
+        )?);
+    }
+
+    if cases.is_empty() {
+        integrated_cases(file_path)
+    } else {
+        Ok(cases)
+    }
 }
 
 #[derive(Debug)]
 struct TestFail {
+    expected_output: Vec<u8>,
     output: Vec<u8>,
 }
 
 impl std::fmt::Display for TestFail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Output is wrong. Actual output:")?;
-        writeln!(f, "{}", String::from_utf8_lossy(&self.output))?;
-        Ok(())
+        writeln!(f, "Output is wrong.")?;
+        write!(
+            f,
+            "{}",
+            diff::format_diff(&self.expected_output, &self.output)
+        )
     }
 }
 
@@ -107,30 +223,196 @@ fn test_one_case(
     let mut process = command
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
         .spawn()?;
 
-    let stdin = process.stdin.as_mut().unwrap();
-    let stdout = process.stdout.as_mut().unwrap();
-
-    let mut actual_output = vec![];
+    // Close stdin after the input, so that reading more gets end of file instead of blocking
+    let mut stdin = process.stdin.take().unwrap();
     stdin.write_all(input)?;
-    stdout.read_to_end(&mut actual_output)?;
-    process.wait()?;
+    drop(stdin);
+    let output = process.wait_with_output()?;
+
+    // Runtime errors are printed to stderr when the program exits,
+    // so the expected output has them after everything on stdout
+    let mut actual_output = output.stdout;
+    actual_output.extend_from_slice(&output.stderr);
     if expected_output == &actual_output[..] {
         Ok(())
     } else {
         Err(Box::new(TestFail {
+            expected_output: expected_output.to_vec(),
             output: actual_output,
         }))
     }
 }
 
-fn main() {
-    let temp_path = std::env::temp_dir();
+fn list_sources(dir: &str) -> Vec<std::path::PathBuf> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|f| f.unwrap())
+        .filter(|f| f.file_name().to_str().unwrap().ends_with(".py"))
+        .map(|f| f.path())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+// Compile each program once, with additional compiler arguments, and time its test cases over
+// several runs
+fn bench(dir: &str, compiler_path: &std::path::Path, runs: u32, compiler_args: &[String]) {
+    println!("Benchmarking Directory {} ({} runs)", dir, runs);
+    let exe_path = temp::create("").unwrap().0;
+
+    for file_path in list_sources(dir) {
+        let file_name = file_path.file_name().unwrap().to_owned();
+        assert!(std::process::Command::new(compiler_path)
+            .arg(&file_path)
+            .arg(&exe_path)
+            .args(compiler_args)
+            .spawn()
+            .unwrap()
+            .wait()
+            .unwrap()
+            .success());
+
+        let mut times = vec![];
+        for _ in 0..runs {
+            let mut elapsed = std::time::Duration::default();
+            let cases = get_cases(&file_path).unwrap_or_else(|e| panic!("{}", e));
+            for (input, expected_output) in cases {
+                let start = std::time::Instant::now();
+                let command = std::process::Command::new(&exe_path);
+                if let Err(e) = test_one_case(command, &input, &expected_output) {
+                    panic!("{}: {}", file_name.to_str().unwrap(), e);
+                }
+                elapsed += start.elapsed();
+            }
+            times.push(elapsed);
+        }
+        std::fs::remove_file(&exe_path).unwrap();
+
+        let min = times.iter().min().unwrap();
+        let mean = times.iter().sum::<std::time::Duration>() / runs;
+        println!(
+            "{:<32} min {:>9.3} ms    mean {:>9.3} ms",
+            file_name.to_str().unwrap(),
+            min.as_secs_f64() * 1000.0,
+            mean.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+// Quote a word for the repro script of the current platform
+fn quote(word: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", word)
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+// Keep the input and outputs of a failed case in `dir`, along with a script that runs
+// `command` in `dir` with the same input
+fn keep_failure(
+    dir: &std::path::Path,
+    case_name: &str,
+    command: &[String],
+    input: &[u8],
+    error: &(dyn std::error::Error + 'static),
+) -> std::io::Result<()> {
+    let input_name = format!("{}.input", case_name);
+    std::fs::write(dir.join(&input_name), input)?;
+    if let Some(fail) = error.downcast_ref::<TestFail>() {
+        std::fs::write(
+            dir.join(format!("{}.expected", case_name)),
+            &fail.expected_output,
+        )?;
+        std::fs::write(dir.join(format!("{}.actual", case_name)), &fail.output)?;
+    }
+
+    let command = command
+        .iter()
+        .map(|word| quote(word))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let (script_name, script) = if cfg!(windows) {
+        (
+            format!("{}.bat", case_name),
+            format!(
+                "@echo off\r\ncd /d \"%~dp0\"\r\n{} < {}\r\n",
+                command,
+                quote(&input_name)
+            ),
+        )
+    } else {
+        (
+            format!("{}.sh", case_name),
+            format!(
+                "#!/bin/sh\ncd \"$(dirname \"$0\")\"\nexec {} < {}\n",
+                command,
+                quote(&input_name)
+            ),
+        )
+    };
+    let script_path = dir.join(script_name);
+    std::fs::write(&script_path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    println!("Reproduce with {}", script_path.display());
+    Ok(())
+}
+
+// Run the tests and return whether all of them passed. Panics on errors of the harness itself.
+fn run() -> bool {
+    let mut args: Vec<_> = std::env::args().collect();
+
+    // --keep-failures <dir> can be given after any other option
+    let keep_failures = args
+        .iter()
+        .position(|arg| arg == "--keep-failures")
+        .map(|i| {
+            let dir = std::path::PathBuf::from(
+                args.get(i + 1)
+                    .expect("Directory required for --keep-failures"),
+            );
+            args.drain(i..i + 2);
+            std::fs::create_dir_all(&dir).expect("Failed to create directory for failures");
+            dir
+        });
+
+    // --seed <N> makes the names of temporary files the same in every run, including
+    // those of the compiler, which reads the seed from the environment
+    if let Some(i) = args.iter().position(|arg| arg == "--seed") {
+        let seed = args.get(i + 1).expect("Seed required for --seed");
+        temp::seed(seed.parse().expect("Invalid seed"));
+        std::env::set_var(temp::SEED_VAR, seed);
+        args.drain(i..i + 2);
+    }
+
+    // --gc-final-check makes every compiled program check its garbage collector at exit.
+    // Objects it lost track of are printed, which fails the case.
+    if let Some(i) = args.iter().position(|arg| arg == "--gc-final-check") {
+        std::env::set_var("CHOCOPY_GC_FINAL_CHECK", "1");
+        args.remove(i);
+    }
 
-    let args: Vec<_> = std::env::args().collect();
     let dir = args.get(1).expect("Path required");
     let option = args.get(2).map(|s| s.as_str());
+
+    let mut compiler_path = std::env::current_exe().unwrap();
+    compiler_path.set_file_name("chocopy-rs");
+
+    if option == Some("--bench") {
+        let runs = args
+            .get(3)
+            .map_or(5, |s| s.parse().expect("Invalid run count"));
+        assert!(runs > 0);
+        bench(dir, &compiler_path, runs, args.get(4..).unwrap_or(&[]));
+        return true;
+    }
     let python = option == Some("--python");
     let static_lib = option == Some("--static");
     let python_command;
@@ -153,51 +435,74 @@ fn main() {
         println!("Testing using chocopy compiler");
     }
 
-    let mut compiler_path = std::env::current_exe().unwrap();
-    compiler_path.set_file_name("chocopy-rs");
-
     let mut passed = 0;
     let mut total = 0;
 
     println!("Testing Directory {}", dir);
-    let mut files = std::fs::read_dir(dir)
-        .unwrap()
-        .map(|f| f.unwrap())
-        .filter(|f| f.file_name().to_str().unwrap().ends_with(".py"))
-        .map(|f| f.path())
-        .collect::<Vec<_>>();
-    files.sort();
-    for file_path in files {
+    for file_path in list_sources(dir) {
         let file_name = file_path.file_name().unwrap().to_owned();
         println!("Testing {}", file_name.to_str().unwrap());
-        let exe_file = format!("chocopy-{}", rand::random::<u32>());
-        let mut exe_path = temp_path.clone();
-        exe_path.push(exe_file);
+        let exe_path = temp::create("").unwrap().0;
 
-        if !python {
-            assert!(std::process::Command::new(&compiler_path)
+        if !python
+            && !std::process::Command::new(&compiler_path)
                 .arg(&file_path)
                 .arg(&exe_path)
                 .spawn()
                 .unwrap()
                 .wait()
                 .unwrap()
-                .success());
+                .success()
+        {
+            println!("\x1b[31mError\x1b[0m Compilation failed");
+            total += 1;
+            continue;
         }
+        let name = file_path.file_stem().unwrap().to_str().unwrap();
+        let mut exe_kept = false;
+
+        let cases = match get_cases(&file_path) {
+            Ok(cases) => cases,
+            Err(e) => {
+                println!("\x1b[31mError\x1b[0m {}", e);
+                total += 1;
+                if !python {
+                    std::fs::remove_file(exe_path).unwrap();
+                }
+                continue;
+            }
+        };
 
         let mut no_case = true;
-        for (case, (input, expected_output)) in get_cases(&file_path).enumerate() {
+        for (case, (input, expected_output)) in cases.into_iter().enumerate() {
             no_case = false;
             print!("Case {} ---- ", case);
 
+            // The same command as run in the kept failure directory
+            let mut repro_command;
             let command = if python {
                 let mut p = std::process::Command::new(python_command.unwrap());
                 p.arg(&file_path);
+                repro_command = vec![
+                    python_command.unwrap().to_owned(),
+                    std::fs::canonicalize(&file_path)
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_owned(),
+                ];
                 p
             } else {
                 let mut command = std::process::Command::new(&exe_path);
+                let exe_name = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+                repro_command = vec![if cfg!(windows) {
+                    exe_name
+                } else {
+                    format!("./{}", exe_name)
+                }];
                 if static_lib {
                     command.arg("--static");
+                    repro_command.push("--static".to_owned());
                 }
                 command
             };
@@ -209,6 +514,22 @@ fn main() {
                 }
                 Err(e) => {
                     println!("\x1b[31mError\x1b[0m {}", e);
+                    if let Some(keep_dir) = &keep_failures {
+                        if !python && !exe_kept {
+                            let exe_name = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+                            std::fs::copy(&exe_path, keep_dir.join(exe_name))
+                                .expect("Failed to keep executable");
+                            exe_kept = true;
+                        }
+                        keep_failure(
+                            keep_dir,
+                            &format!("{}.case{}", name, case),
+                            &repro_command,
+                            &input,
+                            &*e,
+                        )
+                        .expect("Failed to keep failure");
+                    }
                 }
             }
 
@@ -225,5 +546,63 @@ fn main() {
     }
 
     println!("Passed / Total: {} / {}", passed, total);
-    assert_eq!(passed, total)
+    passed == total
+}
+
+fn main() {
+    let code = match std::panic::catch_unwind(run) {
+        Ok(true) => EXIT_PASSED,
+        Ok(false) => EXIT_FAILED,
+        Err(_) => EXIT_HARNESS_ERROR,
+    };
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cases(name: &str) -> Result<Vec<Case>, CaseError> {
+        get_cases(std::path::Path::new(&format!("test/cases/{}", name)))
+    }
+
+    fn case(input: &str, expected_output: &str) -> Case {
+        (
+            input.as_bytes().to_vec(),
+            expected_output.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn integrated() {
+        assert_eq!(
+            cases("integrated.py").unwrap(),
+            vec![case("a\n", "a\n"), case("", "\n")]
+        );
+        assert_eq!(cases("crlf.py").unwrap(), vec![case("c\n", "c\n")]);
+    }
+
+    #[test]
+    fn reference() {
+        assert_eq!(cases("plain.py").unwrap(), vec![case("", "1\n")]);
+        assert_eq!(cases("sibling.py").unwrap(), vec![case("x\n", "x\n")]);
+        assert_eq!(cases("header.py").unwrap(), vec![case("y\n", "y\n")]);
+        assert_eq!(
+            cases("numbered.py").unwrap(),
+            vec![case("p\n", "p\n"), case("q\n", "q\n"), case("", "\n")]
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let line = |name| cases(name).unwrap_err().line;
+        assert_eq!(line("bad_prefix.py"), 5);
+        assert_eq!(line("unterminated.py"), 6);
+        assert_eq!(line("both.py"), 1);
+        assert_eq!(line("bad_header.py"), 3);
+
+        let error = cases("blank_line.py").unwrap_err();
+        assert_eq!(error.line, 8);
+        assert!(error.message.starts_with("Blank line"), "{}", error.message);
+    }
 }
@@ -0,0 +1,8 @@
+print(input())
+
+#!
+#a
+pass
+#<->#
+#a
+#<->#
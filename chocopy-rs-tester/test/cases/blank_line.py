@@ -0,0 +1,9 @@
+print(input())
+print("")
+
+#!
+#a
+#<->#
+#a
+
+#<->#
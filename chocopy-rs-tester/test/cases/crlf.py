@@ -0,0 +1,7 @@
+print(input())
+
+#!
+#c
+#<->#
+#c
+#<->#
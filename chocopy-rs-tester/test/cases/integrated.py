@@ -0,0 +1,12 @@
+print(input())
+
+#!
+#a
+#<->#
+#a
+#<->#
+
+#!
+#<->#
+#
+#<->#
@@ -0,0 +1,6 @@
+print(input())
+
+#!
+#a
+#<->#
+#a
@@ -0,0 +1,76 @@
+// Embeds the standard library and the C runtime into the compiler for the `embedded-runtime`
+// feature. The standard library must be built before, either in the same target directory or at
+// the path in CHOCOPY_STD_LIB.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// C runtime files that `cc -static` links with, before and after the program and the libraries
+const START_FILES: [&str; 3] = ["crt1.o", "crti.o", "crtbeginT.o"];
+const LIBRARIES: [&str; 3] = ["libgcc.a", "libgcc_eh.a", "libc.a"];
+const END_FILES: [&str; 2] = ["crtend.o", "crtn.o"];
+
+// Find a file of the C runtime with the system compiler
+fn c_runtime_file(name: &str) -> PathBuf {
+    let output = Command::new("cc")
+        .arg(format!("-print-file-name={}", name))
+        .output()
+        .expect("Failed to run cc");
+    // The name is printed as it is if the file is not found
+    let path = PathBuf::from(String::from_utf8(output.stdout).unwrap().trim());
+    if !path.is_file() {
+        panic!(
+            "{} not found. The static C library needs to be installed",
+            name
+        );
+    }
+    path
+}
+
+fn include_list(name: &str, paths: &[PathBuf]) -> String {
+    let mut list = format!("pub const {}: [&[u8]; {}] = [\n", name, paths.len());
+    for path in paths {
+        println!("cargo:rerun-if-changed={}", path.display());
+        list += &format!("    include_bytes!({:?}),\n", path.to_str().unwrap());
+    }
+    list + "];\n"
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if std::env::var_os("CARGO_FEATURE_EMBEDDED_RUNTIME").is_none() {
+        return;
+    }
+    println!("cargo:rerun-if-env-changed=CHOCOPY_STD_LIB");
+
+    // OUT_DIR is target/<profile>/build/<package>/out
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    let std_lib = std::env::var_os("CHOCOPY_STD_LIB")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            out_dir
+                .ancestors()
+                .nth(3)
+                .unwrap()
+                .join("libchocopy_rs_std.a")
+        });
+    if !std_lib.is_file() {
+        panic!(
+            "{} not found. Build chocopy-rs-std first, or set CHOCOPY_STD_LIB",
+            std_lib.display()
+        );
+    }
+
+    let files = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| c_runtime_file(name))
+            .collect::<Vec<_>>()
+    };
+    let mut archives = vec![std_lib];
+    archives.extend(files(&LIBRARIES));
+    let runtime = include_list("START_FILES", &files(&START_FILES))
+        + &include_list("ARCHIVES", &archives)
+        + &include_list("END_FILES", &files(&END_FILES));
+    std::fs::write(Path::new(&out_dir).join("runtime.rs"), runtime).unwrap();
+}
@@ -0,0 +1,466 @@
+// Builds a directory of ChocoPy files as separate object files linked together.
+//
+// One file (the main unit) owns all global variables and top-level statements.
+// Other files (library units) only contain functions and classes, and are
+// checked in file name order, so a library unit can use declarations from
+// library units before it. The main unit is checked last and can use all of them.
+//
+// Object files are cached in a sub-directory along with a hash of everything
+// they depend on: the source file itself, the language extensions and compile
+// options, and the signatures of the declarations from earlier units. Only units
+// whose hash changed are regenerated.
+
+use crate::gen::{self, CompileOptions, Platform, Unit};
+use crate::node::*;
+use crate::{check, check_error, parse, CodeError};
+use md5::{Digest, Md5};
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::path::*;
+
+const CACHE_DIR: &str = ".chocopy-build";
+
+// An object file of a built unit
+pub struct BuildObject {
+    pub source: PathBuf,
+    pub object: PathBuf,
+    pub rebuilt: bool, // false if the cached object file was reused
+}
+
+#[derive(Debug)]
+struct BuildError(String);
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Build failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+// Write the part of declarations that other units depend on. This includes initial values of
+// attributes, which constructors store into new objects
+fn write_signature(declarations: &[Declaration], indent: &str, out: &mut String) {
+    let type_str = |t| ValueType::from_annotation(t).to_string();
+    for declaration in declarations {
+        match declaration {
+            Declaration::VarDef(v) => {
+                let value = match &v.value.content {
+                    LiteralContent::IntegerLiteral(i) => i.value.to_string(),
+                    LiteralContent::BooleanLiteral(b) => b.value.to_string(),
+                    LiteralContent::NoneLiteral(_) => "None".to_owned(),
+                    LiteralContent::StringLiteral(s) => format!("{:?}", s.value),
+                };
+                let _ = writeln!(
+                    out,
+                    "{}{}{}:{}={}",
+                    indent,
+                    if v.constant { "const " } else { "" },
+                    v.var.identifier.name,
+                    type_str(&v.var.type_),
+                    value
+                );
+            }
+            Declaration::FuncDef(f) => {
+                let params: Vec<_> = f.params.iter().map(|p| type_str(&p.type_)).collect();
+                let _ = writeln!(
+                    out,
+                    "{}def {}({})->{}",
+                    indent,
+                    f.name.name,
+                    params.join(","),
+                    type_str(&f.return_type)
+                );
+            }
+            Declaration::ClassDef(c) => {
+                let _ = writeln!(
+                    out,
+                    "{}class {}({})",
+                    indent, c.name.name, c.super_class.name
+                );
+                write_signature(&c.declarations, "  ", out);
+            }
+            _ => (),
+        }
+    }
+}
+
+// Feeds values hashed with `Hash` into an MD5 digest. Unlike `DefaultHasher`, the digest
+// doesn't change between runs of the compiler, so it can key cached object files
+struct Md5Hasher<'a>(&'a mut Md5);
+
+impl Hasher for Md5Hasher<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+}
+
+// Hash the compiler version and all options that change the code generated from a source
+pub fn hash_options(
+    md5: &mut Md5,
+    parse_options: parse::Options,
+    check_options: check::Options,
+    options: CompileOptions,
+    platform: Platform,
+) {
+    md5.update(env!("CARGO_PKG_VERSION"));
+    (platform, parse_options, check_options, options).hash(&mut Md5Hasher(md5));
+}
+
+// Hash of everything the object file of a unit depends on
+fn unit_hash(
+    source: &Path,
+    signature: &str,
+    parse_options: parse::Options,
+    check_options: check::Options,
+    options: CompileOptions,
+    platform: Platform,
+) -> std::io::Result<String> {
+    let mut md5 = Md5::new();
+    hash_options(&mut md5, parse_options, check_options, options, platform);
+    md5.update(signature);
+    md5.update(std::fs::read(source)?);
+    Ok(md5
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+// Compile all ChocoPy files in the directory into object files, reusing cached ones.
+// Returns the object files in link order.
+pub fn compile(
+    dir: &Path,
+    werror: bool,
+    parse_options: parse::Options,
+    check_options: check::Options,
+    options: CompileOptions,
+    platform: Platform,
+) -> std::result::Result<Vec<BuildObject>, Box<dyn std::error::Error>> {
+    let mut sources = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    sources.retain(|path| path.extension().is_some_and(|e| e == "py"));
+    sources.sort();
+
+    // Parse all files and find the main unit
+    let mut units = vec![];
+    let mut main = None;
+    for source in sources {
+        let source_str = source.to_str().ok_or(gen::PathError)?.to_owned();
+        let ast = parse::process(&source_str, parse_options)?;
+        if !check_error(&source_str, &ast, werror) {
+            return Err(CodeError.into());
+        }
+
+        let has_global = ast
+            .declarations
+            .iter()
+            .any(|d| matches!(d, Declaration::VarDef(_)));
+        if has_global || !ast.statements.is_empty() {
+            if let Some((main_source, _)) = &main {
+                return Err(BuildError(format!(
+                    "both {} and {} contain global variables or top-level statements",
+                    source_str, main_source
+                ))
+                .into());
+            }
+            main = Some((source_str, ast));
+        } else {
+            units.push((source_str, ast));
+        }
+    }
+
+    let main = main.ok_or_else(|| {
+        BuildError("no file contains global variables or top-level statements".to_owned())
+    })?;
+    units.push(main);
+    let main_index = units.len() - 1;
+
+    let cache_dir = dir.join(CACHE_DIR);
+    std::fs::create_dir_all(&cache_dir)?;
+
+    // Check each unit against the declarations of units before it
+    let mut external = vec![];
+    let mut objects = vec![];
+    let mut jobs = vec![];
+    for (i, (source_str, ast)) in units.into_iter().enumerate() {
+        let mut signature = String::new();
+        write_signature(&external, "", &mut signature);
+
+        let own = ast.declarations;
+        let external_declarations = external.len();
+        let merged = Program {
+            declarations: external
+                .iter()
+                .cloned()
+                .chain(own.iter().cloned())
+                .collect(),
+            ..ast
+        };
+        external.extend(own);
+
+        let mut typed = check::check(merged, check_options);
+        check::friendly(&mut typed);
+        if !check_error(&source_str, &typed, werror) {
+            return Err(CodeError.into());
+        }
+
+        let source = PathBuf::from(source_str);
+        let mut object = cache_dir.join(source.file_stem().unwrap());
+        object.set_extension("o");
+        let hash_path = object.with_extension("o.md5");
+        let hash = unit_hash(
+            &source,
+            &signature,
+            parse_options,
+            check_options,
+            options,
+            platform,
+        )?;
+        let rebuilt = !object.exists()
+            || std::fs::read_to_string(&hash_path).map_or(true, |cached| cached != hash);
+
+        if rebuilt {
+            let unit = Unit {
+                external_declarations,
+                main: i == main_index,
+                exported: true,
+            };
+            jobs.push((source.clone(), typed, unit, object.clone(), hash_path, hash));
+        }
+
+        objects.push(BuildObject {
+            source,
+            object,
+            rebuilt,
+        });
+    }
+
+    // Generate object files in parallel
+    let threads = jobs
+        .into_iter()
+        .map(|(source, typed, unit, object, hash_path, hash)| {
+            std::thread::Builder::new().stack_size(16_000_000).spawn(
+                move || -> Result<(), String> {
+                    let source_str = source.to_str().unwrap();
+                    let _ = std::fs::remove_file(&hash_path);
+                    gen::gen_unit_object(source_str, typed, unit, options, &object, platform)
+                        .map_err(|e| format!("{}: {}", source_str, e))?;
+                    std::fs::write(&hash_path, hash).map_err(|e| e.to_string())
+                },
+            )
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    for thread in threads {
+        thread
+            .join()
+            .map_err(|_| BuildError("code generation panicked".to_owned()))?
+            .map_err(BuildError)?;
+    }
+
+    Ok(objects)
+}
+
+// Compile all ChocoPy files in the directory and link them into an executable,
+// or bundle them with the standard library into a static library
+#[allow(clippy::too_many_arguments)]
+pub fn build(
+    dir: &str,
+    path: &str,
+    static_lib: bool,
+    archive: bool,
+    werror: bool,
+    parse_options: parse::Options,
+    check_options: check::Options,
+    options: CompileOptions,
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let dir = Path::new(dir);
+    let objects = compile(dir, werror, parse_options, check_options, options, platform)?;
+    for object in &objects {
+        if object.rebuilt {
+            println!("Compiled {}", object.source.display());
+        }
+    }
+    // Reused object files may have been replaced since they were generated.
+    // The main unit is the last one in link order
+    for (i, object) in objects.iter().enumerate() {
+        if !object.rebuilt {
+            gen::check_object(&object.object, i == objects.len() - 1, platform)?;
+        }
+    }
+    let obj_paths: Vec<_> = objects.iter().map(|o| o.object.as_path()).collect();
+    if archive {
+        gen::write_staticlib(&obj_paths, path, platform)
+    } else {
+        gen::link(&obj_paths, path, static_lib, None, platform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen::test_util;
+
+    const LIB: &str = "
+class Counter(object):
+    n:int = 0
+    def inc(self:\"Counter\") -> int:
+        self.n = self.n + 1
+        return self.n
+
+def twice(x:int) -> int:
+    return x * 2
+";
+
+    const MAIN: &str = "
+class Big(Counter):
+    def inc(self:\"Big\") -> int:
+        self.n = self.n + 10
+        return self.n
+
+class Same(Counter):
+    pass
+
+c:Counter = None
+c = Counter()
+c.inc()
+print(twice(c.inc()))
+c = Big()
+print(c.inc())
+c = Same()
+c.inc()
+print(c.inc())
+";
+
+    fn rebuilt(objects: &[BuildObject]) -> Vec<bool> {
+        objects.iter().map(|o| o.rebuilt).collect()
+    }
+
+    // Compile without extensions, with the default options for the native platform
+    fn compile_plain(dir: &Path) -> Result<Vec<BuildObject>, Box<dyn std::error::Error>> {
+        let (parse_options, check_options) = Default::default();
+        compile(
+            dir,
+            false,
+            parse_options,
+            check_options,
+            Default::default(),
+            crate::PLATFORM,
+        )
+    }
+
+    fn run(objects: &[BuildObject], expected: &str) {
+        let obj_paths: Vec<_> = objects.iter().map(|o| o.object.as_path()).collect();
+        if let Some(output) = test_util::link_and_run(&obj_paths, "") {
+            assert!(output.status.success());
+            assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn incremental() {
+        let dir = test_util::temp_path("");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("lib.py"), LIB).unwrap();
+        std::fs::write(dir.join("main.py"), MAIN).unwrap();
+
+        // The main unit is linked last regardless of its name
+        let objects = compile_plain(&dir).unwrap();
+        assert!(objects[1].source.ends_with("main.py"));
+        assert_eq!(rebuilt(&objects), [true, true]);
+        run(&objects, "4\n10\n2\n");
+
+        // Nothing changed
+        let objects = compile_plain(&dir).unwrap();
+        assert_eq!(rebuilt(&objects), [false, false]);
+
+        // Changing a function body only rebuilds its own unit
+        std::fs::write(dir.join("lib.py"), LIB.replace("x * 2", "x * 3")).unwrap();
+        let objects = compile_plain(&dir).unwrap();
+        assert_eq!(rebuilt(&objects), [true, false]);
+        run(&objects, "6\n10\n2\n");
+
+        // Changing an attribute initializer rebuilds units constructing the class
+        std::fs::write(dir.join("lib.py"), LIB.replace("n:int = 0", "n:int = 5")).unwrap();
+        let objects = compile_plain(&dir).unwrap();
+        assert_eq!(rebuilt(&objects), [true, true]);
+        run(&objects, "14\n15\n7\n");
+        std::fs::write(dir.join("lib.py"), LIB).unwrap();
+        let objects = compile_plain(&dir).unwrap();
+        assert_eq!(rebuilt(&objects), [true, true]);
+        run(&objects, "4\n10\n2\n");
+
+        // Changing a signature rebuilds units depending on it
+        std::fs::write(dir.join("lib.py"), LIB.to_owned() + "x:int = 0\n").unwrap();
+        assert!(compile_plain(&dir).is_err());
+        std::fs::write(
+            dir.join("lib.py"),
+            LIB.to_owned() + "def f() -> int:\n    return 0\n",
+        )
+        .unwrap();
+        let objects = compile_plain(&dir).unwrap();
+        assert_eq!(rebuilt(&objects), [true, true]);
+        run(&objects, "4\n10\n2\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extensions() {
+        let dir = test_util::temp_path("");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(
+            dir.join("lib.py"),
+            "def mask(x:int) -> int:\n    return x & 3\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("main.py"), "print(mask(6))\nprint([1] == [1])\n").unwrap();
+        assert!(compile_plain(&dir).is_err());
+
+        let parse_options = parse::Options {
+            bitwise: true,
+            ..Default::default()
+        };
+        let mut check_options = check::Options {
+            list_eq: true,
+            ..Default::default()
+        };
+        let platform = crate::PLATFORM;
+        let objects = compile(
+            &dir,
+            false,
+            parse_options,
+            check_options,
+            Default::default(),
+            platform,
+        )
+        .unwrap();
+        assert_eq!(rebuilt(&objects), [true, true]);
+        run(&objects, "2\nTrue\n");
+
+        // Enabling another extension rebuilds all units
+        check_options.membership = true;
+        let objects = compile(
+            &dir,
+            false,
+            parse_options,
+            check_options,
+            Default::default(),
+            platform,
+        )
+        .unwrap();
+        assert_eq!(rebuilt(&objects), [true, true]);
+        run(&objects, "2\nTrue\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+// Caches the object file of a single-file compilation.
+//
+// The object file is stored in the cache directory under a hash of everything
+// the generated code depends on: the source file, its path (which is recorded
+// in the debug info), the language extensions, the code generation options and
+// the platform. A compilation with the same hash skips parsing, type checking
+// and code generation, and only links the cached object file.
+
+use crate::gen::{self, CompileOptions, Platform};
+use crate::node::*;
+use crate::{build, check, parse};
+use chocopy_rs_common::temp;
+use md5::{Digest, Md5};
+use std::path::*;
+
+pub struct Cache {
+    object: PathBuf,
+    options: CompileOptions,
+    platform: Platform,
+}
+
+impl Cache {
+    pub fn new(
+        dir: &Path,
+        source: &str,
+        parse_options: parse::Options,
+        check_options: check::Options,
+        options: CompileOptions,
+        platform: Platform,
+    ) -> std::io::Result<Cache> {
+        let mut md5 = Md5::new();
+        build::hash_options(&mut md5, parse_options, check_options, options, platform);
+        md5.update(source);
+        md5.update([0]);
+        md5.update(std::fs::read(source)?);
+        let hash: String = md5
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        std::fs::create_dir_all(dir)?;
+        Ok(Cache {
+            object: dir.join(hash + ".o"),
+            options,
+            platform,
+        })
+    }
+
+    // Whether an object file was cached for the same hash
+    pub fn hit(&self) -> bool {
+        self.object.exists()
+    }
+
+    pub fn object(&self) -> &Path {
+        &self.object
+    }
+
+    // Generate the object file into the cache
+    pub fn store(
+        &self,
+        source: &str,
+        ast: Program,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        // Write to a file of our own first, so that a concurrent compilation
+        // never sees an incomplete object file
+        let dir = self.object.parent().unwrap_or_else(|| Path::new("."));
+        let (partial, _) = temp::create_in(dir, ".o.tmp")?;
+        if let Err(e) = gen::gen_object(source, ast, self.options, &partial, self.platform) {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+        std::fs::rename(&partial, &self.object)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen::test_util;
+
+    fn cache(dir: &Path, source: &Path, options: CompileOptions) -> Cache {
+        let source = source.to_str().unwrap();
+        Cache::new(
+            dir,
+            source,
+            Default::default(),
+            Default::default(),
+            options,
+            crate::PLATFORM,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reuse() {
+        let dir = test_util::temp_path("");
+        let (source, ast) = test_util::check_source("print(1 + 2)\n");
+
+        let first = cache(&dir, &source, Default::default());
+        assert!(!first.hit());
+        first.store(source.to_str().unwrap(), ast).unwrap();
+
+        // A second compilation of the same source finds the object file
+        let second = cache(&dir, &source, Default::default());
+        assert!(second.hit());
+        assert_eq!(second.object(), first.object());
+        if let Some(output) = test_util::link_and_run(&[second.object()], "") {
+            assert!(output.status.success());
+            assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "3\n");
+        }
+
+        // Options affecting the generated code are part of the hash
+        let options = CompileOptions {
+            opt_level: 2,
+            ..Default::default()
+        };
+        assert!(!cache(&dir, &source, options).hit());
+
+        std::fs::write(&source, "print(1 + 3)\n").unwrap();
+        assert!(!cache(&dir, &source, Default::default()).hit());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&source).unwrap();
+    }
+}
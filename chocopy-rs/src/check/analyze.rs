@@ -2,19 +2,35 @@
 
 use super::class_env::*;
 use super::error::*;
+use super::types::*;
+use crate::explain;
 use crate::local_env::*;
 use crate::node::*;
 use std::collections::HashMap;
 
-type TypeLocalEnv = LocalEnv<FuncType, ValueType>;
+type TypeLocalEnv = LocalEnv<Signature, TypeId>;
+
+// Prepare the friendly wording of the last error, showing the ancestors of a class that was given
+// where an unrelated class is expected
+fn prepare_ancestry(errors: &mut [CompilerError], m: &ClassEnv, got: TypeId, expected: TypeId) {
+    if let Some(ancestry) = m.ancestry(got, expected) {
+        let error = errors.last_mut().unwrap();
+        error.friendly = Some(friendly_ancestry(
+            &error.message,
+            m.types.value_type(got),
+            &ancestry,
+            m.types.value_type(expected),
+        ));
+    }
+}
 
 impl Expr {
     pub fn analyze(
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
         let inferred_type = match &mut self.content {
             ExprContent::BinaryExpr(s) => s.analyze(errors, o, m),
             ExprContent::IntegerLiteral(s) => s.analyze(errors, o, m),
@@ -30,7 +46,7 @@ impl Expr {
             ExprContent::StringLiteral(s) => s.analyze(errors, o, m),
             ExprContent::UnaryExpr(s) => s.analyze(errors, o, m),
         };
-        self.inferred_type = Some(inferred_type.clone());
+        self.inferred_type = Some(m.types.value_type(inferred_type).clone());
         inferred_type
     }
 }
@@ -40,15 +56,15 @@ impl Literal {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
         let inferred_type = match &mut self.content {
             LiteralContent::IntegerLiteral(s) => s.analyze(errors, o, m),
             LiteralContent::BooleanLiteral(s) => s.analyze(errors, o, m),
             LiteralContent::NoneLiteral(s) => s.analyze(errors, o, m),
             LiteralContent::StringLiteral(s) => s.analyze(errors, o, m),
         };
-        self.inferred_type = Some(inferred_type.clone());
+        self.inferred_type = Some(m.types.value_type(inferred_type).clone());
         inferred_type
     }
 }
@@ -59,15 +75,34 @@ impl Variable {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        _m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
         match o.get(&self.name) {
+            // Extension: a function as a value. Built-in functions and constructors aren't
+            // compiled as functions that can be called through a value, and nested functions
+            // would need their static link
+            Some(EnvSlot::Func(f)) if m.options().func_values => {
+                let top_level = match o.get_global(&self.name) {
+                    Some(EnvSlot::Func(g)) => std::ptr::eq(f, g),
+                    _ => false,
+                };
+                if top_level
+                    && !m.contains(&self.name)
+                    && !super::BUILTIN_FUNCTIONS.contains(&self.name.as_str())
+                {
+                    m.types.func(f.clone())
+                } else {
+                    let msg = error_func_value(&self.name);
+                    self.add_error(errors, msg);
+                    TypeId::OBJECT
+                }
+            }
             None | Some(EnvSlot::Func(_)) => {
                 let msg = error_variable(&self.name);
-                self.add_error(errors, msg);
-                TYPE_OBJECT.clone()
+                self.add_coded_error(errors, explain::UNDEFINED_VARIABLE, msg);
+                TypeId::OBJECT
             }
-            Some(EnvSlot::Var(t, _)) => t.clone(),
+            Some(EnvSlot::Var(t, _)) => *t,
         }
     }
 }
@@ -77,39 +112,55 @@ impl AssignStmt {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-        _r: Option<&ValueType>,
+        m: &mut ClassEnv,
+        _r: Option<TypeId>,
     ) {
-        let right: ValueType = self.value.analyze(errors, o, m);
+        let right = self.value.analyze(errors, o, m);
 
         // We don't do `for target in &mut self.targets` because of mut ref conflict
         for i in 0..self.targets.len() {
-            let left: ValueType = self.targets[i].analyze(errors, o, m);
+            let left = self.targets[i].analyze(errors, o, m);
             match &self.targets[i].content {
-                ExprContent::Variable(Variable { name, .. }) => {
-                    if let Some(EnvSlot::Var(_, Assignable(false))) = o.get(name) {
+                ExprContent::Variable(Variable { name, .. }) => match o.get(name) {
+                    Some(EnvSlot::Var(_, Assignable(false))) => {
                         let msg = error_nonlocal_assign(name);
                         self.targets[i].add_error(errors, msg);
                     }
-                }
-                ExprContent::IndexExpr(index_expr) => {
-                    if index_expr.list.get_type() == &*TYPE_STR
-                        && self.targets[i].base().error_msg.is_none()
+                    // Functions used as values can't be assigned to (extension)
+                    Some(EnvSlot::Func(_)) if self.targets[i].base().error_msg.is_none() => {
+                        let msg = error_variable(name);
+                        self.targets[i].add_coded_error(errors, explain::UNDEFINED_VARIABLE, msg);
+                    }
+                    _ => (),
+                },
+                ExprContent::MemberExpr(member_expr) => {
+                    if let ValueType::ClassValueType(ClassValueType { class_name }) =
+                        member_expr.object.get_type()
                     {
-                        let msg = error_str_index_assign();
-                        self.targets[i].add_error(errors, msg);
+                        if m.is_constant(class_name, &member_expr.member.name) {
+                            let msg = error_constant_assign(&member_expr.member.name);
+                            self.targets[i].add_error(errors, msg);
+                        }
                     }
                 }
+                ExprContent::IndexExpr(index_expr)
+                    if index_expr.list.get_type() == &*TYPE_STR
+                        && self.targets[i].base().error_msg.is_none() =>
+                {
+                    let msg = error_str_index_assign();
+                    self.targets[i].add_error(errors, msg);
+                }
                 _ => (),
             }
 
-            if !m.is_compatible(&right, &left) && self.base.error_msg.is_none() {
-                let msg = error_assign(&left, &right);
-                self.add_error(errors, msg);
+            if !m.is_compatible(right, left) && self.base.error_msg.is_none() {
+                let msg = error_assign(m.types.value_type(left), m.types.value_type(right));
+                self.add_coded_error(errors, explain::TYPE_ASSIGN, msg);
+                prepare_ancestry(errors, m, right, left);
             }
         }
 
-        if self.targets.len() > 1 && right == *TYPE_NONE_LIST && self.base().error_msg.is_none() {
+        if self.targets.len() > 1 && right == TypeId::NONE_LIST && self.base().error_msg.is_none() {
             let msg = error_multi_assign();
             self.add_error(errors, msg);
         }
@@ -117,12 +168,17 @@ impl AssignStmt {
 }
 
 impl VarDef {
-    pub fn analyze(&mut self, errors: &mut Vec<CompilerError>, o: &mut TypeLocalEnv, m: &ClassEnv) {
+    pub fn analyze(
+        &mut self,
+        errors: &mut Vec<CompilerError>,
+        o: &mut TypeLocalEnv,
+        m: &mut ClassEnv,
+    ) {
         let right = self.value.analyze(errors, o, m);
-        let left = ValueType::from_annotation(&self.var.type_);
-        if !m.is_compatible(&right, &left) {
-            let msg = error_assign(&left, &right);
-            self.add_error(errors, msg);
+        let left = m.types.annotation_type(&self.var.type_);
+        if !m.is_compatible(right, left) {
+            let msg = error_assign(m.types.value_type(left), m.types.value_type(right));
+            self.add_coded_error(errors, explain::TYPE_ASSIGN, msg);
         }
     }
 }
@@ -132,8 +188,8 @@ impl ExprStmt {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-        _r: Option<&ValueType>,
+        m: &mut ClassEnv,
+        _r: Option<TypeId>,
     ) {
         self.expr.analyze(errors, o, m);
     }
@@ -144,9 +200,9 @@ impl BooleanLiteral {
         &mut self,
         _errors: &mut Vec<CompilerError>,
         _o: &mut TypeLocalEnv,
-        _m: &ClassEnv,
-    ) -> ValueType {
-        TYPE_BOOL.clone()
+        _m: &mut ClassEnv,
+    ) -> TypeId {
+        TypeId::BOOL
     }
 }
 
@@ -155,9 +211,9 @@ impl IntegerLiteral {
         &mut self,
         _errors: &mut Vec<CompilerError>,
         _o: &mut TypeLocalEnv,
-        _m: &ClassEnv,
-    ) -> ValueType {
-        TYPE_INT.clone()
+        _m: &mut ClassEnv,
+    ) -> TypeId {
+        TypeId::INT
     }
 }
 
@@ -166,9 +222,9 @@ impl StringLiteral {
         &mut self,
         _errors: &mut Vec<CompilerError>,
         _o: &mut TypeLocalEnv,
-        _m: &ClassEnv,
-    ) -> ValueType {
-        TYPE_STR.clone()
+        _m: &mut ClassEnv,
+    ) -> TypeId {
+        TypeId::STR
     }
 }
 
@@ -177,9 +233,9 @@ impl NoneLiteral {
         &mut self,
         _errors: &mut Vec<CompilerError>,
         _o: &mut TypeLocalEnv,
-        _m: &ClassEnv,
-    ) -> ValueType {
-        TYPE_NONE.clone()
+        _m: &mut ClassEnv,
+    ) -> TypeId {
+        TypeId::NONE
     }
 }
 
@@ -188,121 +244,170 @@ impl UnaryExpr {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
-        let operand: ValueType = self.operand.analyze(errors, o, m);
+        m: &mut ClassEnv,
+    ) -> TypeId {
+        let operand = self.operand.analyze(errors, o, m);
         match self.operator {
             UnaryOp::Negative => {
-                if operand != *TYPE_INT {
-                    let msg = error_unary("-", &operand);
+                if operand != TypeId::INT {
+                    let msg = error_unary("-", m.types.value_type(operand));
                     self.add_error(errors, msg);
                 }
-                TYPE_INT.clone()
+                TypeId::INT
             }
             UnaryOp::Not => {
-                if operand != *TYPE_BOOL {
-                    let msg = error_unary("not", &operand);
+                if operand != TypeId::BOOL {
+                    let msg = error_unary("not", m.types.value_type(operand));
                     self.add_error(errors, msg);
                 }
-                TYPE_BOOL.clone()
+                TypeId::BOOL
             }
         }
     }
 }
 
+// Element type of a list that can be compared element-wise with the list equality extension,
+// or Some(None) for the empty list, which can be compared with any of them
+fn comparable_element(types: &TypeInterner, t: TypeId) -> Option<Option<TypeId>> {
+    match types.get(t) {
+        TypeData::List(element @ (TypeId::INT | TypeId::BOOL | TypeId::STR)) => Some(Some(element)),
+        _ if t == TypeId::EMPTY => Some(None),
+        _ => None,
+    }
+}
+
+fn list_comparable(types: &TypeInterner, left: TypeId, right: TypeId) -> bool {
+    match (
+        comparable_element(types, left),
+        comparable_element(types, right),
+    ) {
+        (Some(Some(l)), Some(Some(r))) => l == r,
+        (Some(_), Some(_)) => true,
+        _ => false,
+    }
+}
+
+// Objects of the same user-defined class, which the class equality extension compares
+// attribute by attribute
+fn class_comparable(types: &TypeInterner, left: TypeId, right: TypeId) -> bool {
+    let special = [
+        TypeId::OBJECT,
+        TypeId::NONE,
+        TypeId::EMPTY,
+        TypeId::STR,
+        TypeId::INT,
+        TypeId::BOOL,
+    ];
+    left == right && matches!(types.get(left), TypeData::Class(_)) && !special.contains(&left)
+}
+
 impl BinaryExpr {
     pub fn analyze(
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
-        let left: ValueType = self.left.analyze(errors, o, m);
-        let right: ValueType = self.right.analyze(errors, o, m);
+        m: &mut ClassEnv,
+    ) -> TypeId {
+        let left = self.left.analyze(errors, o, m);
+        let right = self.right.analyze(errors, o, m);
 
         let mut error = false;
         let output = match self.operator {
-            BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
-                if left != *TYPE_INT || right != *TYPE_INT {
+            BinaryOp::Sub
+            | BinaryOp::Mul
+            | BinaryOp::Div
+            | BinaryOp::Mod
+            | BinaryOp::BitAnd
+            | BinaryOp::BitOr
+            | BinaryOp::BitXor
+            | BinaryOp::Shl
+            | BinaryOp::Shr => {
+                if left != TypeId::INT || right != TypeId::INT {
                     error = true;
                 }
-                TYPE_INT.clone()
+                TypeId::INT
             }
             BinaryOp::Or | BinaryOp::And => {
-                if left != *TYPE_BOOL || right != *TYPE_BOOL {
+                if left == TypeId::BOOL && right == TypeId::BOOL {
+                    TypeId::BOOL
+                } else if m.options().truthy_and_or {
+                    // Extension: the result is one of the operands, as in Python
+                    m.join(left, right)
+                } else {
                     error = true;
+                    TypeId::BOOL
                 }
-                TYPE_BOOL.clone()
             }
             BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
-                if left != *TYPE_INT || right != *TYPE_INT {
+                if left != TypeId::INT || right != TypeId::INT {
                     error = true;
                 }
-                TYPE_BOOL.clone()
+                TypeId::BOOL
             }
             BinaryOp::Is => {
-                let is_basic =
-                    |t: &ValueType| *t == *TYPE_INT || *t == *TYPE_BOOL || *t == *TYPE_STR;
-                if is_basic(&left) || is_basic(&right) {
+                let is_basic = |t| matches!(t, TypeId::INT | TypeId::BOOL | TypeId::STR);
+                if is_basic(left) || is_basic(right) {
                     error = true;
                 }
-                TYPE_BOOL.clone()
+                TypeId::BOOL
             }
             BinaryOp::Add => {
-                if left == *TYPE_INT || right == *TYPE_INT {
+                if left == TypeId::INT || right == TypeId::INT {
                     if left != right {
                         error = true;
                     }
-                    TYPE_INT.clone()
-                } else if left == *TYPE_STR {
+                    TypeId::INT
+                } else if left == TypeId::STR {
                     if left != right {
                         error = true;
-                        TYPE_OBJECT.clone()
+                        TypeId::OBJECT
                     } else {
-                        TYPE_STR.clone()
+                        TypeId::STR
                     }
-                } else if let (
-                    ValueType::ListValueType(ListValueType {
-                        element_type: left_element,
-                    }),
-                    ValueType::ListValueType(ListValueType {
-                        element_type: right_element,
-                    }),
-                ) = (&left, &right)
+                } else if let (TypeData::List(left_element), TypeData::List(right_element)) =
+                    (m.types.get(left), m.types.get(right))
                 {
-                    let element_type = Box::new(m.join(left_element, right_element));
-                    ValueType::ListValueType(ListValueType { element_type })
+                    let element_type = m.join(left_element, right_element);
+                    m.types.list(element_type)
                 } else {
                     error = true;
-                    TYPE_OBJECT.clone()
+                    TypeId::OBJECT
                 }
             }
             BinaryOp::Eq | BinaryOp::Ne => {
-                if (left != *TYPE_INT && left != *TYPE_STR && left != *TYPE_BOOL) || left != right {
+                if m.options().list_eq && list_comparable(&m.types, left, right) {
+                    // Extension: element-wise list comparison
+                } else if m.options().class_eq && class_comparable(&m.types, left, right) {
+                    // Extension: attribute-wise object comparison
+                } else if (left != TypeId::INT && left != TypeId::STR && left != TypeId::BOOL)
+                    || left != right
+                {
                     error = true
                 }
-                TYPE_BOOL.clone()
+                TypeId::BOOL
+            }
+            BinaryOp::In => {
+                let is_basic = |t| matches!(t, TypeId::INT | TypeId::BOOL | TypeId::STR);
+                if !m.options().membership {
+                    error = true;
+                } else if right == TypeId::STR {
+                    // Extension: substring test
+                    error = left != TypeId::STR;
+                } else if let TypeData::List(element_type) = m.types.get(right) {
+                    // Extension: elements are compared by value if they are int, bool or str,
+                    // and by identity otherwise, so both sides must agree on which
+                    error = !m.is_compatible(left, element_type)
+                        || is_basic(left) != is_basic(element_type);
+                } else if right != TypeId::EMPTY {
+                    error = true;
+                }
+                TypeId::BOOL
             }
         };
 
         if error {
-            let op_name = match self.operator {
-                BinaryOp::Or => "or",
-                BinaryOp::And => "and",
-                BinaryOp::Add => "+",
-                BinaryOp::Sub => "-",
-                BinaryOp::Mul => "*",
-                BinaryOp::Div => "//",
-                BinaryOp::Mod => "%",
-                BinaryOp::Eq => "==",
-                BinaryOp::Ne => "!=",
-                BinaryOp::Lt => "<",
-                BinaryOp::Gt => ">",
-                BinaryOp::Le => "<=",
-                BinaryOp::Ge => ">=",
-                BinaryOp::Is => "is",
-            };
-            let msg = error_binary(op_name, &left, &right);
+            let op_name = self.operator.symbol();
+            let msg = error_binary(op_name, m.types.value_type(left), m.types.value_type(right));
             self.add_error(errors, msg);
         }
 
@@ -315,16 +420,16 @@ impl IfExpr {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
         let condition = self.condition.analyze(errors, o, m);
-        if condition != *TYPE_BOOL {
-            let msg = error_condition(&condition);
+        if condition != TypeId::BOOL {
+            let msg = error_condition(m.types.value_type(condition));
             self.add_error(errors, msg);
         }
         let then_type = self.then_expr.analyze(errors, o, m);
         let else_type = self.else_expr.analyze(errors, o, m);
-        m.join(&then_type, &else_type)
+        m.join(then_type, else_type)
     }
 }
 
@@ -333,18 +438,18 @@ impl ListExpr {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
         if self.elements.is_empty() {
-            return TYPE_EMPTY.clone();
+            return TypeId::EMPTY;
         }
         let mut element_type = self.elements[0].analyze(errors, o, m);
         for element in self.elements.iter_mut().skip(1) {
-            element_type = m.join(&element_type, &element.analyze(errors, o, m));
+            let next = element.analyze(errors, o, m);
+            element_type = m.join(element_type, next);
         }
 
-        let element_type = Box::new(element_type);
-        ValueType::ListValueType(ListValueType { element_type })
+        m.types.list(element_type)
     }
 }
 
@@ -353,22 +458,22 @@ impl IndexExpr {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
         let left = self.list.analyze(errors, o, m);
-        let element_type = if let ValueType::ListValueType(ListValueType { element_type }) = left {
-            *element_type
-        } else if left == *TYPE_STR {
-            TYPE_STR.clone()
+        let element_type = if let TypeData::List(element_type) = m.types.get(left) {
+            element_type
+        } else if left == TypeId::STR {
+            TypeId::STR
         } else {
-            let msg = error_index_left(&left);
+            let msg = error_index_left(m.types.value_type(left));
             self.add_error(errors, msg);
-            TYPE_OBJECT.clone()
+            TypeId::OBJECT
         };
 
         let index = self.index.analyze(errors, o, m);
-        if index != *TYPE_INT && self.base().error_msg.is_none() {
-            let msg = error_index_right(&index);
+        if index != TypeId::INT && self.base().error_msg.is_none() {
+            let msg = error_index_right(m.types.value_type(index));
             self.add_error(errors, msg);
         }
 
@@ -381,24 +486,36 @@ impl MemberExpr {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
+        // A class constant can be read through the class name
+        if let ExprContent::Variable(Variable { name, .. }) = &self.object.content {
+            if !matches!(o.get(name), Some(EnvSlot::Var(..)))
+                && m.is_constant(name, &self.member.name)
+            {
+                let class_name = m.types.symbol(name);
+                let class = m.types.class(class_name);
+                self.object.inferred_type = Some(m.types.value_type(class).clone());
+                return m.get_attribute(class_name, &self.member.name).unwrap();
+            }
+        }
+
         let class = self.object.analyze(errors, o, m);
-        let class_name = if let ValueType::ClassValueType(ClassValueType { class_name }) = class {
+        let class_name = if let TypeData::Class(class_name) = m.types.get(class) {
             class_name
         } else {
-            let msg = error_member(&class);
+            let msg = error_member(m.types.value_type(class));
             self.add_error(errors, msg);
-            return TYPE_OBJECT.clone();
+            return TypeId::OBJECT;
         };
 
         let name = &self.member.name;
-        if let Some(member) = m.get_attribute(&class_name, name) {
-            member.clone()
+        if let Some(member) = m.get_attribute(class_name, name) {
+            member
         } else {
-            let msg = error_attribute(name, &class_name);
+            let msg = error_attribute(name, m.types.name(class_name));
             self.add_error(errors, msg);
-            TYPE_OBJECT.clone()
+            TypeId::OBJECT
         }
     }
 }
@@ -408,51 +525,153 @@ impl CallExpr {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
+        if m.options().list_append && self.is_append(|name| o.get(name).is_some()) {
+            return analyze_append(self, errors, o, m);
+        }
+        if m.options().membership && self.is_count(|name| o.get(name).is_some()) {
+            return analyze_count(self, errors, o, m);
+        }
+
         let args: Vec<_> = self
             .args
             .iter_mut()
             .map(|arg| arg.analyze(errors, o, m))
             .collect();
 
-        let function = if let Some(EnvSlot::Func(f)) = o.get(&self.function.name) {
-            f
+        let function = match o.get(&self.function.name) {
+            Some(EnvSlot::Func(f)) => Some(f),
+            // Extension: calling a function value
+            Some(EnvSlot::Var(&t, _)) => match m.types.get(t) {
+                TypeData::Func(signature) => Some(m.types.signature(signature)),
+                _ => None,
+            },
+            None => None,
+        };
+        let function = if let Some(function) = function {
+            function
         } else {
             let msg = error_function(&self.function.name);
             self.add_error(errors, msg);
-            return TYPE_OBJECT.clone();
+            return TypeId::OBJECT;
         };
 
         // Reference program: don't attach type to constructor
         if !m.contains(&self.function.name) {
-            self.function.inferred_type = Some(function.clone());
+            self.function.inferred_type = Some(m.types.func_type(function));
         }
 
         if function.parameters.len() != args.len() {
             let msg = error_call_count(function.parameters.len(), args.len());
-            self.add_error(errors, msg);
+            self.add_coded_error(errors, explain::CALL_COUNT, msg);
         } else {
             for (i, arg) in args.into_iter().enumerate() {
-                if !m.is_compatible(&arg, &function.parameters[i]) {
-                    let msg = error_call_type(i, &function.parameters[i], &arg);
+                if !m.is_compatible(arg, function.parameters[i]) {
+                    let expected = m.types.value_type(function.parameters[i]);
+                    let msg = error_call_type(i, expected, m.types.value_type(arg));
                     self.add_error(errors, msg);
+                    prepare_ancestry(errors, m, arg, function.parameters[i]);
                     break;
                 }
             }
         }
 
-        function.return_type.clone()
+        function.return_type
     }
 }
 
+// Analyze the `append(list, x)` extension, which returns a new list with `x` at the end.
+// It is typed like a function taking the list and an element of it
+fn analyze_append(
+    call: &mut CallExpr,
+    errors: &mut Vec<CompilerError>,
+    o: &mut TypeLocalEnv,
+    m: &mut ClassEnv,
+) -> TypeId {
+    let args: Vec<_> = call
+        .args
+        .iter_mut()
+        .map(|arg| arg.analyze(errors, o, m))
+        .collect();
+
+    if args.len() != 2 {
+        let msg = error_call_count(2, args.len());
+        call.add_coded_error(errors, explain::CALL_COUNT, msg);
+        return TypeId::OBJECT;
+    }
+
+    let (list, value) = (args[0], args[1]);
+    let element_type = if let TypeData::List(element_type) = m.types.get(list) {
+        element_type
+    } else if list == TypeId::EMPTY {
+        // The empty list takes the type of the new element
+        value
+    } else {
+        let msg = error_append_list(m.types.value_type(list));
+        call.add_error(errors, msg);
+        return TypeId::OBJECT;
+    };
+
+    if !m.is_compatible(value, element_type) {
+        let expected = m.types.value_type(element_type);
+        let msg = error_call_type(1, expected, m.types.value_type(value));
+        call.add_error(errors, msg);
+    }
+
+    let list_type = m.types.list(element_type);
+    let function = Signature {
+        parameters: vec![list_type, element_type],
+        return_type: list_type,
+    };
+    call.function.inferred_type = Some(m.types.func_type(&function));
+    list_type
+}
+
+// Analyze the `count(list, x)` extension, the number of elements of a list of int equal to `x`.
+// It is typed like a function `count(l:[int], x:int) -> int`
+fn analyze_count(
+    call: &mut CallExpr,
+    errors: &mut Vec<CompilerError>,
+    o: &mut TypeLocalEnv,
+    m: &mut ClassEnv,
+) -> TypeId {
+    let args: Vec<_> = call
+        .args
+        .iter_mut()
+        .map(|arg| arg.analyze(errors, o, m))
+        .collect();
+
+    let function = Signature {
+        parameters: vec![m.types.list(TypeId::INT), TypeId::INT],
+        return_type: TypeId::INT,
+    };
+    call.function.inferred_type = Some(m.types.func_type(&function));
+
+    if args.len() != 2 {
+        let msg = error_call_count(2, args.len());
+        call.add_coded_error(errors, explain::CALL_COUNT, msg);
+    } else {
+        for (i, (&arg, &parameter)) in args.iter().zip(&function.parameters).enumerate() {
+            if !m.is_compatible(arg, parameter) {
+                let expected = m.types.value_type(parameter);
+                let msg = error_call_type(i, expected, m.types.value_type(arg));
+                call.add_error(errors, msg);
+                break;
+            }
+        }
+    }
+
+    TypeId::INT
+}
+
 impl MethodCallExpr {
     pub fn analyze(
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-    ) -> ValueType {
+        m: &mut ClassEnv,
+    ) -> TypeId {
         let args: Vec<_> = self
             .args
             .iter_mut()
@@ -461,40 +680,42 @@ impl MethodCallExpr {
 
         let member = &mut self.method;
         let class = member.object.analyze(errors, o, m);
-        let class_name = if let ValueType::ClassValueType(ClassValueType { class_name }) = class {
+        let class_name = if let TypeData::Class(class_name) = m.types.get(class) {
             class_name
         } else {
-            let msg = error_member(&class);
+            let msg = error_member(m.types.value_type(class));
             self.add_error(errors, msg);
-            return TYPE_OBJECT.clone();
+            return TypeId::OBJECT;
         };
 
         let method_name = &member.member.name;
 
-        let method = if let Some(method) = m.get_method(&class_name, method_name) {
+        let method = if let Some(method) = m.get_method(class_name, method_name) {
             method
         } else {
-            let msg = error_method(method_name, &class_name);
+            let msg = error_method(method_name, m.types.name(class_name));
             self.add_error(errors, msg);
-            return TYPE_OBJECT.clone();
+            return TypeId::OBJECT;
         };
 
-        member.inferred_type = Some(method.clone());
+        member.inferred_type = Some(m.types.func_type(method));
 
         if method.parameters.len() - 1 != args.len() {
             let msg = error_call_count(method.parameters.len() - 1, args.len());
-            self.add_error(errors, msg);
+            self.add_coded_error(errors, explain::CALL_COUNT, msg);
         } else {
             for (i, arg) in args.into_iter().enumerate() {
-                if !m.is_compatible(&arg, &method.parameters[i + 1]) {
-                    let msg = error_call_type(i + 1, &method.parameters[i + 1], &arg);
+                if !m.is_compatible(arg, method.parameters[i + 1]) {
+                    let expected = m.types.value_type(method.parameters[i + 1]);
+                    let msg = error_call_type(i + 1, expected, m.types.value_type(arg));
                     self.add_error(errors, msg);
+                    prepare_ancestry(errors, m, arg, method.parameters[i + 1]);
                     break;
                 }
             }
         }
 
-        method.return_type.clone()
+        method.return_type
     }
 }
 
@@ -503,24 +724,26 @@ impl ReturnStmt {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-        r: Option<&ValueType>,
+        m: &mut ClassEnv,
+        r: Option<TypeId>,
     ) {
         // Reference program: do not analyze the expression on top-level return
         if let Some(return_expected) = r {
             let return_type = if let Some(value) = &mut self.value {
                 value.analyze(errors, o, m)
             } else {
-                TYPE_NONE.clone()
+                TypeId::NONE
             };
-            if !m.is_compatible(&return_type, return_expected) {
+            if !m.is_compatible(return_type, return_expected) {
+                let expected = m.types.value_type(return_expected);
                 // Reference program has some inconsistency here
                 let msg = if self.value.is_some() {
-                    error_assign(return_expected, &return_type)
+                    error_assign(expected, m.types.value_type(return_type))
                 } else {
-                    error_none_return(return_expected)
+                    error_none_return(expected)
                 };
-                self.add_error(errors, msg);
+                self.add_coded_error(errors, explain::TYPE_ASSIGN, msg);
+                prepare_ancestry(errors, m, return_type, return_expected);
             }
         } else {
             let msg = error_top_return();
@@ -534,12 +757,12 @@ impl IfStmt {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-        r: Option<&ValueType>,
+        m: &mut ClassEnv,
+        r: Option<TypeId>,
     ) {
         let condition = self.condition.analyze(errors, o, m);
-        if condition != *TYPE_BOOL {
-            let msg = error_condition(&condition);
+        if condition != TypeId::BOOL {
+            let msg = error_condition(m.types.value_type(condition));
             self.add_error(errors, msg);
         }
 
@@ -553,12 +776,12 @@ impl WhileStmt {
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-        r: Option<&ValueType>,
+        m: &mut ClassEnv,
+        r: Option<TypeId>,
     ) {
         let condition = self.condition.analyze(errors, o, m);
-        if condition != *TYPE_BOOL {
-            let msg = error_condition(&condition);
+        if condition != TypeId::BOOL {
+            let msg = error_condition(m.types.value_type(condition));
             self.add_error(errors, msg);
         }
 
@@ -566,52 +789,185 @@ impl WhileStmt {
     }
 }
 
+// Analyze `range(n)` or `range(a, b)` used as a for-loop iterable.
+// It is typed like a function returning [int], but never materialized as a list
+fn analyze_range(
+    call: &mut CallExpr,
+    errors: &mut Vec<CompilerError>,
+    o: &mut TypeLocalEnv,
+    m: &mut ClassEnv,
+) -> TypeId {
+    let args: Vec<_> = call
+        .args
+        .iter_mut()
+        .map(|arg| arg.analyze(errors, o, m))
+        .collect();
+
+    let function = Signature {
+        parameters: vec![TypeId::INT; if args.len() == 2 { 2 } else { 1 }],
+        return_type: m.types.list(TypeId::INT),
+    };
+
+    if function.parameters.len() != args.len() {
+        let msg = error_range_count(args.len());
+        call.add_coded_error(errors, explain::CALL_COUNT, msg);
+    } else if let Some(i) = args.iter().position(|&arg| arg != TypeId::INT) {
+        let msg = error_call_type(i, &TYPE_INT, m.types.value_type(args[i]));
+        call.add_error(errors, msg);
+    }
+
+    call.function.inferred_type = Some(m.types.func_type(&function));
+    function.return_type
+}
+
+// Analyze `enumerate(x)` used as the iterable of a loop with an index variable.
+// It is typed like a function returning its argument, whose elements the loop assigns
+fn analyze_enumerate(
+    call: &mut CallExpr,
+    errors: &mut Vec<CompilerError>,
+    o: &mut TypeLocalEnv,
+    m: &mut ClassEnv,
+) -> TypeId {
+    let args: Vec<_> = call
+        .args
+        .iter_mut()
+        .map(|arg| arg.analyze(errors, o, m))
+        .collect();
+
+    if args.len() != 1 {
+        let msg = error_call_count(1, args.len());
+        call.add_coded_error(errors, explain::CALL_COUNT, msg);
+        return TypeId::OBJECT;
+    }
+
+    let function = Signature {
+        parameters: vec![args[0]],
+        return_type: args[0],
+    };
+    call.function.inferred_type = Some(m.types.func_type(&function));
+    function.return_type
+}
+
+// Type a loop variable that is assigned values of `element_type`, giving the code and
+// the message of an error to attach to the whole statement
+fn analyze_for_target(
+    target: &mut ForTarget,
+    element_type: TypeId,
+    errors: &mut Vec<CompilerError>,
+    o: &mut TypeLocalEnv,
+    m: &mut ClassEnv,
+) -> Option<(&'static str, String)> {
+    let variable = match o.get(&target.name) {
+        None | Some(EnvSlot::Func(_)) => None,
+        Some(EnvSlot::Var(&t, assignable)) => Some((t, assignable)),
+    };
+
+    if let Some((variable, Assignable(assignable))) = variable {
+        if m.is_compatible(element_type, variable) {
+            // yes, we attach the type here
+            target.inferred_type = Some(m.types.value_type(variable).clone());
+            if !assignable {
+                let msg = error_nonlocal_assign(&target.name);
+                // and this error is attached to the identifier
+                target.add_error(errors, msg);
+            }
+            None
+        } else {
+            let msg = error_assign(
+                m.types.value_type(variable),
+                m.types.value_type(element_type),
+            );
+            Some((explain::TYPE_ASSIGN, msg))
+        }
+    } else {
+        Some((explain::UNDEFINED_VARIABLE, error_variable(&target.name)))
+    }
+}
+
 impl ForStmt {
     pub fn analyze(
         &mut self,
         errors: &mut Vec<CompilerError>,
         o: &mut TypeLocalEnv,
-        m: &ClassEnv,
-        r: Option<&ValueType>,
+        m: &mut ClassEnv,
+        r: Option<TypeId>,
     ) {
         // Eh, the error handling is a mess in the reference program
 
-        let iterable = self.iterable.analyze(errors, o, m);
-        let element_type = if iterable == *TYPE_STR {
-            Some(&iterable)
-        } else if let ValueType::ListValueType(ListValueType { element_type }) = &iterable {
-            Some(&**element_type)
-        } else {
-            let msg = error_iterable(&iterable);
+        let enumerate = match &self.iterable.content {
+            ExprContent::CallExpr(call) => {
+                m.options().enumerate && call.is_enumerate(|name| o.get(name).is_some())
+            }
+            _ => false,
+        };
+        if self.index.is_some() && !enumerate {
+            let msg = error_enumerate();
             self.add_error(errors, msg);
+        }
+
+        // A loop over `range(...)` with the wrong number of arguments still counts with int.
+        // An undeclared loop variable is taken as an int in the loop, so that only the
+        // arguments are reported rather than every use of the variable
+        let range_count = self
+            .range_args(|name| o.get(name).is_some())
+            .map(<[Expr]>::len);
+        let bad_range = range_count.is_some_and(|count| !matches!(count, 1 | 2));
+        let declared_target = if bad_range && o.get(&self.identifier.name).is_none() {
+            let name = self.identifier.name.clone();
+            Some(o.insert(name, LocalSlot::Var(TypeId::INT)))
+        } else {
             None
         };
 
-        if let Some(element_type) = element_type {
-            let variable = match o.get(&self.identifier.name) {
-                None | Some(EnvSlot::Func(_)) => None,
-                Some(EnvSlot::Var(t, assignable)) => Some((t.clone(), assignable)),
+        let iterable = if range_count.is_some() {
+            let iterable = &mut self.iterable;
+            let range_type = if let ExprContent::CallExpr(call) = &mut iterable.content {
+                analyze_range(call, errors, o, m)
+            } else {
+                unreachable!()
             };
-
-            if let Some((variable, Assignable(assignable))) = variable {
-                if m.is_compatible(element_type, &variable) {
-                    self.identifier.inferred_type = Some(variable); // yes, we attach the type here
-                    if !assignable {
-                        let msg = error_nonlocal_assign(&self.identifier.name);
-                        // and this error is attached to the identifier
-                        self.identifier.add_error(errors, msg);
-                    }
-                } else {
-                    let msg = error_assign(&variable, element_type);
-                    self.add_error(errors, msg);
-                }
+            iterable.inferred_type = Some(m.types.value_type(range_type).clone());
+            range_type
+        } else if enumerate && self.index.is_some() {
+            let iterable = &mut self.iterable;
+            let sequence_type = if let ExprContent::CallExpr(call) = &mut iterable.content {
+                analyze_enumerate(call, errors, o, m)
             } else {
-                let msg = error_variable(&self.identifier.name);
-                self.add_error(errors, msg);
+                unreachable!()
+            };
+            iterable.inferred_type = Some(m.types.value_type(sequence_type).clone());
+            sequence_type
+        } else {
+            self.iterable.analyze(errors, o, m)
+        };
+        let element_type = if iterable == TypeId::STR {
+            Some(iterable)
+        } else if let TypeData::List(element_type) = m.types.get(iterable) {
+            Some(element_type)
+        } else {
+            let msg = error_iterable(m.types.value_type(iterable));
+            self.add_error(errors, msg);
+            None
+        };
+
+        if let Some(element_type) = element_type {
+            let index_error = self
+                .index
+                .as_mut()
+                .and_then(|index| analyze_for_target(index, TypeId::INT, errors, o, m));
+            if let Some((code, msg)) = index_error {
+                self.add_coded_error(errors, code, msg);
+            }
+            let target = &mut self.identifier;
+            if let Some((code, msg)) = analyze_for_target(target, element_type, errors, o, m) {
+                self.add_coded_error(errors, code, msg);
             }
         }
 
         analyze_stmt(&mut self.body, errors, o, m, r);
+        if let Some(old) = declared_target {
+            o.restore(&self.identifier.name, old);
+        }
     }
 }
 
@@ -619,8 +975,8 @@ fn analyze_stmt(
     statements: &mut [Stmt],
     errors: &mut Vec<CompilerError>,
     o: &mut TypeLocalEnv,
-    m: &ClassEnv,
-    r: Option<&ValueType>,
+    m: &mut ClassEnv,
+    r: Option<TypeId>,
 ) {
     for statement in statements {
         match statement {
@@ -638,7 +994,7 @@ fn analyze_decl(
     declarations: &mut [Declaration],
     errors: &mut Vec<CompilerError>,
     o: &mut TypeLocalEnv,
-    m: &ClassEnv,
+    m: &mut ClassEnv,
 ) {
     for declaration in declarations {
         match declaration {
@@ -651,55 +1007,89 @@ fn analyze_decl(
 }
 
 impl FuncDef {
-    pub fn analyze(&mut self, errors: &mut Vec<CompilerError>, o: &mut TypeLocalEnv, m: &ClassEnv) {
-        let frame: HashMap<String, LocalSlot<FuncType, ValueType>> = self
+    pub fn signature(&self, types: &mut TypeInterner) -> Signature {
+        Signature {
+            parameters: self
+                .params
+                .iter()
+                .map(|tv| types.annotation_type(&tv.type_))
+                .collect(),
+            return_type: types.annotation_type(&self.return_type),
+        }
+    }
+
+    // Names declared by the function itself, including its parameters.
+    // Erroneous global and nonlocal declarations are left out so that lookups never fail.
+    pub fn frame(&self, types: &mut TypeInterner) -> HashMap<String, LocalSlot<Signature, TypeId>> {
+        let mut frame: HashMap<_, _> = self
             .declarations
             .iter()
-            .map(|decl| match decl {
-                Declaration::FuncDef(f) => (
-                    f.name.name.clone(),
-                    LocalSlot::Func(FuncType {
-                        parameters: f
-                            .params
-                            .iter()
-                            .map(|tv| ValueType::from_annotation(&tv.type_))
-                            .collect(),
-                        return_type: ValueType::from_annotation(&f.return_type),
-                    }),
-                ),
-                Declaration::VarDef(v) => (
+            .filter_map(|decl| match decl {
+                Declaration::FuncDef(f) => {
+                    Some((f.name.name.clone(), LocalSlot::Func(f.signature(types))))
+                }
+                Declaration::VarDef(v) => Some((
                     v.var.identifier.name.clone(),
-                    LocalSlot::Var(ValueType::from_annotation(&v.var.type_)),
-                ),
-                Declaration::GlobalDecl(v) => (v.variable.name.clone(), LocalSlot::Global),
-                Declaration::NonLocalDecl(v) => (v.variable.name.clone(), LocalSlot::NonLocal),
+                    LocalSlot::Var(types.annotation_type(&v.var.type_)),
+                )),
+                Declaration::GlobalDecl(v) if v.variable.base.error_msg.is_none() => {
+                    Some((v.variable.name.clone(), LocalSlot::Global))
+                }
+                Declaration::NonLocalDecl(v) if v.variable.base.error_msg.is_none() => {
+                    Some((v.variable.name.clone(), LocalSlot::NonLocal))
+                }
+                Declaration::GlobalDecl(_) | Declaration::NonLocalDecl(_) => None,
                 _ => panic!(),
             })
-            .chain(self.params.iter().map(|param| {
-                (
-                    param.identifier.name.clone(),
-                    LocalSlot::Var(ValueType::from_annotation(&param.type_)),
-                )
-            }))
             .collect();
+        frame.extend(self.params.iter().map(|param| {
+            (
+                param.identifier.name.clone(),
+                LocalSlot::Var(types.annotation_type(&param.type_)),
+            )
+        }));
+        frame
+    }
 
+    pub fn analyze(
+        &mut self,
+        errors: &mut Vec<CompilerError>,
+        o: &mut TypeLocalEnv,
+        m: &mut ClassEnv,
+    ) {
+        let frame = self.frame(&mut m.types);
         let mut handle = o.push(frame);
         analyze_decl(&mut self.declarations, errors, handle.inner(), m);
 
-        let return_type = ValueType::from_annotation(&self.return_type);
-        let r = Some(&return_type);
-        analyze_stmt(&mut self.statements, errors, handle.inner(), m, r);
+        let return_type = m.types.annotation_type(&self.return_type);
+        analyze_stmt(
+            &mut self.statements,
+            errors,
+            handle.inner(),
+            m,
+            Some(return_type),
+        );
     }
 }
 
 impl ClassDef {
-    pub fn analyze(&mut self, errors: &mut Vec<CompilerError>, o: &mut TypeLocalEnv, m: &ClassEnv) {
+    pub fn analyze(
+        &mut self,
+        errors: &mut Vec<CompilerError>,
+        o: &mut TypeLocalEnv,
+        m: &mut ClassEnv,
+    ) {
         analyze_decl(&mut self.declarations, errors, o, m);
     }
 }
 
 impl Program {
-    pub fn analyze(&mut self, errors: &mut Vec<CompilerError>, o: &mut TypeLocalEnv, m: &ClassEnv) {
+    pub fn analyze(
+        &mut self,
+        errors: &mut Vec<CompilerError>,
+        o: &mut TypeLocalEnv,
+        m: &mut ClassEnv,
+    ) {
         analyze_decl(&mut self.declarations, errors, o, m);
         analyze_stmt(&mut self.statements, errors, o, m, None);
     }
@@ -1,34 +1,51 @@
 use super::error::*;
+use super::types::*;
 use crate::node::*;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
 #[derive(Clone, PartialEq, Eq)]
 enum Type {
-    ValueType(ValueType),
-    FuncType(FuncType),
+    Attribute(TypeId),
+    Method(Signature),
+    Constant(TypeId),
 }
 
 struct ClassInfo {
-    super_class: String,
-    items: HashMap<String, Type>,
+    super_class: Symbol,
+    items: HashMap<Symbol, Type>,
 }
 
-pub struct ClassEnv(HashMap<String, ClassInfo>);
+pub struct ClassEnv {
+    // Indexed by class name symbols
+    classes: Vec<Option<ClassInfo>>,
+    options: super::Options,
+    pub types: TypeInterner,
+}
 
 impl ClassEnv {
-    fn add_basic_type(&mut self, name: &str) {
-        self.0.insert(
-            name.to_owned(),
+    fn class(&self, name: Symbol) -> Option<&ClassInfo> {
+        self.classes.get(name.index())?.as_ref()
+    }
+
+    fn insert_class(&mut self, name: Symbol, class: ClassInfo) {
+        if self.classes.len() <= name.index() {
+            self.classes.resize_with(name.index() + 1, || None);
+        }
+        self.classes[name.index()] = Some(class);
+    }
+
+    fn add_basic_type(&mut self, name: Symbol) {
+        let init = self.types.symbol("__init__");
+        self.insert_class(
+            name,
             ClassInfo {
-                super_class: "object".to_owned(),
+                super_class: Symbol::OBJECT,
                 items: std::iter::once((
-                    "__init__".to_owned(),
-                    Type::FuncType(FuncType {
-                        parameters: vec![ValueType::ClassValueType(ClassValueType {
-                            class_name: "object".to_owned(),
-                        })],
-                        return_type: TYPE_NONE.clone(),
+                    init,
+                    Type::Method(Signature {
+                        parameters: vec![TypeId::OBJECT],
+                        return_type: TypeId::NONE,
                     }),
                 ))
                 .collect(),
@@ -36,14 +53,18 @@ impl ClassEnv {
         );
     }
 
-    pub fn new() -> ClassEnv {
-        let mut class_env = ClassEnv(HashMap::new());
-        class_env.add_basic_type("object");
-        class_env.add_basic_type("str");
-        class_env.add_basic_type("int");
-        class_env.add_basic_type("bool");
-        class_env.add_basic_type("<None>");
-        class_env.add_basic_type("<Empty>");
+    pub fn new(options: super::Options) -> ClassEnv {
+        let mut class_env = ClassEnv {
+            classes: vec![],
+            options,
+            types: TypeInterner::new(),
+        };
+        class_env.add_basic_type(Symbol::OBJECT);
+        class_env.add_basic_type(Symbol::STR);
+        class_env.add_basic_type(Symbol::INT);
+        class_env.add_basic_type(Symbol::BOOL);
+        class_env.add_basic_type(Symbol::NONE);
+        class_env.add_basic_type(Symbol::EMPTY);
         class_env
     }
 
@@ -53,13 +74,15 @@ impl ClassEnv {
         errors: &mut Vec<CompilerError>,
         id_set: &HashSet<String>,
     ) {
-        let class_name = &class_def.name.name;
+        let class_name = self.types.symbol(&class_def.name.name);
+        let class_type = self.types.class(class_name);
         let super_name = &class_def.super_class.name;
-        let super_class = if matches!(super_name.as_str(), "int" | "str" | "bool") {
+        let super_symbol = self.types.find_symbol(super_name);
+        let inherited = if matches!(super_name.as_str(), "int" | "str" | "bool") {
             let msg = error_super_special(super_name);
             class_def.super_class.add_error(errors, msg);
-            self.0.get("object").unwrap()
-        } else if let Some(super_class) = self.0.get(super_name) {
+            Symbol::OBJECT
+        } else if let Some(super_class) = super_symbol.filter(|&s| self.class(s).is_some()) {
             super_class
         } else {
             let msg = if id_set.contains(super_name) {
@@ -68,19 +91,20 @@ impl ClassEnv {
                 error_super_undef
             }(super_name);
             class_def.super_class.add_error(errors, msg);
-            self.0.get("object").unwrap()
+            Symbol::OBJECT
         };
 
         // Inherit items
-        let mut items = super_class.items.clone();
+        let mut items = self.class(inherited).unwrap().items.clone();
 
         // Check and insert new items
         let mut id_set = HashSet::new();
         for item_decl in &mut class_def.declarations {
             let name_str = item_decl.name_mut().name.clone();
+            let name_symbol = self.types.symbol(&name_str);
 
             // Class scope identifier collision check
-            if !id_set.insert(name_str.clone()) {
+            if !id_set.insert(name_symbol) {
                 let msg = error_dup(&name_str);
                 let name = item_decl.name_mut();
                 name.add_error(errors, msg);
@@ -92,35 +116,40 @@ impl ClassEnv {
                     let parameters: Vec<_> = func
                         .params
                         .iter()
-                        .map(|t| ValueType::from_annotation(&t.type_))
+                        .map(|t| self.types.annotation_type(&t.type_))
                         .collect();
-                    let return_type = ValueType::from_annotation(&func.return_type);
+                    let return_type = self.types.annotation_type(&func.return_type);
 
                     let name = item_decl.name_mut();
 
                     // Self parameter check
-                    if parameters.get(0)
-                        != Some(&ValueType::ClassValueType(ClassValueType {
-                            class_name: class_name.clone(),
-                        }))
-                    {
+                    if parameters.first() != Some(&class_type) {
                         let msg = error_method_self(&name_str);
                         name.add_error(errors, msg);
                     }
 
-                    let item_type = Type::FuncType(FuncType {
+                    // `print` calls `__str__` to convert the object
+                    if name_str == "__str__"
+                        && (parameters.len() != 1 || return_type != TypeId::STR)
+                    {
+                        let msg = error_method_str();
+                        name.add_error(errors, msg);
+                    }
+
+                    let item_type = Type::Method(Signature {
                         parameters,
                         return_type,
                     });
 
                     // Override check
-                    match items.insert(name_str.clone(), item_type.clone()) {
+                    match items.insert(name_symbol, item_type.clone()) {
                         None => (),
-                        Some(Type::FuncType(mut old)) => {
-                            old.parameters[0] = ValueType::ClassValueType(ClassValueType {
-                                class_name: class_name.clone(),
-                            });
-                            if Type::FuncType(old) != item_type {
+                        Some(Type::Method(mut old)) => {
+                            // A missing `self` in the inherited method is reported there
+                            if let Some(self_type) = old.parameters.first_mut() {
+                                *self_type = class_type;
+                            }
+                            if Type::Method(old) != item_type {
                                 let msg = error_method_override(&name_str);
                                 name.add_error(errors, msg);
                             }
@@ -132,14 +161,15 @@ impl ClassEnv {
                     }
                 }
                 Declaration::VarDef(var) => {
-                    // Redefinition check
-                    if items
-                        .insert(
-                            name_str.clone(),
-                            Type::ValueType(ValueType::from_annotation(&var.var.type_)),
-                        )
-                        .is_some()
-                    {
+                    let value_type = self.types.annotation_type(&var.var.type_);
+                    let item_type = if var.constant {
+                        Type::Constant(value_type)
+                    } else {
+                        Type::Attribute(value_type)
+                    };
+
+                    // Redefinition check. This also rejects shadowing an inherited constant
+                    if items.insert(name_symbol, item_type).is_some() {
                         let name = item_decl.name_mut();
                         let msg = error_attribute_redefine(&name_str);
                         name.add_error(errors, msg);
@@ -148,101 +178,122 @@ impl ClassEnv {
                 _ => unreachable!(),
             }
         }
-        self.0.insert(
-            class_name.clone(),
-            ClassInfo {
-                super_class: class_def.super_class.name.clone(),
-                items,
-            },
-        );
+        let super_class = self.types.symbol(&class_def.super_class.name);
+        self.insert_class(class_name, ClassInfo { super_class, items });
     }
 
-    pub fn is_compatible(&self, sub_class: &ValueType, super_class: &ValueType) -> bool {
+    pub fn is_compatible(&self, sub_class: TypeId, super_class: TypeId) -> bool {
         if sub_class == super_class {
             return true;
         }
-        if *super_class == *TYPE_OBJECT {
+        if super_class == TypeId::OBJECT {
             return true;
         }
-        if *sub_class == *TYPE_NONE {
-            if let ValueType::ClassValueType(ClassValueType { class_name }) = super_class {
-                return class_name != "int" && class_name != "str" && class_name != "bool";
-            } else {
-                return true;
-            }
+        if sub_class == TypeId::NONE {
+            return !matches!(super_class, TypeId::INT | TypeId::STR | TypeId::BOOL);
         }
-        if *sub_class == *TYPE_EMPTY {
-            if let ValueType::ListValueType(_) = super_class {
-                return true;
-            } else {
-                return false;
-            }
+        if sub_class == TypeId::EMPTY {
+            return matches!(self.types.get(super_class), TypeData::List(_));
         }
-        if *sub_class == *TYPE_NONE_LIST {
-            if let ValueType::ListValueType(ListValueType { element_type }) = super_class {
-                return self.is_compatible(&TYPE_NONE, element_type);
+        if sub_class == TypeId::NONE_LIST {
+            if let TypeData::List(element_type) = self.types.get(super_class) {
+                return self.is_compatible(TypeId::NONE, element_type);
             } else {
                 return false;
             }
         }
 
-        if *super_class == *TYPE_NONE || *super_class == *TYPE_EMPTY {
+        if super_class == TypeId::NONE || super_class == TypeId::EMPTY {
             return false;
         }
 
-        let mut sub_name =
-            if let ValueType::ClassValueType(ClassValueType { class_name }) = sub_class {
-                class_name
-            } else {
-                return false;
-            };
-
-        let super_name =
-            if let ValueType::ClassValueType(ClassValueType { class_name }) = super_class {
-                class_name
-            } else {
-                return false;
+        let (mut sub_name, super_name) =
+            match (self.types.get(sub_class), self.types.get(super_class)) {
+                (TypeData::Class(sub_name), TypeData::Class(super_name)) => (sub_name, super_name),
+                _ => return false,
             };
 
         loop {
             if sub_name == super_name {
                 return true;
             }
-            if sub_name == "object" {
+            if sub_name == Symbol::OBJECT {
                 return false;
             }
-            sub_name = &self.0.get(sub_name).unwrap().super_class;
+            sub_name = self.class(sub_name).unwrap().super_class;
+        }
+    }
+
+    // Names of the classes from `class` up to `object`. A super class that had an error ends the
+    // chain when it is not a class, or when it repeats a class already in the chain
+    pub fn ancestors(&self, class: Symbol) -> Vec<&str> {
+        let mut chain = vec![class];
+        let mut name = class;
+        while name != Symbol::OBJECT {
+            name = match self.class(name) {
+                Some(class) => class.super_class,
+                None => break,
+            };
+            let repeated = chain.contains(&name);
+            chain.push(name);
+            if repeated {
+                break;
+            }
         }
+        chain
+            .into_iter()
+            .map(|name| self.types.name(name))
+            .collect()
     }
 
-    pub fn join(&self, a: &ValueType, b: &ValueType) -> ValueType {
+    // The ancestors of `got` as in "C -> B -> object", for explaining why it is not compatible
+    // with `expected`. Only when both are user classes, and `got` inherits from another one
+    pub fn ancestry(&self, got: TypeId, expected: TypeId) -> Option<String> {
+        const BASIC: [Symbol; 6] = [
+            Symbol::OBJECT,
+            Symbol::NONE,
+            Symbol::EMPTY,
+            Symbol::STR,
+            Symbol::INT,
+            Symbol::BOOL,
+        ];
+        match (self.types.get(got), self.types.get(expected)) {
+            (TypeData::Class(got), TypeData::Class(expected))
+                if !BASIC.contains(&got) && !BASIC.contains(&expected) =>
+            {
+                let chain = self.ancestors(got);
+                if chain.len() > 2 {
+                    Some(chain.join(" -> "))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn join(&self, a: TypeId, b: TypeId) -> TypeId {
         if self.is_compatible(a, b) {
-            return b.clone();
+            return b;
         }
         if self.is_compatible(b, a) {
-            return a.clone();
+            return a;
         }
-        if let (
-            ValueType::ClassValueType(ClassValueType {
-                class_name: a_class,
-            }),
-            ValueType::ClassValueType(ClassValueType {
-                class_name: b_class,
-            }),
-        ) = (a, b)
+        if let (TypeData::Class(a_class), TypeData::Class(b_class)) =
+            (self.types.get(a), self.types.get(b))
         {
-            if a_class == "<None>"
-                || a_class == "<Empty>"
-                || b_class == "<None>"
-                || b_class == "<Empty>"
+            if a_class == Symbol::NONE
+                || a_class == Symbol::EMPTY
+                || b_class == Symbol::NONE
+                || b_class == Symbol::EMPTY
             {
-                return TYPE_OBJECT.clone();
+                return TypeId::OBJECT;
             }
 
             let gen_chain = |mut t| {
                 let mut v = vec![t];
-                while t != "object" {
-                    t = &self.0.get(t).unwrap().super_class;
+                while t != Symbol::OBJECT {
+                    t = self.class(t).unwrap().super_class;
                     v.push(t);
                 }
                 v
@@ -255,31 +306,82 @@ impl ClassEnv {
                 let common = a_chain.pop().unwrap();
                 b_chain.pop();
                 if a_chain.last() != b_chain.last() {
-                    return ValueType::ClassValueType(ClassValueType {
-                        class_name: common.to_owned(),
-                    });
+                    // Every class type is interned when the class is added
+                    return self.types.find_class(common).unwrap();
                 }
             }
         } else {
-            TYPE_OBJECT.clone()
+            TypeId::OBJECT
         }
     }
 
-    pub fn get_attribute(&self, class_name: &str, name: &str) -> Option<&ValueType> {
-        match self.0.get(class_name)?.items.get(name)? {
-            Type::ValueType(t) => Some(t),
+    fn get_item(&self, class_name: Symbol, name: &str) -> Option<&Type> {
+        let name = self.types.find_symbol(name)?;
+        self.class(class_name)?.items.get(&name)
+    }
+
+    pub fn get_attribute(&self, class_name: Symbol, name: &str) -> Option<TypeId> {
+        match self.get_item(class_name, name)? {
+            Type::Attribute(t) | Type::Constant(t) => Some(*t),
             _ => None,
         }
     }
 
-    pub fn get_method(&self, class_name: &str, name: &str) -> Option<&FuncType> {
-        match self.0.get(class_name)?.items.get(name)? {
-            Type::FuncType(t) => Some(t),
+    pub fn is_constant(&self, class_name: &str, name: &str) -> bool {
+        let class_name = if let Some(class_name) = self.types.find_symbol(class_name) {
+            class_name
+        } else {
+            return false;
+        };
+        matches!(self.get_item(class_name, name), Some(Type::Constant(_)))
+    }
+
+    pub fn get_method(&self, class_name: Symbol, name: &str) -> Option<&Signature> {
+        match self.get_item(class_name, name)? {
+            Type::Method(t) => Some(t),
             _ => None,
         }
     }
 
+    // Attributes of the class, including inherited ones
+    pub fn attributes<'a>(
+        &'a self,
+        class_name: &str,
+    ) -> impl Iterator<Item = (&'a str, &'a ValueType)> {
+        self.types
+            .find_symbol(class_name)
+            .and_then(|class_name| self.class(class_name))
+            .into_iter()
+            .flat_map(|class| class.items.iter())
+            .filter_map(move |(name, item)| match item {
+                Type::Attribute(t) | Type::Constant(t) => {
+                    Some((self.types.name(*name), self.types.value_type(*t)))
+                }
+                _ => None,
+            })
+    }
+
+    // Methods of the class, including inherited ones
+    pub fn methods<'a>(&'a self, class_name: &str) -> impl Iterator<Item = (&'a str, FuncType)> {
+        self.types
+            .find_symbol(class_name)
+            .and_then(|class_name| self.class(class_name))
+            .into_iter()
+            .flat_map(|class| class.items.iter())
+            .filter_map(move |(name, item)| match item {
+                Type::Method(t) => Some((self.types.name(*name), self.types.func_type(t))),
+                _ => None,
+            })
+    }
+
     pub fn contains(&self, class_name: &str) -> bool {
-        self.0.contains_key(class_name)
+        self.types
+            .find_symbol(class_name)
+            .is_some_and(|class_name| self.class(class_name).is_some())
+    }
+
+    // Extensions enabled for this program
+    pub fn options(&self) -> super::Options {
+        self.options
     }
 }
@@ -26,16 +26,87 @@ pub fn error_method_self(name: &str) -> String {
     )
 }
 
+pub fn error_method_str() -> String {
+    "Method __str__ must only take the object and return str".to_owned()
+}
+
 pub fn error_method_override(name: &str) -> String {
     format!("Method overridden with different type signature: {}", name)
 }
 
+pub fn friendly_override_count(
+    name: &str,
+    super_class: &str,
+    expected: usize,
+    got: usize,
+) -> String {
+    format!(
+        "{}; `{}.{}` has {} parameter{} but this has {}",
+        error_method_override(name),
+        super_class,
+        name,
+        expected,
+        if expected == 1 { "" } else { "s" },
+        got
+    )
+}
+
+pub fn friendly_override_param(
+    name: &str,
+    super_class: &str,
+    param: &str,
+    expected: &ValueType,
+    got: &ValueType,
+) -> String {
+    format!(
+        "{}; parameter `{}` must be of type `{}` as in `{}.{}`, not `{}`",
+        error_method_override(name),
+        param,
+        expected,
+        super_class,
+        name,
+        got
+    )
+}
+
+pub fn friendly_override_return(
+    name: &str,
+    super_class: &str,
+    expected: &ValueType,
+    got: &ValueType,
+) -> String {
+    format!(
+        "{}; return type must be `{}` as in `{}.{}`, not `{}`",
+        error_method_override(name),
+        expected,
+        super_class,
+        name,
+        got
+    )
+}
+
 pub fn error_attribute_redefine(name: &str) -> String {
     format!("Cannot re-define attribute: {}", name)
 }
 
+pub fn error_constant_assign(name: &str) -> String {
+    format!("Cannot assign to class constant: {}", name)
+}
+
 pub fn error_invalid_type(name: &str) -> String {
-    format!("Invalid type annotation; there is no class named: {}", name)
+    let msg = format!("Invalid type annotation; there is no class named: {}", name);
+
+    // Hints for type names commonly carried over from other languages
+    let hint = match name {
+        "list" | "List" => "list types are written as [T], for example [int]",
+        "string" => "use str instead",
+        "boolean" => "use bool instead",
+        "integer" => "use int instead",
+        "float" => "ChocoPy has no floating-point type",
+        "None" => "None is not a type in ChocoPy; use object or a class type",
+        _ => return msg,
+    };
+    format!("{} ({})", msg, hint)
 }
 
 pub fn error_shadow(name: &str) -> String {
@@ -61,10 +132,52 @@ pub fn error_variable(name: &str) -> String {
     format!("Not a variable: {}", name)
 }
 
+pub fn error_func_value(name: &str) -> String {
+    format!(
+        "Only functions defined at the top level can be used as values: {}",
+        name
+    )
+}
+
+// `declaration`: the declaration to suggest, if the type of the variable is known
+pub fn friendly_for_undeclared(name: &str, declaration: Option<String>) -> String {
+    match declaration {
+        Some(declaration) => format!(
+            "{}; ChocoPy loop variables must be declared before the loop, as in `{}`",
+            error_variable(name),
+            declaration
+        ),
+        None => format!(
+            "{}; ChocoPy loop variables must be declared with a type before the loop",
+            error_variable(name)
+        ),
+    }
+}
+
+pub fn friendly_for_function(name: &str) -> String {
+    format!(
+        "{}; `{}` is a function or class, which cannot be a loop variable",
+        error_variable(name),
+        name
+    )
+}
+
 pub fn error_assign(left: &ValueType, right: &ValueType) -> String {
     format!("Expected type `{}`; got type `{}`", &left, &right)
 }
 
+pub fn friendly_ancestry(
+    message: &str,
+    got: &ValueType,
+    ancestry: &str,
+    expected: &ValueType,
+) -> String {
+    format!(
+        "{}; the class hierarchy of `{}` is {}, without `{}`",
+        message, got, ancestry, expected
+    )
+}
+
 pub fn error_nonlocal_assign(name: &str) -> String {
     format!(
         "Cannot assign to variable that is not explicitly declared in this scope: {}",
@@ -83,10 +196,45 @@ pub fn error_binary(operator: &str, left: &ValueType, right: &ValueType) -> Stri
     )
 }
 
+pub fn friendly_none_compare(operator: &str, left: &ValueType, right: &ValueType) -> String {
+    format!(
+        "{}; use `is` to compare with None",
+        error_binary(operator, left, right)
+    )
+}
+
+pub fn friendly_is_value(left: &ValueType, right: &ValueType, value: &ValueType) -> String {
+    format!(
+        "{}; `is` cannot be used on values of type `{}`; use `==`",
+        error_binary("is", left, right),
+        value
+    )
+}
+
 pub fn error_condition(condition: &ValueType) -> String {
     format!("Condition expression cannot be of type `{}`", condition)
 }
 
+pub fn warning_div_zero() -> String {
+    "Division by zero always fails at runtime".to_owned()
+}
+
+pub fn warning_dead_else() -> String {
+    "Branch is unreachable because a preceding condition is always True".to_owned()
+}
+
+pub fn warning_dead_if() -> String {
+    "Branch is unreachable because its condition is always False".to_owned()
+}
+
+pub fn warning_infinite_loop() -> String {
+    "This loop never terminates".to_owned()
+}
+
+pub fn warning_object_list() -> String {
+    "List elements have different types, so the list is inferred as `[object]`".to_owned()
+}
+
 pub fn error_member(t: &ValueType) -> String {
     format!("Cannot access member of non-class type `{}`", t)
 }
@@ -95,6 +243,22 @@ pub fn error_call_count(expected: usize, got: usize) -> String {
     format!("Expected {} arguments; got {}", expected, got)
 }
 
+pub fn error_range_count(got: usize) -> String {
+    format!("Expected 1 or 2 arguments; got {}", got)
+}
+
+pub fn friendly_print_count(got: usize) -> String {
+    if got == 0 {
+        "`print` expects exactly 1 argument; use `print(\"\")` to print an empty line".to_owned()
+    } else {
+        format!(
+            "`print` expects exactly 1 argument; got {}. \
+             Concatenate strings with `+` or call `print` once per value",
+            got
+        )
+    }
+}
+
 pub fn error_call_type(location: usize, expected: &ValueType, got: &ValueType) -> String {
     format!(
         "Expected type `{}`; got type `{}` in parameter {}",
@@ -102,6 +266,10 @@ pub fn error_call_type(location: usize, expected: &ValueType, got: &ValueType) -
     )
 }
 
+pub fn error_append_list(list: &ValueType) -> String {
+    format!("Cannot append to non-list type `{}`", list)
+}
+
 pub fn error_index_left(left: &ValueType) -> String {
     format!("Cannot index into type `{}`", &left)
 }
@@ -136,6 +304,10 @@ pub fn error_iterable(iterable: &ValueType) -> String {
     format!("Cannot iterate over value of type `{}`", &iterable)
 }
 
+pub fn error_enumerate() -> String {
+    "Two loop variables need `enumerate(...)` as the iterable".to_owned()
+}
+
 pub fn error_multi_assign() -> String {
     "Right-hand side of multiple assignment may not be [<None>]".to_owned()
 }
@@ -147,3 +319,32 @@ pub fn error_top_return() -> String {
 pub fn error_str_index_assign() -> String {
     "`str` is not a list type".to_owned()
 }
+
+pub fn error_not_inferred() -> String {
+    "Typed AST is missing the inferred type of this node".to_owned()
+}
+
+pub fn error_call_arity(expected: usize, got: usize) -> String {
+    format!(
+        "Typed AST has a call with {} arguments to a function with {} parameters",
+        got, expected
+    )
+}
+
+pub fn error_declaration_position() -> String {
+    "Typed AST has a declaration in a scope where it is not allowed".to_owned()
+}
+
+pub fn error_method_no_self(name: &str) -> String {
+    format!(
+        "Typed AST has a method without the self parameter: {}",
+        name
+    )
+}
+
+pub fn error_member_kind(name: &str) -> String {
+    format!(
+        "Typed AST has an attribute and a method of the same name in a class: {}",
+        name
+    )
+}
@@ -0,0 +1,372 @@
+// Rewording of some type errors for people used to Python.
+// Not applied to typed AST output, which is compared against the reference.
+
+use super::error::*;
+use crate::node::*;
+use std::collections::HashMap;
+
+// Names and types of the parameters, and the return type of a method
+type Signature = (Vec<(String, ValueType)>, ValueType);
+
+struct ClassMethods {
+    super_class: String,
+    methods: HashMap<String, Signature>,
+}
+
+fn signature(f: &FuncDef) -> Signature {
+    let params = f
+        .params
+        .iter()
+        .map(|p| {
+            (
+                p.identifier.name.clone(),
+                ValueType::from_annotation(&p.type_),
+            )
+        })
+        .collect();
+    (params, ValueType::from_annotation(&f.return_type))
+}
+
+// Methods declared by each class, as they are written
+fn class_methods(declarations: &[Declaration]) -> HashMap<String, ClassMethods> {
+    let mut classes = HashMap::new();
+    classes.insert(
+        "object".to_owned(),
+        ClassMethods {
+            super_class: "".to_owned(),
+            methods: std::iter::once((
+                "__init__".to_owned(),
+                (
+                    vec![("self".to_owned(), TYPE_OBJECT.clone())],
+                    TYPE_NONE.clone(),
+                ),
+            ))
+            .collect(),
+        },
+    );
+    for declaration in declarations {
+        if let Declaration::ClassDef(c) = declaration {
+            let methods = c
+                .declarations
+                .iter()
+                .filter_map(|d| match d {
+                    Declaration::FuncDef(f) => Some((f.name.name.clone(), signature(f))),
+                    _ => None,
+                })
+                .collect();
+            classes.entry(c.name.name.clone()).or_insert(ClassMethods {
+                super_class: c.super_class.name.clone(),
+                methods,
+            });
+        }
+    }
+    classes
+}
+
+// Names declared in a scope, and whether each is a function or class
+type Scope = HashMap<String, bool>;
+
+const BUILTINS: [&str; 11] = [
+    "print", "input", "len", "obj_id", "collect", "seed", "randint", "int", "bool", "str", "object",
+];
+
+fn scope(declarations: &[Declaration]) -> Scope {
+    declarations
+        .iter()
+        .map(|d| {
+            let function = matches!(d, Declaration::FuncDef(_) | Declaration::ClassDef(_));
+            (d.name().name.clone(), function)
+        })
+        .collect()
+}
+
+// The declaration to suggest for a loop variable over `iterable`
+fn loop_variable(name: &str, iterable: &ValueType) -> Option<String> {
+    let element = if iterable == &*TYPE_STR {
+        iterable
+    } else if let ValueType::ListValueType(list) = iterable {
+        &list.element_type
+    } else {
+        return None;
+    };
+    let value = if element == &*TYPE_INT {
+        "0"
+    } else if element == &*TYPE_BOOL {
+        "False"
+    } else if element == &*TYPE_STR {
+        "\"\""
+    } else if element == &*TYPE_NONE || element == &*TYPE_EMPTY {
+        return None;
+    } else {
+        "None"
+    };
+    Some(format!("{}:{} = {}", name, element, value))
+}
+
+struct Friendly<'a> {
+    errors: &'a mut [CompilerError],
+    classes: HashMap<String, ClassMethods>,
+    // From the global scope to the innermost function
+    scopes: Vec<Scope>,
+}
+
+impl Friendly<'_> {
+    fn reword(&mut self, base: &mut NodeBase, old: String, new: String) {
+        if base.error_msg.as_ref() != Some(&old) {
+            return;
+        }
+        for error in self.errors.iter_mut() {
+            if error.base.location == base.location && error.message == old {
+                error.message = new.clone();
+            }
+        }
+        base.error_msg = Some(new);
+    }
+
+    // Use the wording that the checker prepared for the error on this node, if any
+    fn prepared(&mut self, base: &mut NodeBase) {
+        let old = if let Some(old) = &base.error_msg {
+            old.clone()
+        } else {
+            return;
+        };
+        let new = self
+            .errors
+            .iter()
+            .find(|error| error.base.location == base.location && error.message == old)
+            .and_then(|error| error.friendly.clone());
+        if let Some(new) = new {
+            self.reword(base, old, new);
+        }
+    }
+
+    fn is_function(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    // Explain that loop variables need declarations, unless the name is a function
+    fn for_target(&mut self, s: &mut ForStmt) {
+        let name = &s.identifier.name;
+        let new = if self.is_function(name) {
+            friendly_for_function(name)
+        } else {
+            // The type of an iterable with errors comes from recovery and can be misleading
+            let location = s.iterable.base().location;
+            let declaration = if self
+                .errors
+                .iter()
+                .any(|error| location.contains(error.base.location.start))
+            {
+                None
+            } else {
+                s.iterable
+                    .inferred_type
+                    .as_ref()
+                    .and_then(|iterable| loop_variable(name, iterable))
+            };
+            friendly_for_undeclared(name, declaration)
+        };
+        self.reword(&mut s.base, error_variable(name), new);
+    }
+
+    // The closest definition of a method that `class_name` inherits, with the class defining it
+    fn inherited(&self, class_name: &str, method: &str) -> Option<(&str, &Signature)> {
+        let mut name = self.classes.get(class_name)?.super_class.as_str();
+        // Bounded in case of an erroneous cycle of super classes
+        for _ in 0..self.classes.len() {
+            let class = self.classes.get(name)?;
+            if let Some(signature) = class.methods.get(method) {
+                return Some((name, signature));
+            }
+            name = &class.super_class;
+        }
+        None
+    }
+
+    // Point out the part of the signature that differs from the overridden method
+    fn method_override(&mut self, class_name: &str, f: &mut FuncDef) {
+        let name = f.name.name.clone();
+        let (params, return_type) = signature(f);
+        let new = {
+            let (super_class, (super_params, super_return)) =
+                if let Some(inherited) = self.inherited(class_name, &name) {
+                    inherited
+                } else {
+                    return;
+                };
+            // `self` differs by design, and a wrong `self` is reported separately
+            let param = params
+                .iter()
+                .zip(super_params)
+                .skip(1)
+                .find(|((_, got), (_, expected))| got != expected);
+            if params.len() != super_params.len() {
+                friendly_override_count(&name, super_class, super_params.len(), params.len())
+            } else if let Some(((param, got), (_, expected))) = param {
+                friendly_override_param(&name, super_class, param, expected, got)
+            } else if &return_type != super_return {
+                friendly_override_return(&name, super_class, super_return, &return_type)
+            } else {
+                return;
+            }
+        };
+        self.reword(&mut f.name.base, error_method_override(&name), new);
+    }
+
+    // `shadowed`: whether `print` refers to a local declaration instead of the builtin
+    fn expr(&mut self, expr: &mut Expr, shadowed: bool) {
+        match &mut expr.content {
+            ExprContent::BinaryExpr(e) => {
+                self.expr(&mut e.left, shadowed);
+                self.expr(&mut e.right, shadowed);
+                let operator = match e.operator {
+                    BinaryOp::Eq => "==",
+                    BinaryOp::Ne => "!=",
+                    BinaryOp::Is => "is",
+                    _ => return,
+                };
+                if let (Some(left), Some(right)) = (&e.left.inferred_type, &e.right.inferred_type) {
+                    // Values of these types have no identity to compare with `is`
+                    let is_value = |t| [&*TYPE_INT, &*TYPE_BOOL, &*TYPE_STR].contains(&t);
+                    let value = if is_value(left) { left } else { right };
+                    let new = if e.operator == BinaryOp::Is && is_value(value) {
+                        friendly_is_value(left, right, value)
+                    } else if e.operator != BinaryOp::Is
+                        && (left == &*TYPE_NONE || right == &*TYPE_NONE)
+                    {
+                        friendly_none_compare(operator, left, right)
+                    } else {
+                        return;
+                    };
+                    self.reword(&mut e.base, error_binary(operator, left, right), new);
+                }
+            }
+            ExprContent::CallExpr(e) => {
+                self.exprs(&mut e.args, shadowed);
+                let got = e.args.len();
+                if e.function.name == "print" && !shadowed && got != 1 {
+                    self.reword(
+                        &mut e.base,
+                        error_call_count(1, got),
+                        friendly_print_count(got),
+                    );
+                }
+                self.prepared(&mut e.base);
+            }
+            ExprContent::IfExpr(e) => {
+                self.expr(&mut e.condition, shadowed);
+                self.expr(&mut e.then_expr, shadowed);
+                self.expr(&mut e.else_expr, shadowed);
+            }
+            ExprContent::IndexExpr(e) => {
+                self.expr(&mut e.list, shadowed);
+                self.expr(&mut e.index, shadowed);
+            }
+            ExprContent::ListExpr(e) => self.exprs(&mut e.elements, shadowed),
+            ExprContent::MemberExpr(e) => self.expr(&mut e.object, shadowed),
+            ExprContent::MethodCallExpr(e) => {
+                self.expr(&mut e.method.object, shadowed);
+                self.exprs(&mut e.args, shadowed);
+                self.prepared(&mut e.base);
+            }
+            ExprContent::UnaryExpr(e) => self.expr(&mut e.operand, shadowed),
+            ExprContent::IntegerLiteral(_)
+            | ExprContent::BooleanLiteral(_)
+            | ExprContent::Variable(_)
+            | ExprContent::NoneLiteral(_)
+            | ExprContent::StringLiteral(_) => (),
+        }
+    }
+
+    fn exprs(&mut self, exprs: &mut [Expr], shadowed: bool) {
+        for expr in exprs {
+            self.expr(expr, shadowed);
+        }
+    }
+
+    fn stmts(&mut self, statements: &mut [Stmt], shadowed: bool) {
+        for statement in statements {
+            match statement {
+                Stmt::ExprStmt(s) => self.expr(&mut s.expr, shadowed),
+                Stmt::AssignStmt(s) => {
+                    self.exprs(&mut s.targets, shadowed);
+                    self.expr(&mut s.value, shadowed);
+                    self.prepared(&mut s.base);
+                }
+                Stmt::ForStmt(s) => {
+                    self.expr(&mut s.iterable, shadowed);
+                    self.stmts(&mut s.body, shadowed);
+                    self.for_target(s);
+                }
+                Stmt::IfStmt(s) => {
+                    self.expr(&mut s.condition, shadowed);
+                    self.stmts(&mut s.then_body, shadowed);
+                    self.stmts(&mut s.else_body, shadowed);
+                }
+                Stmt::ReturnStmt(s) => {
+                    if let Some(value) = &mut s.value {
+                        self.expr(value, shadowed);
+                    }
+                    self.prepared(&mut s.base);
+                }
+                Stmt::WhileStmt(s) => {
+                    self.expr(&mut s.condition, shadowed);
+                    self.stmts(&mut s.body, shadowed);
+                }
+            }
+        }
+    }
+
+    fn decls(&mut self, declarations: &mut [Declaration], shadowed: bool) {
+        for declaration in declarations {
+            match declaration {
+                Declaration::FuncDef(f) => {
+                    let shadowed = shadowed
+                        || f.params.iter().any(|p| p.identifier.name == "print")
+                        || f.declarations.iter_mut().any(|d| match d {
+                            Declaration::GlobalDecl(_) => false,
+                            d => d.name_mut().name == "print",
+                        });
+                    let mut locals = scope(&f.declarations);
+                    for param in &f.params {
+                        locals.insert(param.identifier.name.clone(), false);
+                    }
+                    self.scopes.push(locals);
+                    self.decls(&mut f.declarations, shadowed);
+                    self.stmts(&mut f.statements, shadowed);
+                    self.scopes.pop();
+                }
+                // Methods are not in scope inside the class body
+                Declaration::ClassDef(c) => {
+                    for declaration in &mut c.declarations {
+                        if let Declaration::FuncDef(f) = declaration {
+                            self.method_override(&c.name.name, f);
+                        }
+                    }
+                    self.decls(&mut c.declarations, shadowed)
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+pub fn friendly(ast: &mut Program) {
+    let mut friendly = Friendly {
+        errors: &mut ast.errors.errors,
+        classes: class_methods(&ast.declarations),
+        scopes: vec![],
+    };
+    let mut globals = scope(&ast.declarations);
+    for &builtin in &BUILTINS {
+        globals.entry(builtin.to_owned()).or_insert(true);
+    }
+    friendly.scopes.push(globals);
+    friendly.decls(&mut ast.declarations, false);
+    friendly.stmts(&mut ast.statements, false);
+}
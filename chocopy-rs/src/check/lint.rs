@@ -0,0 +1,152 @@
+// Warnings for valid code that is likely a mistake.
+// Only run on programs that pass type checking.
+
+use super::error::*;
+use crate::node::*;
+
+fn warn(warnings: &mut Vec<CompilerError>, node: &impl Node, message: String) {
+    warnings.push(CompilerError {
+        base: NodeBase::from_location(node.base().location),
+        message,
+        code: None,
+        syntax: false,
+        friendly: None,
+    });
+}
+
+fn lint_expr(expr: &Expr, warnings: &mut Vec<CompilerError>) {
+    match &expr.content {
+        ExprContent::BinaryExpr(e) => {
+            // The divisor is checked at runtime, so the program is still valid
+            if matches!(e.operator, BinaryOp::Div | BinaryOp::Mod)
+                && matches!(
+                    e.right.content,
+                    ExprContent::IntegerLiteral(IntegerLiteral { value: 0, .. })
+                )
+            {
+                warn(warnings, expr, warning_div_zero());
+            }
+            lint_expr(&e.left, warnings);
+            lint_expr(&e.right, warnings);
+        }
+        ExprContent::CallExpr(e) => lint_exprs(&e.args, warnings),
+        ExprContent::IfExpr(e) => {
+            lint_expr(&e.condition, warnings);
+            lint_expr(&e.then_expr, warnings);
+            lint_expr(&e.else_expr, warnings);
+        }
+        ExprContent::IndexExpr(e) => {
+            lint_expr(&e.list, warnings);
+            lint_expr(&e.index, warnings);
+        }
+        ExprContent::ListExpr(e) => {
+            // Elements of different types are joined to `object`, which is easy to miss
+            if let ValueType::ListValueType(l) = expr.get_type() {
+                if *l.element_type == *TYPE_OBJECT
+                    && e.elements.iter().any(|e| *e.get_type() != *TYPE_OBJECT)
+                {
+                    warn(warnings, expr, warning_object_list());
+                }
+            }
+            lint_exprs(&e.elements, warnings);
+        }
+        ExprContent::MemberExpr(e) => lint_expr(&e.object, warnings),
+        ExprContent::MethodCallExpr(e) => {
+            lint_expr(&e.method.object, warnings);
+            lint_exprs(&e.args, warnings);
+        }
+        ExprContent::UnaryExpr(e) => lint_expr(&e.operand, warnings),
+        ExprContent::IntegerLiteral(_)
+        | ExprContent::BooleanLiteral(_)
+        | ExprContent::Variable(_)
+        | ExprContent::NoneLiteral(_)
+        | ExprContent::StringLiteral(_) => (),
+    }
+}
+
+// Whether a return statement is among the statements or nested in their bodies
+fn has_return(statements: &[Stmt]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Stmt::ReturnStmt(_) => true,
+        Stmt::ForStmt(s) => has_return(&s.body),
+        Stmt::IfStmt(s) => has_return(&s.then_body) || has_return(&s.else_body),
+        Stmt::WhileStmt(s) => has_return(&s.body),
+        Stmt::ExprStmt(_) | Stmt::AssignStmt(_) => false,
+    })
+}
+
+fn lint_exprs(exprs: &[Expr], warnings: &mut Vec<CompilerError>) {
+    for expr in exprs {
+        lint_expr(expr, warnings);
+    }
+}
+
+fn lint_stmts(statements: &[Stmt], warnings: &mut Vec<CompilerError>) {
+    for statement in statements {
+        match statement {
+            Stmt::ExprStmt(s) => lint_expr(&s.expr, warnings),
+            Stmt::AssignStmt(s) => {
+                lint_exprs(&s.targets, warnings);
+                lint_expr(&s.value, warnings);
+            }
+            Stmt::ForStmt(s) => {
+                lint_expr(&s.iterable, warnings);
+                lint_stmts(&s.body, warnings);
+            }
+            Stmt::IfStmt(s) => {
+                // Constant conditions leave `elif`/`else` or the whole `if` dead
+                match s.condition.content {
+                    ExprContent::BooleanLiteral(BooleanLiteral { value: true, .. }) => {
+                        if let Some(first) = s.else_body.first() {
+                            warn(warnings, first, warning_dead_else());
+                        }
+                    }
+                    ExprContent::BooleanLiteral(BooleanLiteral { value: false, .. }) => {
+                        warn(warnings, s, warning_dead_if());
+                    }
+                    _ => (),
+                }
+                lint_expr(&s.condition, warnings);
+                lint_stmts(&s.then_body, warnings);
+                lint_stmts(&s.else_body, warnings);
+            }
+            Stmt::ReturnStmt(s) => {
+                if let Some(value) = &s.value {
+                    lint_expr(value, warnings);
+                }
+            }
+            Stmt::WhileStmt(s) => {
+                // Returning is the only way out of the loop other than a runtime error
+                if matches!(
+                    s.condition.content,
+                    ExprContent::BooleanLiteral(BooleanLiteral { value: true, .. })
+                ) && !has_return(&s.body)
+                {
+                    warn(warnings, s, warning_infinite_loop());
+                }
+                lint_expr(&s.condition, warnings);
+                lint_stmts(&s.body, warnings);
+            }
+        }
+    }
+}
+
+fn lint_decls(declarations: &[Declaration], warnings: &mut Vec<CompilerError>) {
+    for declaration in declarations {
+        match declaration {
+            Declaration::FuncDef(f) => {
+                lint_decls(&f.declarations, warnings);
+                lint_stmts(&f.statements, warnings);
+            }
+            Declaration::ClassDef(c) => lint_decls(&c.declarations, warnings),
+            _ => (),
+        }
+    }
+}
+
+pub fn lint(ast: &Program) -> Vec<CompilerError> {
+    let mut warnings = vec![];
+    lint_decls(&ast.declarations, &mut warnings);
+    lint_stmts(&ast.statements, &mut warnings);
+    warnings
+}
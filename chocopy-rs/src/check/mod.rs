@@ -1,38 +1,57 @@
 mod analyze;
 mod class_env;
 mod error;
+mod friendly;
+mod lint;
+mod types;
+mod validate;
 
+use crate::explain;
 use crate::local_env::*;
 use crate::node::*;
-use class_env::*;
 use error::*;
 use std::collections::{HashMap, HashSet};
 
-fn check_var_def(v: &mut VarDef, errors: &mut Vec<CompilerError>, classes: &ClassEnv) {
-    let core_type = v.var.type_.core_type_mut();
-    if !classes.contains(&core_type.class_name) {
-        let msg = error_invalid_type(&core_type.class_name);
-        core_type.add_error(errors, msg);
-    }
+pub use class_env::ClassEnv;
+pub use friendly::friendly;
+pub use types::{Signature, TypeId, TypeInterner};
+pub use validate::validate;
+
+// Language extensions that only change type checking
+#[derive(Clone, Copy, Default, Hash)]
+pub struct Options {
+    pub list_eq: bool, // Allow `==` and `!=` on lists of int, bool or str (extension)
+    pub list_append: bool, // Allow `append(list, x)` returning a new, longer list (extension)
+    pub truthy_and_or: bool, // Allow `and` and `or` on any values, giving an operand (extension)
+    pub enumerate: bool, // Allow `for i, x in enumerate(list)` with the index in `i` (extension)
+    pub class_eq: bool, // Allow `==` and `!=` on two objects of the same user class (extension)
+    pub func_values: bool, // Allow top-level functions as values of `Callable` types (extension)
+    pub membership: bool, // Allow `x in list`, `s in str` and `count(list, x)` (extension)
 }
 
-fn always_return(statements: &[Stmt]) -> bool {
-    for statement in statements {
-        match statement {
-            Stmt::ReturnStmt(_) => return true,
-            Stmt::IfStmt(IfStmt {
-                then_body,
-                else_body,
-                ..
-            }) => {
-                if always_return(then_body) && always_return(else_body) {
-                    return true;
-                }
-            }
-            _ => (),
+const BUILTIN_FUNCTIONS: [&str; 7] = [
+    "print", "input", "len", "obj_id", "collect", "seed", "randint",
+];
+
+// Every class in a type annotation must be defined. `Callable` types need the extension
+fn check_type(t: &mut TypeAnnotation, errors: &mut Vec<CompilerError>, classes: &ClassEnv) {
+    if let TypeAnnotation::CallableType(c) = t {
+        if !classes.options().func_values {
+            let msg = error_invalid_type("Callable");
+            c.add_coded_error(errors, explain::INVALID_TYPE, msg);
+            return;
+        }
+    }
+    for class_type in t.class_types_mut() {
+        if !classes.contains(&class_type.class_name) {
+            let msg = error_invalid_type(&class_type.class_name);
+            class_type.add_coded_error(errors, explain::INVALID_TYPE, msg);
         }
     }
-    false
+}
+
+fn check_var_def(v: &mut VarDef, errors: &mut Vec<CompilerError>, classes: &ClassEnv) {
+    check_type(&mut v.var.type_, errors, classes);
 }
 
 fn check_func(
@@ -47,11 +66,7 @@ fn check_func(
     // Check parameter type, collision and shadowing
     // semantic rule: 1(param), 2(param), 11(param)
     for param in &mut f.params {
-        let core_type = param.type_.core_type_mut();
-        if !classes.contains(&core_type.class_name) {
-            let msg = error_invalid_type(&core_type.class_name);
-            core_type.add_error(errors, msg);
-        }
+        check_type(&mut param.type_, errors, classes);
 
         let id = &mut param.identifier;
         if classes.contains(&id.name) {
@@ -68,11 +83,7 @@ fn check_func(
 
     // Check return type
     // semantic rule: 11(return)
-    let core_type = f.return_type.core_type_mut();
-    if !classes.contains(&core_type.class_name) {
-        let msg = error_invalid_type(&core_type.class_name);
-        core_type.add_error(errors, msg);
-    }
+    check_type(&mut f.return_type, errors, classes);
 
     let mut nonlocal_remove = HashSet::new();
     // semantic rule: 1, 2(local/function), 3, 11(local)
@@ -87,11 +98,7 @@ fn check_func(
         match decl {
             Declaration::VarDef(v) => {
                 let var = &mut v.var;
-                let core_type = var.type_.core_type_mut();
-                if !classes.contains(&core_type.class_name) {
-                    let msg = error_invalid_type(&core_type.class_name);
-                    core_type.add_error(errors, msg);
-                }
+                check_type(&mut var.type_, errors, classes);
 
                 let id = &mut var.identifier;
                 if classes.contains(&id.name) {
@@ -149,7 +156,107 @@ fn check_func(
     }
 }
 
-pub fn check(mut ast: Program) -> Program {
+// Builtins and global declarations, as seen from the top level of the program
+fn global_env(
+    declarations: &[Declaration],
+    types: &mut TypeInterner,
+) -> HashMap<String, LocalSlot<Signature, TypeId>> {
+    let mut global_env = HashMap::new();
+    global_env.insert(
+        "print".to_owned(),
+        LocalSlot::Func(Signature {
+            parameters: vec![TypeId::OBJECT],
+            return_type: TypeId::NONE,
+        }),
+    );
+    global_env.insert(
+        "input".to_owned(),
+        LocalSlot::Func(Signature {
+            parameters: vec![],
+            return_type: TypeId::STR,
+        }),
+    );
+    global_env.insert(
+        "len".to_owned(),
+        LocalSlot::Func(Signature {
+            parameters: vec![TypeId::OBJECT],
+            return_type: TypeId::INT,
+        }),
+    );
+    global_env.insert(
+        "obj_id".to_owned(),
+        LocalSlot::Func(Signature {
+            parameters: vec![TypeId::OBJECT],
+            return_type: TypeId::INT,
+        }),
+    );
+    global_env.insert(
+        "collect".to_owned(),
+        LocalSlot::Func(Signature {
+            parameters: vec![],
+            return_type: TypeId::NONE,
+        }),
+    );
+    global_env.insert(
+        "seed".to_owned(),
+        LocalSlot::Func(Signature {
+            parameters: vec![TypeId::INT],
+            return_type: TypeId::NONE,
+        }),
+    );
+    global_env.insert(
+        "randint".to_owned(),
+        LocalSlot::Func(Signature {
+            parameters: vec![TypeId::INT, TypeId::INT],
+            return_type: TypeId::INT,
+        }),
+    );
+
+    for &(name, return_type) in &[
+        ("int", TypeId::INT),
+        ("bool", TypeId::BOOL),
+        ("str", TypeId::STR),
+        ("object", TypeId::OBJECT),
+    ] {
+        global_env.insert(
+            name.to_owned(),
+            LocalSlot::Func(Signature {
+                parameters: vec![],
+                return_type,
+            }),
+        );
+    }
+
+    for decl in declarations {
+        match decl {
+            Declaration::FuncDef(f) => {
+                global_env.insert(f.name.name.clone(), LocalSlot::Func(f.signature(types)));
+            }
+            Declaration::ClassDef(c) => {
+                let name = &c.name.name;
+                let class_name = types.symbol(name);
+                global_env.insert(
+                    name.clone(),
+                    LocalSlot::Func(Signature {
+                        parameters: vec![],
+                        return_type: types.class(class_name),
+                    }),
+                );
+            }
+            Declaration::VarDef(v) => {
+                let name = &v.var.identifier.name;
+                global_env.insert(
+                    name.clone(),
+                    LocalSlot::Var(types.annotation_type(&v.var.type_)),
+                );
+            }
+            _ => panic!(),
+        }
+    }
+    global_env
+}
+
+pub fn check(mut ast: Program, options: Options) -> Program {
     let mut errors = vec![];
 
     let mut id_set = HashSet::new();
@@ -157,11 +264,9 @@ pub fn check(mut ast: Program) -> Program {
     id_set.insert("bool".to_owned());
     id_set.insert("int".to_owned());
     id_set.insert("object".to_owned());
-    id_set.insert("print".to_owned());
-    id_set.insert("input".to_owned());
-    id_set.insert("len".to_owned());
+    id_set.extend(BUILTIN_FUNCTIONS.iter().map(|&name| name.to_owned()));
 
-    let mut classes = ClassEnv::new();
+    let mut classes = ClassEnv::new(options);
 
     // Pass A
     // semantic rule: 1(global/class), 4, 5, 6, 7
@@ -201,87 +306,12 @@ pub fn check(mut ast: Program) -> Program {
         }
     }
 
-    let mut global_env: HashMap<String, LocalSlot<FuncType, ValueType>> = HashMap::new();
-    global_env.insert(
-        "print".to_owned(),
-        LocalSlot::Func(FuncType {
-            parameters: vec![TYPE_OBJECT.clone()],
-            return_type: TYPE_NONE.clone(),
-        }),
-    );
-    global_env.insert(
-        "input".to_owned(),
-        LocalSlot::Func(FuncType {
-            parameters: vec![],
-            return_type: TYPE_STR.clone(),
-        }),
-    );
-    global_env.insert(
-        "len".to_owned(),
-        LocalSlot::Func(FuncType {
-            parameters: vec![TYPE_OBJECT.clone()],
-            return_type: TYPE_INT.clone(),
-        }),
-    );
-
-    global_env.insert(
-        "int".to_owned(),
-        LocalSlot::Func(FuncType {
-            parameters: vec![],
-            return_type: ValueType::ClassValueType(ClassValueType {
-                class_name: "int".to_owned(),
-            }),
-        }),
-    );
-
-    global_env.insert(
-        "bool".to_owned(),
-        LocalSlot::Func(FuncType {
-            parameters: vec![],
-            return_type: ValueType::ClassValueType(ClassValueType {
-                class_name: "bool".to_owned(),
-            }),
-        }),
-    );
-
-    global_env.insert(
-        "str".to_owned(),
-        LocalSlot::Func(FuncType {
-            parameters: vec![],
-            return_type: ValueType::ClassValueType(ClassValueType {
-                class_name: "str".to_owned(),
-            }),
-        }),
-    );
-
-    global_env.insert(
-        "object".to_owned(),
-        LocalSlot::Func(FuncType {
-            parameters: vec![],
-            return_type: ValueType::ClassValueType(ClassValueType {
-                class_name: "object".to_owned(),
-            }),
-        }),
-    );
-
     // Pass C
     // semantic rules: 1(function), 2, 3, 9, 11(function)
-    // collects global environment
     for decl in &mut ast.declarations {
         match decl {
             Declaration::FuncDef(f) => {
                 check_func(f, &mut errors, &classes, &globals, &HashSet::new());
-                global_env.insert(
-                    f.name.name.clone(),
-                    LocalSlot::Func(FuncType {
-                        parameters: f
-                            .params
-                            .iter()
-                            .map(|tv| ValueType::from_annotation(&tv.type_))
-                            .collect(),
-                        return_type: ValueType::from_annotation(&f.return_type),
-                    }),
-                );
             }
             Declaration::ClassDef(c) => {
                 for decl in &mut c.declarations {
@@ -289,25 +319,8 @@ pub fn check(mut ast: Program) -> Program {
                         check_func(f, &mut errors, &classes, &globals, &HashSet::new())
                     }
                 }
-                let name = &c.name.name;
-                global_env.insert(
-                    name.clone(),
-                    LocalSlot::Func(FuncType {
-                        parameters: vec![],
-                        return_type: ValueType::ClassValueType(ClassValueType {
-                            class_name: name.clone(),
-                        }),
-                    }),
-                );
             }
-            Declaration::VarDef(v) => {
-                let name = &v.var.identifier.name;
-                global_env.insert(
-                    name.clone(),
-                    LocalSlot::Var(ValueType::from_annotation(&v.var.type_)),
-                );
-            }
-            _ => panic!(),
+            _ => (),
         }
     }
 
@@ -315,57 +328,699 @@ pub fn check(mut ast: Program) -> Program {
     // semantic rules: 8, 10
     // and type checking
     if errors.is_empty() {
-        let mut env = LocalEnv::new(global_env);
-        ast.analyze(&mut errors, &mut env, &classes);
+        let mut env = LocalEnv::new(global_env(&ast.declarations, &mut classes.types));
+        ast.analyze(&mut errors, &mut env, &mut classes);
     }
 
+    let warnings = if errors.is_empty() {
+        lint::lint(&ast)
+    } else {
+        vec![]
+    };
+
     ast.errors = Errors {
         base: NodeBase::new(0, 0, 0, 0),
         errors,
+        warnings,
     };
     ast.errors.sort();
     ast
 }
 
+fn with_frames<R>(
+    env: &mut LocalEnv<Signature, TypeId>,
+    classes: &mut ClassEnv,
+    scope: &[&FuncDef],
+    f: impl FnOnce(&LocalEnv<Signature, TypeId>, &ClassEnv) -> R,
+) -> R {
+    match scope.split_first() {
+        None => f(env, classes),
+        Some((func, inner)) => {
+            let mut handle = env.push(func.frame(&mut classes.types));
+            with_frames(handle.inner(), classes, inner, f)
+        }
+    }
+}
+
+// Rebuilds the environment that type checking uses inside the nested functions `scope`,
+// listed from the outermost, and passes it to `f` along with the class environment.
+// Works on programs with errors, in which case erroneous declarations may be missing.
+pub fn environment<R>(
+    ast: &Program,
+    scope: &[&FuncDef],
+    options: Options,
+    f: impl FnOnce(&LocalEnv<Signature, TypeId>, &ClassEnv) -> R,
+) -> R {
+    let mut classes = ClassEnv::new(options);
+    for decl in &ast.declarations {
+        if let Declaration::ClassDef(c) = decl {
+            // Errors were already reported by check
+            classes.add_class(&mut c.clone(), &mut vec![], &HashSet::new());
+        }
+    }
+
+    let mut env = LocalEnv::new(global_env(&ast.declarations, &mut classes.types));
+    with_frames(&mut env, &mut classes, scope, f)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::{stdout, Write};
+    use crate::snapshot::{self, Snapshots};
+
     #[test]
     fn sample() {
-        let mut passed = true;
-        let test_dirs = ["test/pa2", "test/original/pa2"];
-        for dir in &test_dirs {
+        let mut snapshots = Snapshots::new();
+        let test_dirs = [
+            ("test/pa2", false),
+            ("test/original/pa2", false),
+            ("test/ext", false),
+            ("test/friendly", true),
+        ];
+        for &(dir, friendly_errors) in &test_dirs {
             println!("Testing Directory {}", dir);
-            let mut files = std::fs::read_dir(dir)
-                .unwrap()
-                .map(|f| f.unwrap())
-                .filter(|f| f.file_name().to_str().unwrap().ends_with(".ast"))
-                .map(|f| f.path())
-                .collect::<Vec<_>>();
-            files.sort();
-
-            for ast_file in files {
-                let mut typed_file = ast_file.clone();
-                let mut file_name = ast_file.file_name().unwrap().to_owned();
-                print!("Testing {} ---- ", file_name.to_str().unwrap());
-                stdout().flush().unwrap();
-                file_name.push(".typed");
-                typed_file.set_file_name(file_name);
+            for ast_file in snapshot::files(dir, ".ast") {
+                snapshots.start(&ast_file);
+                let typed_file = snapshot::with_suffix(&ast_file, ".typed");
                 let ast_string = String::from_utf8(std::fs::read(ast_file).unwrap()).unwrap();
-                let typed_string = String::from_utf8(std::fs::read(typed_file).unwrap()).unwrap();
-                let ast = serde_json::from_str::<Program>(&ast_string).unwrap();
-                let mut typed = serde_json::from_str::<Program>(&typed_string).unwrap();
-                let result = check(ast);
+                let typed_string = String::from_utf8(std::fs::read(&typed_file).unwrap()).unwrap();
+                let ast = crate::parse::ast_from_json(&ast_string).unwrap();
+                let mut typed = crate::parse::ast_from_json(&typed_string).unwrap();
+                // Language extensions are enabled for their own test directory
+                let options = Options {
+                    list_eq: dir.ends_with("ext"),
+                    list_append: dir.ends_with("ext"),
+                    truthy_and_or: dir.ends_with("ext"),
+                    enumerate: dir.ends_with("ext"),
+                    class_eq: dir.ends_with("ext"),
+                    func_values: dir.ends_with("ext"),
+                    membership: dir.ends_with("ext"),
+                };
+                let mut result = check(ast, options);
+                if friendly_errors {
+                    friendly(&mut result);
+                }
+                if dir.starts_with("test/original") {
+                    crate::conformance::as_reference(&mut result);
+                }
                 typed.errors.sort();
-                if result == typed {
-                    println!("\x1b[32mOK\x1b[0m");
+                let expected = serde_json::to_value(&typed).unwrap();
+                let actual = serde_json::to_value(&result).unwrap();
+                snapshots.compare(&typed_file, &expected, &actual, &actual);
+            }
+        }
+        assert!(snapshots.passed());
+    }
+
+    // A program with many classes and functions, exercising class lookups, joins and calls
+    fn large_program(lines: usize) -> String {
+        let mut source = String::new();
+        let mut count = 0;
+        let mut i = 0;
+        while count < lines {
+            let super_class = if i % 10 == 0 {
+                "object".to_owned()
+            } else {
+                format!("C{}", i - 1)
+            };
+            source += &format!(
+                "\
+class C{i}({super_class}):
+    x{i}:int = {i}
+    l{i}:[int] = None
+    def get{i}(self:\"C{i}\", o:object) -> int:
+        return self.x{i} + 1
+def f{i}(a:int, b:[int], c:C{i}) -> [object]:
+    d:[str] = None
+    e:bool = False
+    d = [\"x\", \"y\"]
+    e = a > len(b) and c.get{i}(None) == a
+    if e:
+        b = b + [a, c.x{i}, c.get{i}(d)]
+        return [c, b, d[0]]
+    else:
+        return [None, [], a if e else c]
+",
+                i = i,
+                super_class = super_class
+            );
+            count += 15;
+            i += 1;
+        }
+        source
+    }
+
+    #[test]
+    fn large_program_timing() {
+        let source = large_program(50000);
+        let ast = crate::parse::process_str(&source, Default::default());
+        assert!(ast.errors.errors.is_empty(), "{:?}", ast.errors.errors);
+        // Best of several runs, as allocation makes single runs noisy
+        let mut best = std::time::Duration::MAX;
+        for _ in 0..5 {
+            let ast = ast.clone();
+            let start = std::time::Instant::now();
+            let typed = check(ast, Default::default());
+            best = best.min(start.elapsed());
+            assert!(typed.errors.errors.is_empty(), "{:?}", typed.errors.errors);
+        }
+        println!("Checked {} lines in {:?}", source.lines().count(), best);
+    }
+
+    #[test]
+    fn validate_typed() {
+        let ast = crate::parse::load_ast("test/original/pa3/call.py.ast.typed").unwrap();
+        assert!(validate(ast.clone()).errors.errors.is_empty());
+
+        let mut broken = ast.clone();
+        if let Stmt::ExprStmt(s) = &mut broken.statements[0] {
+            s.expr.inferred_type = None;
+        }
+        let errors = validate(broken).errors.errors;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, error_not_inferred());
+
+        let mut broken = ast;
+        if let Stmt::ExprStmt(ExprStmt {
+            expr:
+                Expr {
+                    content: ExprContent::CallExpr(call),
+                    ..
+                },
+            ..
+        }) = &mut broken.statements[0]
+        {
+            call.args.push(call.args[0].clone());
+        }
+        let errors = validate(broken).errors.errors;
+        assert_eq!(errors.len(), 1);
+
+        let malformed = crate::gen::test_util::temp_path(".ast");
+        std::fs::write(&malformed, "{\"kind\": \"Program\",").unwrap();
+        let error = crate::parse::load_ast(malformed.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("line 1"));
+        std::fs::remove_file(malformed).unwrap();
+    }
+
+    #[test]
+    fn member_kind() {
+        let source = "\
+class A(object):
+    x:int = 1
+    def f(self:\"A\") -> int:
+        return 1
+class B(A):
+    g:int = 2
+    def y(self:\"B\") -> int:
+        return 2
+";
+        let rows = |errors: &[CompilerError], message: fn(&str) -> String| -> Vec<u32> {
+            errors
+                .iter()
+                .map(|error| {
+                    let name = if error.base.location.start.row == 6 {
+                        "f"
+                    } else {
+                        "x"
+                    };
+                    assert_eq!(error.message, message(name));
+                    error.base.location.start.row
+                })
+                .collect()
+        };
+
+        // An attribute named like an inherited method, and a method named like an inherited attribute
+        let renamed = source.replace("g:int", "f:int").replace("def y", "def x");
+        let ast = crate::parse::process_str(&renamed, Default::default());
+        let errors = check(ast, Default::default()).errors.errors;
+        assert_eq!(rows(&errors, error_attribute_redefine), [6, 7]);
+
+        // Typed ASTs from elsewhere skip the checker
+        let ast = crate::parse::process_str(source, Default::default());
+        let mut ast = check(ast, Default::default());
+        assert!(ast.errors.errors.is_empty());
+        if let Declaration::ClassDef(c) = &mut ast.declarations[1] {
+            c.declarations[0].name_mut().name = "f".to_owned();
+            c.declarations[1].name_mut().name = "x".to_owned();
+        }
+        let errors = validate(ast).errors.errors;
+        assert_eq!(rows(&errors, error_member_kind), [6, 7]);
+    }
+
+    #[test]
+    fn validate_names() {
+        let source = "\
+class A(object):
+    x:int = 1
+    def f(self:\"A\") -> int:
+        return self.x
+class B(A):
+    pass
+def g() -> int:
+    return 2
+a:A = None
+a = B()
+print(a.f() + g())
+";
+        let ast = crate::parse::process_str(source, Default::default());
+        let ast = check(ast, Default::default());
+        assert!(ast.errors.errors.is_empty());
+        assert!(validate(ast.clone()).errors.errors.is_empty());
+
+        // Names of declarations removed from the typed AST are reported where they are used
+        fn class_a(ast: &mut Program) -> &mut Vec<Declaration> {
+            match &mut ast.declarations[0] {
+                Declaration::ClassDef(c) => &mut c.declarations,
+                _ => panic!(),
+            }
+        }
+        let without = |remove: fn(&mut Program)| -> Vec<String> {
+            let mut ast = ast.clone();
+            remove(&mut ast);
+            let errors = validate(ast).errors.errors;
+            errors.into_iter().map(|error| error.message).collect()
+        };
+        let a = error_variable("a");
+        assert_eq!(
+            without(|ast| drop(ast.declarations.remove(3))),
+            [a.clone(), a]
+        );
+        let g = error_function("g");
+        assert_eq!(without(|ast| drop(ast.declarations.remove(2))), [g]);
+        let x = error_attribute("x", "A");
+        assert_eq!(without(|ast| drop(class_a(ast).remove(0))), [x]);
+        let f = error_method("f", "A");
+        assert_eq!(without(|ast| drop(class_a(ast).remove(1))), [f]);
+        let errors = without(|ast| drop(ast.declarations.remove(0)));
+        assert!(errors.contains(&error_super_undef("A")));
+    }
+
+    #[test]
+    fn str_method() {
+        let source = "\
+class A(object):
+    def __str__(self:\"A\") -> str:
+        return \"A\"
+class B(A):
+    def __str__(self:\"B\") -> str:
+        return \"B\"
+class C(object):
+    def __str__(self:\"C\") -> int:
+        return 1
+class D(object):
+    def __str__(self:\"D\", x:int) -> str:
+        return \"D\"
+";
+        let ast = crate::parse::process_str(source, Default::default());
+        let errors = check(ast, Default::default()).errors.errors;
+        let rows: Vec<_> = errors
+            .iter()
+            .map(|error| {
+                assert_eq!(error.message, error_method_str());
+                error.base.location.start.row
+            })
+            .collect();
+        assert_eq!(rows, [8, 11]);
+    }
+
+    #[test]
+    fn object_list_warning() {
+        let source = "\
+o:object = None
+[1, 2]
+[None, None]
+[o, o]
+[[1], [2, 3]]
+[1, \"x\"]
+[\"x\", None]
+[o, 1]
+[[1], [True]]
+";
+        let ast = crate::parse::process_str(source, Default::default());
+        let ast = check(ast, Default::default());
+        assert!(ast.errors.errors.is_empty(), "{:?}", ast.errors.errors);
+        let rows: Vec<_> = ast
+            .errors
+            .warnings
+            .iter()
+            .map(|warning| {
+                assert_eq!(warning.message, warning_object_list());
+                warning.base.location.start.row
+            })
+            .collect();
+        assert_eq!(rows, [6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn infinite_loop_warning() {
+        let source = "\
+def f(x: int) -> int:
+    while True:
+        if x > 0:
+            return x
+        x = x + 1
+    return 0
+
+def g() -> int:
+    while True:
+        while True:
+            pass
+    return 0
+
+x: int = 0
+while True:
+    x = x + 1
+while x < 10:
+    x = x + 1
+while False:
+    pass
+while True:
+    print(f(x))
+";
+        let ast = crate::parse::process_str(source, Default::default());
+        let ast = check(ast, Default::default());
+        assert!(ast.errors.errors.is_empty(), "{:?}", ast.errors.errors);
+        let rows: Vec<_> = ast
+            .errors
+            .warnings
+            .iter()
+            .map(|warning| {
+                assert_eq!(warning.message, warning_infinite_loop());
+                warning.base.location.start.row
+            })
+            .collect();
+        // The inner loop of g never ends, so neither does the outer one
+        assert_eq!(rows, [9, 10, 15, 21]);
+    }
+
+    #[test]
+    fn return_paths() {
+        // Each return type with a value of it. Only int, bool and str values can't be None
+        let types = [
+            ("int", "1"),
+            ("bool", "True"),
+            ("str", "\"s\""),
+            ("A", "A()"),
+            ("[int]", "[1]"),
+            ("<None>", "None"),
+        ];
+        for &(name, value) in &types {
+            let annotation = if name == "<None>" {
+                "".to_owned()
+            } else {
+                format!(" -> {}", name)
+            };
+            let plain = matches!(name, "int" | "bool" | "str");
+            for &ending in &["return ", "return", "pass"] {
+                let ending = if ending == "return " {
+                    ending.to_owned() + value
                 } else {
-                    println!("\x1b[31mError\x1b[0m");
-                    passed = false;
-                }
+                    ending.to_owned()
+                };
+                let source = format!(
+                    "class A(object):\n    pass\ndef f(){}:\n    {}\nf()\n",
+                    annotation, ending
+                );
+                let ast = crate::parse::process_str(&source, Default::default());
+                let errors = check(ast, Default::default()).errors.errors;
+                let messages: Vec<_> = errors.into_iter().map(|e| e.message).collect();
+                let expected = match ending.as_str() {
+                    "return" if plain => vec![format!("Expected type `{}`; got `None`", name)],
+                    "pass" if plain => vec![error_return("f")],
+                    _ => vec![],
+                };
+                assert_eq!(messages, expected, "{}", source);
             }
         }
-        assert!(passed);
+    }
+
+    #[test]
+    fn class_eq() {
+        let source = "\
+class A(object):
+    x:int = 0
+class B(A):
+    y:int = 1
+a:A = None
+b:B = None
+o:object = None
+print(a == A())
+print(a != a)
+print(a == b)
+print(a == None)
+print(o == o)
+print(None != None)
+print(b == B())
+";
+        let rows = |options: Options| -> Vec<u32> {
+            let ast = crate::parse::process_str(source, Default::default());
+            let errors = check(ast, options).errors.errors;
+            errors.iter().map(|e| e.base.location.start.row).collect()
+        };
+
+        // Only objects of exactly the same user class can be compared
+        let options = Options {
+            class_eq: true,
+            ..Default::default()
+        };
+        assert_eq!(rows(options), [10, 11, 12, 13]);
+        assert_eq!(rows(Default::default()), [8, 9, 10, 11, 12, 13, 14]);
+
+        let ast = crate::parse::process_str(source, Default::default());
+        let errors = check(ast, options).errors.errors;
+        let a = ValueType::ClassValueType(ClassValueType {
+            class_name: "A".to_owned(),
+        });
+        let b = ValueType::ClassValueType(ClassValueType {
+            class_name: "B".to_owned(),
+        });
+        assert_eq!(errors[0].message, error_binary("==", &a, &b));
+    }
+
+    #[test]
+    fn func_values_disabled() {
+        let source = "\
+def f(x:int) -> int:
+    return x
+g:Callable[[int], int] = None
+o:object = None
+o = f
+";
+        let rows = |options: Options| -> Vec<(u32, String)> {
+            let ast = crate::parse::process_str(source, Default::default());
+            let errors = check(ast, options).errors.errors;
+            errors
+                .into_iter()
+                .map(|e| (e.base.location.start.row, e.message))
+                .collect()
+        };
+
+        let options = Options {
+            func_values: true,
+            ..Default::default()
+        };
+        assert_eq!(rows(options), []);
+        assert_eq!(
+            rows(Default::default()),
+            [(3, error_invalid_type("Callable"))]
+        );
+
+        let source = source.replace("g:Callable[[int], int] = None\n", "");
+        let ast = crate::parse::process_str(&source, Default::default());
+        let errors = check(ast, Default::default()).errors.errors;
+        assert_eq!(errors[0].message, error_variable("f"));
+    }
+
+    #[test]
+    fn error_spans() {
+        // Declaration errors, then type errors, which are only reported without the former
+        let declarations = "\
+class A(object):
+    x:int = 0
+    def f(self:A) -> int:
+        pass
+class B(C):
+    x:int = 1
+class D(A):
+    def f(self:D, z:int) -> int:
+        return z
+x:int = 0
+x:bool = True
+";
+        let types = "\
+class A(object):
+    x:int = 0
+def g(y:int) -> int:
+    return y
+x:int = 0
+y = x
+x = \"a\"
+for x in True:
+    x = g(1, 2)
+print(A().y)
+print(-None)
+[1][True] = 2
+";
+        for (source, count) in [(declarations, 4), (types, 7)] {
+            let ast = crate::parse::process_str(source, Default::default());
+            let errors = check(ast, Default::default()).errors.errors;
+            assert_eq!(errors.len(), count);
+            for error in &errors {
+                assert!(!error.base.location.is_empty(), "{:?}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn membership_disabled() {
+        let source = "\
+l:[int] = None
+b:bool = False
+b = 1 in l
+b = \"a\" in \"abc\"
+count(l, 1)
+";
+        let rows = |options: Options| -> Vec<(u32, String)> {
+            let ast = crate::parse::process_str(source, Default::default());
+            let errors = check(ast, options).errors.errors;
+            errors
+                .into_iter()
+                .map(|e| (e.base.location.start.row, e.message))
+                .collect()
+        };
+
+        let options = Options {
+            membership: true,
+            ..Default::default()
+        };
+        assert_eq!(rows(options), []);
+        let int_list = ValueType::ListValueType(ListValueType {
+            element_type: Box::new(TYPE_INT.clone()),
+        });
+        assert_eq!(
+            rows(Default::default()),
+            [
+                (3, error_binary("in", &TYPE_INT, &int_list)),
+                (4, error_binary("in", &TYPE_STR, &TYPE_STR)),
+                (5, error_function("count")),
+            ]
+        );
+    }
+
+    #[test]
+    fn class_ancestry() {
+        let source = "\
+class A(object):
+    pass
+class B(A):
+    pass
+class C(B):
+    pass
+class D(Missing):
+    pass
+class E(D):
+    pass
+class F(F):
+    pass
+class G(int):
+    pass
+";
+        let mut ast = crate::parse::process_str(source, Default::default());
+        let mut classes = ClassEnv::new(Default::default());
+        let mut errors = vec![];
+        for decl in &mut ast.declarations {
+            if let Declaration::ClassDef(c) = decl {
+                classes.add_class(c, &mut errors, &HashSet::new());
+            }
+        }
+        assert_eq!(errors.len(), 3);
+
+        let symbol = |name: &str| classes.types.find_symbol(name).unwrap();
+        assert_eq!(classes.ancestors(symbol("object")), ["object"]);
+        assert_eq!(classes.ancestors(symbol("A")), ["A", "object"]);
+        assert_eq!(classes.ancestors(symbol("C")), ["C", "B", "A", "object"]);
+        // Super classes with errors end the chain where the checker stopped following them
+        assert_eq!(classes.ancestors(symbol("D")), ["D", "Missing"]);
+        assert_eq!(classes.ancestors(symbol("E")), ["E", "D", "Missing"]);
+        assert_eq!(classes.ancestors(symbol("F")), ["F", "F"]);
+        assert_eq!(classes.ancestors(symbol("G")), ["G", "int", "object"]);
+
+        let class = |name: &str| classes.types.find_class(symbol(name)).unwrap();
+        assert_eq!(
+            classes.ancestry(class("C"), class("E")).unwrap(),
+            "C -> B -> A -> object"
+        );
+        assert_eq!(
+            classes.ancestry(class("E"), class("A")).unwrap(),
+            "E -> D -> Missing"
+        );
+        // Nothing to show beyond `object`, or for types that are not user classes
+        assert_eq!(classes.ancestry(class("A"), class("E")), None);
+        assert_eq!(classes.ancestry(class("C"), TypeId::INT), None);
+        assert_eq!(classes.ancestry(TypeId::STR, class("A")), None);
+        assert_eq!(classes.ancestry(class("C"), TypeId::NONE), None);
+    }
+
+    #[test]
+    fn friendly_is() {
+        let source = "\
+x:object = None
+print(1 is 2)
+print(\"a\" is \"b\")
+print(x is None)
+print(x is True)
+";
+        let ast = crate::parse::process_str(source, Default::default());
+        let mut ast = check(ast, Default::default());
+        friendly(&mut ast);
+        let errors: Vec<_> = ast
+            .errors
+            .errors
+            .iter()
+            .map(|e| (e.base.location.start.row, e.message.as_str()))
+            .collect();
+        let str_message = friendly_is_value(&TYPE_STR, &TYPE_STR, &TYPE_STR);
+        let bool_message = friendly_is_value(&TYPE_OBJECT, &TYPE_BOOL, &TYPE_BOOL);
+        assert_eq!(
+            errors,
+            [
+                (
+                    2,
+                    "Cannot apply operator `is` on types `int` and `int`; \
+                     `is` cannot be used on values of type `int`; use `==`"
+                ),
+                (3, str_message.as_str()),
+                (5, bool_message.as_str()),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_range_loop_variable() {
+        // Only the arguments are reported, not the undeclared loop variable used in the loop.
+        // The variable is still undeclared after the loop and with a valid range
+        let source = "\
+for i in range(1, 2, 3, 4):
+    print(i + 1)
+print(i)
+for j in range(3):
+    pass
+";
+        let ast = crate::parse::process_str(source, Default::default());
+        let ast = check(ast, Default::default());
+        let errors: Vec<_> = ast
+            .errors
+            .errors
+            .iter()
+            .map(|e| (e.base.location.start.row, e.message.clone()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (1, error_range_count(4)),
+                (3, error_variable("i")),
+                (4, error_variable("j")),
+            ]
+        );
     }
 }
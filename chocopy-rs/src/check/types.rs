@@ -0,0 +1,249 @@
+// Interned types used during type checking.
+// Each distinct type is stored once and referred to by a small id, so that types can be copied
+// and compared without allocation. They are only turned back into `ValueType` when written
+// into the AST.
+
+use crate::node::*;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub const OBJECT: Symbol = Symbol(0);
+    pub const NONE: Symbol = Symbol(1);
+    pub const EMPTY: Symbol = Symbol(2);
+    pub const STR: Symbol = Symbol(3);
+    pub const INT: Symbol = Symbol(4);
+    pub const BOOL: Symbol = Symbol(5);
+
+    // Symbols are numbered from 0 in the order they are interned
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TypeId(u32);
+
+impl TypeId {
+    pub const OBJECT: TypeId = TypeId(0);
+    pub const NONE: TypeId = TypeId(1);
+    pub const EMPTY: TypeId = TypeId(2);
+    pub const STR: TypeId = TypeId(3);
+    pub const INT: TypeId = TypeId(4);
+    pub const BOOL: TypeId = TypeId(5);
+    pub const NONE_LIST: TypeId = TypeId(6);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TypeData {
+    Class(Symbol),
+    List(TypeId),
+    // Extension: function values, by the index of their signature in the interner
+    Func(u32),
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Signature {
+    pub parameters: Vec<TypeId>,
+    pub return_type: TypeId,
+}
+
+pub struct TypeInterner {
+    names: Vec<String>,
+    symbols: HashMap<String, Symbol>,
+    types: Vec<TypeData>,
+    ids: HashMap<TypeData, TypeId>,
+    signatures: Vec<Signature>,
+    signature_ids: HashMap<Signature, u32>,
+    // The AST form of each type, cloned into `inferred_type`
+    value_types: Vec<ValueType>,
+}
+
+impl TypeInterner {
+    pub fn new() -> TypeInterner {
+        let mut types = TypeInterner {
+            names: vec![],
+            symbols: HashMap::new(),
+            types: vec![],
+            ids: HashMap::new(),
+            signatures: vec![],
+            signature_ids: HashMap::new(),
+            value_types: vec![],
+        };
+
+        // Predefined in the order of their constants
+        for name in &["object", "<None>", "<Empty>", "str", "int", "bool"] {
+            let symbol = types.symbol(name);
+            types.class(symbol);
+        }
+        types.list(TypeId::NONE);
+        types
+    }
+
+    pub fn symbol(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_owned());
+        self.symbols.insert(name.to_owned(), symbol);
+        symbol
+    }
+
+    // Unlike `symbol`, doesn't intern names that haven't been seen
+    pub fn find_symbol(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.index()]
+    }
+
+    fn intern(&mut self, data: TypeData) -> TypeId {
+        if let Some(&id) = self.ids.get(&data) {
+            return id;
+        }
+        let value_type = match data {
+            TypeData::Class(name) => ValueType::ClassValueType(ClassValueType {
+                class_name: self.name(name).to_owned(),
+            }),
+            TypeData::List(element) => ValueType::ListValueType(ListValueType {
+                element_type: Box::new(self.value_type(element).clone()),
+            }),
+            TypeData::Func(signature) => {
+                let FuncType {
+                    parameters,
+                    return_type,
+                } = self.func_type(self.signature(signature));
+                ValueType::FuncValueType(Box::new(FuncValueType {
+                    parameters,
+                    return_type: Box::new(return_type),
+                }))
+            }
+        };
+        let id = TypeId(self.types.len() as u32);
+        self.types.push(data);
+        self.ids.insert(data, id);
+        self.value_types.push(value_type);
+        id
+    }
+
+    pub fn class(&mut self, name: Symbol) -> TypeId {
+        self.intern(TypeData::Class(name))
+    }
+
+    // Unlike `class`, doesn't intern types that haven't been seen
+    pub fn find_class(&self, name: Symbol) -> Option<TypeId> {
+        self.ids.get(&TypeData::Class(name)).copied()
+    }
+
+    pub fn list(&mut self, element: TypeId) -> TypeId {
+        self.intern(TypeData::List(element))
+    }
+
+    pub fn func(&mut self, signature: Signature) -> TypeId {
+        let index = if let Some(&index) = self.signature_ids.get(&signature) {
+            index
+        } else {
+            let index = self.signatures.len() as u32;
+            self.signatures.push(signature.clone());
+            self.signature_ids.insert(signature, index);
+            index
+        };
+        self.intern(TypeData::Func(index))
+    }
+
+    // The signature of a function value type
+    pub fn signature(&self, index: u32) -> &Signature {
+        &self.signatures[index as usize]
+    }
+
+    pub fn get(&self, t: TypeId) -> TypeData {
+        self.types[t.0 as usize]
+    }
+
+    pub fn annotation_type(&mut self, t: &TypeAnnotation) -> TypeId {
+        match t {
+            TypeAnnotation::ClassType(c) => {
+                let name = self.symbol(&c.class_name);
+                self.class(name)
+            }
+            TypeAnnotation::ListType(c) => {
+                let element = self.annotation_type(&c.element_type);
+                self.list(element)
+            }
+            TypeAnnotation::CallableType(c) => {
+                let signature = Signature {
+                    parameters: c
+                        .parameters
+                        .iter()
+                        .map(|t| self.annotation_type(t))
+                        .collect(),
+                    return_type: self.annotation_type(&c.return_type),
+                };
+                self.func(signature)
+            }
+        }
+    }
+
+    pub fn value_type(&self, t: TypeId) -> &ValueType {
+        &self.value_types[t.0 as usize]
+    }
+
+    pub fn func_type(&self, signature: &Signature) -> FuncType {
+        FuncType {
+            parameters: signature
+                .parameters
+                .iter()
+                .map(|&t| self.value_type(t).clone())
+                .collect(),
+            return_type: self.value_type(signature.return_type).clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn predefined() {
+        let mut types = TypeInterner::new();
+        assert_eq!(*types.value_type(TypeId::OBJECT), *TYPE_OBJECT);
+        assert_eq!(*types.value_type(TypeId::NONE), *TYPE_NONE);
+        assert_eq!(*types.value_type(TypeId::EMPTY), *TYPE_EMPTY);
+        assert_eq!(*types.value_type(TypeId::STR), *TYPE_STR);
+        assert_eq!(*types.value_type(TypeId::INT), *TYPE_INT);
+        assert_eq!(*types.value_type(TypeId::BOOL), *TYPE_BOOL);
+        assert_eq!(types.value_type(TypeId::NONE_LIST).to_string(), "[<None>]");
+        assert_eq!(types.class(Symbol::BOOL), TypeId::BOOL);
+        assert_eq!(types.list(TypeId::NONE), TypeId::NONE_LIST);
+    }
+
+    #[test]
+    fn structural() {
+        let mut types = TypeInterner::new();
+        let a = types.symbol("A");
+        let class = types.class(a);
+        let nested = types.list(class);
+        let nested = types.list(nested);
+        assert_eq!(types.symbol("A"), a);
+        let inner = types.list(class);
+        assert_eq!(types.list(inner), nested);
+        assert_ne!(inner, nested);
+        assert_eq!(types.value_type(nested).to_string(), "[[A]]");
+
+        let signature = Signature {
+            parameters: vec![nested, TypeId::INT],
+            return_type: TypeId::NONE,
+        };
+        let func = types.func(signature.clone());
+        assert_eq!(types.func(signature), func);
+        assert_eq!(
+            types.value_type(func).to_string(),
+            "Callable[[[[A]], int], <None>]"
+        );
+    }
+}
@@ -0,0 +1,392 @@
+// Validation for typed ASTs that are not produced by the checker, such as ones
+// loaded from JSON. Code generation assumes these invariants and would panic otherwise.
+
+use super::error::*;
+use crate::node::*;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Global,
+    Class,
+    Function,
+}
+
+// What a name refers to. Class names are functions that construct objects
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Binding {
+    Var,
+    Func,
+}
+
+const BUILTIN_NAMES: [&str; 11] = [
+    "print", "input", "len", "obj_id", "collect", "seed", "randint", "int", "bool", "str", "object",
+];
+
+// Names visible at a point of the program, innermost scope last, and the members of each class
+// by whether they are methods. Code generation looks them up without checking
+struct Env {
+    scopes: Vec<HashMap<String, Binding>>,
+    classes: HashMap<String, HashMap<String, bool>>,
+}
+
+impl Env {
+    fn get(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // Whether the class of the value has an attribute, or a method, of the name
+    fn has_member(&self, object: &Expr, name: &str, method: bool) -> Result<bool, String> {
+        match &object.inferred_type {
+            Some(ValueType::ClassValueType(c)) => Ok(self
+                .classes
+                .get(&c.class_name)
+                .and_then(|members| members.get(name))
+                == Some(&method)),
+            Some(t) => Err(error_member(t)),
+            None => Ok(true), // Already reported
+        }
+    }
+}
+
+fn validate_expr(expr: &mut Expr, env: &Env, errors: &mut Vec<CompilerError>) {
+    if expr.inferred_type.is_none() {
+        expr.add_error(errors, error_not_inferred());
+    }
+
+    match &mut expr.content {
+        ExprContent::BinaryExpr(e) => {
+            validate_expr(&mut e.left, env, errors);
+            validate_expr(&mut e.right, env, errors);
+        }
+        ExprContent::CallExpr(e) => {
+            validate_exprs(&mut e.args, env, errors);
+            let is_defined = |name: &str| env.is_defined(name);
+            if !env.is_defined(&e.function.name)
+                && !e.is_append(is_defined)
+                && !e.is_count(is_defined)
+            {
+                let msg = error_function(&e.function.name);
+                e.function.add_error(errors, msg);
+            }
+            // Constructor calls have no function type, which is only needed for arguments
+            match &e.function.inferred_type {
+                None if e.args.is_empty() => (),
+                None => e.function.add_error(errors, error_not_inferred()),
+                Some(t) if t.parameters.len() != e.args.len() => {
+                    let msg = error_call_arity(t.parameters.len(), e.args.len());
+                    e.function.add_error(errors, msg);
+                }
+                _ => (),
+            }
+        }
+        ExprContent::IfExpr(e) => {
+            validate_expr(&mut e.condition, env, errors);
+            validate_expr(&mut e.then_expr, env, errors);
+            validate_expr(&mut e.else_expr, env, errors);
+        }
+        ExprContent::IndexExpr(e) => {
+            validate_expr(&mut e.list, env, errors);
+            validate_expr(&mut e.index, env, errors);
+        }
+        ExprContent::ListExpr(e) => validate_exprs(&mut e.elements, env, errors),
+        ExprContent::MemberExpr(e) => validate_member(e, env, errors),
+        ExprContent::MethodCallExpr(e) => {
+            validate_expr(&mut e.method.object, env, errors);
+            validate_exprs(&mut e.args, env, errors);
+            let method = &e.method;
+            match env.has_member(&method.object, &method.member.name, true) {
+                Ok(true) => (),
+                Ok(false) => {
+                    let class_name = method.object.get_type().to_string();
+                    let msg = error_method(&method.member.name, &class_name);
+                    e.method.member.add_error(errors, msg);
+                }
+                Err(msg) => e.method.member.add_error(errors, msg),
+            }
+            // The object is passed as the first parameter
+            match &e.method.inferred_type {
+                None => e.method.add_error(errors, error_not_inferred()),
+                Some(t) if t.parameters.len() != e.args.len() + 1 => {
+                    let msg = error_call_arity(t.parameters.len(), e.args.len() + 1);
+                    e.method.add_error(errors, msg);
+                }
+                _ => (),
+            }
+        }
+        ExprContent::UnaryExpr(e) => validate_expr(&mut e.operand, env, errors),
+        ExprContent::Variable(v) => {
+            if !env.is_defined(&v.name) {
+                let msg = error_variable(&v.name);
+                v.add_error(errors, msg);
+            }
+        }
+        ExprContent::IntegerLiteral(_)
+        | ExprContent::BooleanLiteral(_)
+        | ExprContent::NoneLiteral(_)
+        | ExprContent::StringLiteral(_) => (),
+    }
+}
+
+fn validate_member(e: &mut MemberExpr, env: &Env, errors: &mut Vec<CompilerError>) {
+    validate_expr(&mut e.object, env, errors);
+    match env.has_member(&e.object, &e.member.name, false) {
+        Ok(true) => (),
+        Ok(false) => {
+            let class_name = e.object.get_type().to_string();
+            let msg = error_attribute(&e.member.name, &class_name);
+            e.member.add_error(errors, msg);
+        }
+        Err(msg) => e.member.add_error(errors, msg),
+    }
+}
+
+fn validate_exprs(exprs: &mut [Expr], env: &Env, errors: &mut Vec<CompilerError>) {
+    for expr in exprs {
+        validate_expr(expr, env, errors);
+    }
+}
+
+// Variables and attributes are the only names that can be assigned to
+fn validate_target(target: &mut Expr, env: &Env, errors: &mut Vec<CompilerError>) {
+    match &mut target.content {
+        ExprContent::Variable(v) if env.get(&v.name) != Some(Binding::Var) => {
+            let msg = error_variable(&v.name);
+            v.add_error(errors, msg);
+        }
+        ExprContent::MemberExpr(e) => validate_member(e, env, errors),
+        _ => validate_expr(target, env, errors),
+    }
+}
+
+fn validate_stmts(statements: &mut [Stmt], env: &Env, errors: &mut Vec<CompilerError>) {
+    for statement in statements {
+        match statement {
+            Stmt::ExprStmt(s) => validate_expr(&mut s.expr, env, errors),
+            Stmt::AssignStmt(s) => {
+                for target in &mut s.targets {
+                    validate_target(target, env, errors);
+                }
+                validate_expr(&mut s.value, env, errors);
+            }
+            Stmt::ForStmt(s) => {
+                if s.identifier.inferred_type.is_none() {
+                    s.identifier.add_error(errors, error_not_inferred());
+                }
+                if env.get(&s.identifier.name) != Some(Binding::Var) {
+                    let msg = error_variable(&s.identifier.name);
+                    s.identifier.add_error(errors, msg);
+                }
+                if let Some(index) = &mut s.index {
+                    if index.inferred_type.is_none() {
+                        index.add_error(errors, error_not_inferred());
+                    }
+                    if env.get(&index.name) != Some(Binding::Var) {
+                        let msg = error_variable(&index.name);
+                        index.add_error(errors, msg);
+                    }
+                    if s.enumerate_arg().is_none() {
+                        let msg = error_enumerate();
+                        s.add_error(errors, msg);
+                    }
+                }
+                // `range(...)` and `enumerate(...)` are lowered without calling anything
+                let is_defined = |name: &str| env.is_defined(name);
+                let special = s.range_args(is_defined).is_some() || s.index.is_some();
+                match &mut s.iterable.content {
+                    ExprContent::CallExpr(call) if special => {
+                        validate_exprs(&mut call.args, env, errors)
+                    }
+                    _ => validate_expr(&mut s.iterable, env, errors),
+                }
+                validate_stmts(&mut s.body, env, errors);
+            }
+            Stmt::IfStmt(s) => {
+                validate_expr(&mut s.condition, env, errors);
+                validate_stmts(&mut s.then_body, env, errors);
+                validate_stmts(&mut s.else_body, env, errors);
+            }
+            Stmt::ReturnStmt(s) => {
+                if let Some(value) = &mut s.value {
+                    validate_expr(value, env, errors);
+                }
+            }
+            Stmt::WhileStmt(s) => {
+                validate_expr(&mut s.condition, env, errors);
+                validate_stmts(&mut s.body, env, errors);
+            }
+        }
+    }
+}
+
+// Names defined by a function, including the ones it declares global or nonlocal
+fn function_scope(
+    f: &mut FuncDef,
+    env: &Env,
+    errors: &mut Vec<CompilerError>,
+) -> HashMap<String, Binding> {
+    let mut scope: HashMap<String, Binding> = f
+        .params
+        .iter()
+        .map(|param| (param.identifier.name.clone(), Binding::Var))
+        .collect();
+    for declaration in &mut f.declarations {
+        match declaration {
+            Declaration::VarDef(v) => {
+                scope.insert(v.var.identifier.name.clone(), Binding::Var);
+            }
+            Declaration::FuncDef(f) => {
+                scope.insert(f.name.name.clone(), Binding::Func);
+            }
+            Declaration::GlobalDecl(d) => {
+                let name = &d.variable.name;
+                if env.scopes[0].get(name) == Some(&Binding::Var) {
+                    scope.insert(name.clone(), Binding::Var);
+                } else {
+                    let msg = error_global(name);
+                    d.variable.add_error(errors, msg);
+                }
+            }
+            Declaration::NonLocalDecl(d) => {
+                let name = &d.variable.name;
+                let enclosing = env.scopes[1..]
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.get(name));
+                if enclosing == Some(&Binding::Var) {
+                    scope.insert(name.clone(), Binding::Var);
+                } else {
+                    let msg = error_nonlocal(name);
+                    d.variable.add_error(errors, msg);
+                }
+            }
+            Declaration::ClassDef(_) => (),
+        }
+    }
+    scope
+}
+
+fn validate_decls(
+    declarations: &mut [Declaration],
+    scope: Scope,
+    env: &mut Env,
+    errors: &mut Vec<CompilerError>,
+) {
+    for declaration in declarations {
+        let allowed = match declaration {
+            Declaration::VarDef(_) | Declaration::FuncDef(_) => true,
+            Declaration::ClassDef(_) => scope == Scope::Global,
+            Declaration::GlobalDecl(_) | Declaration::NonLocalDecl(_) => scope == Scope::Function,
+        };
+        if !allowed {
+            declaration.add_error(errors, error_declaration_position());
+            continue;
+        }
+
+        match declaration {
+            Declaration::VarDef(v) if v.value.inferred_type.is_none() => {
+                v.value.add_error(errors, error_not_inferred());
+            }
+            Declaration::FuncDef(f) => {
+                if scope == Scope::Class && f.params.is_empty() {
+                    let msg = error_method_no_self(&f.name.name);
+                    f.name.add_error(errors, msg);
+                }
+                let function_scope = function_scope(f, env, errors);
+                env.scopes.push(function_scope);
+                validate_decls(&mut f.declarations, Scope::Function, env, errors);
+                validate_stmts(&mut f.statements, env, errors);
+                env.scopes.pop();
+            }
+            Declaration::ClassDef(c) => {
+                validate_decls(&mut c.declarations, Scope::Class, env, errors)
+            }
+            _ => (),
+        }
+    }
+}
+
+// Code generation keeps attributes and methods in separate tables, so a name must stay
+// the same kind of member in subclasses. Returns whether each member of a class, including
+// inherited ones, is a method
+fn validate_members(
+    declarations: &mut [Declaration],
+    errors: &mut Vec<CompilerError>,
+) -> HashMap<String, HashMap<String, bool>> {
+    // Special types only have `__init__` from `object`, which can be called on None as well
+    let mut classes: HashMap<String, HashMap<String, bool>> = HashMap::new();
+    let object_members: HashMap<_, _> = std::iter::once(("__init__".to_owned(), true)).collect();
+    for name in &["object", "int", "bool", "str", "<None>", "<Empty>"] {
+        classes.insert(name.to_string(), object_members.clone());
+    }
+    for declaration in declarations {
+        if let Declaration::ClassDef(c) = declaration {
+            let super_name = &c.super_class.name;
+            let mut members = match classes.get(super_name) {
+                Some(_)
+                    if matches!(
+                        super_name.as_str(),
+                        "int" | "bool" | "str" | "<None>" | "<Empty>"
+                    ) =>
+                {
+                    let msg = error_super_special(super_name);
+                    c.super_class.add_error(errors, msg);
+                    HashMap::new()
+                }
+                Some(members) => members.clone(),
+                None => {
+                    let msg = error_super_undef(super_name);
+                    c.super_class.add_error(errors, msg);
+                    HashMap::new()
+                }
+            };
+            for item in &mut c.declarations {
+                let method = match item {
+                    Declaration::FuncDef(_) => true,
+                    Declaration::VarDef(_) => false,
+                    _ => continue,
+                };
+                let name = item.name_mut();
+                if members.insert(name.name.clone(), method) == Some(!method) {
+                    let msg = error_member_kind(&name.name);
+                    name.add_error(errors, msg);
+                }
+            }
+            classes.insert(c.name.name.clone(), members);
+        }
+    }
+    classes
+}
+
+pub fn validate(mut ast: Program) -> Program {
+    let mut errors = vec![];
+    let classes = validate_members(&mut ast.declarations, &mut errors);
+    let mut globals: HashMap<String, Binding> = BUILTIN_NAMES
+        .iter()
+        .map(|&name| (name.to_owned(), Binding::Func))
+        .collect();
+    for declaration in &ast.declarations {
+        let binding = match declaration {
+            Declaration::VarDef(_) => Binding::Var,
+            Declaration::FuncDef(_) | Declaration::ClassDef(_) => Binding::Func,
+            _ => continue,
+        };
+        globals.insert(declaration.name().name.clone(), binding);
+    }
+    let mut env = Env {
+        scopes: vec![globals],
+        classes,
+    };
+    validate_decls(&mut ast.declarations, Scope::Global, &mut env, &mut errors);
+    validate_stmts(&mut ast.statements, &env, &mut errors);
+    ast.errors.errors.append(&mut errors);
+    ast.errors.sort();
+    ast
+}
@@ -0,0 +1,279 @@
+// Completion candidates at a cursor position for editors.
+// After `expr.` the candidates are the members of the expression's class,
+// otherwise they are the names visible in the innermost scope containing the cursor.
+
+use crate::check;
+use crate::location::*;
+use crate::node::*;
+use crate::parse;
+use serde_derive::Serialize;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub name: String,
+    pub kind: &'static str,
+    #[serde(rename = "type")]
+    pub type_: String,
+}
+
+// Stands in for the member name while it hasn't been typed yet, so that `expr.` parses
+const PLACEHOLDER: &str = "__complete__";
+
+fn is_identifier_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+// Byte offset of a 1-based position, if it is within the source
+fn offset(source: &str, position: Position) -> Option<usize> {
+    let mut line_start = 0;
+    for _ in 1..position.row {
+        line_start += source[line_start..].find('\n')? + 1;
+    }
+    let line_end = source[line_start..]
+        .find('\n')
+        .map_or(source.len(), |end| line_start + end);
+    let offset = line_start + position.col as usize - 1;
+    if position.col == 0 || offset > line_end {
+        return None;
+    }
+    Some(offset)
+}
+
+// Innermost functions containing the position, from the outermost
+fn scope_at(declarations: &[Declaration], position: Position) -> Vec<&FuncDef> {
+    for declaration in declarations {
+        match declaration {
+            Declaration::FuncDef(f) if f.base.location.contains(position) => {
+                let mut scope = vec![f];
+                scope.extend(scope_at(&f.declarations, position));
+                return scope;
+            }
+            Declaration::ClassDef(c) if c.base.location.contains(position) => {
+                return scope_at(&c.declarations, position);
+            }
+            _ => (),
+        }
+    }
+    vec![]
+}
+
+// The object of the member access whose member name starts at the position
+fn member_object_in_expr(expr: &Expr, position: Position) -> Option<&Expr> {
+    if !expr.base().location.contains(position) {
+        return None;
+    }
+    match &expr.content {
+        ExprContent::MemberExpr(e) => {
+            if e.member.base.location.start == position {
+                Some(&e.object)
+            } else {
+                member_object_in_expr(&e.object, position)
+            }
+        }
+        ExprContent::MethodCallExpr(e) => {
+            if e.method.member.base.location.start == position {
+                Some(&e.method.object)
+            } else {
+                member_object_in_expr(&e.method.object, position)
+                    .or_else(|| member_object_in_exprs(&e.args, position))
+            }
+        }
+        ExprContent::BinaryExpr(e) => member_object_in_expr(&e.left, position)
+            .or_else(|| member_object_in_expr(&e.right, position)),
+        ExprContent::CallExpr(e) => member_object_in_exprs(&e.args, position),
+        ExprContent::IfExpr(e) => member_object_in_expr(&e.condition, position)
+            .or_else(|| member_object_in_expr(&e.then_expr, position))
+            .or_else(|| member_object_in_expr(&e.else_expr, position)),
+        ExprContent::IndexExpr(e) => member_object_in_expr(&e.list, position)
+            .or_else(|| member_object_in_expr(&e.index, position)),
+        ExprContent::ListExpr(e) => member_object_in_exprs(&e.elements, position),
+        ExprContent::UnaryExpr(e) => member_object_in_expr(&e.operand, position),
+        ExprContent::IntegerLiteral(_)
+        | ExprContent::BooleanLiteral(_)
+        | ExprContent::Variable(_)
+        | ExprContent::NoneLiteral(_)
+        | ExprContent::StringLiteral(_) => None,
+    }
+}
+
+fn member_object_in_exprs(exprs: &[Expr], position: Position) -> Option<&Expr> {
+    exprs
+        .iter()
+        .find_map(|expr| member_object_in_expr(expr, position))
+}
+
+fn member_object_in_stmts(statements: &[Stmt], position: Position) -> Option<&Expr> {
+    statements.iter().find_map(|statement| match statement {
+        Stmt::ExprStmt(s) => member_object_in_expr(&s.expr, position),
+        Stmt::AssignStmt(s) => member_object_in_exprs(&s.targets, position)
+            .or_else(|| member_object_in_expr(&s.value, position)),
+        Stmt::ForStmt(s) => member_object_in_expr(&s.iterable, position)
+            .or_else(|| member_object_in_stmts(&s.body, position)),
+        Stmt::IfStmt(s) => member_object_in_expr(&s.condition, position)
+            .or_else(|| member_object_in_stmts(&s.then_body, position))
+            .or_else(|| member_object_in_stmts(&s.else_body, position)),
+        Stmt::ReturnStmt(s) => s
+            .value
+            .as_ref()
+            .and_then(|value| member_object_in_expr(value, position)),
+        Stmt::WhileStmt(s) => member_object_in_expr(&s.condition, position)
+            .or_else(|| member_object_in_stmts(&s.body, position)),
+    })
+}
+
+fn member_candidates(object: &Expr, classes: &check::ClassEnv) -> Vec<Candidate> {
+    let class_name = match &object.inferred_type {
+        Some(ValueType::ClassValueType(ClassValueType { class_name })) => class_name,
+        _ => return vec![],
+    };
+    let attributes = classes.attributes(class_name).map(|(name, t)| Candidate {
+        name: name.to_owned(),
+        kind: "attribute",
+        type_: t.to_string(),
+    });
+    let methods = classes.methods(class_name).map(|(name, t)| {
+        // The method is called on the object, so `self` is already bound
+        let bound = FuncType {
+            parameters: t.parameters[1..].to_vec(),
+            return_type: t.return_type.clone(),
+        };
+        Candidate {
+            name: name.to_owned(),
+            kind: "method",
+            type_: bound.to_string(),
+        }
+    });
+    attributes.chain(methods).collect()
+}
+
+fn scope_candidates(
+    env: &crate::local_env::LocalEnv<check::Signature, check::TypeId>,
+    classes: &check::ClassEnv,
+) -> Vec<Candidate> {
+    env.names()
+        .filter_map(|name| {
+            let candidate = match env.get(name)? {
+                crate::local_env::EnvSlot::Var(&t, _) => Candidate {
+                    name: name.clone(),
+                    kind: "variable",
+                    type_: classes.types.value_type(t).to_string(),
+                },
+                // Class names are only ever bound to constructors
+                crate::local_env::EnvSlot::Func(t) if classes.contains(name) => Candidate {
+                    name: name.clone(),
+                    kind: "class",
+                    type_: classes.types.value_type(t.return_type).to_string(),
+                },
+                crate::local_env::EnvSlot::Func(t) => Candidate {
+                    name: name.clone(),
+                    kind: "function",
+                    type_: classes.types.func_type(t).to_string(),
+                },
+            };
+            Some(candidate)
+        })
+        .collect()
+}
+
+pub fn complete(
+    source: &str,
+    position: Position,
+    parse_options: parse::Options,
+    check_options: check::Options,
+) -> Vec<Candidate> {
+    let offset = if let Some(offset) = offset(source, position) {
+        offset
+    } else {
+        return vec![];
+    };
+
+    // The cursor may be in the middle of a name. Completion is for the whole name.
+    let bytes = source.as_bytes();
+    let word_start = bytes[..offset]
+        .iter()
+        .rposition(|&c| !is_identifier_char(c))
+        .map_or(0, |i| i + 1);
+    let start = Position {
+        row: position.row,
+        col: position.col - (offset - word_start) as u32,
+    };
+    let member = word_start > 0 && bytes[word_start - 1] == b'.';
+
+    let typed = bytes
+        .get(word_start)
+        .copied()
+        .is_some_and(is_identifier_char);
+
+    let mut source = source.to_owned();
+    if member && !typed {
+        source.insert_str(word_start, PLACEHOLDER);
+    }
+
+    // Errors are expected while editing. Whatever was inferred is still useful.
+    let ast = check::check(parse::process_str(&source, parse_options), check_options);
+
+    let scope = scope_at(&ast.declarations, start);
+    let mut candidates = check::environment(&ast, &scope, check_options, |env, classes| {
+        if member {
+            let statements = scope.last().map_or(&ast.statements, |f| &f.statements);
+            member_object_in_stmts(statements, start)
+                .map_or_else(Vec::new, |object| member_candidates(object, classes))
+        } else {
+            scope_candidates(env, classes)
+        }
+    });
+
+    // A name declared in several frames resolves to the same slot, so list it once
+    candidates.sort_by(|a, b| a.name.cmp(&b.name));
+    candidates.dedup_by(|a, b| a.name == b.name);
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_options() -> parse::Options {
+        parse::Options {
+            keep_comments: false,
+            class_const: true,
+            bitwise: true,
+        }
+    }
+
+    #[test]
+    fn fixture_cursors() {
+        let source = std::fs::read_to_string("test/complete/shop.py").unwrap();
+        for (row, col) in [(23, 1), (18, 9), (12, 14), (26, 12)].iter() {
+            let position = Position {
+                row: *row,
+                col: *col,
+            };
+            let candidates = complete(&source, position, parse_options(), Default::default());
+            let json = serde_json::to_string_pretty(&candidates).unwrap() + "\n";
+            let path = format!("test/complete/shop.py.{}_{}.json", row, col);
+            let expected = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(json, expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn incomplete_member() {
+        let source = "class A(object):\n    x:int = 1\n    def f(self:\"A\") -> int:\n        return self.x\na:A = None\na = A()\na.";
+        let candidates = complete(
+            source,
+            Position { row: 7, col: 3 },
+            parse_options(),
+            Default::default(),
+        );
+        let names: Vec<_> = candidates
+            .iter()
+            .map(|c| (c.name.as_str(), c.kind))
+            .collect();
+        assert_eq!(
+            names,
+            [("__init__", "method"), ("f", "method"), ("x", "attribute")]
+        );
+        assert_eq!(candidates[1].type_, "() -> int");
+    }
+}
@@ -0,0 +1,159 @@
+// Comparison of typed ASTs with the JSON output of the reference compiler from the course.
+//
+// A mismatch is reported as the first JSON path where the values differ, with both values there,
+// which is also how the snapshot tests report theirs.
+
+use crate::node::*;
+use serde_json::Value;
+
+fn describe(value: &Value) -> String {
+    let text = value.to_string();
+    if text.chars().count() > 60 {
+        text.chars().take(60).collect::<String>() + "..."
+    } else {
+        text
+    }
+}
+
+// The first place where the values differ, as a path of keys and indices with both values there
+pub fn first_difference(expected: &Value, actual: &Value, at: &str) -> Option<String> {
+    match (expected, actual) {
+        (Value::Object(expected_fields), Value::Object(actual_fields)) => {
+            let extra_keys = actual_fields
+                .keys()
+                .filter(|k| !expected_fields.contains_key(*k));
+            let keys = expected_fields.keys().chain(extra_keys);
+            for key in keys {
+                let at = if at.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", at, key)
+                };
+                match (expected_fields.get(key), actual_fields.get(key)) {
+                    (Some(e), Some(a)) => {
+                        if let Some(difference) = first_difference(e, a, &at) {
+                            return Some(difference);
+                        }
+                    }
+                    (Some(e), None) => return Some(format!("{}: {} is missing", at, describe(e))),
+                    (None, Some(a)) => return Some(format!("{}: {} is extra", at, describe(a))),
+                    (None, None) => unreachable!(),
+                }
+            }
+            None
+        }
+        (Value::Array(expected_elements), Value::Array(actual_elements)) => {
+            for (i, (e, a)) in expected_elements.iter().zip(actual_elements).enumerate() {
+                if let Some(difference) = first_difference(e, a, &format!("{}[{}]", at, i)) {
+                    return Some(difference);
+                }
+            }
+            if expected_elements.len() != actual_elements.len() {
+                return Some(format!(
+                    "{}: expected {} elements, got {}",
+                    at,
+                    expected_elements.len(),
+                    actual_elements.len()
+                ));
+            }
+            None
+        }
+        _ if expected == actual => None,
+        _ => Some(format!(
+            "{}: expected {}, got {}",
+            at,
+            describe(expected),
+            describe(actual)
+        )),
+    }
+}
+
+// Leave out what the reference compiler doesn't produce: warnings and error codes
+pub fn as_reference(ast: &mut Program) {
+    ast.errors.warnings.clear();
+    for error in &mut ast.errors.errors {
+        error.code = None;
+    }
+}
+
+// The first difference of the checked AST from the reference output, whose errors may be in
+// another order
+pub fn compare(mut reference: Program, mut ast: Program) -> Option<String> {
+    reference.errors.sort();
+    as_reference(&mut ast);
+    let expected = serde_json::to_value(&reference).unwrap();
+    let actual = serde_json::to_value(&ast).unwrap();
+    first_difference(&expected, &actual, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn difference_path() {
+        let expected = json!({"a": [1, {"b": "x"}], "c": true});
+        assert_eq!(first_difference(&expected, &expected.clone(), ""), None);
+
+        let actual = json!({"a": [1, {"b": "y"}], "c": false});
+        assert_eq!(
+            first_difference(&expected, &actual, "").unwrap(),
+            "a[1].b: expected \"x\", got \"y\""
+        );
+        let actual = json!({"a": [1], "c": true});
+        assert_eq!(
+            first_difference(&expected, &actual, "").unwrap(),
+            "a: expected 2 elements, got 1"
+        );
+        let actual = json!({"a": [1, {"b": "x", "d": null}], "c": true});
+        assert_eq!(
+            first_difference(&expected, &actual, "").unwrap(),
+            "a[1].d: null is extra"
+        );
+    }
+
+    fn check_source(path: &str) -> Program {
+        let source = std::fs::read_to_string(path).unwrap();
+        let ast = crate::parse::process_str(&source, Default::default());
+        crate::check::check(ast, Default::default())
+    }
+
+    #[test]
+    fn reference_output() {
+        let path = "test/original/pa2/bad_duplicate_global.py";
+        let reference = crate::parse::load_ast(&(path.to_owned() + ".ast.typed")).unwrap();
+        assert_eq!(compare(reference, check_source(path)), None);
+
+        // A reference output edited to word the error differently
+        let path = "test/conformance/divergent.py";
+        let reference = crate::parse::load_ast(&(path.to_owned() + ".ast.typed")).unwrap();
+        assert_eq!(
+            compare(reference, check_source(path)).unwrap(),
+            "declarations[1].var.identifier.errorMsg: expected \"Duplicate declaration of identifier: x\", \
+             got \"Duplicate declaration of identifier in same scope: x\""
+        );
+    }
+
+    #[test]
+    fn pass_leaves_no_statement() {
+        // The reference compiler drops `pass`, so blocks of only `pass` have no statements
+        let path = "test/original/pa2/class_def_methods.py";
+        let reference = crate::parse::load_ast(&(path.to_owned() + ".ast.typed")).unwrap();
+        let ast = check_source(path);
+        let init = match &ast.declarations[1] {
+            Declaration::ClassDef(class_def) => match &class_def.declarations[0] {
+                Declaration::FuncDef(func_def) => func_def,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        assert_eq!(init.name.name, "__init__");
+        assert!(init.statements.is_empty());
+        assert_eq!(compare(reference, ast), None);
+
+        let path = "test/original/pa2/stmt_if.py";
+        let reference = crate::parse::load_ast(&(path.to_owned() + ".ast.typed")).unwrap();
+        assert_eq!(compare(reference, check_source(path)), None);
+    }
+}
@@ -0,0 +1,124 @@
+// Documented API surface of a program for documentation generators.
+// Doc comments are the full-line comments attached to declarations with --keep-comments.
+
+use crate::node::*;
+use serde_derive::Serialize;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct VarDoc {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FuncDoc {
+    pub name: String,
+    pub params: Vec<VarDoc>,
+    #[serde(rename = "returnType")]
+    pub return_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ClassDoc {
+    pub name: String,
+    #[serde(rename = "superClass")]
+    pub super_class: String,
+    pub attributes: Vec<VarDoc>,
+    pub methods: Vec<FuncDoc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ProgramDoc {
+    pub classes: Vec<ClassDoc>,
+    pub functions: Vec<FuncDoc>,
+}
+
+// Joins comment lines, dropping the space conventionally written after '#'
+fn doc(base: &NodeBase) -> Option<String> {
+    if base.comments.is_empty() {
+        return None;
+    }
+    let lines: Vec<_> = base
+        .comments
+        .iter()
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect();
+    Some(lines.join("\n"))
+}
+
+fn var_doc(var: &TypedVar, base: &NodeBase) -> VarDoc {
+    VarDoc {
+        name: var.identifier.name.clone(),
+        type_: ValueType::from_annotation(&var.type_).to_string(),
+        doc: doc(base),
+    }
+}
+
+fn func_doc(f: &FuncDef) -> FuncDoc {
+    FuncDoc {
+        name: f.name.name.clone(),
+        params: f.params.iter().map(|p| var_doc(p, &p.base)).collect(),
+        return_type: ValueType::from_annotation(&f.return_type).to_string(),
+        doc: doc(&f.base),
+    }
+}
+
+pub fn program_doc(ast: &Program) -> ProgramDoc {
+    let mut program = ProgramDoc {
+        classes: vec![],
+        functions: vec![],
+    };
+    for declaration in &ast.declarations {
+        match declaration {
+            Declaration::ClassDef(c) => {
+                let mut class = ClassDoc {
+                    name: c.name.name.clone(),
+                    super_class: c.super_class.name.clone(),
+                    attributes: vec![],
+                    methods: vec![],
+                    doc: doc(&c.base),
+                };
+                for declaration in &c.declarations {
+                    match declaration {
+                        Declaration::VarDef(v) => class.attributes.push(var_doc(&v.var, &v.base)),
+                        Declaration::FuncDef(f) => class.methods.push(func_doc(f)),
+                        _ => (),
+                    }
+                }
+                program.classes.push(class);
+            }
+            Declaration::FuncDef(f) => program.functions.push(func_doc(f)),
+            _ => (),
+        }
+    }
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documented_fixture() {
+        let source = std::fs::read_to_string("test/doc/shapes.py").unwrap();
+        let options = crate::parse::Options {
+            keep_comments: true,
+            ..Default::default()
+        };
+        let ast = crate::check::check(
+            crate::parse::process_str(&source, options),
+            Default::default(),
+        );
+        assert!(ast.errors.errors.is_empty());
+        let json = serde_json::to_string_pretty(&program_doc(&ast)).unwrap() + "\n";
+        let expected = std::fs::read_to_string("test/doc/shapes.py.doc.json").unwrap();
+        assert_eq!(json, expected);
+    }
+}
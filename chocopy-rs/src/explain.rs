@@ -0,0 +1,146 @@
+// Stable codes for diagnostics, shown along with their messages,
+// and the long explanations printed by `--explain CODE`
+
+pub const SYNTAX: &str = "E-SYNTAX";
+pub const TYPE_ASSIGN: &str = "E-TYPE-ASSIGN";
+pub const CALL_COUNT: &str = "E-CALL-COUNT";
+pub const UNDEFINED_VARIABLE: &str = "E-UNDEFINED-VAR";
+pub const INVALID_TYPE: &str = "E-INVALID-TYPE";
+
+const EXPLANATIONS: [(&str, &str); 5] = [
+    (
+        SYNTAX,
+        "\
+The source code doesn't follow the grammar of ChocoPy, so it couldn't be parsed.
+
+ChocoPy is a subset of Python 3. Some Python syntax is not part of the language, such as
+floating-point numbers, tuples, `lambda` and `import`. Indentation is significant like in
+Python, and integer literals must fit in 32 bits.
+
+Erroneous code example:
+
+    x:int = 1 +
+
+The expression after `+` is missing.
+",
+    ),
+    (
+        TYPE_ASSIGN,
+        "\
+A value is assigned to a variable, or returned from a function, whose declared type isn't
+compatible with the type of the value.
+
+A value can be used where its own type, one of its superclasses or `object` is expected.
+`None` can be used for any class type other than `int`, `bool` and `str`, and an empty list
+`[]` for any list type. Unlike Python, `int` and `bool` are not compatible with each other.
+
+Erroneous code example:
+
+    x:int = 0
+    x = \"1\"
+
+Convert the value or change the declared type of the variable, for example `x:str = \"\"`.
+",
+    ),
+    (
+        CALL_COUNT,
+        "\
+A function or method is called with a different number of arguments than it has parameters.
+
+ChocoPy has no default values or variable numbers of parameters, so every parameter needs an
+argument. For a method, `self` is the object before the dot and is not counted.
+
+Erroneous code example:
+
+    def add(a:int, b:int) -> int:
+        return a + b
+
+    print(add(1))
+
+Pass one argument for each parameter, as in `add(1, 2)`.
+",
+    ),
+    (
+        UNDEFINED_VARIABLE,
+        "\
+A name is used as a variable, but no variable with that name is declared in the scope.
+
+Unlike Python, every variable must be declared with a type before it is used, including the
+variable of a `for` loop. A variable of an enclosing function or the global scope can be read
+anywhere, but must be declared `nonlocal` or `global` to be assigned. Functions and classes are
+not variables.
+
+Erroneous code example:
+
+    for i in [1, 2, 3]:
+        print(i)
+
+Declare the variable first, as in `i:int = 0` before the loop.
+",
+    ),
+    (
+        INVALID_TYPE,
+        "\
+A type annotation names a class that doesn't exist.
+
+The types in annotations are `int`, `bool`, `str`, `object`, classes declared by the program
+and lists of these written as `[T]`. Classes can be used in annotations before they are
+declared.
+
+Erroneous code example:
+
+    x:float = 1
+
+ChocoPy has no floating-point type. Use `int` instead, or declare a class with the name.
+",
+    ),
+];
+
+// The long explanation of a code, which is case insensitive
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(source: &str) -> Vec<Option<String>> {
+        let mut ast = crate::parse::process_str(source, Default::default());
+        if ast.errors.errors.is_empty() {
+            ast = crate::check::check(ast, Default::default());
+        }
+        ast.errors.errors.into_iter().map(|e| e.code).collect()
+    }
+
+    #[test]
+    fn emitted_codes() {
+        let code = |code: &str| Some(code.to_owned());
+        let syntax = codes("x:int = 1 +\n");
+        assert!(!syntax.is_empty() && syntax.iter().all(|c| *c == code(SYNTAX)));
+        assert_eq!(codes("x:[float] = None\n"), [code(INVALID_TYPE)]);
+        assert_eq!(
+            codes("x:int = 0\nx = \"1\"\nfor y in [1]:\n    pass\n"),
+            [code(TYPE_ASSIGN), code(UNDEFINED_VARIABLE)]
+        );
+        assert_eq!(
+            codes("def f() -> int:\n    return True\nprint(f(1))\n"),
+            [code(TYPE_ASSIGN), code(CALL_COUNT)]
+        );
+        // Other errors don't have codes yet
+        assert_eq!(codes("x:int = 0\nx = -True\n"), [None]);
+    }
+
+    #[test]
+    fn explanations() {
+        for (code, _) in &EXPLANATIONS {
+            assert!(explain(code).unwrap().contains("Erroneous code example:"));
+        }
+        assert_eq!(explain("e-type-assign"), explain(TYPE_ASSIGN));
+        assert!(explain(CALL_COUNT).unwrap().contains("add(1, 2)"));
+        assert_eq!(explain("E-UNKNOWN"), None);
+    }
+}
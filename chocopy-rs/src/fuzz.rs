@@ -0,0 +1,509 @@
+// Differential testing of the compiler against the interpreter.
+//
+// Random programs that pass the type checker are run both by the interpreter and as compiled
+// executables, which must print the same output and exit with the same code. A program that tells
+// them apart is shrunk by removing statements for as long as the two still disagree, and reported.
+
+use chocopy_rs_common::random::Random;
+use std::fmt::{self, Display, Formatter};
+
+// Number of programs tried, which can be raised for a longer run
+const SEEDS_VAR: &str = "CHOCOPY_DIFF_SEEDS";
+const DEFAULT_SEEDS: i32 = 20;
+
+const INT_VARS: [&str; 3] = ["a", "b", "c"];
+const BOOL_VARS: [&str; 2] = ["p", "q"];
+const STR_VARS: [&str; 2] = ["s", "t"];
+const LIST_VARS: [&str; 2] = ["l", "m"];
+
+const MAX_DEPTH: u32 = 3;
+const MAX_NESTING: u32 = 2;
+const MAX_ITERATIONS: i32 = 3;
+
+#[derive(Clone, Copy)]
+enum Type {
+    Int,
+    Bool,
+    Str,
+    List, // [int]
+}
+
+#[derive(Clone)]
+enum Statement {
+    Simple(String),
+    If(String, Vec<Statement>, Vec<Statement>),
+    // A loop with a counter of its own, so that it ends whatever the body does
+    While(usize, i32, Vec<Statement>),
+    For(String, String, Vec<Statement>),
+}
+
+#[derive(Clone)]
+pub struct Program {
+    functions: String,
+    counters: usize,
+    statements: Vec<Statement>,
+}
+
+fn write_block(f: &mut Formatter<'_>, statements: &[Statement], indent: usize) -> fmt::Result {
+    if statements.is_empty() {
+        return writeln!(f, "{:indent$}pass", "", indent = indent);
+    }
+    for statement in statements {
+        statement.write(f, indent)?;
+    }
+    Ok(())
+}
+
+impl Statement {
+    fn write(&self, f: &mut Formatter<'_>, indent: usize) -> fmt::Result {
+        let pad = " ".repeat(indent);
+        match self {
+            Statement::Simple(line) => writeln!(f, "{}{}", pad, line),
+            Statement::If(condition, then_body, else_body) => {
+                writeln!(f, "{}if {}:", pad, condition)?;
+                write_block(f, then_body, indent + 4)?;
+                writeln!(f, "{}else:", pad)?;
+                write_block(f, else_body, indent + 4)
+            }
+            Statement::While(counter, times, body) => {
+                writeln!(f, "{}k{} = 0", pad, counter)?;
+                writeln!(f, "{}while k{} < {}:", pad, counter, times)?;
+                writeln!(f, "{}    k{} = k{} + 1", pad, counter, counter)?;
+                write_block(f, body, indent + 4)
+            }
+            Statement::For(variable, iterable, body) => {
+                writeln!(f, "{}for {} in {}:", pad, variable, iterable)?;
+                write_block(f, body, indent + 4)
+            }
+        }
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for name in &INT_VARS {
+            writeln!(f, "{}: int = 0", name)?;
+        }
+        for name in &BOOL_VARS {
+            writeln!(f, "{}: bool = False", name)?;
+        }
+        for name in &STR_VARS {
+            writeln!(f, "{}: str = \"{}\"", name, name)?;
+        }
+        for name in &LIST_VARS {
+            writeln!(f, "{}: [int] = None", name)?;
+        }
+        for counter in 0..self.counters {
+            writeln!(f, "k{}: int = 0", counter)?;
+        }
+        write!(f, "{}", self.functions)?;
+        writeln!(f, "l = [1, 2, 3]")?;
+        writeln!(f, "m = [4]")?;
+        write_block(f, &self.statements, 0)
+    }
+}
+
+// Names that expressions can use, with the functions that can be called
+struct Scope {
+    ints: Vec<&'static str>,
+    bools: Vec<&'static str>,
+    strs: Vec<&'static str>,
+    calls: bool,
+}
+
+impl Scope {
+    fn global() -> Scope {
+        Scope {
+            ints: INT_VARS.to_vec(),
+            bools: BOOL_VARS.to_vec(),
+            strs: STR_VARS.to_vec(),
+            calls: true,
+        }
+    }
+
+    // Inside the functions, which read globals but don't call other functions, so that
+    // every program ends
+    fn function(ints: &[&'static str], strs: &[&'static str]) -> Scope {
+        let mut scope = Scope::global();
+        scope.ints.extend(ints);
+        scope.strs.extend(strs);
+        scope.calls = false;
+        scope
+    }
+}
+
+struct Generator {
+    random: Random,
+    counters: usize,
+}
+
+impl Generator {
+    fn chance(&mut self, percent: i32) -> bool {
+        self.random.int(1, 100) <= percent
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.random.int(0, items.len() as i32 - 1) as usize]
+    }
+
+    fn expr(&mut self, scope: &Scope, ty: Type, depth: u32) -> String {
+        if depth >= MAX_DEPTH || self.chance(30) {
+            return self.leaf(scope, ty);
+        }
+        let depth = depth + 1;
+        match ty {
+            Type::Int => match self.random.int(0, 7) {
+                0 => {
+                    let operator = self.pick(&["+", "-", "*"]);
+                    let left = self.expr(scope, Type::Int, depth);
+                    let right = self.expr(scope, Type::Int, depth);
+                    format!("({} {} {})", left, operator, right)
+                }
+                1 => {
+                    let operator = self.pick(&["//", "%"]);
+                    let left = self.expr(scope, Type::Int, depth);
+                    let right = self.operand(scope, 1, 9, depth);
+                    format!("({} {} {})", left, operator, right)
+                }
+                2 => format!("(-{})", self.expr(scope, Type::Int, depth)),
+                3 => format!("len({})", self.expr(scope, Type::Str, depth)),
+                4 => format!("len({})", self.expr(scope, Type::List, depth)),
+                5 => {
+                    let list = self.expr(scope, Type::List, depth);
+                    let index = self.operand(scope, 0, 0, depth);
+                    format!("{}[{}]", list, index)
+                }
+                6 if scope.calls => {
+                    let x = self.expr(scope, Type::Int, depth);
+                    let y = self.expr(scope, Type::Int, depth);
+                    format!("f({}, {})", x, y)
+                }
+                _ => self.conditional(scope, Type::Int, depth),
+            },
+            Type::Bool => match self.random.int(0, 6) {
+                0 => {
+                    let operator = self.pick(&["<", "<=", ">", ">=", "==", "!="]);
+                    let left = self.expr(scope, Type::Int, depth);
+                    let right = self.expr(scope, Type::Int, depth);
+                    format!("({} {} {})", left, operator, right)
+                }
+                1 => {
+                    let operator = self.pick(&["==", "!="]);
+                    let left = self.expr(scope, Type::Str, depth);
+                    let right = self.expr(scope, Type::Str, depth);
+                    format!("({} {} {})", left, operator, right)
+                }
+                2 => format!("(not {})", self.expr(scope, Type::Bool, depth)),
+                3 => {
+                    let operator = self.pick(&["and", "or"]);
+                    let left = self.expr(scope, Type::Bool, depth);
+                    let right = self.expr(scope, Type::Bool, depth);
+                    format!("({} {} {})", left, operator, right)
+                }
+                4 if scope.calls => format!("g({})", self.expr(scope, Type::Int, depth)),
+                5 => {
+                    let left = self.expr(scope, Type::List, depth);
+                    let right = self.expr(scope, Type::List, depth);
+                    format!("({} is {})", left, right)
+                }
+                _ => self.conditional(scope, Type::Bool, depth),
+            },
+            Type::Str => match self.random.int(0, 3) {
+                0 => {
+                    let left = self.expr(scope, Type::Str, depth);
+                    let right = self.expr(scope, Type::Str, depth);
+                    format!("({} + {})", left, right)
+                }
+                1 => {
+                    let string = self.expr(scope, Type::Str, depth);
+                    let index = self.operand(scope, 0, 0, depth);
+                    format!("{}[{}]", string, index)
+                }
+                2 if scope.calls => format!("h({})", self.expr(scope, Type::Str, depth)),
+                _ => self.conditional(scope, Type::Str, depth),
+            },
+            Type::List => match self.random.int(0, 1) {
+                0 => {
+                    let left = self.expr(scope, Type::List, depth);
+                    let right = self.expr(scope, Type::List, depth);
+                    format!("({} + {})", left, right)
+                }
+                _ => {
+                    let len = self.random.int(1, 3);
+                    let elements: Vec<_> = (0..len)
+                        .map(|_| self.expr(scope, Type::Int, depth))
+                        .collect();
+                    format!("[{}]", elements.join(", "))
+                }
+            },
+        }
+    }
+
+    // An int that is mostly a literal in the range, for divisors and indices. Other values
+    // still come up to exercise the runtime errors.
+    fn operand(&mut self, scope: &Scope, lo: i32, hi: i32, depth: u32) -> String {
+        if self.chance(80) {
+            self.random.int(lo, hi).to_string()
+        } else {
+            self.expr(scope, Type::Int, depth)
+        }
+    }
+
+    fn conditional(&mut self, scope: &Scope, ty: Type, depth: u32) -> String {
+        let then_value = self.expr(scope, ty, depth);
+        let condition = self.expr(scope, Type::Bool, depth);
+        let else_value = self.expr(scope, ty, depth);
+        format!("({} if {} else {})", then_value, condition, else_value)
+    }
+
+    fn leaf(&mut self, scope: &Scope, ty: Type) -> String {
+        match ty {
+            Type::Int if self.chance(50) => self.pick(&scope.ints).to_owned(),
+            Type::Int => self.random.int(-9, 99).to_string(),
+            Type::Bool if self.chance(50) => self.pick(&scope.bools).to_owned(),
+            Type::Bool => self.pick(&["True", "False"]).to_owned(),
+            Type::Str if self.chance(50) => self.pick(&scope.strs).to_owned(),
+            Type::Str => self.pick(&["\"a\"", "\"bc\"", "\"xyz\""]).to_owned(),
+            Type::List => self.pick(&LIST_VARS).to_owned(),
+        }
+    }
+
+    fn block(&mut self, nesting: u32) -> Vec<Statement> {
+        let len = self.random.int(1, 3);
+        (0..len).map(|_| self.statement(nesting)).collect()
+    }
+
+    fn statement(&mut self, nesting: u32) -> Statement {
+        let scope = Scope::global();
+        let compound = nesting < MAX_NESTING && self.chance(30);
+        if compound {
+            let nesting = nesting + 1;
+            return match self.random.int(0, 3) {
+                0 => {
+                    let condition = self.expr(&scope, Type::Bool, 0);
+                    let then_body = self.block(nesting);
+                    let else_body = self.block(nesting);
+                    Statement::If(condition, then_body, else_body)
+                }
+                1 => {
+                    let counter = self.counters;
+                    self.counters += 1;
+                    let times = self.random.int(0, MAX_ITERATIONS);
+                    Statement::While(counter, times, self.block(nesting))
+                }
+                2 => {
+                    let variable = self.pick(&INT_VARS).to_owned();
+                    let iterable = self.expr(&scope, Type::List, 1);
+                    Statement::For(variable, iterable, self.block(nesting))
+                }
+                _ => {
+                    let variable = self.pick(&STR_VARS).to_owned();
+                    let iterable = self.expr(&scope, Type::Str, 1);
+                    Statement::For(variable, iterable, self.block(nesting))
+                }
+            };
+        }
+        let line = match self.random.int(0, 6) {
+            0 => format!("print({})", self.expr(&scope, Type::Int, 0)),
+            1 => format!("print({})", self.expr(&scope, Type::Bool, 0)),
+            2 => format!("print({})", self.expr(&scope, Type::Str, 0)),
+            3 => {
+                let variable = self.pick(&INT_VARS);
+                format!("{} = {}", variable, self.expr(&scope, Type::Int, 0))
+            }
+            4 => {
+                let variable = self.pick(&BOOL_VARS);
+                format!("{} = {}", variable, self.expr(&scope, Type::Bool, 0))
+            }
+            5 => {
+                let variable = self.pick(&STR_VARS);
+                format!("{} = {}", variable, self.expr(&scope, Type::Str, 0))
+            }
+            _ if self.chance(50) => {
+                let variable = self.pick(&LIST_VARS);
+                format!("{} = {}", variable, self.expr(&scope, Type::List, 1))
+            }
+            _ => {
+                let variable = self.pick(&LIST_VARS);
+                let index = self.operand(&scope, 0, 0, 1);
+                let value = self.expr(&scope, Type::Int, 1);
+                format!("{}[{}] = {}", variable, index, value)
+            }
+        };
+        Statement::Simple(line)
+    }
+
+    fn functions(&mut self) -> String {
+        let scope = Scope::function(&["x", "y"], &[]);
+        let condition = self.expr(&scope, Type::Bool, 0);
+        let then_value = self.expr(&scope, Type::Int, 0);
+        let else_value = self.expr(&scope, Type::Int, 0);
+        let f = format!(
+            "def f(x: int, y: int) -> int:\n    if {}:\n        return {}\n    return {}\n",
+            condition, then_value, else_value
+        );
+        let scope = Scope::function(&["x"], &[]);
+        let g = format!(
+            "def g(x: int) -> bool:\n    return {}\n",
+            self.expr(&scope, Type::Bool, 0)
+        );
+        let scope = Scope::function(&[], &["u"]);
+        let h = format!(
+            "def h(u: str) -> str:\n    return {}\n",
+            self.expr(&scope, Type::Str, 0)
+        );
+        f + &g + &h
+    }
+}
+
+// A random program of bounded size that passes the type checker and always ends
+pub fn generate(seed: i32) -> Program {
+    let mut generator = Generator {
+        random: Random::new(seed),
+        counters: 0,
+    };
+    let functions = generator.functions();
+    let len = generator.random.int(3, 10);
+    let statements = (0..len).map(|_| generator.statement(0)).collect();
+    Program {
+        functions,
+        counters: generator.counters,
+        statements,
+    }
+}
+
+// Remove the statement at the position `n` counts down from, in the order they are written.
+// Returns false if there are fewer statements.
+fn remove(statements: &mut Vec<Statement>, n: &mut usize) -> bool {
+    for i in 0..statements.len() {
+        if *n == 0 {
+            statements.remove(i);
+            return true;
+        }
+        *n -= 1;
+        let removed = match &mut statements[i] {
+            Statement::Simple(_) => false,
+            Statement::If(_, then_body, else_body) => remove(then_body, n) || remove(else_body, n),
+            Statement::While(_, _, body) | Statement::For(_, _, body) => remove(body, n),
+        };
+        if removed {
+            return true;
+        }
+    }
+    false
+}
+
+// Remove statements one by one as long as the program keeps failing
+pub fn shrink(program: &mut Program, fails: impl Fn(&Program) -> bool) {
+    let mut position = 0;
+    loop {
+        let mut candidate = program.clone();
+        let mut n = position;
+        if !remove(&mut candidate.statements, &mut n) {
+            return;
+        }
+        if fails(&candidate) {
+            *program = candidate;
+        } else {
+            position += 1;
+        }
+    }
+}
+
+// What a program did: its output and exit code
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub stdout: String,
+    pub code: i32,
+}
+
+pub fn interpret(source: &str) -> Outcome {
+    let ast = crate::parse::process_str(source, Default::default());
+    let ast = crate::check::check(ast, Default::default());
+    assert!(
+        ast.errors.errors.is_empty(),
+        "{:?}\n{}",
+        ast.errors.errors,
+        source
+    );
+    let (stdout, error) = crate::interp::run_program(&ast, "");
+    Outcome {
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        code: error.map_or(0, |e| e.code),
+    }
+}
+
+// Returns None if the standard library is not built
+pub fn compile(source: &str) -> Option<Outcome> {
+    let output = crate::gen::test_util::run(source, "")?;
+    Some(Outcome {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        code: exit_code(output.status),
+    })
+}
+
+// A program killed by a signal gets the exit code that a shell would report
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_programs() {
+        for seed in 0..100 {
+            let source = generate(seed).to_string();
+            // Panics if the program doesn't pass the checker
+            interpret(&source);
+        }
+    }
+
+    #[test]
+    fn shrink_statements() {
+        let mut program = generate(1);
+        let prints = |program: &Program| program.to_string().matches("print(").count();
+        assert!(prints(&program) > 1);
+        shrink(&mut program, |program| prints(program) > 0);
+        assert_eq!(prints(&program), 1);
+    }
+
+    #[test]
+    fn interpreter_and_compiler_agree() {
+        let seeds = std::env::var(SEEDS_VAR)
+            .ok()
+            .map_or(DEFAULT_SEEDS, |seeds| seeds.parse().unwrap());
+        let differs = |program: &Program| {
+            let source = program.to_string();
+            compile(&source).is_some_and(|compiled| compiled != interpret(&source))
+        };
+        for seed in 0..seeds {
+            let mut program = generate(seed);
+            let source = program.to_string();
+            let compiled = match compile(&source) {
+                Some(compiled) => compiled,
+                None => return,
+            };
+            if compiled != interpret(&source) {
+                shrink(&mut program, differs);
+                let source = program.to_string();
+                panic!(
+                    "Seed {}: the compiled program and the interpreter differ on\n{}\n\
+                     Compiled: {:?}\nInterpreted: {:?}",
+                    seed,
+                    source,
+                    compile(&source).unwrap(),
+                    interpret(&source)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,391 @@
+// Argument passing for calls into the standard library, following the system ABI
+//
+// ChocoPy procedures pass all arguments on the stack with a private convention.
+// Calls into the standard library, however, must follow the platform ABI:
+//  - Windows: the first 4 arguments in rcx, rdx, r8, r9. The rest on the stack
+//    starting at [rsp+32], right after the 32-byte "shadow space".
+//  - Linux / macOS (System V): the first 6 arguments in rdi, rsi, rdx, rcx, r8, r9.
+//    The rest on the stack starting at [rsp].
+// All arguments are assumed to be 64-bit integers or pointers.
+
+use super::target::X86_64;
+use super::Platform;
+use std::convert::TryFrom;
+
+// 64-bit general purpose registers, in their encoding order
+#[allow(dead_code)] // Not all of them are used for arguments yet
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+}
+
+impl Reg {
+    fn code(self) -> u8 {
+        self as u8
+    }
+
+    // REX prefix bit when the register is in ModRM.reg
+    fn rex_r(self) -> u8 {
+        (self.code() >> 3) << 2
+    }
+
+    // REX prefix bit when the register is in ModRM.rm
+    fn rex_b(self) -> u8 {
+        self.code() >> 3
+    }
+
+    fn low(self) -> u8 {
+        self.code() & 7
+    }
+}
+
+// Scratch register for moving arguments onto the stack
+const SCRATCH: Reg = Reg::R11;
+
+// Where the value of an argument comes from
+#[derive(Clone, Copy, Debug)]
+pub enum Arg<'a> {
+    Reg(Reg),        // A register
+    Frame(i32),      // Stack frame slot [rbp+offset]
+    Symbol(&'a str), // Address of a symbol
+    Imm(i32),        // Sign-extended immediate value
+}
+
+// Where an argument goes to
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ArgSlot {
+    Reg(Reg),
+    Stack(i32), // [rsp+offset]
+}
+
+// A machine instruction. If `link` is not None, a 4-byte RIP-relative address
+// to the symbol should be appended to `code`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Instruction<'a> {
+    pub code: Vec<u8>,
+    pub link: Option<&'a str>,
+}
+
+// Location of the argument at `index` when calling a system ABI function
+pub fn arg_slot(platform: Platform, index: usize) -> ArgSlot {
+    const WINDOWS_REGS: [Reg; 4] = [Reg::Rcx, Reg::Rdx, Reg::R8, Reg::R9];
+    const SYSV_REGS: [Reg; 6] = [Reg::Rdi, Reg::Rsi, Reg::Rdx, Reg::Rcx, Reg::R8, Reg::R9];
+    match platform {
+        Platform::Windows => WINDOWS_REGS
+            .get(index)
+            .map_or(ArgSlot::Stack(index as i32 * 8), |&reg| ArgSlot::Reg(reg)),
+        Platform::Linux | Platform::Macos => SYSV_REGS.get(index).map_or_else(
+            || ArgSlot::Stack((index - SYSV_REGS.len()) as i32 * 8),
+            |&reg| ArgSlot::Reg(reg),
+        ),
+        Platform::Wasm => unreachable!("WebAssembly modules are generated by gen::wasm"),
+    }
+}
+
+// Stack space to reserve for a call with `arg_count` arguments, in qwords (8 bytes)
+pub fn stack_reserve(platform: Platform, arg_count: usize) -> usize {
+    match platform {
+        // "Shadow space" for the 4 register arguments is always reserved in Microsoft ABI
+        Platform::Windows => std::cmp::max(4, arg_count),
+        Platform::Linux | Platform::Macos => arg_count.saturating_sub(6),
+        Platform::Wasm => unreachable!("WebAssembly modules are generated by gen::wasm"),
+    }
+}
+
+fn rex_w(reg: Reg, rm: Reg) -> u8 {
+    0x48 | reg.rex_r() | rm.rex_b()
+}
+
+// mov dst,src
+fn mov_reg(dst: Reg, src: Reg) -> Vec<u8> {
+    vec![rex_w(src, dst), 0x89, 0xC0 | src.low() << 3 | dst.low()]
+}
+
+// mov dst,[rbp+offset]
+fn load_frame(dst: Reg, offset: i32) -> Vec<u8> {
+    let mut code = vec![rex_w(dst, Reg::Rbp), 0x8B];
+    if let Ok(offset) = i8::try_from(offset) {
+        code.push(0x40 | dst.low() << 3 | Reg::Rbp.low());
+        code.push(offset as u8);
+    } else {
+        code.push(0x80 | dst.low() << 3 | Reg::Rbp.low());
+        code.extend_from_slice(&X86_64.endian.bytes(offset));
+    }
+    code
+}
+
+// lea dst,[rip+{}]
+fn lea_rip(dst: Reg) -> Vec<u8> {
+    vec![rex_w(dst, Reg::Rax), 0x8D, dst.low() << 3 | 0x05]
+}
+
+// mov dst,imm
+fn load_imm(dst: Reg, imm: i32) -> Vec<u8> {
+    if imm == 0 {
+        // xor dst,dst
+        vec![rex_w(dst, dst), 0x31, 0xC0 | dst.low() << 3 | dst.low()]
+    } else {
+        let mut code = vec![rex_w(Reg::Rax, dst), 0xC7, 0xC0 | dst.low()];
+        code.extend_from_slice(&X86_64.endian.bytes(imm));
+        code
+    }
+}
+
+// mov [rsp+offset],src
+fn store_stack(offset: i32, src: Reg) -> Vec<u8> {
+    let mut code = vec![rex_w(src, Reg::Rsp), 0x89];
+    if let Ok(offset) = i8::try_from(offset) {
+        code.extend_from_slice(&[0x44 | src.low() << 3, 0x24, offset as u8]);
+    } else {
+        code.extend_from_slice(&[0x84 | src.low() << 3, 0x24]);
+        code.extend_from_slice(&X86_64.endian.bytes(offset));
+    }
+    code
+}
+
+// Instructions that load an argument value into a register
+fn load<'a>(dst: Reg, arg: Arg<'a>) -> Vec<Instruction<'a>> {
+    let (code, link) = match arg {
+        Arg::Reg(src) if src == dst => return vec![],
+        Arg::Reg(src) => (mov_reg(dst, src), None),
+        Arg::Frame(offset) => (load_frame(dst, offset), None),
+        Arg::Symbol(symbol) => (lea_rip(dst), Some(symbol)),
+        Arg::Imm(imm) => (load_imm(dst, imm), None),
+    };
+    vec![Instruction { code, link }]
+}
+
+// Generate instructions that move all arguments into place for a system ABI call.
+// Stack arguments are written first, using r11 as scratch,
+// and then register arguments are written in order.
+// Panics if a register argument would be overwritten before it is read.
+pub fn arg_moves<'a>(platform: Platform, args: &[Arg<'a>]) -> Vec<Instruction<'a>> {
+    let slots: Vec<ArgSlot> = (0..args.len()).map(|i| arg_slot(platform, i)).collect();
+    let mut instructions = vec![];
+
+    for (&arg, &slot) in args.iter().zip(&slots) {
+        assert!(!matches!(arg, Arg::Reg(SCRATCH)));
+        if let ArgSlot::Stack(offset) = slot {
+            let src = if let Arg::Reg(src) = arg {
+                src
+            } else {
+                instructions.append(&mut load(SCRATCH, arg));
+                SCRATCH
+            };
+            instructions.push(Instruction {
+                code: store_stack(offset, src),
+                link: None,
+            });
+        }
+    }
+
+    let mut written = vec![];
+    for (&arg, &slot) in args.iter().zip(&slots) {
+        if let ArgSlot::Reg(dst) = slot {
+            if let Arg::Reg(src) = arg {
+                assert!(!written.contains(&src), "Argument register clobbered");
+            }
+            instructions.append(&mut load(dst, arg));
+            written.push(dst);
+        }
+    }
+
+    instructions
+}
+
+// Generate instructions that move the parameters of a procedure called with the system ABI
+// to [rsp], [rsp+8], ..., where the ChocoPy procedure it calls takes its arguments.
+// Stack parameters are read from above the saved rbp and the return address, using r11 as scratch.
+pub fn param_moves(platform: Platform, count: usize) -> Vec<Vec<u8>> {
+    let mut instructions = vec![];
+    for i in 0..count {
+        let offset = i as i32 * 8;
+        match arg_slot(platform, i) {
+            ArgSlot::Reg(src) => instructions.push(store_stack(offset, src)),
+            ArgSlot::Stack(param_offset) => {
+                instructions.push(load_frame(SCRATCH, param_offset + 16));
+                instructions.push(store_stack(offset, SCRATCH));
+            }
+        }
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flatten(instructions: &[Instruction]) -> Vec<u8> {
+        let mut code = vec![];
+        for instruction in instructions {
+            code.extend_from_slice(&instruction.code);
+            if instruction.link.is_some() {
+                code.extend_from_slice(&[0; 4]);
+            }
+        }
+        code
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(mov_reg(Reg::R8, Reg::Rbp), [0x49, 0x89, 0xE8]);
+        assert_eq!(mov_reg(Reg::Rdx, Reg::Rsi), [0x48, 0x89, 0xF2]);
+        assert_eq!(mov_reg(Reg::Rcx, Reg::R10), [0x4C, 0x89, 0xD1]);
+        assert_eq!(load_frame(Reg::Rcx, 16), [0x48, 0x8B, 0x4D, 0x10]);
+        assert_eq!(load_frame(Reg::Rdi, 16), [0x48, 0x8B, 0x7D, 0x10]);
+        assert_eq!(
+            load_frame(Reg::R9, -0x100),
+            [0x4C, 0x8B, 0x8D, 0x00, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(lea_rip(Reg::Rcx), [0x48, 0x8D, 0x0D]);
+        assert_eq!(lea_rip(Reg::R11), [0x4C, 0x8D, 0x1D]);
+        assert_eq!(load_imm(Reg::Rsi, 0), [0x48, 0x31, 0xF6]);
+        assert_eq!(
+            load_imm(Reg::R11, -2),
+            [0x49, 0xC7, 0xC3, 0xFE, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(store_stack(32, Reg::R11), [0x4C, 0x89, 0x5C, 0x24, 0x20]);
+        assert_eq!(
+            store_stack(0x80, Reg::Rax),
+            [0x48, 0x89, 0x84, 0x24, 0x80, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn slots() {
+        assert_eq!(arg_slot(Platform::Windows, 3), ArgSlot::Reg(Reg::R9));
+        assert_eq!(arg_slot(Platform::Windows, 4), ArgSlot::Stack(32));
+        assert_eq!(arg_slot(Platform::Windows, 7), ArgSlot::Stack(56));
+        assert_eq!(arg_slot(Platform::Linux, 5), ArgSlot::Reg(Reg::R9));
+        assert_eq!(arg_slot(Platform::Linux, 6), ArgSlot::Stack(0));
+        assert_eq!(arg_slot(Platform::Macos, 7), ArgSlot::Stack(8));
+
+        assert_eq!(stack_reserve(Platform::Windows, 0), 4);
+        assert_eq!(stack_reserve(Platform::Windows, 6), 6);
+        assert_eq!(stack_reserve(Platform::Windows, 8), 8);
+        assert_eq!(stack_reserve(Platform::Linux, 6), 0);
+        assert_eq!(stack_reserve(Platform::Macos, 8), 2);
+    }
+
+    fn args8() -> Vec<Arg<'static>> {
+        vec![
+            Arg::Imm(1),
+            Arg::Reg(Reg::Rax),
+            Arg::Frame(-8),
+            Arg::Symbol("a"),
+            Arg::Imm(0),
+            Arg::Reg(Reg::Rbp),
+            Arg::Frame(-16),
+            Arg::Symbol("b"),
+        ]
+    }
+
+    #[test]
+    fn windows_args8() {
+        let instructions = arg_moves(Platform::Windows, &args8());
+        let links: Vec<_> = instructions.iter().filter_map(|i| i.link).collect();
+        assert_eq!(links, ["b", "a"]);
+        #[rustfmt::skip]
+        let expected = [
+            0x4D, 0x31, 0xDB,                         // xor r11,r11
+            0x4C, 0x89, 0x5C, 0x24, 0x20,             // mov [rsp+32],r11
+            0x48, 0x89, 0x6C, 0x24, 0x28,             // mov [rsp+40],rbp
+            0x4C, 0x8B, 0x5D, 0xF0,                   // mov r11,[rbp-16]
+            0x4C, 0x89, 0x5C, 0x24, 0x30,             // mov [rsp+48],r11
+            0x4C, 0x8D, 0x1D, 0, 0, 0, 0,             // lea r11,[rip+b]
+            0x4C, 0x89, 0x5C, 0x24, 0x38,             // mov [rsp+56],r11
+            0x48, 0xC7, 0xC1, 0x01, 0x00, 0x00, 0x00, // mov rcx,1
+            0x48, 0x89, 0xC2,                         // mov rdx,rax
+            0x4C, 0x8B, 0x45, 0xF8,                   // mov r8,[rbp-8]
+            0x4C, 0x8D, 0x0D, 0, 0, 0, 0,             // lea r9,[rip+a]
+        ];
+        assert_eq!(flatten(&instructions), expected);
+    }
+
+    #[test]
+    fn sysv_args8() {
+        for platform in [Platform::Linux, Platform::Macos] {
+            let instructions = arg_moves(platform, &args8());
+            let links: Vec<_> = instructions.iter().filter_map(|i| i.link).collect();
+            assert_eq!(links, ["b", "a"]);
+            #[rustfmt::skip]
+            let expected = [
+                0x4C, 0x8B, 0x5D, 0xF0,                   // mov r11,[rbp-16]
+                0x4C, 0x89, 0x5C, 0x24, 0x00,             // mov [rsp+0],r11
+                0x4C, 0x8D, 0x1D, 0, 0, 0, 0,             // lea r11,[rip+b]
+                0x4C, 0x89, 0x5C, 0x24, 0x08,             // mov [rsp+8],r11
+                0x48, 0xC7, 0xC7, 0x01, 0x00, 0x00, 0x00, // mov rdi,1
+                0x48, 0x89, 0xC6,                         // mov rsi,rax
+                0x48, 0x8B, 0x55, 0xF8,                   // mov rdx,[rbp-8]
+                0x48, 0x8D, 0x0D, 0, 0, 0, 0,             // lea rcx,[rip+a]
+                0x4D, 0x31, 0xC0,                         // xor r8,r8
+                0x49, 0x89, 0xE9,                         // mov r9,rbp
+            ];
+            assert_eq!(flatten(&instructions), expected);
+        }
+    }
+
+    #[test]
+    fn alloc_args() {
+        let args = [
+            Arg::Symbol("p"),
+            Arg::Reg(Reg::Rsi),
+            Arg::Reg(Reg::Rbp),
+            Arg::Reg(Reg::Rsp),
+        ];
+        #[rustfmt::skip]
+        assert_eq!(flatten(&arg_moves(Platform::Windows, &args)), [
+            0x48, 0x8D, 0x0D, 0, 0, 0, 0, // lea rcx,[rip+p]
+            0x48, 0x89, 0xF2,             // mov rdx,rsi
+            0x49, 0x89, 0xE8,             // mov r8,rbp
+            0x49, 0x89, 0xE1,             // mov r9,rsp
+        ]);
+        #[rustfmt::skip]
+        assert_eq!(flatten(&arg_moves(Platform::Linux, &args)), [
+            0x48, 0x8D, 0x3D, 0, 0, 0, 0, // lea rdi,[rip+p]
+            0x48, 0x89, 0xEA,             // mov rdx,rbp
+            0x48, 0x89, 0xE1,             // mov rcx,rsp
+        ]);
+    }
+
+    #[test]
+    fn params() {
+        #[rustfmt::skip]
+        assert_eq!(param_moves(Platform::Linux, 7).concat(), [
+            0x48, 0x89, 0x7C, 0x24, 0x00, // mov [rsp+0],rdi
+            0x48, 0x89, 0x74, 0x24, 0x08, // mov [rsp+8],rsi
+            0x48, 0x89, 0x54, 0x24, 0x10, // mov [rsp+16],rdx
+            0x48, 0x89, 0x4C, 0x24, 0x18, // mov [rsp+24],rcx
+            0x4C, 0x89, 0x44, 0x24, 0x20, // mov [rsp+32],r8
+            0x4C, 0x89, 0x4C, 0x24, 0x28, // mov [rsp+40],r9
+            0x4C, 0x8B, 0x5D, 0x10,       // mov r11,[rbp+16]
+            0x4C, 0x89, 0x5C, 0x24, 0x30, // mov [rsp+48],r11
+        ]);
+        #[rustfmt::skip]
+        assert_eq!(param_moves(Platform::Windows, 5).concat(), [
+            0x48, 0x89, 0x4C, 0x24, 0x00, // mov [rsp+0],rcx
+            0x48, 0x89, 0x54, 0x24, 0x08, // mov [rsp+8],rdx
+            0x4C, 0x89, 0x44, 0x24, 0x10, // mov [rsp+16],r8
+            0x4C, 0x89, 0x4C, 0x24, 0x18, // mov [rsp+24],r9
+            0x4C, 0x8B, 0x5D, 0x30,       // mov r11,[rbp+48]
+            0x4C, 0x89, 0x5C, 0x24, 0x20, // mov [rsp+32],r11
+        ]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn clobber() {
+        arg_moves(Platform::Linux, &[Arg::Imm(0), Arg::Reg(Reg::Rdi)]);
+    }
+}
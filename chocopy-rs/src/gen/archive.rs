@@ -0,0 +1,279 @@
+// Writes static library archives that bundle ChocoPy object files with the standard library.
+//
+// Archives are written in the format the platform linker expects, along with the symbol index
+// that `ranlib` would add:
+//  - Linux and Windows use the GNU/COFF layout, with a "/" symbol index and a "//" name table.
+//    Windows archives only have the first linker member and are not tested with link.exe.
+//  - macOS uses the BSD layout, with a "__.SYMDEF" symbol index and names stored in the data.
+
+use super::target::Endian;
+use super::Platform;
+use object::{Object, ObjectSymbol};
+use std::collections::HashMap;
+
+const MAGIC: &[u8] = b"!<arch>\n";
+const HEADER_SIZE: usize = 60;
+
+pub struct Member {
+    pub name: String,
+    pub data: Vec<u8>,
+    // Whether this is a ChocoPy object, as opposed to a member of the standard library
+    pub chocopy: bool,
+}
+
+#[derive(Debug)]
+pub struct ArchiveError(String);
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to create archive: {}", self.0)
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+// Read all object files in an archive as members
+pub fn read_members(data: &[u8]) -> Result<Vec<Member>, ArchiveError> {
+    let archive =
+        object::read::archive::ArchiveFile::parse(data).map_err(|e| ArchiveError(e.to_string()))?;
+    archive
+        .members()
+        .map(|member| {
+            let member = member.map_err(|e| ArchiveError(e.to_string()))?;
+            Ok(Member {
+                name: String::from_utf8_lossy(member.name()).into_owned(),
+                data: member
+                    .data(data)
+                    .map_err(|e| ArchiveError(e.to_string()))?
+                    .to_vec(),
+                chocopy: false,
+            })
+        })
+        .collect()
+}
+
+// Global symbols defined by each member. Members that aren't object files define none.
+// Fails if a ChocoPy object defines the same symbol as another member.
+fn member_symbols(members: &[Member]) -> Result<Vec<Vec<String>>, ArchiveError> {
+    let mut definitions: HashMap<String, usize> = HashMap::new();
+    let mut symbols = vec![];
+    for (i, member) in members.iter().enumerate() {
+        let mut names = vec![];
+        if let Ok(file) = object::File::parse(&*member.data) {
+            for symbol in file.symbols() {
+                if symbol.is_undefined() || !symbol.is_global() {
+                    continue;
+                }
+                let name = if let Ok(name) = symbol.name() {
+                    name.to_owned()
+                } else {
+                    continue;
+                };
+                if !symbol.is_weak() && !symbol.is_common() {
+                    if let Some(&first) = definitions.get(&name) {
+                        if member.chocopy || members[first].chocopy {
+                            return Err(ArchiveError(format!(
+                                "symbol `{}` is defined in both {} and {}",
+                                name, members[first].name, member.name
+                            )));
+                        }
+                    } else {
+                        definitions.insert(name.clone(), i);
+                    }
+                }
+                names.push(name);
+            }
+        }
+        symbols.push(names);
+    }
+    Ok(symbols)
+}
+
+fn write_header(out: &mut Vec<u8>, name: &str, size: usize) {
+    let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+        name, 0, 0, 0, 644, size
+    );
+    assert_eq!(header.len(), HEADER_SIZE);
+    out.extend_from_slice(header.as_bytes());
+}
+
+// Pad a member so that the next one starts at a multiple of `align`
+fn pad(out: &mut Vec<u8>, align: usize) {
+    while !out.len().is_multiple_of(align) {
+        out.push(b'\n');
+    }
+}
+
+fn write_gnu(members: &[Member], symbols: &[Vec<String>]) -> Vec<u8> {
+    // Long names are moved to the name table and referred to by their offset
+    let mut name_table = vec![];
+    let names: Vec<_> = members
+        .iter()
+        .map(|member| {
+            if member.name.len() < 16 {
+                format!("{}/", member.name)
+            } else {
+                let name = format!("/{}", name_table.len());
+                name_table.extend_from_slice(member.name.as_bytes());
+                name_table.extend_from_slice(b"/\n");
+                name
+            }
+        })
+        .collect();
+
+    let symbol_count: usize = symbols.iter().map(Vec::len).sum();
+    let string_size: usize = symbols.iter().flatten().map(|s| s.len() + 1).sum();
+    let index_size = 4 + symbol_count * 4 + string_size;
+
+    // Member offsets are known once the size of the index and the name table are
+    let mut offset = MAGIC.len() + HEADER_SIZE + index_size + index_size % 2;
+    if !name_table.is_empty() {
+        offset += HEADER_SIZE + name_table.len() + name_table.len() % 2;
+    }
+    let mut offsets = vec![];
+    for member in members {
+        offsets.push(offset);
+        offset += HEADER_SIZE + member.data.len() + member.data.len() % 2;
+    }
+
+    let mut out = MAGIC.to_vec();
+    write_header(&mut out, "/", index_size);
+    out.extend_from_slice(&Endian::Big.bytes(symbol_count as u32));
+    for (names, &offset) in symbols.iter().zip(&offsets) {
+        for _ in names {
+            out.extend_from_slice(&Endian::Big.bytes(offset as u32));
+        }
+    }
+    for name in symbols.iter().flatten() {
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+    }
+    pad(&mut out, 2);
+
+    if !name_table.is_empty() {
+        write_header(&mut out, "//", name_table.len());
+        out.extend_from_slice(&name_table);
+        pad(&mut out, 2);
+    }
+
+    for (member, name) in members.iter().zip(&names) {
+        write_header(&mut out, name, member.data.len());
+        out.extend_from_slice(&member.data);
+        pad(&mut out, 2);
+    }
+    out
+}
+
+// Length of a BSD member name padded with zeros, so that the data of a member
+// starting at `offset` is 8-byte aligned
+fn bsd_name_size(name: &str, offset: usize) -> usize {
+    let data = offset + HEADER_SIZE + name.len() + 1;
+    name.len() + 1 + (8 - data % 8) % 8
+}
+
+// Size of a BSD member starting at `offset`, including the padding after it
+fn bsd_member_size(name: &str, data: &[u8], offset: usize) -> usize {
+    let size = HEADER_SIZE + bsd_name_size(name, offset) + data.len();
+    size + size % 2
+}
+
+fn write_bsd_member(out: &mut Vec<u8>, name: &str, data: &[u8]) {
+    let name_size = bsd_name_size(name, out.len());
+    write_header(out, &format!("#1/{}", name_size), name_size + data.len());
+    out.extend_from_slice(name.as_bytes());
+    out.resize(out.len() + name_size - name.len(), 0);
+    out.extend_from_slice(data);
+    pad(out, 2);
+}
+
+fn write_bsd(members: &[Member], symbols: &[Vec<String>]) -> Vec<u8> {
+    const INDEX_NAME: &str = "__.SYMDEF";
+
+    let mut strings = vec![];
+    let mut string_offsets = vec![];
+    for name in symbols.iter().flatten() {
+        string_offsets.push(strings.len());
+        strings.extend_from_slice(name.as_bytes());
+        strings.push(0);
+    }
+    while !strings.len().is_multiple_of(8) {
+        strings.push(0);
+    }
+
+    let symbol_count = string_offsets.len();
+    let index_size = 4 + symbol_count * 8 + 4 + strings.len();
+    let mut offset = MAGIC.len();
+    offset += HEADER_SIZE + bsd_name_size(INDEX_NAME, offset) + index_size;
+    offset += offset % 2;
+    let mut offsets = vec![];
+    for member in members {
+        offsets.push(offset);
+        offset += bsd_member_size(&member.name, &member.data, offset);
+    }
+
+    let mut index = vec![];
+    index.extend_from_slice(&Endian::Little.bytes(symbol_count as u32 * 8));
+    let mut string_offsets = string_offsets.into_iter();
+    for (names, &offset) in symbols.iter().zip(&offsets) {
+        for _ in names {
+            let string_offset = string_offsets.next().unwrap();
+            index.extend_from_slice(&Endian::Little.bytes(string_offset as u32));
+            index.extend_from_slice(&Endian::Little.bytes(offset as u32));
+        }
+    }
+    index.extend_from_slice(&Endian::Little.bytes(strings.len() as u32));
+    index.extend_from_slice(&strings);
+
+    let mut out = MAGIC.to_vec();
+    write_bsd_member(&mut out, INDEX_NAME, &index);
+    for member in members {
+        write_bsd_member(&mut out, &member.name, &member.data);
+    }
+    out
+}
+
+// Build an archive of the members with a symbol index
+pub fn write_archive(members: &[Member], platform: Platform) -> Result<Vec<u8>, ArchiveError> {
+    let symbols = member_symbols(members)?;
+    Ok(match platform {
+        Platform::Windows | Platform::Linux => write_gnu(members, &symbols),
+        Platform::Macos => write_bsd(members, &symbols),
+        Platform::Wasm => unreachable!("WebAssembly modules are generated by gen::wasm"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, data: &[u8]) -> Member {
+        Member {
+            name: name.to_owned(),
+            data: data.to_vec(),
+            chocopy: false,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let members = [
+            member("a.o", b"short"),
+            member("a_very_long_member_name.o", b"odd"),
+            member("b.o", b""),
+        ];
+        for &platform in &[Platform::Linux, Platform::Macos] {
+            let archive = write_archive(&members, platform).unwrap();
+            let read = read_members(&archive).unwrap();
+            let read: Vec<_> = read.iter().map(|m| (&*m.name, &*m.data)).collect();
+            assert_eq!(
+                read,
+                [
+                    ("a.o", &b"short"[..]),
+                    ("a_very_long_member_name.o", b"odd"),
+                    ("b.o", b""),
+                ]
+            );
+        }
+    }
+}
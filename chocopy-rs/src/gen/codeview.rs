@@ -1,6 +1,7 @@
 // Debug info generator for CodeView/PDB (Windows)
 
 use super::debug::*;
+use super::target::Endian;
 use super::*;
 use chocopy_rs_common::*;
 use md5::*;
@@ -45,12 +46,13 @@ trait VecWriter {
     fn write_u8(&mut self, value: u8);
     fn align4(&mut self);
 
+    // CodeView is little-endian regardless of the target
     fn write_u16(&mut self, value: u16) {
-        self.write_slice(&value.to_le_bytes())
+        self.write_slice(&Endian::Little.bytes(value))
     }
 
     fn write_u32(&mut self, value: u32) {
-        self.write_slice(&value.to_le_bytes())
+        self.write_slice(&Endian::Little.bytes(value))
     }
 
     fn write_str(&mut self, s: &str) {
@@ -80,7 +82,7 @@ impl VecWriter for Vec<u8> {
         self.push(value)
     }
     fn align4(&mut self) {
-        while self.len() % 4 != 0 {
+        while !self.len().is_multiple_of(4) {
             self.push(0)
         }
     }
@@ -269,12 +271,14 @@ impl Codeview {
             "[]".to_owned(),
             ClassDebug {
                 size: 8,
+                prototype_size: OBJECT_PROTOTYPE_SIZE,
                 attributes: vec![VarDebug {
                     offset: ARRAY_LEN_OFFSET as i32,
                     line: 0,
                     name: "$len".to_owned(),
                     var_type: TypeDebug::class_type("int"),
                 }],
+                constants: vec![],
                 methods: std::iter::once((
                     PROTOTYPE_INIT_OFFSET,
                     (
@@ -321,6 +325,7 @@ impl DebugWriter for Codeview {
 
     fn add_class(&mut self, name: String, class_debug: ClassDebug) {
         const MEMBER: u16 = 0x150D;
+        const STMEMBER: u16 = 0x150E;
 
         let mut proto_fields = vec![];
 
@@ -398,8 +403,7 @@ impl DebugWriter for Codeview {
         proto_storage_type.write_u32(proto_fields_id);
         proto_storage_type.write_u32(0); // derived
         proto_storage_type.write_u32(0); // vshape
-        proto_storage_type
-            .write_u16(class_debug.methods.len() as u16 * 8 + PROTOTYPE_INIT_OFFSET as u16); // size
+        proto_storage_type.write_u16(class_debug.prototype_size as u16); // size
         proto_storage_type.write_str(&(name.clone() + ".$prototype"));
         let proto_storage_type_id = self.write_leaf(LeafType::Structure, proto_storage_type);
 
@@ -435,11 +439,17 @@ impl DebugWriter for Codeview {
             fields.write_u16(attribute.offset as u16);
             fields.write_str(&attribute.name);
         }
+        for constant in &class_debug.constants {
+            fields.write_u16(STMEMBER);
+            fields.write_u16(3); // public
+            fields.write_u32(self.get_type(&constant.var_type));
+            fields.write_str(&constant.name);
+        }
         let fields_id = self.write_leaf(LeafType::FieldList, fields);
 
+        let member_count = class_debug.attributes.len() + class_debug.constants.len();
         let mut storage_type = vec![];
-        storage_type
-            .write_u16(class_debug.attributes.len() as u16 + OBJECT_HEADER_MEMBER_COUNT as u16); // element count
+        storage_type.write_u16(member_count as u16 + OBJECT_HEADER_MEMBER_COUNT as u16); // element count
         storage_type.write_u16(0); // no flag
         storage_type.write_u32(fields_id);
         storage_type.write_u32(0); // derived
@@ -455,6 +465,14 @@ impl DebugWriter for Codeview {
         udt_subsection.write_record(RecordType::Udt, udt);
         self.symbol_stream
             .write_subsection(SubsectionType::Symbols, udt_subsection);
+
+        // Storage of static members
+        for constant in class_debug.constants {
+            self.add_global(VarDebug {
+                name: name.clone() + "::" + &constant.name,
+                ..constant
+            });
+        }
     }
 
     fn add_chunk(&mut self, chunk: &Chunk) {
@@ -490,12 +508,17 @@ impl DebugWriter for Codeview {
             proc.write_u32(0); // end
             proc.write_u32(0); // next
             proc.write_u32(chunk.code.len() as u32);
-            proc.write_u32(11); // debug start
+            let prologue_size = if procedure.frameless { 7 } else { 11 };
+            proc.write_u32(prologue_size); // debug start
             proc.write_u32(chunk.code.len() as u32); // debug end
             proc.write_u32(func_id_id);
             proc.write_u32(0); // offset
             proc.write_u16(0); // segment
-            proc.write_u8(1 | (1 << 5)); // CV_PFLAG_CUST_CALL | CV_PFLAG_NOFPO
+            if procedure.frameless {
+                proc.write_u8(1); // CV_PFLAG_CUST_CALL
+            } else {
+                proc.write_u8(1 | (1 << 5)); // CV_PFLAG_CUST_CALL | CV_PFLAG_NOFPO
+            }
             proc.write_str(&chunk.name);
 
             let mut frame_proc = vec![];
@@ -505,7 +528,11 @@ impl DebugWriter for Codeview {
             frame_proc.write_u32(0); // save regs
             frame_proc.write_u32(0); // exception handler
             frame_proc.write_u16(0); // exception handler id
-            frame_proc.write_u32((2 << 16) | (2 << 14)); // flags: RBP as frame pointer
+            if procedure.frameless {
+                frame_proc.write_u32((1 << 16) | (1 << 14)); // flags: RSP as frame pointer
+            } else {
+                frame_proc.write_u32((2 << 16) | (2 << 14)); // flags: RBP as frame pointer
+            }
 
             let mut symbols = vec![];
             symbols.write_record(proc_id_type, proc);
@@ -524,8 +551,14 @@ impl DebugWriter for Codeview {
                 symbol.write_str(&var.name);
                 symbols.write_record(RecordType::Local, symbol);
 
+                // Offsets from rsp after the prologue in frameless procedures
+                let offset = if procedure.frameless {
+                    var.offset + procedure.frame_size as i32
+                } else {
+                    var.offset
+                };
                 let mut location = vec![];
-                location.write_u32(var.offset as u32);
+                location.write_u32(offset as u32);
                 symbols.write_record(RecordType::DefRangFramePointerRelFullScope, location);
             }
 
@@ -548,7 +581,10 @@ impl DebugWriter for Codeview {
             self.symbol_stream
                 .write_subsection(SubsectionType::Symbols, symbols);
 
-            if !procedure.artificial {
+            // A procedure without statements (such as main of a program with only
+            // declarations) or generated without lines has no line table at all instead of an
+            // empty one. Constructors have lines of their class.
+            if !procedure.lines.is_empty() {
                 let mut lines = vec![];
 
                 lines.write_u32(0); // offset
@@ -584,13 +620,22 @@ impl DebugWriter for Codeview {
 
             let xdata_offset = self.xdata.len();
             self.xdata.write_u8(1); // version
-            self.xdata.write_u8(11); // prolog
-            self.xdata.write_u8(3); // code count
-            self.xdata.write_u8(0); // frame register
-            self.xdata.write_u16(0x010B); // UWOP_ALLOC_LARGE
-            self.xdata.write_u16((procedure.frame_size / 8) as u16);
-            self.xdata.write_u16(0x5001); // UWOP_PUSH_NONVOL RBP
-            self.xdata.write_u16(0); // padding
+            if procedure.frameless {
+                // sub rsp also allocates the word where rbp would be saved
+                self.xdata.write_u8(7); // prolog
+                self.xdata.write_u8(2); // code count
+                self.xdata.write_u8(0); // frame register
+                self.xdata.write_u16(0x0107); // UWOP_ALLOC_LARGE
+                self.xdata.write_u16((procedure.frame_size / 8 + 1) as u16);
+            } else {
+                self.xdata.write_u8(11); // prolog
+                self.xdata.write_u8(3); // code count
+                self.xdata.write_u8(0); // frame register
+                self.xdata.write_u16(0x010B); // UWOP_ALLOC_LARGE
+                self.xdata.write_u16((procedure.frame_size / 8) as u16);
+                self.xdata.write_u16(0x5001); // UWOP_PUSH_NONVOL RBP
+                self.xdata.write_u16(0); // padding
+            }
 
             self.pdata_links.push(DebugChunkLink {
                 link_type: DebugChunkLinkType::ImageRelative,
@@ -4,7 +4,7 @@ use super::debug::*;
 use super::gimli_writer::*;
 use super::*;
 use chocopy_rs_common::*;
-use gimli::{constants::*, write::*, *};
+use gimli::{constants::*, write::*, Encoding, Format, LineEncoding, Register};
 use std::collections::HashMap;
 
 fn dwarf_add_base_type(
@@ -180,6 +180,17 @@ impl Dwarf {
         }
     }
 
+    // Location of a variable in the global section
+    fn global_location(&mut self, offset: i32) -> Expression {
+        let mut location = Expression::new();
+        location.op_addr(Address::Symbol {
+            symbol: self.symbol_pool.len(),
+            addend: offset as i64,
+        });
+        self.symbol_pool.push(GLOBAL_SECTION.to_owned());
+        location
+    }
+
     fn add_method_type(&mut self, method_type: MethodDebug) -> UnitEntryId {
         if let Some(&id) = self.debug_method_types.get(&method_type) {
             return id;
@@ -319,7 +330,7 @@ impl DebugWriter for Dwarf {
             dwarf_add_struct_type(
                 &mut self.dwarf,
                 &prototype_name,
-                (class_debug.methods.len() * 8) as u64 + PROTOTYPE_INIT_OFFSET as u64,
+                class_debug.prototype_size as u64,
             )
         };
 
@@ -394,6 +405,28 @@ impl DebugWriter for Dwarf {
                 attribute.offset as u64,
             );
         }
+
+        // Static member declaration, and its definition in the global section
+        for constant in class_debug.constants {
+            let member_id = self.dwarf.unit.add(tag_id, DW_TAG_member);
+            let member = self.dwarf.unit.get_mut(member_id);
+            member.set(DW_AT_name, AttributeValue::String(constant.name.into()));
+            member.set(
+                DW_AT_type,
+                AttributeValue::UnitRef(self.debug_types[&constant.var_type]),
+            );
+            member.set(DW_AT_external, AttributeValue::Flag(true));
+            member.set(DW_AT_declaration, AttributeValue::Flag(true));
+
+            let location = self.global_location(constant.offset);
+            let root_id = self.dwarf.unit.root();
+            let node_id = self.dwarf.unit.add(root_id, DW_TAG_variable);
+            let node = self.dwarf.unit.get_mut(node_id);
+            node.set(DW_AT_specification, AttributeValue::UnitRef(member_id));
+            node.set(DW_AT_location, AttributeValue::Exprloc(location));
+            node.set(DW_AT_decl_file, AttributeValue::Data1(1));
+            node.set(DW_AT_decl_line, AttributeValue::Udata(constant.line as u64));
+        }
     }
 
     fn add_chunk(&mut self, chunk: &Chunk) {
@@ -437,7 +470,12 @@ impl DebugWriter for Dwarf {
                 AttributeValue::Flag(procedure_debug.artificial),
             );
             let mut frame_base = Expression::new();
-            frame_base.op_reg(Register(6));
+            if procedure_debug.frameless {
+                // Where rbp would be, so that variables have the same offsets
+                frame_base.op_breg(Register(7), procedure_debug.frame_size as i64);
+            } else {
+                frame_base.op_reg(Register(6));
+            }
             sub_program.set(DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
             sub_program.set(
                 DW_AT_type,
@@ -509,17 +547,12 @@ impl DebugWriter for Dwarf {
     }
 
     fn add_global(&mut self, global_debug: VarDebug) {
+        let location = self.global_location(global_debug.offset);
+
         let root_id = self.dwarf.unit.root();
         let node_id = self.dwarf.unit.add(root_id, DW_TAG_variable);
         let node = self.dwarf.unit.get_mut(node_id);
 
-        let mut location = Expression::new();
-        location.op_addr(Address::Symbol {
-            symbol: self.symbol_pool.len(),
-            addend: global_debug.offset as i64,
-        });
-        self.symbol_pool.push(GLOBAL_SECTION.to_owned());
-
         node.set(DW_AT_location, AttributeValue::Exprloc(location));
 
         node.set(DW_AT_name, AttributeValue::String(global_debug.name.into()));
@@ -0,0 +1,1189 @@
+// Links an x86-64 Linux object file with the runtime into a static executable in memory,
+// without invoking the system linker.
+//
+// Only what the standard library and a static C library need is supported:
+//  - The start files, the program object and the end files are loaded in order. Archive members
+//    are then loaded while they define symbols that are still undefined, searching all archives
+//    as one group like `--start-group`. Of COMDAT groups with the same signature, only the first
+//    one is kept.
+//  - Allocated sections are merged into output sections by name, which are laid out in three
+//    PT_LOAD segments (read-only, code, data) of a non-PIE executable. The ELF header and the
+//    program headers are at the start of the read-only segment. There are no section headers.
+//  - General and local dynamic TLS accesses are relaxed to local exec, because a static C library
+//    has no `__tls_get_addr`. Other GOT-relative relocations get GOT entries instead of having
+//    their instructions relaxed.
+//  - GNU indirect functions are called through PLT entries, whose GOT entries the C library fills
+//    in at startup from the IRELATIVE relocations between `__rela_iplt_start` and
+//    `__rela_iplt_end`.
+//  - Debug info and other sections that aren't allocated are left out.
+
+use super::target::Endian;
+use object::elf;
+use object::read::elf::{FileHeader as _, Rela as _, SectionHeader as _, Sym as _};
+use object::{LittleEndian, I64, U16, U32, U64};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+type FileHeader = elf::FileHeader64<LittleEndian>;
+type SectionTable<'a> = object::read::elf::SectionTable<'a, FileHeader>;
+type SymbolTable<'a> = object::read::elf::SymbolTable<'a, FileHeader>;
+type Relocations<'a> = &'a [elf::Rela64<LittleEndian>];
+
+const BASE_ADDRESS: u64 = 0x400000;
+const PAGE_SIZE: u64 = 0x1000;
+const PLT_ENTRY_SIZE: u64 = 8;
+
+// Objects and archives that a program object is linked with, in the order `cc -static` passes
+// them to the linker
+pub struct Runtime<'a> {
+    pub start_files: Vec<&'a [u8]>, // Objects before the program, like crt1.o
+    pub archives: Vec<&'a [u8]>,    // Libraries for undefined symbols, like libc.a
+    pub end_files: Vec<&'a [u8]>,   // Objects after the libraries, like crtn.o
+}
+
+#[derive(Debug)]
+pub struct LinkError(String);
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to link: {}", self.0)
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl From<object::read::Error> for LinkError {
+    fn from(error: object::read::Error) -> LinkError {
+        LinkError(error.to_string())
+    }
+}
+
+// What a symbol refers to
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Target<'a> {
+    Section {
+        file: usize,
+        section: usize,
+        value: u64,
+    },
+    Absolute(u64),
+    Common(usize),    // Index into `Linker::commons`
+    Linker(&'a [u8]), // Defined by the linker from the layout, like `_end`
+}
+
+#[derive(Default)]
+struct Global<'a> {
+    target: Option<Target<'a>>,
+    weak: bool, // Whether the definition is weak
+    symbol_type: u8,
+    size: u64,
+    referenced: bool, // Whether a non-weak reference needs a definition
+}
+
+// A relocation target after symbol resolution
+#[derive(Clone, Copy)]
+struct Resolved<'a> {
+    target: Option<Target<'a>>, // None for undefined weak symbols, which are zero
+    ifunc: bool,
+    size: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum GotEntry<'a> {
+    Address(Option<Target<'a>>),
+    TpOff(Option<Target<'a>>), // Offset from the thread pointer
+}
+
+struct InputFile<'a> {
+    data: &'a [u8],
+    sections: SectionTable<'a>,
+    symbols: SymbolTable<'a>,
+    kept: Vec<bool>, // False for sections of COMDAT groups that were already loaded
+    placements: Vec<Option<(usize, u64)>>, // Output section and offset in it
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Synthetic {
+    Got,
+    Plt,
+    PltGot,
+    RelaPlt,
+    Common,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Segment {
+    ReadOnly,
+    Code,
+    Data,
+}
+
+struct OutputSection {
+    name: Vec<u8>,
+    flags: u64,
+    nobits: bool,
+    align: u64,
+    size: u64,
+    address: u64,
+    offset: u64,
+    inputs: Vec<(usize, usize)>, // File and section
+    synthetic: Option<Synthetic>,
+}
+
+impl OutputSection {
+    fn segment(&self) -> Segment {
+        if self.flags & u64::from(elf::SHF_WRITE | elf::SHF_TLS) != 0 {
+            Segment::Data
+        } else if self.flags & u64::from(elf::SHF_EXECINSTR) != 0 {
+            Segment::Code
+        } else {
+            Segment::ReadOnly
+        }
+    }
+
+    // Position in the segment. Sections that take no space in the file come last
+    fn rank(&self) -> (Segment, u32) {
+        let rank = match (self.synthetic, &self.name[..]) {
+            (Some(Synthetic::RelaPlt), _) | (Some(Synthetic::Plt), _) => 9,
+            (Some(Synthetic::Got), _) => 6,
+            (Some(Synthetic::PltGot), _) => 7,
+            (Some(Synthetic::Common), _) => 13,
+            (None, b".rodata") | (None, b".init") | (None, b".tdata") => 0,
+            (None, b".eh_frame") | (None, b".text") | (None, b".tbss") => 1,
+            (None, b".gcc_except_table") | (None, b".preinit_array") => 2,
+            (None, b".init_array") => 3,
+            (None, b".fini_array") => 4,
+            (None, b".data.rel.ro") => 5,
+            (None, b".data") => 8,
+            (None, b".fini") => 10,
+            (None, b".bss") => 12,
+            _ if self.nobits => 11,
+            _ if self.segment() == Segment::Code => 3,
+            _ => 8,
+        };
+        (self.segment(), rank)
+    }
+}
+
+// Name of the output section that an input section goes to
+fn output_name(name: &[u8]) -> &[u8] {
+    const MERGED: [&[u8]; 11] = [
+        b".text",
+        b".rodata",
+        b".data.rel.ro",
+        b".data",
+        b".bss",
+        b".tdata",
+        b".tbss",
+        b".preinit_array",
+        b".init_array",
+        b".fini_array",
+        b".gcc_except_table",
+    ];
+    MERGED
+        .iter()
+        .find(|&&prefix| {
+            name.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(b"."))
+        })
+        .copied()
+        .unwrap_or(name)
+}
+
+// Constructors with a priority, like `.init_array.00099`, run before those without
+fn init_priority(name: &[u8]) -> (bool, u32) {
+    let priority = name
+        .iter()
+        .rposition(|&c| c == b'.')
+        .and_then(|dot| std::str::from_utf8(&name[dot + 1..]).ok())
+        .and_then(|suffix| suffix.parse().ok());
+    (priority.is_none(), priority.unwrap_or(0))
+}
+
+// Whether `__start_` and `__stop_` symbols are defined for the section
+fn is_c_identifier(name: &[u8]) -> bool {
+    !name.is_empty() && name.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_')
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    let align = align.max(1);
+    value.div_ceil(align) * align
+}
+
+// An archive member, and whether it is loaded already
+struct Member<'a> {
+    data: &'a [u8],
+    loaded: bool,
+}
+
+struct Linker<'a> {
+    files: Vec<InputFile<'a>>,
+    globals: HashMap<&'a [u8], Global<'a>>,
+    groups: HashSet<&'a [u8]>,
+    commons: Vec<(u64, u64)>, // Size and alignment
+    outputs: Vec<OutputSection>,
+    got: Vec<GotEntry<'a>>,
+    got_offsets: HashMap<GotEntry<'a>, u64>,
+    plt: Vec<Option<Target<'a>>>,
+    plt_indices: HashMap<Option<Target<'a>>, u64>,
+    tls: (u64, u64, u64), // Start address, size in memory and alignment of the TLS template
+    linker_symbols: HashMap<&'a [u8], u64>,
+}
+
+impl<'a> Linker<'a> {
+    fn load(&mut self, data: &'a [u8]) -> Result<(), LinkError> {
+        let header = FileHeader::parse(data)?;
+        if header.e_type(LittleEndian) != elf::ET_REL
+            || header.e_machine(LittleEndian) != elf::EM_X86_64
+        {
+            return Err(LinkError("input is not an x86-64 object file".to_owned()));
+        }
+        let sections = header.sections(LittleEndian, data)?;
+        let symbols = sections.symbols(LittleEndian, data, elf::SHT_SYMTAB)?;
+
+        let mut kept = vec![true; sections.len()];
+        for section in sections.iter() {
+            if section.sh_type(LittleEndian) != elf::SHT_GROUP {
+                continue;
+            }
+            let words: &[U32<LittleEndian>] = section
+                .data_as_array(LittleEndian, data)
+                .map_err(|_| LinkError("invalid section group".to_owned()))?;
+            let (flags, members) = words
+                .split_first()
+                .ok_or_else(|| LinkError("empty section group".to_owned()))?;
+            if flags.get(LittleEndian) & elf::GRP_COMDAT == 0 {
+                continue;
+            }
+            let signature = symbols.symbol(section.sh_info(LittleEndian) as usize)?;
+            if !self
+                .groups
+                .insert(symbols.symbol_name(LittleEndian, signature)?)
+            {
+                for member in members {
+                    kept[member.get(LittleEndian) as usize] = false;
+                }
+            }
+        }
+
+        let file = self.files.len();
+        for (index, symbol) in symbols.iter().enumerate() {
+            if symbol.st_bind() == elf::STB_LOCAL {
+                continue;
+            }
+            let name = symbols.symbol_name(LittleEndian, symbol)?;
+            let weak = symbol.st_bind() == elf::STB_WEAK;
+            let value = symbol.st_value(LittleEndian);
+            let target = match symbol.st_shndx(LittleEndian) {
+                elf::SHN_UNDEF => None,
+                elf::SHN_ABS => Some(Target::Absolute(value)),
+                elf::SHN_COMMON => {
+                    let global = self.globals.entry(name).or_default();
+                    match global.target {
+                        Some(Target::Common(common)) => {
+                            let (size, align) = &mut self.commons[common];
+                            *size = (*size).max(symbol.st_size(LittleEndian));
+                            *align = (*align).max(value);
+                        }
+                        Some(_) => (),
+                        None => {
+                            global.target = Some(Target::Common(self.commons.len()));
+                            global.symbol_type = elf::STT_OBJECT;
+                            global.size = symbol.st_size(LittleEndian);
+                            self.commons.push((symbol.st_size(LittleEndian), value));
+                        }
+                    }
+                    continue;
+                }
+                _ => symbols
+                    .symbol_section(LittleEndian, symbol, index)?
+                    .map(|section| section.0)
+                    .filter(|&section| kept[section])
+                    .map(|section| Target::Section {
+                        file,
+                        section,
+                        value,
+                    }),
+            };
+
+            let global = self.globals.entry(name).or_default();
+            let Some(target) = target else {
+                // A reference, or a definition in a discarded group that refers to the kept one
+                if !weak && symbol.st_shndx(LittleEndian) == elf::SHN_UNDEF {
+                    global.referenced = true;
+                }
+                continue;
+            };
+            let replace = match global.target {
+                None | Some(Target::Common(_)) => true,
+                Some(_) if global.weak => !weak,
+                Some(_) if weak => false,
+                Some(_) => {
+                    return Err(LinkError(format!(
+                        "multiple definitions of `{}`",
+                        String::from_utf8_lossy(name)
+                    )))
+                }
+            };
+            if replace {
+                global.target = Some(target);
+                global.weak = weak;
+                global.symbol_type = symbol.st_type();
+                global.size = symbol.st_size(LittleEndian);
+            }
+        }
+
+        self.files.push(InputFile {
+            data,
+            sections,
+            symbols,
+            placements: vec![None; kept.len()],
+            kept,
+        });
+        Ok(())
+    }
+
+    // Load archive members until no more undefined symbols are defined by them.
+    // Returns the indices of the loaded files
+    fn load_archives(&mut self, archives: &[&'a [u8]]) -> Result<Vec<usize>, LinkError> {
+        // The first member that defines each symbol
+        let mut members = vec![];
+        let mut index: HashMap<&'a [u8], usize> = HashMap::new();
+        for &archive in archives {
+            let archive_file = object::read::archive::ArchiveFile::parse(archive)?;
+            for member in archive_file.members() {
+                let data = member?.data(archive)?;
+                if !data.starts_with(&elf::ELFMAG) {
+                    continue;
+                }
+                let header = FileHeader::parse(data)?;
+                let sections = header.sections(LittleEndian, data)?;
+                let symbols = sections.symbols(LittleEndian, data, elf::SHT_SYMTAB)?;
+                for symbol in symbols.iter() {
+                    if symbol.st_bind() != elf::STB_LOCAL
+                        && symbol.st_shndx(LittleEndian) != elf::SHN_UNDEF
+                    {
+                        index
+                            .entry(symbols.symbol_name(LittleEndian, symbol)?)
+                            .or_insert(members.len());
+                    }
+                }
+                members.push(Member {
+                    data,
+                    loaded: false,
+                });
+            }
+        }
+
+        let mut loaded = vec![];
+        loop {
+            let mut wanted: Vec<(usize, &'a [u8])> = self
+                .globals
+                .iter()
+                .filter(|(_, global)| global.target.is_none() && global.referenced)
+                .filter_map(|(&name, _)| {
+                    let &member = index.get(name)?;
+                    (!members[member].loaded).then_some((member, name))
+                })
+                .collect();
+            if wanted.is_empty() {
+                return Ok(loaded);
+            }
+            // Members are loaded in archive order, unless an earlier one defined the symbol
+            wanted.sort_unstable();
+            for (member, name) in wanted {
+                if members[member].loaded || self.globals[name].target.is_some() {
+                    continue;
+                }
+                members[member].loaded = true;
+                loaded.push(self.files.len());
+                self.load(members[member].data)?;
+            }
+        }
+    }
+
+    fn is_linker_symbol(&self, name: &[u8]) -> bool {
+        const NAMES: [&[u8]; 19] = [
+            b"__ehdr_start",
+            b"__executable_start",
+            b"_GLOBAL_OFFSET_TABLE_",
+            b"__preinit_array_start",
+            b"__preinit_array_end",
+            b"__init_array_start",
+            b"__init_array_end",
+            b"__fini_array_start",
+            b"__fini_array_end",
+            b"__rela_iplt_start",
+            b"__rela_iplt_end",
+            b"etext",
+            b"_etext",
+            b"__etext",
+            b"edata",
+            b"_edata",
+            b"__bss_start",
+            b"end",
+            b"_end",
+        ];
+        NAMES.contains(&name)
+            || name
+                .strip_prefix(b"__start_")
+                .or_else(|| name.strip_prefix(b"__stop_"))
+                .is_some_and(|section| self.outputs.iter().any(|output| output.name == section))
+    }
+
+    fn section_name(&self, file: usize, section: usize) -> Result<&'a [u8], LinkError> {
+        let sections = &self.files[file].sections;
+        let header = sections.section(object::SectionIndex(section))?;
+        Ok(sections.section_name(LittleEndian, header)?)
+    }
+
+    // Merge the allocated sections of all files in order into output sections
+    fn place_sections(&mut self, order: &[usize]) -> Result<(), LinkError> {
+        let mut outputs: HashMap<&[u8], usize> = HashMap::new();
+        for &file in order {
+            let sections = self.files[file].sections;
+            for (index, section) in sections.iter().enumerate() {
+                let flags = section.sh_flags(LittleEndian);
+                let kept = self.files[file].kept[index]
+                    && flags & u64::from(elf::SHF_ALLOC) != 0
+                    && flags & u64::from(elf::SHF_EXCLUDE) == 0
+                    && matches!(
+                        section.sh_type(LittleEndian),
+                        elf::SHT_PROGBITS
+                            | elf::SHT_NOBITS
+                            | elf::SHT_INIT_ARRAY
+                            | elf::SHT_FINI_ARRAY
+                            | elf::SHT_PREINIT_ARRAY
+                            | elf::SHT_X86_64_UNWIND
+                    );
+                if !kept {
+                    continue;
+                }
+                let name = output_name(self.section_name(file, index)?);
+                let output = match outputs.get(name) {
+                    Some(&output) => output,
+                    None => {
+                        outputs.insert(name, self.outputs.len());
+                        self.outputs.push(OutputSection {
+                            name: name.to_vec(),
+                            flags: 0,
+                            nobits: true,
+                            align: 1,
+                            size: 0,
+                            address: 0,
+                            offset: 0,
+                            inputs: vec![],
+                            synthetic: None,
+                        });
+                        self.outputs.len() - 1
+                    }
+                };
+                let output = &mut self.outputs[output];
+                output.flags |=
+                    flags & u64::from(elf::SHF_WRITE | elf::SHF_EXECINSTR | elf::SHF_TLS);
+                output.nobits &= section.sh_type(LittleEndian) == elf::SHT_NOBITS;
+                output.inputs.push((file, index));
+            }
+        }
+
+        for output in 0..self.outputs.len() {
+            let mut inputs = std::mem::take(&mut self.outputs[output].inputs);
+            if [&b".init_array"[..], b".fini_array"].contains(&&self.outputs[output].name[..]) {
+                let mut keys = vec![];
+                for &(file, section) in &inputs {
+                    keys.push(init_priority(self.section_name(file, section)?));
+                }
+                let mut keyed: Vec<_> = keys.into_iter().zip(inputs).collect();
+                keyed.sort_by_key(|&(key, _)| key);
+                inputs = keyed.into_iter().map(|(_, input)| input).collect();
+            }
+            let output_section = &mut self.outputs[output];
+            for &(file, section) in &inputs {
+                let header = self.files[file]
+                    .sections
+                    .section(object::SectionIndex(section))?;
+                let align = header.sh_addralign(LittleEndian).max(1);
+                let offset = align_up(output_section.size, align);
+                output_section.align = output_section.align.max(align);
+                output_section.size = offset + header.sh_size(LittleEndian);
+                self.files[file].placements[section] = Some((output, offset));
+            }
+            output_section.inputs = inputs;
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, file: usize, index: usize) -> Result<Resolved<'a>, LinkError> {
+        let input = &self.files[file];
+        let symbol = input.symbols.symbol(index)?;
+        if symbol.st_bind() == elf::STB_LOCAL {
+            let target = match symbol.st_shndx(LittleEndian) {
+                elf::SHN_ABS => Some(Target::Absolute(symbol.st_value(LittleEndian))),
+                _ => input
+                    .symbols
+                    .symbol_section(LittleEndian, symbol, index)?
+                    .map(|section| Target::Section {
+                        file,
+                        section: section.0,
+                        value: symbol.st_value(LittleEndian),
+                    }),
+            };
+            return Ok(Resolved {
+                target,
+                ifunc: symbol.st_type() == elf::STT_GNU_IFUNC,
+                size: symbol.st_size(LittleEndian),
+            });
+        }
+
+        let name = input.symbols.symbol_name(LittleEndian, symbol)?;
+        let global = &self.globals[name];
+        Ok(match global.target {
+            Some(target) => Resolved {
+                target: Some(target),
+                ifunc: global.symbol_type == elf::STT_GNU_IFUNC,
+                size: global.size,
+            },
+            None => Resolved {
+                target: Some(Target::Linker(name)).filter(|_| self.is_linker_symbol(name)),
+                ifunc: false,
+                size: 0,
+            },
+        })
+    }
+
+    // Relocation sections that apply to placed sections, with the index of the section
+    fn relocations(&self, file: usize) -> Result<Vec<(usize, Relocations<'a>)>, LinkError> {
+        let input = &self.files[file];
+        let mut relocations = vec![];
+        for section in input.sections.iter() {
+            if let Some((relas, _)) = section.rela(LittleEndian, input.data)? {
+                let target = section.sh_info(LittleEndian) as usize;
+                if input.placements.get(target).copied().flatten().is_some() {
+                    relocations.push((target, relas));
+                }
+            }
+        }
+        Ok(relocations)
+    }
+
+    // Create GOT and PLT entries for the relocations that need them
+    fn scan_relocations(&mut self, order: &[usize]) -> Result<(), LinkError> {
+        for &file in order {
+            for (_, relas) in self.relocations(file)? {
+                for rela in relas {
+                    let resolved = self.resolve(file, rela.r_sym(LittleEndian, false) as usize)?;
+                    if resolved.ifunc && !self.plt_indices.contains_key(&resolved.target) {
+                        self.plt_indices
+                            .insert(resolved.target, self.plt.len() as u64);
+                        self.plt.push(resolved.target);
+                    }
+                    let entry = match rela.r_type(LittleEndian, false) {
+                        elf::R_X86_64_GOTPCREL
+                        | elf::R_X86_64_GOTPCRELX
+                        | elf::R_X86_64_REX_GOTPCRELX => GotEntry::Address(resolved.target),
+                        elf::R_X86_64_GOTTPOFF => GotEntry::TpOff(resolved.target),
+                        _ => continue,
+                    };
+                    if !self.got_offsets.contains_key(&entry) {
+                        self.got_offsets.insert(entry, self.got.len() as u64 * 8);
+                        self.got.push(entry);
+                    }
+                }
+            }
+        }
+
+        let got_size = self.got.len() as u64 * 8;
+        let plt_count = self.plt.len() as u64;
+        let mut common_size = 0;
+        let mut common_align = 1;
+        for (size, align) in &mut self.commons {
+            let offset = align_up(common_size, *align);
+            common_size = offset + *size;
+            common_align = common_align.max(*align);
+            // From now on, the size is the offset in the synthetic section
+            *size = offset;
+        }
+
+        let outputs = &mut self.outputs;
+        let mut add_synthetic = |name: &[u8], flags: u32, size: u64, synthetic: Synthetic| {
+            outputs.push(OutputSection {
+                name: name.to_vec(),
+                flags: u64::from(flags),
+                nobits: synthetic == Synthetic::Common,
+                align: 8,
+                size,
+                address: 0,
+                offset: 0,
+                inputs: vec![],
+                synthetic: Some(synthetic),
+            })
+        };
+        add_synthetic(b".got", elf::SHF_WRITE, got_size, Synthetic::Got);
+        add_synthetic(
+            b".iplt",
+            elf::SHF_EXECINSTR,
+            plt_count * PLT_ENTRY_SIZE,
+            Synthetic::Plt,
+        );
+        add_synthetic(
+            b".got.iplt",
+            elf::SHF_WRITE,
+            plt_count * 8,
+            Synthetic::PltGot,
+        );
+        add_synthetic(
+            b".rela.iplt",
+            0,
+            plt_count * std::mem::size_of::<elf::Rela64<LittleEndian>>() as u64,
+            Synthetic::RelaPlt,
+        );
+        add_synthetic(b"COMMON", elf::SHF_WRITE, common_size, Synthetic::Common);
+        outputs.last_mut().unwrap().align = common_align;
+        Ok(())
+    }
+
+    // Assign addresses to output sections. Returns the program headers
+    fn layout(&mut self) -> Vec<elf::ProgramHeader64<LittleEndian>> {
+        let mut order: Vec<usize> = (0..self.outputs.len()).collect();
+        order.sort_by_key(|&output| self.outputs[output].rank());
+
+        let has_tls = self
+            .outputs
+            .iter()
+            .any(|output| output.flags & u64::from(elf::SHF_TLS) != 0);
+        let header_count = 4 + has_tls as u64;
+        let headers_size = std::mem::size_of::<elf::FileHeader64<LittleEndian>>() as u64
+            + header_count * std::mem::size_of::<elf::ProgramHeader64<LittleEndian>>() as u64;
+
+        let tls_align = self
+            .outputs
+            .iter()
+            .filter(|output| output.flags & u64::from(elf::SHF_TLS) != 0)
+            .map(|output| output.align)
+            .max()
+            .unwrap_or(1);
+        let mut tls_start = None;
+        let mut tls_end = 0;
+        let mut tls_file_size = 0;
+
+        let mut headers = vec![];
+        let mut offset = headers_size;
+        for (segment, flags) in [
+            (Segment::ReadOnly, elf::PF_R),
+            (Segment::Code, elf::PF_R | elf::PF_X),
+            (Segment::Data, elf::PF_R | elf::PF_W),
+        ] {
+            let start = if segment == Segment::ReadOnly {
+                0
+            } else {
+                align_up(offset, PAGE_SIZE)
+            };
+            offset = start.max(offset);
+            let mut memory_end = BASE_ADDRESS + offset;
+            for &output in &order {
+                let section = &mut self.outputs[output];
+                if section.segment() != segment {
+                    continue;
+                }
+                let tls = section.flags & u64::from(elf::SHF_TLS) != 0;
+                let align = if tls { tls_align } else { section.align };
+                if tls && section.nobits {
+                    // The TLS template ends here, and the following sections overlap it
+                    section.address = align_up(memory_end, section.align);
+                    tls_start.get_or_insert(section.address);
+                    tls_end = section.address + section.size;
+                } else if section.nobits && segment == Segment::Data {
+                    section.address = align_up(memory_end, align);
+                    memory_end = section.address + section.size;
+                } else {
+                    offset = align_up(offset, align);
+                    section.offset = offset;
+                    section.address = BASE_ADDRESS + offset;
+                    offset += section.size;
+                    memory_end = BASE_ADDRESS + offset;
+                    if tls {
+                        tls_start.get_or_insert(section.address);
+                        tls_end = memory_end;
+                        tls_file_size = memory_end - tls_start.unwrap();
+                    }
+                }
+            }
+            headers.push(program_header(
+                elf::PT_LOAD,
+                flags,
+                start,
+                BASE_ADDRESS + start,
+                offset - start,
+                memory_end - (BASE_ADDRESS + start),
+                PAGE_SIZE,
+            ));
+        }
+
+        if let Some(tls_start) = tls_start {
+            self.tls = (tls_start, tls_end - tls_start, tls_align);
+            headers.push(program_header(
+                elf::PT_TLS,
+                elf::PF_R,
+                tls_start - BASE_ADDRESS,
+                tls_start,
+                tls_file_size,
+                tls_end - tls_start,
+                tls_align,
+            ));
+        }
+        headers.push(program_header(
+            elf::PT_GNU_STACK,
+            elf::PF_R | elf::PF_W,
+            0,
+            0,
+            0,
+            0,
+            16,
+        ));
+        headers
+    }
+
+    fn define_linker_symbols(&mut self, headers: &[elf::ProgramHeader64<LittleEndian>]) {
+        let code = &headers[1];
+        let data = &headers[2];
+        let code_end = code.p_vaddr.get(LittleEndian) + code.p_memsz.get(LittleEndian);
+        let data_start = data.p_vaddr.get(LittleEndian);
+        let data_file_end = data_start + data.p_filesz.get(LittleEndian);
+        let data_end = data_start + data.p_memsz.get(LittleEndian);
+
+        let mut symbols: Vec<(&'static [u8], u64)> = vec![
+            (b"__ehdr_start", BASE_ADDRESS),
+            (b"__executable_start", BASE_ADDRESS),
+            (b"etext", code_end),
+            (b"_etext", code_end),
+            (b"__etext", code_end),
+            (b"edata", data_file_end),
+            (b"_edata", data_file_end),
+            (b"__bss_start", data_file_end),
+            (b"end", data_end),
+            (b"_end", data_end),
+        ];
+        let bounds = |name: &[u8], synthetic| {
+            self.outputs
+                .iter()
+                .find(|output| output.synthetic == synthetic && output.name == name)
+                .map_or((data_start, data_start), |output| {
+                    (output.address, output.address + output.size)
+                })
+        };
+        let got = bounds(b".got", Some(Synthetic::Got));
+        let rela_iplt = bounds(b".rela.iplt", Some(Synthetic::RelaPlt));
+        let preinit_array = bounds(b".preinit_array", None);
+        let init_array = bounds(b".init_array", None);
+        let fini_array = bounds(b".fini_array", None);
+        symbols.extend_from_slice(&[
+            (b"_GLOBAL_OFFSET_TABLE_", got.0),
+            (b"__rela_iplt_start", rela_iplt.0),
+            (b"__rela_iplt_end", rela_iplt.1),
+            (b"__preinit_array_start", preinit_array.0),
+            (b"__preinit_array_end", preinit_array.1),
+            (b"__init_array_start", init_array.0),
+            (b"__init_array_end", init_array.1),
+            (b"__fini_array_start", fini_array.0),
+            (b"__fini_array_end", fini_array.1),
+        ]);
+        self.linker_symbols.extend(symbols);
+
+        let globals = &self.globals;
+        let section_bounds = self
+            .outputs
+            .iter()
+            .filter(|output| output.synthetic.is_none() && is_c_identifier(&output.name));
+        for output in section_bounds {
+            for (prefix, address) in [
+                (&b"__start_"[..], output.address),
+                (b"__stop_", output.address + output.size),
+            ] {
+                let name = [prefix, &output.name].concat();
+                if let Some((&name, _)) = globals.get_key_value(&name[..]) {
+                    self.linker_symbols.insert(name, address);
+                }
+            }
+        }
+    }
+
+    fn address(&self, target: Option<Target<'a>>) -> u64 {
+        match target {
+            None => 0,
+            Some(Target::Section {
+                file,
+                section,
+                value,
+            }) => self.files[file]
+                .placements
+                .get(section)
+                .copied()
+                .flatten()
+                .map_or(0, |(output, offset)| {
+                    self.outputs[output].address + offset + value
+                }),
+            Some(Target::Absolute(value)) => value,
+            Some(Target::Common(common)) => {
+                let output = self
+                    .outputs
+                    .iter()
+                    .find(|output| output.synthetic == Some(Synthetic::Common))
+                    .unwrap();
+                output.address + self.commons[common].0
+            }
+            Some(Target::Linker(name)) => self.linker_symbols.get(name).copied().unwrap_or(0),
+        }
+    }
+
+    fn synthetic_address(&self, synthetic: Synthetic) -> u64 {
+        self.outputs
+            .iter()
+            .find(|output| output.synthetic == Some(synthetic))
+            .unwrap()
+            .address
+    }
+
+    // Address that references to the symbol use. Indirect functions are called through the PLT
+    fn symbol_address(&self, resolved: &Resolved<'a>) -> u64 {
+        if resolved.ifunc {
+            self.synthetic_address(Synthetic::Plt)
+                + self.plt_indices[&resolved.target] * PLT_ENTRY_SIZE
+        } else {
+            self.address(resolved.target)
+        }
+    }
+
+    fn tp_offset(&self, address: u64) -> u64 {
+        let (start, size, align) = self.tls;
+        address.wrapping_sub(start + align_up(size, align))
+    }
+
+    fn write_sections(&self, out: &mut [u8]) -> Result<(), LinkError> {
+        for output in &self.outputs {
+            if output.nobits {
+                continue;
+            }
+            for &(file, section) in &output.inputs {
+                let input = &self.files[file];
+                let header = input.sections.section(object::SectionIndex(section))?;
+                if header.sh_type(LittleEndian) == elf::SHT_NOBITS {
+                    continue;
+                }
+                let data = header.data(LittleEndian, input.data)?;
+                let (_, offset) = input.placements[section].unwrap();
+                let start = (output.offset + offset) as usize;
+                out[start..start + data.len()].copy_from_slice(data);
+            }
+        }
+
+        let got = self.synthetic_address(Synthetic::Got);
+        for (entry, offset) in &self.got_offsets {
+            let start = (got - BASE_ADDRESS + offset) as usize;
+            let value = match *entry {
+                GotEntry::Address(target) => self.symbol_address(&Resolved {
+                    target,
+                    ifunc: self.plt_indices.contains_key(&target),
+                    size: 0,
+                }),
+                GotEntry::TpOff(target) => self.tp_offset(self.address(target)),
+            };
+            Endian::Little.write(&mut out[start..], value);
+        }
+
+        let plt = self.synthetic_address(Synthetic::Plt);
+        let plt_got = self.synthetic_address(Synthetic::PltGot);
+        let rela_plt = self.synthetic_address(Synthetic::RelaPlt);
+        for (i, &target) in self.plt.iter().enumerate() {
+            let i = i as u64;
+            // jmp *slot(%rip), then a two-byte nop
+            let entry = plt + i * PLT_ENTRY_SIZE;
+            let slot = plt_got + i * 8;
+            let displacement = (slot.wrapping_sub(entry + 6) as i64) as i32;
+            let start = (entry - BASE_ADDRESS) as usize;
+            out[start..start + 2].copy_from_slice(&[0xFF, 0x25]);
+            Endian::Little.write(&mut out[start + 2..], displacement);
+            out[start + 6..start + 8].copy_from_slice(&[0x66, 0x90]);
+
+            let rela = elf::Rela64 {
+                r_offset: U64::new(LittleEndian, slot),
+                r_info: U64::new(LittleEndian, u64::from(elf::R_X86_64_IRELATIVE)),
+                r_addend: I64::new(LittleEndian, self.address(target) as i64),
+            };
+            let size = std::mem::size_of::<elf::Rela64<LittleEndian>>();
+            let start = (rela_plt - BASE_ADDRESS) as usize + i as usize * size;
+            out[start..start + size].copy_from_slice(object::bytes_of(&rela));
+        }
+        Ok(())
+    }
+
+    fn apply_relocations(&self, order: &[usize], out: &mut [u8]) -> Result<(), LinkError> {
+        let got = self.synthetic_address(Synthetic::Got);
+        for &file in order {
+            for (section, relas) in self.relocations(file)? {
+                let (output, offset) = self.files[file].placements[section].unwrap();
+                let output = &self.outputs[output];
+                if output.nobits {
+                    continue;
+                }
+                let code = self.files[file]
+                    .sections
+                    .section(object::SectionIndex(section))?
+                    .sh_flags(LittleEndian)
+                    & u64::from(elf::SHF_EXECINSTR)
+                    != 0;
+                let mut relas = relas.iter();
+                while let Some(rela) = relas.next() {
+                    let r_type = rela.r_type(LittleEndian, false);
+                    let resolved = self.resolve(file, rela.r_sym(LittleEndian, false) as usize)?;
+                    let s = self.symbol_address(&resolved) as i64;
+                    let a = rela.r_addend(LittleEndian);
+                    let p = (output.address + offset + rela.r_offset(LittleEndian)) as i64;
+                    let got_entry = |entry| got as i64 + self.got_offsets[&entry] as i64;
+                    let tls_start = self.tls.0 as i64;
+                    let (value, size) = match r_type {
+                        elf::R_X86_64_NONE => continue,
+                        elf::R_X86_64_64 => (s.wrapping_add(a), 8),
+                        elf::R_X86_64_PC64 => (s.wrapping_add(a).wrapping_sub(p), 8),
+                        elf::R_X86_64_PC32 | elf::R_X86_64_PLT32 => {
+                            (s.wrapping_add(a).wrapping_sub(p), -4)
+                        }
+                        elf::R_X86_64_32 => (s.wrapping_add(a), 4),
+                        elf::R_X86_64_32S => (s.wrapping_add(a), -4),
+                        elf::R_X86_64_GOTPCREL
+                        | elf::R_X86_64_GOTPCRELX
+                        | elf::R_X86_64_REX_GOTPCRELX => {
+                            (got_entry(GotEntry::Address(resolved.target)) + a - p, -4)
+                        }
+                        elf::R_X86_64_GOTTPOFF => {
+                            (got_entry(GotEntry::TpOff(resolved.target)) + a - p, -4)
+                        }
+                        elf::R_X86_64_TLSGD | elf::R_X86_64_TLSLD => {
+                            let start =
+                                (output.offset + offset + rela.r_offset(LittleEndian)) as usize;
+                            let call = relas.next().map(|call| {
+                                call.r_offset(LittleEndian) - rela.r_offset(LittleEndian)
+                            });
+                            relax_tls(out, start, r_type, self.tp_offset(s as u64), call)?;
+                            continue;
+                        }
+                        elf::R_X86_64_TPOFF32 => {
+                            ((self.tp_offset(s as u64) as i64).wrapping_add(a), -4)
+                        }
+                        elf::R_X86_64_TPOFF64 => {
+                            ((self.tp_offset(s as u64) as i64).wrapping_add(a), 8)
+                        }
+                        // Code that used `__tls_get_addr` for the module is relaxed already
+                        elf::R_X86_64_DTPOFF32 if code => {
+                            ((self.tp_offset(s as u64) as i64).wrapping_add(a), -4)
+                        }
+                        elf::R_X86_64_DTPOFF32 => (s - tls_start + a, -4),
+                        elf::R_X86_64_DTPOFF64 => (s - tls_start + a, 8),
+                        elf::R_X86_64_GOTPC32 => (got as i64 + a - p, -4),
+                        elf::R_X86_64_GOTOFF64 => (s + a - got as i64, 8),
+                        elf::R_X86_64_SIZE32 => (resolved.size as i64 + a, 4),
+                        elf::R_X86_64_SIZE64 => (resolved.size as i64 + a, 8),
+                        _ => {
+                            return Err(LinkError(format!(
+                                "unsupported relocation type {}",
+                                r_type
+                            )))
+                        }
+                    };
+
+                    // Negative sizes are for signed 32-bit values
+                    let start = (output.offset + offset + rela.r_offset(LittleEndian)) as usize;
+                    let out = &mut out[start..];
+                    let in_range = match size {
+                        8 => {
+                            Endian::Little.write(out, value);
+                            true
+                        }
+                        4 => u32::try_from(value)
+                            .map(|value| Endian::Little.write(out, value))
+                            .is_ok(),
+                        _ => i32::try_from(value)
+                            .map(|value| Endian::Little.write(out, value))
+                            .is_ok(),
+                    };
+                    if !in_range {
+                        return Err(LinkError(format!(
+                            "relocation type {} out of range",
+                            r_type
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replace a general or local dynamic TLS access at the relocation with a local exec one.
+// The call to `__tls_get_addr` after it is replaced too. Its relocation is `call` bytes after.
+fn relax_tls(
+    out: &mut [u8],
+    start: usize,
+    r_type: u32,
+    tp_offset: u64,
+    call: Option<u64>,
+) -> Result<(), LinkError> {
+    let tp_offset = i32::try_from(tp_offset as i64)
+        .map_err(|_| LinkError("TLS offset out of range".to_owned()))?;
+    // Bytes before the relocation, and the offset of the call relocation from the start
+    let (original, call_offset, relaxed): (&[u8], usize, Vec<u8>) = if r_type == elf::R_X86_64_TLSGD
+    {
+        // data16 lea x@tlsgd(%rip), %rdi; data16 data16 rex64 call __tls_get_addr
+        // -> mov %fs:0, %rax; lea x@tpoff(%rax), %rax
+        let mut relaxed = vec![0x64, 0x48, 0x8B, 0x04, 0x25, 0, 0, 0, 0, 0x48, 0x8D, 0x80];
+        relaxed.extend_from_slice(&Endian::Little.bytes(tp_offset));
+        (&[0x66, 0x48, 0x8D, 0x3D], 12, relaxed)
+    } else {
+        // lea x@tlsld(%rip), %rdi; call __tls_get_addr
+        // -> data16 data16 data16 mov %fs:0, %rax
+        let relaxed = vec![0x66, 0x66, 0x66, 0x64, 0x48, 0x8B, 0x04, 0x25, 0, 0, 0, 0];
+        (&[0x48, 0x8D, 0x3D], 8, relaxed)
+    };
+    let code_start = start.checked_sub(original.len());
+    let code = code_start.and_then(|code_start| out.get(code_start..code_start + relaxed.len()));
+    let matched = code
+        .is_some_and(|code| code.starts_with(original) && code[call_offset - 1] == 0xE8)
+        && call == Some((call_offset - original.len()) as u64);
+    if !matched {
+        return Err(LinkError(format!(
+            "unsupported code sequence for relocation type {}",
+            r_type
+        )));
+    }
+    let code_start = code_start.unwrap();
+    out[code_start..code_start + relaxed.len()].copy_from_slice(&relaxed);
+    Ok(())
+}
+
+fn program_header(
+    p_type: u32,
+    p_flags: u32,
+    offset: u64,
+    address: u64,
+    file_size: u64,
+    memory_size: u64,
+    align: u64,
+) -> elf::ProgramHeader64<LittleEndian> {
+    elf::ProgramHeader64 {
+        p_type: U32::new(LittleEndian, p_type),
+        p_flags: U32::new(LittleEndian, p_flags),
+        p_offset: U64::new(LittleEndian, offset),
+        p_vaddr: U64::new(LittleEndian, address),
+        p_paddr: U64::new(LittleEndian, address),
+        p_filesz: U64::new(LittleEndian, file_size),
+        p_memsz: U64::new(LittleEndian, memory_size),
+        p_align: U64::new(LittleEndian, align),
+    }
+}
+
+// Link the object with the runtime into the content of a static executable
+pub fn link_executable<'a>(object: &'a [u8], runtime: &Runtime<'a>) -> Result<Vec<u8>, LinkError> {
+    let mut linker = Linker {
+        files: vec![],
+        globals: HashMap::new(),
+        groups: HashSet::new(),
+        commons: vec![],
+        outputs: vec![],
+        got: vec![],
+        got_offsets: HashMap::new(),
+        plt: vec![],
+        plt_indices: HashMap::new(),
+        tls: (0, 0, 1),
+        linker_symbols: HashMap::new(),
+    };
+    for &file in runtime.start_files.iter().chain([&object]) {
+        linker.load(file)?;
+    }
+    let first_end_file = linker.files.len();
+    for &file in &runtime.end_files {
+        linker.load(file)?;
+    }
+    let end_files = first_end_file..linker.files.len();
+    let members = linker.load_archives(&runtime.archives)?;
+
+    // Sections of the end files, like the end of `.init` in crtn.o, go last
+    let order: Vec<usize> = (0..first_end_file)
+        .chain(members)
+        .chain(end_files)
+        .collect();
+    linker.place_sections(&order)?;
+
+    let mut undefined: Vec<&[u8]> = linker
+        .globals
+        .iter()
+        .filter(|&(&name, global)| {
+            // Calls to `__tls_get_addr` are replaced when TLS accesses are relaxed
+            global.target.is_none()
+                && global.referenced
+                && !linker.is_linker_symbol(name)
+                && name != b"__tls_get_addr"
+        })
+        .map(|(&name, _)| name)
+        .collect();
+    if !undefined.is_empty() {
+        undefined.sort_unstable();
+        let names: Vec<_> = undefined
+            .iter()
+            .map(|name| format!("`{}`", String::from_utf8_lossy(name)))
+            .collect();
+        return Err(LinkError(format!("undefined symbols {}", names.join(", "))));
+    }
+
+    linker.scan_relocations(&order)?;
+    let headers = linker.layout();
+    linker.define_linker_symbols(&headers);
+
+    let entry = linker
+        .globals
+        .get(&b"_start"[..])
+        .and_then(|global| global.target)
+        .ok_or_else(|| LinkError("undefined entry point `_start`".to_owned()))?;
+    let file_size = headers[2].p_offset.get(LittleEndian) + headers[2].p_filesz.get(LittleEndian);
+    let mut out = vec![0; file_size as usize];
+    linker.write_sections(&mut out)?;
+    linker.apply_relocations(&order, &mut out)?;
+
+    let file_header = elf::FileHeader64 {
+        e_ident: elf::Ident {
+            magic: elf::ELFMAG,
+            class: elf::ELFCLASS64,
+            data: elf::ELFDATA2LSB,
+            version: elf::EV_CURRENT,
+            os_abi: elf::ELFOSABI_NONE,
+            abi_version: 0,
+            padding: [0; 7],
+        },
+        e_type: U16::new(LittleEndian, elf::ET_EXEC),
+        e_machine: U16::new(LittleEndian, elf::EM_X86_64),
+        e_version: U32::new(LittleEndian, u32::from(elf::EV_CURRENT)),
+        e_entry: U64::new(LittleEndian, linker.address(Some(entry))),
+        e_phoff: U64::new(
+            LittleEndian,
+            std::mem::size_of::<elf::FileHeader64<LittleEndian>>() as u64,
+        ),
+        e_shoff: U64::new(LittleEndian, 0),
+        e_flags: U32::new(LittleEndian, 0),
+        e_ehsize: U16::new(
+            LittleEndian,
+            std::mem::size_of::<elf::FileHeader64<LittleEndian>>() as u16,
+        ),
+        e_phentsize: U16::new(
+            LittleEndian,
+            std::mem::size_of::<elf::ProgramHeader64<LittleEndian>>() as u16,
+        ),
+        e_phnum: U16::new(LittleEndian, headers.len() as u16),
+        e_shentsize: U16::new(LittleEndian, 0),
+        e_shnum: U16::new(LittleEndian, 0),
+        e_shstrndx: U16::new(LittleEndian, 0),
+    };
+    let mut header_bytes = object::bytes_of(&file_header).to_vec();
+    for header in &headers {
+        header_bytes.extend_from_slice(object::bytes_of(header));
+    }
+    out[..header_bytes.len()].copy_from_slice(&header_bytes);
+    Ok(out)
+}
@@ -1,4 +1,4 @@
-use gimli::{write::*, *};
+use gimli::{constants, write::*, LittleEndian, SectionId};
 
 #[derive(Clone)]
 pub struct DwarfReloc {
@@ -0,0 +1,228 @@
+// Orders procedures in the text section so that each caller sits next to the procedures it calls
+// most, keeping hot call paths within fewer cache lines and pages.
+//
+// Procedures are merged into clusters along the heaviest calls first, in the manner of
+// Pettis and Hansen: the two clusters of a call are joined end to end, in the orientation that
+// places the caller and the callee closest. The weights count call sites by default, but any
+// measured call counts can take their place.
+
+use super::*;
+use std::cmp::Reverse;
+
+// Affinity of procedure pairs, keyed by (caller, callee)
+pub type CallWeights = HashMap<(String, String), u64>;
+
+fn is_procedure(chunk: &Chunk) -> bool {
+    matches!(chunk.extra, ChunkExtra::Procedure(_))
+}
+
+// Count the links from each procedure to other procedures in the same unit
+pub fn static_call_weights(chunks: &[Chunk]) -> CallWeights {
+    let procedures: HashSet<&str> = chunks
+        .iter()
+        .filter(|chunk| is_procedure(chunk))
+        .map(|chunk| chunk.name.as_str())
+        .collect();
+    let mut weights = CallWeights::new();
+    for chunk in chunks.iter().filter(|chunk| is_procedure(chunk)) {
+        for link in &chunk.links {
+            if let ChunkLinkTarget::Symbol(target, _) = &link.to {
+                if *target != chunk.name && procedures.contains(target.as_str()) {
+                    *weights
+                        .entry((chunk.name.clone(), target.clone()))
+                        .or_default() += 1;
+                }
+            }
+        }
+    }
+    weights
+}
+
+// Reorder the procedure chunks among the positions they already take. Data chunks stay in place.
+// Ties between calls of the same weight, and clusters with no calls between them, keep the
+// original order
+pub fn reorder_procedures(chunks: &mut Vec<Chunk>, weights: &CallWeights) {
+    let slots: Vec<usize> = (0..chunks.len())
+        .filter(|&i| is_procedure(&chunks[i]))
+        .collect();
+    let index: HashMap<&str, usize> = slots
+        .iter()
+        .enumerate()
+        .map(|(p, &i)| (chunks[i].name.as_str(), p))
+        .collect();
+
+    // Calls in either direction add up to the affinity of a pair
+    let mut edges: HashMap<(usize, usize), u64> = HashMap::new();
+    for ((caller, callee), &weight) in weights {
+        if let (Some(&a), Some(&b)) = (index.get(caller.as_str()), index.get(callee.as_str())) {
+            if a != b && weight != 0 {
+                *edges.entry((a.min(b), a.max(b))).or_default() += weight;
+            }
+        }
+    }
+    let mut edges: Vec<((usize, usize), u64)> = edges.into_iter().collect();
+    edges.sort_unstable_by_key(|&((a, b), weight)| (Reverse(weight), a, b));
+
+    // Each cluster is kept at the index of its first procedure in the original order
+    let mut cluster_of: Vec<usize> = (0..slots.len()).collect();
+    let mut clusters: Vec<Vec<usize>> = (0..slots.len()).map(|p| vec![p]).collect();
+    for ((a, b), _) in edges {
+        let (cluster_a, cluster_b) = (cluster_of[a], cluster_of[b]);
+        if cluster_a == cluster_b {
+            continue;
+        }
+        let first = std::mem::take(&mut clusters[cluster_a]);
+        let second = std::mem::take(&mut clusters[cluster_b]);
+        let position = |cluster: &[usize], p| cluster.iter().position(|&q| q == p).unwrap();
+        let forward = first.len() - position(&first, a) + position(&second, b);
+        let backward = second.len() - position(&second, b) + position(&first, a);
+        let merged = if forward <= backward {
+            [first, second].concat()
+        } else {
+            [second, first].concat()
+        };
+        let target = cluster_a.min(cluster_b);
+        for &p in &merged {
+            cluster_of[p] = target;
+        }
+        clusters[target] = merged;
+    }
+
+    let mut sources = clusters.into_iter().flatten().map(|p| slots[p]);
+    let mut old: Vec<Option<Chunk>> = chunks.drain(..).map(Some).collect();
+    let mut slot = slots.iter().peekable();
+    for i in 0..old.len() {
+        let from = if slot.peek() == Some(&&i) {
+            slot.next();
+            sources.next().unwrap()
+        } else {
+            i
+        };
+        chunks.push(old[from].take().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn procedure_names(chunks: &[Chunk]) -> Vec<String> {
+        chunks
+            .iter()
+            .filter(|chunk| is_procedure(chunk))
+            .map(|chunk| chunk.name.clone())
+            .collect()
+    }
+
+    fn code_set(source: &str) -> CodeSet {
+        let (source_path, ast) = test_util::check_source(source);
+        std::fs::remove_file(&source_path).unwrap();
+        x64::gen_code_set(
+            ast,
+            Unit::whole_program(),
+            Default::default(),
+            Platform::Linux,
+        )
+    }
+
+    #[test]
+    fn permutation() {
+        let mut code_set = code_set(
+            "
+class A(object):
+    x:int = 1
+    def f(self:\"A\", n:int) -> int:
+        def g(m:int) -> int:
+            return m + self.x
+        return g(n) + g(n + 1)
+def h(a:A) -> int:
+    return a.f(len(\"ab\"))
+print(h(A()))
+print([1, 2] + [3])
+",
+        );
+        let before: Vec<(String, bool)> = code_set
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.name.clone(), is_procedure(chunk)))
+            .collect();
+        let weights = static_call_weights(&code_set.chunks);
+        assert!(!weights.is_empty());
+        reorder_procedures(&mut code_set.chunks, &weights);
+
+        // Data chunks keep their place, and procedures only move among procedure positions
+        assert_eq!(code_set.chunks.len(), before.len());
+        for (chunk, (name, procedure)) in code_set.chunks.iter().zip(&before) {
+            assert_eq!(is_procedure(chunk), *procedure);
+            if !procedure {
+                assert_eq!(chunk.name, *name);
+            }
+        }
+        let mut after = procedure_names(&code_set.chunks);
+        after.sort_unstable();
+        let mut expected: Vec<String> = before
+            .iter()
+            .filter(|(_, procedure)| *procedure)
+            .map(|(name, _)| name.clone())
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(after, expected);
+    }
+
+    #[test]
+    fn hot_pair_adjacent() {
+        let mut code_set = code_set(
+            "
+def hot(n:int) -> int:
+    return cold(n) + cold(n + 1) + cold(n + 2) + cold(n + 3)
+def b() -> int:
+    return 1
+def c() -> int:
+    return b()
+def d() -> int:
+    return 2
+def cold(n:int) -> int:
+    return n * 2
+print(hot(1) + c() + d())
+",
+        );
+        let before = procedure_names(&code_set.chunks);
+        let weights = static_call_weights(&code_set.chunks);
+        assert_eq!(weights[&("hot".to_owned(), "cold".to_owned())], 4);
+        assert_eq!(weights[&("c".to_owned(), "b".to_owned())], 1);
+        reorder_procedures(&mut code_set.chunks, &weights);
+
+        let after = procedure_names(&code_set.chunks);
+        let position = |name: &str| after.iter().position(|n| n == name).unwrap();
+        assert_eq!(position("cold"), position("hot") + 1);
+        assert_ne!(after, before);
+
+        // Without weights, the original order is kept
+        reorder_procedures(&mut code_set.chunks, &CallWeights::new());
+        assert_eq!(procedure_names(&code_set.chunks), after);
+    }
+
+    #[test]
+    fn same_behavior() {
+        let source = "
+def fib(n:int) -> int:
+    if n < 2:
+        return n
+    return fib(n - 1) + fib(n - 2)
+class Counter(object):
+    n:int = 0
+    def add(self:\"Counter\", k:int) -> \"Counter\":
+        self.n = self.n + fib(k)
+        return self
+print(Counter().add(10).add(fib(5)).n)
+";
+        let options = CompileOptions {
+            reorder_functions: true,
+            ..Default::default()
+        };
+        if let Some(output) = test_util::run_with_options(source, "", options) {
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "60\n");
+        }
+    }
+}
@@ -1,15 +1,29 @@
+mod abi;
+mod archive;
 mod codeview;
 mod debug;
 mod dwarf;
+#[cfg(any(test, feature = "embedded-runtime"))]
+mod elf_link;
 mod gimli_writer;
+mod layout;
+mod target;
+#[cfg(test)]
+pub mod test_util;
+mod wasm;
 mod x64;
 
 use crate::local_env::*;
 use crate::node::*;
+use chocopy_rs_common::temp;
 use debug::*;
-use object::{write::*, *};
+use object::{
+    write::*, BinaryFormat, RelocationEncoding, RelocationKind, SectionKind, SymbolFlags,
+    SymbolKind, SymbolScope,
+};
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::*;
 use std::ffi::OsStr;
 use std::io::Write;
@@ -24,29 +38,131 @@ const STR_PROTOTYPE: &str = "str.$proto";
 const BOOL_LIST_PROTOTYPE: &str = "[bool].$proto";
 const INT_LIST_PROTOTYPE: &str = "[int].$proto";
 const OBJECT_LIST_PROTOTYPE: &str = "[object].$proto";
+// Prototype of function values (extension)
+const FUNC_PROTOTYPE: &str = "<function>.$proto";
 
 // Standard library function symboles
 const BUILTIN_ALLOC_OBJ: &str = "$alloc_obj";
+const BUILTIN_INVALID_ARG: &str = "$invalid_arg";
 const BUILTIN_DIV_ZERO: &str = "$div_zero";
 const BUILTIN_OUT_OF_BOUND: &str = "$out_of_bound";
 const BUILTIN_NONE_OP: &str = "$none_op";
 const BUILTIN_LEN: &str = "$len";
+const BUILTIN_OBJ_ID: &str = "$obj_id";
+const BUILTIN_COLLECT: &str = "$collect";
+const BUILTIN_SEED: &str = "$seed";
+const BUILTIN_RANDINT: &str = "$randint";
+const BUILTIN_LIST_APPEND: &str = "$list_append";
+const BUILTIN_TRUTHY: &str = "$truthy";
+const BUILTIN_OBJ_EQ: &str = "$obj_eq";
 const BUILTIN_INPUT: &str = "$input";
 const BUILTIN_PRINT: &str = "$print";
 const BUILTIN_INIT: &str = "$init";
+const BUILTIN_TRACE_LINE: &str = "$trace_line";
+const BUILTIN_CATCH_INTERRUPT: &str = "$catch_interrupt";
+const BUILTIN_INTERRUPTED: &str = "$interrupted";
+// A byte set by the runtime on Ctrl-C. The only data imported from the standard library
+const BUILTIN_INTERRUPT_FLAG: &str = "$interrupt_flag";
+
+// All standard library symbols referenced by a ChocoPy program
+const BUILTIN_IMPORTS: [&str; 20] = [
+    BUILTIN_ALLOC_OBJ,
+    BUILTIN_INVALID_ARG,
+    BUILTIN_DIV_ZERO,
+    BUILTIN_OUT_OF_BOUND,
+    BUILTIN_NONE_OP,
+    BUILTIN_LEN,
+    BUILTIN_OBJ_ID,
+    BUILTIN_COLLECT,
+    BUILTIN_SEED,
+    BUILTIN_RANDINT,
+    BUILTIN_LIST_APPEND,
+    BUILTIN_TRUTHY,
+    BUILTIN_OBJ_EQ,
+    BUILTIN_PRINT,
+    BUILTIN_INPUT,
+    BUILTIN_INIT,
+    BUILTIN_TRACE_LINE,
+    BUILTIN_CATCH_INTERRUPT,
+    BUILTIN_INTERRUPTED,
+    BUILTIN_INTERRUPT_FLAG,
+];
 
 // Program entry point symbol
 const BUILTIN_CHOCOPY_MAIN: &str = "$chocopy_main";
 
+// Prefix for symbols shared between object files of a multi-file build
+const UNIT_SYMBOL_PREFIX: &str = "$chocopy.";
+// Prefix of the system ABI entry points for constructors and methods, see `export_classes`
+const ENTRY_PREFIX: &str = "chocopy$";
+
 // Special data section symbols
 const GLOBAL_SECTION: &str = "$global";
 const INIT_PARAM: &str = "$init_param";
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Platform {
     Windows,
     Linux,
     Macos,
+    Wasm, // Written as a module by its own backend instead of as an object file
+}
+
+// Format of generated object files. Debug info follows the format:
+// CodeView for COFF and DWARF for ELF and Mach-O
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ObjectFormat {
+    Elf,
+    Coff,
+    MachO,
+}
+
+impl ObjectFormat {
+    // The format the linker of the platform expects
+    pub fn native(platform: Platform) -> ObjectFormat {
+        match platform {
+            Platform::Windows => ObjectFormat::Coff,
+            Platform::Linux => ObjectFormat::Elf,
+            Platform::Macos => ObjectFormat::MachO,
+            Platform::Wasm => unreachable!("WebAssembly modules are generated by gen::wasm"),
+        }
+    }
+
+    fn binary_format(self) -> BinaryFormat {
+        match self {
+            ObjectFormat::Elf => BinaryFormat::Elf,
+            ObjectFormat::Coff => BinaryFormat::Coff,
+            ObjectFormat::MachO => BinaryFormat::MachO,
+        }
+    }
+
+    // Segment, name and kind of the section holding all prototypes together. The ELF and COFF
+    // linkers place it as one piece within read-only data, and Mach-O keeps it as its own section
+    fn prototype_section(self) -> (&'static [u8], &'static [u8], SectionKind) {
+        match self {
+            ObjectFormat::Elf => (
+                b"",
+                b".data.rel.ro.chocopy_proto",
+                SectionKind::ReadOnlyDataWithRel,
+            ),
+            ObjectFormat::Coff => (b"", b".rdata$chocopy_proto", SectionKind::ReadOnlyData),
+            ObjectFormat::MachO => (
+                b"__DATA",
+                b"__chocopy_proto",
+                SectionKind::ReadOnlyDataWithRel,
+            ),
+        }
+    }
+}
+
+// Implicit runtime checks kept in generated code, from the fewest to all of them. Leaving out
+// a check makes the program crash or read invalid memory where it would have reported an error
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub enum RuntimeChecks {
+    Off,    // No checks on index bounds, None operands or division by zero
+    Bounds, // Only checks on index bounds
+    #[default]
+    All,
 }
 
 /// Type for debug info
@@ -86,6 +202,8 @@ impl TypeDebug {
                 type_debug.array_level += 1;
                 type_debug
             }
+            // Debuggers see function values as plain objects
+            TypeAnnotation::CallableType(_) => TypeDebug::class_type("object"),
         }
     }
 }
@@ -132,6 +250,7 @@ struct ProcedureDebug {
     params: Vec<VarDebug>,
     locals: Vec<VarDebug>,
     frame_size: u32, // Stack frame size, excluding saved ret and rbp.
+    frameless: bool, // rbp isn't saved, and the frame is addressed from rsp with -O2
 }
 
 impl ProcedureDebug {
@@ -159,6 +278,7 @@ enum ChunkLinkTarget {
 struct ChunkLink {
     pos: usize, // Relocation source location
     to: ChunkLinkTarget,
+    imm_size: u8, // Size of the immediate operand after a rip-relative displacement
 }
 
 // A piece of data with a symbol name
@@ -204,22 +324,72 @@ struct MethodDebug {
 // Class info for debug info
 #[derive(Clone)]
 struct ClassDebug {
-    size: u32, // Object size, excluding the object header
+    size: u32,           // Object size, excluding the object header
+    prototype_size: u32, // Including slots of methods that are not defined
     attributes: Vec<VarDebug>,
+    constants: Vec<VarDebug>, // Class constants defined by this class, stored in global section
     methods: BTreeMap<u32, (String, MethodDebug)>, // Map from prototype offset to (name, type)
 }
 
 impl ClassDebug {
     // Collect all types appeared in this class
     fn used_types(&self) -> impl Iterator<Item = &TypeDebug> {
-        self.attributes.iter().map(|attribute| &attribute.var_type)
+        self.attributes
+            .iter()
+            .chain(&self.constants)
+            .map(|attribute| &attribute.var_type)
     }
 }
 
+// The part of a multi-file program that an object file is generated for
+#[derive(Clone, Copy)]
+pub struct Unit {
+    pub external_declarations: usize, // Number of leading declarations defined in other units
+    pub main: bool,                   // Whether this unit provides the entry point
+    pub exported: bool,               // Whether top-level definitions are visible to other units
+}
+
+impl Unit {
+    // A whole program in a single object file
+    pub fn whole_program() -> Unit {
+        Unit {
+            external_declarations: 0,
+            main: true,
+            exported: false,
+        }
+    }
+}
+
+// Options that change the generated code without changing the program meaning.
+// Cached object files are keyed by the hash of all of them
+#[derive(Clone, Copy, Default, Hash)]
+pub struct CompileOptions {
+    pub trace_exec: bool, // Log each executed source line to stderr
+    pub opt_level: u32,   // 2 = inline tiny leaf functions, call some methods directly
+    pub strip: bool,      // Leave out symbols for definitions not visible to other objects
+    pub object_format: Option<ObjectFormat>, // Instead of the native format of the platform
+    pub no_debug: bool,   // Leave out debug info, but keep unwind info
+    pub export_classes: bool, // Add system ABI entry points for class constructors and methods
+    pub interruptible: bool, // Check for Ctrl-C at the end of each loop iteration
+    pub reorder_functions: bool, // Place procedures next to the procedures they call most
+    pub runtime_checks: RuntimeChecks,
+    pub group_prototypes: bool, // Put all prototypes in a section of their own, next to each other
+    pub class_eq: bool, // Compare objects of a user class attribute by attribute (extension)
+}
+
 // The generated ChocoPy program, without linking to other libraries
 struct CodeSet {
+    target: target::Target,
     chunks: Vec<Chunk>,
-    global_size: u64,             // Section size reserved for all global variables
+    imports: Vec<&'static str>, // External functions referenced by chunks
+    exports: Vec<String>,       // Chunks visible to other units
+    externs: Vec<String>,       // Symbols defined in other units
+    entries: Vec<String>,       // Chunks called from C code, visible under their own names
+    global_size: u64,           // Section size reserved for all global variables
+    strip: bool,                // Refer to local definitions by section and offset, without symbols
+    group_prototypes: bool,     // Put prototypes in their own section
+    object_format: Option<ObjectFormat>,
+    no_debug: bool,
     globals_debug: Vec<VarDebug>, // Debug info for global variables
     classes_debug: HashMap<String, ClassDebug>,
 }
@@ -240,14 +410,14 @@ impl CodeSet {
             .chain(self.globals_debug.iter().map(|global| &global.var_type))
             .chain(
                 self.classes_debug
-                    .iter()
-                    .flat_map(|(_, class)| class.used_types()),
+                    .values()
+                    .flat_map(|class| class.used_types()),
             )
     }
 
     // Collects all types appeared in the program, and returns representives
     // that have the highest array level.
-    fn used_types_representive(&self) -> impl Iterator<Item = TypeDebugRepresentive> {
+    fn used_types_representive(&self) -> impl Iterator<Item = TypeDebugRepresentive<'_>> {
         let mut array_level_map = HashMap::<&str, u32>::new();
         for type_used in self.used_types() {
             if let Some(array_level) = array_level_map.get_mut(type_used.core_name.as_str()) {
@@ -284,6 +454,61 @@ impl std::fmt::Display for ToolChainError {
 
 impl std::error::Error for ToolChainError {}
 
+#[derive(Debug)]
+struct SplitDebugError;
+
+impl std::fmt::Display for SplitDebugError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The debug file needs a UTF-8 file name to be found by debuggers"
+        )
+    }
+}
+
+impl std::error::Error for SplitDebugError {}
+
+#[derive(Debug)]
+struct VerifyError;
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Object file contains references to undefined symbols")
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+#[derive(Debug)]
+struct RelocationError {
+    chunk: String,
+    symbol: String,
+    addend: i64,
+}
+
+impl std::fmt::Display for RelocationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Relocation from `{}` to `{}` has an out-of-range addend {}",
+            self.chunk, self.symbol, self.addend
+        )
+    }
+}
+
+impl std::error::Error for RelocationError {}
+
+#[derive(Debug)]
+struct ObjectFileError(String);
+
+impl std::fmt::Display for ObjectFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ObjectFileError {}
+
 #[derive(Debug)]
 pub struct PathError;
 
@@ -295,73 +520,186 @@ impl std::fmt::Display for PathError {
 
 impl std::error::Error for PathError {}
 
-// Encode path string for Windows command line argument
+// Why a program compiled in memory has no executable
+#[cfg(any(test, feature = "embedded-runtime"))]
+#[derive(Debug)]
+pub enum Diagnostics {
+    Source(Vec<CompilerError>), // Errors found by the parser or the type checker
+    Executable(String),         // Errors from writing the object or linking it
+}
+
+#[cfg(any(test, feature = "embedded-runtime"))]
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostics::Source(errors) => {
+                write!(f, "Found {} errors in the source code", errors.len())
+            }
+            Diagnostics::Executable(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "embedded-runtime"))]
+impl std::error::Error for Diagnostics {}
+
+// Encode path string for a quoted command line argument in a batch file.
+// Quotes can't be in Windows file names, and control characters would end the line. Inside quotes,
+// cmd still expands percent signs, but not carets, parentheses or spaces. Backslashes before the
+// closing quote are doubled for the argument parsing of the program.
 fn windows_path_escape(path: &Path) -> std::result::Result<String, Box<dyn std::error::Error>> {
     let path = path.to_str().ok_or(PathError)?;
-
-    // TODO: actually escape the path
-    // For now we just forbid suspicious strings.
-    if path
-        .find(|c| matches!(c, '\"' | '\'' | '^') || c.is_control())
-        .is_some()
-        || path.ends_with('\\')
-    {
+    if path.contains(|c: char| c == '\"' || c.is_control()) {
         return Err(PathError.into());
     }
 
-    Ok(path.to_owned())
+    let mut escaped = path.replace('%', "%%");
+    let trailing = path.len() - path.trim_end_matches('\\').len();
+    escaped += &"\\".repeat(trailing);
+    Ok(escaped)
+}
+
+// Encode path string for the batch file run with `call`, which expands percent signs again and
+// doubles carets even inside quotes. The batch file comes from the toolchain, so instead of
+// escaping for that, such paths are rejected
+fn windows_call_escape(path: &Path) -> std::result::Result<String, Box<dyn std::error::Error>> {
+    let escaped = windows_path_escape(path)?;
+    if escaped.contains(['%', '^']) || escaped.ends_with('\\') {
+        return Err(PathError.into());
+    }
+    Ok(escaped)
 }
 
 // Generate object file
 pub fn gen_object(
     source_path: &str,
     ast: Program,
+    options: CompileOptions,
+    obj_path: &Path,
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    gen_unit_object(
+        source_path,
+        ast,
+        Unit::whole_program(),
+        options,
+        obj_path,
+        platform,
+    )
+}
+
+// Generate object file for one unit of a multi-file program
+pub fn gen_unit_object(
+    source_path: &str,
+    ast: Program,
+    unit: Unit,
+    options: CompileOptions,
+    obj_path: &Path,
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let code_set = gen_code_set(ast, unit, options, platform);
+    write_object(source_path, code_set, obj_path, platform)
+}
+
+// Generate machine code and debug info
+fn gen_code_set(ast: Program, unit: Unit, options: CompileOptions, platform: Platform) -> CodeSet {
+    let mut code_set = x64::gen_code_set(ast, unit, options, platform);
+    if options.reorder_functions {
+        let weights = layout::static_call_weights(&code_set.chunks);
+        layout::reorder_procedures(&mut code_set.chunks, &weights);
+    }
+    code_set
+}
+
+// Symbol name for ad hoc data, derived from a short hash of its content
+fn data_symbol_name(data: &[u8]) -> String {
+    use md5::{Digest, Md5};
+    let hash = Md5::digest(data);
+    let hex: String = hash[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("$data_{}", hex)
+}
+
+// Write generated code into an object file
+fn write_object(
+    source_path: &str,
+    code_set: CodeSet,
     obj_path: &Path,
     platform: Platform,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let current_dir_buf = std::env::current_dir();
-    let current_dir = current_dir_buf
-        .as_ref()
-        .map(|s| s.to_str())
+    let obj = object_bytes(source_path, code_set, obj_path, platform)?;
+    std::fs::write(obj_path, obj)?;
+    Ok(())
+}
+
+// Build the object file in memory. `obj_path` is only recorded in debug info
+fn object_bytes(
+    source_path: &str,
+    code_set: CodeSet,
+    obj_path: &Path,
+    platform: Platform,
+) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let current_dir = current_dir();
+    Ok(build_object(source_path, code_set, obj_path, &current_dir, platform)?.write()?)
+}
+
+// The directory that relative source paths in debug info are resolved against
+fn current_dir() -> String {
+    std::env::current_dir()
         .ok()
-        .flatten()
-        .unwrap_or("");
+        .and_then(|dir| dir.to_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+fn build_object(
+    source_path: &str,
+    code_set: CodeSet,
+    obj_path: &Path,
+    current_dir: &str,
+    platform: Platform,
+) -> std::result::Result<Object<'static>, Box<dyn std::error::Error>> {
+    let strip = code_set.strip;
+    let no_debug = code_set.no_debug;
+    let format = code_set
+        .object_format
+        .unwrap_or_else(|| ObjectFormat::native(platform));
 
     // Debug section generator
-    let mut debug: Box<dyn DebugWriter> = match platform {
-        Platform::Windows => Box::new(codeview::Codeview::new(
+    let mut debug: Box<dyn DebugWriter> = match format {
+        ObjectFormat::Coff => Box::new(codeview::Codeview::new(
             source_path,
             current_dir,
             obj_path.as_os_str().to_str().unwrap_or(""),
         )?),
-        Platform::Linux => Box::new(dwarf::Dwarf::new(
+        ObjectFormat::Elf => Box::new(dwarf::Dwarf::new(
             dwarf::DwarfFlavor::Linux,
             source_path,
             current_dir,
         )),
-        Platform::Macos => Box::new(dwarf::Dwarf::new(
+        ObjectFormat::MachO => Box::new(dwarf::Dwarf::new(
             dwarf::DwarfFlavor::Macos,
             source_path,
             current_dir,
         )),
     };
 
-    let binary_format = match platform {
-        Platform::Windows => BinaryFormat::Coff,
-        Platform::Linux => BinaryFormat::Elf,
-        Platform::Macos => BinaryFormat::MachO,
-    };
-
     // Object file generator
-    let mut obj = Object::new(binary_format, Architecture::X86_64, Endianness::Little);
+    let mut obj = Object::new(
+        format.binary_format(),
+        code_set.target.architecture,
+        code_set.target.endianness(),
+    );
 
     // Import standard library functions
-    let import_function = |obj: &mut Object, name: &str| {
+    let import_function = |obj: &mut Object, name: &[u8]| {
         obj.add_symbol(Symbol {
             name: name.into(),
             value: 0,
             size: 0,
-            kind: SymbolKind::Text,
+            kind: if name == BUILTIN_INTERRUPT_FLAG.as_bytes() {
+                SymbolKind::Data
+            } else {
+                SymbolKind::Text
+            },
             scope: SymbolScope::Linkage,
             weak: false,
             section: SymbolSection::Undefined,
@@ -369,17 +707,41 @@ pub fn gen_object(
         })
     };
 
-    import_function(&mut obj, BUILTIN_ALLOC_OBJ);
-    import_function(&mut obj, BUILTIN_DIV_ZERO);
-    import_function(&mut obj, BUILTIN_OUT_OF_BOUND);
-    import_function(&mut obj, BUILTIN_NONE_OP);
-    import_function(&mut obj, BUILTIN_LEN);
-    import_function(&mut obj, BUILTIN_PRINT);
-    import_function(&mut obj, BUILTIN_INPUT);
-    import_function(&mut obj, BUILTIN_INIT);
+    for &name in &code_set.imports {
+        import_function(&mut obj, name.as_bytes());
+    }
+
+    // Symbols shared with other units get a prefix so that they don't clash with system libraries
+    let shared: HashSet<&str> = code_set
+        .exports
+        .iter()
+        .chain(code_set.externs.iter())
+        .map(String::as_str)
+        .collect();
+    let symbol_name = |name: &str| -> Vec<u8> {
+        if shared.contains(name) {
+            (UNIT_SYMBOL_PREFIX.to_owned() + name).into_bytes()
+        } else {
+            name.as_bytes().to_vec()
+        }
+    };
 
-    // Generate machine code and debug info
-    let code_set = x64::gen_code_set(ast, platform);
+    for name in &code_set.externs {
+        obj.add_symbol(Symbol {
+            name: symbol_name(name),
+            value: 0,
+            size: 0,
+            kind: if name.ends_with(".$proto") {
+                SymbolKind::Data
+            } else {
+                SymbolKind::Text
+            },
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+    }
 
     // Feed type/class debug info to debug section generator
     for t in code_set.used_types_representive() {
@@ -421,6 +783,16 @@ pub fn gen_object(
     let data_section = obj.section_id(StandardSection::Data);
     let ro_section = obj.section_id(StandardSection::ReadOnlyData);
     let ro_reloc_section = obj.section_id(StandardSection::ReadOnlyDataWithRel);
+    let is_prototype = |chunk: &Chunk| {
+        matches!(chunk.extra, ChunkExtra::Data { writable: false })
+            && chunk.name.ends_with(".$proto")
+    };
+    let proto_section = if code_set.group_prototypes && code_set.chunks.iter().any(is_prototype) {
+        let (segment, name, kind) = format.prototype_section();
+        Some(obj.add_section(segment.to_vec(), name.to_vec(), kind))
+    } else {
+        None
+    };
 
     for chunk in &code_set.chunks {
         debug.add_chunk(chunk); // Feed the chunk debug info to debug section generator
@@ -432,12 +804,14 @@ pub fn gen_object(
         match chunk.extra {
             ChunkExtra::Procedure(_) => {
                 section = text_section;
-                align = 1;
+                align = 8; // for the local data after the code
                 kind = SymbolKind::Text;
             }
             ChunkExtra::Data { writable } => {
                 section = if writable {
                     data_section
+                } else if let (Some(proto_section), true) = (proto_section, is_prototype(chunk)) {
+                    proto_section
                 } else if chunk.links.is_empty() {
                     ro_section
                 } else {
@@ -448,61 +822,63 @@ pub fn gen_object(
             }
         }
 
-        // Only the entry point is exposed in linkage scope for linking with external entry point
-        let scope = if chunk.name == BUILTIN_CHOCOPY_MAIN {
+        // Only the entry points and exported definitions, which have a prefix, are exposed in
+        // linkage scope. User definitions named like `main` or `memcpy` can't clash with the runtime
+        let scope = if chunk.name == BUILTIN_CHOCOPY_MAIN
+            || code_set.entries.contains(&chunk.name)
+            || shared.contains(chunk.name.as_str())
+        {
             SymbolScope::Linkage
         } else {
             SymbolScope::Compilation
         };
 
         let offset = obj.append_section_data(section, &chunk.code, align);
-        obj.add_symbol(Symbol {
-            name: chunk.name.as_bytes().into(),
-            value: offset,
-            size: chunk.code.len() as u64,
-            kind,
-            scope,
-            weak: false,
-            section: SymbolSection::Section(section),
-            flags: SymbolFlags::None,
-        });
+        if !strip || scope == SymbolScope::Linkage {
+            obj.add_symbol(Symbol {
+                name: symbol_name(&chunk.name),
+                value: offset,
+                size: chunk.code.len() as u64,
+                kind,
+                scope,
+                weak: false,
+                section: SymbolSection::Section(section),
+                flags: SymbolFlags::None,
+            });
+        }
         section_map.insert(&chunk.name, (section, offset));
     }
 
     // Add relocations
 
-    let mut data_id = 0;
+    // Ad hoc data is pooled and named by its content, so names are stable across builds
+    let mut data_map = HashMap::new();
 
     for chunk in &code_set.chunks {
         let (from, from_offset) = section_map[&chunk.name];
-        let size;
-        let kind;
-        let encoding;
-        let addend;
-        if let ChunkExtra::Procedure(_) = chunk.extra {
-            size = 32;
-            kind = RelocationKind::Relative;
-            encoding = RelocationEncoding::X86RipRelative;
-            addend = -4;
-        } else {
-            size = 64;
-            kind = RelocationKind::Absolute;
-            encoding = RelocationEncoding::Generic;
-            addend = 0;
-        };
+        let procedure = matches!(chunk.extra, ChunkExtra::Procedure(_));
         for link in &chunk.links {
             let (symbol, symbol_addend) = match &link.to {
                 ChunkLinkTarget::Symbol(symbol, addend) => {
-                    (obj.symbol_id(symbol.as_bytes()).unwrap(), *addend)
+                    let name = symbol_name(symbol);
+                    if let Some(symbol) = obj.symbol_id(&name) {
+                        (symbol, *addend as i64)
+                    } else if let Some(&(section, offset)) = section_map.get(symbol) {
+                        // Stripped definition
+                        (obj.section_symbol(section), offset as i64 + *addend as i64)
+                    } else {
+                        // Unknown symbols are left for the linker (or --verify-obj) to report
+                        (import_function(&mut obj, &name), *addend as i64)
+                    }
                 }
                 ChunkLinkTarget::Data(data) => {
-                    let name = format!("$str{}", data_id);
-                    data_id += 1;
-                    let offset = obj.append_section_data(ro_section, data, 1);
-
-                    (
-                        obj.add_symbol(Symbol {
-                            name: name.into(),
+                    *data_map.entry(data.as_slice()).or_insert_with(|| {
+                        let offset = obj.append_section_data(ro_section, data, 1);
+                        if strip {
+                            return (obj.section_symbol(ro_section), offset as i64);
+                        }
+                        let symbol = obj.add_symbol(Symbol {
+                            name: data_symbol_name(data).into(),
                             value: offset,
                             size: 0,
                             kind: SymbolKind::Data,
@@ -510,11 +886,56 @@ pub fn gen_object(
                             weak: false,
                             section: SymbolSection::Section(ro_section),
                             flags: SymbolFlags::None,
-                        }),
-                        0,
-                    )
+                        });
+                        (symbol, 0)
+                    })
                 }
             };
+
+            let size;
+            let kind;
+            let encoding;
+            let addend;
+            if procedure {
+                // The displacement is relative to the end of the instruction,
+                // which is after the immediate operand if there is one
+                let bias = 4 + link.imm_size as i64;
+                size = 32;
+                encoding = RelocationEncoding::X86RipRelative;
+                match (format, link.imm_size) {
+                    (ObjectFormat::MachO, imm_size @ (1 | 2 | 4)) => {
+                        // ld64 needs to know the bias to find the target atom.
+                        // The implicit addend excludes the 4-byte displacement itself.
+                        kind = RelocationKind::MachO {
+                            value: match imm_size {
+                                1 => object::macho::X86_64_RELOC_SIGNED_1,
+                                2 => object::macho::X86_64_RELOC_SIGNED_2,
+                                _ => object::macho::X86_64_RELOC_SIGNED_4,
+                            },
+                            relative: true,
+                        };
+                        addend = symbol_addend - imm_size as i64;
+                    }
+                    _ => {
+                        kind = RelocationKind::Relative;
+                        addend = symbol_addend - bias;
+                    }
+                }
+                if i32::try_from(symbol_addend - bias).is_err() {
+                    return Err(RelocationError {
+                        chunk: chunk.name.clone(),
+                        symbol: String::from_utf8_lossy(&obj.symbol(symbol).name).into_owned(),
+                        addend: symbol_addend - bias,
+                    }
+                    .into());
+                }
+            } else {
+                size = 64;
+                kind = RelocationKind::Absolute;
+                encoding = RelocationEncoding::Generic;
+                addend = symbol_addend;
+            }
+
             obj.add_relocation(
                 from,
                 Relocation {
@@ -523,7 +944,7 @@ pub fn gen_object(
                     kind,
                     encoding,
                     symbol,
-                    addend: addend + symbol_addend as i64,
+                    addend,
                 },
             )?;
         }
@@ -531,7 +952,10 @@ pub fn gen_object(
 
     // Finalize debug section generation and feed them to the object file
 
-    let debug_chunks = debug.finalize();
+    let mut debug_chunks = debug.finalize();
+    if no_debug {
+        debug_chunks.retain(|chunk| !chunk.discardable);
+    }
     let mut debug_section_map = HashMap::new();
     for chunk in &debug_chunks {
         let kind = if chunk.discardable {
@@ -552,15 +976,26 @@ pub fn gen_object(
 
     for chunk in debug_chunks {
         for link in chunk.links {
-            let to = obj
-                .symbol_id(link.to.as_bytes())
-                .unwrap_or_else(|| obj.section_symbol(debug_section_map[&link.to]));
+            let (to, addend) = if let Some(symbol) = obj.symbol_id(&symbol_name(&link.to)) {
+                (symbol, 0)
+            } else if let Some(&(section, offset)) = section_map.get(&link.to) {
+                // Stripped definition
+                (obj.section_symbol(section), offset as i64)
+            } else {
+                (obj.section_symbol(debug_section_map[&link.to]), 0)
+            };
             let kind = match link.link_type {
                 DebugChunkLinkType::Absolute => RelocationKind::Absolute,
                 DebugChunkLinkType::SectionRelative => RelocationKind::SectionOffset,
                 DebugChunkLinkType::SectionId => RelocationKind::SectionIndex,
                 DebugChunkLinkType::ImageRelative => RelocationKind::ImageOffset,
             };
+            // The section index doesn't depend on where the definition is in the section
+            let addend = if kind == RelocationKind::SectionIndex {
+                0
+            } else {
+                addend
+            };
             obj.add_relocation(
                 debug_section_map[&chunk.name],
                 Relocation {
@@ -569,35 +1004,257 @@ pub fn gen_object(
                     kind,
                     encoding: RelocationEncoding::Generic,
                     symbol: to,
-                    addend: 0,
+                    addend,
                 },
             )?;
         }
     }
 
-    // Output the object file
-    let mut obj_file = std::fs::File::create(obj_path)?;
-    obj_file.write_all(&obj.write()?)?;
+    Ok(obj)
+}
+
+// CRC-32 as checked by debuggers following `.gnu_debuglink`
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
 
+// Generate an object file without debug info, pointing debuggers to the file at `debug_path`.
+// The debug file must have the same code, so it is either the object file with debug info,
+// or the executable linked from it
+fn gen_debuglinked_object(
+    source_path: &str,
+    ast: Program,
+    options: CompileOptions,
+    obj_path: &Path,
+    debug_path: &Path,
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let options = CompileOptions {
+        no_debug: true,
+        ..options
+    };
+    let code_set = x64::gen_code_set(ast, Unit::whole_program(), options, platform);
+    let endian = code_set.target.endian;
+    let current_dir = current_dir();
+    let mut obj = build_object(source_path, code_set, obj_path, &current_dir, platform)?;
+
+    // File name, padded with zeros to 4-byte alignment, then the CRC of the file
+    let mut debuglink = debug_path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .ok_or(SplitDebugError)?
+        .as_bytes()
+        .to_vec();
+    debuglink.resize((debuglink.len() + 4) & !3, 0);
+    debuglink.extend_from_slice(&endian.bytes(crc32(&std::fs::read(debug_path)?)));
+    let section = obj.add_section(vec![], b".gnu_debuglink".to_vec(), SectionKind::Debug);
+    obj.append_section_data(section, &debuglink, 4);
+
+    std::fs::write(obj_path, obj.write()?)?;
     Ok(())
 }
 
-// Link the object file with libraries to produce an executable
-pub fn link(
+fn binary_format(platform: Platform) -> BinaryFormat {
+    ObjectFormat::native(platform).binary_format()
+}
+
+// Check that a previously generated object file can be linked for the platform:
+// it has the right format and architecture, and the main object defines the entry point.
+pub fn check_object(
     obj_path: &Path,
-    path: &str,
-    static_lib: bool, // prefer static library instead of dynamic library
+    main: bool,
     platform: Platform,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    // Find the standard library
-    let lib_file = match platform {
+    use object::read::{Object as _, ObjectSymbol as _};
+
+    let data = std::fs::read(obj_path)?;
+    let file = match object::File::parse(&*data) {
+        Ok(file) => file,
+        Err(_) if obj_path.extension().is_some_and(|e| e == "py") => {
+            return Err(ObjectFileError(format!(
+                "{} is a source file, not an object file. Compile it to link it",
+                obj_path.display()
+            ))
+            .into());
+        }
+        Err(e) => {
+            return Err(ObjectFileError(format!(
+                "{} is not an object file: {}",
+                obj_path.display(),
+                e
+            ))
+            .into())
+        }
+    };
+
+    let expected = (binary_format(platform), target::X86_64.architecture);
+    if (file.format(), file.architecture()) != expected {
+        return Err(ObjectFileError(format!(
+            "{} is in format {:?} for {:?}, but {:?} for {:?} is expected",
+            obj_path.display(),
+            file.format(),
+            file.architecture(),
+            expected.0,
+            expected.1
+        ))
+        .into());
+    }
+
+    // Mach-O symbols have an extra underscore prefix
+    let entry = if file.format() == BinaryFormat::MachO {
+        "_".to_owned() + BUILTIN_CHOCOPY_MAIN
+    } else {
+        BUILTIN_CHOCOPY_MAIN.to_owned()
+    };
+    if main
+        && !file
+            .symbols()
+            .any(|s| s.is_definition() && s.name().ok() == Some(entry.as_str()))
+    {
+        return Err(ObjectFileError(format!(
+            "{} doesn't define `{}`. Is it compiled from the file with top-level statements?",
+            obj_path.display(),
+            BUILTIN_CHOCOPY_MAIN
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+// Read back an object file and check that every relocation targets either a
+// defined symbol or one of the expected imports.
+fn verify_object(
+    obj_path: &Path,
+    imports: &[&str],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    use object::read::{Object as _, ObjectSection as _, ObjectSymbol as _};
+
+    let data = std::fs::read(obj_path)?;
+    let file = object::File::parse(&*data)?;
+
+    // Mach-O symbols have an extra underscore prefix
+    let unprefixed = |name: &'_ str| -> String {
+        if file.format() == BinaryFormat::MachO {
+            name.strip_prefix('_').unwrap_or(name).to_owned()
+        } else {
+            name.to_owned()
+        }
+    };
+
+    let mut passed = true;
+    for section in file.sections() {
+        for (offset, relocation) in section.relocations() {
+            let symbol = if let object::RelocationTarget::Symbol(index) = relocation.target() {
+                file.symbol_by_index(index)?
+            } else {
+                continue;
+            };
+            if !symbol.is_undefined() {
+                continue;
+            }
+
+            let name = unprefixed(symbol.name()?);
+            if imports.contains(&name.as_str()) {
+                continue;
+            }
+
+            // The chunk containing the relocation is the closest symbol before it
+            let address = section.address() + offset;
+            let chunk = file
+                .symbols()
+                .filter(|s| {
+                    s.section_index() == Some(section.index())
+                        && s.kind() != SymbolKind::Section
+                        && s.address() <= address
+                })
+                .max_by_key(|s| s.address())
+                .and_then(|s| s.name().ok())
+                .filter(|name| !name.is_empty())
+                .map_or_else(|| section.name().unwrap_or("").to_owned(), unprefixed);
+
+            eprintln!("Error: `{}` references undefined symbol `{}`", chunk, name);
+            passed = false;
+        }
+    }
+
+    if passed {
+        Ok(())
+    } else {
+        Err(VerifyError.into())
+    }
+}
+
+// File name of the standard library
+fn std_lib_name(platform: Platform) -> &'static str {
+    match platform {
         Platform::Windows => "chocopy_rs_std.lib",
         Platform::Linux | Platform::Macos => "libchocopy_rs_std.a",
-    };
+        Platform::Wasm => unreachable!("WebAssembly modules are generated by gen::wasm"),
+    }
+}
+
+// Link the object files with libraries to produce an executable
+pub fn link(
+    obj_paths: &[&Path],
+    path: &str,
+    static_lib: bool,        // prefer static library instead of dynamic library
+    pdb_path: Option<&Path>, // where link.exe writes debug info, instead of next to `path`
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    // Find the standard library
+    let mut lib_path = std::env::current_exe()?;
+    lib_path.set_file_name(std_lib_name(platform));
+    link_with(obj_paths, &lib_path, path, static_lib, pdb_path, platform)
+}
 
+// Bundle the object files with the standard library into one static library
+pub fn write_staticlib(
+    obj_paths: &[&Path],
+    path: &str,
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
     let mut lib_path = std::env::current_exe()?;
-    lib_path.set_file_name(lib_file);
+    lib_path.set_file_name(std_lib_name(platform));
+    write_staticlib_with(obj_paths, &lib_path, path, platform)
+}
+
+fn write_staticlib_with(
+    obj_paths: &[&Path],
+    lib_path: &Path,
+    path: &str,
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let mut members = vec![];
+    for obj_path in obj_paths {
+        let name = obj_path.file_name().ok_or(PathError)?;
+        members.push(archive::Member {
+            name: name.to_str().ok_or(PathError)?.to_owned(),
+            data: std::fs::read(obj_path)?,
+            chocopy: true,
+        });
+    }
+    members.extend(archive::read_members(&std::fs::read(lib_path)?)?);
+    std::fs::write(path, archive::write_archive(&members, platform)?)?;
+    Ok(())
+}
 
+// Link the object files with the given standard library to produce an executable
+fn link_with(
+    obj_paths: &[&Path],
+    lib_path: &Path,
+    path: &str,
+    static_lib: bool,
+    pdb_path: Option<&Path>,
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
     // Invoke the linker
     let ld_output = match platform {
         Platform::Windows => {
@@ -627,25 +1284,36 @@ pub fn link(
             // standard escaping format, and rust std::process::Command doesn't
             // support it. To work around this, we make a temporary batch file
             // with the commands we want, and execute that batch file.
+            let mut objs = String::new();
+            for obj_path in obj_paths {
+                objs += &format!("\"{}\" ", windows_path_escape(obj_path)?);
+            }
+
+            // The debug info in object files goes to the PDB, which the executable refers to
+            let pdb = if let Some(pdb_path) = pdb_path {
+                format!(" /PDB:\"{}\"", windows_path_escape(pdb_path)?)
+            } else {
+                String::new()
+            };
+
             let batch_content = format!(
                 "@echo off
     call \"{}\" amd64
     link /NOLOGO /NXCOMPAT /OPT:REF,NOICF \
-    \"{}\" \"{}\" /OUT:\"{}\" \
+    {}\"{}\" /OUT:\"{}\" \
     kernel32.lib advapi32.lib ws2_32.lib userenv.lib Bcrypt.lib ntdll.lib {} \
-    /SUBSYSTEM:CONSOLE /DEBUG",
-                windows_path_escape(&vcvarsall)?,
-                windows_path_escape(obj_path)?,
-                windows_path_escape(&lib_path)?,
+    /SUBSYSTEM:CONSOLE /DEBUG{}",
+                windows_call_escape(&vcvarsall)?,
+                objs,
+                windows_path_escape(lib_path)?,
                 windows_path_escape(Path::new(path))?,
-                libs
+                libs,
+                pdb
             );
 
-            let mut bat_path = std::env::temp_dir();
-            let bat_name = format!("chocopy-{}.bat", rand::random::<u32>());
-            bat_path.push(bat_name);
-
-            std::fs::write(&bat_path, batch_content)?;
+            let (bat_path, mut bat_file) = temp::create(".bat")?;
+            bat_file.write_all(batch_content.as_bytes())?;
+            drop(bat_file);
 
             let ld_output = std::process::Command::new("cmd")
                 .args([OsStr::new("/c"), bat_path.as_os_str()])
@@ -655,10 +1323,9 @@ pub fn link(
         }
         Platform::Linux | Platform::Macos => {
             let mut command = std::process::Command::new("cc");
+            command.args([OsStr::new("-o"), OsStr::new(path)]);
+            command.args(obj_paths);
             command.args([
-                OsStr::new("-o"),
-                OsStr::new(path),
-                obj_path.as_os_str(),
                 lib_path.as_os_str(),
                 OsStr::new("-pthread"),
                 OsStr::new("-ldl"),
@@ -668,6 +1335,7 @@ pub fn link(
             }
             command.output()?
         }
+        Platform::Wasm => unreachable!("WebAssembly modules are generated by gen::wasm"),
     };
 
     if !ld_output.status.success() {
@@ -685,34 +1353,1967 @@ pub fn link(
     Ok(())
 }
 
-// Generates object file or executable
+// Generates object file, executable or static library
+#[allow(clippy::too_many_arguments)]
 pub fn gen(
     source_path: &str,
     ast: Program,
     path: &str,
     no_link: bool,
     static_lib: bool,
+    archive: bool, // output a static library with the standard library instead of an executable
+    verify_obj: bool,
+    options: CompileOptions,
+    split_debug: Option<&Path>, // Put debug info into this file instead of the output
     platform: Platform,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    if platform == Platform::Wasm {
+        std::fs::write(path, wasm::gen_module(&ast)?)?;
+        return Ok(());
+    }
+
     let obj_path = if no_link {
         let obj_path = Path::new(path);
         obj_path.to_owned()
     } else {
-        let mut obj_path = std::env::temp_dir();
-        let obj_name = format!("chocopy-{}.o", rand::random::<u32>());
-        obj_path.push(obj_name);
-        obj_path
+        // The empty file keeps the name reserved until the object file is written over it
+        temp::create(".o")?.0
     };
 
-    gen_object(source_path, ast, &obj_path, platform)?;
+    // On Windows, the linker moves debug info to the PDB anyway
+    let debug_path = split_debug.filter(|_| platform != Platform::Windows);
+    let pdb_path = split_debug.filter(|_| platform == Platform::Windows);
+
+    if let Some(debug_path) = debug_path {
+        // The executable linked with debug info is the debug file of the one linked without
+        if no_link {
+            gen_object(source_path, ast.clone(), options, debug_path, platform)?;
+        } else {
+            let debug_obj_path = temp::create(".o")?.0;
+            gen_object(source_path, ast.clone(), options, &debug_obj_path, platform)?;
+            let debug_exe = debug_path.to_str().ok_or(SplitDebugError)?;
+            link(&[&debug_obj_path], debug_exe, static_lib, None, platform)?;
+            std::fs::remove_file(&debug_obj_path)?;
+        }
+        gen_debuglinked_object(source_path, ast, options, &obj_path, debug_path, platform)?;
+    } else {
+        gen_object(source_path, ast, options, &obj_path, platform)?;
+    }
+
+    if verify_obj {
+        verify_object(&obj_path, &BUILTIN_IMPORTS)?;
+    }
 
     if no_link {
         return Ok(());
     }
 
-    link(&obj_path, path, static_lib, platform)?;
+    if archive {
+        write_staticlib(&[&obj_path], path, platform)?;
+    } else {
+        link(&[&obj_path], path, static_lib, pdb_path, platform)?;
+    }
 
     std::fs::remove_file(&obj_path)?;
 
     Ok(())
 }
+
+// Same as `gen`, but from an object file that is already generated
+pub fn gen_from_object(
+    obj_path: &Path,
+    path: &str,
+    no_link: bool,
+    static_lib: bool,
+    archive: bool,
+    verify_obj: bool,
+    platform: Platform,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    check_object(obj_path, true, platform)?;
+    if verify_obj {
+        verify_object(obj_path, &BUILTIN_IMPORTS)?;
+    }
+
+    if no_link {
+        std::fs::copy(obj_path, path)?;
+    } else if archive {
+        write_staticlib(&[obj_path], path, platform)?;
+    } else {
+        link(&[obj_path], path, static_lib, None, platform)?;
+    }
+
+    Ok(())
+}
+
+// Compile the program and link it with the runtime into a static x86-64 Linux executable,
+// without writing the object file or reading anything but the runtime
+#[cfg(any(test, feature = "embedded-runtime"))]
+fn compile_to_executable_elf_with(
+    source: &str,
+    options: &CompileOptions,
+    runtime: &elf_link::Runtime,
+) -> std::result::Result<Vec<u8>, Diagnostics> {
+    let ast = crate::parse::process_str(source, Default::default());
+    if !ast.errors.errors.is_empty() {
+        return Err(Diagnostics::Source(ast.errors.errors));
+    }
+    let check_options = crate::check::Options {
+        class_eq: options.class_eq,
+        ..Default::default()
+    };
+    let ast = crate::check::check(ast, check_options);
+    if !ast.errors.errors.is_empty() {
+        return Err(Diagnostics::Source(ast.errors.errors));
+    }
+
+    // There is no source file for debug info to refer to
+    let options = CompileOptions {
+        object_format: Some(ObjectFormat::Elf),
+        no_debug: true,
+        ..*options
+    };
+    let code_set = gen_code_set(ast, Unit::whole_program(), options, Platform::Linux);
+    let object = build_object("<string>", code_set, Path::new(""), "", Platform::Linux)
+        .and_then(|obj| Ok(obj.write()?))
+        .map_err(|e| Diagnostics::Executable(e.to_string()))?;
+    elf_link::link_executable(&object, runtime).map_err(|e| Diagnostics::Executable(e.to_string()))
+}
+
+// The standard library and the C runtime, embedded by build.rs
+#[cfg(feature = "embedded-runtime")]
+mod embedded_runtime {
+    include!(concat!(env!("OUT_DIR"), "/runtime.rs"));
+}
+
+// Same as `compile_to_executable_elf_with`, with the runtime embedded in the compiler
+#[cfg(feature = "embedded-runtime")]
+pub fn compile_to_executable_elf(
+    source: &str,
+    options: &CompileOptions,
+) -> std::result::Result<Vec<u8>, Diagnostics> {
+    let runtime = elf_link::Runtime {
+        start_files: embedded_runtime::START_FILES.to_vec(),
+        archives: embedded_runtime::ARCHIVES.to_vec(),
+        end_files: embedded_runtime::END_FILES.to_vec(),
+    };
+    compile_to_executable_elf_with(source, options, &runtime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_path_escapes() {
+        let escape = |path: &str| windows_path_escape(Path::new(path)).ok();
+        assert_eq!(
+            escape("C:\\Program Files (x86)\\a b\\out.exe").unwrap(),
+            "C:\\Program Files (x86)\\a b\\out.exe"
+        );
+        assert_eq!(
+            escape("C:\\%TEMP%\\100%.o").unwrap(),
+            "C:\\%%TEMP%%\\100%%.o"
+        );
+        assert_eq!(
+            escape("C:\\Üben\\日本語\\a^b&c.o").unwrap(),
+            "C:\\Üben\\日本語\\a^b&c.o"
+        );
+        assert_eq!(escape("C:\\out\\").unwrap(), "C:\\out\\\\");
+        assert_eq!(escape("a\"b"), None);
+        assert_eq!(escape("a\r\nb"), None);
+
+        let call_escape = |path: &str| windows_call_escape(Path::new(path)).ok();
+        assert_eq!(
+            call_escape("C:\\Program Files (x86)\\vcvarsall.bat").unwrap(),
+            "C:\\Program Files (x86)\\vcvarsall.bat"
+        );
+        assert_eq!(call_escape("C:\\%VS%\\vcvarsall.bat"), None);
+        assert_eq!(call_escape("C:\\a^b\\vcvarsall.bat"), None);
+    }
+
+    #[test]
+    fn verify_obj() {
+        let (source_path, ast) = test_util::check_source(
+            "class A(object):\n    def f(self:\"A\") -> int:\n        return 1\nprint(A().f())\n",
+        );
+        let source_path_str = source_path.to_str().unwrap();
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let obj_path = test_util::temp_path(".o");
+
+            let code_set = x64::gen_code_set(
+                ast.clone(),
+                Unit::whole_program(),
+                Default::default(),
+                platform,
+            );
+            write_object(source_path_str, code_set, &obj_path, platform).unwrap();
+            verify_object(&obj_path, &BUILTIN_IMPORTS).unwrap();
+
+            let mut code_set = x64::gen_code_set(
+                ast.clone(),
+                Unit::whole_program(),
+                Default::default(),
+                platform,
+            );
+            code_set.chunks.push(Chunk {
+                name: "broken".to_owned(),
+                code: vec![0; 8],
+                links: vec![ChunkLink {
+                    pos: 0,
+                    to: ChunkLinkTarget::Symbol("$missing".to_owned(), 0),
+                    imm_size: 0,
+                }],
+                extra: ChunkExtra::Data { writable: false },
+            });
+            write_object(source_path_str, code_set, &obj_path, platform).unwrap();
+            assert!(verify_object(&obj_path, &BUILTIN_IMPORTS).is_err());
+
+            std::fs::remove_file(&obj_path).unwrap();
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    // A small object file, defining `$chocopy_main` if `main` is set
+    fn tiny_object(
+        format: BinaryFormat,
+        architecture: object::Architecture,
+        main: bool,
+    ) -> PathBuf {
+        let mut obj = Object::new(format, architecture, object::Endianness::Little);
+        let section = obj.section_id(StandardSection::Text);
+        obj.append_section_data(section, &[0xC3], 1);
+        if main {
+            obj.add_symbol(Symbol {
+                name: BUILTIN_CHOCOPY_MAIN.as_bytes().into(),
+                value: 0,
+                size: 1,
+                kind: SymbolKind::Text,
+                scope: SymbolScope::Linkage,
+                weak: false,
+                section: SymbolSection::Section(section),
+                flags: SymbolFlags::None,
+            });
+        }
+        let path = test_util::temp_path(".o");
+        std::fs::write(&path, obj.write().unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn object_format() {
+        let error = |path: &Path, main: bool, platform: Platform| {
+            check_object(path, main, platform).unwrap_err().to_string()
+        };
+        let x86_64 = object::Architecture::X86_64;
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let format = binary_format(platform);
+            let main = tiny_object(format, x86_64, true);
+            check_object(&main, true, platform).unwrap();
+            std::fs::remove_file(&main).unwrap();
+
+            let other = tiny_object(format, x86_64, false);
+            check_object(&other, false, platform).unwrap();
+            assert!(error(&other, true, platform).contains("doesn't define `$chocopy_main`"));
+            std::fs::remove_file(&other).unwrap();
+        }
+
+        let elf = tiny_object(BinaryFormat::Elf, x86_64, true);
+        assert!(error(&elf, true, Platform::Windows)
+            .ends_with("is in format Elf for X86_64, but Coff for X86_64 is expected"));
+        std::fs::remove_file(&elf).unwrap();
+
+        let arm = tiny_object(BinaryFormat::Elf, object::Architecture::Aarch64, true);
+        assert!(error(&arm, true, Platform::Linux).contains("for Aarch64, but Elf for X86_64"));
+        std::fs::remove_file(&arm).unwrap();
+
+        // A source file given in place of the object file
+        let source = test_util::temp_path(".py");
+        std::fs::write(&source, "print(1)\n").unwrap();
+        assert!(error(&source, true, Platform::Linux)
+            .ends_with("is a source file, not an object file. Compile it to link it"));
+        std::fs::remove_file(&source).unwrap();
+    }
+
+    #[test]
+    fn from_typed_ast() {
+        let dir = Path::new("test/original/pa3");
+        for name in &["call", "list_concat", "object_method_override", "nested2"] {
+            let source_path = dir.join(name.to_string() + ".py");
+            let source_path_str = source_path.to_str().unwrap();
+            let ast_path = source_path_str.to_owned() + ".ast.typed";
+            let expected = std::fs::read_to_string(ast_path.clone() + ".s.result").unwrap();
+
+            let ast = crate::parse::load_ast(&ast_path).unwrap();
+            let ast = crate::check::validate(ast);
+            assert!(ast.errors.errors.is_empty(), "{:?}", ast.errors.errors);
+
+            let obj_path = test_util::temp_path(".o");
+            gen_object(
+                source_path_str,
+                ast,
+                Default::default(),
+                &obj_path,
+                crate::PLATFORM,
+            )
+            .unwrap();
+            if let Some(output) = test_util::link_and_run(&[&obj_path], "") {
+                assert!(output.status.success());
+                assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), expected);
+            }
+            std::fs::remove_file(&obj_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn class_const() {
+        let source_path = "test/ext/class_const.py";
+        if let Some(output) = test_util::run_fixture(source_path, Default::default(), &[]) {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "square\nTrue\n12\n120\n14\n"
+            );
+        }
+    }
+
+    #[test]
+    fn bitwise() {
+        let source_path = "test/ext/bitwise.py";
+        if let Some(output) = test_util::run_fixture(source_path, Default::default(), &[]) {
+            assert_eq!(output.status.code(), Some(1));
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "8\n15\n9\n249\n-7\n6\n1\n-2147483648\n0\n-56\n-7\n-4\n-1\n-1\n0\n24\nTrue\n3\n-3\n24\n\
+                 28004\n"
+            );
+            assert_eq!(
+                std::str::from_utf8(&output.stderr).unwrap(),
+                "Invalid argument\nExited with error code 1\n"
+            );
+        }
+    }
+
+    #[test]
+    fn list_eq() {
+        let source_path = "test/ext/list_eq.py";
+        if let Some(output) = test_util::run_fixture(source_path, Default::default(), &[]) {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "True\nFalse\nFalse\nTrue\nFalse\nTrue\nTrue\nTrue\nFalse\nFalse\nTrue\nFalse\nTrue\nTrue\nTrue\nTrue\n"
+            );
+        }
+    }
+
+    #[test]
+    fn list_append() {
+        let source_path = "test/ext/list_append.py";
+        // Every allocation collects garbage, so the lists being copied must stay referenced
+        let envs = [("CHOCOPY_GC_STRESS", "1")];
+        if let Some(output) = test_util::run_fixture(source_path, Default::default(), &envs) {
+            assert_eq!(output.status.code(), Some(4));
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "328350\n100\nTrue\nFalse\na\nab\nabb\nabbb\n16\n3\n2\n1\n3\n"
+            );
+            assert_eq!(
+                std::str::from_utf8(&output.stderr).unwrap(),
+                "Operation on None\nExited with error code 4\n"
+            );
+        }
+    }
+
+    #[test]
+    fn truthy_and_or() {
+        let source_path = "test/ext/truthy_and_or.py";
+        // Boxing the left operand allocates, which must keep the operand referenced
+        let envs = [("CHOCOPY_GC_STRESS", "1")];
+        if let Some(output) = test_util::run_fixture(source_path, Default::default(), &envs) {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "5\n3\n0\n5\nb\n7\nTrue\nempty\na\n\n2\n1\nTrue\n2\n2\n0\nFalse\nFalse\n1\n0\nevaluated\n2\n"
+            );
+        }
+    }
+
+    #[test]
+    fn enumerate() {
+        let source_path = "test/ext/enumerate.py";
+        // Boxing the index allocates, which must keep the list being iterated referenced
+        let envs = [("CHOCOPY_GC_STRESS", "1")];
+        if let Some(output) = test_util::run_fixture(source_path, Default::default(), &envs) {
+            assert_eq!(output.status.code(), Some(4));
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "0\na\n1\nb\n2\nc\ntwo!\n20\n0\n1\n2\n2\n4\n5\n"
+            );
+            assert_eq!(
+                std::str::from_utf8(&output.stderr).unwrap(),
+                "Operation on None\nExited with error code 4\n"
+            );
+        }
+    }
+
+    #[test]
+    fn membership() {
+        let source_path = "test/ext/membership.py";
+        // The value is held on the stack while the list is evaluated, which can allocate
+        let envs = [("CHOCOPY_GC_STRESS", "1")];
+        for opt_level in [0, 2] {
+            let options = CompileOptions {
+                opt_level,
+                ..Default::default()
+            };
+            if let Some(output) = test_util::run_fixture(source_path, options, &envs) {
+                assert_eq!(output.status.code(), Some(4));
+                assert_eq!(
+                    std::str::from_utf8(&output.stdout).unwrap(),
+                    "True\nFalse\nTrue\nTrue\nFalse\nTrue\nFalse\nTrue\nTrue\nFalse\nFalse\n\
+                     True\nTrue\nTrue\nTrue\nFalse\nFalse\nTrue\nFalse\nTrue\nTrue\nFalse\n2\n0\n0\n7\n"
+                );
+                assert_eq!(
+                    std::str::from_utf8(&output.stderr).unwrap(),
+                    "Operation on None\nExited with error code 4\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn func_values() {
+        let source_path = "test/ext/func_values.py";
+        // Function values are allocated, so they must survive collections while in use
+        let envs = [("CHOCOPY_GC_STRESS", "1")];
+        for opt_level in [0, 2] {
+            let options = CompileOptions {
+                opt_level,
+                ..Default::default()
+            };
+            if let Some(output) = test_util::run_fixture(source_path, options, &envs) {
+                assert_eq!(output.status.code(), Some(4));
+                assert_eq!(
+                    std::str::from_utf8(&output.stdout).unwrap(),
+                    "25\n-5\n1\n4\n9\nhi!!\n10\n20\n-9\n49\nTrue\nFalse\n"
+                );
+                assert_eq!(
+                    std::str::from_utf8(&output.stderr).unwrap(),
+                    "Operation on None\nExited with error code 4\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn class_eq() {
+        let source = "\
+class Point(object):
+    x:int = 0
+    y:int = 0
+    label:str = \"\"
+    on:bool = False
+class Point3(Point):
+    z:int = 0
+class Node(object):
+    next:Node = None
+    point:Point = None
+    items:[int] = None
+def point(x:int, y:int, label:str) -> Point:
+    p:Point = None
+    p = Point()
+    p.x = x
+    p.y = y
+    p.label = label
+    return p
+a:Point = None
+b:Point = None
+n:Node = None
+m:Node = None
+a = point(1, 2, \"a\")
+b = point(1, 2, \"a\")
+print(a == b)
+print(a != b)
+b.label = \"b\"
+print(a == b)
+b = a
+print(a == b)
+b = point(1, 2, \"a\")
+b.on = True
+print(a == b)
+b = Point3()
+b.x = 1
+b.y = 2
+b.label = \"a\"
+print(a == b)
+b = None
+print(a == b)
+a = None
+print(a == b)
+n = Node()
+m = Node()
+n.point = point(3, 4, \"p\")
+m.point = point(3, 4, \"p\")
+print(n == m)
+m.point.y = 5
+print(n == m)
+m.point.y = 4
+n.items = [1]
+m.items = [1]
+print(n == m)
+m.items = n.items
+print(n == m)
+n.next = n
+m.next = m
+print(n != m)
+m.next = n
+print(n == m)
+";
+        let source_path = test_util::temp_path(".py");
+        std::fs::write(&source_path, source).unwrap();
+        let source_path_str = source_path.to_str().unwrap();
+        let ast = crate::parse::process(source_path_str, Default::default()).unwrap();
+        let options = crate::check::Options {
+            class_eq: true,
+            ..Default::default()
+        };
+        let ast = crate::check::check(ast, options);
+        assert!(ast.errors.errors.is_empty(), "{:?}", ast.errors.errors);
+
+        let obj_path = test_util::temp_path(".o");
+        let compile_options = CompileOptions {
+            class_eq: true,
+            ..Default::default()
+        };
+        gen_object(
+            source_path_str,
+            ast,
+            compile_options,
+            &obj_path,
+            crate::PLATFORM,
+        )
+        .unwrap();
+        if let Some(output) = test_util::link_and_run(&[&obj_path], "") {
+            assert!(output.status.success());
+            // Distinct cycles are unequal after the depth limit, and objects of a subclass
+            // are unequal to objects of the class
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "True\nFalse\nFalse\nTrue\nFalse\nFalse\nFalse\nTrue\n\
+                 True\nFalse\nFalse\nTrue\nTrue\nTrue\n"
+            );
+        }
+        std::fs::remove_file(&obj_path).unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn plain_eq() {
+        // Comparing ints and bools never goes to the object comparison, with or without it
+        let source = "\
+x:int = 1
+b:bool = True
+print(1 == 2)
+print(x == 1)
+print(x != 1)
+print(b == True)
+print(b != (x == 1))
+";
+        let class_eq = CompileOptions {
+            class_eq: true,
+            ..Default::default()
+        };
+        for options in [Default::default(), class_eq] {
+            if let Some(output) = test_util::run_with_options(source, "", options) {
+                assert!(output.status.success());
+                assert_eq!(
+                    std::str::from_utf8(&output.stdout).unwrap(),
+                    "False\nTrue\nFalse\nTrue\nFalse\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn obj_id_under_gc_stress() {
+        // Every allocation collects garbage, which must not change identities of live objects
+        let source = std::fs::read_to_string("test/pa3/obj_id.py").unwrap();
+        let envs = [("CHOCOPY_GC_STRESS", "1")];
+        if let Some(output) = test_util::run_with_env(&source, "", &envs) {
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "True\nFalse\n0\nTrue\nTrue\n"
+            );
+        }
+
+        // Freed objects give their addresses to new ones, which still get new identities
+        let source = "\
+x:[int] = None
+i:int = 0
+last:int = 0
+increasing:bool = True
+while i < 100:
+    x = [i]
+    increasing = increasing and obj_id(x) > last
+    last = obj_id(x)
+    i = i + 1
+print(increasing)
+";
+        if let Some(output) = test_util::run_with_env(source, "", &envs) {
+            assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "True\n");
+        }
+    }
+
+    #[test]
+    fn globals_under_gc_stress() {
+        // References between scalars of every size, so that the global section size is not a
+        // multiple of 8. Every allocation collects garbage, which must keep all of them
+        let source = "\
+a:bool = True
+s:str = \"s\"
+b:bool = False
+n:int = 1
+x:[int] = None
+c:bool = True
+t:str = \"t\"
+m:int = 2
+y:[str] = None
+i:int = 0
+d:bool = False
+x = [1, 2, 3]
+y = [\"a\", \"b\"]
+while i < 50:
+    s = s + \"s\"
+    x = x + [i]
+    i = i + 1
+t = t + s
+print(len(s))
+print(len(x))
+print(y[1])
+print(t[0] + t[1])
+print(a and c and not b and not d)
+print(n + m)
+";
+        let envs = [("CHOCOPY_GC_STRESS", "1"), ("CHOCOPY_GC_FINAL_CHECK", "1")];
+        if let Some(output) = test_util::run_with_env(source, "", &envs) {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "51\n53\nb\nts\nTrue\n3\n"
+            );
+        }
+    }
+
+    #[test]
+    fn collect_keeps_live_objects() {
+        // Explicit collections must find the references in every frame, after which
+        // the final check makes sure that nothing reachable was freed or lost
+        let source = std::fs::read_to_string("test/pa3/collect.py").unwrap();
+        let envs = [("CHOCOPY_GC_FINAL_CHECK", "1")];
+        if let Some(output) = test_util::run_with_env(&source, "", &envs) {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "15\n55\nTrue\n"
+            );
+        }
+    }
+
+    #[test]
+    fn list_concat_coercion() {
+        // Elements are boxed when the result holds objects, and every box allocates
+        let source = std::fs::read_to_string("test/pa3/list_concat.py").unwrap();
+        let envs = [("CHOCOPY_GC_STRESS", "1")];
+        if let Some(output) = test_util::run_with_env(&source, "", &envs) {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "0\n2\n3\n2\nNone\nNone\n3\nNone\n1\n2\n3\n1\nNone\na\n3\nb\nNone\nTrue\n2\nTrue\n5\n2\nTrue\n"
+            );
+        }
+    }
+
+    #[test]
+    fn pass_only_bodies() {
+        // `__init__` of B is only `pass`, so it has no statements at all
+        let source = std::fs::read_to_string("test/original/pa2/class_def_methods.py").unwrap();
+        let source = source + "print(a.x)\nprint(b.x)\nprint(c.x)\n";
+        if let Some(output) = test_util::run(&source, "") {
+            assert!(output.status.success());
+            assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "1\n1\n0\n");
+        }
+        let source = "def f():\n    pass\nprint(f() is None)\n";
+        if let Some(output) = test_util::run(source, "") {
+            assert!(output.status.success());
+            assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "True\n");
+        }
+    }
+
+    #[test]
+    fn random_numbers() {
+        let source = std::fs::read_to_string("test/pa3/random.py").unwrap();
+        if let Some(output) = test_util::run(&source, "") {
+            assert_eq!(output.status.code(), Some(1));
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "1\n2\n4\n3\n5\n-2\n337216\n1\n2\n4\n3\n5\n7\n"
+            );
+        }
+        // The full range doesn't overflow, and the numbers are those of the shared generator
+        let source =
+            "seed(-5)\nprint(randint(-2147483647 - 1, 2147483647))\nprint(randint(0, 9))\n";
+        let mut random = chocopy_rs_common::random::Random::new(-5);
+        let expected = format!("{}\n{}\n", random.int(i32::MIN, i32::MAX), random.int(0, 9));
+        if let Some(output) = test_util::run(source, "") {
+            assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn unicode_input() {
+        let source = std::fs::read_to_string("test/pa3/unicode_input.py").unwrap();
+        let input = std::fs::read_to_string("test/pa3/unicode_input.py.1.input").unwrap();
+        if let Some(output) = test_util::run(&source, &input) {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "a\u{e9}\u{20ac}\n6\n\u{fffd}\n\u{e9}\nTrue\n"
+            );
+        }
+    }
+
+    #[test]
+    fn runtime_error_stderr() {
+        let source = "x:int = 0\nprint(1)\nprint(1 // x)\n";
+        for &(quiet, message) in &[
+            ("0", "Division by zero\nExited with error code 2\n"),
+            ("1", "Division by zero\n"),
+        ] {
+            let envs = [("CHOCOPY_QUIET", quiet)];
+            if let Some(output) = test_util::run_with_env(source, "", &envs) {
+                assert_eq!(output.status.code(), Some(2));
+                assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "1\n");
+                assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), message);
+            }
+        }
+    }
+
+    #[test]
+    fn executable_in_memory() {
+        if crate::PLATFORM != Platform::Linux {
+            return;
+        }
+        test_util::with_linux_runtime(|runtime| {
+            let compile = |source: &str| {
+                compile_to_executable_elf_with(source, &Default::default(), runtime).unwrap()
+            };
+
+            let output = test_util::run_executable(&compile("print(\"Hello, world!\")\n"), "");
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "Hello, world!\n"
+            );
+
+            let source = "x:int = 0\nprint(input())\nprint(1 // x)\n";
+            let output = test_util::run_executable(&compile(source), "a\u{e9}\n");
+            assert_eq!(output.status.code(), Some(2));
+            assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "a\u{e9}\n");
+            assert_eq!(
+                std::str::from_utf8(&output.stderr).unwrap(),
+                "Division by zero\nExited with error code 2\n"
+            );
+
+            match compile_to_executable_elf_with("x:int = True\n", &Default::default(), runtime) {
+                Err(Diagnostics::Source(errors)) => assert_eq!(errors.len(), 1),
+                _ => panic!("Expected a type error"),
+            }
+        });
+    }
+
+    #[cfg(feature = "embedded-runtime")]
+    #[test]
+    fn executable_with_embedded_runtime() {
+        let executable =
+            compile_to_executable_elf("print(\"Hello, world!\")\n", &Default::default()).unwrap();
+        let output = test_util::run_executable(&executable, "");
+        assert!(output.status.success());
+        assert_eq!(
+            std::str::from_utf8(&output.stdout).unwrap(),
+            "Hello, world!\n"
+        );
+    }
+
+    fn data_symbols(source: &str) -> Vec<String> {
+        use object::read::{Object as _, ObjectSymbol as _};
+
+        let (source_path, ast) = test_util::check_source(source);
+        let obj_path = test_util::temp_path(".o");
+        gen_object(
+            source_path.to_str().unwrap(),
+            ast,
+            Default::default(),
+            &obj_path,
+            Platform::Linux,
+        )
+        .unwrap();
+        let data = std::fs::read(&obj_path).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let mut names: Vec<_> = file
+            .symbols()
+            .map(|symbol| symbol.name().unwrap().to_owned())
+            .filter(|name| name.starts_with("$data_"))
+            .collect();
+        names.sort();
+        std::fs::remove_file(&obj_path).unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+        names
+    }
+
+    #[test]
+    fn data_symbol_names() {
+        // Prototypes refer to the reference map of their attributes
+        let classes = "class A(object):\n    x:object = None\nclass B(object):\n    y:str = \"\"\n";
+        let before = data_symbols(classes);
+        let after = data_symbols(
+            &(classes.to_owned() + "class C(object):\n    x:int = 0\n    y:object = None\n"),
+        );
+
+        // Identical data is pooled into one symbol
+        let mut unique = before.clone();
+        unique.dedup();
+        assert_eq!(unique, before);
+
+        // Inserting a new class with a different map only adds a symbol
+        assert_eq!(after.len(), before.len() + 1);
+        assert!(before.iter().all(|name| after.contains(name)));
+    }
+
+    #[test]
+    fn local_data() {
+        use object::read::{Object as _, ObjectSection as _, ObjectSymbol as _};
+
+        // String literals and reference maps of procedures are stored after their code
+        let mut source = "s:str = \"\"\n".to_owned();
+        for i in 0..50 {
+            source += &format!("s = s + \"literal {}\"\nprint(s)\n", i % 10);
+        }
+        let (source_path, ast) = test_util::check_source(&source);
+        let source_path_str = source_path.to_str().unwrap();
+
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let code_set = x64::gen_code_set(
+                ast.clone(),
+                Unit::whole_program(),
+                Default::default(),
+                platform,
+            );
+            let obj_path = test_util::temp_path(".o");
+            let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+            let file = object::File::parse(&*data).unwrap();
+            let text = file
+                .section_by_name(".text")
+                .or_else(|| file.section_by_name("__text"));
+            for (_, relocation) in text.unwrap().relocations() {
+                if let object::RelocationTarget::Symbol(index) = relocation.target() {
+                    let name = file.symbol_by_index(index).unwrap().name().unwrap();
+                    assert!(!name.contains("$data_"), "{}", name);
+                }
+            }
+        }
+
+        if let Some(output) = test_util::run(&source, "") {
+            let expected: String = (0..50)
+                .scan(String::new(), |s, i| {
+                    *s += &format!("literal {}", i % 10);
+                    Some(s.clone() + "\n")
+                })
+                .collect();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn group_prototypes() {
+        use object::read::{Object as _, ObjectSection as _, ObjectSymbol as _};
+
+        let source = "
+class A(object):
+    def f(self:\"A\") -> str:
+        return \"A\"
+class B(A):
+    def f(self:\"B\") -> str:
+        return \"B\"
+x:A = None
+x = B()
+print(x.f())
+print(A().f())
+print([1, 2][1])
+";
+        let (source_path, ast) = test_util::check_source(source);
+        let source_path_str = source_path.to_str().unwrap();
+        let options = CompileOptions {
+            group_prototypes: true,
+            ..Default::default()
+        };
+
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let code_set = x64::gen_code_set(ast.clone(), Unit::whole_program(), options, platform);
+            let obj_path = test_util::temp_path(".o");
+            let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+            let file = object::File::parse(&*data).unwrap();
+            let (_, section_name, _) = ObjectFormat::native(platform).prototype_section();
+            let section = file
+                .sections()
+                .find(|section| section.name_bytes().unwrap() == section_name)
+                .unwrap();
+
+            // All prototypes, and nothing else, are defined in the section. The reader doesn't
+            // know the kind of Mach-O symbols in a custom section, so only section symbols are
+            // left out
+            let mut prototypes = vec![];
+            for symbol in file.symbols() {
+                if symbol.kind() == SymbolKind::Section {
+                    continue;
+                }
+                let name = symbol.name().unwrap();
+                if symbol.section_index() == Some(section.index()) {
+                    assert!(name.ends_with(".$proto"), "{}", name);
+                    prototypes.push(name);
+                } else {
+                    assert!(!name.ends_with(".$proto"), "{}", name);
+                }
+            }
+            for class in &["A", "B", "int", "str", "[int]"] {
+                let proto = format!("{}.$proto", class);
+                let found = prototypes.iter().any(|name| name.ends_with(&proto));
+                assert!(found, "{}", proto);
+            }
+
+            // Method pointers are still linked
+            assert!(section.relocations().next().is_some());
+        }
+
+        if let Some(output) = test_util::run_with_options(source, "", options) {
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "B\nA\n2\n");
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn strip() {
+        use object::read::{Object as _, ObjectSymbol as _};
+
+        let source = "\
+class Counter(object):
+    n:int = 0
+    def tick(self:\"Counter\") -> int:
+        self.n = self.n + 1
+        return self.n
+def greet(name:str) -> str:
+    return \"hello \" + name
+c:Counter = None
+c = Counter()
+c.tick()
+print(c.tick())
+print(greet(\"strip\"))
+";
+        let options = CompileOptions {
+            strip: true,
+            ..Default::default()
+        };
+        let (source_path, ast) = test_util::check_source(source);
+        let source_path_str = source_path.to_str().unwrap();
+
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let code_set = x64::gen_code_set(ast.clone(), Unit::whole_program(), options, platform);
+            let obj_path = test_util::temp_path(".o");
+            let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+            let file = object::File::parse(&*data).unwrap();
+            let names: Vec<_> = file
+                .symbols()
+                .filter(|symbol| symbol.is_definition())
+                .map(|symbol| symbol.name().unwrap().to_owned())
+                .collect();
+            assert!(
+                names
+                    .iter()
+                    .any(|name| name.ends_with(BUILTIN_CHOCOPY_MAIN)),
+                "{:?}",
+                names
+            );
+            for name in &names {
+                assert!(
+                    !name.contains("greet")
+                        && !name.contains("Counter")
+                        && !name.contains("$data_"),
+                    "{}",
+                    name
+                );
+            }
+        }
+
+        if let Some(output) = test_util::run_with_options(source, "", options) {
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "2\nhello strip\n"
+            );
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn global_relocations() {
+        use object::read::{Object as _, ObjectSection as _, ObjectSymbol as _};
+
+        // Enough globals to push the last ones far from the start of the section
+        const COUNT: usize = 3000;
+        let mut source = String::new();
+        for i in 0..COUNT {
+            source += &format!("i{0}:int = {0}\nb{0}:bool = True\ns{0}:str = \"\"\n", i);
+        }
+        source += &format!(
+            "i{0} = i{0} + 1\nb{0} = not b{0}\nprint(i{0})\nprint(b{0})\n",
+            COUNT - 1
+        );
+        let (source_path, ast) = test_util::check_source(&source);
+        let source_path_str = source_path.to_str().unwrap();
+
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let obj_path = test_util::temp_path(".o");
+            let code_set = x64::gen_code_set(
+                ast.clone(),
+                Unit::whole_program(),
+                Default::default(),
+                platform,
+            );
+            let global_size = code_set.global_size as i64;
+            write_object(source_path_str, code_set, &obj_path, platform).unwrap();
+
+            let data = std::fs::read(&obj_path).unwrap();
+            let file = object::File::parse(&*data).unwrap();
+            let mut imm_sizes = vec![];
+            for section in file.sections() {
+                let section_data = section.data().unwrap();
+                for (offset, relocation) in section.relocations() {
+                    let symbol = match relocation.target() {
+                        object::RelocationTarget::Symbol(index) => {
+                            file.symbol_by_index(index).unwrap()
+                        }
+                        _ => continue,
+                    };
+                    if !symbol.name().unwrap().ends_with(GLOBAL_SECTION) {
+                        continue;
+                    }
+                    if section.kind() != SectionKind::Text {
+                        continue;
+                    }
+
+                    assert_ne!(relocation.kind(), RelocationKind::GotRelative);
+                    let offset = offset as usize;
+                    let implicit = if relocation.has_implicit_addend() {
+                        i32::from_le_bytes(section_data[offset..][..4].try_into().unwrap()) as i64
+                    } else {
+                        0
+                    };
+
+                    // Displacement is relative to the end of the immediate operand
+                    let imm_size = match section_data[offset - 2..offset] {
+                        [0xC7, 0x05] => 4,
+                        [0xC6, 0x05] => 1,
+                        _ => 0,
+                    };
+                    let target = implicit + relocation.addend() + 4 + imm_size;
+                    assert!(
+                        (0..global_size).contains(&target),
+                        "{} out of $global",
+                        target
+                    );
+                    if platform == Platform::Macos && imm_size != 0 {
+                        let value = if imm_size == 1 {
+                            object::macho::X86_64_RELOC_SIGNED_1
+                        } else {
+                            object::macho::X86_64_RELOC_SIGNED_4
+                        };
+                        assert_eq!(
+                            relocation.kind(),
+                            RelocationKind::MachO {
+                                value,
+                                relative: true
+                            }
+                        );
+                    }
+                    imm_sizes.push(imm_size);
+                }
+            }
+            assert!(imm_sizes.contains(&1) && imm_sizes.contains(&4));
+
+            if platform == crate::PLATFORM {
+                if let Some(output) = test_util::link_and_run(&[&obj_path], "") {
+                    assert!(output.status.success());
+                    assert_eq!(
+                        std::str::from_utf8(&output.stdout).unwrap(),
+                        format!("{}\nFalse\n", COUNT)
+                    );
+                }
+            }
+            std::fs::remove_file(&obj_path).unwrap();
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    // Check that CodeView subsections are well-formed and line tables are not empty
+    // Returns the code offsets and lines of each procedure
+    fn check_codeview(file: &object::File) -> Vec<Vec<(u64, u32)>> {
+        use object::read::{Object as _, ObjectSection as _};
+
+        let data = file.section_by_name(".debug$S").unwrap().data().unwrap();
+        let read_u32 = |pos: usize| u32::from_le_bytes(data[pos..][..4].try_into().unwrap());
+        assert_eq!(read_u32(0), 4); // C13 signature
+        let mut tables = vec![];
+        let mut pos = 4;
+        while pos < data.len() {
+            let subsection_type = read_u32(pos);
+            let len = read_u32(pos + 4) as usize;
+            let end = pos + 8 + len;
+            assert!(end <= data.len());
+            if subsection_type == 0xF2 {
+                // header, then a single block of line entries
+                let count = read_u32(pos + 8 + 16) as usize;
+                let block_size = read_u32(pos + 8 + 20) as usize;
+                assert_ne!(count, 0);
+                assert_eq!(block_size, 12 + count * 8);
+                assert_eq!(len, 12 + block_size);
+                let entries = pos + 8 + 24;
+                tables.push(
+                    (0..count)
+                        .map(|i| {
+                            let code_pos = read_u32(entries + i * 8) as u64;
+                            (code_pos, read_u32(entries + i * 8 + 4) & 0x7FFF_FFFF)
+                        })
+                        .collect(),
+                );
+            }
+            pos = (end + 3) & !3;
+        }
+        assert_eq!(pos, data.len());
+        tables
+    }
+
+    // Check that DWARF units and line programs can be fully parsed
+    // Returns the code offsets and lines of each procedure
+    fn check_dwarf(file: &object::File) -> Vec<Vec<(u64, u32)>> {
+        use gimli::{EndianSlice, LittleEndian};
+        use object::read::{Object as _, ObjectSection as _};
+
+        let dwarf = gimli::Dwarf::load(|id| -> gimli::Result<_> {
+            let data = file
+                .section_by_name(id.name())
+                .and_then(|section| section.data().ok())
+                .unwrap_or(&[]);
+            Ok(EndianSlice::new(data, LittleEndian))
+        })
+        .unwrap();
+        let mut units = dwarf.units();
+        let mut unit_count = 0;
+        let mut tables = vec![vec![]];
+        while let Some(header) = units.next().unwrap() {
+            let unit = dwarf.unit(header).unwrap();
+            let mut entries = unit.entries();
+            while entries.next_dfs().unwrap().is_some() {}
+            if let Some(program) = unit.line_program.clone() {
+                let mut rows = program.rows();
+                while let Some((_, row)) = rows.next_row().unwrap() {
+                    if row.end_sequence() {
+                        tables.push(vec![]);
+                    } else {
+                        let line = row.line().unwrap().get() as u32;
+                        tables.last_mut().unwrap().push((row.address(), line));
+                    }
+                }
+            }
+            unit_count += 1;
+        }
+        assert_eq!(unit_count, 1);
+        tables.pop();
+        tables
+    }
+
+    #[test]
+    fn runtime_names() {
+        use object::read::{Object as _, ObjectSymbol as _};
+
+        // Functions named like the C entry point and libc functions
+        let source = "\
+def main() -> int:
+    return memcpy(2)
+def memcpy(x:int) -> int:
+    return malloc(x) * 10
+def malloc(x:int) -> int:
+    return x + 1
+def exit():
+    print(\"exit\")
+def abort() -> str:
+    return \"abort\"
+print(main())
+exit()
+print(abort())
+";
+        let names = ["main", "memcpy", "malloc", "exit", "abort"];
+        let (source_path, ast) = test_util::check_source(source);
+        let source_path_str = source_path.to_str().unwrap();
+        let exported = Unit {
+            exported: true,
+            ..Unit::whole_program()
+        };
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            for &unit in &[Unit::whole_program(), exported] {
+                let code_set = x64::gen_code_set(ast.clone(), unit, Default::default(), platform);
+                let obj_path = test_util::temp_path(".o");
+                let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+                let file = object::File::parse(&*data).unwrap();
+                // They stay local to the object file, or get the prefix of shared symbols,
+                // so they can't clash with the runtime
+                let globals = file
+                    .symbols()
+                    .filter(|s| s.is_definition() && s.is_global());
+                for symbol in globals {
+                    let name = symbol.name().unwrap();
+                    let name = name.strip_prefix('_').unwrap_or(name);
+                    assert!(!names.contains(&name), "{}", name);
+                }
+            }
+        }
+        std::fs::remove_file(&source_path).unwrap();
+
+        if let Some(output) = test_util::run(source, "") {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "30\nexit\nabort\n"
+            );
+        }
+    }
+
+    #[test]
+    fn no_debug() {
+        use object::read::{Object as _, ObjectSection as _};
+
+        let (source_path, ast) = test_util::check_source("x:int = 1\nprint(x)\n");
+        let source_path_str = source_path.to_str().unwrap();
+        let options = CompileOptions {
+            no_debug: true,
+            ..Default::default()
+        };
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let code_set = x64::gen_code_set(ast.clone(), Unit::whole_program(), options, platform);
+            let obj_path = test_util::temp_path(".o");
+            let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+            let file = object::File::parse(&*data).unwrap();
+            for section in file.sections() {
+                let name = section.name().unwrap();
+                assert!(!name.contains("debug"), "{}", name);
+            }
+            // Unwind info stays
+            if platform == Platform::Windows {
+                assert!(file.section_by_name(".pdata").is_some());
+                assert!(file.section_by_name(".xdata").is_some());
+            }
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn split_debug() {
+        use object::read::{Object as _, ObjectSection as _};
+
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let (source_path, ast) = test_util::check_source("x:int = 1\nprint(x)\n");
+        let source_path_str = source_path.to_str().unwrap();
+        let obj_path = test_util::temp_path(".o");
+        let debug_path = test_util::temp_path(".debug");
+        let options = Default::default();
+        gen_object(
+            source_path_str,
+            ast.clone(),
+            options,
+            &debug_path,
+            Platform::Linux,
+        )
+        .unwrap();
+        gen_debuglinked_object(
+            source_path_str,
+            ast,
+            options,
+            &obj_path,
+            &debug_path,
+            Platform::Linux,
+        )
+        .unwrap();
+
+        let data = std::fs::read(&obj_path).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let debug_data = std::fs::read(&debug_path).unwrap();
+        let debug_file = object::File::parse(&*debug_data).unwrap();
+        let name = debug_path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(
+            file.gnu_debuglink().unwrap(),
+            Some((name.as_bytes(), crc32(&debug_data)))
+        );
+        assert!(file.section_by_name(".debug_info").is_none());
+        assert!(debug_file.section_by_name(".debug_info").is_some());
+        check_dwarf(&debug_file);
+
+        // Both have the same code, so the debug info matches the object without it
+        let text = file.section_by_name(".text").unwrap();
+        let debug_text = debug_file.section_by_name(".text").unwrap();
+        assert_eq!(text.data().unwrap(), debug_text.data().unwrap());
+
+        if crate::PLATFORM == Platform::Linux {
+            if let Some(output) = test_util::link_and_run(&[&obj_path], "") {
+                assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "1\n");
+            }
+        }
+        std::fs::remove_file(&obj_path).unwrap();
+        std::fs::remove_file(&debug_path).unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn implicit_return_line() {
+        let source = "\
+def f(x:int):
+    while x > 0:
+        x = x - 1
+    print(x)
+def g() -> int:
+    return 1
+f(g())
+";
+        let (source_path, ast) = test_util::check_source(source);
+        let source_path_str = source_path.to_str().unwrap();
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let code_set = x64::gen_code_set(
+                ast.clone(),
+                Unit::whole_program(),
+                Default::default(),
+                platform,
+            );
+            let lines = |name: &str| -> Vec<(u64, u32)> {
+                let chunk = code_set.chunks.iter().find(|c| c.name == name).unwrap();
+                if let ChunkExtra::Procedure(procedure) = &chunk.extra {
+                    procedure
+                        .lines
+                        .iter()
+                        .map(|l| (l.code_pos as u64, l.line_number))
+                        .collect()
+                } else {
+                    panic!()
+                }
+            };
+            // The implicit return after the loop gets an entry of its own
+            let f = lines("f");
+            let rows: Vec<_> = f.iter().map(|&(_, line)| line).collect();
+            assert_eq!(rows, [1, 2, 3, 4, 4]);
+            assert!(f[3].0 < f[4].0);
+            // A function that always returns has none
+            let g = lines("g");
+            assert_eq!(g.iter().map(|&(_, line)| line).collect::<Vec<_>>(), [5, 6]);
+
+            let obj_path = test_util::temp_path(".o");
+            let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+            let file = object::File::parse(&*data).unwrap();
+            let tables = if platform == Platform::Windows {
+                check_codeview(&file)
+            } else {
+                check_dwarf(&file)
+            };
+            assert!(tables.contains(&f));
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn helper_lines() {
+        let source = "\
+class A(object):
+    x: int = 1
+    s: str = \"a\"
+def cat(a: str, b: str) -> str:
+    return a + b
+x: A = None
+x = A()
+print(cat(x.s, x.s))
+";
+        let (source_path, ast) = test_util::check_source(source);
+        let source_path_str = source_path.to_str().unwrap();
+        let options = CompileOptions {
+            opt_level: 2,
+            ..Default::default()
+        };
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let code_set = x64::gen_code_set(ast.clone(), Unit::whole_program(), options, platform);
+            let lines = |name: &str| -> Vec<(u64, u32)> {
+                let chunk = code_set.chunks.iter().find(|c| c.name == name).unwrap();
+                if let ChunkExtra::Procedure(procedure) = &chunk.extra {
+                    procedure
+                        .lines
+                        .iter()
+                        .map(|l| (l.code_pos as u64, l.line_number))
+                        .collect()
+                } else {
+                    panic!()
+                }
+            };
+            let rows = |table: &[(u64, u32)]| -> Vec<u32> {
+                table.iter().map(|&(_, line)| line).collect()
+            };
+            // The constructor is on the class line, except where it initializes attributes
+            let ctor = lines("A");
+            assert_eq!(rows(&ctor), [1, 2, 3, 1]);
+            // The inlined concatenation is on its own line, then back on the calling statement
+            let main = lines(BUILTIN_CHOCOPY_MAIN);
+            assert_eq!(rows(&main), [7, 8, 5, 8]);
+
+            let obj_path = test_util::temp_path(".o");
+            let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+            let file = object::File::parse(&*data).unwrap();
+            let tables = if platform == Platform::Windows {
+                check_codeview(&file)
+            } else {
+                check_dwarf(&file)
+            };
+            assert!(tables.contains(&ctor));
+            assert!(tables.contains(&main));
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn frameless_leaves() {
+        let source = "\
+def gcd(a: int, b: int) -> int:
+    t: int = 0
+    while b != 0:
+        t = a % b
+        a = b
+        b = t
+    return a
+def sign(x: int) -> int:
+    if x < 0:
+        return -1
+    return 1 if x > 0 else 0
+def is_even(n: int) -> bool:
+    global count
+    count = count + 1
+    return n % 2 == 0
+def ratio(a: int, b: int) -> int:
+    q: int = 0
+    q = a // b
+    return q
+def boxed(n: int) -> object:
+    return n
+def run(n: int) -> str:
+    s: str = \"\"
+    k: int = 0
+    while k < n:
+        s = s + (\"e\" if is_even(gcd(k, 8)) else \"o\")
+        k = k + 1
+    return s
+count: int = 0
+l: [object] = None
+print(run(10))
+l = [boxed(gcd(84, 36)), boxed(sign(-5)), run(3)]
+print(l[0])
+print(l[1])
+print(l[2])
+print(count)
+print(ratio(84, 36))
+print(ratio(7, 0))
+";
+        let (source_path, ast) = test_util::check_source(source);
+        let source_path_str = source_path.to_str().unwrap();
+        let o2 = CompileOptions {
+            opt_level: 2,
+            ..Default::default()
+        };
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            let code_set = x64::gen_code_set(ast.clone(), Unit::whole_program(), o2, platform);
+            let frameless: Vec<_> = code_set
+                .chunks
+                .iter()
+                .filter(|chunk| {
+                    matches!(&chunk.extra, ChunkExtra::Procedure(procedure) if procedure.frameless)
+                })
+                .map(|chunk| {
+                    // sub rsp,{} instead of push rbp
+                    assert_eq!(chunk.code[..3], [0x48, 0x81, 0xEC]);
+                    chunk.name.as_str()
+                })
+                .collect();
+            // Functions that hold references, allocate or call keep their frame
+            assert_eq!(frameless, ["gcd", "sign", "is_even", "ratio"]);
+
+            let obj_path = test_util::temp_path(".o");
+            let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+            let file = object::File::parse(&*data).unwrap();
+            if platform == Platform::Windows {
+                check_codeview(&file);
+            } else {
+                check_dwarf(&file);
+            }
+        }
+
+        // Collections between the calls walk the frames of `run` and main, which the frameless
+        // functions must leave intact
+        let envs = [("CHOCOPY_GC_STRESS", "1")];
+        for options in [Default::default(), o2] {
+            let obj_path = test_util::temp_path(".o");
+            gen_object(
+                source_path_str,
+                ast.clone(),
+                options,
+                &obj_path,
+                crate::PLATFORM,
+            )
+            .unwrap();
+            if let Some(output) = test_util::link_and_run_with_env(&[&obj_path], "", &envs) {
+                assert_eq!(output.status.code(), Some(2));
+                assert_eq!(
+                    std::str::from_utf8(&output.stdout).unwrap(),
+                    "eoeoeoeoeo\n12\n-1\neoe\n13\n2\n"
+                );
+                assert_eq!(
+                    std::str::from_utf8(&output.stderr).unwrap(),
+                    "Division by zero\nExited with error code 2\n"
+                );
+            }
+            std::fs::remove_file(&obj_path).unwrap();
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn object_formats() {
+        use object::read::{Object as _, ObjectSymbol as _};
+
+        let (source_path, ast) = test_util::check_source("x:int = 1\nprint(x)\n");
+        let source_path_str = source_path.to_str().unwrap();
+        let formats = [ObjectFormat::Elf, ObjectFormat::Coff, ObjectFormat::MachO];
+        for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+            for &format in &formats {
+                let options = CompileOptions {
+                    object_format: Some(format),
+                    ..Default::default()
+                };
+                let code_set =
+                    x64::gen_code_set(ast.clone(), Unit::whole_program(), options, platform);
+                let obj_path = test_util::temp_path(".o");
+                let data = object_bytes(source_path_str, code_set, &obj_path, platform).unwrap();
+                let file = object::File::parse(&*data).unwrap();
+                assert_eq!(file.format(), format.binary_format());
+                assert_eq!(file.architecture(), target::X86_64.architecture);
+
+                let main = if format == ObjectFormat::MachO {
+                    "_$chocopy_main"
+                } else {
+                    BUILTIN_CHOCOPY_MAIN
+                };
+                assert!(file.symbols().any(|s| s.name() == Ok(main)));
+                if format == ObjectFormat::Coff {
+                    check_codeview(&file);
+                } else {
+                    check_dwarf(&file);
+                }
+            }
+        }
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn no_statements() {
+        let sources = [
+            "",
+            "x:int = 1\ny:str = \"y\"\n",
+            "class A(object):\n    x:int = 1\n    def f(self:\"A\") -> int:\n        return self.x\n",
+        ];
+        for source in &sources {
+            let (source_path, ast) = test_util::check_source(source);
+            let source_path_str = source_path.to_str().unwrap();
+
+            for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+                let obj_path = test_util::temp_path(".o");
+                gen_object(
+                    source_path_str,
+                    ast.clone(),
+                    Default::default(),
+                    &obj_path,
+                    platform,
+                )
+                .unwrap();
+
+                let data = std::fs::read(&obj_path).unwrap();
+                let file = object::File::parse(&*data).unwrap();
+                if platform == Platform::Windows {
+                    check_codeview(&file);
+                } else {
+                    check_dwarf(&file);
+                }
+
+                if platform == crate::PLATFORM {
+                    if let Some(output) = test_util::link_and_run(&[&obj_path], "") {
+                        assert!(output.status.success());
+                        assert!(output.stdout.is_empty());
+                    }
+                }
+                std::fs::remove_file(&obj_path).unwrap();
+            }
+            std::fs::remove_file(&source_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn dwarf_locals() {
+        use gimli::{EndianSlice, LittleEndian, Operation};
+        use object::read::{Object as _, ObjectSection as _};
+
+        let (source_path, ast) = test_util::check_source(
+            "def f(a:int, b:str) -> int:\n    c:int = 3\n    return a + c\nprint(f(1, \"x\"))\n",
+        );
+        let obj_path = test_util::temp_path(".o");
+        gen_object(
+            source_path.to_str().unwrap(),
+            ast,
+            Default::default(),
+            &obj_path,
+            Platform::Linux,
+        )
+        .unwrap();
+        let data = std::fs::read(&obj_path).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let dwarf = gimli::Dwarf::load(|id| -> gimli::Result<_> {
+            let data = file
+                .section_by_name(id.name())
+                .and_then(|section| section.data().ok())
+                .unwrap_or(&[]);
+            Ok(EndianSlice::new(data, LittleEndian))
+        })
+        .unwrap();
+        let header = dwarf.units().next().unwrap().unwrap();
+        let unit = dwarf.unit(header).unwrap();
+        let encoding = unit.encoding();
+
+        // Returns the single operation of an expression attribute
+        let operation = |entry: &gimli::DebuggingInformationEntry<_>, name| {
+            let expression = entry
+                .attr_value(name)
+                .unwrap()
+                .unwrap()
+                .exprloc_value()
+                .unwrap();
+            let mut operations = expression.operations(encoding);
+            let operation = operations.next().unwrap().unwrap();
+            assert!(operations.next().unwrap().is_none());
+            operation
+        };
+
+        let mut tree = unit.entries_tree(None).unwrap();
+        let root = tree.root().unwrap();
+        let mut children = root.children();
+        let mut found = false;
+        while let Some(node) = children.next().unwrap() {
+            let entry = node.entry();
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+            let name = entry.attr_value(gimli::DW_AT_name).unwrap().unwrap();
+            if dwarf.attr_string(&unit, name).unwrap().slice() != b"f" {
+                continue;
+            }
+            found = true;
+            assert_eq!(
+                operation(entry, gimli::DW_AT_frame_base),
+                Operation::Register {
+                    register: gimli::Register(6)
+                }
+            );
+
+            let mut vars = vec![];
+            let mut children = node.children();
+            while let Some(node) = children.next().unwrap() {
+                let entry = node.entry();
+                let name = entry.attr_value(gimli::DW_AT_name).unwrap().unwrap();
+                let name = dwarf.attr_string(&unit, name).unwrap().to_string().unwrap();
+                let offset = match operation(entry, gimli::DW_AT_location) {
+                    Operation::FrameOffset { offset } => offset,
+                    operation => panic!("{:?}", operation),
+                };
+                vars.push((entry.tag(), name.to_owned(), offset));
+            }
+            // Parameters are passed on the stack above the return address and saved rbp,
+            // and locals are below rbp
+            assert_eq!(
+                vars,
+                [
+                    (gimli::DW_TAG_formal_parameter, "a".to_owned(), 16),
+                    (gimli::DW_TAG_formal_parameter, "b".to_owned(), 24),
+                    (gimli::DW_TAG_variable, "c".to_owned(), -8),
+                ]
+            );
+        }
+        assert!(found);
+
+        std::fs::remove_file(&obj_path).unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    // A C program that runs the ChocoPy program between its own output
+    const STATICLIB_MAIN_C: &str = r#"
+#include <stdio.h>
+#ifdef __APPLE__
+extern void chocopy_main(void) __asm__("\"_$chocopy_main\"");
+#else
+extern void chocopy_main(void) __asm__("\"$chocopy_main\"");
+#endif
+int main(void) {
+    printf("from C\n");
+    fflush(stdout);
+    chocopy_main();
+    printf("back in C\n");
+    return 0;
+}
+"#;
+
+    #[test]
+    fn staticlib() {
+        if crate::PLATFORM == Platform::Windows {
+            return;
+        }
+        let lib_path = if let Some(lib_path) = test_util::std_lib_path(crate::PLATFORM) {
+            lib_path
+        } else {
+            return;
+        };
+        let (source_path, ast) =
+            test_util::check_source("def f(x:int) -> int:\n    return x * 2\nprint(f(21))\n");
+        let obj_path = test_util::temp_path(".o");
+        gen_object(
+            source_path.to_str().unwrap(),
+            ast,
+            Default::default(),
+            &obj_path,
+            crate::PLATFORM,
+        )
+        .unwrap();
+
+        // The same program twice would define the entry point twice
+        let archive_path = test_util::temp_path(".a");
+        let archive_str = archive_path.to_str().unwrap();
+        let error = write_staticlib_with(
+            &[&obj_path, &obj_path],
+            &lib_path,
+            archive_str,
+            crate::PLATFORM,
+        )
+        .unwrap_err();
+        assert!(
+            error.to_string().contains(BUILTIN_CHOCOPY_MAIN),
+            "{}",
+            error
+        );
+
+        write_staticlib_with(&[&obj_path], &lib_path, archive_str, crate::PLATFORM).unwrap();
+        let c_path = test_util::temp_path(".c");
+        std::fs::write(&c_path, STATICLIB_MAIN_C).unwrap();
+        let exe_path = test_util::temp_path("");
+        let status = std::process::Command::new("cc")
+            .arg("-o")
+            .args([&exe_path, &c_path, &archive_path])
+            .args(["-pthread", "-ldl"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = std::process::Command::new(&exe_path).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            std::str::from_utf8(&output.stdout).unwrap(),
+            "from C\n42\nback in C\n"
+        );
+
+        for path in &[source_path, obj_path, archive_path, c_path, exe_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    // A C program that sets up the ChocoPy program and then uses its classes
+    const EXPORT_CLASSES_MAIN_C: &str = r#"
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#ifdef __APPLE__
+extern void chocopy_main(void) __asm__("\"_$chocopy_main\"");
+#else
+extern void chocopy_main(void) __asm__("\"$chocopy_main\"");
+#endif
+extern void *chocopy$Counter(void);
+extern void *chocopy$Loud(void);
+extern int32_t chocopy$Counter$add(void *self, int32_t n, bool twice);
+extern int32_t chocopy$Counter$calls(void *self);
+extern bool chocopy$Counter$big(void *self);
+int main(void) {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    chocopy_main();
+    void *counter = chocopy$Counter();
+    printf("%d\n", chocopy$Counter$add(counter, 1, false));
+    printf("%d\n", chocopy$Counter$add(counter, 5, true));
+    printf("%d %d\n", chocopy$Counter$calls(counter), chocopy$Counter$big(counter));
+    void *loud = chocopy$Loud();
+    printf("%d\n", chocopy$Counter$add(loud, 1, true));
+    return 0;
+}
+"#;
+
+    #[test]
+    fn export_classes() {
+        if crate::PLATFORM == Platform::Windows {
+            return;
+        }
+        let lib_path = if let Some(lib_path) = test_util::std_lib_path(crate::PLATFORM) {
+            lib_path
+        } else {
+            return;
+        };
+        let source = "\
+class Counter(object):
+    count:int = 0
+    log:str = \"\"
+    def __init__(self:\"Counter\"):
+        self.count = 10
+    def add(self:\"Counter\", n:int, twice:bool) -> int:
+        self.log = self.log + \"x\"
+        if twice:
+            n = n * 2
+        self.count = self.count + n
+        return self.count
+    def calls(self:\"Counter\") -> int:
+        return len(self.log)
+    def big(self:\"Counter\") -> bool:
+        return self.count > 20
+class Loud(Counter):
+    def add(self:\"Loud\", n:int, twice:bool) -> int:
+        print(\"add\")
+        return self.count
+print(\"init\")
+";
+        let (source_path, ast) = test_util::check_source(source);
+        let obj_path = test_util::temp_path(".o");
+        let options = CompileOptions {
+            export_classes: true,
+            ..Default::default()
+        };
+        gen_object(
+            source_path.to_str().unwrap(),
+            ast,
+            options,
+            &obj_path,
+            crate::PLATFORM,
+        )
+        .unwrap();
+
+        let archive_path = test_util::temp_path(".a");
+        let archive_str = archive_path.to_str().unwrap();
+        write_staticlib_with(&[&obj_path], &lib_path, archive_str, crate::PLATFORM).unwrap();
+        let c_path = test_util::temp_path(".c");
+        std::fs::write(&c_path, EXPORT_CLASSES_MAIN_C).unwrap();
+        let exe_path = test_util::temp_path("");
+        let status = std::process::Command::new("cc")
+            .arg("-o")
+            .args([&exe_path, &c_path, &archive_path])
+            .args(["-pthread", "-ldl"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // Every allocation collects garbage, which must find the bottom frame of each call
+        let output = std::process::Command::new(&exe_path)
+            .env("CHOCOPY_GC_STRESS", "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            std::str::from_utf8(&output.stdout).unwrap(),
+            "init\n11\n21\n2 1\nadd\n10\n"
+        );
+
+        for path in &[source_path, obj_path, archive_path, c_path, exe_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    // A C program in place of a ChocoPy program that prints the layout from the standard library.
+    // LAYOUT_VALUES is defined by the test.
+    const LAYOUT_MAIN_C: &str = r#"
+#include <stdint.h>
+#include <stdio.h>
+struct layout {
+    uint32_t values[LAYOUT_VALUES];
+};
+#ifdef __APPLE__
+extern struct layout layout(void) __asm__("\"_$layout\"");
+void chocopy_main(void) __asm__("\"_$chocopy_main\"");
+#else
+extern struct layout layout(void) __asm__("\"$layout\"");
+void chocopy_main(void) __asm__("\"$chocopy_main\"");
+#endif
+void chocopy_main(void) {
+    struct layout values = layout();
+    for (int i = 0; i < LAYOUT_VALUES; ++i) {
+        printf("%u\n", values.values[i]);
+    }
+}
+"#;
+
+    #[test]
+    fn std_layout() {
+        use chocopy_rs_common::layout::Layout;
+        if crate::PLATFORM == Platform::Windows {
+            return;
+        }
+        let lib_path = if let Some(lib_path) = test_util::std_lib_path(crate::PLATFORM) {
+            lib_path
+        } else {
+            return;
+        };
+        let c_path = test_util::temp_path(".c");
+        let count = std::mem::size_of::<Layout>() / 4;
+        let c_source = format!("#define LAYOUT_VALUES {}\n{}", count, LAYOUT_MAIN_C);
+        std::fs::write(&c_path, c_source).unwrap();
+        let exe_path = test_util::temp_path("");
+        let status = std::process::Command::new("cc")
+            .arg("-o")
+            .args([&exe_path, &c_path, &lib_path])
+            .args(["-pthread", "-ldl"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // The library is built separately, possibly from another version of the constants
+        let output = std::process::Command::new(&exe_path).output().unwrap();
+        assert!(output.status.success());
+        let values: Vec<u32> = std::str::from_utf8(&output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        let std_layout = Layout::from_values(&values).unwrap();
+        let mismatches = Layout::constants().mismatches(&std_layout);
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+
+        for path in &[c_path, exe_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// Description of the target architecture for the object writer and code generators.
+// All multi-byte values in generated code and data are encoded through `Endian`,
+// so that byte order is decided in one place.
+
+use object::{Architecture, Endianness};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Endian {
+    Little,
+    Big, // No big-endian target yet, but archive symbol indices are big-endian
+}
+
+// Integers that can be written into generated code and data
+pub trait TargetBytes: Copy {
+    type Bytes: AsRef<[u8]>;
+    fn to_bytes(self, endian: Endian) -> Self::Bytes;
+}
+
+macro_rules! impl_target_bytes {
+    ($($t:ty),*) => {
+        $(impl TargetBytes for $t {
+            type Bytes = [u8; std::mem::size_of::<$t>()];
+            fn to_bytes(self, endian: Endian) -> Self::Bytes {
+                match endian {
+                    Endian::Little => self.to_le_bytes(),
+                    Endian::Big => self.to_be_bytes(),
+                }
+            }
+        })*
+    };
+}
+
+impl_target_bytes!(u16, i16, u32, i32, u64, i64);
+
+impl Endian {
+    pub fn bytes<T: TargetBytes>(self, value: T) -> T::Bytes {
+        value.to_bytes(self)
+    }
+
+    // Overwrite the start of the buffer with the value
+    pub fn write<T: TargetBytes>(self, buffer: &mut [u8], value: T) {
+        let bytes = value.to_bytes(self);
+        buffer[..bytes.as_ref().len()].copy_from_slice(bytes.as_ref());
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Target {
+    pub architecture: Architecture,
+    pub endian: Endian,
+}
+
+impl Target {
+    pub fn endianness(&self) -> Endianness {
+        match self.endian {
+            Endian::Little => Endianness::Little,
+            Endian::Big => Endianness::Big,
+        }
+    }
+}
+
+pub const X86_64: Target = Target {
+    architecture: Architecture::X86_64,
+    endian: Endian::Little,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_order() {
+        assert_eq!(Endian::Little.bytes(0x0102_0304u32), [4, 3, 2, 1]);
+        assert_eq!(Endian::Big.bytes(0x0102_0304u32), [1, 2, 3, 4]);
+        assert_eq!(Endian::Big.bytes(-2i16), [0xFF, 0xFE]);
+
+        let mut buffer = [0; 6];
+        Endian::Big.write(&mut buffer[1..], 0x0A0Bu16);
+        assert_eq!(buffer, [0, 0x0A, 0x0B, 0, 0, 0]);
+    }
+
+    #[test]
+    fn no_direct_byte_order() {
+        // Code generation outside of tests encodes multi-byte values only through Endian
+        for entry in std::fs::read_dir("src/gen").unwrap() {
+            let path = entry.unwrap().path();
+            if path.file_name().unwrap() == "target.rs" {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            let code = source.split("#[cfg(test)]\nmod tests").next().unwrap();
+            for (i, line) in code.lines().enumerate() {
+                assert!(
+                    !line.contains("to_le_bytes") && !line.contains("to_be_bytes"),
+                    "{}:{}: {}",
+                    path.display(),
+                    i + 1,
+                    line.trim()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// Helpers for tests that compile, link and run ChocoPy programs on the host platform
+
+use super::*;
+use std::process::{Command, Output, Stdio};
+
+// Path to a temporary file with a unique name
+pub fn temp_path(extension: &str) -> PathBuf {
+    chocopy_rs_common::temp::temp_path(extension)
+}
+
+// Find the standard library built in the same target directory as the test.
+// Returns None if it is not built yet, in which case execution tests are skipped.
+pub fn std_lib_path(platform: Platform) -> Option<PathBuf> {
+    let mut lib_path = std::env::current_exe().ok()?;
+    lib_path.pop();
+    lib_path.pop();
+    lib_path.push(std_lib_name(platform));
+    if lib_path.exists() {
+        Some(lib_path)
+    } else {
+        eprintln!("Skipped execution: {} not built", lib_path.display());
+        None
+    }
+}
+
+// Link the object files for the host platform and run it with the input
+pub fn link_and_run(obj_paths: &[&Path], input: &str) -> Option<Output> {
+    link_and_run_with_env(obj_paths, input, &[])
+}
+
+// Link the object files into an executable for the host platform, and return its path
+pub fn link(obj_paths: &[&Path]) -> Option<String> {
+    let platform = crate::PLATFORM;
+    let lib_path = std_lib_path(platform)?;
+    let exe_path = temp_path(if platform == Platform::Windows {
+        ".exe"
+    } else {
+        ""
+    });
+    let exe_path = exe_path.to_str().unwrap();
+    link_with(obj_paths, &lib_path, exe_path, false, None, platform).unwrap();
+    Some(exe_path.to_owned())
+}
+
+// Same as `link_and_run`, with additional environment variables for the program
+pub fn link_and_run_with_env(
+    obj_paths: &[&Path],
+    input: &str,
+    envs: &[(&str, &str)],
+) -> Option<Output> {
+    let exe_path = link(obj_paths)?;
+    let output = run_exe(&exe_path, input, envs);
+    std::fs::remove_file(&exe_path).unwrap();
+    Some(output)
+}
+
+// Write the executable to a temporary file and run it with the input
+pub fn run_executable(executable: &[u8], input: &str) -> Output {
+    let exe_path = temp_path("");
+    std::fs::write(&exe_path, executable).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&exe_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let exe_path = exe_path.to_str().unwrap();
+    let output = run_exe(exe_path, input, &[]);
+    std::fs::remove_file(exe_path).unwrap();
+    output
+}
+
+fn run_exe(exe_path: &str, input: &str, envs: &[(&str, &str)]) -> Output {
+    let mut process = Command::new(exe_path)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    process
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    process.wait_with_output().unwrap()
+}
+
+// C runtime files that `cc -static` links with, in the order that build.rs embeds them
+const C_START_FILES: [&str; 3] = ["crt1.o", "crti.o", "crtbeginT.o"];
+const C_LIBRARIES: [&str; 3] = ["libgcc.a", "libgcc_eh.a", "libc.a"];
+const C_END_FILES: [&str; 2] = ["crtend.o", "crtn.o"];
+
+// Call the function with the runtime that static Linux executables are linked with.
+// Returns None if the standard library is not built or the static C library is not installed.
+pub fn with_linux_runtime<T>(f: impl FnOnce(&elf_link::Runtime) -> T) -> Option<T> {
+    let read = |name: &str| {
+        let output = Command::new("cc")
+            .arg(format!("-print-file-name={}", name))
+            .output()
+            .ok()?;
+        // The name is printed as it is if the file is not found
+        let path = String::from_utf8(output.stdout).ok()?;
+        let data = std::fs::read(path.trim());
+        if data.is_err() {
+            eprintln!("Skipped execution: {} not found", name);
+        }
+        data.ok()
+    };
+    let read_all = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| read(name))
+            .collect::<Option<Vec<_>>>()
+    };
+
+    let start_files = read_all(&C_START_FILES)?;
+    let mut archives = vec![std::fs::read(std_lib_path(Platform::Linux)?).ok()?];
+    archives.extend(read_all(&C_LIBRARIES)?);
+    let end_files = read_all(&C_END_FILES)?;
+    fn slices(files: &[Vec<u8>]) -> Vec<&[u8]> {
+        files.iter().map(Vec::as_slice).collect()
+    }
+    Some(f(&elf_link::Runtime {
+        start_files: slices(&start_files),
+        archives: slices(&archives),
+        end_files: slices(&end_files),
+    }))
+}
+
+// Write the source code to a temporary file, then parse and check it.
+// Panics if the source code has errors.
+pub fn check_source(source: &str) -> (PathBuf, Program) {
+    let source_path = temp_path(".py");
+    std::fs::write(&source_path, source).unwrap();
+
+    let ast = crate::parse::process(source_path.to_str().unwrap(), Default::default()).unwrap();
+    assert!(ast.errors.errors.is_empty(), "{:?}", ast.errors.errors);
+    let ast = crate::check::check(ast, Default::default());
+    assert!(ast.errors.errors.is_empty(), "{:?}", ast.errors.errors);
+    (source_path, ast)
+}
+
+// Compile the source code and run it with the input.
+// Panics if the source code has errors.
+pub fn run(source: &str, input: &str) -> Option<Output> {
+    run_with_options(source, input, Default::default())
+}
+
+// Same as `run`, but with code generation options
+pub fn run_with_options(source: &str, input: &str, options: CompileOptions) -> Option<Output> {
+    compile_and_run(source, input, options, &[])
+}
+
+// Same as `run`, with additional environment variables for the program
+pub fn run_with_env(source: &str, input: &str, envs: &[(&str, &str)]) -> Option<Output> {
+    compile_and_run(source, input, Default::default(), envs)
+}
+
+fn compile_and_run(
+    source: &str,
+    input: &str,
+    options: CompileOptions,
+    envs: &[(&str, &str)],
+) -> Option<Output> {
+    let (source_path, ast) = check_source(source);
+    let source_path_str = source_path.to_str().unwrap();
+
+    let obj_path = temp_path(".o");
+    gen_object(source_path_str, ast, options, &obj_path, crate::PLATFORM).unwrap();
+    let output = link_and_run_with_env(&[&obj_path], input, envs);
+
+    std::fs::remove_file(&obj_path).unwrap();
+    std::fs::remove_file(&source_path).unwrap();
+    output
+}
+
+// Compile the typed AST stored next to a source file in the test directory, and run it
+// with the environment variables. Panics if the AST has errors.
+pub fn run_fixture(
+    source_path: &str,
+    options: CompileOptions,
+    envs: &[(&str, &str)],
+) -> Option<Output> {
+    let ast = crate::parse::load_ast(&(source_path.to_owned() + ".ast.typed")).unwrap();
+    assert!(ast.errors.errors.is_empty(), "{:?}", ast.errors.errors);
+
+    let obj_path = temp_path(".o");
+    gen_object(source_path, ast, options, &obj_path, crate::PLATFORM).unwrap();
+    let output = link_and_run_with_env(&[&obj_path], "", envs);
+
+    std::fs::remove_file(&obj_path).unwrap();
+    output
+}
@@ -0,0 +1,1217 @@
+// WebAssembly backend, for running programs in the browser.
+//
+// This first version covers programs made of global variables of type int, bool and str, and
+// top-level statements using them. Functions, classes, lists and None are reported as unsupported.
+//
+// The module is written directly instead of through the `object` crate:
+//  - ints and bools are i32 values, and str values are pointers into the linear memory.
+//  - A str is its length as i32 followed by its bytes. Literals live in the data segment.
+//  - New strings are taken from a bump allocator and never freed.
+//  - print, input and runtime errors are imported from the JavaScript runtime in
+//    chocopy-rs-std/wasm.js, which calls back into the exported `alloc` for input strings.
+
+use super::target::Endian;
+use crate::node::*;
+use std::collections::HashMap;
+
+// Imported functions from the runtime
+const IMPORT_PRINT_INT: u32 = 0;
+const IMPORT_PRINT_BOOL: u32 = 1;
+const IMPORT_PRINT_STR: u32 = 2;
+const IMPORT_INPUT: u32 = 3;
+const IMPORT_ERROR: u32 = 4;
+
+// Functions defined by the module, numbered after the imports
+const FUNC_ALLOC: u32 = 5;
+const FUNC_STR_CONCAT: u32 = 6;
+const FUNC_STR_EQ: u32 = 7;
+const FUNC_STR_CHAR: u32 = 8;
+const FUNC_INT_DIV: u32 = 9;
+const FUNC_INT_MOD: u32 = 10;
+const FUNC_INT_SHL: u32 = 11;
+const FUNC_INT_SHR: u32 = 12;
+const FUNC_MAIN: u32 = 13;
+
+// Function types
+const TYPE_I32_TO_NONE: u32 = 0;
+const TYPE_NONE_TO_I32: u32 = 1;
+const TYPE_I32_TO_I32: u32 = 2;
+const TYPE_I32_I32_TO_I32: u32 = 3;
+const TYPE_NONE_TO_NONE: u32 = 4;
+
+// The bump allocator's next free address. Program variables follow it
+const GLOBAL_HEAP: u32 = 0;
+
+// Error codes, the same as the exit codes of the native runtime
+const ERROR_INVALID_ARG: i32 = 1;
+const ERROR_DIV_ZERO: i32 = 2;
+const ERROR_OUT_OF_BOUND: i32 = 3;
+
+// Address 0 is left unused, so that no str is at it
+const DATA_START: u32 = 8;
+
+const PAGE_SIZE: u32 = 0x10000;
+
+const I32: u8 = 0x7F;
+const BLOCK_EMPTY: u8 = 0x40;
+
+#[derive(Debug)]
+pub struct UnsupportedError(String);
+
+impl std::fmt::Display for UnsupportedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WebAssembly output doesn't support {} yet", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedError {}
+
+fn unsupported<T>(what: &str) -> Result<T, UnsupportedError> {
+    Err(UnsupportedError(what.to_owned()))
+}
+
+fn write_uleb(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_sleb(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_uleb(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_vec(out: &mut Vec<u8>, items: &[Vec<u8>]) {
+    write_uleb(out, items.len() as u32);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, items: &[Vec<u8>]) {
+    let mut content = vec![];
+    write_vec(&mut content, items);
+    out.push(id);
+    write_uleb(out, content.len() as u32);
+    out.extend(content);
+}
+
+// Instruction stream of a function body
+#[derive(Default)]
+struct Code(Vec<u8>);
+
+impl Code {
+    fn i32_const(&mut self, value: i32) {
+        self.0.push(0x41);
+        write_sleb(&mut self.0, value);
+    }
+
+    fn op(&mut self, opcode: u8) {
+        self.0.push(opcode);
+    }
+
+    fn op_index(&mut self, opcode: u8, index: u32) {
+        self.0.push(opcode);
+        write_uleb(&mut self.0, index);
+    }
+
+    fn local_get(&mut self, index: u32) {
+        self.op_index(0x20, index);
+    }
+
+    fn local_set(&mut self, index: u32) {
+        self.op_index(0x21, index);
+    }
+
+    fn local_tee(&mut self, index: u32) {
+        self.op_index(0x22, index);
+    }
+
+    fn global_get(&mut self, index: u32) {
+        self.op_index(0x23, index);
+    }
+
+    fn global_set(&mut self, index: u32) {
+        self.op_index(0x24, index);
+    }
+
+    fn call(&mut self, function: u32) {
+        self.op_index(0x10, function);
+    }
+
+    fn br(&mut self, depth: u32) {
+        self.op_index(0x0C, depth);
+    }
+
+    fn br_if(&mut self, depth: u32) {
+        self.op_index(0x0D, depth);
+    }
+
+    // Memory access at `offset` from the address on the stack
+    fn memory(&mut self, opcode: u8, align: u32, offset: u32) {
+        self.0.push(opcode);
+        write_uleb(&mut self.0, align);
+        write_uleb(&mut self.0, offset);
+    }
+
+    fn load(&mut self, offset: u32) {
+        self.memory(0x28, 2, offset);
+    }
+
+    fn load8(&mut self, offset: u32) {
+        self.memory(0x2D, 0, offset);
+    }
+
+    fn store(&mut self, offset: u32) {
+        self.memory(0x36, 2, offset);
+    }
+
+    fn store8(&mut self, offset: u32) {
+        self.memory(0x3A, 0, offset);
+    }
+
+    fn block(&mut self, opcode: u8, block_type: u8) {
+        self.0.push(opcode);
+        self.0.push(block_type);
+    }
+
+    fn if_(&mut self, block_type: u8) {
+        self.block(0x04, block_type);
+    }
+
+    fn else_(&mut self) {
+        self.op(0x05);
+    }
+
+    fn end(&mut self) {
+        self.op(0x0B);
+    }
+
+    // Report a runtime error. Control doesn't come back
+    fn error(&mut self, code: i32) {
+        self.i32_const(code);
+        self.call(IMPORT_ERROR);
+        self.op(0x00); // unreachable
+    }
+
+    // Function body with `locals` i32 locals after the parameters
+    fn body(mut self, locals: u32) -> Vec<u8> {
+        self.end();
+        let mut content = vec![];
+        if locals == 0 {
+            write_uleb(&mut content, 0);
+        } else {
+            write_uleb(&mut content, 1);
+            write_uleb(&mut content, locals);
+            content.push(I32);
+        }
+        content.extend(self.0);
+        let mut body = vec![];
+        write_uleb(&mut body, content.len() as u32);
+        body.extend(content);
+        body
+    }
+}
+
+const OP_I32_EQZ: u8 = 0x45;
+const OP_I32_EQ: u8 = 0x46;
+const OP_I32_NE: u8 = 0x47;
+const OP_I32_LT_S: u8 = 0x48;
+const OP_I32_GT_S: u8 = 0x4A;
+const OP_I32_GT_U: u8 = 0x4B;
+const OP_I32_LE_S: u8 = 0x4C;
+const OP_I32_GE_S: u8 = 0x4E;
+const OP_I32_GE_U: u8 = 0x4F;
+const OP_I32_ADD: u8 = 0x6A;
+const OP_I32_SUB: u8 = 0x6B;
+const OP_I32_MUL: u8 = 0x6C;
+const OP_I32_DIV_S: u8 = 0x6D;
+const OP_I32_REM_S: u8 = 0x6F;
+const OP_I32_AND: u8 = 0x71;
+const OP_I32_OR: u8 = 0x72;
+const OP_I32_XOR: u8 = 0x73;
+const OP_I32_SHL: u8 = 0x74;
+const OP_I32_SHR_S: u8 = 0x75;
+const OP_I32_SHR_U: u8 = 0x76;
+const OP_RETURN: u8 = 0x0F;
+const OP_BLOCK: u8 = 0x02;
+const OP_LOOP: u8 = 0x03;
+
+// alloc(size) -> address, 4-byte aligned. Grows the memory when needed
+fn gen_alloc() -> Vec<u8> {
+    let mut code = Code::default();
+    code.global_get(GLOBAL_HEAP);
+    code.local_tee(1);
+    code.local_get(0);
+    code.op(OP_I32_ADD);
+    code.i32_const(3);
+    code.op(OP_I32_ADD);
+    code.i32_const(-4);
+    code.op(OP_I32_AND);
+    code.global_set(GLOBAL_HEAP);
+
+    // Memory size in bytes
+    code.global_get(GLOBAL_HEAP);
+    code.op(0x3F); // memory.size
+    code.op(0x00);
+    code.i32_const(16);
+    code.op(OP_I32_SHL);
+    code.op(OP_I32_GT_U);
+    code.if_(BLOCK_EMPTY);
+    code.global_get(GLOBAL_HEAP);
+    code.op(0x3F);
+    code.op(0x00);
+    code.i32_const(16);
+    code.op(OP_I32_SHL);
+    code.op(OP_I32_SUB);
+    code.i32_const(PAGE_SIZE as i32 - 1);
+    code.op(OP_I32_ADD);
+    code.i32_const(16);
+    code.op(OP_I32_SHR_U);
+    code.op(0x40); // memory.grow
+    code.op(0x00);
+    code.i32_const(-1);
+    code.op(OP_I32_EQ);
+    code.if_(BLOCK_EMPTY);
+    code.op(0x00); // Out of memory
+    code.end();
+    code.end();
+
+    code.local_get(1);
+    code.body(1)
+}
+
+// memory.copy(destination, source, size), from the bulk memory operations
+fn memory_copy(code: &mut Code) {
+    code.op(0xFC);
+    write_uleb(&mut code.0, 10);
+    code.op(0x00);
+    code.op(0x00);
+}
+
+// str_concat(a, b) -> a + b
+fn gen_str_concat() -> Vec<u8> {
+    let (a, b, a_len, b_len, result) = (0, 1, 2, 3, 4);
+    let mut code = Code::default();
+    code.local_get(a);
+    code.load(0);
+    code.local_set(a_len);
+    code.local_get(b);
+    code.load(0);
+    code.local_set(b_len);
+
+    code.local_get(a_len);
+    code.local_get(b_len);
+    code.op(OP_I32_ADD);
+    code.i32_const(4);
+    code.op(OP_I32_ADD);
+    code.call(FUNC_ALLOC);
+    code.local_tee(result);
+    code.local_get(a_len);
+    code.local_get(b_len);
+    code.op(OP_I32_ADD);
+    code.store(0);
+
+    code.local_get(result);
+    code.i32_const(4);
+    code.op(OP_I32_ADD);
+    code.local_get(a);
+    code.i32_const(4);
+    code.op(OP_I32_ADD);
+    code.local_get(a_len);
+    memory_copy(&mut code);
+
+    code.local_get(result);
+    code.i32_const(4);
+    code.op(OP_I32_ADD);
+    code.local_get(a_len);
+    code.op(OP_I32_ADD);
+    code.local_get(b);
+    code.i32_const(4);
+    code.op(OP_I32_ADD);
+    code.local_get(b_len);
+    memory_copy(&mut code);
+
+    code.local_get(result);
+    code.body(3)
+}
+
+// str_eq(a, b) -> a == b
+fn gen_str_eq() -> Vec<u8> {
+    let (a, b, len, i) = (0, 1, 2, 3);
+    let mut code = Code::default();
+    code.local_get(a);
+    code.load(0);
+    code.local_tee(len);
+    code.local_get(b);
+    code.load(0);
+    code.op(OP_I32_NE);
+    code.if_(BLOCK_EMPTY);
+    code.i32_const(0);
+    code.op(OP_RETURN);
+    code.end();
+
+    code.block(OP_BLOCK, BLOCK_EMPTY);
+    code.block(OP_LOOP, BLOCK_EMPTY);
+    code.local_get(i);
+    code.local_get(len);
+    code.op(OP_I32_GE_U);
+    code.br_if(1);
+    code.local_get(a);
+    code.local_get(i);
+    code.op(OP_I32_ADD);
+    code.load8(4);
+    code.local_get(b);
+    code.local_get(i);
+    code.op(OP_I32_ADD);
+    code.load8(4);
+    code.op(OP_I32_NE);
+    code.if_(BLOCK_EMPTY);
+    code.i32_const(0);
+    code.op(OP_RETURN);
+    code.end();
+    code.local_get(i);
+    code.i32_const(1);
+    code.op(OP_I32_ADD);
+    code.local_set(i);
+    code.br(0);
+    code.end();
+    code.end();
+
+    code.i32_const(1);
+    code.body(2)
+}
+
+// str_char(s, i) -> s[i], as a new str of length 1
+fn gen_str_char() -> Vec<u8> {
+    let (s, i, result) = (0, 1, 2);
+    let mut code = Code::default();
+    // Negative indices are out of bounds too when compared as unsigned
+    code.local_get(i);
+    code.local_get(s);
+    code.load(0);
+    code.op(OP_I32_GE_U);
+    code.if_(BLOCK_EMPTY);
+    code.error(ERROR_OUT_OF_BOUND);
+    code.end();
+
+    code.i32_const(5);
+    code.call(FUNC_ALLOC);
+    code.local_tee(result);
+    code.i32_const(1);
+    code.store(0);
+    code.local_get(result);
+    code.local_get(s);
+    code.local_get(i);
+    code.op(OP_I32_ADD);
+    code.load8(4);
+    code.store8(4);
+    code.local_get(result);
+    code.body(1)
+}
+
+// Push whether a and b (locals 0 and 1) have different signs
+fn signs_differ(code: &mut Code, a: u32, b: u32) {
+    code.local_get(a);
+    code.i32_const(0);
+    code.op(OP_I32_LT_S);
+    code.local_get(b);
+    code.i32_const(0);
+    code.op(OP_I32_LT_S);
+    code.op(OP_I32_NE);
+}
+
+fn check_div_zero(code: &mut Code, b: u32) {
+    code.local_get(b);
+    code.op(OP_I32_EQZ);
+    code.if_(BLOCK_EMPTY);
+    code.error(ERROR_DIV_ZERO);
+    code.end();
+}
+
+// int_div(a, b) -> a // b, rounding towards negative infinity
+fn gen_int_div() -> Vec<u8> {
+    let (a, b, q) = (0, 1, 2);
+    let mut code = Code::default();
+    check_div_zero(&mut code, b);
+
+    // i32.div_s traps on overflow, but the quotient wraps around in ChocoPy
+    code.local_get(b);
+    code.i32_const(-1);
+    code.op(OP_I32_EQ);
+    code.if_(BLOCK_EMPTY);
+    code.i32_const(0);
+    code.local_get(a);
+    code.op(OP_I32_SUB);
+    code.op(OP_RETURN);
+    code.end();
+
+    code.local_get(a);
+    code.local_get(b);
+    code.op(OP_I32_DIV_S);
+    code.local_set(q);
+    code.local_get(a);
+    code.local_get(b);
+    code.op(OP_I32_REM_S);
+    code.i32_const(0);
+    code.op(OP_I32_NE);
+    signs_differ(&mut code, a, b);
+    code.op(OP_I32_AND);
+    code.if_(BLOCK_EMPTY);
+    code.local_get(q);
+    code.i32_const(1);
+    code.op(OP_I32_SUB);
+    code.op(OP_RETURN);
+    code.end();
+    code.local_get(q);
+    code.body(1)
+}
+
+// int_mod(a, b) -> a % b, with the sign of b
+fn gen_int_mod() -> Vec<u8> {
+    let (a, b, r) = (0, 1, 2);
+    let mut code = Code::default();
+    check_div_zero(&mut code, b);
+
+    code.local_get(a);
+    code.local_get(b);
+    code.op(OP_I32_REM_S);
+    code.local_tee(r);
+    code.i32_const(0);
+    code.op(OP_I32_NE);
+    signs_differ(&mut code, r, b);
+    code.op(OP_I32_AND);
+    code.if_(BLOCK_EMPTY);
+    code.local_get(r);
+    code.local_get(b);
+    code.op(OP_I32_ADD);
+    code.op(OP_RETURN);
+    code.end();
+    code.local_get(r);
+    code.body(1)
+}
+
+// int_shl(a, b) and int_shr(a, b) for the bitwise extension.
+// Negative shift counts are errors, and larger counts than 31 don't wrap around
+fn gen_int_shift(left: bool) -> Vec<u8> {
+    let (a, b) = (0, 1);
+    let mut code = Code::default();
+    code.local_get(b);
+    code.i32_const(0);
+    code.op(OP_I32_LT_S);
+    code.if_(BLOCK_EMPTY);
+    code.error(ERROR_INVALID_ARG);
+    code.end();
+
+    code.local_get(a);
+    if left {
+        code.local_get(b);
+        code.i32_const(31);
+        code.op(OP_I32_GT_S);
+        code.if_(BLOCK_EMPTY);
+        code.i32_const(0);
+        code.op(OP_RETURN);
+        code.end();
+        code.local_get(b);
+        code.op(OP_I32_SHL);
+    } else {
+        code.local_get(b);
+        code.i32_const(31);
+        code.local_get(b);
+        code.i32_const(31);
+        code.op(OP_I32_LE_S);
+        code.op(0x1B); // select
+        code.op(OP_I32_SHR_S);
+    }
+    code.body(0)
+}
+
+// Data segment with the string literals
+struct Data {
+    bytes: Vec<u8>,
+    strings: HashMap<String, u32>,
+}
+
+impl Data {
+    // Address of a str with the value
+    fn string(&mut self, value: &str) -> u32 {
+        if let Some(&address) = self.strings.get(value) {
+            return address;
+        }
+        let address = DATA_START + self.bytes.len() as u32;
+        self.bytes
+            .extend_from_slice(&Endian::Little.bytes(value.len() as u32));
+        self.bytes.extend_from_slice(value.as_bytes());
+        while !self.bytes.len().is_multiple_of(4) {
+            self.bytes.push(0);
+        }
+        self.strings.insert(value.to_owned(), address);
+        address
+    }
+}
+
+struct Emitter<'a> {
+    code: Code,
+    locals: u32,
+    globals: &'a HashMap<String, u32>,
+    data: &'a mut Data,
+}
+
+fn is_type(expr: &Expr, value_type: &ValueType) -> bool {
+    expr.inferred_type.as_ref() == Some(value_type)
+}
+
+impl<'a> Emitter<'a> {
+    fn new_local(&mut self) -> u32 {
+        self.locals += 1;
+        self.locals - 1
+    }
+
+    fn global(&self, name: &str) -> u32 {
+        self.globals[name]
+    }
+
+    fn emit_call(&mut self, expr: &CallExpr) -> Result<(), UnsupportedError> {
+        match expr.function.name.as_str() {
+            "print" => {
+                let arg = &expr.args[0];
+                let function = if is_type(arg, &TYPE_INT) {
+                    IMPORT_PRINT_INT
+                } else if is_type(arg, &TYPE_BOOL) {
+                    IMPORT_PRINT_BOOL
+                } else if is_type(arg, &TYPE_STR) {
+                    IMPORT_PRINT_STR
+                } else {
+                    return unsupported("printing values other than int, bool and str");
+                };
+                self.emit_expression(arg)?;
+                self.code.call(function);
+                // print returns None, which is only used by expression statements
+                self.code.i32_const(0);
+            }
+            "len" => {
+                let arg = &expr.args[0];
+                self.emit_expression(arg)?;
+                if is_type(arg, &TYPE_STR) {
+                    self.code.load(0);
+                } else if is_type(arg, &TYPE_INT) || is_type(arg, &TYPE_BOOL) {
+                    self.code.op(0x1A); // drop
+                    self.code.error(ERROR_INVALID_ARG);
+                } else {
+                    return unsupported("len() of values other than str");
+                }
+            }
+            "input" => self.code.call(IMPORT_INPUT),
+            _ => return unsupported("calling functions other than print, len and input"),
+        }
+        Ok(())
+    }
+
+    fn emit_binary(&mut self, expr: &BinaryExpr) -> Result<(), UnsupportedError> {
+        // Short-circuiting operators
+        if matches!(expr.operator, BinaryOp::And | BinaryOp::Or) {
+            if !is_type(&expr.left, &TYPE_BOOL) || !is_type(&expr.right, &TYPE_BOOL) {
+                return unsupported("`and` and `or` on values other than bool");
+            }
+            self.emit_expression(&expr.left)?;
+            self.code.if_(I32);
+            if expr.operator == BinaryOp::And {
+                self.emit_expression(&expr.right)?;
+                self.code.else_();
+                self.code.i32_const(0);
+            } else {
+                self.code.i32_const(1);
+                self.code.else_();
+                self.emit_expression(&expr.right)?;
+            }
+            self.code.end();
+            return Ok(());
+        }
+
+        let is_str = is_type(&expr.left, &TYPE_STR);
+        self.emit_expression(&expr.left)?;
+        self.emit_expression(&expr.right)?;
+        match expr.operator {
+            BinaryOp::Add if is_str => self.code.call(FUNC_STR_CONCAT),
+            BinaryOp::Eq if is_str => self.code.call(FUNC_STR_EQ),
+            BinaryOp::Ne if is_str => {
+                self.code.call(FUNC_STR_EQ);
+                self.code.op(OP_I32_EQZ);
+            }
+            BinaryOp::Add => self.code.op(OP_I32_ADD),
+            BinaryOp::Sub => self.code.op(OP_I32_SUB),
+            BinaryOp::Mul => self.code.op(OP_I32_MUL),
+            BinaryOp::Div => self.code.call(FUNC_INT_DIV),
+            BinaryOp::Mod => self.code.call(FUNC_INT_MOD),
+            BinaryOp::Eq => self.code.op(OP_I32_EQ),
+            BinaryOp::Ne => self.code.op(OP_I32_NE),
+            BinaryOp::Lt => self.code.op(OP_I32_LT_S),
+            BinaryOp::Gt => self.code.op(OP_I32_GT_S),
+            BinaryOp::Le => self.code.op(OP_I32_LE_S),
+            BinaryOp::Ge => self.code.op(OP_I32_GE_S),
+            BinaryOp::BitAnd => self.code.op(OP_I32_AND),
+            BinaryOp::BitOr => self.code.op(OP_I32_OR),
+            BinaryOp::BitXor => self.code.op(OP_I32_XOR),
+            BinaryOp::Shl => self.code.call(FUNC_INT_SHL),
+            BinaryOp::Shr => self.code.call(FUNC_INT_SHR),
+            BinaryOp::Is => return unsupported("`is`"),
+            BinaryOp::In => return unsupported("`in`"),
+            BinaryOp::And | BinaryOp::Or => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // Push the value of the expression as i32
+    fn emit_expression(&mut self, expr: &Expr) -> Result<(), UnsupportedError> {
+        match &expr.content {
+            ExprContent::IntegerLiteral(literal) => self.code.i32_const(literal.value),
+            ExprContent::BooleanLiteral(literal) => self.code.i32_const(literal.value as i32),
+            ExprContent::StringLiteral(literal) => {
+                let address = self.data.string(&literal.value);
+                self.code.i32_const(address as i32);
+            }
+            ExprContent::Variable(variable) => self.code.global_get(self.global(&variable.name)),
+            ExprContent::UnaryExpr(expr) => match expr.operator {
+                UnaryOp::Negative => {
+                    self.code.i32_const(0);
+                    self.emit_expression(&expr.operand)?;
+                    self.code.op(OP_I32_SUB);
+                }
+                UnaryOp::Not => {
+                    self.emit_expression(&expr.operand)?;
+                    self.code.op(OP_I32_EQZ);
+                }
+            },
+            ExprContent::BinaryExpr(expr) => self.emit_binary(expr)?,
+            ExprContent::IfExpr(expr) => {
+                self.emit_expression(&expr.condition)?;
+                self.code.if_(I32);
+                self.emit_expression(&expr.then_expr)?;
+                self.code.else_();
+                self.emit_expression(&expr.else_expr)?;
+                self.code.end();
+            }
+            ExprContent::IndexExpr(expr) => {
+                self.emit_expression(&expr.list)?;
+                self.emit_expression(&expr.index)?;
+                self.code.call(FUNC_STR_CHAR);
+            }
+            ExprContent::CallExpr(expr) => self.emit_call(expr)?,
+            ExprContent::NoneLiteral(_) => return unsupported("None"),
+            ExprContent::ListExpr(_) => return unsupported("lists"),
+            ExprContent::MemberExpr(_) | ExprContent::MethodCallExpr(_) => {
+                return unsupported("classes")
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_for(&mut self, stmt: &ForStmt) -> Result<(), UnsupportedError> {
+        let target = self.global(&stmt.identifier.name);
+        let index = stmt.index.as_ref().map(|index| self.global(&index.name));
+        let counter = self.new_local();
+        let bound = self.new_local();
+        let range_args = stmt.range_args(|name| self.globals.contains_key(name));
+
+        // For a str, the counter is the index and the bound is the length
+        let iterable = if let Some(args) = range_args {
+            if args.len() == 2 {
+                self.emit_expression(&args[0])?;
+            } else {
+                self.code.i32_const(0);
+            }
+            self.code.local_set(counter);
+            self.emit_expression(args.last().unwrap())?;
+            self.code.local_set(bound);
+            None
+        } else {
+            let iterable = self.new_local();
+            self.code.i32_const(0);
+            self.code.local_set(counter);
+            self.emit_expression(stmt.enumerate_arg().unwrap_or(&stmt.iterable))?;
+            self.code.local_tee(iterable);
+            self.code.load(0);
+            self.code.local_set(bound);
+            Some(iterable)
+        };
+
+        self.code.block(OP_BLOCK, BLOCK_EMPTY);
+        self.code.block(OP_LOOP, BLOCK_EMPTY);
+        self.code.local_get(counter);
+        self.code.local_get(bound);
+        self.code.op(OP_I32_GE_S);
+        self.code.br_if(1);
+        if let Some(index) = index {
+            self.code.local_get(counter);
+            self.code.global_set(index);
+        }
+        if let Some(iterable) = iterable {
+            self.code.local_get(iterable);
+            self.code.local_get(counter);
+            self.code.call(FUNC_STR_CHAR);
+        } else {
+            self.code.local_get(counter);
+        }
+        self.code.global_set(target);
+        self.emit_statements(&stmt.body)?;
+        self.code.local_get(counter);
+        self.code.i32_const(1);
+        self.code.op(OP_I32_ADD);
+        self.code.local_set(counter);
+        self.code.br(0);
+        self.code.end();
+        self.code.end();
+        Ok(())
+    }
+
+    fn emit_statement(&mut self, stmt: &Stmt) -> Result<(), UnsupportedError> {
+        match stmt {
+            Stmt::ExprStmt(stmt) => {
+                self.emit_expression(&stmt.expr)?;
+                self.code.op(0x1A); // drop
+            }
+            Stmt::AssignStmt(stmt) => {
+                let value = self.new_local();
+                self.emit_expression(&stmt.value)?;
+                self.code.local_set(value);
+                for target in &stmt.targets {
+                    let name = if let ExprContent::Variable(variable) = &target.content {
+                        &variable.name
+                    } else {
+                        return unsupported("assigning to lists or attributes");
+                    };
+                    self.code.local_get(value);
+                    self.code.global_set(self.global(name));
+                }
+            }
+            Stmt::IfStmt(stmt) => {
+                self.emit_expression(&stmt.condition)?;
+                self.code.if_(BLOCK_EMPTY);
+                self.emit_statements(&stmt.then_body)?;
+                if !stmt.else_body.is_empty() {
+                    self.code.else_();
+                    self.emit_statements(&stmt.else_body)?;
+                }
+                self.code.end();
+            }
+            Stmt::WhileStmt(stmt) => {
+                self.code.block(OP_BLOCK, BLOCK_EMPTY);
+                self.code.block(OP_LOOP, BLOCK_EMPTY);
+                self.emit_expression(&stmt.condition)?;
+                self.code.op(OP_I32_EQZ);
+                self.code.br_if(1);
+                self.emit_statements(&stmt.body)?;
+                self.code.br(0);
+                self.code.end();
+                self.code.end();
+            }
+            Stmt::ForStmt(stmt) => self.emit_for(stmt)?,
+            Stmt::ReturnStmt(_) => unreachable!(), // Rejected by the type checker at top level
+        }
+        Ok(())
+    }
+
+    fn emit_statements(&mut self, statements: &[Stmt]) -> Result<(), UnsupportedError> {
+        for stmt in statements {
+            self.emit_statement(stmt)?;
+        }
+        Ok(())
+    }
+}
+
+// Initial value of a global variable
+fn global_init(var: &VarDef, data: &mut Data) -> Result<i32, UnsupportedError> {
+    let value_type = ValueType::from_annotation(&var.var.type_);
+    if value_type != *TYPE_INT && value_type != *TYPE_BOOL && value_type != *TYPE_STR {
+        return unsupported("variables of types other than int, bool and str");
+    }
+    Ok(match &var.value.content {
+        LiteralContent::IntegerLiteral(literal) => literal.value,
+        LiteralContent::BooleanLiteral(literal) => literal.value as i32,
+        LiteralContent::StringLiteral(literal) => data.string(&literal.value) as i32,
+        LiteralContent::NoneLiteral(_) => return unsupported("None"),
+    })
+}
+
+fn function_type(parameters: usize, result: bool) -> Vec<u8> {
+    let mut item = vec![0x60];
+    write_uleb(&mut item, parameters as u32);
+    item.resize(item.len() + parameters, I32);
+    if result {
+        item.extend_from_slice(&[1, I32]);
+    } else {
+        item.push(0);
+    }
+    item
+}
+
+fn global_entry(value: i32) -> Vec<u8> {
+    let mut item = vec![I32, 0x01]; // mutable
+    item.push(0x41);
+    write_sleb(&mut item, value);
+    item.push(0x0B);
+    item
+}
+
+// Generate a WebAssembly module from the typed AST
+pub fn gen_module(ast: &Program) -> Result<Vec<u8>, UnsupportedError> {
+    let mut data = Data {
+        bytes: vec![],
+        strings: HashMap::new(),
+    };
+
+    let mut globals = HashMap::new();
+    let mut global_values = vec![];
+    for declaration in &ast.declarations {
+        match declaration {
+            Declaration::VarDef(var) => {
+                let index = global_values.len() as u32 + 1;
+                globals.insert(var.var.identifier.name.clone(), index);
+                global_values.push(global_init(var, &mut data)?);
+            }
+            Declaration::FuncDef(_) => return unsupported("functions"),
+            Declaration::ClassDef(_) => return unsupported("classes"),
+            Declaration::GlobalDecl(_) | Declaration::NonLocalDecl(_) => unreachable!(),
+        }
+    }
+
+    let mut emitter = Emitter {
+        code: Code::default(),
+        locals: 0,
+        globals: &globals,
+        data: &mut data,
+    };
+    emitter.emit_statements(&ast.statements)?;
+    let main_locals = emitter.locals;
+    let main = emitter.code.body(main_locals);
+
+    // Literals are padded, so the heap starts 4-byte aligned
+    let heap_start = DATA_START + data.bytes.len() as u32;
+
+    let mut module = b"\0asm".to_vec();
+    module.extend_from_slice(&[1, 0, 0, 0]);
+
+    write_section(
+        &mut module,
+        1,
+        &[
+            function_type(1, false), // TYPE_I32_TO_NONE
+            function_type(0, true),  // TYPE_NONE_TO_I32
+            function_type(1, true),  // TYPE_I32_TO_I32
+            function_type(2, true),  // TYPE_I32_I32_TO_I32
+            function_type(0, false), // TYPE_NONE_TO_NONE
+        ],
+    );
+
+    let imports: Vec<_> = [
+        ("print_int", TYPE_I32_TO_NONE),
+        ("print_bool", TYPE_I32_TO_NONE),
+        ("print_str", TYPE_I32_TO_NONE),
+        ("input", TYPE_NONE_TO_I32),
+        ("error", TYPE_I32_TO_NONE),
+    ]
+    .iter()
+    .map(|&(name, type_index)| {
+        let mut item = vec![];
+        write_name(&mut item, "env");
+        write_name(&mut item, name);
+        item.push(0x00);
+        write_uleb(&mut item, type_index);
+        item
+    })
+    .collect();
+    write_section(&mut module, 2, &imports);
+
+    let functions = [
+        (TYPE_I32_TO_I32, gen_alloc()),
+        (TYPE_I32_I32_TO_I32, gen_str_concat()),
+        (TYPE_I32_I32_TO_I32, gen_str_eq()),
+        (TYPE_I32_I32_TO_I32, gen_str_char()),
+        (TYPE_I32_I32_TO_I32, gen_int_div()),
+        (TYPE_I32_I32_TO_I32, gen_int_mod()),
+        (TYPE_I32_I32_TO_I32, gen_int_shift(true)),
+        (TYPE_I32_I32_TO_I32, gen_int_shift(false)),
+        (TYPE_NONE_TO_NONE, main),
+    ];
+    assert_eq!(
+        FUNC_ALLOC as usize + functions.len() - 1,
+        FUNC_MAIN as usize
+    );
+    let function_types: Vec<_> = functions
+        .iter()
+        .map(|(type_index, _)| {
+            let mut item = vec![];
+            write_uleb(&mut item, *type_index);
+            item
+        })
+        .collect();
+    write_section(&mut module, 3, &function_types);
+
+    // Memory, with at least one page of heap
+    let mut memory = vec![0x00];
+    write_uleb(&mut memory, heap_start / PAGE_SIZE + 1);
+    write_section(&mut module, 5, &[memory]);
+
+    let globals: Vec<_> = std::iter::once(heap_start as i32)
+        .chain(global_values)
+        .map(global_entry)
+        .collect();
+    write_section(&mut module, 6, &globals);
+
+    let exports: Vec<_> = [
+        ("memory", 0x02, 0),
+        ("alloc", 0x00, FUNC_ALLOC),
+        ("main", 0x00, FUNC_MAIN),
+    ]
+    .iter()
+    .map(|&(name, kind, index)| {
+        let mut item = vec![];
+        write_name(&mut item, name);
+        item.push(kind);
+        write_uleb(&mut item, index);
+        item
+    })
+    .collect();
+    write_section(&mut module, 7, &exports);
+
+    let bodies: Vec<_> = functions.iter().map(|(_, body)| body.clone()).collect();
+    write_section(&mut module, 10, &bodies);
+
+    let mut segment = vec![0x00, 0x41];
+    write_sleb(&mut segment, DATA_START as i32);
+    segment.push(0x0B);
+    write_uleb(&mut segment, data.bytes.len() as u32);
+    segment.extend_from_slice(&data.bytes);
+    write_section(&mut module, 11, &[segment]);
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    // Compile the program and run it with the JavaScript runtime under Node.js.
+    // Returns the output and the exit code, or None if Node.js isn't installed.
+    fn run(source: &str, input: &str) -> Option<(String, i32)> {
+        let (source_path, ast) = super::super::test_util::check_source(source);
+        let output = run_module(&gen_module(&ast).unwrap(), input);
+        std::fs::remove_file(&source_path).unwrap();
+        output
+    }
+
+    fn run_module(module: &[u8], input: &str) -> Option<(String, i32)> {
+        let module_path = super::super::test_util::temp_path(".wasm");
+        std::fs::write(&module_path, module).unwrap();
+
+        let runtime = concat!(env!("CARGO_MANIFEST_DIR"), "/../chocopy-rs-std/wasm.js");
+        let process = Command::new("node")
+            .arg(runtime)
+            .arg(&module_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let output = match process {
+            Ok(mut process) => {
+                process
+                    .stdin
+                    .take()
+                    .unwrap()
+                    .write_all(input.as_bytes())
+                    .unwrap();
+                Some(process.wait_with_output().unwrap())
+            }
+            Err(_) => {
+                eprintln!("Skipped execution: node not found");
+                None
+            }
+        };
+        std::fs::remove_file(&module_path).unwrap();
+
+        let output = output?;
+        // Runtime errors are printed to stderr at exit, after the output of the program
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stderr.contains("Fatal error"), "{}", stderr);
+        Some((
+            String::from_utf8(output.stdout).unwrap() + &stderr,
+            output.status.code().unwrap(),
+        ))
+    }
+
+    #[test]
+    fn leb128() {
+        let encode = |value: i32| {
+            let mut out = vec![];
+            write_sleb(&mut out, value);
+            out
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(63), [0x3F]);
+        assert_eq!(encode(64), [0xC0, 0x00]);
+        assert_eq!(encode(-1), [0x7F]);
+        assert_eq!(encode(-65), [0xBF, 0x7F]);
+        assert_eq!(encode(i32::MIN), [0x80, 0x80, 0x80, 0x80, 0x78]);
+        let mut out = vec![];
+        write_uleb(&mut out, 624485);
+        assert_eq!(out, [0xE5, 0x8E, 0x26]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let source = "\
+x:int = 7
+y:int = 0
+y = -2
+print(x + y * 3)
+print(x // y)
+print(x % y)
+print(-x // 2)
+print(-x % 2)
+print((-2147483647 - 1) // -1)
+x = y = 2147483647
+print(x + 1)
+print(x == y or 1 // 0 == 0)
+print(not (x == y and y < 0))
+print(5 if x == y else 6)
+";
+        if let Some((output, code)) = run(source, "") {
+            assert_eq!(
+                output,
+                "1\n-4\n-1\n-4\n1\n-2147483648\n-2147483648\nTrue\nTrue\n5\n"
+            );
+            assert_eq!(code, 0);
+        }
+    }
+
+    #[test]
+    fn strings() {
+        let source = "\
+s:str = \"Hello\"
+t:str = \"\"
+c:str = \"\"
+i:int = 0
+t = s + \", \" + input() + \"!\"
+print(t)
+print(len(t))
+print(s[1] == \"e\")
+print(s + \"\" != s)
+for c in s:
+    t = c + t
+print(t)
+for i in range(2, 5):
+    print(s[i])
+while i > 0:
+    i = i - 1
+    if i % 2 == 0:
+        print(i)
+    else:
+        pass
+print(s[5])
+";
+        if let Some((output, code)) = run(source, "wasm\nignored\n") {
+            assert_eq!(
+                output,
+                "Hello, wasm!\n12\nTrue\nFalse\nolleHHello, wasm!\nl\nl\no\n\
+                 2\n0\nIndex out of bounds\nExited with error code 3\n"
+            );
+            assert_eq!(code, 3);
+        }
+    }
+
+    #[test]
+    fn grow_memory() {
+        // Each concatenation allocates a new str, far more than the initial page
+        let source = "\
+s:str = \"\"
+i:int = 0
+while i < 1000:
+    s = s + \"ab\"
+    i = i + 1
+print(len(s))
+print(s[1999])
+print(1 // (i - 1000))
+";
+        if let Some((output, code)) = run(source, "") {
+            assert_eq!(
+                output,
+                "2000\nb\nDivision by zero\nExited with error code 2\n"
+            );
+            assert_eq!(code, 2);
+        }
+    }
+
+    #[test]
+    fn reference_programs() {
+        // Programs with reference output and no input, as long as they are supported
+        let mut count = 0;
+        for entry in std::fs::read_dir("test/original/pa3").unwrap() {
+            let typed_path = entry.unwrap().path();
+            let typed_str = typed_path.to_str().unwrap();
+            let source_str = if let Some(source_str) = typed_str.strip_suffix(".ast.typed") {
+                source_str
+            } else {
+                continue;
+            };
+            let result_path = typed_str.to_owned() + ".s.result";
+            if std::path::Path::new(&(source_str.to_owned() + ".input")).exists() {
+                continue;
+            }
+            let expected = if let Ok(expected) = std::fs::read_to_string(&result_path) {
+                expected
+            } else {
+                continue;
+            };
+
+            let ast = crate::parse::load_ast(typed_str).unwrap();
+            let module = if let Ok(module) = gen_module(&ast) {
+                module
+            } else {
+                continue;
+            };
+            let (output, _) = if let Some(output) = run_module(&module, "") {
+                output
+            } else {
+                return;
+            };
+            assert_eq!(output, expected, "{}", source_str);
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn unsupported() {
+        let (source_path, ast) =
+            super::super::test_util::check_source("def f() -> int:\n    return 1\nprint(f())\n");
+        std::fs::remove_file(&source_path).unwrap();
+        assert_eq!(
+            gen_module(&ast).unwrap_err().to_string(),
+            "WebAssembly output doesn't support functions yet"
+        );
+
+        let (source_path, ast) = super::super::test_util::check_source("x:[int] = None\nx = [1]\n");
+        std::fs::remove_file(&source_path).unwrap();
+        assert!(gen_module(&ast).is_err());
+    }
+}
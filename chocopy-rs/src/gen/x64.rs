@@ -1,13 +1,29 @@
 // Machine code generator for x86-64
 
+use super::abi::{self, Arg, Reg};
+use super::target::{self, Endian, Target, TargetBytes};
 use super::*;
 use chocopy_rs_common::*;
 
+const TARGET: Target = target::X86_64;
+const ENDIAN: Endian = TARGET.endian;
+
 struct FuncSlot {
     link_name: String,
-    level: u32, // 0 = global function / method
+    level: u32,                 // 0 = global function / method
+    inline: Option<InlineBody>, // Set if calls are replaced by the body with -O2
+}
+
+// A function whose body is a single `return` of an expression without calls
+struct InlineBody {
+    params: Vec<String>,
+    value: Expr,
+    return_type: ValueType,
 }
 
+// Largest number of expression nodes in a function body that is inlined
+const INLINE_MAX_NODES: usize = 12;
+
 struct VarSlot {
     offset: i32, // relative to global seciton or rbp
     level: u32,  // 0 = global variable
@@ -21,20 +37,30 @@ struct AttributeSlot {
     source_type: ValueType, // Type of the initial value
     target_type: ValueType, // Actual type in declaration
     init: LiteralContent,   // Initial value
+    line: u32,              // Line of the declaration, for the constructor that initializes it
+}
+
+#[derive(Clone)]
+struct ConstantSlot {
+    offset: i32, // relative to global section
+    target_type: ValueType,
 }
 
 #[derive(Clone)]
 struct MethodSlot {
     offset: u32, // Offset into prototype
     link_name: String,
+    direct: bool, // Set if no subclass overrides this implementation
 }
 
 #[derive(Clone)]
 struct ClassSlot {
     attributes: HashMap<String, AttributeSlot>,
-    object_size: u32, // excluding the object header
+    constants: HashMap<String, ConstantSlot>, // Stored in global section, including inherited ones
+    object_size: u32,                         // excluding the object header
     methods: BTreeMap<String, MethodSlot>,
     prototype_size: u32,
+    line: u32, // Line of the class definition, 0 for `object`
 }
 
 struct Emitter<'a> {
@@ -48,17 +74,24 @@ struct Emitter<'a> {
     level: u32,
     code: Vec<u8>,
     links: Vec<ChunkLink>,
+    lines: Vec<LineMap>,               // Moved into the debug info by `finalize`
+    local_data: Vec<(usize, Vec<u8>)>, // Position of the displacement and the data it refers to
+    trace_exec: bool,
+    interruptible: bool,
+    opt_level: u32,
+    runtime_checks: RuntimeChecks,
+    class_eq: bool,
     platform: Platform,
-}
-
-impl Platform {
-    // Extra stack space reservation according to system ABI, in qwords (8 bytes)
-    fn stack_reserve(self) -> usize {
-        match self {
-            Platform::Windows => 4, // "Shadow space" in Microsoft ABI
-            Platform::Linux | Platform::Macos => 0,
-        }
-    }
+    // Offsets relative to rbp of the arguments while emitting an inlined function body
+    inline_params: Option<HashMap<String, i32>>,
+    // Class and `self` parameter name of the method being emitted, if calls on `self`
+    // to methods that are not overridden go directly to the implementation with -O2
+    self_receiver: Option<(String, String)>,
+    // Set if slots are addressed from rsp, see `omit_frame_pointer`
+    frameless: bool,
+    // Positions of displacements from rsp with the offset from where rbp would be. The frame
+    // size is added to them in finalize
+    frame_displacements: Vec<(usize, i32)>,
 }
 
 // Label generator for forward branching
@@ -105,6 +138,12 @@ impl ValueType {
         *self == *TYPE_INT || *self == *TYPE_BOOL
     }
 
+    // A class defined in the program, as opposed to the built-in and special ones
+    fn is_user_class(&self) -> bool {
+        matches!(self, ValueType::ClassValueType(c) if !matches!(c.class_name.as_str(),
+            "object" | "int" | "bool" | "str" | "<None>" | "<Empty>"))
+    }
+
     fn ticket_type(&self) -> TicketType {
         if self.is_plain() {
             TicketType::Plain
@@ -112,6 +151,26 @@ impl ValueType {
             TicketType::Reference
         }
     }
+
+    // Prototype of lists with this element type
+    fn list_prototype(&self) -> &'static str {
+        if *self == *TYPE_INT {
+            INT_LIST_PROTOTYPE
+        } else if *self == *TYPE_BOOL {
+            BOOL_LIST_PROTOTYPE
+        } else {
+            OBJECT_LIST_PROTOTYPE
+        }
+    }
+
+    // Element type of a list, or None for `<Empty>`, the type of `[]`
+    fn list_element(&self) -> Option<&ValueType> {
+        match self {
+            ValueType::ListValueType(l) => Some(&*l.element_type),
+            _ if *self == *TYPE_EMPTY => None,
+            _ => panic!(),
+        }
+    }
 }
 
 impl<'a> Emitter<'a> {
@@ -144,7 +203,18 @@ impl<'a> Emitter<'a> {
             // push rbp; mov rbp,rsp; add rsp,{}
             code: vec![0x55, 0x48, 0x89, 0xe5, 0x48, 0x81, 0xEC, 0, 0, 0, 0],
             links: vec![],
+            lines: vec![],
+            local_data: vec![],
+            trace_exec: false,
+            interruptible: false,
+            opt_level: 0,
+            runtime_checks: RuntimeChecks::All,
+            class_eq: false,
             platform,
+            inline_params: None,
+            self_receiver: None,
+            frameless: false,
+            frame_displacements: vec![],
         }
     }
 
@@ -161,10 +231,42 @@ impl<'a> Emitter<'a> {
         self.code.extend_from_slice(instruction);
     }
 
+    // Emit a multi-byte value in the byte order of the target
+    pub fn emit_value(&mut self, value: impl TargetBytes) {
+        self.emit(ENDIAN.bytes(value).as_ref());
+    }
+
     pub fn pos(&self) -> usize {
         self.code.len()
     }
 
+    // Attribute the code emitted from here on to the line
+    pub fn mark_line(&mut self, line_number: u32) {
+        self.lines.push(LineMap {
+            code_pos: self.pos(),
+            line_number,
+        });
+    }
+
+    // Attribute the code emitted from here on to the line of an expression, so that a long
+    // sequence for it isn't counted as time spent on the line of the statement (or of the
+    // inlined function) around it. Returns the line to go back to with `exit_expr_line`.
+    // Procedures without line info are left without.
+    pub fn enter_expr_line(&mut self, line_number: u32) -> Option<u32> {
+        let line = self.lines.last()?.line_number;
+        if line == line_number {
+            return None;
+        }
+        self.mark_line(line_number);
+        Some(line)
+    }
+
+    pub fn exit_expr_line(&mut self, line: Option<u32>) {
+        if let Some(line) = line {
+            self.mark_line(line);
+        }
+    }
+
     // Reserve a slot from the current stack frame and get a ticket for it
     pub fn alloc_stack(&mut self, ticket_type: TicketType) -> StackTicket {
         self.current_stack_top -= 8;
@@ -191,29 +293,63 @@ impl<'a> Emitter<'a> {
     // This will append the ticket value (offset to rbp) to the instruction.
     // This should be used with instructions like `mov [rbp+ticket],rax`
     pub fn emit_with_stack(&mut self, instruction: &[u8], ticket: &StackTicket) {
-        self.emit(instruction);
-        self.emit(&ticket.offset.to_le_bytes());
+        self.emit_frame_access(instruction, ticket.offset);
+    }
+
+    // Append the offset of a frame slot to an instruction ending with a ModRM byte for
+    // [rbp+disp32]. Without a frame pointer, the operand becomes [rsp+disp32] instead.
+    pub fn emit_frame_access(&mut self, instruction: &[u8], offset: i32) {
+        if !self.frameless {
+            self.emit(instruction);
+            self.emit_value(offset);
+            return;
+        }
+        let (&modrm, prefix) = instruction.split_last().unwrap();
+        assert_eq!(modrm & 0xC7, 0x85);
+        self.emit(prefix);
+        // rm = 100 takes a SIB byte, which is 0x24 for rsp without an index
+        self.emit(&[modrm - 1, 0x24]);
+        self.frame_displacements.push((self.pos(), offset));
+        self.emit(&[0; 4]);
+    }
+
+    // Allocate the frame by moving rsp only, without saving rbp or pointing it at the frame.
+    // The GC walks stack frames through the rbp chain, so this is only for procedures where
+    // nothing can trigger a collection, and that neither read nor pass a static link.
+    // Must be called before emitting any code.
+    pub fn omit_frame_pointer(&mut self) {
+        assert_eq!(self.code.len(), 11);
+        // sub rsp,{}
+        self.code = vec![0x48, 0x81, 0xEC, 0, 0, 0, 0];
+        self.frameless = true;
     }
 
     // Emit a map for GC describing which stack frame slots are currently references.
     // This should be called after each function invocation that can lead to GC.
     pub fn emit_ref_map(&mut self) {
+        // Each reference takes a whole slot, which gets one bit in the map.
+        // Slots are at most 2^28 away from rbp on either side, so the length can't overflow.
+        assert!(self.ref_list.iter().all(|&offset| offset % 8 == 0));
         let min_index = self.ref_list.iter().min().cloned().unwrap_or(0) / 8;
         let max_index = self.ref_list.iter().max().cloned().unwrap_or(0) / 8;
-        let len = max_index - min_index + 1;
-        let mut ref_map = vec![0; 8 + (len as usize + 7) / 8];
-        ref_map[0..4].copy_from_slice(&min_index.to_le_bytes());
-        ref_map[4..8].copy_from_slice(&max_index.to_le_bytes());
+        let len = (max_index - min_index + 1) as usize;
+        let mut ref_map = vec![0; 8 + len.div_ceil(8)];
+        ENDIAN.write(&mut ref_map[0..], min_index);
+        ENDIAN.write(&mut ref_map[4..], max_index);
         for &offset in &self.ref_list {
             let index = (offset / 8 - min_index) as usize;
             ref_map[8 + index / 8] |= 1 << (index % 8);
         }
 
         self.emit(&[0x0F, 0x18, 0x05]);
-        self.links.push(ChunkLink {
-            pos: self.pos(),
-            to: ChunkLinkTarget::Data(ref_map),
-        });
+        self.emit_link_local_data(ref_map);
+    }
+
+    // Append a rip-relative displacement to read-only data stored after the code of this
+    // procedure. It is resolved in finalize, so unlike ChunkLinkTarget::Data,
+    // it doesn't need a relocation.
+    pub fn emit_link_local_data(&mut self, data: Vec<u8>) {
+        self.local_data.push((self.pos(), data));
         self.emit(&[0; 4]);
     }
 
@@ -229,7 +365,7 @@ impl<'a> Emitter<'a> {
     pub fn to_here(&mut self, jump: ForwardJumper) {
         let from = jump.from;
         let delta = (self.pos() - from - 4) as u32;
-        self.code[from..from + 4].copy_from_slice(&delta.to_le_bytes());
+        ENDIAN.write(&mut self.code[from..], delta);
     }
 
     // Mark the current position as the destination of a backward branching instruction
@@ -241,13 +377,23 @@ impl<'a> Emitter<'a> {
     #[allow(clippy::wrong_self_convention)]
     pub fn from_here(&mut self, jump: BackwardJumper) {
         let delta = -((self.pos() - jump.to + 4) as i32);
-        self.emit(&delta.to_le_bytes());
+        self.emit_value(delta);
     }
 
     // Emit code that exits from the procedure
     pub fn end_proc(&mut self) {
-        // leave; ret
-        self.emit(&[0xc9, 0xc3])
+        if self.frameless {
+            // add rsp,{}
+            self.emit(&[0x48, 0x81, 0xC4]);
+            // The return address is right above where rbp would be saved
+            self.frame_displacements.push((self.pos(), 8));
+            self.emit(&[0; 4]);
+            // ret
+            self.emit(&[0xc3]);
+        } else {
+            // leave; ret
+            self.emit(&[0xc9, 0xc3])
+        }
     }
 
     // Allocate stack space for parameters
@@ -260,19 +406,43 @@ impl<'a> Emitter<'a> {
 
     // Append instruction with an address that links to an external symbol
     pub fn emit_link(&mut self, name: impl Into<String>, offset: i32) {
+        self.emit_link_imm(name, offset, &[]);
+    }
+
+    // Rip-relative displacement followed by an immediate operand
+    pub fn emit_link_imm(&mut self, name: impl Into<String>, offset: i32, imm: &[u8]) {
         self.links.push(ChunkLink {
             pos: self.pos(),
             to: ChunkLinkTarget::Symbol(name.into(), offset),
+            imm_size: imm.len() as u8,
         });
         self.emit(&[0; 4]);
+        self.emit(imm);
     }
 
-    // Call a function
+    // Call a function.
+    // rsp stays where the prologue puts it, at rbp minus the frame size, which finalize rounds to
+    // a multiple of 16. As rbp is 16-byte aligned after `push rbp`, every call, including those
+    // into the standard library, starts with rsp aligned as the system ABI requires.
     pub fn call(&mut self, name: &str) {
+        // Slots and parameter space are whole words, so that the rounding is all that is needed
+        debug_assert!(self.current_stack_top % 8 == 0 && self.max_stack_top % 8 == 0);
         self.emit(&[0xe8]);
         self.emit_link(name, 0);
     }
 
+    // Call a standard library function following the system ABI
+    pub fn call_system(&mut self, name: &str, args: &[Arg]) {
+        self.prepare_call(abi::stack_reserve(self.platform, args.len()));
+        for instruction in abi::arg_moves(self.platform, args) {
+            self.emit(&instruction.code);
+            if let Some(symbol) = instruction.link {
+                self.emit_link(symbol, 0);
+            }
+        }
+        self.call(name);
+    }
+
     // Call a class method. Offset is into the prototype
     pub fn call_virtual(&mut self, offset: u32) {
         // mov rdi,[rsp]
@@ -281,7 +451,7 @@ impl<'a> Emitter<'a> {
         self.emit(&[0x48, 0x8B, 0x07]);
         // call [rax+{}]
         self.emit(&[0xFF, 0x90]);
-        self.emit(&offset.to_le_bytes());
+        self.emit_value(offset);
     }
 
     // Finalize code generation for this chunk
@@ -293,8 +463,36 @@ impl<'a> Emitter<'a> {
             frame_size += 8;
         }
         procedure_debug.frame_size = frame_size as u32;
+        procedure_debug.frameless = self.frameless;
+        procedure_debug.lines = std::mem::take(&mut self.lines);
         // Patch the prologue to allocate the stack frame
-        self.code[7..11].copy_from_slice(&frame_size.to_le_bytes());
+        if self.frameless {
+            // Nothing is pushed, so the frame also takes the word where rbp would be saved.
+            // That keeps rsp aligned for calls the same way.
+            ENDIAN.write(&mut self.code[3..], frame_size + 8);
+            for (pos, offset) in std::mem::take(&mut self.frame_displacements) {
+                ENDIAN.write(&mut self.code[pos..], offset + frame_size);
+            }
+        } else {
+            ENDIAN.write(&mut self.code[7..], frame_size);
+        }
+
+        // Append local data 8-aligned after the code, storing identical data once.
+        // The data is part of the procedure chunk, so the symbol size, line table and
+        // unwind info all cover it.
+        let code = &mut self.code;
+        let mut data_offsets = HashMap::new();
+        for (pos, data) in std::mem::take(&mut self.local_data) {
+            let offset = *data_offsets.entry(data).or_insert_with_key(|data| {
+                code.resize(code.len().next_multiple_of(8), 0);
+                let offset = code.len();
+                code.extend_from_slice(data);
+                offset
+            });
+            let delta = (offset - (pos + 4)) as i32;
+            ENDIAN.write(&mut code[pos..], delta);
+        }
+
         Chunk {
             name: self.name,
             code: self.code,
@@ -304,45 +502,67 @@ impl<'a> Emitter<'a> {
     }
 
     // Call into standard library to allocate object
+    // The array length is passed in rsi
     pub fn call_builtin_alloc(&mut self, prototype: &str) {
-        match self.platform {
-            Platform::Windows => {
-                // mov r8,rbp
-                self.emit(&[0x49, 0x89, 0xE8]);
-                // mov r9,rsp
-                self.emit(&[0x49, 0x89, 0xE1]);
-                // mov rdx,rsi
-                self.emit(&[0x48, 0x89, 0xF2]);
-                // lea rcx,[rip+{_PROTOTYPE}]
-                self.emit(&[0x48, 0x8D, 0x0D]);
-            }
-            Platform::Linux | Platform::Macos => {
-                // mov rdx,rbp
-                self.emit(&[0x48, 0x89, 0xEA]);
-                // mov rcx,rsp
-                self.emit(&[0x48, 0x89, 0xE1]);
-                // lea rdi,[rip+{_PROTOTYPE}]
-                self.emit(&[0x48, 0x8D, 0x3D]);
-            }
-        }
-        self.emit_link(prototype, 0);
-        self.prepare_call(self.platform.stack_reserve());
-        self.call(BUILTIN_ALLOC_OBJ);
+        self.call_system(
+            BUILTIN_ALLOC_OBJ,
+            &[
+                Arg::Symbol(prototype),
+                Arg::Reg(Reg::Rsi),
+                Arg::Reg(Reg::Rbp),
+                Arg::Reg(Reg::Rsp),
+            ],
+        );
         self.emit_ref_map();
     }
 
     // Ensure rax is not None
     pub fn emit_check_none(&mut self) {
+        if self.runtime_checks < RuntimeChecks::All {
+            return;
+        }
         // test rax,rax
         self.emit(&[0x48, 0x85, 0xC0]);
         // jne
         self.emit(&[0x0F, 0x85]);
         let ok = self.jump_from();
-        self.prepare_call(self.platform.stack_reserve());
-        self.call(BUILTIN_NONE_OP);
+        self.call_system(BUILTIN_NONE_OP, &[]);
         self.to_here(ok);
     }
 
+    // Whether a call to the built-in `len` reads the length field directly with -O2.
+    // Only lists and strings are known to have one; other arguments still go through `$len`
+    pub fn is_inline_len(&self, expr: &CallExpr) -> bool {
+        if self.opt_level < 2 || expr.function.name != "len" {
+            return false;
+        }
+        let builtin = matches!(self.storage_env().get("len"),
+            Some(EnvSlot::Func(f)) if f.link_name == "len");
+        builtin
+            && expr.args.first().is_some_and(|arg| {
+                let arg_type = arg.get_type();
+                arg_type == &*TYPE_STR || matches!(arg_type, ValueType::ListValueType(_))
+            })
+    }
+
+    // Compute the length of a list or string without calling `len`
+    pub fn emit_inline_len(&mut self, arg: &Expr) {
+        self.emit_expression(arg);
+        // Strings are never None
+        if arg.get_type() != &*TYPE_STR {
+            // test rax,rax
+            self.emit(&[0x48, 0x85, 0xC0]);
+            // jne
+            self.emit(&[0x0F, 0x85]);
+            let ok = self.jump_from();
+            // `$len` fails on None with this error instead of the one from emit_check_none
+            self.call_system(BUILTIN_INVALID_ARG, &[]);
+            self.to_here(ok);
+        }
+        // mov eax,[rax+ARRAY_LEN_OFFSET]
+        self.emit(&[0x8B, 0x40, ARRAY_LEN_OFFSET as u8]);
+    }
+
     // All function below puts the result in rax
 
     // Box the int value in rax and return in rax
@@ -383,7 +603,7 @@ impl<'a> Emitter<'a> {
     pub fn emit_int_literal(&mut self, i: i32) {
         // mov eax,{i}
         self.emit(&[0xB8]);
-        self.emit(&i.to_le_bytes());
+        self.emit_value(i);
     }
 
     pub fn emit_bool_literal(&mut self, b: bool) {
@@ -397,21 +617,17 @@ impl<'a> Emitter<'a> {
 
         // mov rsi,{len}
         self.emit(&[0x48, 0xc7, 0xc6]);
-        self.emit(&(s.len() as u32).to_le_bytes());
+        self.emit_value(s.len() as u32);
         self.call_builtin_alloc(STR_PROTOTYPE);
         if !s.is_empty() {
             // lea rdi,[rax+ARRAY_ELEMENT_OFFSET]
             self.emit(&[0x48, 0x8D, 0x78, ARRAY_ELEMENT_OFFSET as u8]);
             // lea rsi,[rip+{STR}]
             self.emit(&[0x48, 0x8d, 0x35]);
-            self.links.push(ChunkLink {
-                pos: self.pos(),
-                to: ChunkLinkTarget::Data(s.into()),
-            });
-            self.emit(&[0; 4]);
+            self.emit_link_local_data(s.into());
             // mov rcx,{len}
             self.emit(&[0x48, 0xc7, 0xc1]);
-            self.emit(&(s.len() as u32).to_le_bytes());
+            self.emit_value(s.len() as u32);
             // mov dl,[rsi]
             self.emit(&[0x8A, 0x16]);
             // mov [rdi],dl
@@ -445,6 +661,7 @@ impl<'a> Emitter<'a> {
         let right = self.alloc_stack(TicketType::Reference);
         // mov [rbp+{}],rax
         self.emit_with_stack(&[0x48, 0x89, 0x85], &right);
+        let line = self.enter_expr_line(expr.base().location.start.row);
         self.call_builtin_alloc(STR_PROTOTYPE);
 
         // mov r11,[rbp+{}]
@@ -489,6 +706,7 @@ impl<'a> Emitter<'a> {
             ARRAY_ELEMENT_OFFSET as u8,
             0x8A, 0x16, 0x88, 0x17, 0x48, 0xFF, 0xC6, 0x48, 0xFF, 0xC7, 0xE2, 0xF4,
         ]);
+        self.exit_expr_line(line);
     }
 
     pub fn emit_list_add_half(&mut self, source_element: &ValueType, target_element: &ValueType) {
@@ -575,13 +793,7 @@ impl<'a> Emitter<'a> {
     }
 
     pub fn emit_list_add(&mut self, expr: &BinaryExpr, target_element: &ValueType) {
-        let prototype = if target_element == &*TYPE_INT {
-            INT_LIST_PROTOTYPE
-        } else if target_element == &*TYPE_BOOL {
-            BOOL_LIST_PROTOTYPE
-        } else {
-            OBJECT_LIST_PROTOTYPE
-        };
+        let prototype = target_element.list_prototype();
 
         self.emit_expression(&expr.left);
         self.emit_check_none();
@@ -603,6 +815,7 @@ impl<'a> Emitter<'a> {
         let right = self.alloc_stack(TicketType::Reference);
         // mov [rbp+{}],rax
         self.emit_with_stack(&[0x48, 0x89, 0x85], &right);
+        let line = self.enter_expr_line(expr.base().location.start.row);
         self.call_builtin_alloc(prototype);
         let result = self.alloc_stack(TicketType::Reference);
         // mov [rbp+{}],rax
@@ -610,23 +823,19 @@ impl<'a> Emitter<'a> {
         // add rax,ARRAY_ELEMENT_OFFSET
         self.emit(&[0x48, 0x83, 0xC0, ARRAY_ELEMENT_OFFSET as u8]);
 
-        // mov rsi,[rbp+{}]
-        self.emit_with_stack(&[0x48, 0x8B, 0xB5], &left);
-        let source_element = if let ValueType::ListValueType(l) = expr.left.get_type() {
-            &*l.element_type
-        } else {
-            panic!()
-        };
-        self.emit_list_add_half(source_element, target_element);
+        // An operand typed `<Empty>` has nothing to copy
+        if let Some(source_element) = expr.left.get_type().list_element() {
+            // mov rsi,[rbp+{}]
+            self.emit_with_stack(&[0x48, 0x8B, 0xB5], &left);
+            self.emit_list_add_half(source_element, target_element);
+        }
+        if let Some(source_element) = expr.right.get_type().list_element() {
+            // mov rsi,[rbp+{}]
+            self.emit_with_stack(&[0x48, 0x8B, 0xB5], &right);
+            self.emit_list_add_half(source_element, target_element);
+        }
 
-        // mov rsi,[rbp+{}]
-        self.emit_with_stack(&[0x48, 0x8B, 0xB5], &right);
-        let source_element = if let ValueType::ListValueType(l) = expr.right.get_type() {
-            &*l.element_type
-        } else {
-            panic!()
-        };
-        self.emit_list_add_half(source_element, target_element);
+        self.exit_expr_line(line);
 
         // mov rax,[rbp+{}]
         self.emit_with_stack(&[0x48, 0x8B, 0x85], &result);
@@ -635,6 +844,54 @@ impl<'a> Emitter<'a> {
         self.free_stack(left);
     }
 
+    // Extension: `append(list, x)` copies the list with the new element in the standard library
+    pub fn emit_list_append(&mut self, expr: &CallExpr, target_element: &ValueType) {
+        self.emit_expression(&expr.args[0]);
+        let list = self.alloc_stack(TicketType::Reference);
+        // mov [rbp+{}],rax
+        self.emit_with_stack(&[0x48, 0x89, 0x85], &list);
+
+        self.emit_expression(&expr.args[1]);
+        self.emit_coerce(expr.args[1].get_type(), target_element);
+        let value = self.alloc_stack(target_element.ticket_type());
+        // mov [rbp+{}],rax
+        self.emit_with_stack(&[0x48, 0x89, 0x85], &value);
+
+        self.call_system(
+            BUILTIN_LIST_APPEND,
+            &[
+                Arg::Frame(list.offset),
+                Arg::Frame(value.offset),
+                Arg::Symbol(target_element.list_prototype()),
+                Arg::Reg(Reg::Rbp),
+                Arg::Reg(Reg::Rsp),
+            ],
+        );
+        self.emit_ref_map();
+        self.free_stack(value);
+        self.free_stack(list);
+    }
+
+    // Extension: compare objects of a user class attribute by attribute in the standard library
+    pub fn emit_obj_compare(&mut self, expr: &BinaryExpr) {
+        self.emit_expression(&expr.left);
+        let left = self.alloc_stack(TicketType::Reference);
+        // mov [rbp+{}],rax
+        self.emit_with_stack(&[0x48, 0x89, 0x85], &left);
+        self.emit_expression(&expr.right);
+        self.call_system(
+            BUILTIN_OBJ_EQ,
+            &[Arg::Frame(left.offset), Arg::Reg(Reg::Rax)],
+        );
+        self.free_stack(left);
+        // movzx eax,al
+        self.emit(&[0x0F, 0xB6, 0xC0]);
+        if expr.operator == BinaryOp::Ne {
+            // xor al,1
+            self.emit(&[0x34, 0x01]);
+        }
+    }
+
     pub fn emit_str_compare(&mut self, expr: &BinaryExpr) {
         self.emit_expression(&expr.left);
         let left = self.alloc_stack(TicketType::Reference);
@@ -691,83 +948,466 @@ impl<'a> Emitter<'a> {
         self.emit(&[0x48, 0x89, 0xD0]);
     }
 
-    pub fn emit_binary_expr(&mut self, expr: &BinaryExpr, target_type: &ValueType) {
+    // Extension: compare lists of int, bool or str element-wise.
+    // Like Python, a list is equal to itself and None is only equal to None.
+    pub fn emit_list_compare(&mut self, expr: &BinaryExpr) {
+        // Either side can be the empty list, whose elements never need to be compared
+        let element_type = [expr.left.get_type(), expr.right.get_type()]
+            .iter()
+            .find_map(|t| match t {
+                ValueType::ListValueType(l) => Some(l.element_type.as_ref()),
+                _ => None,
+            })
+            .unwrap_or(&TYPE_INT);
+
+        self.emit_expression(&expr.left);
+        let left = self.alloc_stack(TicketType::Reference);
+        // mov [rbp+{}],rax
+        self.emit_with_stack(&[0x48, 0x89, 0x85], &left);
+        self.emit_expression(&expr.right);
+        // mov r11,[rbp+{}]
+        self.emit_with_stack(&[0x4C, 0x8B, 0x9D], &left);
+        self.free_stack(left);
+
+        let mut not_equal = vec![];
+
+        // mov edx,1
+        self.emit(&[0xBA, 0x01, 0x00, 0x00, 0x00]);
+        // cmp rax,r11
+        self.emit(&[0x4C, 0x39, 0xD8]);
+        // je
+        self.emit(&[0x0F, 0x84]);
+        let same = self.jump_from();
+        // test rax,rax
+        self.emit(&[0x48, 0x85, 0xC0]);
+        // je
+        self.emit(&[0x0F, 0x84]);
+        not_equal.push(self.jump_from());
+        // test r11,r11
+        self.emit(&[0x4D, 0x85, 0xDB]);
+        // je
+        self.emit(&[0x0F, 0x84]);
+        not_equal.push(self.jump_from());
+
+        // mov rcx,[rax+ARRAY_LEN_OFFSET]
+        self.emit(&[0x48, 0x8B, 0x48, ARRAY_LEN_OFFSET as u8]);
+        // cmp rcx,[r11+ARRAY_LEN_OFFSET]
+        self.emit(&[0x49, 0x3B, 0x4B, ARRAY_LEN_OFFSET as u8]);
+        // jne
+        self.emit(&[0x0F, 0x85]);
+        not_equal.push(self.jump_from());
+        // lea rdi,[rax+ARRAY_ELEMENT_OFFSET]
+        self.emit(&[0x48, 0x8D, 0x78, ARRAY_ELEMENT_OFFSET as u8]);
+        // lea rsi,[r11+ARRAY_ELEMENT_OFFSET]
+        self.emit(&[0x49, 0x8D, 0x73, ARRAY_ELEMENT_OFFSET as u8]);
+
+        let element_loop = self.jump_to();
+        // test rcx,rcx
+        self.emit(&[0x48, 0x85, 0xC9]);
+        // je
+        self.emit(&[0x0F, 0x84]);
+        let equal = self.jump_from();
+
+        let element_size = if *element_type == *TYPE_INT {
+            // mov r8d,[rdi]
+            self.emit(&[0x44, 0x8B, 0x07]);
+            // cmp r8d,[rsi]
+            self.emit(&[0x44, 0x3B, 0x06]);
+            // jne
+            self.emit(&[0x0F, 0x85]);
+            not_equal.push(self.jump_from());
+            4
+        } else if *element_type == *TYPE_BOOL {
+            // mov r8b,[rdi]
+            self.emit(&[0x44, 0x8A, 0x07]);
+            // cmp r8b,[rsi]
+            self.emit(&[0x44, 0x3A, 0x06]);
+            // jne
+            self.emit(&[0x0F, 0x85]);
+            not_equal.push(self.jump_from());
+            1
+        } else {
+            // mov r8,[rdi]
+            self.emit(&[0x4C, 0x8B, 0x07]);
+            // mov r9,[rsi]
+            self.emit(&[0x4C, 0x8B, 0x0E]);
+            self.emit_str_equal(&mut not_equal);
+            8
+        };
+
+        // add rdi,{element_size}
+        self.emit(&[0x48, 0x83, 0xC7, element_size]);
+        // add rsi,{element_size}
+        self.emit(&[0x48, 0x83, 0xC6, element_size]);
+        // dec rcx
+        self.emit(&[0x48, 0xFF, 0xC9]);
+        // jmp
+        self.emit(&[0xE9]);
+        self.from_here(element_loop);
+
+        self.to_here(equal);
+        // jmp
+        self.emit(&[0xE9]);
+        let finish = self.jump_from();
+        for jump in not_equal {
+            self.to_here(jump);
+        }
+        // xor edx,edx
+        self.emit(&[0x31, 0xD2]);
+        self.to_here(finish);
+        self.to_here(same);
+
+        if expr.operator == BinaryOp::Ne {
+            // xor edx,1
+            self.emit(&[0x83, 0xF2, 0x01]);
+        }
+
+        // mov rax,rdx
+        self.emit(&[0x48, 0x89, 0xD0]);
+    }
+
+    // Compare the strings in r8 and r9 like emit_str_compare, from the last character.
+    // Falls through if they are equal, and jumps to a new entry of `not_equal` otherwise.
+    // Overwrites r10 and al
+    fn emit_str_equal(&mut self, not_equal: &mut Vec<ForwardJumper>) {
+        // mov r10,[r8+ARRAY_LEN_OFFSET]
+        self.emit(&[0x4D, 0x8B, 0x50, ARRAY_LEN_OFFSET as u8]);
+        // cmp r10,[r9+ARRAY_LEN_OFFSET]
+        self.emit(&[0x4D, 0x3B, 0x51, ARRAY_LEN_OFFSET as u8]);
+        // jne
+        self.emit(&[0x0F, 0x85]);
+        not_equal.push(self.jump_from());
+        let char_loop = self.jump_to();
+        // test r10,r10
+        self.emit(&[0x4D, 0x85, 0xD2]);
+        // je
+        self.emit(&[0x0F, 0x84]);
+        let equal = self.jump_from();
+        // mov al,[r8+r10+ARRAY_ELEMENT_OFFSET-1]
+        self.emit(&[0x43, 0x8A, 0x44, 0x10, ARRAY_ELEMENT_OFFSET as u8 - 1]);
+        // cmp al,[r9+r10+ARRAY_ELEMENT_OFFSET-1]
+        self.emit(&[0x43, 0x3A, 0x44, 0x11, ARRAY_ELEMENT_OFFSET as u8 - 1]);
+        // jne
+        self.emit(&[0x0F, 0x85]);
+        not_equal.push(self.jump_from());
+        // dec r10
+        self.emit(&[0x49, 0xFF, 0xCA]);
+        // jmp
+        self.emit(&[0xE9]);
+        self.from_here(char_loop);
+        self.to_here(equal);
+    }
+
+    // Extension: `x in list` scans the list, and `x in str` searches for the substring
+    pub fn emit_membership(&mut self, expr: &BinaryExpr) {
         let left_type = expr.left.get_type();
-        if expr.operator == BinaryOp::Add && left_type == &*TYPE_STR {
-            self.emit_string_add(expr);
-        } else if expr.operator == BinaryOp::Add && left_type != &*TYPE_INT {
-            let target_element = if let ValueType::ListValueType(l) = &target_type {
-                &*l.element_type
-            } else {
-                panic!()
-            };
-            self.emit_list_add(expr, target_element);
-        } else if (expr.operator == BinaryOp::Eq || expr.operator == BinaryOp::Ne)
-            && left_type == &*TYPE_STR
-        {
-            self.emit_str_compare(expr);
-        } else if expr.operator == BinaryOp::Or || expr.operator == BinaryOp::And {
-            self.emit_expression(&expr.left);
-            // test al,al
-            self.emit(&[0x84, 0xC0]);
-            if expr.operator == BinaryOp::Or {
-                // jne
-                self.emit(&[0x0f, 0x85]);
-            } else {
-                // je
-                self.emit(&[0x0f, 0x84]);
-            }
-            let skip = self.jump_from();
-            self.emit_expression(&expr.right);
-            self.to_here(skip);
+        self.emit_expression(&expr.left);
+        let left = self.alloc_stack(left_type.ticket_type());
+        // mov [rbp+{}],rax
+        self.emit_with_stack(&[0x48, 0x89, 0x85], &left);
+        self.emit_expression(&expr.right);
+        if expr.right.get_type() != &*TYPE_STR {
+            self.emit_check_none();
+        }
+        // mov r11,[rbp+{}]
+        self.emit_with_stack(&[0x4C, 0x8B, 0x9D], &left);
+        self.free_stack(left);
+
+        if expr.right.get_type() == &*TYPE_STR {
+            self.emit_substring_search();
         } else {
-            self.emit_expression(&expr.left);
-            let left = self.alloc_stack(expr.left.get_type().ticket_type());
-            // mov [rbp+{}],rax
-            self.emit_with_stack(&[0x48, 0x89, 0x85], &left);
-            self.emit_expression(&expr.right);
-            // mov r11,[rbp+{}]
-            self.emit_with_stack(&[0x4C, 0x8B, 0x9D], &left);
-            self.free_stack(left);
+            // The checker only allows elements of the same basic type as the value,
+            // or values and elements that are both compared by identity
+            self.emit_list_scan(left_type, false);
+        }
+    }
 
-            match expr.operator {
-                BinaryOp::Add => {
-                    // Note: swapped
-                    // add eax,r11d
-                    self.emit(&[0x44, 0x01, 0xD8]);
-                }
-                BinaryOp::Sub => {
-                    // sub r11d,eax
-                    // mov eax,r11d
-                    self.emit(&[0x41, 0x29, 0xC3, 0x44, 0x89, 0xD8]);
-                }
-                BinaryOp::Mul => {
-                    // imul eax,r11d
-                    self.emit(&[0x41, 0x0F, 0xAF, 0xC3]);
-                }
-                BinaryOp::Div | BinaryOp::Mod => {
-                    // test eax,eax
-                    self.emit(&[0x85, 0xC0]);
-                    // jne
-                    self.emit(&[0x0F, 0x85]);
-                    let ok = self.jump_from();
-                    self.prepare_call(self.platform.stack_reserve());
-                    self.call(BUILTIN_DIV_ZERO);
-                    self.to_here(ok);
-                    // xchg eax,r11d
-                    self.emit(&[0x41, 0x93]);
-                    // mov ecx,r11d
-                    self.emit(&[0x44, 0x89, 0xD9]);
-                    // xor ecx,eax
-                    self.emit(&[0x31, 0xC1]);
-                    // shr ecx,31
-                    self.emit(&[0xC1, 0xE9, 0x1F]);
-                    // cdq
-                    self.emit(&[0x99]);
-                    // idiv,r11d
-                    self.emit(&[0x41, 0xF7, 0xFB]);
-                    if expr.operator == BinaryOp::Mod {
-                        // mov eax,edx
+    // Extension: `count(list, x)` counts the elements of a list of int equal to `x`
+    pub fn emit_list_count(&mut self, expr: &CallExpr) {
+        self.emit_expression(&expr.args[0]);
+        let list = self.alloc_stack(TicketType::Reference);
+        // mov [rbp+{}],rax
+        self.emit_with_stack(&[0x48, 0x89, 0x85], &list);
+        self.emit_expression(&expr.args[1]);
+        // mov r11,rax
+        self.emit(&[0x49, 0x89, 0xC3]);
+        // mov rax,[rbp+{}]
+        self.emit_with_stack(&[0x48, 0x8B, 0x85], &list);
+        self.free_stack(list);
+        self.emit_check_none();
+        self.emit_list_scan(&TYPE_INT, true);
+    }
+
+    // Look for the value in r11 among the elements of the list in rax, comparing int, bool and str
+    // by value and others by identity. The result in rax is whether it is found, or with `count`,
+    // how many times
+    fn emit_list_scan(&mut self, element_type: &ValueType, count: bool) {
+        // xor edx,edx
+        self.emit(&[0x31, 0xD2]);
+        // mov rcx,[rax+ARRAY_LEN_OFFSET]
+        self.emit(&[0x48, 0x8B, 0x48, ARRAY_LEN_OFFSET as u8]);
+        // lea rdi,[rax+ARRAY_ELEMENT_OFFSET]
+        self.emit(&[0x48, 0x8D, 0x78, ARRAY_ELEMENT_OFFSET as u8]);
+
+        let element_loop = self.jump_to();
+        // test rcx,rcx
+        self.emit(&[0x48, 0x85, 0xC9]);
+        // je
+        self.emit(&[0x0F, 0x84]);
+        let end = self.jump_from();
+
+        let mut mismatch = vec![];
+        let element_size = if *element_type == *TYPE_INT {
+            // cmp r11d,[rdi]
+            self.emit(&[0x44, 0x3B, 0x1F]);
+            // jne
+            self.emit(&[0x0F, 0x85]);
+            mismatch.push(self.jump_from());
+            4
+        } else if *element_type == *TYPE_BOOL {
+            // cmp r11b,[rdi]
+            self.emit(&[0x44, 0x3A, 0x1F]);
+            // jne
+            self.emit(&[0x0F, 0x85]);
+            mismatch.push(self.jump_from());
+            1
+        } else if *element_type == *TYPE_STR {
+            // mov r8,[rdi]
+            self.emit(&[0x4C, 0x8B, 0x07]);
+            // mov r9,r11
+            self.emit(&[0x4D, 0x89, 0xD9]);
+            self.emit_str_equal(&mut mismatch);
+            8
+        } else {
+            // cmp r11,[rdi]
+            self.emit(&[0x4C, 0x3B, 0x1F]);
+            // jne
+            self.emit(&[0x0F, 0x85]);
+            mismatch.push(self.jump_from());
+            8
+        };
+
+        let found = if count {
+            // inc edx
+            self.emit(&[0xFF, 0xC2]);
+            None
+        } else {
+            // mov edx,1
+            self.emit(&[0xBA, 0x01, 0x00, 0x00, 0x00]);
+            // jmp
+            self.emit(&[0xE9]);
+            Some(self.jump_from())
+        };
+
+        for jump in mismatch {
+            self.to_here(jump);
+        }
+        // add rdi,{element_size}
+        self.emit(&[0x48, 0x83, 0xC7, element_size]);
+        // dec rcx
+        self.emit(&[0x48, 0xFF, 0xC9]);
+        // jmp
+        self.emit(&[0xE9]);
+        self.from_here(element_loop);
+
+        self.to_here(end);
+        if let Some(found) = found {
+            self.to_here(found);
+        }
+        // mov eax,edx
+        self.emit(&[0x89, 0xD0]);
+    }
+
+    // Whether the string in r11 is a substring of the one in rax, trying each position in turn.
+    // The result is in rax
+    fn emit_substring_search(&mut self) {
+        // mov rcx,[rax+ARRAY_LEN_OFFSET]
+        self.emit(&[0x48, 0x8B, 0x48, ARRAY_LEN_OFFSET as u8]);
+        // The number of positions to try, minus one
+        // sub rcx,[r11+ARRAY_LEN_OFFSET]
+        self.emit(&[0x49, 0x2B, 0x4B, ARRAY_LEN_OFFSET as u8]);
+        // jl
+        self.emit(&[0x0F, 0x8C]);
+        let too_long = self.jump_from();
+        // lea rdi,[rax+ARRAY_ELEMENT_OFFSET]
+        self.emit(&[0x48, 0x8D, 0x78, ARRAY_ELEMENT_OFFSET as u8]);
+
+        let position_loop = self.jump_to();
+        // mov r10,[r11+ARRAY_LEN_OFFSET]
+        self.emit(&[0x4D, 0x8B, 0x53, ARRAY_LEN_OFFSET as u8]);
+        let char_loop = self.jump_to();
+        // test r10,r10
+        self.emit(&[0x4D, 0x85, 0xD2]);
+        // je
+        self.emit(&[0x0F, 0x84]);
+        let found = self.jump_from();
+        // mov al,[rdi+r10-1]
+        self.emit(&[0x42, 0x8A, 0x44, 0x17, 0xFF]);
+        // cmp al,[r11+r10+ARRAY_ELEMENT_OFFSET-1]
+        self.emit(&[0x43, 0x3A, 0x44, 0x13, ARRAY_ELEMENT_OFFSET as u8 - 1]);
+        // jne
+        self.emit(&[0x0F, 0x85]);
+        let mismatch = self.jump_from();
+        // dec r10
+        self.emit(&[0x49, 0xFF, 0xCA]);
+        // jmp
+        self.emit(&[0xE9]);
+        self.from_here(char_loop);
+
+        self.to_here(mismatch);
+        // inc rdi
+        self.emit(&[0x48, 0xFF, 0xC7]);
+        // dec rcx
+        self.emit(&[0x48, 0xFF, 0xC9]);
+        // jns
+        self.emit(&[0x0F, 0x89]);
+        self.from_here(position_loop);
+
+        self.to_here(too_long);
+        // xor eax,eax
+        self.emit(&[0x31, 0xC0]);
+        // jmp
+        self.emit(&[0xE9]);
+        let end = self.jump_from();
+        self.to_here(found);
+        // mov eax,1
+        self.emit(&[0xB8, 0x01, 0x00, 0x00, 0x00]);
+        self.to_here(end);
+    }
+
+    // Extension: `and` and `or` on any values. Like Python, the result is the left operand
+    // if it decides the result by its truthiness, otherwise the right operand
+    fn emit_truthy_and_or(&mut self, expr: &BinaryExpr, target_type: &ValueType) {
+        let left_type = expr.left.get_type();
+        self.emit_expression(&expr.left);
+        let left = self.alloc_stack(left_type.ticket_type());
+        // mov [rbp+{}],rax
+        self.emit_with_stack(&[0x48, 0x89, 0x85], &left);
+        if left_type == &*TYPE_INT {
+            // test eax,eax
+            self.emit(&[0x85, 0xC0]);
+        } else {
+            if left_type != &*TYPE_BOOL {
+                self.call_system(BUILTIN_TRUTHY, &[Arg::Reg(Reg::Rax)]);
+            }
+            // test al,al
+            self.emit(&[0x84, 0xC0]);
+        }
+        if expr.operator == BinaryOp::Or {
+            // je
+            self.emit(&[0x0f, 0x84]);
+        } else {
+            // jne
+            self.emit(&[0x0f, 0x85]);
+        }
+        let label_right = self.jump_from();
+
+        // mov rax,[rbp+{}]
+        self.emit_with_stack(&[0x48, 0x8B, 0x85], &left);
+        self.free_stack(left);
+        self.emit_coerce(left_type, target_type);
+
+        // jmp
+        self.emit(&[0xe9]);
+        let label_end = self.jump_from();
+        self.to_here(label_right);
+
+        self.emit_expression(&expr.right);
+        self.emit_coerce(expr.right.get_type(), target_type);
+
+        self.to_here(label_end);
+    }
+
+    pub fn emit_binary_expr(&mut self, expr: &BinaryExpr, target_type: &ValueType) {
+        let left_type = expr.left.get_type();
+        if expr.operator == BinaryOp::In {
+            self.emit_membership(expr);
+        } else if expr.operator == BinaryOp::Add && left_type == &*TYPE_STR {
+            self.emit_string_add(expr);
+        } else if expr.operator == BinaryOp::Add && left_type != &*TYPE_INT {
+            // Two empty lists make another one of any element type
+            let target_element = target_type.list_element().unwrap_or(&*TYPE_OBJECT);
+            self.emit_list_add(expr, target_element);
+        } else if (expr.operator == BinaryOp::Eq || expr.operator == BinaryOp::Ne)
+            && left_type == &*TYPE_STR
+        {
+            self.emit_str_compare(expr);
+        } else if (expr.operator == BinaryOp::Eq || expr.operator == BinaryOp::Ne)
+            && self.class_eq
+            && left_type.is_user_class()
+        {
+            self.emit_obj_compare(expr);
+        } else if (expr.operator == BinaryOp::Eq || expr.operator == BinaryOp::Ne)
+            && !left_type.is_plain()
+        {
+            self.emit_list_compare(expr);
+        } else if (expr.operator == BinaryOp::Or || expr.operator == BinaryOp::And)
+            && target_type != &*TYPE_BOOL
+        {
+            self.emit_truthy_and_or(expr, target_type);
+        } else if expr.operator == BinaryOp::Or || expr.operator == BinaryOp::And {
+            self.emit_expression(&expr.left);
+            // test al,al
+            self.emit(&[0x84, 0xC0]);
+            if expr.operator == BinaryOp::Or {
+                // jne
+                self.emit(&[0x0f, 0x85]);
+            } else {
+                // je
+                self.emit(&[0x0f, 0x84]);
+            }
+            let skip = self.jump_from();
+            self.emit_expression(&expr.right);
+            self.to_here(skip);
+        } else {
+            self.emit_expression(&expr.left);
+            let left = self.alloc_stack(expr.left.get_type().ticket_type());
+            // mov [rbp+{}],rax
+            self.emit_with_stack(&[0x48, 0x89, 0x85], &left);
+            self.emit_expression(&expr.right);
+            // mov r11,[rbp+{}]
+            self.emit_with_stack(&[0x4C, 0x8B, 0x9D], &left);
+            self.free_stack(left);
+
+            match expr.operator {
+                BinaryOp::Add => {
+                    // Note: swapped
+                    // add eax,r11d
+                    self.emit(&[0x44, 0x01, 0xD8]);
+                }
+                BinaryOp::Sub => {
+                    // sub r11d,eax
+                    // mov eax,r11d
+                    self.emit(&[0x41, 0x29, 0xC3, 0x44, 0x89, 0xD8]);
+                }
+                BinaryOp::Mul => {
+                    // imul eax,r11d
+                    self.emit(&[0x41, 0x0F, 0xAF, 0xC3]);
+                }
+                BinaryOp::Div | BinaryOp::Mod => {
+                    if self.runtime_checks == RuntimeChecks::All {
+                        // test eax,eax
+                        self.emit(&[0x85, 0xC0]);
+                        // jne
+                        self.emit(&[0x0F, 0x85]);
+                        let ok = self.jump_from();
+                        self.call_system(BUILTIN_DIV_ZERO, &[]);
+                        self.to_here(ok);
+                    }
+                    // xchg eax,r11d
+                    self.emit(&[0x41, 0x93]);
+                    // mov ecx,r11d
+                    self.emit(&[0x44, 0x89, 0xD9]);
+                    // xor ecx,eax
+                    self.emit(&[0x31, 0xC1]);
+                    // shr ecx,31
+                    self.emit(&[0xC1, 0xE9, 0x1F]);
+                    // cdq
+                    self.emit(&[0x99]);
+                    // idiv,r11d
+                    self.emit(&[0x41, 0xF7, 0xFB]);
+                    if expr.operator == BinaryOp::Mod {
+                        // mov eax,edx
                         self.emit(&[0x89, 0xD0]);
                         // test edx,edx
                         self.emit(&[0x85, 0xD2]);
@@ -788,6 +1428,53 @@ impl<'a> Emitter<'a> {
                         self.emit(&[0x29, 0xC8]);
                     }
                 }
+                BinaryOp::BitAnd => {
+                    // and eax,r11d
+                    self.emit(&[0x44, 0x21, 0xD8]);
+                }
+                BinaryOp::BitOr => {
+                    // or eax,r11d
+                    self.emit(&[0x44, 0x09, 0xD8]);
+                }
+                BinaryOp::BitXor => {
+                    // xor eax,r11d
+                    self.emit(&[0x44, 0x31, 0xD8]);
+                }
+                BinaryOp::Shl | BinaryOp::Shr => {
+                    // Python raises an error on negative shift counts
+                    // test eax,eax
+                    self.emit(&[0x85, 0xC0]);
+                    // jns
+                    self.emit(&[0x0F, 0x89]);
+                    let ok = self.jump_from();
+                    self.call_system(BUILTIN_INVALID_ARG, &[]);
+                    self.to_here(ok);
+                    // mov ecx,eax
+                    self.emit(&[0x89, 0xC1]);
+                    // mov eax,r11d
+                    self.emit(&[0x44, 0x89, 0xD8]);
+                    // Counts above 31 are not masked, so the result is what Python
+                    // gives truncated to 32 bits
+                    if expr.operator == BinaryOp::Shl {
+                        // shl eax,cl
+                        self.emit(&[0xD3, 0xE0]);
+                        // xor edx,edx
+                        self.emit(&[0x31, 0xD2]);
+                        // cmp ecx,31
+                        self.emit(&[0x83, 0xF9, 0x1F]);
+                        // cmova eax,edx
+                        self.emit(&[0x0F, 0x47, 0xC2]);
+                    } else {
+                        // mov edx,31
+                        self.emit(&[0xBA, 0x1F, 0x00, 0x00, 0x00]);
+                        // cmp ecx,31
+                        self.emit(&[0x83, 0xF9, 0x1F]);
+                        // cmova ecx,edx
+                        self.emit(&[0x0F, 0x47, 0xCA]);
+                        // sar eax,cl
+                        self.emit(&[0xD3, 0xF8]);
+                    }
+                }
                 BinaryOp::Is => {
                     // cmp r11,rax
                     self.emit(&[0x49, 0x39, 0xC3]);
@@ -845,6 +1532,17 @@ impl<'a> Emitter<'a> {
     ) {
         let mut args_stack = vec![];
 
+        let inline = if virtual_call {
+            None
+        } else {
+            match self.storage_env().get(name) {
+                Some(EnvSlot::Func(f)) => f.inline.as_ref(),
+                // A function value (extension)
+                Some(EnvSlot::Var(..)) => None,
+                None => panic!("internal compiler error: `{}` is not a function", name),
+            }
+        };
+
         // Evaluate all arguments
         for (i, arg) in args.iter().enumerate() {
             self.emit_expression(arg);
@@ -863,6 +1561,11 @@ impl<'a> Emitter<'a> {
             args_stack.push(arg_stack);
         }
 
+        if let Some(inline) = inline {
+            self.emit_inline_body(inline, args_stack);
+            return;
+        }
+
         // Transfer arguments to parameter slots
         self.prepare_call(args.len());
         for (i, arg_stack) in args_stack.into_iter().enumerate().rev() {
@@ -871,31 +1574,27 @@ impl<'a> Emitter<'a> {
             let offset = i * 8;
             // mov QWORD PTR [rsp+{offset}],rax
             self.emit(&[0x48, 0x89, 0x84, 0x24]);
-            self.emit(&(offset as u32).to_le_bytes());
+            self.emit_value(offset as u32);
             self.free_stack(arg_stack);
         }
 
         // Call the function
         if virtual_call {
-            let offset = if let ValueType::ClassValueType(c) = args[0].get_type() {
-                if matches!(
-                    c.class_name.as_str(),
-                    "int" | "bool" | "str" | "<None>" | "<Empty>"
-                ) {
-                    assert!(name == "__init__");
-                    PROTOTYPE_INIT_OFFSET
-                } else {
-                    self.classes()[&c.class_name].methods[name].offset
-                }
+            if let Some(link_name) = self.direct_method(&args[0], name) {
+                self.call(&link_name);
             } else {
-                panic!()
-            };
-            self.call_virtual(offset);
+                let offset = self.method_offset(args[0].get_type(), name);
+                self.call_virtual(offset);
+            }
         } else {
-            let slot = if let Some(EnvSlot::Func(f)) = self.storage_env().get(name) {
-                f
-            } else {
-                panic!()
+            let slot = match self.storage_env().get(name) {
+                Some(EnvSlot::Func(f)) => f,
+                Some(EnvSlot::Var(..)) => {
+                    self.call_func_value(name);
+                    self.emit_ref_map();
+                    return;
+                }
+                None => panic!("internal compiler error: `{}` is not a function", name),
             };
 
             let link_name = slot.link_name.clone();
@@ -916,6 +1615,111 @@ impl<'a> Emitter<'a> {
         self.emit_ref_map();
     }
 
+    // Call the function value in the variable `name` (extension). The arguments are already in
+    // place, and the static link is passed like for direct calls
+    fn call_func_value(&mut self, name: &str) {
+        let variable = Variable {
+            base: NodeBase::new(0, 0, 0, 0),
+            name: name.to_owned(),
+        };
+        self.emit_load_var(&variable, &TYPE_OBJECT);
+        self.emit_check_none();
+        // mov r10,[rax+OBJECT_ATTRIBUTE_OFFSET+8]
+        self.emit(&[0x4C, 0x8B, 0x50, OBJECT_ATTRIBUTE_OFFSET as u8 + 8]);
+        // call [rax+OBJECT_ATTRIBUTE_OFFSET]
+        self.emit(&[0xFF, 0x50, OBJECT_ATTRIBUTE_OFFSET as u8]);
+    }
+
+    // Offset of a method in the prototype of the receiver's static type.
+    // Special types have no class slot, and their only method is `__init__` inherited from `object`.
+    fn method_offset(&self, receiver: &ValueType, name: &str) -> u32 {
+        let class_name = if let ValueType::ClassValueType(c) = receiver {
+            c.class_name.as_str()
+        } else {
+            panic!(
+                "internal compiler error: method `{}` called on list type `{}`",
+                name, receiver
+            )
+        };
+        if matches!(class_name, "int" | "bool" | "str" | "<None>" | "<Empty>") {
+            if name != "__init__" {
+                panic!(
+                    "internal compiler error: method `{}` called on special type `{}`",
+                    name, class_name
+                )
+            }
+            return PROTOTYPE_INIT_OFFSET;
+        }
+        let class = self.classes().get(class_name).unwrap_or_else(|| {
+            panic!(
+                "internal compiler error: method `{}` called on unknown class `{}`",
+                name, class_name
+            )
+        });
+        class
+            .methods
+            .get(name)
+            .unwrap_or_else(|| {
+                panic!(
+                    "internal compiler error: class `{}` has no method `{}`",
+                    class_name, name
+                )
+            })
+            .offset
+    }
+
+    // Link name of a method called on `self`, if it is declared in the class of the method
+    // being emitted and no subclass overrides it, so the receiver can't change the target.
+    fn direct_method(&self, receiver: &Expr, name: &str) -> Option<String> {
+        let (class_name, self_name) = self.self_receiver.as_ref()?;
+        if !matches!(&receiver.content, ExprContent::Variable(v) if v.name == *self_name) {
+            return None;
+        }
+        let method = self.classes().get(class_name)?.methods.get(name)?;
+        if method.direct && method.link_name == class_name.clone() + "." + name {
+            Some(method.link_name.clone())
+        } else {
+            None
+        }
+    }
+
+    // Evaluate the body of an inlined function in place of a call,
+    // reading parameters from the stack slots holding the arguments
+    fn emit_inline_body(&mut self, inline: &InlineBody, args_stack: Vec<StackTicket>) {
+        let params = inline
+            .params
+            .iter()
+            .cloned()
+            .zip(args_stack.iter().map(|arg_stack| arg_stack.offset))
+            .collect();
+        let outer = self.inline_params.replace(params);
+        self.emit_expression(&inline.value);
+        self.emit_coerce(inline.value.get_type(), &inline.return_type);
+        self.inline_params = outer;
+        for arg_stack in args_stack.into_iter().rev() {
+            self.free_stack(arg_stack);
+        }
+    }
+
+    // Check the int index in eax against the length of the array object in rsi.
+    // The index is sign-extended into rax, and then compared unsigned with the length,
+    // so that negative indices are seen as huge values and are out of bound.
+    // On success, rax holds the index as a 64-bit value, which is all that is left without checks.
+    pub fn emit_index_bounds_check(&mut self) {
+        // cdqe
+        self.emit(&[0x48, 0x98]);
+        if self.runtime_checks == RuntimeChecks::Off {
+            return;
+        }
+        // cmp rax,[rsi+ARRAY_LEN_OFFSET]
+        self.emit(&[0x48, 0x3B, 0x46, ARRAY_LEN_OFFSET as u8]);
+        // jb
+        self.emit(&[0x0F, 0x82]);
+        let ok = self.jump_from();
+        self.call_system(BUILTIN_OUT_OF_BOUND, &[]);
+        self.to_here(ok);
+    }
+
     pub fn emit_str_index(&mut self, expr: &IndexExpr) {
         self.emit_expression(&expr.list);
         let list = self.alloc_stack(TicketType::Reference);
@@ -923,8 +1727,9 @@ impl<'a> Emitter<'a> {
         self.emit_with_stack(&[0x48, 0x89, 0x85], &list);
 
         self.emit_expression(&expr.index);
-        // cdqe
-        self.emit(&[0x48, 0x98]);
+        // mov rsi,[rbp+{}]
+        self.emit_with_stack(&[0x48, 0x8B, 0xB5], &list);
+        self.emit_index_bounds_check();
         let index = self.alloc_stack(TicketType::Plain);
         // mov [rbp+{}],rax
         self.emit_with_stack(&[0x48, 0x89, 0x85], &index);
@@ -937,14 +1742,6 @@ impl<'a> Emitter<'a> {
         // mov r11,[rbp+{}]
         self.emit_with_stack(&[0x4C, 0x8B, 0x9D], &list);
         self.free_stack(list);
-        // cmp rsi,[r11+ARRAY_LEN_OFFSET]
-        self.emit(&[0x49, 0x3B, 0x73, ARRAY_LEN_OFFSET as u8]);
-        // jb
-        self.emit(&[0x0F, 0x82]);
-        let ok = self.jump_from();
-        self.prepare_call(self.platform.stack_reserve());
-        self.call(BUILTIN_OUT_OF_BOUND);
-        self.to_here(ok);
         // mov r10b,[r11+rsi+ARRAY_ELEMENT_OFFSET]
         self.emit(&[0x45, 0x8A, 0x54, 0x33, ARRAY_ELEMENT_OFFSET as u8]);
         // mov [rax+ARRAY_ELEMENT_OFFSET],r10b
@@ -958,8 +1755,6 @@ impl<'a> Emitter<'a> {
         // mov [rbp+{}],rax
         self.emit_with_stack(&[0x48, 0x89, 0x85], &list);
         self.emit_expression(&expr.index);
-        // cdqe
-        self.emit(&[0x48, 0x98]);
         // mov rsi,[rbp+{}]
         self.emit_with_stack(&[0x48, 0x8B, 0xB5], &list);
         self.free_stack(list);
@@ -968,15 +1763,7 @@ impl<'a> Emitter<'a> {
         } else {
             panic!()
         };
-
-        // cmp rax,[rsi+ARRAY_LEN_OFFSET]
-        self.emit(&[0x48, 0x3B, 0x46, ARRAY_LEN_OFFSET as u8]);
-        // jb
-        self.emit(&[0x0F, 0x82]);
-        let ok = self.jump_from();
-        self.prepare_call(self.platform.stack_reserve());
-        self.call(BUILTIN_OUT_OF_BOUND);
-        self.to_here(ok);
+        self.emit_index_bounds_check();
 
         if element_type == &*TYPE_INT {
             // mov eax,[rsi+rax*4+ARRAY_ELEMENT_OFFSET]
@@ -991,29 +1778,48 @@ impl<'a> Emitter<'a> {
     }
 
     pub fn emit_member_expr(&mut self, expr: &MemberExpr) {
+        let class_slot = if let ValueType::ClassValueType(c) = expr.object.get_type() {
+            &self.classes()[&c.class_name]
+        } else {
+            panic!()
+        };
+
+        if let Some(slot) = class_slot.constants.get(&expr.member.name) {
+            // Class constant. The object is only evaluated if it is not the class name
+            let is_class_name = if let ExprContent::Variable(v) = &expr.object.content {
+                let is_param =
+                    matches!(&self.inline_params, Some(params) if params.contains_key(&v.name));
+                !is_param && !matches!(self.storage_env().get(&v.name), Some(EnvSlot::Var(..)))
+            } else {
+                false
+            };
+            if !is_class_name {
+                self.emit_expression(&expr.object);
+                self.emit_check_none();
+            }
+            self.emit_load_global(slot.offset, &slot.target_type);
+            return;
+        }
+
         self.emit_expression(&expr.object);
         self.emit_check_none();
         // mov rsi,rax
         self.emit(&[0x48, 0x89, 0xC6]);
 
-        let slot = if let ValueType::ClassValueType(c) = expr.object.get_type() {
-            &self.classes()[&c.class_name].attributes[&expr.member.name]
-        } else {
-            panic!()
-        };
+        let slot = &class_slot.attributes[&expr.member.name];
 
         if slot.target_type == *TYPE_INT {
             // mov eax,[rsi+{}]
             self.emit(&[0x8B, 0x86]);
-            self.emit(&slot.offset.to_le_bytes());
+            self.emit_value(slot.offset);
         } else if slot.target_type == *TYPE_BOOL {
             // mov al,[rsi+{}]
             self.emit(&[0x8A, 0x86]);
-            self.emit(&slot.offset.to_le_bytes());
+            self.emit_value(slot.offset);
         } else {
             // mov rax,[rsi+{}]
             self.emit(&[0x48, 0x8B, 0x86]);
-            self.emit(&slot.offset.to_le_bytes());
+            self.emit_value(slot.offset);
         }
     }
 
@@ -1039,7 +1845,7 @@ impl<'a> Emitter<'a> {
         self.to_here(label_end);
     }
 
-    pub fn emit_if_stmt(&mut self, stmt: &IfStmt, lines: &mut Vec<LineMap>) {
+    pub fn emit_if_stmt(&mut self, stmt: &IfStmt) {
         self.emit_expression(&stmt.condition);
         // test al,al
         self.emit(&[0x84, 0xC0]);
@@ -1047,8 +1853,8 @@ impl<'a> Emitter<'a> {
         self.emit(&[0x0f, 0x84]);
         let label_else = self.jump_from();
 
-        for stmt in &stmt.then_body {
-            self.emit_statement(stmt, lines);
+        for stmt in reachable(&stmt.then_body) {
+            self.emit_statement(stmt);
         }
 
         // jmp
@@ -1056,8 +1862,8 @@ impl<'a> Emitter<'a> {
         let label_end = self.jump_from();
         self.to_here(label_else);
 
-        for stmt in &stmt.else_body {
-            self.emit_statement(stmt, lines);
+        for stmt in reachable(&stmt.else_body) {
+            self.emit_statement(stmt);
         }
 
         self.to_here(label_end);
@@ -1067,7 +1873,7 @@ impl<'a> Emitter<'a> {
         if target_type == &*TYPE_EMPTY {
             // mov rsi,{len}
             self.emit(&[0x48, 0xc7, 0xc6]);
-            self.emit(&(expr.elements.len() as u32).to_le_bytes());
+            self.emit_value(expr.elements.len() as u32);
             self.call_builtin_alloc(OBJECT_LIST_PROTOTYPE);
             return;
         }
@@ -1078,17 +1884,11 @@ impl<'a> Emitter<'a> {
             panic!()
         };
 
-        let prototype = if element_type == &*TYPE_INT {
-            INT_LIST_PROTOTYPE
-        } else if element_type == &*TYPE_BOOL {
-            BOOL_LIST_PROTOTYPE
-        } else {
-            OBJECT_LIST_PROTOTYPE
-        };
+        let prototype = element_type.list_prototype();
 
         // mov rsi,{len}
         self.emit(&[0x48, 0xc7, 0xc6]);
-        self.emit(&(expr.elements.len() as u32).to_le_bytes());
+        self.emit_value(expr.elements.len() as u32);
         self.call_builtin_alloc(prototype);
         let result = self.alloc_stack(TicketType::Reference);
         // mov [rbp+{}],rax
@@ -1102,15 +1902,15 @@ impl<'a> Emitter<'a> {
             if element_type == &*TYPE_INT {
                 // mov [rdi+{}],eax
                 self.emit(&[0x89, 0x87]);
-                self.emit(&((i * 4) as u32 + ARRAY_ELEMENT_OFFSET).to_le_bytes());
+                self.emit_value((i * 4) as u32 + ARRAY_ELEMENT_OFFSET);
             } else if element_type == &*TYPE_BOOL {
                 // mov [rdi+{}],al
                 self.emit(&[0x88, 0x87]);
-                self.emit(&(i as u32 + ARRAY_ELEMENT_OFFSET).to_le_bytes());
+                self.emit_value(i as u32 + ARRAY_ELEMENT_OFFSET);
             } else {
                 // mov [rdi+{}],rax
                 self.emit(&[0x48, 0x89, 0x87]);
-                self.emit(&((i * 8) as u32 + ARRAY_ELEMENT_OFFSET).to_le_bytes());
+                self.emit_value((i * 8) as u32 + ARRAY_ELEMENT_OFFSET);
             }
         }
 
@@ -1119,36 +1919,72 @@ impl<'a> Emitter<'a> {
         self.free_stack(result);
     }
 
-    pub fn emit_load_var(&mut self, identifier: &Variable, target_type: &ValueType) {
-        let (offset, level) =
-            if let Some(EnvSlot::Var(v, _)) = self.storage_env().get(&identifier.name) {
-                (v.offset, v.level)
-            } else {
-                panic!()
-            };
+    pub fn emit_load_global(&mut self, offset: i32, target_type: &ValueType) {
+        if target_type == &*TYPE_INT {
+            // mov eax,[rip+{}]
+            self.emit(&[0x8B, 0x05]);
+            self.emit_link(GLOBAL_SECTION, offset);
+        } else if target_type == &*TYPE_BOOL {
+            // mov al,[rip+{}]
+            self.emit(&[0x8A, 0x05]);
+            self.emit_link(GLOBAL_SECTION, offset);
+        } else {
+            // mov rax,[rip+{}]
+            self.emit(&[0x48, 0x8B, 0x05]);
+            self.emit_link(GLOBAL_SECTION, offset);
+        }
+    }
 
-        if level == 0 {
-            // Global variable
+    // Allocate a function value for the top-level function `link_name` (extension).
+    // The static link after the function address is left null
+    pub fn emit_func_value(&mut self, link_name: &str) {
+        // xor rsi,rsi
+        self.emit(&[0x48, 0x31, 0xF6]);
+        self.call_builtin_alloc(FUNC_PROTOTYPE);
+        // lea rcx,[rip+{link_name}]
+        self.emit(&[0x48, 0x8D, 0x0D]);
+        self.emit_link(link_name, 0);
+        // mov [rax+OBJECT_ATTRIBUTE_OFFSET],rcx
+        self.emit(&[0x48, 0x89, 0x48, OBJECT_ATTRIBUTE_OFFSET as u8]);
+    }
 
-            if target_type == &*TYPE_INT {
-                // mov eax,[rip+{}]
-                self.emit(&[0x8B, 0x05]);
-                self.emit_link(GLOBAL_SECTION, offset);
-            } else if target_type == &*TYPE_BOOL {
-                // mov al,[rip+{}]
-                self.emit(&[0x8A, 0x05]);
-                self.emit_link(GLOBAL_SECTION, offset);
+    pub fn emit_load_var(&mut self, identifier: &Variable, target_type: &ValueType) {
+        if let Some(params) = &self.inline_params {
+            // Inlined function body only sees its parameters and global variables
+            if let Some(&offset) = params.get(&identifier.name) {
+                // mov rax,[rbp+{}]
+                self.emit_frame_access(&[0x48, 0x8B, 0x85], offset);
             } else {
-                // mov rax,[rip+{}]
-                self.emit(&[0x48, 0x8B, 0x05]);
-                self.emit_link(GLOBAL_SECTION, offset);
+                match self.storage_env().get_global(&identifier.name) {
+                    Some(EnvSlot::Var(v, _)) => self.emit_load_global(v.offset, target_type),
+                    Some(EnvSlot::Func(f)) => {
+                        let link_name = f.link_name.clone();
+                        self.emit_func_value(&link_name);
+                    }
+                    None => panic!(),
+                }
+            }
+            return;
+        }
+
+        let (offset, level) = match self.storage_env().get(&identifier.name) {
+            Some(EnvSlot::Var(v, _)) => (v.offset, v.level),
+            Some(EnvSlot::Func(f)) => {
+                let link_name = f.link_name.clone();
+                self.emit_func_value(&link_name);
+                return;
             }
+            None => panic!(),
+        };
+
+        if level == 0 {
+            // Global variable
+            self.emit_load_global(offset, target_type);
         } else if level == self.level + 1 {
             // Local variable in the same scope
 
             // mov rax,[rbp+{}]
-            self.emit(&[0x48, 0x8B, 0x85]);
-            self.emit(&offset.to_le_bytes());
+            self.emit_frame_access(&[0x48, 0x8B, 0x85], offset);
         } else {
             // Local variable in outer scope
 
@@ -1160,7 +1996,7 @@ impl<'a> Emitter<'a> {
             }
             // mov rax,[rax+{}]
             self.emit(&[0x48, 0x8B, 0x80]);
-            self.emit(&offset.to_le_bytes());
+            self.emit_value(offset);
         }
     }
 
@@ -1189,8 +2025,9 @@ impl<'a> Emitter<'a> {
                         self.emit(&[0x48, 0xF7, 0xD8]);
                     }
                     UnaryOp::Not => {
-                        // test rax,rax
-                        self.emit(&[0x48, 0x85, 0xC0]);
+                        // Only al holds the bool
+                        // test al,al
+                        self.emit(&[0x84, 0xC0]);
                         // sete al
                         self.emit(&[0x0F, 0x94, 0xC0]);
                     }
@@ -1199,6 +2036,24 @@ impl<'a> Emitter<'a> {
             ExprContent::BinaryExpr(expr) => {
                 self.emit_binary_expr(expr, expression.get_type());
             }
+            ExprContent::CallExpr(expr)
+                if expr.is_append(|name| self.storage_env().get(name).is_some()) =>
+            {
+                let element_type = if let ValueType::ListValueType(l) = expression.get_type() {
+                    &*l.element_type
+                } else {
+                    panic!()
+                };
+                self.emit_list_append(expr, element_type);
+            }
+            ExprContent::CallExpr(expr)
+                if expr.is_count(|name| self.storage_env().get(name).is_some()) =>
+            {
+                self.emit_list_count(expr);
+            }
+            ExprContent::CallExpr(expr) if self.is_inline_len(expr) => {
+                self.emit_inline_len(&expr.args[0]);
+            }
             ExprContent::CallExpr(expr) => {
                 self.emit_call_expr(
                     &expr.args,
@@ -1231,7 +2086,7 @@ impl<'a> Emitter<'a> {
         }
     }
 
-    pub fn emit_while_stmt(&mut self, stmt: &WhileStmt, lines: &mut Vec<LineMap>) {
+    pub fn emit_while_stmt(&mut self, stmt: &WhileStmt) {
         let start = self.jump_to();
         self.emit_expression(&stmt.condition);
         // test al,al
@@ -1240,16 +2095,33 @@ impl<'a> Emitter<'a> {
         self.emit(&[0x0f, 0x84]);
         let end = self.jump_from();
 
-        for stmt in &stmt.body {
-            self.emit_statement(stmt, lines);
+        for stmt in reachable(&stmt.body) {
+            self.emit_statement(stmt);
         }
 
+        self.emit_interrupt_check();
         // jmp
         self.emit(&[0xe9]);
         self.from_here(start);
         self.to_here(end);
     }
 
+    // Exit through $interrupted if Ctrl-C was pressed, before jumping back to the start of a loop
+    pub fn emit_interrupt_check(&mut self) {
+        if !self.interruptible {
+            return;
+        }
+        // cmp byte [rip+{}],0
+        self.emit(&[0x80, 0x3D]);
+        self.emit_link_imm(BUILTIN_INTERRUPT_FLAG, 0, &[0]);
+        // je
+        self.emit(&[0x0f, 0x84]);
+        let skip = self.jump_from();
+        // $interrupted never returns, so no ref map is needed
+        self.call_system(BUILTIN_INTERRUPTED, &[]);
+        self.to_here(skip);
+    }
+
     pub fn emit_assign_identifier(
         &mut self,
         name: &str,
@@ -1284,8 +2156,7 @@ impl<'a> Emitter<'a> {
                 // Local variable in the same scope
 
                 // lea rdi,[rbp+{}]
-                self.emit(&[0x48, 0x8D, 0xBD]);
-                self.emit(&offset.to_le_bytes());
+                self.emit_frame_access(&[0x48, 0x8D, 0xBD], offset);
             } else {
                 // Local variable in outer scope
 
@@ -1297,7 +2168,7 @@ impl<'a> Emitter<'a> {
                 }
                 // lea rdi,[rdi+{}]
                 self.emit(&[0x48, 0x8D, 0xBF]);
-                self.emit(&offset.to_le_bytes());
+                self.emit_value(offset);
             }
 
             // mov [rdi],rax
@@ -1329,15 +2200,7 @@ impl<'a> Emitter<'a> {
                     self.emit_expression(&expr.index);
                     // mov rsi,[rbp+{}]
                     self.emit_with_stack(&[0x48, 0x8B, 0xB5], &list);
-
-                    // cmp rax,[rsi+ARRAY_LEN_OFFSET]
-                    self.emit(&[0x48, 0x3B, 0x46, ARRAY_LEN_OFFSET as u8]);
-                    // jb
-                    self.emit(&[0x0F, 0x82]);
-                    let ok = self.jump_from();
-                    self.prepare_call(self.platform.stack_reserve());
-                    self.call(BUILTIN_OUT_OF_BOUND);
-                    self.to_here(ok);
+                    self.emit_index_bounds_check();
 
                     let dest = self.alloc_stack(TicketType::Plain);
                     if target_type == &*TYPE_INT {
@@ -1405,7 +2268,7 @@ impl<'a> Emitter<'a> {
                         // mov [rsi+{}],rax
                         self.emit(&[0x48, 0x89, 0x86]);
                     }
-                    self.emit(&slot.offset.to_le_bytes());
+                    self.emit_value(slot.offset);
 
                     self.free_stack(object);
                 }
@@ -1416,10 +2279,66 @@ impl<'a> Emitter<'a> {
         self.free_stack(value);
     }
 
+    // Lower `for i in range(a, b)` to a counting loop without allocating a list
+    pub fn emit_for_range_stmt(&mut self, stmt: &ForStmt, args: &[Expr]) {
+        //// Compute the bounds
+        let counter = self.alloc_stack(TicketType::Plain);
+        if args.len() == 2 {
+            self.emit_expression(&args[0]);
+        } else {
+            // xor eax,eax
+            self.emit(&[0x31, 0xC0]);
+        }
+        // mov [rbp+{}],eax
+        self.emit_with_stack(&[0x89, 0x85], &counter);
+
+        let bound = self.alloc_stack(TicketType::Plain);
+        self.emit_expression(args.last().unwrap());
+        // mov [rbp+{}],eax
+        self.emit_with_stack(&[0x89, 0x85], &bound);
+
+        let start = self.jump_to();
+        //// Check the counter against the bound
+        // mov eax,[rbp+{}]
+        self.emit_with_stack(&[0x8B, 0x85], &counter);
+        // cmp eax,[rbp+{}]
+        self.emit_with_stack(&[0x3B, 0x85], &bound);
+        // jge
+        self.emit(&[0x0f, 0x8D]);
+        let end = self.jump_from();
+
+        //// Assign the counter
+        let target_type = stmt.identifier.get_type();
+        self.emit_assign_identifier(&stmt.identifier.name, &TYPE_INT, target_type);
+
+        //// Execute the loop body
+        for stmt in reachable(&stmt.body) {
+            self.emit_statement(stmt);
+        }
+
+        //// Increase the counter and loop back
+        // inc dword [rbp+{}]
+        self.emit_with_stack(&[0xFF, 0x85], &counter);
+        self.emit_interrupt_check();
+        // jmp
+        self.emit(&[0xe9]);
+        self.from_here(start);
+        self.to_here(end);
+
+        self.free_stack(bound);
+        self.free_stack(counter);
+    }
+
     #[allow(clippy::useless_let_if_seq)] // Tell me which is more readable
-    pub fn emit_for_stmt(&mut self, stmt: &ForStmt, lines: &mut Vec<LineMap>) {
-        //// Compute the iterable
-        self.emit_expression(&stmt.iterable);
+    pub fn emit_for_stmt(&mut self, stmt: &ForStmt) {
+        let storage_env = self.storage_env();
+        if let Some(args) = stmt.range_args(|name| storage_env.get(name).is_some()) {
+            return self.emit_for_range_stmt(stmt, args);
+        }
+
+        //// Compute the iterable, which is the argument of `enumerate(...)` with an index
+        let iterable = stmt.enumerate_arg().unwrap_or(&stmt.iterable);
+        self.emit_expression(iterable);
         self.emit_check_none();
         let list = self.alloc_stack(TicketType::Reference);
         // mov [rbp+{}],rax
@@ -1441,8 +2360,17 @@ impl<'a> Emitter<'a> {
         // mov [rbp+{}],rax
         self.emit_with_stack(&[0x48, 0x89, 0x85], &counter);
 
+        //// Assign the index
+        if let Some(index) = &stmt.index {
+            self.emit_assign_identifier(&index.name, &TYPE_INT, index.get_type());
+            // mov rax,[rbp+{}]
+            self.emit_with_stack(&[0x48, 0x8B, 0x85], &counter);
+            // mov rsi,[rbp+{}]
+            self.emit_with_stack(&[0x48, 0x8B, 0xB5], &list);
+        }
+
         //// Compute the element
-        let iterable_type = stmt.iterable.get_type();
+        let iterable_type = iterable.get_type();
         #[allow(clippy::needless_late_init)]
         let source_type;
         if iterable_type == &*TYPE_STR {
@@ -1485,11 +2413,12 @@ impl<'a> Emitter<'a> {
         self.emit_assign_identifier(&stmt.identifier.name, source_type, target_type);
 
         //// Execute the loop body
-        for stmt in &stmt.body {
-            self.emit_statement(stmt, lines);
+        for stmt in reachable(&stmt.body) {
+            self.emit_statement(stmt);
         }
 
         //// Increase the index and loop back
+        self.emit_interrupt_check();
         // mov rax,[rbp+{}]
         self.emit_with_stack(&[0x48, 0x8B, 0x85], &counter);
         // inc rax
@@ -1503,11 +2432,15 @@ impl<'a> Emitter<'a> {
         self.free_stack(list);
     }
 
-    pub fn emit_statement(&mut self, statement: &Stmt, lines: &mut Vec<LineMap>) {
-        lines.push(LineMap {
-            code_pos: self.pos(),
-            line_number: statement.base().location.start.row,
-        });
+    pub fn emit_statement(&mut self, statement: &Stmt) {
+        self.mark_line(statement.base().location.start.row);
+        if self.trace_exec {
+            // $trace_line never allocates, so no GC can happen and no ref map is needed
+            self.call_system(
+                BUILTIN_TRACE_LINE,
+                &[Arg::Imm(statement.base().location.start.row as i32)],
+            );
+        }
         match statement {
             Stmt::ExprStmt(e) => {
                 self.emit_expression(&e.expr);
@@ -1516,13 +2449,13 @@ impl<'a> Emitter<'a> {
                 self.emit_assign(stmt);
             }
             Stmt::IfStmt(stmt) => {
-                self.emit_if_stmt(stmt, lines);
+                self.emit_if_stmt(stmt);
             }
             Stmt::WhileStmt(stmt) => {
-                self.emit_while_stmt(stmt, lines);
+                self.emit_while_stmt(stmt);
             }
             Stmt::ForStmt(stmt) => {
-                self.emit_for_stmt(stmt, lines);
+                self.emit_for_stmt(stmt);
             }
             Stmt::ReturnStmt(stmt) => {
                 if let Some(value) = &stmt.value {
@@ -1572,6 +2505,29 @@ impl<'a> Emitter<'a> {
             } else {
                 panic!()
             };
+        self.emit_global_init(offset, decl);
+    }
+
+    // Initialize a global variable or a class constant at the offset in global section
+    pub fn emit_global_init(&mut self, offset: i32, decl: &VarDef) {
+        let target_type = ValueType::from_annotation(&decl.var.type_);
+
+        // Store plain literals as immediate operands
+        match &decl.value.content {
+            LiteralContent::IntegerLiteral(i) if target_type == *TYPE_INT => {
+                // mov dword [rip+{}],{}
+                self.emit(&[0xC7, 0x05]);
+                self.emit_link_imm(GLOBAL_SECTION, offset, &ENDIAN.bytes(i.value));
+                return;
+            }
+            LiteralContent::BooleanLiteral(b) if target_type == *TYPE_BOOL => {
+                // mov byte [rip+{}],{}
+                self.emit(&[0xC6, 0x05]);
+                self.emit_link_imm(GLOBAL_SECTION, offset, &[b.value as u8]);
+                return;
+            }
+            _ => (),
+        }
 
         match &decl.value.content {
             LiteralContent::NoneLiteral(_) => {
@@ -1588,7 +2544,6 @@ impl<'a> Emitter<'a> {
             }
         }
 
-        let target_type = ValueType::from_annotation(&decl.var.type_);
         self.emit_coerce(decl.value.get_type(), &target_type);
 
         if target_type == *TYPE_INT {
@@ -1612,6 +2567,7 @@ fn gen_function(
     classes: &BTreeMap<String, ClassSlot>,
     level: u32,
     parent: Option<&str>,
+    options: CompileOptions,
     platform: Platform,
 ) -> Vec<Chunk> {
     let link_name = if let Some(parent) = parent {
@@ -1679,6 +2635,7 @@ fn gen_function(
                     LocalSlot::Func(FuncSlot {
                         link_name: link_name.clone() + "." + name,
                         level: level + 1,
+                        inline: None,
                     }),
                 );
             }
@@ -1698,6 +2655,19 @@ fn gen_function(
         level,
         platform,
     );
+    code.trace_exec = options.trace_exec;
+    code.interruptible = options.interruptible;
+    code.opt_level = options.opt_level;
+    code.runtime_checks = options.runtime_checks;
+    code.class_eq = options.class_eq;
+    if options.opt_level >= 2 && level == 0 && parent.is_none() && frameless_leaf(function) {
+        code.omit_frame_pointer();
+    }
+    if options.opt_level >= 2 && level == 0 {
+        if let (Some(class_name), Some(self_param)) = (parent, function.params.first()) {
+            code.self_receiver = Some((class_name.to_owned(), self_param.identifier.name.clone()));
+        }
+    }
 
     if level != 0 {
         // Save static link
@@ -1714,34 +2684,39 @@ fn gen_function(
         }
     }
 
-    let mut lines = vec![LineMap {
+    code.lines.push(LineMap {
         code_pos: 0,
         line_number: function.base().location.start.row,
-    }];
+    });
 
-    // Generate codes for all statements
-    for statement in &function.statements {
-        code.emit_statement(statement, &mut lines);
+    // Generate codes for all statements, leaving out unreachable ones
+    for statement in reachable(&function.statements) {
+        code.emit_statement(statement);
     }
 
-    // Implicit `return None`
-    code.emit_none_literal();
-    code.end_proc();
+    // Implicit `return None`, unless every path has returned already.
+    // It is on the last line of the function, so stepping out doesn't stop at a stale line
+    if !always_return(&function.statements) {
+        code.mark_line(function.base().location.end.row);
+        code.emit_none_literal();
+        code.end_proc();
+    }
 
     // Package code into a chunk
     let mut chunks = vec![code.finalize(ProcedureDebug {
-        decl_line: function.statements[0].base().location.start.row,
+        decl_line: function.base().location.start.row,
         artificial: false,
         parent: if level == 0 {
             None
         } else {
             parent.map(str::to_owned)
         },
-        lines,
+        lines: vec![],
         return_type: TypeDebug::from_annotation(&function.return_type),
         params: params_debug,
         locals: locals_debug,
         frame_size: 0,
+        frameless: false,
     })];
 
     // Recursively generate codes for nested functions
@@ -1755,6 +2730,7 @@ fn gen_function(
                 classes,
                 level + 1,
                 Some(&link_name),
+                options,
                 platform,
             ));
         }
@@ -1766,34 +2742,25 @@ fn gen_function(
 // Generate machine code for constructor
 fn gen_ctor(class_name: &str, class_slot: &ClassSlot, platform: Platform) -> Chunk {
     let mut code = Emitter::new(class_name, None, None, None, vec![], 0, platform);
+    // Lines of the class definition and of the attribute declarations, so that profilers don't
+    // see the time spent here as an anonymous procedure
+    if class_slot.line != 0 {
+        code.lines.push(LineMap {
+            code_pos: 0,
+            line_number: class_slot.line,
+        });
+    }
 
     // Allocate object
-    code.prepare_call(platform.stack_reserve());
-    match platform {
-        Platform::Windows => {
-            // mov r8,rbp
-            code.emit(&[0x49, 0x89, 0xE8]);
-            // mov r9,rsp
-            code.emit(&[0x49, 0x89, 0xE1]);
-            // xor rdx,rdx
-            code.emit(&[0x48, 0x31, 0xD2]);
-            // lea rcx,[rip+{}]
-            code.emit(&[0x48, 0x8D, 0x0D]);
-        }
-        Platform::Linux | Platform::Macos => {
-            // mov rdx,rbp
-            code.emit(&[0x48, 0x89, 0xEA]);
-            // mov rcx,rsp
-            code.emit(&[0x48, 0x89, 0xE1]);
-            // xor rsi,rsi
-            code.emit(&[0x48, 0x31, 0xF6]);
-            // lea rdi,[rip+{}]
-            code.emit(&[0x48, 0x8D, 0x3D]);
-        }
-    }
-    code.emit_link(class_name.to_owned() + ".$proto", 0);
-
-    code.call(BUILTIN_ALLOC_OBJ);
+    code.call_system(
+        BUILTIN_ALLOC_OBJ,
+        &[
+            Arg::Symbol(&(class_name.to_owned() + ".$proto")),
+            Arg::Imm(0),
+            Arg::Reg(Reg::Rbp),
+            Arg::Reg(Reg::Rsp),
+        ],
+    );
     code.emit_ref_map();
     let object = code.alloc_stack(TicketType::Reference);
     // mov [rbp+{}],rax
@@ -1803,6 +2770,9 @@ fn gen_ctor(class_name: &str, class_slot: &ClassSlot, platform: Platform) -> Chu
     let mut attributes: Vec<_> = class_slot.attributes.values().collect();
     attributes.sort_by_key(|a| a.offset);
     for attribute in attributes {
+        if class_slot.line != 0 {
+            code.mark_line(attribute.line);
+        }
         match &attribute.init {
             LiteralContent::NoneLiteral(_) => {
                 code.emit_none_literal();
@@ -1832,10 +2802,13 @@ fn gen_ctor(class_name: &str, class_slot: &ClassSlot, platform: Platform) -> Chu
             // mov [rdi+{}],rax
             code.emit(&[0x48, 0x89, 0x87]);
         }
-        code.emit(&attribute.offset.to_le_bytes());
+        code.emit_value(attribute.offset);
     }
 
     // Call __init__()
+    if class_slot.line != 0 {
+        code.mark_line(class_slot.line);
+    }
 
     // mov rax,[rbp+{}]
     code.emit_with_stack(&[0x48, 0x8B, 0x85], &object);
@@ -1850,7 +2823,7 @@ fn gen_ctor(class_name: &str, class_slot: &ClassSlot, platform: Platform) -> Chu
     code.free_stack(object);
     code.end_proc();
     code.finalize(ProcedureDebug {
-        decl_line: 0,
+        decl_line: class_slot.line,
         artificial: true,
         parent: None,
         lines: vec![],
@@ -1858,6 +2831,105 @@ fn gen_ctor(class_name: &str, class_slot: &ClassSlot, platform: Platform) -> Chu
         params: vec![],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
+    })
+}
+
+// What a system ABI entry point calls
+enum EntryTarget<'a> {
+    Procedure(&'a str),
+    Method(u32), // Offset into the prototype of the first argument
+}
+
+// Generate a procedure that C code can call with the system ABI, which passes its parameters to
+// a ChocoPy procedure or method and returns the result. The entry point is the bottom frame for GC
+// while the ChocoPy code runs, so it works after `$chocopy_main` has returned.
+fn gen_entry(
+    name: &str,
+    target: EntryTarget,
+    params: &[TypeDebug],
+    return_type: TypeDebug,
+    platform: Platform,
+) -> Chunk {
+    let mut code = Emitter::new_simple(name, platform);
+
+    // Save rdi/rsi according to Windows ABI. Shadow space is used here
+    if platform == Platform::Windows {
+        // mov [rbp+16],rdi
+        code.emit(&[0x48, 0x89, 0x7D, 0x10]);
+        // mov [rbp+24],rsi
+        code.emit(&[0x48, 0x89, 0x75, 0x18]);
+    }
+
+    // Replace the bottom frame, and restore it on return
+    let bottom_frame = code.alloc_stack(TicketType::Plain);
+    // mov rax,[rip+{}]
+    code.emit(&[0x48, 0x8B, 0x05]);
+    code.emit_link(INIT_PARAM, BOTTOM_FRAME_OFFSET as i32);
+    // mov [rbp+{}],rax
+    code.emit_with_stack(&[0x48, 0x89, 0x85], &bottom_frame);
+    // mov [rip+{}],rbp
+    code.emit(&[0x48, 0x89, 0x2D]);
+    code.emit_link(INIT_PARAM, BOTTOM_FRAME_OFFSET as i32);
+
+    code.prepare_call(params.len());
+    for instruction in abi::param_moves(platform, params.len()) {
+        code.emit(&instruction);
+    }
+    // C code only sets the low 32 bits of int and the low 8 bits of bool parameters
+    for (i, param) in params.iter().enumerate() {
+        let offset = i as u32 * 8;
+        if *param == TypeDebug::class_type("int") {
+            // mov eax,[rsp+{}]
+            code.emit(&[0x8B, 0x84, 0x24]);
+        } else if *param == TypeDebug::class_type("bool") {
+            // movzx eax,BYTE PTR [rsp+{}]
+            code.emit(&[0x0F, 0xB6, 0x84, 0x24]);
+        } else {
+            continue;
+        }
+        code.emit_value(offset);
+        // mov [rsp+{}],rax
+        code.emit(&[0x48, 0x89, 0x84, 0x24]);
+        code.emit_value(offset);
+    }
+    match target {
+        EntryTarget::Procedure(link_name) => code.call(link_name),
+        EntryTarget::Method(offset) => {
+            // mov rax,[rsp]
+            code.emit(&[0x48, 0x8B, 0x04, 0x24]);
+            code.emit_check_none();
+            code.call_virtual(offset);
+        }
+    }
+    code.emit_ref_map();
+
+    // mov rcx,[rbp+{}]
+    code.emit_with_stack(&[0x48, 0x8B, 0x8D], &bottom_frame);
+    code.free_stack(bottom_frame);
+    // mov [rip+{}],rcx
+    code.emit(&[0x48, 0x89, 0x0D]);
+    code.emit_link(INIT_PARAM, BOTTOM_FRAME_OFFSET as i32);
+
+    // Restore rdi/rsi for Windows
+    if platform == Platform::Windows {
+        // mov rdi,[rbp+16]
+        code.emit(&[0x48, 0x8B, 0x7D, 0x10]);
+        // mov rsi,[rbp+24]
+        code.emit(&[0x48, 0x8B, 0x75, 0x18]);
+    }
+
+    code.end_proc();
+    code.finalize(ProcedureDebug {
+        decl_line: 0,
+        artificial: true,
+        parent: None,
+        lines: vec![],
+        return_type,
+        params: vec![],
+        locals: vec![],
+        frame_size: 0,
+        frameless: false,
     })
 }
 
@@ -1875,6 +2947,7 @@ fn gen_int(platform: Platform) -> Chunk {
         params: vec![],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
     })
 }
 
@@ -1892,6 +2965,7 @@ fn gen_bool(platform: Platform) -> Chunk {
         params: vec![],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
     })
 }
 
@@ -1909,6 +2983,7 @@ fn gen_str(platform: Platform) -> Chunk {
         params: vec![],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
     })
 }
 
@@ -1931,18 +3006,14 @@ fn gen_object_init(platform: Platform) -> Chunk {
         }],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
     })
 }
 
 // Generate machine code for `len`
 fn gen_len(platform: Platform) -> Chunk {
     let mut code = Emitter::new_simple("len", platform);
-    match platform {
-        Platform::Windows => code.emit(&[0x48, 0x8B, 0x4D, 0x10]), //  mov rcx,[rbp+16]
-        Platform::Linux | Platform::Macos => code.emit(&[0x48, 0x8B, 0x7D, 0x10]), // mov rdi,[rbp+16]
-    }
-    code.prepare_call(platform.stack_reserve());
-    code.call(BUILTIN_LEN);
+    code.call_system(BUILTIN_LEN, &[Arg::Frame(16)]);
     code.end_proc();
     code.finalize(ProcedureDebug {
         decl_line: 0,
@@ -1958,28 +3029,104 @@ fn gen_len(platform: Platform) -> Chunk {
         }],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
+    })
+}
+
+// Generate machine code for `obj_id`
+fn gen_obj_id(platform: Platform) -> Chunk {
+    let mut code = Emitter::new_simple("obj_id", platform);
+    code.call_system(BUILTIN_OBJ_ID, &[Arg::Frame(16)]);
+    code.end_proc();
+    code.finalize(ProcedureDebug {
+        decl_line: 0,
+        artificial: true,
+        parent: None,
+        lines: vec![],
+        return_type: TypeDebug::class_type("int"),
+        params: vec![VarDebug {
+            offset: 16,
+            line: 0,
+            name: "object".to_owned(),
+            var_type: TypeDebug::class_type("object"),
+        }],
+        locals: vec![],
+        frame_size: 0,
+        frameless: false,
+    })
+}
+
+// Generate machine code for `collect`.
+// The call is a safepoint, so the reference map must be emitted like for allocation
+fn gen_collect(platform: Platform) -> Chunk {
+    let mut code = Emitter::new_simple("collect", platform);
+    code.call_system(BUILTIN_COLLECT, &[Arg::Reg(Reg::Rbp), Arg::Reg(Reg::Rsp)]);
+    code.emit_ref_map();
+    code.end_proc();
+    code.finalize(ProcedureDebug {
+        decl_line: 0,
+        artificial: true,
+        parent: None,
+        return_type: TypeDebug::class_type("<None>"),
+        params: vec![],
+        lines: vec![],
+        locals: vec![],
+        frame_size: 0,
+        frameless: false,
+    })
+}
+
+// Generate machine code for `seed`
+fn gen_seed(platform: Platform) -> Chunk {
+    let mut code = Emitter::new_simple("seed", platform);
+    code.call_system(BUILTIN_SEED, &[Arg::Frame(16)]);
+    code.end_proc();
+    code.finalize(ProcedureDebug {
+        decl_line: 0,
+        artificial: true,
+        parent: None,
+        lines: vec![],
+        return_type: TypeDebug::class_type("<None>"),
+        params: vec![VarDebug {
+            offset: 16,
+            line: 0,
+            name: "x".to_owned(),
+            var_type: TypeDebug::class_type("int"),
+        }],
+        locals: vec![],
+        frame_size: 0,
+        frameless: false,
+    })
+}
+
+// Generate machine code for `randint`
+fn gen_randint(platform: Platform) -> Chunk {
+    let mut code = Emitter::new_simple("randint", platform);
+    code.call_system(BUILTIN_RANDINT, &[Arg::Frame(16), Arg::Frame(24)]);
+    code.end_proc();
+    let param = |offset, name: &str| VarDebug {
+        offset,
+        line: 0,
+        name: name.to_owned(),
+        var_type: TypeDebug::class_type("int"),
+    };
+    code.finalize(ProcedureDebug {
+        decl_line: 0,
+        artificial: true,
+        parent: None,
+        lines: vec![],
+        return_type: TypeDebug::class_type("int"),
+        params: vec![param(16, "lo"), param(24, "hi")],
+        locals: vec![],
+        frame_size: 0,
+        frameless: false,
     })
 }
 
 // Generate machine code for `input`
 fn gen_input(platform: Platform) -> Chunk {
     let mut code = Emitter::new_simple("input", platform);
-    match platform {
-        Platform::Windows => {
-            // mov rcx,rbp
-            code.emit(&[0x48, 0x89, 0xE9]);
-            // mov rdx,rsp
-            code.emit(&[0x48, 0x89, 0xE2]);
-        }
-        Platform::Linux | Platform::Macos => {
-            // mov rdi,rbp
-            code.emit(&[0x48, 0x89, 0xEF]);
-            // mov rsi,rsp
-            code.emit(&[0x48, 0x89, 0xE6]);
-        }
-    }
-    code.prepare_call(platform.stack_reserve());
-    code.call(BUILTIN_INPUT);
+    code.call_system(BUILTIN_INPUT, &[Arg::Reg(Reg::Rbp), Arg::Reg(Reg::Rsp)]);
     code.emit_ref_map();
     code.end_proc();
     code.finalize(ProcedureDebug {
@@ -1991,18 +3138,41 @@ fn gen_input(platform: Platform) -> Chunk {
         lines: vec![],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
     })
 }
 
-// Generate machine code for `print`
+// Generate machine code for `print`.
+// An object whose prototype has a `__str__` method is converted by calling it first,
+// so that the standard library only prints int, bool and str objects.
 fn gen_print(platform: Platform) -> Chunk {
     let mut code = Emitter::new_simple("print", platform);
-    match platform {
-        Platform::Windows => code.emit(&[0x48, 0x8B, 0x4D, 0x10]), //  mov rcx,[rbp+16]
-        Platform::Linux | Platform::Macos => code.emit(&[0x48, 0x8B, 0x7D, 0x10]), // mov rdi,[rbp+16]
-    }
-    code.prepare_call(platform.stack_reserve());
-    code.call(BUILTIN_PRINT);
+    // mov rax,[rbp+16]
+    code.emit(&[0x48, 0x8B, 0x45, 0x10]);
+    // test rax,rax
+    code.emit(&[0x48, 0x85, 0xC0]);
+    // je
+    code.emit(&[0x0F, 0x84]);
+    let none = code.jump_from();
+    // mov rcx,[rax], assumed OBJECT_PROTOTYPE_OFFSET = 0
+    code.emit(&[0x48, 0x8B, 0x08]);
+    // mov rcx,[rcx+{}]
+    code.emit(&[0x48, 0x8B, 0x89]);
+    code.emit_value(PROTOTYPE_STR_OFFSET);
+    // test rcx,rcx
+    code.emit(&[0x48, 0x85, 0xC9]);
+    // je
+    code.emit(&[0x0F, 0x84]);
+    let no_str = code.jump_from();
+    code.prepare_call(1);
+    // mov [rsp],rax
+    code.emit(&[0x48, 0x89, 0x04, 0x24]);
+    // call rcx
+    code.emit(&[0xFF, 0xD1]);
+    code.emit_ref_map();
+    code.to_here(none);
+    code.to_here(no_str);
+    code.call_system(BUILTIN_PRINT, &[Arg::Reg(Reg::Rax)]);
     code.end_proc();
     code.finalize(ProcedureDebug {
         decl_line: 0,
@@ -2018,6 +3188,7 @@ fn gen_print(platform: Platform) -> Chunk {
         }],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
     })
 }
 
@@ -2026,6 +3197,7 @@ fn gen_main(
     ast: &Program,
     storage_env: &mut StorageEnv,
     classes: &BTreeMap<String, ClassSlot>,
+    options: CompileOptions,
     platform: Platform,
 ) -> Chunk {
     let mut main_code = Emitter::new(
@@ -2037,6 +3209,11 @@ fn gen_main(
         0,
         platform,
     );
+    main_code.trace_exec = options.trace_exec;
+    main_code.interruptible = options.interruptible;
+    main_code.opt_level = options.opt_level;
+    main_code.runtime_checks = options.runtime_checks;
+    main_code.class_eq = options.class_eq;
 
     // Save rdi/rsi according to Windows ABI. Shadow space is used here
     if platform == Platform::Windows {
@@ -2052,32 +3229,34 @@ fn gen_main(
     main_code.emit(&[0x48, 0x89, 0x2D]);
     main_code.emit_link(INIT_PARAM, BOTTOM_FRAME_OFFSET as i32);
 
-    main_code.prepare_call(platform.stack_reserve());
-    match platform {
-        Platform::Windows => {
-            // lea rcx,[rip+{}]
-            main_code.emit(&[0x48, 0x8D, 0x0D]);
-        }
-        Platform::Linux | Platform::Macos => {
-            // lea rdi,[rip+{}]
-            main_code.emit(&[0x48, 0x8D, 0x3D]);
-        }
+    main_code.call_system(BUILTIN_INIT, &[Arg::Symbol(INIT_PARAM)]);
+    if options.interruptible {
+        main_code.call_system(BUILTIN_CATCH_INTERRUPT, &[]);
     }
-    main_code.emit_link(INIT_PARAM, 0);
-    main_code.call(BUILTIN_INIT);
 
-    // Initialize global variables
+    // Initialize global variables and class constants
     for declaration in &ast.declarations {
-        if let Declaration::VarDef(v) = declaration {
-            main_code.emit_global_var_init(v);
+        match declaration {
+            Declaration::VarDef(v) => main_code.emit_global_var_init(v),
+            Declaration::ClassDef(c) => {
+                let constants = &classes[&c.name.name].constants;
+                for declaration in &c.declarations {
+                    match declaration {
+                        Declaration::VarDef(v) if v.constant => {
+                            let offset = constants[&v.var.identifier.name].offset;
+                            main_code.emit_global_init(offset, v);
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
         }
     }
 
-    let mut lines = vec![];
-
     // Generate machine code for main procedure statements
     for statement in &ast.statements {
-        main_code.emit_statement(statement, &mut lines);
+        main_code.emit_statement(statement);
     }
 
     // Restore rdi/rsi for Windows
@@ -2093,27 +3272,24 @@ fn gen_main(
     main_code.finalize(ProcedureDebug {
         decl_line: ast
             .statements
-            .get(0)
+            .first()
             .map_or(1, |s| s.base().location.start.row),
         artificial: false,
         parent: None,
-        lines,
+        lines: vec![],
         return_type: TypeDebug::class_type("<None>"),
         params: vec![],
         locals: vec![],
         frame_size: 0,
+        frameless: false,
     })
 }
 
 // Generate configuration data for standard library initialization
 fn gen_init_param(global_size: u64, global_ref_indexs: &[i32]) -> Chunk {
     let mut code = vec![0; INIT_PARAM_SIZE as usize];
-    code[GLOBAL_SIZE_OFFSET as usize..][..8].copy_from_slice(&global_size.to_le_bytes());
-    let mut ref_map = vec![0; (global_size as usize / 8 + 7) / 8];
-    for index in global_ref_indexs {
-        let index = *index as usize;
-        ref_map[index / 8] |= 1 << (index % 8);
-    }
+    ENDIAN.write(&mut code[GLOBAL_SIZE_OFFSET as usize..], global_size);
+    let ref_map = global_ref_map(global_size, global_ref_indexs);
     Chunk {
         name: INIT_PARAM.to_owned(),
         code,
@@ -2121,42 +3297,108 @@ fn gen_init_param(global_size: u64, global_ref_indexs: &[i32]) -> Chunk {
             ChunkLink {
                 pos: GLOBAL_SECTION_OFFSET as usize,
                 to: ChunkLinkTarget::Symbol(GLOBAL_SECTION.to_owned(), 0),
+                imm_size: 0,
             },
             ChunkLink {
                 pos: GLOBAL_MAP_OFFSET as usize,
                 to: ChunkLinkTarget::Data(ref_map),
+                imm_size: 0,
             },
             ChunkLink {
                 pos: STR_PROTOTYPE_OFFSET as usize,
                 to: ChunkLinkTarget::Symbol(STR_PROTOTYPE.to_owned(), 0),
+                imm_size: 0,
             },
         ],
         extra: ChunkExtra::Data { writable: true },
     }
 }
 
+// One bit for each 8-byte slot of the global section, set if the slot holds a reference.
+// The runtime only scans the first `global_size / 8` slots. A reference is 8-byte aligned and
+// `global_size` is past its end, so its slot is always scanned, even if the size isn't a multiple
+// of 8, and scalar globals never share a slot with it.
+fn global_ref_map(global_size: u64, global_ref_indexs: &[i32]) -> Vec<u8> {
+    let slots = global_size as usize / 8;
+    let mut ref_map = vec![0; slots.div_ceil(8)];
+    for index in global_ref_indexs {
+        let index = *index as usize;
+        assert!(index < slots, "Reference global out of the global section");
+        ref_map[index / 8] |= 1 << (index % 8);
+    }
+    ref_map
+}
+
 // Add class info into environment and debug info
+// Allocate a variable in global section. Returns the offset
+fn alloc_global(
+    global_offset: &mut i32,
+    global_ref_indexs: &mut Vec<i32>,
+    target_type: &ValueType,
+) -> i32 {
+    let size = if *target_type == *TYPE_INT {
+        4
+    } else if *target_type == *TYPE_BOOL {
+        1
+    } else {
+        8
+    };
+    *global_offset += (size - *global_offset % size) % size;
+    let offset = *global_offset;
+    if !target_type.is_plain() {
+        global_ref_indexs.push(offset / 8);
+    }
+    *global_offset += size;
+    offset
+}
+
 fn add_class(
     globals: &mut HashMap<String, LocalSlot<FuncSlot, VarSlot>>,
     classes: &mut BTreeMap<String, ClassSlot>,
     classes_debug: &mut HashMap<String, ClassDebug>,
+    global_offset: &mut i32,
+    global_ref_indexs: &mut Vec<i32>,
     c: &ClassDef,
 ) {
     let class_name = &c.name.name;
     let super_name = &c.super_class.name;
     let mut class_slot = classes.get(super_name).unwrap().clone();
+    class_slot.line = c.base().location.start.row;
     let mut class_debug = classes_debug.get(super_name).unwrap().clone();
+    // Inherited constants are described in the debug info of the defining class only
+    class_debug.constants.clear();
     // Add constructor function as global function
     globals.insert(
         class_name.clone(),
         LocalSlot::Func(FuncSlot {
             link_name: class_name.clone(),
             level: 0,
+            inline: None,
         }),
     );
 
     for declaration in &c.declarations {
         match declaration {
+            Declaration::VarDef(v) if v.constant => {
+                // Allocate class constant in global section
+                let target_type = ValueType::from_annotation(&v.var.type_);
+                let offset = alloc_global(global_offset, global_ref_indexs, &target_type);
+                let name = &v.var.identifier.name;
+                class_slot.constants.insert(
+                    name.clone(),
+                    ConstantSlot {
+                        offset,
+                        target_type,
+                    },
+                );
+
+                class_debug.constants.push(VarDebug {
+                    offset,
+                    line: v.base().location.start.row,
+                    name: name.clone(),
+                    var_type: TypeDebug::from_annotation(&v.var.type_),
+                });
+            }
             Declaration::VarDef(v) => {
                 // Allocate slot for attribute
                 let source_type = v.value.get_type().clone();
@@ -2178,6 +3420,7 @@ fn add_class(
                         source_type,
                         target_type,
                         init: v.value.content.clone(),
+                        line: v.base().location.start.row,
                     },
                 );
                 class_slot.object_size += size;
@@ -2193,8 +3436,18 @@ fn add_class(
                 let method_name = &f.name.name;
                 let link_name = class_name.clone() + "." + method_name;
                 if let Some(method) = class_slot.methods.get_mut(method_name) {
+                    // Calls to the overridden implementation now depend on the receiver
+                    for class in classes.values_mut() {
+                        if let Some(inherited) = class.methods.get_mut(method_name) {
+                            if inherited.link_name == method.link_name {
+                                inherited.direct = false;
+                            }
+                        }
+                    }
+
                     // Override method with new link name
                     method.link_name = link_name;
+                    method.direct = true;
 
                     let self_type = TypeDebug::from_annotation(&f.params[0].type_);
                     class_debug
@@ -2204,12 +3457,23 @@ fn add_class(
                         .1
                         .params[0] = self_type;
                 } else {
-                    // Allocate prototype slot for new method
-                    let offset = class_slot.prototype_size;
-                    class_slot
-                        .methods
-                        .insert(method_name.clone(), MethodSlot { offset, link_name });
-                    class_slot.prototype_size += FUNCTION_POINTER_SIZE;
+                    // Allocate prototype slot for new method. `__str__` has a reserved slot
+                    // so that `print` can find it in any prototype.
+                    let offset = if method_name == "__str__" {
+                        PROTOTYPE_STR_OFFSET
+                    } else {
+                        let offset = class_slot.prototype_size;
+                        class_slot.prototype_size += FUNCTION_POINTER_SIZE;
+                        offset
+                    };
+                    class_slot.methods.insert(
+                        method_name.clone(),
+                        MethodSlot {
+                            offset,
+                            link_name,
+                            direct: true,
+                        },
+                    );
 
                     let params = f
                         .params
@@ -2234,6 +3498,7 @@ fn add_class(
         }
     }
     class_debug.size = class_slot.object_size;
+    class_debug.prototype_size = class_slot.prototype_size;
     classes.insert(class_name.clone(), class_slot);
     classes_debug.insert(class_name.clone(), class_debug);
 }
@@ -2241,12 +3506,13 @@ fn add_class(
 // Generate prototype for primitive types
 fn gen_special_proto(name: &str, size: i32, tag: TypeTag) -> Chunk {
     let mut code = vec![0; OBJECT_PROTOTYPE_SIZE as usize];
-    code[PROTOTYPE_SIZE_OFFSET as usize..][..4].copy_from_slice(&size.to_le_bytes());
-    code[PROTOTYPE_TAG_OFFSET as usize..][..4].copy_from_slice(&(tag as i32).to_le_bytes());
-    code[PROTOTYPE_MAP_OFFSET as usize..][..8].copy_from_slice(&(0u64).to_le_bytes());
+    ENDIAN.write(&mut code[PROTOTYPE_SIZE_OFFSET as usize..], size);
+    ENDIAN.write(&mut code[PROTOTYPE_TAG_OFFSET as usize..], tag as i32);
+    ENDIAN.write(&mut code[PROTOTYPE_MAP_OFFSET as usize..], 0u64);
     let links = vec![ChunkLink {
         pos: PROTOTYPE_INIT_OFFSET as usize,
         to: ChunkLinkTarget::Symbol("object.__init__".to_owned(), 0),
+        imm_size: 0,
     }];
     Chunk {
         name: name.to_owned(),
@@ -2256,8 +3522,125 @@ fn gen_special_proto(name: &str, size: i32, tag: TypeTag) -> Chunk {
     }
 }
 
+// Prototype of function values, whose two attributes are the function address and the static link
+fn gen_func_proto() -> Chunk {
+    let mut chunk = gen_special_proto(FUNC_PROTOTYPE, 16, TypeTag::Other);
+    // Neither attribute is a reference
+    chunk.links.push(ChunkLink {
+        pos: PROTOTYPE_MAP_OFFSET as usize,
+        to: ChunkLinkTarget::Data(vec![0]),
+        imm_size: 0,
+    });
+    chunk
+}
+
+// Count expression nodes, or None if the expression contains a call
+fn inline_size(expr: &Expr) -> Option<usize> {
+    let children = match &expr.content {
+        ExprContent::CallExpr(_) | ExprContent::MethodCallExpr(_) => return None,
+        ExprContent::IntegerLiteral(_)
+        | ExprContent::BooleanLiteral(_)
+        | ExprContent::NoneLiteral(_)
+        | ExprContent::StringLiteral(_)
+        | ExprContent::Variable(_) => vec![],
+        ExprContent::BinaryExpr(e) => vec![&e.left, &e.right],
+        ExprContent::UnaryExpr(e) => vec![&e.operand],
+        ExprContent::IfExpr(e) => vec![&e.condition, &e.then_expr, &e.else_expr],
+        ExprContent::IndexExpr(e) => vec![&e.list, &e.index],
+        ExprContent::MemberExpr(e) => vec![&e.object],
+        ExprContent::ListExpr(e) => e.elements.iter().collect(),
+    };
+    children
+        .into_iter()
+        .try_fold(1, |size, child| Some(size + inline_size(child)?))
+}
+
+// Get the body to substitute at call sites if the function is a tiny leaf
+fn inline_body(f: &FuncDef) -> Option<InlineBody> {
+    if !f.declarations.is_empty() {
+        return None;
+    }
+    let value = if let [Stmt::ReturnStmt(ReturnStmt {
+        value: Some(value), ..
+    })] = f.statements.as_slice()
+    {
+        value
+    } else {
+        return None;
+    };
+    if inline_size(value)? > INLINE_MAX_NODES {
+        return None;
+    }
+    Some(InlineBody {
+        params: f
+            .params
+            .iter()
+            .map(|param| param.identifier.name.clone())
+            .collect(),
+        value: value.clone(),
+        return_type: ValueType::from_annotation(&f.return_type),
+    })
+}
+
+fn is_int_or_bool(value_type: &ValueType) -> bool {
+    value_type == &*TYPE_INT || value_type == &*TYPE_BOOL
+}
+
+// Whether an expression only computes with int and bool values, without calls or allocations
+fn leaf_expr(expr: &Expr) -> bool {
+    is_int_or_bool(expr.get_type())
+        && match &expr.content {
+            ExprContent::IntegerLiteral(_)
+            | ExprContent::BooleanLiteral(_)
+            | ExprContent::Variable(_) => true,
+            ExprContent::BinaryExpr(e) => leaf_expr(&e.left) && leaf_expr(&e.right),
+            ExprContent::UnaryExpr(e) => leaf_expr(&e.operand),
+            ExprContent::IfExpr(e) => {
+                leaf_expr(&e.condition) && leaf_expr(&e.then_expr) && leaf_expr(&e.else_expr)
+            }
+            _ => false,
+        }
+}
+
+fn leaf_stmt(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::ExprStmt(s) => leaf_expr(&s.expr),
+        Stmt::AssignStmt(s) => s.targets.iter().all(leaf_expr) && leaf_expr(&s.value),
+        Stmt::IfStmt(s) => {
+            leaf_expr(&s.condition)
+                && s.then_body.iter().all(leaf_stmt)
+                && s.else_body.iter().all(leaf_stmt)
+        }
+        Stmt::WhileStmt(s) => leaf_expr(&s.condition) && s.body.iter().all(leaf_stmt),
+        Stmt::ReturnStmt(s) => s.value.as_ref().is_some_and(leaf_expr),
+        Stmt::ForStmt(_) => false,
+    }
+}
+
+// Whether a global function can go without a frame pointer with -O2. It has to take, hold and
+// return only int and bool values and make no calls, so that no GC can happen while it runs and
+// no stack frame of it needs to be found. Runtime errors and the Ctrl-C check still call into
+// the standard library, which exits from there without walking the stack.
+fn frameless_leaf(f: &FuncDef) -> bool {
+    f.params
+        .iter()
+        .all(|param| is_int_or_bool(&ValueType::from_annotation(&param.type_)))
+        && is_int_or_bool(&ValueType::from_annotation(&f.return_type))
+        && f.declarations.iter().all(|declaration| match declaration {
+            Declaration::VarDef(v) => is_int_or_bool(&ValueType::from_annotation(&v.var.type_)),
+            Declaration::GlobalDecl(_) => true,
+            _ => false,
+        })
+        && f.statements.iter().all(leaf_stmt)
+}
+
 // Generate the ChocoPy machine code
-pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
+pub(super) fn gen_code_set(
+    ast: Program,
+    unit: Unit,
+    options: CompileOptions,
+    platform: Platform,
+) -> CodeSet {
     let mut globals = HashMap::new();
     let mut global_ref_indexs = vec![];
     let mut classes = BTreeMap::new();
@@ -2269,15 +3652,18 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
         MethodSlot {
             offset: PROTOTYPE_INIT_OFFSET,
             link_name: "object.__init__".to_owned(),
+            direct: true,
         },
     );
     classes.insert(
         "object".to_owned(),
         ClassSlot {
             attributes: HashMap::new(),
+            constants: HashMap::new(),
             object_size: 0,
             methods: base_methods,
             prototype_size: OBJECT_PROTOTYPE_SIZE,
+            line: 0,
         },
     );
     let mut global_offset = 0;
@@ -2287,7 +3673,9 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
         "object".to_owned(),
         ClassDebug {
             size: 0,
+            prototype_size: OBJECT_PROTOTYPE_SIZE,
             attributes: vec![],
+            constants: vec![],
             methods: std::iter::once((
                 PROTOTYPE_INIT_OFFSET,
                 (
@@ -2303,59 +3691,63 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
     );
 
     // Scan global declarations
-    for declaration in &ast.declarations {
+    for (i, declaration) in ast.declarations.iter().enumerate() {
         match declaration {
             Declaration::VarDef(v) => {
                 // Allocate global variable
                 let name = &v.var.identifier.name;
                 let target_type = ValueType::from_annotation(&v.var.type_);
-                let size = if target_type == *TYPE_INT {
-                    4
-                } else if target_type == *TYPE_BOOL {
-                    1
-                } else {
-                    8
-                };
-                global_offset += (size - global_offset % size) % size;
-                globals.insert(
-                    name.clone(),
-                    LocalSlot::Var(VarSlot {
-                        offset: global_offset,
-                        level: 0,
-                    }),
-                );
-
-                if !target_type.is_plain() {
-                    global_ref_indexs.push(global_offset / 8);
-                }
+                let offset = alloc_global(&mut global_offset, &mut global_ref_indexs, &target_type);
+                globals.insert(name.clone(), LocalSlot::Var(VarSlot { offset, level: 0 }));
 
                 globals_debug.push(VarDebug {
-                    offset: global_offset,
+                    offset,
                     line: v.base().location.start.row,
                     name: name.clone(),
                     var_type: TypeDebug::from_annotation(&v.var.type_),
                 });
-
-                global_offset += size;
             }
             Declaration::FuncDef(f) => {
                 // Register function as available for calling
                 let name = &f.name.name;
+                let inline = if options.opt_level >= 2
+                    && !options.trace_exec
+                    && i >= unit.external_declarations
+                {
+                    inline_body(f)
+                } else {
+                    None
+                };
                 globals.insert(
                     name.clone(),
                     LocalSlot::Func(FuncSlot {
                         link_name: name.clone(),
                         level: 0,
+                        inline,
                     }),
                 );
             }
-            Declaration::ClassDef(c) => {
-                add_class(&mut globals, &mut classes, &mut classes_debug, c)
-            }
+            Declaration::ClassDef(c) => add_class(
+                &mut globals,
+                &mut classes,
+                &mut classes_debug,
+                &mut global_offset,
+                &mut global_ref_indexs,
+                c,
+            ),
             _ => panic!(),
         }
     }
 
+    // Classes of an exported unit can be extended in other units, which may override any method
+    if unit.exported {
+        for class in classes.values_mut() {
+            for method in class.methods.values_mut() {
+                method.direct = false;
+            }
+        }
+    }
+
     // Register built-in procedures as available for calling
     let insert_builtin = |globals: &mut HashMap<_, _>, name: &str| {
         globals.insert(
@@ -2363,11 +3755,16 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
             LocalSlot::Func(FuncSlot {
                 link_name: name.to_owned(),
                 level: 0,
+                inline: None,
             }),
         )
     };
 
     insert_builtin(&mut globals, "len");
+    insert_builtin(&mut globals, "obj_id");
+    insert_builtin(&mut globals, "collect");
+    insert_builtin(&mut globals, "seed");
+    insert_builtin(&mut globals, "randint");
     insert_builtin(&mut globals, "print");
     insert_builtin(&mut globals, "input");
     insert_builtin(&mut globals, "str");
@@ -2377,11 +3774,49 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
 
     let mut storage_env = StorageEnv::new(globals);
 
+    // Collect symbols shared with other units
+    let (external, internal) = ast.declarations.split_at(unit.external_declarations);
+    let shared_symbols = |declarations: &[Declaration]| {
+        let mut symbols = vec![];
+        for declaration in declarations {
+            match declaration {
+                Declaration::FuncDef(f) => symbols.push(f.name.name.clone()),
+                Declaration::ClassDef(c) => {
+                    let class_name = &c.name.name;
+                    symbols.push(class_name.clone());
+                    symbols.push(class_name.clone() + ".$proto");
+                    for declaration in &c.declarations {
+                        if let Declaration::FuncDef(f) = declaration {
+                            symbols.push(class_name.clone() + "." + &f.name.name);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        symbols
+    };
+    let externs = shared_symbols(external);
+    let exports = if unit.exported {
+        shared_symbols(internal)
+    } else {
+        vec![]
+    };
+
     // Generate machine code for main procedure
-    let mut chunks = vec![gen_main(&ast, &mut storage_env, &classes, platform)];
+    let mut chunks = vec![];
+    if unit.main {
+        chunks.push(gen_main(
+            &ast,
+            &mut storage_env,
+            &classes,
+            options,
+            platform,
+        ));
+    }
 
     // Generate machine code for all functions and methods
-    for declaration in &ast.declarations {
+    for declaration in internal {
         match declaration {
             Declaration::FuncDef(f) => {
                 chunks.append(&mut gen_function(
@@ -2390,6 +3825,7 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
                     &classes,
                     0,
                     None,
+                    options,
                     platform,
                 ));
             }
@@ -2402,6 +3838,7 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
                             &classes,
                             0,
                             Some(&c.name.name),
+                            options,
                             platform,
                         ));
                     }
@@ -2412,24 +3849,58 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
     }
 
     // Generate prototypes
+    let mut entries = vec![];
     for (class_name, class_slot) in &classes {
+        if externs.contains(class_name) {
+            continue;
+        }
+
         chunks.push(gen_ctor(class_name, class_slot, platform));
 
+        // Entry points for C code, named with `$` so that they can't clash with ChocoPy names
+        if options.export_classes && class_name != "object" {
+            let entry_name = ENTRY_PREFIX.to_owned() + class_name;
+            chunks.push(gen_entry(
+                &entry_name,
+                EntryTarget::Procedure(class_name),
+                &[],
+                TypeDebug::class_type(class_name),
+                platform,
+            ));
+            entries.push(entry_name);
+            for (&offset, (method_name, method_debug)) in &classes_debug[class_name].methods {
+                let entry_name = ENTRY_PREFIX.to_owned() + class_name + "$" + method_name;
+                chunks.push(gen_entry(
+                    &entry_name,
+                    EntryTarget::Method(offset),
+                    &method_debug.params,
+                    method_debug.return_type.clone(),
+                    platform,
+                ));
+                entries.push(entry_name);
+            }
+        }
+
         let mut prototype = vec![0; class_slot.prototype_size as usize];
-        prototype[PROTOTYPE_SIZE_OFFSET as usize..][..4]
-            .copy_from_slice(&class_slot.object_size.to_le_bytes());
-        prototype[PROTOTYPE_TAG_OFFSET as usize..][..4]
-            .copy_from_slice(&(TypeTag::Other as i32).to_le_bytes());
-        prototype[PROTOTYPE_MAP_OFFSET as usize..][..8].copy_from_slice(&(0u64).to_le_bytes());
+        ENDIAN.write(
+            &mut prototype[PROTOTYPE_SIZE_OFFSET as usize..],
+            class_slot.object_size,
+        );
+        ENDIAN.write(
+            &mut prototype[PROTOTYPE_TAG_OFFSET as usize..],
+            TypeTag::Other as i32,
+        );
+        ENDIAN.write(&mut prototype[PROTOTYPE_MAP_OFFSET as usize..], 0u64);
         let mut links: Vec<ChunkLink> = class_slot
             .methods
             .values()
             .map(|method| ChunkLink {
                 pos: method.offset as usize,
                 to: ChunkLinkTarget::Symbol(method.link_name.clone(), 0),
+                imm_size: 0,
             })
             .collect();
-        let mut ref_map = vec![0u8; ((class_slot.object_size as usize / 8) + 7) / 8];
+        let mut ref_map = vec![0u8; (class_slot.object_size as usize / 8).div_ceil(8)];
         for attribute in class_slot.attributes.values() {
             if !attribute.target_type.is_plain() {
                 let index = (attribute.offset - OBJECT_ATTRIBUTE_OFFSET) as usize / 8;
@@ -2439,6 +3910,7 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
         links.push(ChunkLink {
             pos: PROTOTYPE_MAP_OFFSET as usize,
             to: ChunkLinkTarget::Data(ref_map),
+            imm_size: 0,
         });
         chunks.push(Chunk {
             name: class_name.clone() + ".$proto",
@@ -2454,6 +3926,10 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
     chunks.push(gen_str(platform));
     chunks.push(gen_object_init(platform));
     chunks.push(gen_len(platform));
+    chunks.push(gen_obj_id(platform));
+    chunks.push(gen_collect(platform));
+    chunks.push(gen_seed(platform));
+    chunks.push(gen_randint(platform));
     chunks.push(gen_input(platform));
     chunks.push(gen_print(platform));
 
@@ -2476,14 +3952,1036 @@ pub(super) fn gen_code_set(ast: Program, platform: Platform) -> CodeSet {
         -8,
         TypeTag::RefList,
     ));
+    chunks.push(gen_func_proto());
 
     // Generate configuration data for initialization
     chunks.push(gen_init_param(global_offset as u64, &global_ref_indexs));
 
     CodeSet {
+        target: TARGET,
         chunks,
+        imports: BUILTIN_IMPORTS.to_vec(),
+        exports,
+        externs,
+        entries,
         global_size: global_offset as u64,
+        strip: options.strip,
+        group_prototypes: options.group_prototypes,
+        object_format: options.object_format,
+        no_debug: options.no_debug,
         globals_debug,
         classes_debug,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ARGS6: &str = "$test_args6";
+    const TEST_ARGS8: &str = "$test_args8";
+    const TEST_STACK_ALIGNMENT: &str = "$test_stack_alignment";
+    const VALUE_A: i64 = 0x0123_4567_89AB_CDEF;
+    const VALUE_B: i64 = -0x7EDC_BA98_7654_3210;
+    const VALUE_RAX: i64 = 0x1122_3344_5566_7788;
+
+    // A program that calls standard library test functions with many arguments
+    fn gen_args_test(platform: Platform) -> CodeSet {
+        let mut code = Emitter::new_simple(BUILTIN_CHOCOPY_MAIN, platform);
+        let a = code.alloc_stack(TicketType::Plain);
+        let b = code.alloc_stack(TicketType::Plain);
+        for (ticket, value) in [(&a, VALUE_A), (&b, VALUE_B)] {
+            // mov rax,{}
+            code.emit(&[0x48, 0xB8]);
+            code.emit_value(value);
+            // mov [rbp+{}],rax
+            code.emit_with_stack(&[0x48, 0x89, 0x85], ticket);
+        }
+
+        // mov rax,{}
+        code.emit(&[0x48, 0xB8]);
+        code.emit_value(VALUE_RAX);
+        code.call_system(
+            TEST_ARGS6,
+            &[
+                Arg::Imm(-1),
+                Arg::Frame(a.offset),
+                Arg::Reg(Reg::Rax),
+                Arg::Imm(0),
+                Arg::Frame(b.offset),
+                Arg::Imm(i32::MAX),
+            ],
+        );
+
+        // mov rax,{}
+        code.emit(&[0x48, 0xB8]);
+        code.emit_value(VALUE_RAX);
+        code.call_system(
+            TEST_ARGS8,
+            &[
+                Arg::Reg(Reg::Rax),
+                Arg::Imm(1),
+                Arg::Imm(i32::MIN),
+                Arg::Frame(b.offset),
+                Arg::Reg(Reg::Rax),
+                Arg::Frame(a.offset),
+                Arg::Symbol("test.a"),
+                Arg::Symbol("test.b"),
+            ],
+        );
+
+        code.free_stack(b);
+        code.free_stack(a);
+        code.end_proc();
+        let main = code.finalize(ProcedureDebug {
+            decl_line: 1,
+            artificial: false,
+            parent: None,
+            lines: vec![],
+            return_type: TypeDebug::class_type("<None>"),
+            params: vec![],
+            locals: vec![],
+            frame_size: 0,
+            frameless: false,
+        });
+
+        let data = |name: &str, value: i64| Chunk {
+            name: name.to_owned(),
+            code: ENDIAN.bytes(value).to_vec(),
+            links: vec![],
+            extra: ChunkExtra::Data { writable: false },
+        };
+
+        CodeSet {
+            target: TARGET,
+            chunks: vec![main, data("test.a", VALUE_A), data("test.b", VALUE_B)],
+            imports: vec![TEST_ARGS6, TEST_ARGS8],
+            exports: vec![],
+            externs: vec![],
+            entries: vec![],
+            global_size: 8,
+            strip: false,
+            group_prototypes: false,
+            object_format: None,
+            no_debug: false,
+            globals_debug: vec![],
+            classes_debug: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn many_args() {
+        // Debug info generator reads the source file
+        let source_path = test_util::temp_path(".py");
+        std::fs::write(&source_path, "pass\n").unwrap();
+        let source_path = source_path.to_str().unwrap();
+
+        let platforms = [Platform::Windows, Platform::Linux, Platform::Macos];
+        for &platform in &platforms {
+            let obj_path = test_util::temp_path(".o");
+            write_object(source_path, gen_args_test(platform), &obj_path, platform).unwrap();
+
+            if platform == crate::PLATFORM {
+                if let Some(output) = test_util::link_and_run(&[&obj_path], "") {
+                    assert!(output.status.success());
+                    let expected = format!(
+                        "-1 {a} {rax} 0 {b} {max}\n{rax} 1 {min} {b} {rax} {a} {a} {b}\n",
+                        a = VALUE_A,
+                        b = VALUE_B,
+                        rax = VALUE_RAX,
+                        max = i32::MAX,
+                        min = i32::MIN
+                    );
+                    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+                }
+            }
+
+            std::fs::remove_file(&obj_path).unwrap();
+        }
+
+        std::fs::remove_file(source_path).unwrap();
+    }
+
+    // A program that checks the stack alignment at calls with some numbers of live slots
+    // and of arguments, which are all odd or even
+    fn gen_alignment_test(platform: Platform, frameless: bool) -> (CodeSet, usize) {
+        let mut code = Emitter::new_simple(BUILTIN_CHOCOPY_MAIN, platform);
+        if frameless {
+            code.omit_frame_pointer();
+        }
+        let mut calls = 0;
+        for slots in 0..4 {
+            let tickets: Vec<_> = (0..slots)
+                .map(|_| code.alloc_stack(TicketType::Plain))
+                .collect();
+            for arg_count in [0, 5, 7] {
+                let args = vec![Arg::Imm(0); arg_count];
+                code.call_system(TEST_STACK_ALIGNMENT, &args);
+                calls += 1;
+            }
+            for ticket in tickets.into_iter().rev() {
+                code.free_stack(ticket);
+            }
+        }
+        code.end_proc();
+        let main = code.finalize(ProcedureDebug {
+            decl_line: 1,
+            artificial: false,
+            parent: None,
+            lines: vec![],
+            return_type: TypeDebug::class_type("<None>"),
+            params: vec![],
+            locals: vec![],
+            frame_size: 0,
+            frameless: false,
+        });
+
+        let code_set = CodeSet {
+            target: TARGET,
+            chunks: vec![main],
+            imports: vec![TEST_STACK_ALIGNMENT],
+            exports: vec![],
+            externs: vec![],
+            entries: vec![],
+            global_size: 8,
+            strip: false,
+            group_prototypes: false,
+            object_format: None,
+            no_debug: false,
+            globals_debug: vec![],
+            classes_debug: HashMap::new(),
+        };
+        (code_set, calls)
+    }
+
+    #[test]
+    fn stack_alignment() {
+        let source_path = test_util::temp_path(".py");
+        std::fs::write(&source_path, "pass\n").unwrap();
+        let source_path = source_path.to_str().unwrap();
+
+        // Frameless procedures allocate the word of the saved rbp along with their frame
+        for frameless in [false, true] {
+            for &platform in &[Platform::Windows, Platform::Linux, Platform::Macos] {
+                let obj_path = test_util::temp_path(".o");
+                let (code_set, calls) = gen_alignment_test(platform, frameless);
+                write_object(source_path, code_set, &obj_path, platform).unwrap();
+
+                if platform == crate::PLATFORM {
+                    if let Some(output) = test_util::link_and_run(&[&obj_path], "") {
+                        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+                        assert!(output.status.success());
+                        let expected = "aligned\n".repeat(calls);
+                        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+                    }
+                }
+
+                std::fs::remove_file(&obj_path).unwrap();
+            }
+        }
+
+        std::fs::remove_file(source_path).unwrap();
+
+        // Built-in functions called with many temporaries in nested expressions
+        let source = "
+def f(a:int, b:str, c:int) -> int:
+    return a + len(b) + c
+s:str = \"ab\"
+print(f(len(s + input()), s + s + input(), f(len([1, len(s), 3] + [len(s + s)]), input() + s, len(\"x\"))))
+";
+        let o2 = CompileOptions {
+            opt_level: 2,
+            ..Default::default()
+        };
+        for options in [Default::default(), o2] {
+            if let Some(output) = test_util::run_with_options(source, "c\nde\nf\n", options) {
+                assert!(output.status.success());
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), "17\n");
+            }
+        }
+    }
+
+    #[test]
+    fn list_add_empty_operand() {
+        // The checker rejects `[]` as an operand, but a typed AST from elsewhere may have one
+        let source = "x:[int] = None\nx = [1] + [2]\nprint(len(x))\nprint(x[0])\n";
+        for (empty_left, expected) in [(false, "1\n1\n"), (true, "1\n2\n")] {
+            let (source_path, mut ast) = test_util::check_source(source);
+            if let Stmt::AssignStmt(assign) = &mut ast.statements[0] {
+                if let ExprContent::BinaryExpr(add) = &mut assign.value.content {
+                    let operand = if empty_left {
+                        &mut add.left
+                    } else {
+                        &mut add.right
+                    };
+                    if let ExprContent::ListExpr(list) = &mut operand.content {
+                        list.elements.clear();
+                    }
+                    operand.inferred_type = Some(TYPE_EMPTY.clone());
+                }
+            }
+
+            let obj_path = test_util::temp_path(".o");
+            let source_path_str = source_path.to_str().unwrap();
+            gen_object(
+                source_path_str,
+                ast,
+                Default::default(),
+                &obj_path,
+                crate::PLATFORM,
+            )
+            .unwrap();
+            if let Some(output) = test_util::link_and_run(&[&obj_path], "") {
+                assert!(output.status.success());
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+            }
+            std::fs::remove_file(&obj_path).unwrap();
+            std::fs::remove_file(&source_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn index_bounds() {
+        const OUT_OF_BOUND: &str = "Index out of bounds\nExited with error code 3\n";
+        // Type, initial value, list, printed x[0] and x[2], and a new element as written and shown
+        let lists = [
+            ("[int]", "None", "[1, 2, 3]", ["1", "3"], ("4", "4")),
+            (
+                "[bool]",
+                "None",
+                "[True, False, True]",
+                ["True", "True"],
+                ("False", "False"),
+            ),
+            (
+                "[object]",
+                "None",
+                "[1, \"b\", True]",
+                ["1", "True"],
+                ("\"d\"", "d"),
+            ),
+            ("str", "\"\"", "\"abc\"", ["a", "c"], ("", "")),
+        ];
+        let indices = [
+            ("-1", None),
+            ("0", Some(0)),
+            ("2", Some(1)),
+            ("3", None),
+            ("-2147483647 - 1", None),
+        ];
+
+        for (list_type, init, list, expected, (new_value, new_printed)) in &lists {
+            for (index, expected_index) in &indices {
+                let header = format!(
+                    "x:{} = {}\ni:int = 0\nx = {}\ni = {}\n",
+                    list_type, init, list, index
+                );
+
+                let read = header.clone() + "print(x[i])\n";
+                if let Some(output) = test_util::run(&read, "") {
+                    let stdout = String::from_utf8(output.stdout).unwrap();
+                    if let Some(expected_index) = expected_index {
+                        assert_eq!(stdout, expected[*expected_index].to_owned() + "\n");
+                    } else {
+                        assert_eq!(stdout, "");
+                        assert_eq!(String::from_utf8(output.stderr).unwrap(), OUT_OF_BOUND);
+                        assert_eq!(output.status.code(), Some(3));
+                    }
+                }
+
+                if *list_type == "str" {
+                    continue;
+                }
+
+                let write = format!("{}x[i] = {}\nprint(x[i])\n", header, new_value);
+                if let Some(output) = test_util::run(&write, "") {
+                    let stdout = String::from_utf8(output.stdout).unwrap();
+                    if expected_index.is_some() {
+                        assert_eq!(stdout, new_printed.to_string() + "\n");
+                    } else {
+                        assert_eq!(stdout, "");
+                        assert_eq!(String::from_utf8(output.stderr).unwrap(), OUT_OF_BOUND);
+                        assert_eq!(output.status.code(), Some(3));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn init_on_none() {
+        // `__init__` can be called on anything, but a None receiver is still an error
+        for source in &["None.__init__()\n", "o:object = None\no.__init__()\n"] {
+            if let Some(output) = test_util::run(source, "") {
+                assert!(output.stdout.is_empty());
+                assert_eq!(
+                    std::str::from_utf8(&output.stderr).unwrap(),
+                    "Operation on None\nExited with error code 4\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ref_map_size() {
+        // References on both sides of rbp and far apart, with plain slots in between.
+        // Slot i is at index -(i + 1), and every third one holds a reference, down to -2002.
+        let mut code = Emitter::new("f", None, None, None, vec![16, 8000], 0, Platform::Linux);
+        let tickets: Vec<_> = (0..2002)
+            .map(|i| {
+                code.alloc_stack(if i % 3 == 0 {
+                    TicketType::Reference
+                } else {
+                    TicketType::Plain
+                })
+            })
+            .collect();
+        code.emit_ref_map();
+        // Free the tickets before asserting, as a leaked ticket panics on drop
+        for ticket in tickets.into_iter().rev() {
+            code.free_stack(ticket);
+        }
+
+        let ref_map = &code.local_data.last().unwrap().1;
+        let range = [ENDIAN.bytes(-2002i32), ENDIAN.bytes(1000i32)].concat();
+        assert_eq!(&ref_map[0..8], range);
+        assert_eq!(ref_map.len(), 8 + 3003usize.div_ceil(8));
+        for index in -2002..=1000 {
+            let map_index = (index + 2002) as usize;
+            let flag = ref_map[8 + map_index / 8] & (1 << (map_index % 8)) != 0;
+            let expected = index == 2 || index == 1000 || (index < 0 && (-index - 1) % 3 == 0);
+            assert_eq!(flag, expected, "slot {}", index);
+        }
+        // Bits past the last slot are left clear
+        assert_eq!(ref_map.last().unwrap() >> (3003 % 8), 0);
+    }
+
+    #[test]
+    fn large_frame_under_gc_stress() {
+        // Every allocation collects garbage, which must find all references in the frame
+        // through the reference map, and none of the plain values between them
+        let mut source = "def f(".to_owned();
+        let params: Vec<_> = (0..20).map(|i| format!("p{}:[int]", i)).collect();
+        source += &params.join(", ");
+        source += ") -> int:\n    total:int = 0\n";
+        for i in 0..300 {
+            source += &format!("    r{0}:[int] = None\n    n{0}:int = {0}\n", i);
+        }
+        for i in 0..300 {
+            source += &format!("    r{0} = [n{0}]\n", i);
+        }
+        for i in 0..300 {
+            source += &format!("    total = total + r{}[0]\n", i);
+        }
+        for i in 0..20 {
+            source += &format!("    total = total + p{}[0]\n", i);
+        }
+        source += "    return total\n";
+        let args: Vec<_> = (1..=20).map(|i| format!("[{}]", i)).collect();
+        source += &format!("print(f({}))\n", args.join(", "));
+
+        let envs = [("CHOCOPY_GC_STRESS", "1"), ("CHOCOPY_GC_FINAL_CHECK", "1")];
+        if let Some(output) = test_util::run_with_env(&source, "", &envs) {
+            assert!(output.status.success());
+            assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "45060\n");
+        }
+    }
+
+    #[test]
+    fn return_paths() {
+        // Each return type with a value, a bare return and falling off the end,
+        // where the checker allows them
+        let source = "
+class A(object):
+    pass
+def int_value() -> int:
+    return 1
+def bool_value() -> bool:
+    return True
+def str_value() -> str:
+    return \"s\"
+def object_value() -> object:
+    return 3
+def class_value() -> A:
+    return A()
+def class_bare() -> A:
+    return
+def class_end() -> A:
+    pass
+def list_value() -> [int]:
+    return [1]
+def list_bare() -> [int]:
+    return
+def list_end() -> [int]:
+    pass
+def none_value():
+    return None
+def none_bare():
+    return
+def none_end():
+    pass
+def branches(b:bool) -> int:
+    if b:
+        return 1
+    else:
+        return 2
+    print(\"unreachable\")
+print(int_value())
+print(bool_value())
+print(str_value())
+print(object_value())
+print(class_value() is None)
+print(class_bare() is None)
+print(class_end() is None)
+print(list_value()[0])
+print(list_bare() is None)
+print(list_end() is None)
+print(none_value() is None)
+print(none_bare() is None)
+print(none_end() is None)
+print(branches(True))
+print(branches(False))
+";
+        if let Some(output) = test_util::run(source, "") {
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "1\nTrue\ns\n3\nFalse\nTrue\nTrue\n1\nTrue\nTrue\nTrue\nTrue\nTrue\n1\n2\n"
+            );
+        }
+
+        // Functions that return on every path have no implicit `return None`,
+        // and statements after the return are left out
+        let (source_path, ast) = test_util::check_source(source);
+        std::fs::remove_file(source_path).unwrap();
+        let options = Default::default();
+        let code_set = gen_code_set(ast, Unit::whole_program(), options, Platform::Linux);
+        let returns = |name: &str| {
+            let chunk = code_set.chunks.iter().find(|c| c.name == name).unwrap();
+            // leave; ret
+            chunk.code.windows(2).filter(|w| *w == [0xC9, 0xC3]).count()
+        };
+        assert_eq!(returns("int_value"), 1);
+        assert_eq!(returns("branches"), 2);
+        assert_eq!(returns("none_bare"), 1);
+        assert_eq!(returns("none_end"), 1);
+    }
+
+    #[test]
+    fn not_of_bool_results() {
+        // Bool results only set al, so `not` must ignore the rest of rax
+        let source = "
+p:bool = True
+x:int = 300
+print(not ((not p) and False))
+print(not (x > 1))
+print(not (x == 300 or p))
+print(not not p)
+";
+        if let Some(output) = test_util::run(source, "") {
+            assert!(output.status.success());
+            assert_eq!(
+                std::str::from_utf8(&output.stdout).unwrap(),
+                "True\nFalse\nFalse\nTrue\n"
+            );
+        }
+    }
+
+    #[test]
+    fn trace_exec() {
+        let source = "
+def f(n:int) -> int:
+    if n > 1:
+        return n
+    return 0
+i:int = 0
+n:int = 0
+n = len(input())
+while i < n:
+    i = i + 1
+print(f(n))
+";
+        let options = CompileOptions {
+            trace_exec: true,
+            ..Default::default()
+        };
+        let cases = [
+            ("ab\n", "2\n", [8, 9, 10, 10, 11, 3, 4].as_slice()),
+            ("\n", "0\n", [8, 9, 11, 3, 5].as_slice()),
+        ];
+        for (input, expected, lines) in &cases {
+            if let Some(output) = test_util::run_with_options(source, input, options) {
+                assert!(output.status.success());
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), *expected);
+                let trace: String = lines
+                    .iter()
+                    .map(|line| format!("TRACE: line {}\n", line))
+                    .collect();
+                assert_eq!(String::from_utf8(output.stderr).unwrap(), trace);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn interruptible() {
+        use std::io::{BufRead, BufReader};
+        use std::process::{Command, Stdio};
+
+        let source = "
+l:[int] = None
+x:int = 0
+l = [1, 2]
+print(\"started\")
+while True:
+    for x in l:
+        x = x + 1
+";
+        let options = CompileOptions {
+            interruptible: true,
+            ..Default::default()
+        };
+        let (source_path, ast) = test_util::check_source(source);
+        let obj_path = test_util::temp_path(".o");
+        let source_path_str = source_path.to_str().unwrap();
+        gen_object(source_path_str, ast, options, &obj_path, crate::PLATFORM).unwrap();
+        let exe_path = if let Some(exe_path) = test_util::link(&[&obj_path]) {
+            exe_path
+        } else {
+            return;
+        };
+
+        let mut process = Command::new(&exe_path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // The handler is installed before the first statement runs
+        let mut stdout = BufReader::new(process.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, "started\n");
+        let kill = Command::new("kill")
+            .args(["-INT", &process.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(kill.success());
+
+        let output = process.wait_with_output().unwrap();
+        assert_eq!(output.status.code(), Some(130));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "Interrupted\nExited with error code 130\n"
+        );
+
+        std::fs::remove_file(&exe_path).unwrap();
+        std::fs::remove_file(&obj_path).unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+    }
+
+    #[test]
+    fn inline_leaf_functions() {
+        let source = "
+class Point(object):
+    x:int = 0
+x:int = 10
+def add(a:int, b:int) -> int:
+    return a + b * x
+def exclaim(s:str) -> str:
+    return s + \"!\"
+def boxed(a:int) -> object:
+    return a
+def first(l:[int]) -> int:
+    return l[0]
+def pick(c:bool, a:int, b:int) -> int:
+    return a if c else b
+def get_x(p:Point) -> int:
+    return p.x
+def twice(n:int) -> int:
+    return add(n, n)
+def shadow(y:int) -> int:
+    x:int = 100
+    return add(y, 1)
+p:Point = None
+p = Point()
+p.x = 6
+print(add(1, 2))
+print(exclaim(\"hi\"))
+print(boxed(5))
+print(first([7, 8]))
+print(pick(False, 1, 2))
+print(get_x(p))
+print(twice(3))
+print(shadow(4))
+x = 0
+print(add(first([3]), 9))
+print(first([]))
+";
+        let inlined = ["add", "exclaim", "boxed", "first", "pick", "get_x"];
+        let calls = |options| {
+            let (source_path, ast) = test_util::check_source(source);
+            std::fs::remove_file(source_path).unwrap();
+            let code_set = gen_code_set(ast, Unit::whole_program(), options, Platform::Linux);
+            code_set
+                .chunks
+                .iter()
+                .flat_map(|chunk| &chunk.links)
+                .filter(|link| {
+                    matches!(&link.to, ChunkLinkTarget::Symbol(name, _)
+                        if inlined.contains(&name.as_str()))
+                })
+                .count()
+        };
+        let o2 = CompileOptions {
+            opt_level: 2,
+            ..Default::default()
+        };
+        assert_eq!(calls(Default::default()), 11);
+        assert_eq!(calls(o2), 0);
+        assert_eq!(
+            calls(CompileOptions {
+                trace_exec: true,
+                ..o2
+            }),
+            11
+        );
+
+        let expected = "21\nhi!\n5\n7\n2\n6\n33\n14\n3\n";
+        for options in [Default::default(), o2] {
+            if let Some(output) = test_util::run_with_options(source, "", options) {
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+                assert_eq!(
+                    String::from_utf8(output.stderr).unwrap(),
+                    "Index out of bounds\nExited with error code 3\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn runtime_checks() {
+        let source = "
+class A(object):
+    x:int = 7
+l:[int] = None
+a:A = None
+s:str = \"abc\"
+i:int = 1
+l = [10, 20, 30]
+a = A()
+print(l[i] // i + a.x % 4)
+print(s[i])
+";
+        let code_set = |runtime_checks| {
+            let (source_path, ast) = test_util::check_source(source);
+            std::fs::remove_file(source_path).unwrap();
+            let options = CompileOptions {
+                runtime_checks,
+                ..Default::default()
+            };
+            gen_code_set(ast, Unit::whole_program(), options, Platform::Linux)
+        };
+        let calls = |runtime_checks| {
+            let code_set = code_set(runtime_checks);
+            let links = code_set.chunks.iter().flat_map(|chunk| &chunk.links);
+            let mut counts = [0; 3];
+            for link in links {
+                if let ChunkLinkTarget::Symbol(name, _) = &link.to {
+                    let check = [BUILTIN_OUT_OF_BOUND, BUILTIN_NONE_OP, BUILTIN_DIV_ZERO]
+                        .iter()
+                        .position(|check| check == name);
+                    if let Some(check) = check {
+                        counts[check] += 1;
+                    }
+                }
+            }
+            counts
+        };
+
+        let all = calls(RuntimeChecks::All);
+        assert!(all.iter().all(|&count| count > 0));
+        assert_eq!(calls(RuntimeChecks::Bounds), [all[0], 0, 0]);
+        assert_eq!(calls(RuntimeChecks::Off), [0, 0, 0]);
+
+        // The default is to keep every check, as before there were levels
+        let code = |runtime_checks| -> Vec<Vec<u8>> {
+            let chunks = code_set(runtime_checks).chunks;
+            chunks.into_iter().map(|chunk| chunk.code).collect()
+        };
+        assert!(code(Default::default()) == code(RuntimeChecks::All));
+
+        let levels = [
+            RuntimeChecks::All,
+            RuntimeChecks::Bounds,
+            RuntimeChecks::Off,
+        ];
+        for runtime_checks in levels {
+            let options = CompileOptions {
+                runtime_checks,
+                ..Default::default()
+            };
+            if let Some(output) = test_util::run_with_options(source, "", options) {
+                assert!(output.status.success());
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), "23\nb\n");
+            }
+        }
+
+        // Only the levels with bounds checks report the index as out of bounds
+        let out_of_bounds = "l:[int] = None\nl = [1]\nprint(l[1000000])\n";
+        for runtime_checks in [RuntimeChecks::All, RuntimeChecks::Bounds] {
+            let options = CompileOptions {
+                runtime_checks,
+                ..Default::default()
+            };
+            if let Some(output) = test_util::run_with_options(out_of_bounds, "", options) {
+                assert_eq!(output.status.code(), Some(3));
+                assert_eq!(
+                    String::from_utf8(output.stderr).unwrap(),
+                    "Index out of bounds\nExited with error code 3\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inline_len() {
+        let source = "
+def count(o:object) -> int:
+    return len(o)
+a:[int] = None
+b:[bool] = None
+c:[str] = None
+s:str = \"hello\"
+a = [1, 2, 3]
+b = [True]
+c = []
+print(len(a))
+print(len(b) + len(c))
+print(len(s) + len(\"\"))
+print(len([a, a]))
+print(count(s))
+a = None
+print(len(a))
+";
+        let calls = |options| {
+            let (source_path, ast) = test_util::check_source(source);
+            std::fs::remove_file(source_path).unwrap();
+            let code_set = gen_code_set(ast, Unit::whole_program(), options, Platform::Linux);
+            code_set
+                .chunks
+                .iter()
+                .flat_map(|chunk| &chunk.links)
+                .filter(
+                    |link| matches!(&link.to, ChunkLinkTarget::Symbol(name, _) if name == "len"),
+                )
+                .count()
+        };
+        let o2 = CompileOptions {
+            opt_level: 2,
+            ..Default::default()
+        };
+        assert_eq!(calls(Default::default()), 8);
+        // Only `len(o)` on an object still calls the function
+        assert_eq!(calls(o2), 1);
+
+        for options in [Default::default(), o2] {
+            if let Some(output) = test_util::run_with_options(source, "", options) {
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n1\n5\n2\n5\n");
+                assert_eq!(
+                    String::from_utf8(output.stderr).unwrap(),
+                    "Invalid argument\nExited with error code 1\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inline_len_other_calls() {
+        // Calls that can't be `len`, which must not be mistaken for it
+        let source = "
+class A(object):
+    x:int = 1
+def f() -> int:
+    return 2
+a:A = None
+a = A()
+collect()
+print(f() + a.x)
+";
+        let o2 = CompileOptions {
+            opt_level: 2,
+            ..Default::default()
+        };
+        let (source_path, ast) = test_util::check_source(source);
+        std::fs::remove_file(source_path).unwrap();
+        gen_code_set(ast, Unit::whole_program(), o2, Platform::Linux);
+        if let Some(output) = test_util::run_with_options(source, "", o2) {
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+        }
+    }
+
+    #[test]
+    fn direct_self_calls() {
+        let source = "
+class A(object):
+    def helper(self:\"A\") -> int:
+        return 1
+    def shared(self:\"A\") -> int:
+        return 2
+    def run(self:\"A\") -> int:
+        return self.helper() + self.shared()
+class B(A):
+    def shared(self:\"B\") -> int:
+        return 3
+a:A = None
+a = A()
+print(a.run())
+a = B()
+print(a.run())
+print(a.helper())
+";
+        // Direct calls from procedures to each method. Prototypes link to all of them.
+        let calls = |options, unit| {
+            let (source_path, ast) = test_util::check_source(source);
+            std::fs::remove_file(source_path).unwrap();
+            let code_set = gen_code_set(ast, unit, options, Platform::Linux);
+            let count = |method: &str| {
+                code_set
+                    .chunks
+                    .iter()
+                    .filter(|chunk| matches!(chunk.extra, ChunkExtra::Procedure(_)))
+                    .flat_map(|chunk| &chunk.links)
+                    .filter(|link| {
+                        matches!(&link.to, ChunkLinkTarget::Symbol(name, _) if name == method)
+                    })
+                    .count()
+            };
+            (count("A.helper"), count("A.shared"))
+        };
+        let o2 = CompileOptions {
+            opt_level: 2,
+            ..Default::default()
+        };
+        let exported = Unit {
+            exported: true,
+            ..Unit::whole_program()
+        };
+        assert_eq!(calls(Default::default(), Unit::whole_program()), (0, 0));
+        // `a.helper()` isn't on `self`, and B overrides `shared`
+        assert_eq!(calls(o2, Unit::whole_program()), (1, 0));
+        // Other units may override `helper`
+        assert_eq!(calls(o2, exported), (0, 0));
+
+        for options in [Default::default(), o2] {
+            if let Some(output) = test_util::run_with_options(source, "", options) {
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n4\n1\n");
+            }
+        }
+    }
+
+    // Lay out globals of the types in order, and check the offsets and the reference map
+    fn check_global_layout(types: &[&ValueType]) {
+        let mut global_offset = 0;
+        let mut global_ref_indexs = vec![];
+        let offsets: Vec<_> = types
+            .iter()
+            .map(|t| alloc_global(&mut global_offset, &mut global_ref_indexs, t))
+            .collect();
+        let ref_map = global_ref_map(global_offset as u64, &global_ref_indexs);
+
+        // Slots scanned by the runtime, and whether each holds a reference
+        let slots = global_offset as usize / 8;
+        let mut refs = vec![false; slots];
+        let mut end = 0;
+        for (&offset, t) in offsets.iter().zip(types) {
+            let size = if **t == *TYPE_INT {
+                4
+            } else if **t == *TYPE_BOOL {
+                1
+            } else {
+                8
+            };
+            assert!(offset >= end && offset % size == 0, "{:?}", types);
+            end = offset + size;
+            if !t.is_plain() {
+                assert!(end as usize <= slots * 8, "{:?}", types);
+                refs[offset as usize / 8] = true;
+            }
+        }
+        assert_eq!(end, global_offset);
+
+        // Scalars never share a slot with a reference
+        for (&offset, t) in offsets.iter().zip(types) {
+            if t.is_plain() && (offset as usize / 8) < slots {
+                assert!(!refs[offset as usize / 8], "{:?}", types);
+            }
+        }
+
+        assert_eq!(ref_map.len(), slots.div_ceil(8), "{:?}", types);
+        for (slot, &is_ref) in refs.iter().enumerate() {
+            let bit = ref_map[slot / 8] & (1 << (slot % 8)) != 0;
+            assert_eq!(bit, is_ref, "slot {} of {:?}", slot, types);
+        }
+        // Bits past the last slot are clear
+        for bit in slots..ref_map.len() * 8 {
+            assert_eq!(ref_map[bit / 8] & (1 << (bit % 8)), 0, "{:?}", types);
+        }
+    }
+
+    #[test]
+    fn global_layout() {
+        use chocopy_rs_common::random::{Random, DEFAULT_SEED};
+
+        let int_list = ValueType::ListValueType(ListValueType {
+            element_type: Box::new(TYPE_INT.clone()),
+        });
+        let types = [
+            &*TYPE_INT,
+            &*TYPE_BOOL,
+            &*TYPE_STR,
+            &int_list,
+            &*TYPE_OBJECT,
+        ];
+
+        // Every order of up to 6 globals
+        let mut orders: Vec<Vec<&ValueType>> = vec![vec![]];
+        for _ in 0..6 {
+            orders = orders
+                .iter()
+                .flat_map(|order| {
+                    types.iter().map(move |&t| {
+                        let mut order = order.clone();
+                        order.push(t);
+                        order
+                    })
+                })
+                .collect();
+            for order in &orders {
+                check_global_layout(order);
+            }
+        }
+
+        // Long random orders, with more references than one byte of the map holds
+        let mut random = Random::new(DEFAULT_SEED);
+        for _ in 0..1000 {
+            let len = random.int(1, 200);
+            let order: Vec<_> = (0..len)
+                .map(|_| types[random.int(0, types.len() as i32 - 1) as usize])
+                .collect();
+            check_global_layout(&order);
+        }
+
+        // Scalars filling each slot up to the last byte, around runs of references
+        let mut order = vec![];
+        for i in 0..70 {
+            order.push(&*TYPE_STR);
+            order.extend(std::iter::repeat_n(&*TYPE_BOOL, i % 8));
+            order.extend(std::iter::repeat_n(&*TYPE_INT, i % 3));
+            order.push(&*TYPE_BOOL);
+            check_global_layout(&order);
+        }
+        let mut order = vec![&*TYPE_BOOL; 7];
+        order.extend(std::iter::repeat_n(&*TYPE_STR, 65));
+        check_global_layout(&order);
+    }
+}
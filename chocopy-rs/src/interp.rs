@@ -0,0 +1,1049 @@
+// Tree-walking interpreter for checked ChocoPy programs.
+//
+// It runs the typed AST directly, mimicking the compiled program and the standard
+// library, including their runtime errors. Declarations and statements can be fed
+// in several steps sharing the same global state, which is what the REPL needs.
+
+use crate::node::*;
+use chocopy_rs_common::random::{self, Random};
+use chocopy_rs_common::OBJ_EQ_MAX_DEPTH;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+#[derive(Clone)]
+pub enum Value {
+    None,
+    Int(i32),
+    Bool(bool),
+    Str(Rc<[u8]>), // UTF-8, but indexing can split a character as in compiled code
+    List(Rc<RefCell<Vec<Value>>>),
+    Object(Rc<Object>),
+    Func(Rc<FuncValue>), // Extension: a top-level function used as a value
+}
+
+pub struct Object {
+    class: Rc<Class>,
+    attributes: RefCell<HashMap<String, Value>>,
+}
+
+impl Object {
+    pub fn class_name(&self) -> &str {
+        &self.class.name
+    }
+}
+
+// Compiled code allocates an object each time a function is used as a value,
+// so each one has an identity of its own
+pub struct FuncValue {
+    function: Rc<Function>,
+}
+
+impl FuncValue {
+    pub fn name(&self) -> &str {
+        &self.function.def.name.name
+    }
+}
+
+struct Class {
+    name: String,
+    attributes: Vec<(String, Value)>, // Initial values, including inherited ones
+    methods: HashMap<String, Rc<Function>>, // Including inherited ones
+    constants: HashMap<String, Value>, // Including inherited ones
+}
+
+// A function along with its nested functions, shared by all calls to it
+struct Function {
+    def: FuncDef,
+    nested: Vec<Rc<Function>>,
+}
+
+impl Function {
+    fn new(def: &FuncDef) -> Rc<Function> {
+        let nested = def
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::FuncDef(f) => Some(Function::new(f)),
+                _ => None,
+            })
+            .collect();
+        Rc::new(Function {
+            def: def.clone(),
+            nested,
+        })
+    }
+}
+
+enum Binding {
+    Var(Value),
+    Func(Rc<Function>),
+}
+
+// Variables and functions of one function call, or of the global scope
+struct Frame {
+    bindings: RefCell<HashMap<String, Binding>>,
+    globals: HashSet<String>,  // Names declared as `global` in this function
+    parent: Option<Rc<Frame>>, // Frame of the enclosing function or the global scope
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message: &'static str,
+    pub code: i32,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+// Same messages and exit codes as the standard library
+const INVALID_ARG: RuntimeError = RuntimeError {
+    message: "Invalid argument",
+    code: 1,
+};
+const DIV_ZERO: RuntimeError = RuntimeError {
+    message: "Division by zero",
+    code: 2,
+};
+const OUT_OF_BOUND: RuntimeError = RuntimeError {
+    message: "Index out of bounds",
+    code: 3,
+};
+const NONE_OP: RuntimeError = RuntimeError {
+    message: "Operation on None",
+    code: 4,
+};
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+fn literal_value(literal: &Literal) -> Value {
+    match &literal.content {
+        LiteralContent::IntegerLiteral(i) => Value::Int(i.value),
+        LiteralContent::BooleanLiteral(b) => Value::Bool(b.value),
+        LiteralContent::NoneLiteral(_) => Value::None,
+        LiteralContent::StringLiteral(s) => Value::Str(s.value.as_bytes().into()),
+    }
+}
+
+fn index(len: usize, index: Value) -> Result<usize> {
+    match index {
+        Value::Int(i) if i >= 0 && (i as usize) < len => Ok(i as usize),
+        _ => Err(OUT_OF_BOUND),
+    }
+}
+
+pub struct Interpreter<'a> {
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+    globals: Rc<Frame>,
+    classes: HashMap<String, Rc<Class>>,
+    // Identities handed out by `obj_id`. Objects are kept alive so that addresses aren't reused.
+    object_ids: HashMap<*const u8, (Value, i32)>,
+    next_object_id: i32,
+    // The same generator as the standard library, so that `randint` gives the same numbers
+    random: Random,
+}
+
+// Extension: element-wise equality of lists of int, bool or str, where None equals only None
+fn list_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::None, Value::None) => true,
+        (Value::List(a), Value::List(b)) => {
+            let (a_list, b_list) = (a.borrow(), b.borrow());
+            Rc::ptr_eq(a, b)
+                || a_list.len() == b_list.len()
+                    && a_list.iter().zip(b_list.iter()).all(|pair| match pair {
+                        (Value::Int(a), Value::Int(b)) => a == b,
+                        (Value::Bool(a), Value::Bool(b)) => a == b,
+                        (Value::Str(a), Value::Str(b)) => a == b,
+                        _ => panic!(),
+                    })
+        }
+        _ => false,
+    }
+}
+
+// Extension: whether `x in list` finds an element. Elements of int, bool or str are compared by
+// value, and other elements by identity like `is`
+fn list_contains(list: &[Value], x: &Value, by_value: bool) -> bool {
+    list.iter().any(|element| match (x, element) {
+        (Value::Int(a), Value::Int(b)) => by_value && a == b,
+        (Value::Bool(a), Value::Bool(b)) => by_value && a == b,
+        (Value::Str(a), Value::Str(b)) => by_value && a == b,
+        (Value::None, Value::None) => true,
+        (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+        (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+        (Value::Func(a), Value::Func(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    })
+}
+
+// Extension: attribute-wise equality of objects of the same class, like `$obj_eq` in the
+// standard library. Lists are compared by identity, and None equals only None.
+fn obj_eq(a: &Value, b: &Value, depth: u32) -> bool {
+    match (a, b) {
+        (Value::None, Value::None) => true,
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+        (Value::Object(a), Value::Object(b)) => {
+            Rc::ptr_eq(a, b)
+                || depth < OBJ_EQ_MAX_DEPTH && Rc::ptr_eq(&a.class, &b.class) && {
+                    let b_attributes = b.attributes.borrow();
+                    a.attributes
+                        .borrow()
+                        .iter()
+                        .all(|(name, value)| obj_eq(value, &b_attributes[name], depth + 1))
+                }
+        }
+        _ => false,
+    }
+}
+
+// Extension: Python's truthiness, for `and` and `or` on any values
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::None => false,
+        Value::Int(i) => *i != 0,
+        Value::Bool(b) => *b,
+        Value::Str(s) => !s.is_empty(),
+        Value::List(list) => !list.borrow().is_empty(),
+        Value::Object(_) | Value::Func(_) => true,
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(input: &'a mut dyn BufRead, output: &'a mut dyn Write) -> Interpreter<'a> {
+        let mut classes = HashMap::new();
+        classes.insert(
+            "object".to_owned(),
+            Rc::new(Class {
+                name: "object".to_owned(),
+                attributes: vec![],
+                methods: HashMap::new(),
+                constants: HashMap::new(),
+            }),
+        );
+        Interpreter {
+            input,
+            output,
+            globals: Rc::new(Frame {
+                bindings: RefCell::new(HashMap::new()),
+                globals: HashSet::new(),
+                parent: None,
+            }),
+            classes,
+            object_ids: HashMap::new(),
+            next_object_id: 1,
+            random: Random::new(random::DEFAULT_SEED),
+        }
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.output
+    }
+
+    // Read a line of input without the line break. Returns None at the end of input
+    pub fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line).unwrap_or(0) == 0 {
+            return None;
+        }
+        while line.ends_with(['\n', '\r']) {
+            line.pop();
+        }
+        Some(line)
+    }
+
+    // Add global declarations. They can refer to declarations added before
+    pub fn declare(&mut self, declarations: &[Declaration]) {
+        for declaration in declarations {
+            match declaration {
+                Declaration::VarDef(v) => {
+                    self.globals.bindings.borrow_mut().insert(
+                        v.var.identifier.name.clone(),
+                        Binding::Var(literal_value(&v.value)),
+                    );
+                }
+                Declaration::FuncDef(f) => {
+                    self.globals
+                        .bindings
+                        .borrow_mut()
+                        .insert(f.name.name.clone(), Binding::Func(Function::new(f)));
+                }
+                Declaration::ClassDef(c) => self.add_class(c),
+                _ => (),
+            }
+        }
+    }
+
+    fn add_class(&mut self, class_def: &ClassDef) {
+        let super_class = &self.classes[&class_def.super_class.name];
+        let mut attributes = super_class.attributes.clone();
+        let mut methods = super_class.methods.clone();
+        let mut constants = super_class.constants.clone();
+        for declaration in &class_def.declarations {
+            match declaration {
+                Declaration::VarDef(v) if v.constant => {
+                    constants.insert(v.var.identifier.name.clone(), literal_value(&v.value));
+                }
+                Declaration::VarDef(v) => {
+                    attributes.push((v.var.identifier.name.clone(), literal_value(&v.value)));
+                }
+                Declaration::FuncDef(f) => {
+                    methods.insert(f.name.name.clone(), Function::new(f));
+                }
+                _ => (),
+            }
+        }
+        self.classes.insert(
+            class_def.name.name.clone(),
+            Rc::new(Class {
+                name: class_def.name.name.clone(),
+                attributes,
+                methods,
+                constants,
+            }),
+        );
+    }
+
+    // Execute top-level statements
+    pub fn exec(&mut self, statements: &[Stmt]) -> Result<()> {
+        let globals = self.globals.clone();
+        self.exec_stmts(&globals, statements).map(|_| ())
+    }
+
+    // Evaluate a top-level expression
+    pub fn eval(&mut self, expr: &Expr) -> Result<Value> {
+        let globals = self.globals.clone();
+        self.eval_expr(&globals, expr)
+    }
+
+    // Find the frame where a variable or function visible from the frame is defined
+    fn owner(&self, frame: &Rc<Frame>, name: &str) -> Option<Rc<Frame>> {
+        let mut current = Some(frame.clone());
+        while let Some(frame) = current {
+            if frame.bindings.borrow().contains_key(name) {
+                return Some(frame);
+            }
+            current = if frame.globals.contains(name) {
+                Some(self.globals.clone())
+            } else {
+                frame.parent.clone()
+            };
+        }
+        None
+    }
+
+    fn get_var(&self, frame: &Rc<Frame>, name: &str) -> Value {
+        let owner = self.owner(frame, name).unwrap();
+        let bindings = owner.bindings.borrow();
+        match &bindings[name] {
+            Binding::Var(value) => value.clone(),
+            Binding::Func(function) => Value::Func(Rc::new(FuncValue {
+                function: function.clone(),
+            })),
+        }
+    }
+
+    fn set_var(&self, frame: &Rc<Frame>, name: &str, value: Value) {
+        let owner = self.owner(frame, name).unwrap();
+        owner
+            .bindings
+            .borrow_mut()
+            .insert(name.to_owned(), Binding::Var(value));
+    }
+
+    fn call(&mut self, parent: Rc<Frame>, function: &Function, args: Vec<Value>) -> Result<Value> {
+        let f = &function.def;
+        let mut bindings = HashMap::new();
+        for (param, arg) in f.params.iter().zip(args) {
+            bindings.insert(param.identifier.name.clone(), Binding::Var(arg));
+        }
+        let mut globals = HashSet::new();
+        for declaration in &f.declarations {
+            match declaration {
+                Declaration::VarDef(v) => {
+                    bindings.insert(
+                        v.var.identifier.name.clone(),
+                        Binding::Var(literal_value(&v.value)),
+                    );
+                }
+                Declaration::GlobalDecl(g) => {
+                    globals.insert(g.variable.name.clone());
+                }
+                _ => (),
+            }
+        }
+        for nested in &function.nested {
+            bindings.insert(nested.def.name.name.clone(), Binding::Func(nested.clone()));
+        }
+
+        let frame = Rc::new(Frame {
+            bindings: RefCell::new(bindings),
+            globals,
+            parent: Some(parent),
+        });
+        Ok(self
+            .exec_stmts(&frame, &f.statements)?
+            .unwrap_or(Value::None))
+    }
+
+    fn call_method(&mut self, object: Value, name: &str, mut args: Vec<Value>) -> Result<Value> {
+        let method = match &object {
+            Value::None => return Err(NONE_OP),
+            Value::Object(o) => o.class.methods.get(name).cloned(),
+            _ => None,
+        };
+        // Only `__init__` can be called on other values and it does nothing
+        if let Some(method) = method {
+            args.insert(0, object);
+            self.call(self.globals.clone(), &method, args)
+        } else {
+            Ok(Value::None)
+        }
+    }
+
+    fn construct(&mut self, class_name: &str) -> Result<Value> {
+        let class = match class_name {
+            "int" => return Ok(Value::Int(0)),
+            "bool" => return Ok(Value::Bool(false)),
+            "str" => return Ok(Value::Str(b"".as_ref().into())),
+            _ => self.classes[class_name].clone(),
+        };
+        let object = Value::Object(Rc::new(Object {
+            attributes: RefCell::new(class.attributes.iter().cloned().collect()),
+            class,
+        }));
+        self.call_method(object.clone(), "__init__", vec![])?;
+        Ok(object)
+    }
+
+    fn call_builtin(&mut self, name: &str, args: Vec<Value>) -> Result<Value> {
+        match name {
+            "print" => {
+                // Objects with `__str__` are printed as the string it returns
+                let value = match &args[0] {
+                    Value::Object(o) if o.class.methods.contains_key("__str__") => {
+                        self.call_method(args[0].clone(), "__str__", vec![])?
+                    }
+                    value => value.clone(),
+                };
+                let text = match &value {
+                    Value::Int(i) => i.to_string(),
+                    Value::Bool(true) => "True".to_owned(),
+                    Value::Bool(false) => "False".to_owned(),
+                    Value::Str(s) => String::from_utf8_lossy(s).into_owned(),
+                    _ => return Err(INVALID_ARG),
+                };
+                let _ = writeln!(self.output, "{}", text);
+                Ok(Value::None)
+            }
+            "len" => match &args[0] {
+                Value::Str(s) => Ok(Value::Int(s.len() as i32)),
+                Value::List(l) => Ok(Value::Int(l.borrow().len() as i32)),
+                _ => Err(INVALID_ARG),
+            },
+            "obj_id" => {
+                let address = match &args[0] {
+                    Value::None => return Ok(Value::Int(0)),
+                    Value::Str(s) => Rc::as_ptr(s) as *const u8,
+                    Value::List(l) => Rc::as_ptr(l) as *const u8,
+                    Value::Object(o) => Rc::as_ptr(o) as *const u8,
+                    Value::Func(f) => Rc::as_ptr(f) as *const u8,
+                    // Compiled code boxes these into a new object every time
+                    Value::Int(_) | Value::Bool(_) => {
+                        self.next_object_id += 1;
+                        return Ok(Value::Int(self.next_object_id - 1));
+                    }
+                };
+                let next_object_id = &mut self.next_object_id;
+                let (_, id) = self.object_ids.entry(address).or_insert_with(|| {
+                    *next_object_id += 1;
+                    (args[0].clone(), *next_object_id - 1)
+                });
+                Ok(Value::Int(*id))
+            }
+            // Values are reference counted and freed as soon as they are dropped
+            "collect" => Ok(Value::None),
+            "seed" => match args[0] {
+                Value::Int(seed) => {
+                    self.random = Random::new(seed);
+                    Ok(Value::None)
+                }
+                _ => panic!(),
+            },
+            "randint" => match (&args[0], &args[1]) {
+                (&Value::Int(lo), &Value::Int(hi)) if lo <= hi => {
+                    Ok(Value::Int(self.random.int(lo, hi)))
+                }
+                (Value::Int(_), Value::Int(_)) => Err(INVALID_ARG),
+                _ => panic!(),
+            },
+            // Extension: a new list, leaving the original one unchanged
+            "append" => match &args[0] {
+                Value::List(l) => {
+                    let mut elements = l.borrow().clone();
+                    elements.push(args[1].clone());
+                    Ok(Value::List(Rc::new(RefCell::new(elements))))
+                }
+                _ => Err(NONE_OP),
+            },
+            // Extension: the number of elements equal to an int
+            "count" => match (&args[0], &args[1]) {
+                (Value::List(l), &Value::Int(x)) => {
+                    let equal = |e: &&Value| matches!(e, Value::Int(e) if *e == x);
+                    Ok(Value::Int(l.borrow().iter().filter(equal).count() as i32))
+                }
+                _ => Err(NONE_OP),
+            },
+            "input" => {
+                let _ = self.output.flush();
+                let line = self.read_line().unwrap_or_default();
+                Ok(Value::Str(line.into_bytes().into()))
+            }
+            _ => self.construct(name),
+        }
+    }
+
+    fn exec_stmts(&mut self, frame: &Rc<Frame>, statements: &[Stmt]) -> Result<Option<Value>> {
+        for statement in statements {
+            if let Some(value) = self.exec_stmt(frame, statement)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    // Returns the return value if the statement returns from the function
+    fn exec_stmt(&mut self, frame: &Rc<Frame>, statement: &Stmt) -> Result<Option<Value>> {
+        match statement {
+            Stmt::ExprStmt(s) => {
+                self.eval_expr(frame, &s.expr)?;
+            }
+            Stmt::AssignStmt(s) => {
+                let value = self.eval_expr(frame, &s.value)?;
+                for target in &s.targets {
+                    self.assign(frame, target, value.clone())?;
+                }
+            }
+            Stmt::IfStmt(s) => {
+                let body = if self.eval_bool(frame, &s.condition)? {
+                    &s.then_body
+                } else {
+                    &s.else_body
+                };
+                return self.exec_stmts(frame, body);
+            }
+            Stmt::WhileStmt(s) => {
+                while self.eval_bool(frame, &s.condition)? {
+                    if let Some(value) = self.exec_stmts(frame, &s.body)? {
+                        return Ok(Some(value));
+                    }
+                }
+            }
+            Stmt::ForStmt(s) => return self.exec_for_stmt(frame, s),
+            Stmt::ReturnStmt(s) => {
+                let value = if let Some(value) = &s.value {
+                    self.eval_expr(frame, value)?
+                } else {
+                    Value::None
+                };
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    fn exec_for_stmt(&mut self, frame: &Rc<Frame>, stmt: &ForStmt) -> Result<Option<Value>> {
+        let name = &stmt.identifier.name;
+        let is_defined = |name: &str| self.owner(frame, name).is_some();
+        if let Some(args) = stmt.range_args(is_defined) {
+            let mut counter = if args.len() == 2 {
+                self.eval_int(frame, &args[0])?
+            } else {
+                0
+            };
+            let bound = self.eval_int(frame, args.last().unwrap())?;
+            while counter < bound {
+                self.set_var(frame, name, Value::Int(counter));
+                if let Some(value) = self.exec_stmts(frame, &stmt.body)? {
+                    return Ok(Some(value));
+                }
+                counter += 1;
+            }
+            return Ok(None);
+        }
+
+        let iterable = self.eval_expr(frame, stmt.enumerate_arg().unwrap_or(&stmt.iterable))?;
+        let mut i = 0;
+        loop {
+            let element = match &iterable {
+                Value::None => return Err(NONE_OP),
+                Value::Str(s) => match s.get(i..i + 1) {
+                    Some(c) => Value::Str(c.into()),
+                    None => break,
+                },
+                Value::List(l) => match l.borrow().get(i) {
+                    Some(element) => element.clone(),
+                    None => break,
+                },
+                _ => panic!(),
+            };
+            if let Some(index) = &stmt.index {
+                self.set_var(frame, &index.name, Value::Int(i as i32));
+            }
+            self.set_var(frame, name, element);
+            if let Some(value) = self.exec_stmts(frame, &stmt.body)? {
+                return Ok(Some(value));
+            }
+            i += 1;
+        }
+        Ok(None)
+    }
+
+    fn assign(&mut self, frame: &Rc<Frame>, target: &Expr, value: Value) -> Result<()> {
+        match &target.content {
+            ExprContent::Variable(v) => self.set_var(frame, &v.name, value),
+            ExprContent::MemberExpr(m) => match self.eval_expr(frame, &m.object)? {
+                Value::Object(o) => {
+                    o.attributes
+                        .borrow_mut()
+                        .insert(m.member.name.clone(), value);
+                }
+                _ => return Err(NONE_OP),
+            },
+            ExprContent::IndexExpr(e) => match self.eval_expr(frame, &e.list)? {
+                Value::List(l) => {
+                    let i = self.eval_expr(frame, &e.index)?;
+                    let mut l = l.borrow_mut();
+                    let i = index(l.len(), i)?;
+                    l[i] = value;
+                }
+                _ => return Err(NONE_OP),
+            },
+            _ => panic!(),
+        }
+        Ok(())
+    }
+
+    fn eval_bool(&mut self, frame: &Rc<Frame>, expr: &Expr) -> Result<bool> {
+        match self.eval_expr(frame, expr)? {
+            Value::Bool(b) => Ok(b),
+            _ => panic!(),
+        }
+    }
+
+    fn eval_int(&mut self, frame: &Rc<Frame>, expr: &Expr) -> Result<i32> {
+        match self.eval_expr(frame, expr)? {
+            Value::Int(i) => Ok(i),
+            _ => panic!(),
+        }
+    }
+
+    fn eval_args(&mut self, frame: &Rc<Frame>, args: &[Expr]) -> Result<Vec<Value>> {
+        args.iter().map(|arg| self.eval_expr(frame, arg)).collect()
+    }
+
+    fn eval_expr(&mut self, frame: &Rc<Frame>, expr: &Expr) -> Result<Value> {
+        Ok(match &expr.content {
+            ExprContent::IntegerLiteral(i) => Value::Int(i.value),
+            ExprContent::BooleanLiteral(b) => Value::Bool(b.value),
+            ExprContent::NoneLiteral(_) => Value::None,
+            ExprContent::StringLiteral(s) => Value::Str(s.value.as_bytes().into()),
+            ExprContent::Variable(v) => self.get_var(frame, &v.name),
+            ExprContent::BinaryExpr(e) => return self.eval_binary_expr(frame, e),
+            ExprContent::UnaryExpr(e) => match (&e.operator, self.eval_expr(frame, &e.operand)?) {
+                (UnaryOp::Negative, Value::Int(i)) => Value::Int(i.wrapping_neg()),
+                (UnaryOp::Not, Value::Bool(b)) => Value::Bool(!b),
+                _ => panic!(),
+            },
+            ExprContent::IfExpr(e) => {
+                if self.eval_bool(frame, &e.condition)? {
+                    self.eval_expr(frame, &e.then_expr)?
+                } else {
+                    self.eval_expr(frame, &e.else_expr)?
+                }
+            }
+            ExprContent::ListExpr(e) => {
+                Value::List(Rc::new(RefCell::new(self.eval_args(frame, &e.elements)?)))
+            }
+            ExprContent::IndexExpr(e) => {
+                let list = self.eval_expr(frame, &e.list)?;
+                if let Value::None = list {
+                    return Err(NONE_OP);
+                }
+                let i = self.eval_expr(frame, &e.index)?;
+                match list {
+                    Value::Str(s) => {
+                        let i = index(s.len(), i)?;
+                        Value::Str(s[i..i + 1].into())
+                    }
+                    Value::List(l) => {
+                        let l = l.borrow();
+                        l[index(l.len(), i)?].clone()
+                    }
+                    _ => panic!(),
+                }
+            }
+            ExprContent::MemberExpr(e) => {
+                // Class constant accessed through the class name
+                if let ExprContent::Variable(v) = &e.object.content {
+                    if self.owner(frame, &v.name).is_none() {
+                        return Ok(self.classes[&v.name].constants[&e.member.name].clone());
+                    }
+                }
+                match self.eval_expr(frame, &e.object)? {
+                    Value::Object(o) => {
+                        let name = &e.member.name;
+                        let attribute = o.attributes.borrow().get(name).cloned();
+                        attribute.unwrap_or_else(|| o.class.constants[name].clone())
+                    }
+                    _ => return Err(NONE_OP),
+                }
+            }
+            ExprContent::CallExpr(e) => {
+                let args = self.eval_args(frame, &e.args)?;
+                let name = &e.function.name;
+                if let Some(owner) = self.owner(frame, name) {
+                    let (parent, function) = match &owner.bindings.borrow()[name] {
+                        Binding::Func(f) => (owner.clone(), f.clone()),
+                        // Only top-level functions can be values
+                        Binding::Var(Value::Func(f)) => (self.globals.clone(), f.function.clone()),
+                        Binding::Var(Value::None) => return Err(NONE_OP),
+                        Binding::Var(_) => panic!(),
+                    };
+                    self.call(parent, &function, args)?
+                } else {
+                    self.call_builtin(name, args)?
+                }
+            }
+            ExprContent::MethodCallExpr(e) => {
+                let object = self.eval_expr(frame, &e.method.object)?;
+                if let Value::None = object {
+                    return Err(NONE_OP);
+                }
+                let args = self.eval_args(frame, &e.args)?;
+                self.call_method(object, &e.method.member.name, args)?
+            }
+        })
+    }
+
+    fn eval_binary_expr(&mut self, frame: &Rc<Frame>, expr: &BinaryExpr) -> Result<Value> {
+        match expr.operator {
+            // Gives an operand like Python, which is the same as ChocoPy's for bool operands
+            BinaryOp::And | BinaryOp::Or => {
+                let left = self.eval_expr(frame, &expr.left)?;
+                if truthy(&left) == (expr.operator == BinaryOp::Or) {
+                    return Ok(left);
+                }
+                return self.eval_expr(frame, &expr.right);
+            }
+            _ => (),
+        }
+
+        let left = self.eval_expr(frame, &expr.left)?;
+        let right = self.eval_expr(frame, &expr.right)?;
+        Ok(match (&expr.operator, left, right) {
+            (BinaryOp::Add, Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_add(b)),
+            (BinaryOp::Add, Value::Str(a), Value::Str(b)) => {
+                Value::Str([&a[..], &b[..]].concat().into())
+            }
+            (BinaryOp::Add, Value::List(a), Value::List(b)) => {
+                let list = a
+                    .borrow()
+                    .iter()
+                    .chain(b.borrow().iter())
+                    .cloned()
+                    .collect();
+                Value::List(Rc::new(RefCell::new(list)))
+            }
+            (BinaryOp::Add, _, _) => return Err(NONE_OP),
+            (BinaryOp::Sub, Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_sub(b)),
+            (BinaryOp::Mul, Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_mul(b)),
+            (BinaryOp::Div | BinaryOp::Mod, Value::Int(_), Value::Int(0)) => return Err(DIV_ZERO),
+            // Rounds towards negative infinity like Python
+            (BinaryOp::Div, Value::Int(a), Value::Int(b)) => {
+                let q = a.wrapping_div(b);
+                Value::Int(if a.wrapping_rem(b) != 0 && (a < 0) != (b < 0) {
+                    q - 1
+                } else {
+                    q
+                })
+            }
+            (BinaryOp::Mod, Value::Int(a), Value::Int(b)) => {
+                let r = a.wrapping_rem(b);
+                Value::Int(if r != 0 && (r < 0) != (b < 0) {
+                    r + b
+                } else {
+                    r
+                })
+            }
+            (BinaryOp::BitAnd, Value::Int(a), Value::Int(b)) => Value::Int(a & b),
+            (BinaryOp::BitOr, Value::Int(a), Value::Int(b)) => Value::Int(a | b),
+            (BinaryOp::BitXor, Value::Int(a), Value::Int(b)) => Value::Int(a ^ b),
+            (BinaryOp::Shl | BinaryOp::Shr, Value::Int(_), Value::Int(b)) if b < 0 => {
+                return Err(INVALID_ARG)
+            }
+            (BinaryOp::Shl, Value::Int(a), Value::Int(b)) => {
+                Value::Int(if b > 31 { 0 } else { a << b })
+            }
+            (BinaryOp::Shr, Value::Int(a), Value::Int(b)) => Value::Int(a >> b.min(31)),
+            (BinaryOp::Eq, Value::Int(a), Value::Int(b)) => Value::Bool(a == b),
+            (BinaryOp::Eq, Value::Bool(a), Value::Bool(b)) => Value::Bool(a == b),
+            (BinaryOp::Eq, Value::Str(a), Value::Str(b)) => Value::Bool(a == b),
+            (BinaryOp::Ne, Value::Int(a), Value::Int(b)) => Value::Bool(a != b),
+            (BinaryOp::Ne, Value::Bool(a), Value::Bool(b)) => Value::Bool(a != b),
+            (BinaryOp::Ne, Value::Str(a), Value::Str(b)) => Value::Bool(a != b),
+            (BinaryOp::Eq, a @ Value::Object(_), b) | (BinaryOp::Eq, a, b @ Value::Object(_)) => {
+                Value::Bool(obj_eq(&a, &b, 0))
+            }
+            (BinaryOp::Ne, a @ Value::Object(_), b) | (BinaryOp::Ne, a, b @ Value::Object(_)) => {
+                Value::Bool(!obj_eq(&a, &b, 0))
+            }
+            (BinaryOp::Eq, a, b) => Value::Bool(list_eq(&a, &b)),
+            (BinaryOp::Ne, a, b) => Value::Bool(!list_eq(&a, &b)),
+            (BinaryOp::Lt, Value::Int(a), Value::Int(b)) => Value::Bool(a < b),
+            (BinaryOp::Gt, Value::Int(a), Value::Int(b)) => Value::Bool(a > b),
+            (BinaryOp::Le, Value::Int(a), Value::Int(b)) => Value::Bool(a <= b),
+            (BinaryOp::Ge, Value::Int(a), Value::Int(b)) => Value::Bool(a >= b),
+            (BinaryOp::Is, Value::None, Value::None) => Value::Bool(true),
+            (BinaryOp::Is, Value::List(a), Value::List(b)) => Value::Bool(Rc::ptr_eq(&a, &b)),
+            (BinaryOp::Is, Value::Object(a), Value::Object(b)) => Value::Bool(Rc::ptr_eq(&a, &b)),
+            (BinaryOp::Is, Value::Func(a), Value::Func(b)) => Value::Bool(Rc::ptr_eq(&a, &b)),
+            (BinaryOp::Is, _, _) => Value::Bool(false),
+            (BinaryOp::In, Value::Str(a), Value::Str(b)) => {
+                Value::Bool(a.is_empty() || b.windows(a.len()).any(|w| *w == a[..]))
+            }
+            (BinaryOp::In, _, Value::None) => return Err(NONE_OP),
+            (BinaryOp::In, a, Value::List(l)) => {
+                let by_value =
+                    [&*TYPE_INT, &*TYPE_BOOL, &*TYPE_STR].contains(&expr.left.get_type());
+                Value::Bool(list_contains(&l.borrow(), &a, by_value))
+            }
+            _ => panic!(),
+        })
+    }
+}
+
+// Run a whole program with the input. Returns the output and the error that ended the program,
+// which the compiled program would report on stderr instead
+#[cfg(test)]
+pub fn run_program(ast: &Program, input: &str) -> (Vec<u8>, Option<RuntimeError>) {
+    let mut input = input.as_bytes();
+    let mut output = vec![];
+    let mut interpreter = Interpreter::new(&mut input, &mut output);
+    interpreter.declare(&ast.declarations);
+    let error = interpreter.exec(&ast.statements).err();
+    (output, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run a whole program, reporting runtime errors the same way as the standard library
+    fn run(ast: &Program, input: &str) -> String {
+        let (mut output, error) = run_program(ast, input);
+        if let Some(e) = error {
+            let _ = write!(output, "{}\nExited with error code {}\n", e.message, e.code);
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    // Input and expected output pairs embedded in a test program.
+    // Each case is a "#!" line, input lines, "#<->#", output lines and "#<->#"
+    fn embedded_cases(source: &str) -> Vec<(String, String)> {
+        fn section<'a>(lines: &mut impl Iterator<Item = &'a str>) -> String {
+            lines
+                .take_while(|line| *line != "#<->#")
+                .map(|line| line[1..].to_owned() + "\n")
+                .collect()
+        }
+        let mut lines = source.lines();
+        let mut cases = vec![];
+        while let Some(line) = lines.next() {
+            if line == "#!" {
+                cases.push((section(&mut lines), section(&mut lines)));
+            }
+        }
+        cases
+    }
+
+    fn sample() {
+        let mut passed = true;
+        for dir in &["test/original/pa3", "test/pa3"] {
+            let mut files = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|f| f.unwrap().path())
+                .filter(|f| f.extension().is_some_and(|e| e == "py"))
+                .collect::<Vec<_>>();
+            files.sort();
+
+            for source_path in files {
+                let source_str = source_path.to_str().unwrap();
+                let ast = crate::parse::process(source_str, Default::default()).unwrap();
+                let ast = crate::check::check(ast, Default::default());
+                assert!(ast.errors.errors.is_empty(), "{}", source_str);
+
+                let result_path = source_str.to_owned() + ".ast.typed.s.result";
+                let cases = if let Ok(expected) = std::fs::read_to_string(result_path) {
+                    vec![("".to_owned(), expected)]
+                } else {
+                    embedded_cases(&std::fs::read_to_string(&source_path).unwrap())
+                };
+                for (input, expected) in cases {
+                    if run(&ast, &input) != expected {
+                        println!("{} \x1b[31mError\x1b[0m", source_str);
+                        passed = false;
+                    }
+                }
+            }
+        }
+        assert!(passed);
+    }
+
+    #[test]
+    fn unicode_input() {
+        let source = std::fs::read_to_string("test/pa3/unicode_input.py").unwrap();
+        let ast = crate::parse::process_str(&source, Default::default());
+        let ast = crate::check::check(ast, Default::default());
+        // Indexing splits the character into bytes, as in compiled code
+        assert_eq!(
+            run(&ast, "a\u{e9}\u{20ac}\n"),
+            "a\u{e9}\u{20ac}\n6\n\u{fffd}\n\u{e9}\nTrue\n"
+        );
+    }
+
+    #[test]
+    fn bitwise() {
+        let ast = crate::parse::load_ast("test/ext/bitwise.py.ast.typed").unwrap();
+        assert_eq!(
+            run(&ast, ""),
+            "8\n15\n9\n249\n-7\n6\n1\n-2147483648\n0\n-56\n-7\n-4\n-1\n-1\n0\n24\nTrue\n3\n-3\n24\n\
+             28004\nInvalid argument\nExited with error code 1\n"
+        );
+    }
+
+    #[test]
+    fn list_eq() {
+        let ast = crate::parse::load_ast("test/ext/list_eq.py.ast.typed").unwrap();
+        assert_eq!(
+            run(&ast, ""),
+            "True\nFalse\nFalse\nTrue\nFalse\nTrue\nTrue\nTrue\nFalse\nFalse\nTrue\nFalse\nTrue\nTrue\nTrue\nTrue\n"
+        );
+    }
+
+    #[test]
+    fn list_append() {
+        let ast = crate::parse::load_ast("test/ext/list_append.py.ast.typed").unwrap();
+        assert_eq!(
+            run(&ast, ""),
+            "328350\n100\nTrue\nFalse\na\nab\nabb\nabbb\n16\n3\n2\n1\n3\n\
+             Operation on None\nExited with error code 4\n"
+        );
+    }
+
+    #[test]
+    fn truthy_and_or() {
+        let ast = crate::parse::load_ast("test/ext/truthy_and_or.py.ast.typed").unwrap();
+        assert_eq!(
+            run(&ast, ""),
+            "5\n3\n0\n5\nb\n7\nTrue\nempty\na\n\n2\n1\nTrue\n2\n2\n0\nFalse\nFalse\n1\n0\nevaluated\n2\n"
+        );
+    }
+
+    #[test]
+    fn enumerate() {
+        let ast = crate::parse::load_ast("test/ext/enumerate.py.ast.typed").unwrap();
+        assert_eq!(
+            run(&ast, ""),
+            "0\na\n1\nb\n2\nc\ntwo!\n20\n0\n1\n2\n2\n4\n5\n\
+             Operation on None\nExited with error code 4\n"
+        );
+    }
+
+    #[test]
+    fn func_values() {
+        let ast = crate::parse::load_ast("test/ext/func_values.py.ast.typed").unwrap();
+        assert_eq!(
+            run(&ast, ""),
+            "25\n-5\n1\n4\n9\nhi!!\n10\n20\n-9\n49\nTrue\nFalse\n\
+             Operation on None\nExited with error code 4\n"
+        );
+    }
+
+    #[test]
+    fn membership() {
+        let ast = crate::parse::load_ast("test/ext/membership.py.ast.typed").unwrap();
+        assert_eq!(
+            run(&ast, ""),
+            "True\nFalse\nTrue\nTrue\nFalse\nTrue\nFalse\nTrue\nTrue\nFalse\nFalse\n\
+             True\nTrue\nTrue\nTrue\nFalse\nFalse\nTrue\nFalse\nTrue\nTrue\nFalse\n2\n0\n0\n7\n\
+             Operation on None\nExited with error code 4\n"
+        );
+    }
+
+    #[test]
+    fn class_eq() {
+        let source = "\
+class Pair(object):
+    a:object = None
+    b:Pair = None
+class Pair2(Pair):
+    pass
+p:Pair = None
+q:Pair = None
+p = Pair()
+q = Pair()
+print(p == q)
+p.a = \"x\"
+q.a = 1
+print(p != q)
+q.a = \"x\"
+print(p == q)
+p.b = p
+q.b = q
+print(p == q)
+q.b = p
+print(p == q)
+q = Pair2()
+q.a = \"x\"
+q.b = p
+print(p == q)
+q = None
+print(p == q)
+p = None
+print(p == q)
+";
+        let ast = crate::parse::process_str(source, Default::default());
+        let options = crate::check::Options {
+            class_eq: true,
+            ..Default::default()
+        };
+        let ast = crate::check::check(ast, options);
+        assert!(ast.errors.errors.is_empty());
+        assert_eq!(
+            run(&ast, ""),
+            "True\nTrue\nTrue\nFalse\nTrue\nFalse\nFalse\nTrue\n"
+        );
+    }
+
+    #[test]
+    fn sample_programs() {
+        // Deeply recursive programs need more stack than the default for test threads
+        std::thread::Builder::new()
+            .stack_size(64_000_000)
+            .spawn(sample)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,670 @@
+// A typed three-address intermediate representation lowered from the typed AST.
+//
+// Each function is a list of basic blocks, each ending with a jump, a branch or a return.
+// Instructions compute temporaries of a known type from operands, which are temporaries or
+// constants, and store them to variables, attributes and list elements. Variables are referred
+// to by their names in the source. Temporaries are assigned once, except for the results of
+// `and`, `or` and conditional expressions, and for loop counters, which are assigned in more
+// than one block.
+//
+// Code generation still works from the AST. For now, the IR is only printed with --print-ir.
+
+use crate::node::*;
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+
+pub type Temp = usize;
+pub type BlockId = usize;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Temp(Temp),
+    Int(i32),
+    Bool(bool),
+    Str(String),
+    None,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Place {
+    Var(String),
+    Attribute(Operand, String),
+    Element(Operand, Operand),
+}
+
+// The computation of a temporary
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Copy(Operand),
+    Load(Place),
+    Unary(UnaryOp, Operand),
+    Binary(BinaryOp, Operand, Operand),
+    // Functions, constructors and built-in functions, by name
+    Call(String, Vec<Operand>),
+    CallMethod(Operand, String, Vec<Operand>),
+    List(Vec<Operand>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inst {
+    Compute(Temp, Value),
+    Store(Place, Operand),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    // Goes to the first block if the operand is truthy, and to the second one otherwise
+    Branch(Operand, BlockId, BlockId),
+    Return(Operand),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String, // Qualified with the enclosing functions and classes, as in `A.f.g`
+    pub params: Vec<(String, ValueType)>,
+    pub return_type: ValueType,
+    pub temps: Vec<ValueType>, // Type of each temporary
+    pub blocks: Vec<Block>,    // Starting with the entry block
+}
+
+// Name of the function made of the top level statements
+pub const MAIN: &str = "<main>";
+
+struct Lowering {
+    temps: Vec<ValueType>,
+    blocks: Vec<Block>,
+    current: BlockId,
+    insts: Vec<Inst>,
+    scopes: Vec<HashSet<String>>, // Names declared in the enclosing scopes
+}
+
+impl Lowering {
+    fn new(scopes: Vec<HashSet<String>>) -> Lowering {
+        Lowering {
+            temps: vec![],
+            blocks: vec![Block {
+                insts: vec![],
+                terminator: Terminator::Return(Operand::None),
+            }],
+            current: 0,
+            insts: vec![],
+            scopes,
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    fn temp(&mut self, value_type: &ValueType) -> Temp {
+        self.temps.push(value_type.clone());
+        self.temps.len() - 1
+    }
+
+    fn compute(&mut self, value_type: &ValueType, value: Value) -> Operand {
+        let temp = self.temp(value_type);
+        self.insts.push(Inst::Compute(temp, value));
+        Operand::Temp(temp)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Block {
+            insts: vec![],
+            terminator: Terminator::Return(Operand::None),
+        });
+        self.blocks.len() - 1
+    }
+
+    // End the current block, and continue in the given one
+    fn finish_block(&mut self, terminator: Terminator, next: BlockId) {
+        let block = &mut self.blocks[self.current];
+        block.insts = std::mem::take(&mut self.insts);
+        block.terminator = terminator;
+        self.current = next;
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Vec<Operand> {
+        exprs.iter().map(|expr| self.expr(expr)).collect()
+    }
+
+    // Branch on the condition, evaluate `then` or `otherwise` into a temporary and join
+    fn select(
+        &mut self,
+        value_type: &ValueType,
+        condition: Operand,
+        then: impl FnOnce(&mut Self) -> Operand,
+        otherwise: impl FnOnce(&mut Self) -> Operand,
+    ) -> Operand {
+        let result = self.temp(value_type);
+        let (then_block, else_block, join) = (self.new_block(), self.new_block(), self.new_block());
+        self.finish_block(
+            Terminator::Branch(condition, then_block, else_block),
+            then_block,
+        );
+        let value = then(self);
+        self.insts.push(Inst::Compute(result, Value::Copy(value)));
+        self.finish_block(Terminator::Jump(join), else_block);
+        let value = otherwise(self);
+        self.insts.push(Inst::Compute(result, Value::Copy(value)));
+        self.finish_block(Terminator::Jump(join), join);
+        Operand::Temp(result)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Operand {
+        let value_type = expr.get_type();
+        match &expr.content {
+            ExprContent::IntegerLiteral(i) => Operand::Int(i.value),
+            ExprContent::BooleanLiteral(b) => Operand::Bool(b.value),
+            ExprContent::StringLiteral(s) => Operand::Str(s.value.clone()),
+            ExprContent::NoneLiteral(_) => Operand::None,
+            ExprContent::Variable(v) => {
+                self.compute(value_type, Value::Load(Place::Var(v.name.clone())))
+            }
+            ExprContent::BinaryExpr(e) => {
+                let left = self.expr(&e.left);
+                match e.operator {
+                    // The right operand is only evaluated if the left one doesn't decide
+                    BinaryOp::And => self.select(
+                        value_type,
+                        left.clone(),
+                        |this| this.expr(&e.right),
+                        |_| left,
+                    ),
+                    BinaryOp::Or => self.select(
+                        value_type,
+                        left.clone(),
+                        |_| left,
+                        |this| this.expr(&e.right),
+                    ),
+                    _ => {
+                        let right = self.expr(&e.right);
+                        let value = Value::Binary(e.operator.clone(), left, right);
+                        self.compute(value_type, value)
+                    }
+                }
+            }
+            ExprContent::UnaryExpr(e) => {
+                let operand = self.expr(&e.operand);
+                self.compute(value_type, Value::Unary(e.operator.clone(), operand))
+            }
+            ExprContent::IfExpr(e) => {
+                let condition = self.expr(&e.condition);
+                self.select(
+                    value_type,
+                    condition,
+                    |this| this.expr(&e.then_expr),
+                    |this| this.expr(&e.else_expr),
+                )
+            }
+            ExprContent::CallExpr(e) => {
+                let args = self.exprs(&e.args);
+                self.compute(value_type, Value::Call(e.function.name.clone(), args))
+            }
+            ExprContent::MethodCallExpr(e) => {
+                let object = self.expr(&e.method.object);
+                let args = self.exprs(&e.args);
+                let name = e.method.member.name.clone();
+                self.compute(value_type, Value::CallMethod(object, name, args))
+            }
+            ExprContent::MemberExpr(e) => {
+                let object = self.expr(&e.object);
+                let place = Place::Attribute(object, e.member.name.clone());
+                self.compute(value_type, Value::Load(place))
+            }
+            ExprContent::IndexExpr(e) => {
+                let list = self.expr(&e.list);
+                let index = self.expr(&e.index);
+                self.compute(value_type, Value::Load(Place::Element(list, index)))
+            }
+            ExprContent::ListExpr(e) => {
+                let elements = self.exprs(&e.elements);
+                self.compute(value_type, Value::List(elements))
+            }
+        }
+    }
+
+    // The place that a target of an assignment refers to
+    fn target(&mut self, target: &Expr) -> Place {
+        match &target.content {
+            ExprContent::Variable(v) => Place::Var(v.name.clone()),
+            ExprContent::MemberExpr(e) => {
+                let object = self.expr(&e.object);
+                Place::Attribute(object, e.member.name.clone())
+            }
+            ExprContent::IndexExpr(e) => {
+                let list = self.expr(&e.list);
+                let index = self.expr(&e.index);
+                Place::Element(list, index)
+            }
+            _ => panic!("Invalid assignment target"),
+        }
+    }
+
+    // A loop counting a temporary from `start` up to before `bound`, which is evaluated for
+    // each iteration. `body` is lowered with the counter
+    fn counted_loop(
+        &mut self,
+        start: Operand,
+        bound: impl Fn(&mut Self) -> Operand,
+        body: impl FnOnce(&mut Self, Operand),
+    ) {
+        let counter = self.temp(&TYPE_INT);
+        self.insts.push(Inst::Compute(counter, Value::Copy(start)));
+        let (check, body_block, exit) = (self.new_block(), self.new_block(), self.new_block());
+        self.finish_block(Terminator::Jump(check), check);
+        let bound = bound(self);
+        let condition = Value::Binary(BinaryOp::Lt, Operand::Temp(counter), bound);
+        let condition = self.compute(&TYPE_BOOL, condition);
+        self.finish_block(Terminator::Branch(condition, body_block, exit), body_block);
+        body(self, Operand::Temp(counter));
+        let next = Value::Binary(BinaryOp::Add, Operand::Temp(counter), Operand::Int(1));
+        self.insts.push(Inst::Compute(counter, next));
+        self.finish_block(Terminator::Jump(check), exit);
+    }
+
+    fn for_stmt(&mut self, stmt: &ForStmt) {
+        let name = stmt.identifier.name.clone();
+        if let Some(args) = stmt.range_args(|name| self.is_defined(name)) {
+            let start = if args.len() == 2 {
+                self.expr(&args[0])
+            } else {
+                Operand::Int(0)
+            };
+            let bound = self.expr(args.last().unwrap());
+            self.counted_loop(
+                start,
+                |_| bound.clone(),
+                |this, counter| {
+                    this.insts.push(Inst::Store(Place::Var(name), counter));
+                    this.stmts(&stmt.body);
+                },
+            );
+            return;
+        }
+
+        let iterable = stmt.enumerate_arg().unwrap_or(&stmt.iterable);
+        let element_type = match iterable.get_type() {
+            ValueType::ListValueType(list) => (*list.element_type).clone(),
+            _ => TYPE_STR.clone(),
+        };
+        let iterable = self.expr(iterable);
+        let len = |this: &mut Self| {
+            let len = Value::Call("len".to_owned(), vec![iterable.clone()]);
+            this.compute(&TYPE_INT, len)
+        };
+        self.counted_loop(Operand::Int(0), len, |this, counter| {
+            let element = Value::Load(Place::Element(iterable.clone(), counter.clone()));
+            let element = this.compute(&element_type, element);
+            if let Some(index) = &stmt.index {
+                let place = Place::Var(index.name.clone());
+                this.insts.push(Inst::Store(place, counter));
+            }
+            this.insts.push(Inst::Store(Place::Var(name), element));
+            this.stmts(&stmt.body);
+        });
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::ExprStmt(s) => {
+                    self.expr(&s.expr);
+                }
+                Stmt::AssignStmt(s) => {
+                    let value = self.expr(&s.value);
+                    for target in &s.targets {
+                        let place = self.target(target);
+                        self.insts.push(Inst::Store(place, value.clone()));
+                    }
+                }
+                Stmt::IfStmt(s) => {
+                    let condition = self.expr(&s.condition);
+                    let (then_block, else_block, join) =
+                        (self.new_block(), self.new_block(), self.new_block());
+                    self.finish_block(
+                        Terminator::Branch(condition, then_block, else_block),
+                        then_block,
+                    );
+                    self.stmts(&s.then_body);
+                    self.finish_block(Terminator::Jump(join), else_block);
+                    self.stmts(&s.else_body);
+                    self.finish_block(Terminator::Jump(join), join);
+                }
+                Stmt::WhileStmt(s) => {
+                    let (check, body, exit) =
+                        (self.new_block(), self.new_block(), self.new_block());
+                    self.finish_block(Terminator::Jump(check), check);
+                    let condition = self.expr(&s.condition);
+                    self.finish_block(Terminator::Branch(condition, body, exit), body);
+                    self.stmts(&s.body);
+                    self.finish_block(Terminator::Jump(check), exit);
+                }
+                Stmt::ForStmt(s) => self.for_stmt(s),
+                Stmt::ReturnStmt(s) => {
+                    let value = s.value.as_ref().map_or(Operand::None, |v| self.expr(v));
+                    // Statements after the return go to a block that is removed later
+                    let unreachable = self.new_block();
+                    self.finish_block(Terminator::Return(value), unreachable);
+                }
+            }
+        }
+    }
+
+    // Initial values of the variables declared in the function
+    fn var_defs(&mut self, declarations: &[Declaration]) {
+        for declaration in declarations {
+            if let Declaration::VarDef(v) = declaration {
+                let value = match &v.value.content {
+                    LiteralContent::IntegerLiteral(i) => Operand::Int(i.value),
+                    LiteralContent::BooleanLiteral(b) => Operand::Bool(b.value),
+                    LiteralContent::StringLiteral(s) => Operand::Str(s.value.clone()),
+                    LiteralContent::NoneLiteral(_) => Operand::None,
+                };
+                let place = Place::Var(v.var.identifier.name.clone());
+                self.insts.push(Inst::Store(place, value));
+            }
+        }
+    }
+
+    // Remove blocks that can't be reached from the entry block, and renumber the others
+    fn finish(mut self) -> (Vec<ValueType>, Vec<Block>) {
+        let current = self.current;
+        self.finish_block(Terminator::Return(Operand::None), current);
+
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut pending = vec![0];
+        while let Some(id) = pending.pop() {
+            if std::mem::replace(&mut reachable[id], true) {
+                continue;
+            }
+            match &self.blocks[id].terminator {
+                Terminator::Jump(next) => pending.push(*next),
+                Terminator::Branch(_, then, otherwise) => pending.extend([*then, *otherwise]),
+                Terminator::Return(_) => (),
+            }
+        }
+        let mut new_ids = vec![0; self.blocks.len()];
+        let mut count = 0;
+        for (id, new_id) in new_ids.iter_mut().enumerate() {
+            *new_id = count;
+            count += reachable[id] as usize;
+        }
+
+        let blocks = self
+            .blocks
+            .into_iter()
+            .zip(reachable)
+            .filter(|(_, reachable)| *reachable)
+            .map(|(mut block, _)| {
+                match &mut block.terminator {
+                    Terminator::Jump(next) => *next = new_ids[*next],
+                    Terminator::Branch(_, then, otherwise) => {
+                        *then = new_ids[*then];
+                        *otherwise = new_ids[*otherwise];
+                    }
+                    Terminator::Return(_) => (),
+                }
+                block
+            })
+            .collect();
+        (self.temps, blocks)
+    }
+}
+
+fn declared_names(declarations: &[Declaration]) -> HashSet<String> {
+    declarations
+        .iter()
+        .map(|declaration| declaration.name().name.clone())
+        .collect()
+}
+
+fn lower_func(
+    f: &FuncDef,
+    prefix: &str,
+    scopes: &[HashSet<String>],
+    functions: &mut Vec<Function>,
+) {
+    let name = format!("{}{}", prefix, f.name.name);
+    let mut scope = declared_names(&f.declarations);
+    scope.extend(f.params.iter().map(|p| p.identifier.name.clone()));
+    let mut scopes = scopes.to_vec();
+    scopes.push(scope);
+
+    let mut lowering = Lowering::new(scopes.clone());
+    lowering.var_defs(&f.declarations);
+    lowering.stmts(&f.statements);
+    let (temps, blocks) = lowering.finish();
+    functions.push(Function {
+        name: name.clone(),
+        params: f
+            .params
+            .iter()
+            .map(|p| {
+                let param_type = ValueType::from_annotation(&p.type_);
+                (p.identifier.name.clone(), param_type)
+            })
+            .collect(),
+        return_type: ValueType::from_annotation(&f.return_type),
+        temps,
+        blocks,
+    });
+
+    for declaration in &f.declarations {
+        if let Declaration::FuncDef(inner) = declaration {
+            lower_func(inner, &(name.clone() + "."), &scopes, functions);
+        }
+    }
+}
+
+// Lower the top level statements, then every function and method
+pub fn lower(ast: &Program) -> Vec<Function> {
+    let scopes = vec![declared_names(&ast.declarations)];
+    let mut lowering = Lowering::new(scopes.clone());
+    lowering.var_defs(&ast.declarations);
+    lowering.stmts(&ast.statements);
+    let (temps, blocks) = lowering.finish();
+    let mut functions = vec![Function {
+        name: MAIN.to_owned(),
+        params: vec![],
+        return_type: TYPE_NONE.clone(),
+        temps,
+        blocks,
+    }];
+
+    for declaration in &ast.declarations {
+        match declaration {
+            Declaration::FuncDef(f) => lower_func(f, "", &scopes, &mut functions),
+            // Methods don't see the names in the class body
+            Declaration::ClassDef(c) => {
+                for declaration in &c.declarations {
+                    if let Declaration::FuncDef(f) = declaration {
+                        let prefix = c.name.name.clone() + ".";
+                        lower_func(f, &prefix, &scopes, &mut functions);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    functions
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Temp(t) => write!(f, "%{}", t),
+            Operand::Int(i) => write!(f, "{}", i),
+            Operand::Bool(true) => write!(f, "True"),
+            Operand::Bool(false) => write!(f, "False"),
+            Operand::Str(s) => write!(f, "{:?}", s),
+            Operand::None => write!(f, "None"),
+        }
+    }
+}
+
+impl Display for Place {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Place::Var(name) => write!(f, "{}", name),
+            Place::Attribute(object, name) => write!(f, "{}.{}", object, name),
+            Place::Element(list, index) => write!(f, "{}[{}]", list, index),
+        }
+    }
+}
+
+fn write_operands(f: &mut Formatter<'_>, operands: &[Operand]) -> fmt::Result {
+    for (i, operand) in operands.iter().enumerate() {
+        if i != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", operand)?;
+    }
+    Ok(())
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Copy(operand) => write!(f, "{}", operand),
+            Value::Load(place) => write!(f, "{}", place),
+            Value::Unary(UnaryOp::Negative, operand) => write!(f, "-{}", operand),
+            Value::Unary(UnaryOp::Not, operand) => write!(f, "not {}", operand),
+            Value::Binary(op, left, right) => write!(f, "{} {} {}", left, op.symbol(), right),
+            Value::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                write_operands(f, args)?;
+                write!(f, ")")
+            }
+            Value::CallMethod(object, name, args) => {
+                write!(f, "{}.{}(", object, name)?;
+                write_operands(f, args)?;
+                write!(f, ")")
+            }
+            Value::List(elements) => {
+                write!(f, "[")?;
+                write_operands(f, elements)?;
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "def {}(", self.name)?;
+        for (i, (name, param_type)) in self.params.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", name, param_type)?;
+        }
+        writeln!(f, ") -> {}:", self.return_type)?;
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "  b{}:", id)?;
+            for inst in &block.insts {
+                match inst {
+                    Inst::Compute(temp, value) => {
+                        writeln!(f, "    %{}: {} = {}", temp, self.temps[*temp], value)?
+                    }
+                    Inst::Store(place, operand) => writeln!(f, "    {} = {}", place, operand)?,
+                }
+            }
+            match &block.terminator {
+                Terminator::Jump(next) => writeln!(f, "    jump b{}", next)?,
+                Terminator::Branch(condition, then, otherwise) => {
+                    writeln!(f, "    branch {} b{} b{}", condition, then, otherwise)?
+                }
+                Terminator::Return(value) => writeln!(f, "    return {}", value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print_ir(source: &str) -> String {
+        let ast = crate::check::check(
+            crate::parse::process_str(source, Default::default()),
+            Default::default(),
+        );
+        assert!(ast.errors.errors.is_empty());
+        lower(&ast).iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn function() {
+        let source = "\
+def count(xs: [int], limit: int) -> int:
+    n: int = 0
+    x: int = 0
+    for x in xs:
+        if x < limit and x > 0:
+            n = n + 1
+    return n
+    print(n)
+
+print(count([1, 5], 3))
+";
+        // The loop reads the length on each iteration, and the print after return is dropped
+        let expected = "\
+def <main>() -> <None>:
+  b0:
+    %0: [int] = [1, 5]
+    %1: int = count(%0, 3)
+    %2: <None> = print(%1)
+    return None
+def count(xs: [int], limit: int) -> int:
+  b0:
+    n = 0
+    x = 0
+    %0: [int] = xs
+    %1: int = 0
+    jump b1
+  b1:
+    %2: int = len(%0)
+    %3: bool = %1 < %2
+    branch %3 b2 b3
+  b2:
+    %4: int = %0[%1]
+    x = %4
+    %5: int = x
+    %6: int = limit
+    %7: bool = %5 < %6
+    branch %7 b4 b5
+  b3:
+    %13: int = n
+    return %13
+  b4:
+    %9: int = x
+    %10: bool = %9 > 0
+    %8: bool = %10
+    jump b6
+  b5:
+    %8: bool = %7
+    jump b6
+  b6:
+    branch %8 b7 b8
+  b7:
+    %11: int = n
+    %12: int = %11 + 1
+    n = %12
+    jump b9
+  b8:
+    jump b9
+  b9:
+    %1: int = %1 + 1
+    jump b1
+";
+        assert_eq!(print_ir(source), expected);
+    }
+}
@@ -34,7 +34,7 @@ impl<F, V> LocalEnv<F, V> {
         LocalEnv(vec![base])
     }
 
-    pub fn get(&self, name: &str) -> Option<EnvSlot<F, V>> {
+    pub fn get(&self, name: &str) -> Option<EnvSlot<'_, F, V>> {
         match self.0.last().unwrap().get(name) {
             Some(LocalSlot::Var(t)) => Some(EnvSlot::Var(t, Assignable(true))),
             Some(LocalSlot::Func(t)) => Some(EnvSlot::Func(t)),
@@ -73,7 +73,36 @@ impl<F, V> LocalEnv<F, V> {
         }
     }
 
-    pub fn push(&mut self, frame: HashMap<String, LocalSlot<F, V>>) -> FrameHandle<F, V> {
+    // Look up a name in the global frame only
+    pub fn get_global(&self, name: &str) -> Option<EnvSlot<'_, F, V>> {
+        match self.0[0].get(name) {
+            Some(LocalSlot::Var(t)) => Some(EnvSlot::Var(t, Assignable(true))),
+            Some(LocalSlot::Func(t)) => Some(EnvSlot::Func(t)),
+            _ => None,
+        }
+    }
+
+    // Names declared in any frame. Resolve them with `get` to find which ones are visible.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.0.iter().flat_map(|frame| frame.keys())
+    }
+
+    // Declare a name in the innermost frame, returning what it replaced there
+    pub fn insert(&mut self, name: String, slot: LocalSlot<F, V>) -> Option<LocalSlot<F, V>> {
+        self.0.last_mut().unwrap().insert(name, slot)
+    }
+
+    // Undo `insert`, putting back what the name was replaced with
+    pub fn restore(&mut self, name: &str, old: Option<LocalSlot<F, V>>) {
+        let frame = self.0.last_mut().unwrap();
+        if let Some(old) = old {
+            frame.insert(name.to_owned(), old);
+        } else {
+            frame.remove(name);
+        }
+    }
+
+    pub fn push(&mut self, frame: HashMap<String, LocalSlot<F, V>>) -> FrameHandle<'_, F, V> {
         self.0.push(frame);
         FrameHandle(self)
     }
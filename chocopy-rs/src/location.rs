@@ -21,6 +21,16 @@ impl Location {
             end: Position { row: er, col: ec },
         }
     }
+
+    // Whether the position falls in the range, where the end is inclusive
+    pub fn contains(&self, position: Position) -> bool {
+        self.start <= position && position <= self.end
+    }
+
+    // Whether the range covers no column, like the dedent before the first column of a line
+    pub fn is_empty(&self) -> bool {
+        self.start.col == 0 || self.end < self.start
+    }
 }
 
 impl From<Location> for [u32; 4] {
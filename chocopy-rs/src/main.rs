@@ -1,16 +1,30 @@
+mod build;
+mod cache;
 mod check;
+mod complete;
+mod conformance;
+mod doc;
+mod explain;
+#[cfg(test)]
+mod fuzz;
 mod gen;
+mod interp;
+mod ir;
 mod local_env;
 mod location;
 mod node;
 mod parse;
+mod repl;
+#[cfg(test)]
+mod snapshot;
 
-use gen::Platform;
+use gen::{ObjectFormat, Platform, RuntimeChecks};
 use getopts::Options;
 use location::*;
 use node::*;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
 
 #[cfg(target_os = "windows")]
 const PLATFORM: Platform = Platform::Windows;
@@ -26,40 +40,91 @@ fn print_usage(program: &str, opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
-fn check_error(file: &str, ast: &Program) -> bool {
-    let errors = &ast.errors.errors;
-    if errors.is_empty() {
-        true
-    } else {
-        let file = File::open(file).unwrap();
-        let mut lines = BufReader::new(file)
-            .lines()
-            .take_while(|l| l.is_ok())
-            .map(|l| l.unwrap());
-        let mut current_row = 1;
-        let mut line = lines.next();
-        for error in errors {
-            let Location { start, .. } = error.base.location;
-            let row = start.row;
-            if row > current_row {
-                for _ in 0..row - current_row - 1 {
-                    lines.next();
-                }
-                line = lines.next().map(|s| s.replace('\t', " "));
-                current_row = row;
-            }
-            eprintln!("{}, {}: {}", start.row, start.col, error.message);
-            if let Some(line) = &line {
-                eprintln!("    | {}", line);
-                eprint!("    | ");
-                for _ in 0..std::cmp::max(start.col as i64 - 1, 0) {
-                    eprint!(" ");
-                }
-                eprintln!("^");
-            }
+// Longest source line shown under a message. Longer lines are cut around the error.
+const MAX_CONTEXT_WIDTH: usize = 200;
+
+// Cuts a long line to the part around columns `start..=end`, marking cut ends with "...".
+// Returns the shown text and the offset of the start column in it.
+fn context_excerpt(line: &str, start: usize, end: usize) -> (String, usize) {
+    let length = line.chars().count();
+    let start = start.saturating_sub(1);
+    if length <= MAX_CONTEXT_WIDTH {
+        return (line.to_owned(), start);
+    }
+    let center = (start + end.saturating_sub(1).max(start)) / 2;
+    let first = center
+        .saturating_sub(MAX_CONTEXT_WIDTH / 2)
+        .min(length - MAX_CONTEXT_WIDTH)
+        .min(start);
+    let mut excerpt = String::new();
+    if first > 0 {
+        excerpt += "...";
+    }
+    let caret = start - first + excerpt.len();
+    excerpt.extend(line.chars().skip(first).take(MAX_CONTEXT_WIDTH));
+    if first + MAX_CONTEXT_WIDTH < length {
+        excerpt += "...";
+    }
+    (excerpt, caret)
+}
+
+// Writes messages followed by their source line and a caret under the column.
+// Messages must be sorted by row for the source lines to be found.
+fn write_messages(
+    out: &mut dyn Write,
+    mut lines: impl Iterator<Item = String>,
+    messages: &[CompilerError],
+    prefix: &str,
+) -> std::io::Result<()> {
+    let mut current_row = 0;
+    let mut line = None;
+    for message in messages {
+        let Location { start, end } = message.base.location;
+        while current_row < start.row {
+            line = lines.next().map(|s| s.replace('\t', " "));
+            current_row += 1;
+        }
+        writeln!(
+            out,
+            "{}, {}: {}{}",
+            start.row,
+            start.col,
+            prefix,
+            message.display_message()
+        )?;
+        if let (Some(line), true) = (&line, start.row == current_row) {
+            // Spans over several lines are centered on the rest of the first line
+            let end_col = if end.row == start.row {
+                end.col as usize
+            } else {
+                line.len()
+            };
+            let (excerpt, caret) = context_excerpt(line, start.col as usize, end_col);
+            writeln!(out, "    | {}", excerpt)?;
+            writeln!(out, "    | {}^", " ".repeat(caret))?;
         }
-        false
     }
+    Ok(())
+}
+
+fn print_messages(file: &str, messages: &[CompilerError], prefix: &str) {
+    // Source lines are only shown if the source file is available
+    let lines = File::open(file)
+        .map(|file| BufReader::new(file).lines())
+        .into_iter()
+        .flatten()
+        .take_while(|l| l.is_ok())
+        .map(|l| l.unwrap());
+    let _ = write_messages(&mut std::io::stderr(), lines, messages, prefix);
+}
+
+// Prints errors and warnings. Returns false if compilation should stop
+fn check_error(file: &str, ast: &Program, werror: bool) -> bool {
+    let errors = &ast.errors.errors;
+    let warnings = &ast.errors.warnings;
+    print_messages(file, errors, "");
+    print_messages(file, warnings, if werror { "error: " } else { "warning: " });
+    errors.is_empty() && (!werror || warnings.is_empty())
 }
 
 #[derive(Debug)]
@@ -84,6 +149,195 @@ impl std::fmt::Display for CodeError {
 
 impl std::error::Error for CodeError {}
 
+#[derive(Debug)]
+struct ConformanceError;
+
+impl std::fmt::Display for ConformanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Typed AST differs from the reference")
+    }
+}
+
+impl std::error::Error for ConformanceError {}
+
+fn parse_platform(matches: &getopts::Matches) -> Result<Platform, ArgumentError> {
+    Ok(matches
+        .opt_str("platform")
+        .map(|p| match p.as_str() {
+            "windows" => Ok(Platform::Windows),
+            "linux" => Ok(Platform::Linux),
+            "macos" => Ok(Platform::Macos),
+            "wasm" => Ok(Platform::Wasm),
+            _ => {
+                eprintln!("Unknown platform `{}`", p);
+                Err(ArgumentError)
+            }
+        })
+        .transpose()?
+        .unwrap_or(PLATFORM))
+}
+
+// Output path and platform of a compilation. Fails if the output cannot be produced
+fn output_target(matches: &getopts::Matches) -> Result<(&str, Platform), ArgumentError> {
+    let output = if let Some(output) = matches.free.get(1) {
+        output
+    } else {
+        eprintln!("Please specifiy output path");
+        return Err(ArgumentError);
+    };
+
+    let platform = parse_platform(matches)?;
+
+    // WebAssembly modules need no linking
+    if platform != PLATFORM && platform != Platform::Wasm && !matches.opt_present("o") {
+        eprintln!("Cross-platform linking is unsupported. Please use --obj option.");
+        return Err(ArgumentError);
+    }
+
+    if platform == Platform::Wasm && matches.opt_present("export-classes") {
+        eprintln!("WebAssembly modules have no entry points for classes");
+        return Err(ArgumentError);
+    }
+
+    if platform == Platform::Wasm && matches.opt_present("interruptible") {
+        eprintln!("WebAssembly modules can only be interrupted by their host");
+        return Err(ArgumentError);
+    }
+
+    if platform == Platform::Wasm && matches.opt_present("runtime-checks") {
+        eprintln!("WebAssembly modules always keep their runtime checks");
+        return Err(ArgumentError);
+    }
+
+    if platform == Platform::Wasm && matches.opt_present("group-prototypes") {
+        eprintln!("WebAssembly modules have no sections to place prototypes in");
+        return Err(ArgumentError);
+    }
+
+    if platform == Platform::Wasm && matches.opt_present("reorder-functions") {
+        eprintln!("WebAssembly modules have no text section to lay out");
+        return Err(ArgumentError);
+    }
+
+    if let Some(format) = parse_object_format(matches)? {
+        if platform == Platform::Wasm {
+            eprintln!("WebAssembly modules have no object format to choose");
+            return Err(ArgumentError);
+        }
+        if format != ObjectFormat::native(platform) && !matches.opt_present("o") {
+            eprintln!("The linker only accepts the native object format. Please use --obj option.");
+            return Err(ArgumentError);
+        }
+    }
+
+    Ok((output, platform))
+}
+
+fn parse_object_format(matches: &getopts::Matches) -> Result<Option<ObjectFormat>, ArgumentError> {
+    matches
+        .opt_str("object-format")
+        .map(|f| match f.as_str() {
+            "elf" => Ok(ObjectFormat::Elf),
+            "coff" => Ok(ObjectFormat::Coff),
+            "macho" => Ok(ObjectFormat::MachO),
+            _ => {
+                eprintln!("Unknown object format `{}`", f);
+                Err(ArgumentError)
+            }
+        })
+        .transpose()
+}
+
+// Path of the separate file for debug info. Fails if it can't be split from the output
+fn split_debug(
+    matches: &getopts::Matches,
+    platform: Platform,
+) -> Result<Option<String>, ArgumentError> {
+    let path = if let Some(path) = matches.opt_str("split-debug") {
+        path
+    } else {
+        return Ok(None);
+    };
+    let message = if platform == Platform::Wasm {
+        "WebAssembly modules have no debug info to split"
+    } else if matches.opt_present("no-debug") {
+        "--no-debug leaves out the debug info to split"
+    } else if matches.opt_present("emit-staticlib") {
+        "Debug info of static libraries can't be split"
+    } else if platform == Platform::Windows {
+        if !matches.opt_present("o") {
+            return Ok(Some(path));
+        }
+        "On Windows, the linker splits debug info into the PDB. Please link without --obj option."
+    } else if parse_object_format(matches)?.unwrap_or_else(|| ObjectFormat::native(platform))
+        != ObjectFormat::Elf
+    {
+        "Debug info can only be split from ELF files"
+    } else {
+        return Ok(Some(path));
+    };
+    eprintln!("{}", message);
+    Err(ArgumentError)
+}
+
+fn parse_position(cursor: &str) -> Result<Position, ArgumentError> {
+    let position = cursor.split_once(':').and_then(|(row, col)| {
+        Some(Position {
+            row: row.parse().ok()?,
+            col: col.parse().ok()?,
+        })
+    });
+    position.ok_or_else(|| {
+        eprintln!("Cursor position should be ROW:COL, got `{}`", cursor);
+        ArgumentError
+    })
+}
+
+fn check_options(matches: &getopts::Matches) -> check::Options {
+    check::Options {
+        list_eq: matches.opt_present("ext-list-eq"),
+        list_append: matches.opt_present("ext-list-append"),
+        truthy_and_or: matches.opt_present("ext-truthy-and-or"),
+        enumerate: matches.opt_present("ext-enumerate"),
+        class_eq: matches.opt_present("ext-class-eq"),
+        func_values: matches.opt_present("ext-func-values"),
+        membership: matches.opt_present("ext-membership"),
+    }
+}
+
+fn compile_options(matches: &getopts::Matches) -> Result<gen::CompileOptions, ArgumentError> {
+    let opt_level = match matches.opt_str("O").as_deref() {
+        None | Some("0") => 0,
+        Some("2") => 2,
+        Some(level) => {
+            eprintln!("Unknown optimization level `{}`", level);
+            return Err(ArgumentError);
+        }
+    };
+    let runtime_checks = match matches.opt_str("runtime-checks").as_deref() {
+        None | Some("all") => RuntimeChecks::All,
+        Some("bounds") => RuntimeChecks::Bounds,
+        Some("off") => RuntimeChecks::Off,
+        Some(level) => {
+            eprintln!("Unknown runtime check level `{}`", level);
+            return Err(ArgumentError);
+        }
+    };
+    Ok(gen::CompileOptions {
+        trace_exec: matches.opt_present("trace-exec"),
+        opt_level,
+        strip: matches.opt_present("strip"),
+        object_format: parse_object_format(matches)?,
+        no_debug: matches.opt_present("no-debug"),
+        export_classes: matches.opt_present("export-classes"),
+        interruptible: matches.opt_present("interruptible"),
+        reorder_functions: matches.opt_present("reorder-functions"),
+        runtime_checks,
+        group_prototypes: matches.opt_present("group-prototypes"),
+        class_eq: matches.opt_present("ext-class-eq"),
+    })
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
     let program = args[0].clone();
@@ -92,15 +346,217 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     opts.optflag("h", "help", "Print this help menu");
     opts.optflag("a", "ast", "Print bare AST");
     opts.optflag("t", "typed", "Print typed AST");
+    opts.optflag(
+        "",
+        "emit-typed-ast-binary",
+        "Print typed AST in a compact binary format",
+    );
+    opts.optflag(
+        "",
+        "print-ir",
+        "Print the typed intermediate representation of each function",
+    );
+    opts.optflag(
+        "",
+        "dump-tokens",
+        "Print the tokens from the lexer with their locations",
+    );
+    opts.optflag("", "from-ast", "Read bare AST JSON instead of source code");
+    opts.optflag(
+        "",
+        "from-typed-ast",
+        "Read typed AST JSON or binary instead of source code, skipping type checking",
+    );
+    opts.optopt(
+        "",
+        "max-ast-nodes",
+        "Refuse ASTs read with --from-ast or --from-typed-ast that have more than N nodes",
+        "N",
+    );
+    opts.optflag(
+        "",
+        "keep-comments",
+        "Attach comments preceding declarations to the printed AST",
+    );
+    opts.optopt(
+        "",
+        "conformance",
+        "Compare the typed AST with typed AST JSON from the reference compiler, \
+         and print the first difference",
+        "REF",
+    );
+    opts.optopt(
+        "",
+        "doc-json",
+        "Write classes and functions with their preceding comments as JSON to PATH",
+        "PATH",
+    );
+    opts.optopt(
+        "",
+        "complete",
+        "Print completion candidates at the cursor position as JSON",
+        "ROW:COL",
+    );
+    opts.optflag(
+        "",
+        "ext-class-const",
+        "Extension: allow class constants declared as `CONST x:int = 5`",
+    );
+    opts.optflag(
+        "",
+        "ext-bitwise",
+        "Extension: allow integer bitwise operators `&`, `|`, `^`, `<<` and `>>`",
+    );
+    opts.optflag(
+        "",
+        "ext-list-eq",
+        "Extension: allow `==` and `!=` on lists of int, bool or str",
+    );
+    opts.optflag(
+        "",
+        "ext-list-append",
+        "Extension: allow `append(list, x)`, which returns a new list with x added",
+    );
+    opts.optflag(
+        "",
+        "ext-truthy-and-or",
+        "Extension: allow `and` and `or` on any values, returning an operand like Python",
+    );
+    opts.optflag(
+        "",
+        "ext-enumerate",
+        "Extension: allow `for i, x in enumerate(list)` with the index of each element",
+    );
+    opts.optflag(
+        "",
+        "ext-class-eq",
+        "Extension: allow `==` and `!=` on two objects of the same class, comparing attributes",
+    );
+    opts.optflag(
+        "",
+        "ext-func-values",
+        "Extension: allow top-level functions as values of types like `Callable[[int], str]`",
+    );
+    opts.optflag(
+        "",
+        "ext-membership",
+        "Extension: allow `x in list`, `s in str` and `count(list, x)`",
+    );
+    opts.optflag("", "werror", "Treat warnings as errors");
+    opts.optflag(
+        "",
+        "friendly-errors",
+        "Reword some errors for Python users even when printing the typed AST",
+    );
+    opts.optflag(
+        "",
+        "trace-exec",
+        "Make the program print each executed source line to stderr",
+    );
+    opts.optflag(
+        "",
+        "interruptible",
+        "Make the program exit cleanly on Ctrl-C, checked at the end of each loop iteration",
+    );
+    opts.optopt(
+        "O",
+        "",
+        "Optimization level: 0 (default), or 2 to inline tiny functions and skip dispatch",
+        "LEVEL",
+    );
+    opts.optopt(
+        "",
+        "runtime-checks",
+        "Implicit runtime checks to keep: all (default), bounds for index bounds only, or off",
+        "LEVEL",
+    );
+    opts.optflag(
+        "",
+        "group-prototypes",
+        "Place the method tables of all classes next to each other in a section of their own",
+    );
+    opts.optflag(
+        "",
+        "reorder-functions",
+        "Place each function next to the functions it calls most often",
+    );
+    opts.optflag(
+        "",
+        "strip",
+        "Leave out symbols for procedures and data only used by the program itself",
+    );
+    opts.optflag("", "no-debug", "Leave out debug info");
+    opts.optopt(
+        "",
+        "split-debug",
+        "Write debug info to a separate file that the output refers to",
+        "PATH",
+    );
+    opts.optflag(
+        "",
+        "export-classes",
+        "Add entry points for C code to construct objects and call their methods",
+    );
     opts.optflag("o", "obj", "Output object file without linking");
     opts.optflag("s", "static", "Link against library statically if possible");
+    #[cfg(feature = "embedded-runtime")]
+    opts.optflag(
+        "",
+        "embedded-runtime",
+        "Link a static Linux executable with the runtime built into the compiler, without cc",
+    );
+    opts.optflag(
+        "",
+        "emit-staticlib",
+        "Output a static library containing the program and the runtime instead of an executable",
+    );
+    opts.optflag(
+        "",
+        "verify-obj",
+        "Check that the object file has no references to undefined symbols",
+    );
+    opts.optflag(
+        "",
+        "repl",
+        "Start an interactive session that runs code with an interpreter",
+    );
+    opts.optopt(
+        "",
+        "build",
+        "Compile all files in the directory as separate objects and link them",
+        "DIR",
+    );
+    opts.optopt(
+        "",
+        "cache",
+        "Reuse the object file cached in DIR if the source and options are unchanged",
+        "DIR",
+    );
+    opts.optopt(
+        "",
+        "seed",
+        "Seed for names of temporary files, instead of CHOCOPY_SEED or the current time",
+        "N",
+    );
     opts.optopt(
         "p",
         "platform",
         "Specify target platform",
-        "[windows|linux|macos]",
+        "[windows|linux|macos|wasm]",
+    );
+    opts.optopt(
+        "",
+        "object-format",
+        "Write the object file in this format instead of the one of the platform",
+        "[elf|coff|macho]",
     );
 
+    opts.optopt(
+        "",
+        "explain",
+        "Print the explanation of an error code shown with a message, such as E-TYPE-ASSIGN",
+        "CODE",
+    );
     opts.optflag("", "version", "Display version");
 
     let matches = match opts.parse(&args[1..]) {
@@ -122,64 +578,389 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    let input = if let Some(input) = matches.free.get(0) {
+    if let Some(code) = matches.opt_str("explain") {
+        if let Some(explanation) = explain::explain(&code) {
+            print!("{}", explanation);
+            return Ok(());
+        }
+        eprintln!("Unknown error code `{}`", code);
+        return Err(ArgumentError.into());
+    }
+
+    if let Some(seed) = matches.opt_str("seed") {
+        if let Ok(seed) = seed.parse() {
+            chocopy_rs_common::temp::seed(seed);
+        } else {
+            eprintln!("Seed should be a number, got `{}`", seed);
+            return Err(ArgumentError.into());
+        }
+    }
+
+    if matches.opt_present("repl") {
+        let options = parse::Options {
+            keep_comments: false,
+            class_const: matches.opt_present("ext-class-const"),
+            bitwise: matches.opt_present("ext-bitwise"),
+        };
+        repl::run(
+            options,
+            check_options(&matches),
+            &mut std::io::stdin().lock(),
+            &mut std::io::stdout(),
+            &mut std::io::stderr(),
+        );
+        return Ok(());
+    }
+
+    if let Some(dir) = matches.opt_str("build") {
+        let output = if let Some(output) = matches.free.first() {
+            output
+        } else {
+            eprintln!("Please specifiy output path");
+            return Err(ArgumentError.into());
+        };
+        let platform = parse_platform(&matches)?;
+        if platform != PLATFORM {
+            eprintln!("Cross-platform linking is unsupported.");
+            return Err(ArgumentError.into());
+        }
+        if parse_object_format(&matches)?.is_some_and(|f| f != ObjectFormat::native(platform)) {
+            eprintln!("The linker only accepts the native object format.");
+            return Err(ArgumentError.into());
+        }
+        if matches.opt_present("split-debug") {
+            eprintln!("Debug info of multi-file builds can't be split");
+            return Err(ArgumentError.into());
+        }
+        return build::build(
+            &dir,
+            output,
+            matches.opt_present("s"),
+            matches.opt_present("emit-staticlib"),
+            matches.opt_present("werror"),
+            parse::Options {
+                keep_comments: false,
+                class_const: matches.opt_present("ext-class-const"),
+                bitwise: matches.opt_present("ext-bitwise"),
+            },
+            check_options(&matches),
+            compile_options(&matches)?,
+            platform,
+        );
+    }
+
+    let input = if let Some(input) = matches.free.first() {
         input
     } else {
         eprintln!("Please specifiy source file");
         return Err(ArgumentError.into());
     };
 
-    let ast = parse::process(input)?;
+    if matches.opt_present("dump-tokens") {
+        let options = parse::Options {
+            keep_comments: matches.opt_present("keep-comments"),
+            class_const: false,
+            bitwise: matches.opt_present("ext-bitwise"),
+        };
+        print!("{}", parse::dump_tokens(input, options)?);
+        return Ok(());
+    }
+
+    if let Some(cursor) = matches.opt_str("complete") {
+        let position = parse_position(&cursor)?;
+        let options = parse::Options {
+            keep_comments: false,
+            class_const: matches.opt_present("ext-class-const"),
+            bitwise: matches.opt_present("ext-bitwise"),
+        };
+        let source = std::fs::read_to_string(input)?;
+        let candidates = complete::complete(&source, position, options, check_options(&matches));
+        println!("{}", serde_json::to_string_pretty(&candidates).unwrap());
+        return Ok(());
+    }
+
+    #[cfg(feature = "embedded-runtime")]
+    if matches.opt_present("embedded-runtime") {
+        let (output, platform) = output_target(&matches)?;
+        if platform != Platform::Linux || matches.opt_present("o") {
+            eprintln!("The embedded runtime only links Linux executables");
+            return Err(ArgumentError.into());
+        }
+        let source = std::fs::read_to_string(input)?;
+        let executable = match gen::compile_to_executable_elf(&source, &compile_options(&matches)?)
+        {
+            Ok(executable) => executable,
+            Err(gen::Diagnostics::Source(errors)) => {
+                print_messages(input, &errors, "");
+                return Err(CodeError.into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        std::fs::write(output, executable)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(output, std::fs::Permissions::from_mode(0o755))?;
+        }
+        return Ok(());
+    }
+
+    let werror = matches.opt_present("werror");
+    let from_ast = matches.opt_present("from-ast");
+    let from_typed_ast = matches.opt_present("from-typed-ast");
+    let parse_options = parse::Options {
+        keep_comments: matches.opt_present("keep-comments") || matches.opt_present("doc-json"),
+        class_const: matches.opt_present("ext-class-const"),
+        bitwise: matches.opt_present("ext-bitwise"),
+    };
+
+    // Only object files are cached, so printing ASTs, IR or documentation doesn't use the cache
+    let prints = [
+        "ast",
+        "typed",
+        "emit-typed-ast-binary",
+        "print-ir",
+        "conformance",
+        "doc-json",
+    ]
+    .iter()
+    .any(|name| matches.opt_present(name));
+    // Cached objects have their debug info inside
+    let split = matches.opt_present("split-debug");
+    let cache = match matches.opt_str("cache") {
+        Some(dir) if !from_ast && !from_typed_ast && !prints && !split => {
+            let platform = parse_platform(&matches)?;
+            // Cached objects are checked against the native format when they are reused
+            let foreign = |f: ObjectFormat| f != ObjectFormat::native(platform);
+            if platform == Platform::Wasm || parse_object_format(&matches)?.is_some_and(foreign) {
+                None
+            } else {
+                Some(cache::Cache::new(
+                    Path::new(&dir),
+                    input,
+                    parse_options,
+                    check_options(&matches),
+                    compile_options(&matches)?,
+                    platform,
+                )?)
+            }
+        }
+        _ => None,
+    };
+
+    if let Some(cache) = cache.as_ref().filter(|cache| cache.hit()) {
+        let (output, platform) = output_target(&matches)?;
+        eprintln!("Using cached object file {}", cache.object().display());
+        return gen::gen_from_object(
+            cache.object(),
+            output,
+            matches.opt_present("o"),
+            matches.opt_present("s"),
+            matches.opt_present("emit-staticlib"),
+            matches.opt_present("verify-obj"),
+            platform,
+        );
+    }
+
+    let (source, ast) = if from_ast || from_typed_ast {
+        // Refer to the source file next to the AST file if there is one
+        let source = [".ast.typed.bin", ".ast.typed", ".ast"]
+            .iter()
+            .filter_map(|suffix| input.strip_suffix(suffix))
+            .find(|source| std::path::Path::new(source).is_file())
+            .unwrap_or(input);
+        let mut limits = parse::AstLimits::default();
+        if let Some(max_nodes) = matches.opt_str("max-ast-nodes") {
+            if let Ok(max_nodes) = max_nodes.parse() {
+                limits.max_nodes = max_nodes;
+            } else {
+                eprintln!("Number of nodes should be a number, got `{}`", max_nodes);
+                return Err(ArgumentError.into());
+            }
+        }
+        let ast = parse::load_ast_limited(input, limits).map_err(|e| {
+            eprintln!("{}", e);
+            CodeError
+        })?;
+        (source, ast)
+    } else {
+        (input.as_str(), parse::process(input, parse_options)?)
+    };
 
     if matches.opt_present("ast") {
         println!("{}", serde_json::to_string_pretty(&ast).unwrap());
         return Ok(());
     }
 
-    if !check_error(input, &ast) {
+    if !check_error(source, &ast, werror) {
         return Err(CodeError.into());
     }
 
-    let ast = check::check(ast);
+    let ast = if from_typed_ast {
+        check::validate(ast)
+    } else {
+        let mut ast = check::check(ast, check_options(&matches));
+        // Typed AST output is compared against the reference, so keep its wording
+        let reference_wording = ["typed", "emit-typed-ast-binary", "conformance"]
+            .iter()
+            .any(|name| matches.opt_present(name));
+        if matches.opt_present("friendly-errors") || !reference_wording {
+            check::friendly(&mut ast);
+        }
+        ast
+    };
 
     if matches.opt_present("typed") {
         println!("{}", serde_json::to_string_pretty(&ast).unwrap());
         return Ok(());
     }
 
-    if !check_error(input, &ast) {
+    if matches.opt_present("emit-typed-ast-binary") {
+        std::io::stdout().write_all(&parse::to_binary(&ast))?;
+        return Ok(());
+    }
+
+    // Programs with errors are compared too, as the reference output includes the errors
+    if let Some(path) = matches.opt_str("conformance") {
+        let reference = parse::load_ast(&path)?;
+        return match conformance::compare(reference, ast) {
+            None => {
+                println!("Same as the reference");
+                Ok(())
+            }
+            Some(difference) => {
+                println!("Differs from the reference at {}", difference);
+                Err(ConformanceError.into())
+            }
+        };
+    }
+
+    if !check_error(source, &ast, werror) {
         return Err(CodeError.into());
     }
 
-    let output = if let Some(output) = matches.free.get(1) {
-        output
-    } else {
-        eprintln!("Please specifiy output path");
-        return Err(ArgumentError.into());
-    };
+    if matches.opt_present("print-ir") {
+        for function in ir::lower(&ast) {
+            println!("{}", function);
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = matches.opt_str("doc-json") {
+        let json = serde_json::to_string_pretty(&doc::program_doc(&ast)).unwrap();
+        std::fs::write(path, json + "\n")?;
+        return Ok(());
+    }
 
+    let (output, platform) = output_target(&matches)?;
+    let split_debug = split_debug(&matches, platform)?;
     let no_link = matches.opt_present("o");
     let static_lib = matches.opt_present("s");
-    let platform = matches
-        .opt_str("platform")
-        .map(|p| match p.as_str() {
-            "windows" => Ok(Platform::Windows),
-            "linux" => Ok(Platform::Linux),
-            "macos" => Ok(Platform::Macos),
-            _ => {
-                eprintln!("Unknown platform `{}`", p);
-                Err(ArgumentError)
-            }
-        })
-        .transpose()?
-        .unwrap_or(PLATFORM);
+    let verify_obj = matches.opt_present("verify-obj");
 
-    if platform != PLATFORM && !no_link {
-        eprintln!("Cross-platform linking is unsupported. Please use --obj option.");
-        return Err(ArgumentError.into());
+    // Programs with warnings are not cached, so that the warnings are printed every time
+    if let Some(cache) = cache.filter(|_| ast.errors.warnings.is_empty()) {
+        cache.store(source, ast)?;
+        return gen::gen_from_object(
+            cache.object(),
+            output,
+            no_link,
+            static_lib,
+            matches.opt_present("emit-staticlib"),
+            verify_obj,
+            platform,
+        );
     }
 
-    gen::gen(input, ast, output, no_link, static_lib, platform)?;
+    gen::gen(
+        source,
+        ast,
+        output,
+        no_link,
+        static_lib,
+        matches.opt_present("emit-staticlib"),
+        verify_obj,
+        compile_options(&matches)?,
+        split_debug.as_deref().map(Path::new),
+        platform,
+    )?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> String {
+        let mut ast = parse::process_str(source, Default::default());
+        if ast.errors.errors.is_empty() {
+            ast = check::check(ast, Default::default());
+        }
+        let lines = source.lines().map(str::to_owned);
+        let mut out = vec![];
+        write_messages(&mut out, lines, &ast.errors.errors, "").unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn message_context() {
+        assert_eq!(
+            messages("print(y)\nx = 1\n"),
+            "1, 7: [E-UNDEFINED-VAR] Not a variable: y\n    | print(y)\n    |       ^\n\
+             2, 1: [E-UNDEFINED-VAR] Not a variable: x\n    | x = 1\n    | ^\n"
+        );
+        // Tabs on the first line are shown as spaces too, so the caret lines up
+        assert_eq!(
+            messages("\tprint(y)\n"),
+            "1, 1: [E-SYNTAX] unexptected token\n    |  print(y)\n    | ^\n\
+             2, 1: [E-SYNTAX] unexptected token\n"
+        );
+        assert_eq!(
+            messages("\n\nz = y\n"),
+            "3, 1: [E-UNDEFINED-VAR] Not a variable: z\n    | z = y\n    | ^\n\
+             3, 5: [E-UNDEFINED-VAR] Not a variable: y\n    | z = y\n    |     ^\n"
+        );
+    }
+
+    #[test]
+    fn long_line_context() {
+        let line = "a".repeat(1000);
+        assert_eq!(context_excerpt("x = y", 5, 5), ("x = y".to_owned(), 4));
+        assert_eq!(
+            context_excerpt(&line[..200], 200, 200),
+            (line[..200].to_owned(), 199)
+        );
+
+        // Start of the line
+        let (excerpt, caret) = context_excerpt(&line, 1, 3);
+        assert_eq!(excerpt, line[..200].to_owned() + "...");
+        assert_eq!(caret, 0);
+
+        // Middle of the line, centered on the span
+        let (excerpt, caret) = context_excerpt(&line, 501, 510);
+        assert_eq!(excerpt, format!("...{}...", &line[..200]));
+        assert_eq!(caret, 3 + 96);
+
+        // End of the line
+        let (excerpt, caret) = context_excerpt(&line, 1000, 1000);
+        assert_eq!(excerpt, "...".to_owned() + &line[..200]);
+        assert_eq!(caret, 3 + 199);
+
+        // A span wider than the excerpt still shows where it starts
+        let (_, caret) = context_excerpt(&line, 100, 900);
+        assert_eq!(caret, 3);
+    }
+
+    #[test]
+    fn long_line_messages() {
+        // Machine-generated program on a single 100k character line
+        let source = format!("print(\"{}\" + y)\n", "a".repeat(100_000));
+        let output = messages(&source);
+        let lines: Vec<_> = output.lines().collect();
+        // The error for `+` comes first, followed by the one for `y` at the end
+        assert_eq!(lines[3], "1, 100012: [E-UNDEFINED-VAR] Not a variable: y");
+        assert_eq!(lines[4], format!("    | ...{}\" + y)", "a".repeat(194)));
+        assert_eq!(lines[5], format!("    | {}^", " ".repeat(3 + 198)));
+    }
+}
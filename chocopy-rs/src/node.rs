@@ -10,6 +10,8 @@ pub struct NodeBase {
     pub location: Location,
     #[serde(rename = "errorMsg", skip_serializing_if = "Option::is_none")]
     pub error_msg: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<String>,
 }
 
 impl NodeBase {
@@ -17,6 +19,7 @@ impl NodeBase {
         NodeBase {
             location: Location::new(sr, sc, er, ec),
             error_msg: None,
+            comments: vec![],
         }
     }
 
@@ -24,6 +27,7 @@ impl NodeBase {
         NodeBase {
             location: Location { start, end },
             error_msg: None,
+            comments: vec![],
         }
     }
 
@@ -31,6 +35,7 @@ impl NodeBase {
         NodeBase {
             location,
             error_msg: None,
+            comments: vec![],
         }
     }
 }
@@ -46,9 +51,17 @@ pub trait Node {
         errors.push(CompilerError {
             base: NodeBase::from_location(base.location),
             message,
+            code: None,
             syntax: false,
+            friendly: None,
         })
     }
+
+    // Like add_error, for an error with a code that `--explain` has a long explanation for
+    fn add_coded_error(&mut self, errors: &mut Vec<CompilerError>, code: &str, message: String) {
+        self.add_error(errors, message);
+        errors.last_mut().unwrap().code = Some(code.to_owned());
+    }
 }
 
 impl<T> Node for Box<T>
@@ -119,6 +132,48 @@ pub enum BinaryOp {
     Ge,
     #[serde(rename = "is")]
     Is,
+    // Extension: integer bitwise operators
+    #[serde(rename = "&")]
+    BitAnd,
+    #[serde(rename = "|")]
+    BitOr,
+    #[serde(rename = "^")]
+    BitXor,
+    #[serde(rename = "<<")]
+    Shl,
+    #[serde(rename = ">>")]
+    Shr,
+    // Extension: membership test in a list or a string
+    #[serde(rename = "in")]
+    In,
+}
+
+impl BinaryOp {
+    // The operator as written in the source
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Or => "or",
+            BinaryOp::And => "and",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "//",
+            BinaryOp::Mod => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Gt => ">",
+            BinaryOp::Le => "<=",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Is => "is",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::In => "in",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
@@ -154,6 +209,37 @@ pub struct CallExpr {
 
 impl_node!(CallExpr);
 
+impl CallExpr {
+    // Whether this calls the `append` extension, which, like `range`,
+    // only exists unless the name is defined by the program
+    pub fn is_append(&self, is_defined: impl Fn(&str) -> bool) -> bool {
+        self.function.name == "append" && !is_defined("append")
+    }
+
+    // Whether this calls `enumerate` as the iterable of a loop with an index (extension)
+    pub fn is_enumerate(&self, is_defined: impl Fn(&str) -> bool) -> bool {
+        self.function.name == "enumerate" && !is_defined("enumerate")
+    }
+
+    // Whether this calls the `count` extension, which only exists unless the name is defined
+    pub fn is_count(&self, is_defined: impl Fn(&str) -> bool) -> bool {
+        self.function.name == "count" && !is_defined("count")
+    }
+}
+
+// Extension: the type of function values, written as `Callable[[int, str], bool]`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CallableType {
+    #[serde(flatten)]
+    pub base: NodeBase,
+    pub parameters: Vec<TypeAnnotation>,
+    #[serde(rename = "returnType")]
+    pub return_type: TypeAnnotation,
+}
+
+impl_node!(CallableType);
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct ClassDef {
@@ -202,12 +288,28 @@ pub struct CompilerError {
     #[serde(flatten)]
     pub base: NodeBase,
     pub message: String,
+    // A code from `explain`, for the most common errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
     #[serde(default, skip_serializing_if = "is_not")]
     pub syntax: bool,
+    // A longer message that `check::friendly` shows instead, prepared where the error was found
+    #[serde(skip)]
+    pub friendly: Option<String>,
 }
 
 impl_node!(CompilerError);
 
+impl CompilerError {
+    // The message shown to users, starting with the code if there is one
+    pub fn display_message(&self) -> String {
+        match &self.code {
+            Some(code) => format!("[{}] {}", code, self.message),
+            None => self.message.clone(),
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[enum_dispatch(Node)]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
@@ -221,6 +323,19 @@ pub enum Declaration {
 }
 
 impl Declaration {
+    pub fn name(&self) -> &Identifier {
+        match self {
+            Declaration::ClassDef(ClassDef { name, .. }) => name,
+            Declaration::FuncDef(FuncDef { name, .. }) => name,
+            Declaration::GlobalDecl(GlobalDecl { variable, .. }) => variable,
+            Declaration::NonLocalDecl(NonLocalDecl { variable, .. }) => variable,
+            Declaration::VarDef(VarDef {
+                var: TypedVar { identifier, .. },
+                ..
+            }) => identifier,
+        }
+    }
+
     pub fn name_mut(&mut self) -> &mut Identifier {
         match self {
             Declaration::ClassDef(ClassDef { name, .. }) => name,
@@ -241,6 +356,9 @@ pub struct Errors {
     #[serde(flatten)]
     pub base: NodeBase,
     pub errors: Vec<CompilerError>,
+    // Valid code that is likely a mistake. Not part of the reference format
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<CompilerError>,
 }
 
 impl_node!(Errors);
@@ -248,6 +366,7 @@ impl_node!(Errors);
 impl Errors {
     pub fn sort(&mut self) {
         self.errors.sort_by_key(|error| error.base().location);
+        self.warnings.sort_by_key(|warning| warning.base().location);
     }
 }
 
@@ -341,6 +460,9 @@ impl_node!(ExprStmt);
 pub struct ForStmt {
     #[serde(flatten)]
     pub base: NodeBase,
+    // Extension: the index variable in `for i, x in enumerate(...)`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<ForTarget>,
     pub identifier: ForTarget,
     pub iterable: Expr,
     pub body: Vec<Stmt>,
@@ -348,6 +470,31 @@ pub struct ForStmt {
 
 impl_node!(ForStmt);
 
+impl ForStmt {
+    // Arguments of `range(...)` if the loop iterates over the special `range` function,
+    // which only exists in this context unless the name is defined by the program
+    pub fn range_args(&self, is_defined: impl Fn(&str) -> bool) -> Option<&[Expr]> {
+        match &self.iterable.content {
+            ExprContent::CallExpr(call)
+                if call.function.name == "range" && !is_defined("range") =>
+            {
+                Some(&call.args)
+            }
+            _ => None,
+        }
+    }
+
+    // The argument of `enumerate(...)` if the loop has an index variable. The checker only
+    // accepts the index with that special function, so its name is not looked up again
+    pub fn enumerate_arg(&self) -> Option<&Expr> {
+        self.index.as_ref()?;
+        match &self.iterable.content {
+            ExprContent::CallExpr(call) => call.args.first(),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct FuncDef {
@@ -371,6 +518,41 @@ pub struct FuncType {
     pub return_type: ValueType,
 }
 
+impl Display for FuncType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, parameter) in self.parameters.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", parameter)?;
+        }
+        write!(f, ") -> {}", self.return_type)
+    }
+}
+
+// Extension: the value type of `CallableType`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FuncValueType {
+    pub parameters: Vec<ValueType>,
+    #[serde(rename = "returnType")]
+    pub return_type: Box<ValueType>,
+}
+
+impl Display for FuncValueType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Callable[[")?;
+        for (i, parameter) in self.parameters.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", parameter)?;
+        }
+        write!(f, "], {}]", self.return_type)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 #[serde(tag = "kind", rename = "Identifier")]
 pub struct Function {
@@ -642,6 +824,33 @@ pub enum Stmt {
     WhileStmt(WhileStmt),
 }
 
+// Position of the first statement that returns on every path through it.
+// The statements after it are unreachable.
+fn return_position(statements: &[Stmt]) -> Option<usize> {
+    statements.iter().position(|statement| match statement {
+        Stmt::ReturnStmt(_) => true,
+        Stmt::IfStmt(IfStmt {
+            then_body,
+            else_body,
+            ..
+        }) => always_return(then_body) && always_return(else_body),
+        _ => false,
+    })
+}
+
+// Whether every path through the statements ends with a return statement (semantic rule 9)
+pub fn always_return(statements: &[Stmt]) -> bool {
+    return_position(statements).is_some()
+}
+
+// The statements without the unreachable ones after a return
+pub fn reachable(statements: &[Stmt]) -> &[Stmt] {
+    match return_position(statements) {
+        Some(position) => &statements[..=position],
+        None => statements,
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct StringLiteral {
@@ -652,19 +861,28 @@ pub struct StringLiteral {
 
 impl_node!(StringLiteral);
 
+#[allow(clippy::enum_variant_names)]
 #[enum_dispatch(Node)]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 #[serde(tag = "kind", deny_unknown_fields)]
 pub enum TypeAnnotation {
     ClassType(ClassType),
     ListType(Box<ListType>),
+    CallableType(Box<CallableType>),
 }
 
 impl TypeAnnotation {
-    pub fn core_type_mut(&mut self) -> &mut ClassType {
+    // The class types that make up this type, such as `int` and `str` in `Callable[[[int]], str]`
+    pub fn class_types_mut(&mut self) -> Vec<&mut ClassType> {
         match self {
-            TypeAnnotation::ClassType(c) => c,
-            TypeAnnotation::ListType(l) => l.element_type.core_type_mut(),
+            TypeAnnotation::ClassType(c) => vec![c],
+            TypeAnnotation::ListType(l) => l.element_type.class_types_mut(),
+            TypeAnnotation::CallableType(c) => c
+                .parameters
+                .iter_mut()
+                .chain(std::iter::once(&mut c.return_type))
+                .flat_map(|t| t.class_types_mut())
+                .collect(),
         }
     }
 }
@@ -721,11 +939,13 @@ pub struct UnaryExpr {
 
 impl_node!(UnaryExpr);
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 #[serde(tag = "kind", deny_unknown_fields)]
 pub enum ValueType {
     ClassValueType(ClassValueType),
     ListValueType(ListValueType),
+    FuncValueType(Box<FuncValueType>),
 }
 
 impl Display for ValueType {
@@ -733,6 +953,7 @@ impl Display for ValueType {
         match self {
             ValueType::ClassValueType(v) => v.fmt(f),
             ValueType::ListValueType(v) => v.fmt(f),
+            ValueType::FuncValueType(v) => v.fmt(f),
         }
     }
 }
@@ -746,6 +967,14 @@ impl ValueType {
             TypeAnnotation::ListType(c) => ValueType::ListValueType(ListValueType {
                 element_type: Box::new(ValueType::from_annotation(&c.element_type)),
             }),
+            TypeAnnotation::CallableType(c) => ValueType::FuncValueType(Box::new(FuncValueType {
+                parameters: c
+                    .parameters
+                    .iter()
+                    .map(ValueType::from_annotation)
+                    .collect(),
+                return_type: Box::new(ValueType::from_annotation(&c.return_type)),
+            })),
         }
     }
 }
@@ -757,6 +986,9 @@ pub struct VarDef {
     pub base: NodeBase,
     pub var: TypedVar,
     pub value: Literal,
+    // Class constant stored once per class instead of per object (extension)
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub constant: bool,
 }
 
 impl_node!(VarDef);
@@ -802,11 +1034,6 @@ pub static TYPE_BOOL: Lazy<ValueType> = Lazy::new(|| {
         class_name: "bool".to_owned(),
     })
 });
-pub static TYPE_NONE_LIST: Lazy<ValueType> = Lazy::new(|| {
-    ValueType::ListValueType(ListValueType {
-        element_type: Box::new(TYPE_NONE.clone()),
-    })
-});
 
 #[cfg(test)]
 mod tests {
@@ -832,6 +1059,7 @@ mod tests {
                     base: NodeBase::new(0, 0, 0, 0),
                     value: true,
                 }),
+                constant: false,
             })],
             statements: vec![Stmt::ExprStmt(ExprStmt {
                 base: NodeBase::new(1, 1, 1, 9),
@@ -859,6 +1087,7 @@ mod tests {
             errors: Errors {
                 base: NodeBase::new(0, 0, 0, 0),
                 errors: vec![],
+                warnings: vec![],
             },
         };
 
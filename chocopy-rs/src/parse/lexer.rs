@@ -1,36 +1,54 @@
 use super::token::*;
 use crate::location::*;
 use std::cmp::Ordering;
-use std::future::*;
+use std::collections::HashMap;
+use std::collections::VecDeque;
 
-// Adapter that preprocess the input character string:
+// Adapter that preprocess the source text:
 //  - Attach row and column information to each character
 //  - Allow peeking the current character without stepping
 //  - Normalize all line breaks to '\n'
 //  - Make sure there is '\n' before EOF
-struct TextReader<GetChar> {
-    get_char: GetChar,
+//  - Remember where the last character other than a line break is
+struct TextReader<'a> {
+    source: &'a [u8], // ASCII only
+    index: usize,     // Next byte to read after the current one
     current: Option<char>,
     position: Position,
     previous_position: Position,
+    last_char_position: Option<Position>,
     early_eof: bool,
 }
 
-impl<GetChar: Iterator<Item = char>> TextReader<GetChar> {
-    fn new(mut get_char: GetChar) -> TextReader<GetChar> {
-        let current = get_char.next();
-        let (current, early_eof) = if current.is_none() {
-            (Some('\n'), true)
-        } else {
-            (current, false)
-        };
-        TextReader {
-            get_char,
-            current,
+impl<'a> TextReader<'a> {
+    fn new(source: &'a str) -> TextReader<'a> {
+        // Lex up to the first non-ASCII character
+        let source = source.as_bytes();
+        let len = source
+            .iter()
+            .position(|c| !c.is_ascii())
+            .unwrap_or(source.len());
+        let mut reader = TextReader {
+            source: &source[..len],
+            index: 0,
+            current: None,
             position: Position { row: 1, col: 1 },
             previous_position: Position { row: 0, col: 0 },
-            early_eof,
+            last_char_position: None,
+            early_eof: false,
+        };
+        reader.current = reader.read();
+        if reader.current.is_none() {
+            reader.current = Some('\n');
+            reader.early_eof = true;
         }
+        reader
+    }
+
+    fn read(&mut self) -> Option<char> {
+        let c = *self.source.get(self.index)?;
+        self.index += 1;
+        Some(c as char)
     }
 
     fn next(&mut self) {
@@ -39,23 +57,24 @@ impl<GetChar: Iterator<Item = char>> TextReader<GetChar> {
             Some('\n') => {
                 self.position.row += 1;
                 self.position.col = 1;
-                self.current = self.get_char.next();
+                self.current = self.read();
             }
             Some('\r') => {
                 self.position.row += 1;
                 self.position.col = 1;
-                self.current = self.get_char.next();
+                self.current = self.read();
                 if self.current == Some('\n') {
-                    self.current = self.get_char.next();
+                    self.current = self.read();
                 }
             }
             None => (),
             _ => {
+                self.last_char_position = Some(self.position);
                 self.position.col += 1;
                 self.current = if self.early_eof {
                     None
                 } else {
-                    let c = self.get_char.next();
+                    let c = self.read();
                     if c.is_none() {
                         self.early_eof = true;
                         Some('\n')
@@ -81,96 +100,128 @@ impl<GetChar: Iterator<Item = char>> TextReader<GetChar> {
     fn previous_position(&self) -> Position {
         self.previous_position
     }
+    fn last_char_position(&self) -> Option<Position> {
+        self.last_char_position
+    }
 }
 
-async fn lex_string<
-    GetChar: Iterator<Item = char>,
-    PutTokenFuture: Future<Output = ()>,
-    PutToken: FnMut(Token, Position, Position) -> PutTokenFuture,
->(
-    reader: &mut TextReader<GetChar>,
-    put_token: &mut PutToken,
-    start: Position,
-) {
-    reader.next();
-    let mut s = "".to_owned();
-    let mut is_id = true;
-    loop {
-        match reader.current_char().unwrap() {
-            // end quote
-            '\"' => {
-                reader.next();
-                break;
-            }
-            // escape
-            '\\' => {
-                is_id = false;
-                reader.next();
-                match reader.current_char().unwrap() {
-                    'n' => s.push('\n'),
-                    't' => s.push('\t'),
-                    '\\' => s.push('\\'),
-                    '\"' => s.push('\"'),
-                    c => {
-                        reader.next();
-                        put_token(
-                            Token::Unrecognized(c.to_string()),
-                            start,
-                            reader.previous_position(),
-                        )
-                        .await;
-                        break;
+// Where the lexer is in the source
+#[derive(PartialEq, Eq)]
+enum State {
+    LineStart, // Before the indentation of a line
+    InLine,    // Between tokens of a line
+    Done,      // After end of file
+}
+
+// Iterator of the tokens in the source text
+pub struct Lexer<'a> {
+    reader: TextReader<'a>,
+    state: State,
+    pending: VecDeque<ComplexToken>, // Tokens lexed but not returned yet
+    indentation_stack: Vec<u32>,
+    operators: &'static HashMap<char, HashMap<char, Token>>,
+    keep_comments: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(
+        source: &'a str,
+        keep_comments: bool,
+        bitwise: bool, // Lex bitwise operators (extension)
+    ) -> Lexer<'a> {
+        let operators = if bitwise {
+            &*BITWISE_OPERATORS
+        } else {
+            &*OPERATORS
+        };
+        Lexer {
+            reader: TextReader::new(source),
+            state: State::LineStart,
+            pending: VecDeque::new(),
+            indentation_stack: vec![0],
+            operators,
+            keep_comments,
+        }
+    }
+
+    fn put_token(&mut self, token: Token, start: Position, end: Position) {
+        self.pending.push_back(ComplexToken {
+            token,
+            location: Location { start, end },
+        });
+    }
+
+    fn lex_string(&mut self, start: Position) {
+        let reader = &mut self.reader;
+        reader.next();
+        let mut s = "".to_owned();
+        loop {
+            match reader.current_char().unwrap() {
+                // end quote
+                '\"' => {
+                    reader.next();
+                    break;
+                }
+                // escape
+                '\\' => {
+                    reader.next();
+                    match reader.current_char().unwrap() {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        '\\' => s.push('\\'),
+                        '\"' => s.push('\"'),
+                        c => {
+                            if c != '\n' {
+                                reader.next();
+                            }
+                            let end = reader.previous_position();
+                            self.put_token(Token::Unrecognized(c.to_string()), start, end);
+                            break;
+                        }
                     }
                 }
-            }
-            // normal char
-            c @ ' '..='~' => {
-                if !matches!(c, 'a'..='z' | 'A'..='Z' | '_' | '0'..='9') {
-                    is_id = false;
+                // normal char
+                c @ ' '..='~' => s.push(c),
+                // unrecognized. An unterminated string leaves the line break for the line to end.
+                c => {
+                    if c != '\n' {
+                        reader.next();
+                    }
+                    let end = reader.previous_position();
+                    self.put_token(Token::Unrecognized(c.to_string()), start, end);
+                    break;
                 }
-                s.push(c);
-            }
-            // unrecognized
-            c => {
-                reader.next();
-                put_token(
-                    Token::Unrecognized(c.to_string()),
-                    start,
-                    reader.previous_position(),
-                )
-                .await;
-                break;
             }
+            reader.next();
         }
-        reader.next();
-    }
-    let end = reader.previous_position();
-    if matches!(s.chars().next(), Some('0'..='9') | None) {
-        is_id = false;
+        let end = self.reader.previous_position();
+        // Whether the string can be an identifier depends on the decoded content,
+        // so "\\" or "\n" never can, and "Abc" is the same class name as Abc
+        let is_id = !matches!(s.chars().next(), Some('0'..='9') | None)
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        self.put_token(
+            if is_id {
+                Token::IdString(s)
+            } else {
+                Token::StringLiteral(s)
+            },
+            start,
+            end,
+        );
     }
-    put_token(
-        if is_id {
-            Token::IdString(s)
-        } else {
-            Token::StringLiteral(s)
-        },
-        start,
-        end,
-    )
-    .await;
-}
 
-async fn lex_line<
-    GetChar: Iterator<Item = char>,
-    PutTokenFuture: Future<Output = ()>,
-    PutToken: FnMut(Token, Position, Position) -> PutTokenFuture,
->(
-    reader: &mut TextReader<GetChar>,
-    put_token: &mut PutToken,
-) {
-    while reader.current_char() != Some('\n') {
+    // Lex the next token of the line, or the line break if there is none
+    fn lex_in_line(&mut self) {
+        let reader = &mut self.reader;
         let start = reader.current_position();
         match reader.current_char().unwrap() {
+            // Finish the line
+            '\n' => {
+                reader.next();
+                self.state = State::LineStart;
+                self.put_token(Token::NewLine, start, start);
+            }
+
             // Skip spaces
             ' ' | '\t' => {
                 while reader.current_char() == Some(' ') || reader.current_char() == Some('\t') {
@@ -187,16 +238,15 @@ async fn lex_line<
 
             // Numbers
             '0'..='9' => {
-                let mut s = "".to_owned();
+                let mut n: i64 = 0;
                 while let c @ '0'..='9' = reader.current_char().unwrap() {
-                    s.push(c);
+                    n = n
+                        .saturating_mul(10)
+                        .saturating_add(c.to_digit(10).unwrap() as i64);
                     reader.next();
                 }
                 let end = reader.previous_position();
-                match s.parse() {
-                    Ok(n) => put_token(Token::Number(n), start, end).await,
-                    Err(_) => put_token(Token::BadNumber, start, end).await,
-                }
+                self.put_token(Token::Number(n), start, end);
             }
 
             // Words
@@ -209,27 +259,26 @@ async fn lex_line<
                     reader.next();
                 }
                 let end = reader.previous_position();
-                put_token(
+                self.put_token(
                     KEYWORDS
                         .get(&s[..])
                         .cloned()
                         .unwrap_or(Token::Identifier(s)),
                     start,
                     end,
-                )
-                .await;
+                );
             }
 
             // Strings
             '\"' => {
-                lex_string(reader, put_token, start).await;
+                self.lex_string(start);
             }
 
             // Operators
             c => {
                 reader.next();
 
-                let token = if let Some(operator) = OPERATORS.get(&c) {
+                let token = if let Some(operator) = self.operators.get(&c) {
                     let second = reader.current_char().unwrap();
                     if let Some(operator) = operator.get(&second) {
                         reader.next();
@@ -242,26 +291,21 @@ async fn lex_line<
                 } else {
                     Token::Unrecognized(c.to_string())
                 };
-                put_token(token, start, reader.previous_position()).await;
+                let end = reader.previous_position();
+                self.put_token(token, start, end);
             }
         }
     }
-}
 
-pub async fn lex(
-    get_char: impl Iterator<Item = char>,
-    put_token: super::generator::Sender<ComplexToken>,
-) {
-    let mut reader = TextReader::new(get_char);
-    let mut put_token = |token, start, end| {
-        put_token.send(ComplexToken {
-            token,
-            location: Location { start, end },
-        })
-    };
-    let mut indentation_stack = vec![0];
+    // Lex the indentation of the next line that is not empty,
+    // or the end of file if there is none
+    fn lex_line_start(&mut self) {
+        let reader = &mut self.reader;
+        if reader.current_char().is_none() {
+            self.lex_eof();
+            return;
+        }
 
-    while reader.current_char().is_some() {
         // count indentation
         let indentation_begin = reader.current_position();
         let mut indentation: u32 = 0;
@@ -280,23 +324,36 @@ pub async fn lex(
 
         // Found comment immediately, skip to line break
         if reader.current_char() == Some('#') {
-            while reader.current_char() != Some('\n') {
+            let start = reader.current_position();
+            reader.next();
+            let mut text = String::new();
+            while let Some(c) = reader.current_char() {
+                if c == '\n' {
+                    break;
+                }
+                text.push(c);
                 reader.next();
             }
+            if self.keep_comments {
+                let end = self.reader.previous_position();
+                self.put_token(Token::Comment(text), start, end);
+            }
         }
 
         // Found line break immediately. This is an empty line
-        if reader.current_char() == Some('\n') {
-            reader.next();
-            continue;
+        if self.reader.current_char() == Some('\n') {
+            self.reader.next();
+            return;
         }
 
         // Calculate indentation
+        self.state = State::InLine;
+        let indentation_stack = &mut self.indentation_stack;
         match indentation.cmp(indentation_stack.last().unwrap()) {
             Ordering::Equal => (),
             Ordering::Greater => {
                 indentation_stack.push(indentation);
-                put_token(Token::Indent, indentation_begin, indentation_end).await;
+                self.put_token(Token::Indent, indentation_begin, indentation_end);
             }
             Ordering::Less => {
                 let mut dedent_count = 0;
@@ -305,42 +362,53 @@ pub async fn lex(
                     indentation_stack.pop();
                 }
                 if indentation != *indentation_stack.last().unwrap() {
-                    put_token(Token::Badent, indentation_end, indentation_end).await;
+                    self.put_token(Token::Badent, indentation_end, indentation_end);
                 } else {
                     for _ in 0..dedent_count {
-                        put_token(Token::Dedent, indentation_end, indentation_end).await;
+                        self.put_token(Token::Dedent, indentation_end, indentation_end);
                     }
                 }
             }
         }
-
-        // Lex normal tokens
-        lex_line(&mut reader, &mut put_token).await;
-
-        // Finish the line
-        let new_line_begin = reader.current_position();
-        put_token(Token::NewLine, new_line_begin, new_line_begin).await;
-        reader.next();
     }
 
-    let mut end = reader.current_position();
+    fn lex_eof(&mut self) {
+        self.state = State::Done;
+        let mut end = self.reader.current_position();
+
+        // Last dedent
+        for _ in 1..self.indentation_stack.len() {
+            self.put_token(Token::Dedent, end, end);
+            end.col += 1; // The reference program does this weird thing
+        }
 
-    // Last dedent
-    for _ in 1..indentation_stack.len() {
-        put_token(Token::Dedent, end, end).await;
-        end.col += 1; // The reference program does this weird thing
+        // Unlike dedents, which mark where blocks end in the AST, end of file is only
+        // used for errors. Place it on the last character instead of a row past the end.
+        let eof = self.reader.last_char_position().unwrap_or(end);
+        self.put_token(Token::Eof, eof, eof);
     }
+}
 
-    put_token(Token::Eof, end, end).await;
+impl Iterator for Lexer<'_> {
+    type Item = ComplexToken;
+    fn next(&mut self) -> Option<ComplexToken> {
+        while self.pending.is_empty() {
+            match self.state {
+                State::LineStart => self.lex_line_start(),
+                State::InLine => self.lex_in_line(),
+                State::Done => return None,
+            }
+        }
+        self.pending.pop_front()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::generator::*;
     use super::*;
 
     fn read_all(s: &str) -> Vec<(char, Position)> {
-        let mut reader = TextReader::new(s.chars());
+        let mut reader = TextReader::new(s);
         let mut v = vec![];
         loop {
             let c = reader.current_char();
@@ -407,13 +475,21 @@ mod tests {
     }
 
     fn lex_case(s: &str, tokens_ref: &[Token]) {
-        let result = generator(|put_token| lex(s.chars(), put_token));
+        let result = Lexer::new(s, false, false);
         assert_eq!(&result.map(|t| t.token).collect::<Vec<_>>()[..], tokens_ref);
     }
 
     #[test]
     fn lex_test() {
         lex_case("3", &[Token::Number(3), Token::NewLine, Token::Eof]);
+        lex_case(
+            "2147483648",
+            &[Token::Number(2147483648), Token::NewLine, Token::Eof],
+        );
+        lex_case(
+            "123456789012345678901234567890",
+            &[Token::Number(i64::MAX), Token::NewLine, Token::Eof],
+        );
         lex_case(
             "abc",
             &[
@@ -450,4 +526,55 @@ a _b \t x2
     Token::Dedent, Token::Dedent, Token::Eof
         ]);
     }
+
+    #[test]
+    fn lex_id_string() {
+        let string = |s: &str| Token::StringLiteral(s.to_owned());
+        let id = |s: &str| Token::IdString(s.to_owned());
+        let cases = [
+            ("\"Abc_1\"", id("Abc_1")),
+            ("\"_\"", id("_")),
+            ("\"1a\"", string("1a")),
+            ("\"\"", string("")),
+            ("\"a b\"", string("a b")),
+            ("\"a\\n\"", string("a\n")),
+            ("\"\\\\\"", string("\\")),
+            ("\"\\\"A\\\"\"", string("\"A\"")),
+        ];
+        for (source, token) in cases.iter() {
+            lex_case(source, &[token.clone(), Token::NewLine, Token::Eof]);
+        }
+    }
+
+    fn lex_location_case(s: &str, tokens_ref: &[(Token, Location)]) {
+        let result = Lexer::new(s, false, false);
+        let tokens: Vec<_> = result.map(|t| (t.token, t.location)).collect();
+        for token_ref in tokens_ref {
+            assert!(tokens.contains(token_ref), "{:?}", token_ref);
+        }
+    }
+
+    #[test]
+    fn lex_location() {
+        lex_location_case(
+            "def f() -> int:\n    return 1==2 != 3<=4 >= 5 < 6\n",
+            &[
+                (Token::Arrow, Location::new(1, 9, 1, 10)),
+                (Token::Colon, Location::new(1, 15, 1, 15)),
+                (Token::Equal, Location::new(2, 13, 2, 14)),
+                (Token::NotEqual, Location::new(2, 17, 2, 18)),
+                (Token::LessEqual, Location::new(2, 21, 2, 22)),
+                (Token::GreaterEqual, Location::new(2, 25, 2, 26)),
+                (Token::Less, Location::new(2, 30, 2, 30)),
+            ],
+        );
+        lex_location_case(
+            "x:int=-1\n",
+            &[
+                (Token::Colon, Location::new(1, 2, 1, 2)),
+                (Token::Assign, Location::new(1, 6, 1, 6)),
+                (Token::Minus, Location::new(1, 7, 1, 7)),
+            ],
+        );
+    }
 }
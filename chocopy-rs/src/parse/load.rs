@@ -0,0 +1,348 @@
+// Loading ASTs from files with limits on their size. Errors point at the node that doesn't
+// match, because serde buffers flattened and tagged nodes and loses the position of the error.
+use crate::node::*;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Copy, Debug)]
+pub struct AstLimits {
+    pub max_depth: usize, // Nesting of arrays and objects, to not overflow the stack
+    pub max_nodes: usize, // Nodes in the whole AST
+}
+
+impl Default for AstLimits {
+    fn default() -> AstLimits {
+        // The depth is the default recursion limit of serde_json
+        AstLimits {
+            max_depth: 128,
+            max_nodes: 1_000_000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AstFormatError {
+    at: String,           // Keys and indices from the root to the node, or empty for the root
+    kind: Option<String>, // Kind of the innermost node around the error
+    message: String,
+}
+
+impl Display for AstFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.at.is_empty() {
+            write!(f, " at {}", self.at)?;
+        }
+        if let Some(kind) = &self.kind {
+            write!(f, " (in {})", kind)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AstFormatError {}
+
+fn push_key(at: &str, key: &str) -> String {
+    if at.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", at, key)
+    }
+}
+
+// Scan the JSON text without recursion for the first array or object nested too deep.
+// The text is assumed to be well-formed here; serde_json reports syntax errors later.
+fn check_depth(json: &[u8], max_depth: usize) -> Result<(), AstFormatError> {
+    struct Level {
+        array: bool,
+        index: usize,
+        key: Option<String>,
+        kind: Option<String>,
+    }
+
+    let mut levels: Vec<Level> = vec![];
+    let mut expect_key = false;
+    let mut i = 0;
+    while i < json.len() {
+        match json[i] {
+            b'[' | b'{' => {
+                if levels.len() == max_depth {
+                    let mut at = String::new();
+                    for level in &levels {
+                        if level.array {
+                            at += &format!("[{}]", level.index);
+                        } else if let Some(key) = &level.key {
+                            at = push_key(&at, key);
+                        }
+                    }
+                    return Err(AstFormatError {
+                        at,
+                        kind: levels.iter().rev().find_map(|level| level.kind.clone()),
+                        message: format!("nested deeper than {} levels", max_depth),
+                    });
+                }
+                expect_key = json[i] == b'{';
+                levels.push(Level {
+                    array: !expect_key,
+                    index: 0,
+                    key: None,
+                    kind: None,
+                });
+            }
+            b']' | b'}' => {
+                levels.pop();
+                expect_key = false;
+            }
+            b',' => {
+                if let Some(level) = levels.last_mut() {
+                    if level.array {
+                        level.index += 1;
+                    } else {
+                        expect_key = true;
+                    }
+                }
+            }
+            b'"' => {
+                let start = i + 1;
+                i = start;
+                while i < json.len() && json[i] != b'"' {
+                    if json[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                let string = String::from_utf8_lossy(&json[start..i.min(json.len())]);
+                if let Some(level) = levels.last_mut() {
+                    if expect_key {
+                        level.key = Some(string.into_owned());
+                        expect_key = false;
+                    } else if !level.array && level.key.as_deref() == Some("kind") {
+                        level.kind = Some(string.into_owned());
+                    }
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+fn check<T: DeserializeOwned>(value: &Value) -> Result<(), String> {
+    T::deserialize(value).map(|_| ()).map_err(|e| e.to_string())
+}
+
+type Check = fn(&Value) -> Result<(), String>;
+
+// Check a node by itself as each type its kind can be
+fn check_node(kind: &str, value: &Value) -> Result<(), String> {
+    let checks: &[Check] = match kind {
+        "Program" => &[check::<Program>],
+        "ClassDef" | "FuncDef" | "GlobalDecl" | "NonLocalDecl" | "VarDef" => {
+            &[check::<Declaration>]
+        }
+        "ExprStmt" | "AssignStmt" | "ForStmt" | "IfStmt" | "ReturnStmt" | "WhileStmt" => {
+            &[check::<Stmt>]
+        }
+        "Identifier" => &[
+            check::<Expr>,
+            check::<Identifier>,
+            check::<Function>,
+            check::<ForTarget>,
+        ],
+        "BinaryExpr" | "CallExpr" | "IfExpr" | "IndexExpr" | "ListExpr" | "MethodCallExpr"
+        | "UnaryExpr" => &[check::<Expr>],
+        "MemberExpr" => &[check::<Expr>, check::<Method>],
+        "IntegerLiteral" | "BooleanLiteral" | "NoneLiteral" | "StringLiteral" => {
+            &[check::<Expr>, check::<Literal>]
+        }
+        "ClassType" | "ListType" | "CallableType" => &[check::<TypeAnnotation>],
+        "ClassValueType" | "ListValueType" | "FuncValueType" => &[check::<ValueType>],
+        "FuncType" => &[check::<FuncType>],
+        "TypedVar" => &[check::<TypedVar>],
+        "Errors" => &[check::<Errors>],
+        "CompilerError" => &[check::<CompilerError>],
+        _ => return Err(format!("unknown kind `{}`", kind)),
+    };
+    let mut first_error = None;
+    for check_as in checks {
+        match check_as(value) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error.unwrap())
+}
+
+// Find the innermost node that fails to deserialize by itself
+fn node_error(value: &Value, at: &str) -> Option<AstFormatError> {
+    let error = match value {
+        Value::Array(elements) => elements
+            .iter()
+            .enumerate()
+            .find_map(|(i, element)| node_error(element, &format!("{}[{}]", at, i))),
+        Value::Object(fields) => fields
+            .iter()
+            .find_map(|(key, field)| node_error(field, &push_key(at, key))),
+        _ => None,
+    };
+    if error.is_some() {
+        return error;
+    }
+
+    let kind = value.get("kind")?.as_str()?;
+    check_node(kind, value).err().map(|message| AstFormatError {
+        at: at.to_owned(),
+        kind: Some(kind.to_owned()),
+        message,
+    })
+}
+
+// Nodes in the AST, counted as the objects with a kind in its JSON form
+fn count_nodes(value: &Value) -> usize {
+    match value {
+        Value::Array(elements) => elements.iter().map(count_nodes).sum(),
+        Value::Object(fields) => {
+            let node = fields.contains_key("kind") as usize;
+            node + fields.values().map(count_nodes).sum::<usize>()
+        }
+        _ => 0,
+    }
+}
+
+fn check_size(ast: &Program, limits: AstLimits) -> Result<(), AstFormatError> {
+    let nodes = count_nodes(&serde_json::to_value(ast).unwrap());
+    if nodes > limits.max_nodes {
+        return Err(AstFormatError {
+            at: String::new(),
+            kind: None,
+            message: format!(
+                "the AST has {} nodes, more than the limit of {}",
+                nodes, limits.max_nodes
+            ),
+        });
+    }
+    Ok(())
+}
+
+pub fn from_json(json: &[u8], limits: AstLimits) -> Result<Program, Box<dyn Error>> {
+    check_depth(json, limits.max_depth)?;
+
+    let mut deserializer = serde_json::Deserializer::from_slice(json);
+    // The depth is already limited, possibly above the default limit of serde_json
+    deserializer.disable_recursion_limit();
+    let value = Value::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+
+    let ast = Program::deserialize(&value).map_err(|e| {
+        node_error(&value, "").unwrap_or_else(|| AstFormatError {
+            at: String::new(),
+            kind: None,
+            message: e.to_string(),
+        })
+    })?;
+    check_size(&ast, limits)?;
+    Ok(ast)
+}
+
+pub fn from_binary(data: &[u8], limits: AstLimits) -> Result<Program, Box<dyn Error>> {
+    let mut deserializer = rmp_serde::Deserializer::from_read_ref(data);
+    deserializer.set_max_depth(limits.max_depth);
+    let ast = Program::deserialize(&mut deserializer)?;
+    check_size(&ast, limits)?;
+    Ok(ast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(json: &str, limits: AstLimits) -> String {
+        from_json(json.as_bytes(), limits).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 10000;
+        let json = format!(
+            "{{\"kind\": \"Program\", \"statements\": [{{\"kind\": \"ExprStmt\", \"expr\": {}{}}}]}}",
+            "[".repeat(depth),
+            "]".repeat(depth)
+        );
+        let message = error(&json, AstLimits::default());
+        let at = format!("statements[0].expr{}", "[0]".repeat(125));
+        assert_eq!(
+            message,
+            format!("nested deeper than 128 levels at {} (in ExprStmt)", at)
+        );
+
+        let limits = AstLimits {
+            max_depth: 3,
+            ..AstLimits::default()
+        };
+        let message = error(&json, limits);
+        assert_eq!(
+            message,
+            "nested deeper than 3 levels at statements[0].expr (in ExprStmt)"
+        );
+    }
+
+    #[test]
+    fn error_path() {
+        let ast = crate::parse::process_str("print(1 + 2)\n", Default::default());
+        let json = serde_json::to_string(&ast).unwrap();
+        assert!(from_json(json.as_bytes(), AstLimits::default()).unwrap() == ast);
+
+        // The node has both an unknown field and a missing one
+        let message = error(&json.replace("\"left\"", "\"lfet\""), AstLimits::default());
+        let field = message.contains("`lfet`") || message.contains("`left`");
+        assert!(field, "{}", message);
+        assert!(
+            message.ends_with(" at statements[0].expr.args[0] (in BinaryExpr)"),
+            "{}",
+            message
+        );
+
+        let message = error(&json.replace("ExprStmt", "ExprStnt"), AstLimits::default());
+        assert_eq!(
+            message,
+            "unknown kind `ExprStnt` at statements[0] (in ExprStnt)"
+        );
+
+        let message = error("{\"kind\": \"Program\"}", AstLimits::default());
+        assert!(message.starts_with("missing field"), "{}", message);
+        assert!(message.ends_with("(in Program)"), "{}", message);
+        let message = error("[]", AstLimits::default());
+        assert!(!message.contains(" at "), "{}", message);
+    }
+
+    #[test]
+    fn node_limit() {
+        let ast = crate::parse::process_str("print(1 + 2)\n", Default::default());
+        let json = serde_json::to_vec(&ast).unwrap();
+        let binary = crate::parse::to_binary(&ast);
+        // Program, Errors, ExprStmt, CallExpr, Identifier, BinaryExpr and two IntegerLiterals
+        for &max_nodes in &[8, 100] {
+            let limits = AstLimits {
+                max_nodes,
+                ..AstLimits::default()
+            };
+            assert!(from_json(&json, limits).is_ok());
+            assert!(from_binary(&binary, limits).is_ok());
+        }
+        let limits = AstLimits {
+            max_nodes: 7,
+            ..AstLimits::default()
+        };
+        let message = "the AST has 8 nodes, more than the limit of 7";
+        let json_error = from_json(&json, limits).unwrap_err();
+        let binary_error = from_binary(&binary, limits).unwrap_err();
+        assert_eq!(json_error.to_string(), message);
+        assert_eq!(binary_error.to_string(), message);
+    }
+}
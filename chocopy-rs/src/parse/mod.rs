@@ -1,24 +1,110 @@
-mod generator;
 mod lexer;
+mod load;
 mod parser;
 mod token;
 use crate::node::*;
+pub use load::AstLimits;
 
-pub fn process(path: &str) -> Result<Program, Box<dyn std::error::Error>> {
-    use std::fs::*;
-    use std::io::*;
+#[derive(Clone, Copy, Default, Hash)]
+pub struct Options {
+    pub keep_comments: bool, // Attach comments preceding declarations to them
+    pub class_const: bool,   // Allow "CONST" class constant declarations (extension)
+    pub bitwise: bool,       // Allow integer bitwise operators (extension)
+}
+
+fn parse(source: &str, options: Options) -> Program {
+    let get_token = lexer::Lexer::new(source, options.keep_comments, options.bitwise);
+    let mut ast = parser::parse(get_token, options.class_const);
+
+    ast.errors.sort();
+
+    ast
+}
+
+pub fn process(path: &str, options: Options) -> Result<Program, Box<dyn std::error::Error>> {
+    Ok(parse(&read_source(path)?, options))
+}
+
+// Whole content of a source file. The lexer stops at the first non-ASCII character
+fn read_source(path: &str) -> std::io::Result<String> {
+    let source = std::fs::read(path)?;
+    Ok(String::from_utf8(source)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+}
+
+// One line per token with its location, for debugging the lexer
+fn token_dump(source: &str, options: Options) -> String {
+    use std::fmt::Write;
+
+    let get_token = lexer::Lexer::new(source, options.keep_comments, options.bitwise);
+    let mut dump = String::new();
+    for token::ComplexToken { token, location } in get_token {
+        let _ = writeln!(
+            dump,
+            "{}:{}-{}:{} {:?}",
+            location.start.row, location.start.col, location.end.row, location.end.col, token
+        );
+    }
+    dump
+}
+
+// Run only the lexer over the file and list the tokens
+pub fn dump_tokens(path: &str, options: Options) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(token_dump(&read_source(path)?, options))
+}
 
-    let get_char = File::open(path)?
-        .bytes()
-        .map(|c| match c {
-            Ok(c) if c < 0x80 => Some(c as char),
-            _ => None,
-        })
-        .take_while(|c| c.is_some())
-        .map(|c| c.unwrap());
+// Same as process, but for source code in memory
+pub fn process_str(source: &str, options: Options) -> Program {
+    parse(source, options)
+}
+
+#[derive(Debug)]
+pub struct AstLoadError {
+    path: String,
+    error: Box<dyn std::error::Error>,
+}
+
+impl std::fmt::Display for AstLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to load AST from {}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for AstLoadError {}
+
+// Load an AST from JSON in memory for tests, with the default limits. The errors are not sorted.
+#[cfg(test)]
+pub fn ast_from_json(json: &str) -> Result<Program, Box<dyn std::error::Error>> {
+    load::from_json(json.as_bytes(), AstLimits::default())
+}
+
+// Serialize an AST in the compact binary format (MessagePack)
+pub fn to_binary(ast: &Program) -> Vec<u8> {
+    rmp_serde::to_vec_named(ast).unwrap()
+}
 
-    let get_token = generator::generator(|put_token| lexer::lex(get_char, put_token));
-    let mut ast = parser::parse(get_token);
+// Load an AST in place of parsing source code.
+// The file can be either JSON or the binary format produced by to_binary.
+pub fn load_ast(path: &str) -> Result<Program, Box<dyn std::error::Error>> {
+    load_ast_limited(path, AstLimits::default())
+}
+
+// Same as load_ast, with limits other than the default ones
+pub fn load_ast_limited(
+    path: &str,
+    limits: AstLimits,
+) -> Result<Program, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)?;
+    let json = data.iter().find(|c| !c.is_ascii_whitespace()) == Some(&b'{');
+    let ast = if json {
+        load::from_json(&data, limits)
+    } else {
+        load::from_binary(&data, limits)
+    };
+    let mut ast = ast.map_err(|error| AstLoadError {
+        path: path.to_owned(),
+        error,
+    })?;
 
     ast.errors.sort();
 
@@ -28,36 +114,21 @@ pub fn process(path: &str) -> Result<Program, Box<dyn std::error::Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::{stdout, Write};
+    use crate::location::*;
+    use crate::snapshot::{self, Snapshots};
 
-    fn compare_ast(a: &Program, b: &Program) -> bool {
-        let Program {
-            errors: Errors {
-                errors: a_errors, ..
-            },
-            ..
-        } = a;
-        let Program {
-            errors: Errors {
-                errors: b_errors, ..
-            },
-            ..
-        } = b;
-        if a_errors.is_empty() {
-            b_errors.is_empty() && a == b
-        } else {
-            if b_errors.is_empty() {
-                return false;
-            }
-            let CompilerError { base: a_base, .. } = &a_errors[0];
-            let CompilerError { base: b_base, .. } = &b_errors[0];
-            a_base == b_base
+    // The part of an AST that the sample test compares. Error recovery differs from the
+    // reference compiler, so only the first error is compared if there is any
+    fn compared_part(ast: &Program) -> serde_json::Value {
+        match ast.errors.errors.first() {
+            None => serde_json::to_value(ast).unwrap(),
+            Some(error) => serde_json::json!({ "firstError": error.base }),
         }
     }
 
     #[test]
     fn sample() {
-        let mut passed = true;
+        let mut snapshots = Snapshots::new();
 
         let test_dirs = [
             "test/original/pa1",
@@ -65,51 +136,444 @@ mod tests {
             "test/original/pa2",
             "test/pa1",
             "test/pa2",
+            "test/ext",
         ];
 
         for dir in &test_dirs {
             println!("Testing Directory {}", dir);
-            let mut files = std::fs::read_dir(dir)
-                .unwrap()
-                .map(|f| f.unwrap())
-                .filter(|f| f.file_name().to_str().unwrap().ends_with(".py"))
-                .map(|f| f.path())
-                .collect::<Vec<_>>();
-
-            files.sort();
-
-            for source_file in files {
-                let mut ast_file = source_file.clone();
-                let mut file_name = ast_file.file_name().unwrap().to_owned();
-                print!("Testing {} ---- ", file_name.to_str().unwrap());
-                stdout().flush().unwrap();
-                file_name.push(".ast");
-                ast_file.set_file_name(file_name);
-
-                let ast_string = String::from_utf8(std::fs::read(ast_file).unwrap()).unwrap();
-                let ast_reference = serde_json::from_str::<Program>(&ast_string).unwrap();
+            // Language extensions are enabled for their own test directory
+            let options = Options {
+                class_const: dir.ends_with("ext"),
+                bitwise: dir.ends_with("ext"),
+                ..Options::default()
+            };
+
+            for source_file in snapshot::files(dir, ".py") {
+                snapshots.start(&source_file);
+                let ast_file = snapshot::with_suffix(&source_file, ".ast");
+                let ast_string = String::from_utf8(std::fs::read(&ast_file).unwrap()).unwrap();
+                let ast_reference = ast_from_json(&ast_string).unwrap();
 
                 let (sender, receiver) = std::sync::mpsc::channel();
                 std::thread::Builder::new()
                     .stack_size(16_000_000)
                     .spawn(move || {
-                        sender.send(process(source_file.as_os_str().to_str().unwrap()).unwrap())
+                        let ast =
+                            process(source_file.as_os_str().to_str().unwrap(), options).unwrap();
+                        let _ = sender.send(ast);
                     })
                     .unwrap();
 
                 if let Ok(ast) = receiver.recv_timeout(std::time::Duration::from_secs(1)) {
-                    if compare_ast(&ast, &ast_reference) {
-                        println!("\x1b[32mOK\x1b[0m");
-                    } else {
-                        println!("\x1b[31mError\x1b[0m");
-                        passed = false;
-                    }
+                    snapshots.compare(
+                        &ast_file,
+                        &compared_part(&ast_reference),
+                        &compared_part(&ast),
+                        &serde_json::to_value(&ast).unwrap(),
+                    );
                 } else {
-                    println!("\x1b[31mTimeout\x1b[0m");
-                    passed = false;
+                    snapshots.fail("timeout");
+                }
+            }
+        }
+        assert!(snapshots.passed());
+    }
+
+    // Stricter than sample: every field of error-free ASTs, including the end
+    // positions, must serialize to exactly the reference JSON
+    #[test]
+    fn exact_ast() {
+        let mut snapshots = Snapshots::new();
+
+        let test_dirs = ["test/original/pa1", "test/original/pa1/hidden", "test/pa1"];
+
+        for dir in &test_dirs {
+            for source_file in snapshot::files(dir, ".py") {
+                let ast_file = snapshot::with_suffix(&source_file, ".ast");
+                let reference: serde_json::Value =
+                    serde_json::from_slice(&std::fs::read(&ast_file).unwrap()).unwrap();
+                if reference["errors"]["errors"] != serde_json::json!([]) {
+                    continue;
                 }
+
+                snapshots.start(&source_file);
+                let ast = process(source_file.to_str().unwrap(), Options::default()).unwrap();
+                let ast = serde_json::to_value(&ast).unwrap();
+                snapshots.compare(&ast_file, &reference, &ast, &ast);
             }
         }
-        assert!(passed);
+        assert!(snapshots.passed());
+    }
+
+    #[test]
+    fn dump() {
+        let source = "if x:\n    y = 1\nz\n";
+        assert_eq!(
+            token_dump(source, Default::default()),
+            "\
+1:1-1:2 If
+1:4-1:4 Identifier(\"x\")
+1:5-1:5 Colon
+1:6-1:6 NewLine
+2:1-2:4 Indent
+2:5-2:5 Identifier(\"y\")
+2:7-2:7 Assign
+2:9-2:9 Number(1)
+2:10-2:10 NewLine
+3:0-3:0 Dedent
+3:1-3:1 Identifier(\"z\")
+3:2-3:2 NewLine
+3:1-3:1 Eof
+"
+        );
+    }
+
+    #[test]
+    fn keep_comments() {
+        let source = "\
+x:int = 0
+# Unrelated
+
+# Adds one
+#  to the input
+def f(y:int) -> int:
+    # Local
+    z:int = 1
+    return y + z # Not attached
+f(x)
+";
+        let get_token = lexer::Lexer::new(source, true, false);
+        let ast = parser::parse(get_token, false);
+        assert!(ast.errors.errors.is_empty());
+        assert!(ast.declarations[0].base().comments.is_empty());
+        let func = match &ast.declarations[1] {
+            Declaration::FuncDef(func) => func,
+            _ => panic!(),
+        };
+        assert_eq!(func.base.comments, [" Adds one", "  to the input"]);
+        assert_eq!(func.declarations[0].base().comments, [" Local"]);
+
+        let get_token = lexer::Lexer::new(source, false, false);
+        let ast = parser::parse(get_token, false);
+        assert!(ast.declarations[1].base().comments.is_empty());
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        let error_at = |source: &str| {
+            let ast = process_str(source, Default::default());
+            ast.errors.errors.first().unwrap().base.location
+        };
+        // The line break added at the end of the last line
+        assert_eq!(error_at("x:int = 1\nx = x +"), Location::new(2, 8, 2, 8));
+        assert_eq!(
+            error_at("def f() -> int:\n    return 1 +"),
+            Location::new(2, 14, 2, 14)
+        );
+        // End of file is reported on the last character, not on a row past the end
+        assert_eq!(error_at("if True:"), Location::new(1, 8, 1, 8));
+        assert_eq!(error_at("while True:\n\n"), Location::new(1, 11, 1, 11));
+        // Unterminated strings end at the line break
+        assert_eq!(error_at("x = \"ab"), Location::new(1, 5, 1, 7));
+        assert_eq!(
+            error_at("def f() -> str:\n    return \"ab\\"),
+            Location::new(2, 12, 2, 15)
+        );
+    }
+
+    #[test]
+    fn array_type_hint() {
+        let get_token = lexer::Lexer::new("x:int[] = None\ny:[int] = None\n", false, false);
+        let ast = parser::parse(get_token, false);
+        assert_eq!(ast.errors.errors.len(), 1);
+        let error = &ast.errors.errors[0];
+        assert_eq!(error.message, "array types are written as [T], not T[]");
+        assert_eq!(error.base.location, Location::new(1, 3, 1, 7));
+        assert_eq!(ast.declarations.len(), 2);
+    }
+
+    #[test]
+    fn id_string_type() {
+        // Types of the variables and the function
+        let types = |source: &str| {
+            let ast = process_str(source, Default::default());
+            assert!(ast.errors.errors.is_empty(), "{}", source);
+            let mut types = vec![];
+            for declaration in &ast.declarations {
+                match declaration {
+                    Declaration::VarDef(v) => types.push(&v.var.type_),
+                    Declaration::FuncDef(f) => {
+                        types.extend(f.params.iter().map(|p| &p.type_));
+                        types.push(&f.return_type);
+                    }
+                    _ => (),
+                }
+            }
+            types
+                .into_iter()
+                .map(|t| ValueType::from_annotation(t).to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let bare = "x:A = None\ny:[[A]] = None\ndef f(a:A) -> [A]:\n    pass\n";
+        let quoted = "x:\"A\" = None\ny:[[\"A\"]] = None\ndef f(a:\"A\") -> [\"A\"]:\n    pass\n";
+        assert_eq!(types(bare), ["A", "[[A]]", "A", "[A]"]);
+        assert_eq!(types(quoted), types(bare));
+
+        // Strings that don't decode to an identifier can't be types
+        for source in &[
+            "x:\"A\\n\" = None\n",
+            "x:\"A B\" = None\n",
+            "x:\"1\" = None\n",
+        ] {
+            let ast = process_str(source, Default::default());
+            assert!(!ast.errors.errors.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn class_const_soft_keyword() {
+        let source = "class A(object):\n    CONST x:int = 1\n    CONST:int = 2\n";
+        let parse = |class_const| {
+            let get_token = lexer::Lexer::new(source, false, false);
+            parser::parse(get_token, class_const)
+        };
+
+        let ast = parse(true);
+        assert!(ast.errors.errors.is_empty());
+        let class = match &ast.declarations[0] {
+            Declaration::ClassDef(class) => class,
+            _ => panic!(),
+        };
+        let constants: Vec<_> = class
+            .declarations
+            .iter()
+            .map(|d| match d {
+                Declaration::VarDef(v) => (v.var.identifier.name.as_str(), v.constant),
+                _ => panic!(),
+            })
+            .collect();
+        assert_eq!(constants, [("x", true), ("CONST", false)]);
+        assert_eq!(class.declarations[0].base().location.start.col, 5);
+
+        assert!(!parse(false).errors.errors.is_empty());
+    }
+
+    #[test]
+    fn bitwise_precedence() {
+        let options = Options {
+            bitwise: true,
+            ..Options::default()
+        };
+        let ast = process_str("1 | 2 ^ 3 & 4 << 5 + 6 == 7\n", options);
+        assert!(ast.errors.errors.is_empty());
+
+        // Prints the expression with full parentheses
+        fn show(expr: &Expr) -> String {
+            match &expr.content {
+                ExprContent::BinaryExpr(e) => format!(
+                    "({} {} {})",
+                    show(&e.left),
+                    serde_json::to_value(&e.operator).unwrap().as_str().unwrap(),
+                    show(&e.right)
+                ),
+                ExprContent::IntegerLiteral(i) => i.value.to_string(),
+                _ => panic!(),
+            }
+        }
+        if let Stmt::ExprStmt(s) = &ast.statements[0] {
+            assert_eq!(show(&s.expr), "((1 | (2 ^ (3 & (4 << (5 + 6))))) == 7)");
+        } else {
+            panic!()
+        }
+
+        for source in &[
+            "x = 1 & 2\n",
+            "x = 1 | 2\n",
+            "x = 1 ^ 2\n",
+            "x = 1 << 2\n",
+            "x = 1 >> 2\n",
+        ] {
+            assert!(process_str(source, options).errors.errors.is_empty());
+            let ast = process_str(source, Options::default());
+            assert!(!ast.errors.errors.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn membership_precedence() {
+        // Prints the expression with full parentheses
+        fn show(expr: &Expr) -> String {
+            match &expr.content {
+                ExprContent::BinaryExpr(e) => format!(
+                    "({} {} {})",
+                    show(&e.left),
+                    e.operator.symbol(),
+                    show(&e.right)
+                ),
+                ExprContent::UnaryExpr(e) => format!("(not {})", show(&e.operand)),
+                ExprContent::IntegerLiteral(i) => i.value.to_string(),
+                ExprContent::Variable(v) => v.name.clone(),
+                _ => panic!(),
+            }
+        }
+
+        let ast = process_str("not 1 + 2 in l\n", Options::default());
+        assert!(ast.errors.errors.is_empty());
+        if let Stmt::ExprStmt(s) = &ast.statements[0] {
+            assert_eq!(show(&s.expr), "(not ((1 + 2) in l))");
+        } else {
+            panic!()
+        }
+
+        // The `in` of the header comes first, and the rest is the iterable
+        let ast = process_str("for x in a in b:\n    pass\n", Options::default());
+        assert!(ast.errors.errors.is_empty());
+        if let Stmt::ForStmt(s) = &ast.statements[0] {
+            assert_eq!(s.identifier.name, "x");
+            assert_eq!(show(&s.iterable), "(a in b)");
+        } else {
+            panic!()
+        }
+
+        // Comparisons don't chain
+        let ast = process_str("a in b in c\n", Options::default());
+        assert_eq!(ast.errors.errors.len(), 1);
+    }
+
+    #[test]
+    fn error_spans() {
+        // Errors on dedents and the end of file cover a column too
+        for source in &[
+            "x = 1\n  y = 2\nz = 3\n",
+            "if True:\n    x = 1\n  y = 2\n",
+            "class A(object):\nx = 1\n",
+            "def f() -> int:\n",
+            "def f() -> int:\n    return (1 +\n",
+            "x = 99999999999\n",
+            "x:int[] = None\n",
+        ] {
+            let ast = process_str(source, Options::default());
+            assert!(!ast.errors.errors.is_empty(), "{}", source);
+            for error in &ast.errors.errors {
+                assert!(!error.base.location.is_empty(), "{}{:?}", source, error);
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_comma() {
+        let ast = process_str(
+            "def f(a:int, b:[int],) -> int:\n    return a\nf(1, [2, 3,],)\n",
+            Default::default(),
+        );
+        assert!(ast.errors.errors.is_empty());
+        if let Declaration::FuncDef(f) = &ast.declarations[0] {
+            assert_eq!(f.params.len(), 2);
+        } else {
+            panic!()
+        }
+        if let Stmt::ExprStmt(ExprStmt {
+            expr:
+                Expr {
+                    content: ExprContent::CallExpr(call),
+                    ..
+                },
+            ..
+        }) = &ast.statements[0]
+        {
+            assert_eq!(call.args.len(), 2);
+            assert_eq!(call.base.location, Location::new(3, 1, 3, 14));
+            if let ExprContent::ListExpr(list) = &call.args[1].content {
+                assert_eq!(list.elements.len(), 2);
+            } else {
+                panic!()
+            }
+        } else {
+            panic!()
+        }
+
+        for source in &["x = [,]\n", "f(,)\n", "def f(,):\n    pass\n", "f(1,,)\n"] {
+            let ast = process_str(source, Default::default());
+            assert!(!ast.errors.errors.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn integer_literal_range() {
+        let error_at = |source: &str| {
+            let ast = process_str(source, Default::default());
+            let error = ast.errors.errors.first()?;
+            assert_eq!(error.message, "integer literal out of range");
+            Some(error.base.location)
+        };
+        assert_eq!(error_at("x = 2147483647\n"), None);
+        assert_eq!(error_at("x = -2147483648\n"), None);
+        assert_eq!(error_at("x = - -2147483648\n"), None);
+        assert_eq!(
+            error_at("x = 2147483648\n"),
+            Some(Location::new(1, 5, 1, 14))
+        );
+        assert_eq!(
+            error_at("x = -2147483649\n"),
+            Some(Location::new(1, 6, 1, 15))
+        );
+        assert_eq!(
+            error_at("x = 1 - 2147483648\n"),
+            Some(Location::new(1, 9, 1, 18))
+        );
+        assert_eq!(
+            error_at("x = -(2147483648)\n"),
+            Some(Location::new(1, 7, 1, 16))
+        );
+        assert_eq!(
+            error_at("x:int = 123456789012345678901234567890\n"),
+            Some(Location::new(1, 9, 1, 38))
+        );
+        assert_eq!(
+            error_at("class A(object):\n    x:int = 2147483648\n"),
+            Some(Location::new(2, 13, 2, 22))
+        );
+
+        let ast = process_str("-2147483648\n", Default::default());
+        if let Stmt::ExprStmt(ExprStmt {
+            expr:
+                Expr {
+                    content: ExprContent::IntegerLiteral(literal),
+                    ..
+                },
+            ..
+        }) = &ast.statements[0]
+        {
+            assert_eq!(literal.value, i32::MIN);
+            assert_eq!(literal.base.location, Location::new(1, 1, 1, 11));
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let dir = "test/original/pa3";
+        let mut files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|f| f.unwrap().path())
+            .filter(|f| f.to_str().unwrap().ends_with(".ast.typed"))
+            .collect::<Vec<_>>();
+        files.sort();
+        assert!(!files.is_empty());
+
+        let bin_path = crate::gen::test_util::temp_path(".ast.typed.bin");
+        for json_path in files {
+            let json_path = json_path.to_str().unwrap();
+            let ast = load_ast(json_path).unwrap();
+            let binary = to_binary(&ast);
+            assert!(binary.len() < std::fs::metadata(json_path).unwrap().len() as usize);
+
+            std::fs::write(&bin_path, binary).unwrap();
+            let loaded = load_ast(bin_path.to_str().unwrap()).unwrap();
+            assert!(loaded == ast, "{} differs after round trip", json_path);
+        }
+        std::fs::remove_file(&bin_path).unwrap();
     }
 }
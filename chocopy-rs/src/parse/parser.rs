@@ -1,25 +1,53 @@
 use super::token::*;
+use crate::explain;
 use crate::location::*;
 use crate::node::*;
 use std::cmp::Ordering;
 use std::collections::vec_deque::VecDeque;
+use std::convert::TryFrom;
 
 fn unexpected(token: ComplexToken) -> CompilerError {
+    // Tokens that only mark a position can have an empty range. The error is shown on the
+    // column at the position instead
+    let mut location = token.location;
+    if location.is_empty() {
+        location.start.col = location.start.col.max(1);
+        location.end = location.start;
+    }
     CompilerError {
-        base: NodeBase::from_location(token.location),
+        base: NodeBase::from_location(location),
         message: "unexptected token".to_owned(),
+        code: Some(explain::SYNTAX.to_owned()),
         syntax: true,
+        friendly: None,
     }
 }
 
+const NEGATIVE_LITERAL_LIMIT: i64 = -(i32::MIN as i64);
+
+fn integer_literal(base: NodeBase, value: i64, errors: &mut Vec<CompilerError>) -> IntegerLiteral {
+    let value = i32::try_from(value).unwrap_or_else(|_| {
+        errors.push(CompilerError {
+            base: base.clone(),
+            message: "integer literal out of range".to_owned(),
+            code: Some(explain::SYNTAX.to_owned()),
+            syntax: true,
+            friendly: None,
+        });
+        0
+    });
+    IntegerLiteral { base, value }
+}
+
 macro_rules! parse_expr_unary {
     ($name:ident, $parse_next:ident, $operator_token:expr => $operator_name:expr) => {
         fn $name(&mut self) -> Option<Expr> {
             let start = self.next_pos();
 
-            let token = self.take();
-            let expr = if token.token == $operator_token {
-                let expr = self.$parse_next()?;
+            let expr = if *self.peek() == $operator_token {
+                self.take();
+                // The operand can have the same operator again, as in `not not x`
+                let expr = self.$name()?;
 
                 let end = self.prev_pos().unwrap_or(start);
                 Expr::UnaryExpr(Box::new(UnaryExpr {
@@ -28,7 +56,6 @@ macro_rules! parse_expr_unary {
                     operand: expr,
                 }))
             } else {
-                self.push_back(token);
                 self.$parse_next()?
             };
             Some(expr)
@@ -44,14 +71,11 @@ macro_rules! parse_expr_binary {
             let mut expr = self.$parse_next()?;
 
             loop {
-                let token = self.take();
-                let operator = match token.token {
+                let operator = match self.peek() {
                     $( $operator_token => $operator_name, )*
-                    _ => {
-                        self.push_back(token);
-                        break;
-                    }
+                    _ => break,
                 };
+                self.take();
 
                 let right = self.$parse_next()?;
 
@@ -71,98 +95,111 @@ macro_rules! parse_expr_binary {
 
 struct Parser<F> {
     receiver: F,
-    buffer: Vec<ComplexToken>,
-    prev_pos_buf: VecDeque<Position>,
+    lookahead: VecDeque<ComplexToken>, // peek() and peek2() fill at most two tokens
+    prev_pos: Option<Position>,        // end of the last consumed token
     eof: Option<ComplexToken>,
     errors: Vec<CompilerError>,
+    comments: Vec<(u32, String)>,
+    class_const: bool,
 }
 
 impl<F: Iterator<Item = ComplexToken>> Parser<F> {
-    fn new(receiver: F) -> Parser<F> {
+    fn new(receiver: F, class_const: bool) -> Parser<F> {
         Parser {
             receiver,
-            buffer: vec![],
-            prev_pos_buf: VecDeque::new(),
+            lookahead: VecDeque::with_capacity(2),
+            prev_pos: None,
             eof: None,
             errors: vec![],
+            comments: vec![],
+            class_const,
         }
     }
 
-    fn take(&mut self) -> ComplexToken {
-        if self.prev_pos_buf.len() > 3 {
-            self.prev_pos_buf.pop_front();
-        }
-        if let Some(token) = self.buffer.pop() {
-            self.prev_pos_buf.push_back(token.location.end);
-            return token;
-        }
-        if let Some(token) = self.eof.clone() {
-            self.prev_pos_buf.push_back(token.location.end);
-            return token;
+    // Pulls the next token from the lexer, collecting comments on the way.
+    // Once EOF is reached it is returned forever.
+    fn fetch(&mut self) -> ComplexToken {
+        if let Some(token) = &self.eof {
+            return token.clone();
         }
-        let token = self.receiver.next().unwrap();
-        self.prev_pos_buf.push_back(token.location.end);
+        let token = loop {
+            let token = self.receiver.next().unwrap();
+            if let Token::Comment(text) = token.token {
+                self.comments.push((token.location.start.row, text));
+            } else {
+                break token;
+            }
+        };
         if token.token == Token::Eof {
             self.eof = Some(token.clone());
         }
         token
     }
 
-    fn push_back(&mut self, v: ComplexToken) {
-        self.prev_pos_buf.pop_back();
-        self.buffer.push(v);
+    fn fill_lookahead(&mut self, count: usize) {
+        while self.lookahead.len() < count {
+            let token = self.fetch();
+            self.lookahead.push_back(token);
+        }
+    }
+
+    fn peek(&mut self) -> &Token {
+        self.fill_lookahead(1);
+        &self.lookahead[0].token
+    }
+
+    fn peek2(&mut self) -> &Token {
+        self.fill_lookahead(2);
+        &self.lookahead[1].token
+    }
+
+    fn take(&mut self) -> ComplexToken {
+        let token = match self.lookahead.pop_front() {
+            Some(token) => token,
+            None => self.fetch(),
+        };
+        self.prev_pos = Some(token.location.end);
+        token
     }
 
     // For error recovery. Skip pass the next NEWLINE token,
     // and skip the following INDEND..DEDENT block if any.
     fn skip_to_next_line(&mut self) {
         loop {
-            let token = self.take();
-            match token.token {
-                Token::Eof => {
-                    self.push_back(token);
-                    return;
+            match self.peek() {
+                Token::Eof => return,
+                Token::NewLine => {
+                    self.take();
+                    break;
+                }
+                _ => {
+                    self.take();
                 }
-                Token::NewLine => break,
-                _ => (),
             }
         }
-        let token = self.take();
-        if token.token != Token::Indent {
-            self.push_back(token);
+        if *self.peek() != Token::Indent {
             return;
         }
+        self.take();
         let mut level = 1;
-        loop {
-            let token = self.take();
-            match token.token {
-                Token::Eof => {
-                    self.push_back(token);
-                    return;
-                }
-                Token::Dedent => {
-                    level -= 1;
-                    if level == 0 {
-                        return;
-                    }
-                }
-                Token::Indent => {
-                    level += 1;
-                }
+        while level > 0 {
+            match self.peek() {
+                Token::Eof => return,
+                Token::Dedent => level -= 1,
+                Token::Indent => level += 1,
                 _ => (),
             }
+            self.take();
         }
     }
 
     fn next_pos(&mut self) -> Position {
-        let next = self.take();
-        let start = next.location.start;
-        self.push_back(next);
-        start
+        self.fill_lookahead(1);
+        self.lookahead[0].location.start
     }
 
     fn prev_pos(&self) -> Option<Position> {
-        self.prev_pos_buf.back().cloned()
+        self.prev_pos
     }
 
     fn eat(&mut self, expected_token: Token) -> Option<()> {
@@ -194,11 +231,10 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
 
         let then_expr = self.parse_expr2()?;
 
-        let token = self.take();
-        if token.token != Token::If {
-            self.push_back(token);
+        if *self.peek() != Token::If {
             return Some(then_expr);
         }
+        self.take();
 
         let condition = self.parse_expr1()?;
 
@@ -223,10 +259,9 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
     fn parse_expr5(&mut self) -> Option<Expr> {
         let start = self.next_pos();
 
-        let left = self.parse_expr6()?;
+        let left = self.parse_bit_or()?;
 
-        let token = self.take();
-        let operator = match token.token {
+        let operator = match self.peek() {
             Token::Equal => BinaryOp::Eq,
             Token::NotEqual => BinaryOp::Ne,
             Token::Less => BinaryOp::Lt,
@@ -234,13 +269,14 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
             Token::LessEqual => BinaryOp::Le,
             Token::GreaterEqual => BinaryOp::Ge,
             Token::Is => BinaryOp::Is,
-            _ => {
-                self.push_back(token);
-                return Some(left);
-            }
+            // Extension: membership test, checked by the type checker. The `in` of a for-loop
+            // header is taken before its iterable, so an `in` inside the iterable is this one
+            Token::In => BinaryOp::In,
+            _ => return Some(left),
         };
+        self.take();
 
-        let right = self.parse_expr6()?;
+        let right = self.parse_bit_or()?;
         let end = self.prev_pos().unwrap_or(start);
 
         Some(Expr::BinaryExpr(Box::new(BinaryExpr {
@@ -251,6 +287,15 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         })))
     }
 
+    // Bitwise operators from the extension. Without it the lexer never produces them
+    parse_expr_binary!(parse_bit_or, parse_bit_xor, Token::BitOr => BinaryOp::BitOr);
+    parse_expr_binary!(parse_bit_xor, parse_bit_and, Token::BitXor => BinaryOp::BitXor);
+    parse_expr_binary!(parse_bit_and, parse_shift, Token::BitAnd => BinaryOp::BitAnd);
+    parse_expr_binary!(parse_shift, parse_expr6,
+        Token::ShiftLeft => BinaryOp::Shl,
+        Token::ShiftRight => BinaryOp::Shr
+    );
+
     parse_expr_binary!(parse_expr6, parse_expr7,
         Token::Plus => BinaryOp::Add,
         Token::Minus => BinaryOp::Sub
@@ -262,7 +307,38 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         Token::Mod => BinaryOp::Mod
     );
 
-    parse_expr_unary!(parse_expr8, parse_expr9, Token::Minus => UnaryOp::Negative);
+    fn parse_expr8(&mut self) -> Option<Expr> {
+        let start = self.next_pos();
+
+        if *self.peek() != Token::Minus {
+            return self.parse_expr9();
+        }
+        self.take();
+
+        // 2147483648 is only representable as the direct operand of a minus sign
+        if *self.peek() == Token::Number(NEGATIVE_LITERAL_LIMIT)
+            && !matches!(
+                self.peek2(),
+                Token::Dot | Token::LeftSquare | Token::LeftPar
+            )
+        {
+            let end = self.take().location.end;
+            return Some(Expr::IntegerLiteral(IntegerLiteral {
+                base: NodeBase::from_positions(start, end),
+                value: i32::MIN,
+            }));
+        }
+
+        // The operand can have the same operator again, as in `- -x`
+        let expr = self.parse_expr8()?;
+
+        let end = self.prev_pos().unwrap_or(start);
+        Some(Expr::UnaryExpr(Box::new(UnaryExpr {
+            base: NodeBase::from_positions(start, end),
+            operator: UnaryOp::Negative,
+            operand: expr,
+        })))
+    }
 
     fn parse_expr9(&mut self) -> Option<Expr> {
         let start = self.next_pos();
@@ -271,21 +347,27 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         let mut expr = self.parse_expr10()?;
 
         loop {
-            let token = self.take();
-            match token.token {
+            match self.peek() {
                 Token::LeftPar => {
+                    let token = self.take();
                     let mut args = vec![];
 
-                    let token_head = self.take();
-                    if token_head.token != Token::RightPar {
-                        self.push_back(token_head);
+                    if *self.peek() == Token::RightPar {
+                        self.take();
+                    } else {
                         loop {
                             if let Some(arg) = self.parse_expr1() {
                                 args.push(arg);
                             }
                             let token = self.take();
                             match token.token {
-                                Token::Comma => (),
+                                Token::Comma => {
+                                    // Allow a trailing comma
+                                    if *self.peek() == Token::RightPar {
+                                        self.take();
+                                        break;
+                                    }
+                                }
                                 Token::RightPar => break,
                                 _ => {
                                     self.errors.push(unexpected(token));
@@ -326,6 +408,7 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     }
                 }
                 Token::LeftSquare => {
+                    self.take();
                     let index = self.parse_expr1()?;
                     self.eat(Token::RightSquare)?;
                     let end = self.prev_pos().unwrap_or(start);
@@ -337,6 +420,7 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     }));
                 }
                 Token::Dot => {
+                    self.take();
                     let member = self.take_id()?;
                     let end = self.prev_pos().unwrap_or(start);
 
@@ -346,10 +430,7 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                         member,
                     }));
                 }
-                _ => {
-                    self.push_back(token);
-                    break;
-                }
+                _ => break,
             }
         }
 
@@ -368,7 +449,9 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
             Token::None => Expr::NoneLiteral(NoneLiteral { base }),
             Token::True => Expr::BooleanLiteral(BooleanLiteral { base, value: true }),
             Token::False => Expr::BooleanLiteral(BooleanLiteral { base, value: false }),
-            Token::Number(value) => Expr::IntegerLiteral(IntegerLiteral { base, value }),
+            Token::Number(value) => {
+                Expr::IntegerLiteral(integer_literal(base, value, &mut self.errors))
+            }
             Token::StringLiteral(value) | Token::IdString(value) => {
                 Expr::StringLiteral(StringLiteral { base, value })
             }
@@ -380,16 +463,22 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
             Token::LeftSquare => {
                 let mut elements = vec![];
 
-                let token = self.take();
-                if token.token != Token::RightSquare {
-                    self.push_back(token);
+                if *self.peek() == Token::RightSquare {
+                    self.take();
+                } else {
                     loop {
                         if let Some(element) = self.parse_expr1() {
                             elements.push(element);
                         }
                         let token = self.take();
                         match token.token {
-                            Token::Comma => (),
+                            Token::Comma => {
+                                // Allow a trailing comma
+                                if *self.peek() == Token::RightSquare {
+                                    self.take();
+                                    break;
+                                }
+                            }
                             Token::RightSquare => break,
                             _ => {
                                 self.errors.push(unexpected(token));
@@ -462,12 +551,9 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
 
         self.eat(Token::Return)?;
 
-        let token = self.take();
-        let value = if token.token == Token::NewLine {
-            self.push_back(token);
+        let value = if *self.peek() == Token::NewLine {
             None
         } else {
-            self.push_back(token);
             Some(self.parse_expr1()?)
         };
 
@@ -508,17 +594,14 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
 
         self.eat(Token::For)?;
 
-        let token = self.take();
-        let identifier = if let Token::Identifier(name) = token.token {
-            ForTarget {
-                inferred_type: None,
-                base: NodeBase::from_location(token.location),
-                name,
-            }
-        } else {
-            self.errors.push(unexpected(token));
-            return None;
-        };
+        let mut identifier = self.parse_for_target()?;
+
+        // Extension: "for index, element in enumerate(...)", checked by the type checker
+        let mut index = None;
+        if *self.peek() == Token::Comma {
+            self.take();
+            index = Some(std::mem::replace(&mut identifier, self.parse_for_target()?));
+        }
 
         self.eat(Token::In)?;
 
@@ -529,12 +612,27 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
 
         Some(ForStmt {
             base: NodeBase::from_positions(start, end),
+            index,
             identifier,
             iterable,
             body,
         })
     }
 
+    fn parse_for_target(&mut self) -> Option<ForTarget> {
+        let token = self.take();
+        if let Token::Identifier(name) = token.token {
+            Some(ForTarget {
+                inferred_type: None,
+                base: NodeBase::from_location(token.location),
+                name,
+            })
+        } else {
+            self.errors.push(unexpected(token));
+            None
+        }
+    }
+
     fn parse_if(&mut self) -> Option<IfStmt> {
         let start = self.next_pos();
 
@@ -547,17 +645,13 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         let condition = self.parse_expr1()?;
         let then_body = self.parse_block()?;
 
-        let token = self.take();
-        let else_body = match token.token {
-            Token::Else => self.parse_block()?,
-            Token::Elif => {
-                self.push_back(token);
-                vec![Stmt::IfStmt(self.parse_if()?)]
-            }
-            _ => {
-                self.push_back(token);
-                vec![]
+        let else_body = match self.peek() {
+            Token::Else => {
+                self.take();
+                self.parse_block()?
             }
+            Token::Elif => vec![Stmt::IfStmt(self.parse_if()?)],
+            _ => vec![],
         };
 
         let end = self.prev_pos().unwrap_or(start);
@@ -574,13 +668,10 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         let mut stmt_list = vec![];
 
         loop {
-            let token = self.take();
-            match token.token {
-                Token::Eof | Token::Dedent => {
-                    self.push_back(token);
-                    break;
-                }
+            match self.peek() {
+                Token::Eof | Token::Dedent => break,
                 Token::Pass => {
+                    self.take();
                     let token = self.take();
                     if token.token != Token::NewLine {
                         self.errors.push(unexpected(token));
@@ -588,7 +679,6 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     }
                 }
                 Token::Return => {
-                    self.push_back(token);
                     if let Some(return_stmt) = self.parse_return() {
                         stmt_list.push(Stmt::ReturnStmt(return_stmt));
                     } else {
@@ -596,7 +686,6 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     }
                 }
                 Token::While => {
-                    self.push_back(token);
                     if let Some(while_stmt) = self.parse_while() {
                         stmt_list.push(Stmt::WhileStmt(while_stmt));
                     } else {
@@ -604,7 +693,6 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     }
                 }
                 Token::For => {
-                    self.push_back(token);
                     if let Some(for_stmt) = self.parse_for() {
                         stmt_list.push(Stmt::ForStmt(for_stmt));
                     } else {
@@ -612,7 +700,6 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     }
                 }
                 Token::If => {
-                    self.push_back(token);
                     if let Some(if_stmt) = self.parse_if() {
                         stmt_list.push(Stmt::IfStmt(if_stmt));
                     } else {
@@ -620,7 +707,6 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     }
                 }
                 _ => {
-                    self.push_back(token);
                     if let Some(stmt) = self.parse_assign_or_expr_stmt() {
                         stmt_list.push(stmt);
                     } else {
@@ -636,22 +722,15 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
     fn parse_decl_in_class(&mut self) -> Option<Vec<Declaration>> {
         let mut declarations = vec![];
 
-        let token = self.take();
-        if token.token == Token::Pass {
+        if *self.peek() == Token::Pass {
+            self.take();
             self.eat(Token::NewLine)?;
         } else {
             // Parse "[func_def|var_def]* }"
-            self.push_back(token);
-
             loop {
-                let token = self.take();
-                match token.token {
-                    Token::Dedent => {
-                        self.push_back(token);
-                        break;
-                    }
+                match self.peek() {
+                    Token::Dedent => break,
                     Token::Def => {
-                        self.push_back(token);
                         if let Some(func_def) = self.parse_func_def() {
                             declarations.push(Declaration::FuncDef(func_def));
                         } else {
@@ -659,8 +738,20 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                         }
                     }
                     _ => {
-                        self.push_back(token);
-                        if let Some(var_def) = self.parse_var_def() {
+                        // "CONST" is a soft keyword only when followed by the constant name
+                        let start = self.next_pos();
+                        let constant = self.class_const
+                            && *self.peek() == Token::Identifier("CONST".to_owned())
+                            && matches!(self.peek2(), Token::Identifier(_));
+                        if constant {
+                            self.take();
+                        }
+
+                        if let Some(mut var_def) = self.parse_var_def() {
+                            if constant {
+                                var_def.base.location.start = start;
+                                var_def.constant = true;
+                            }
                             declarations.push(Declaration::VarDef(var_def));
                         } else {
                             self.skip_to_next_line();
@@ -705,17 +796,16 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         let mut declarations = vec![];
 
         loop {
-            let head = self.take();
-            match head.token {
+            match self.peek() {
                 Token::Def => {
-                    self.push_back(head);
                     if let Some(func_def) = self.parse_func_def() {
                         declarations.push(Declaration::FuncDef(func_def));
                     } else {
                         self.skip_to_next_line();
                     }
                 }
-                scope @ Token::Global | scope @ Token::Nonlocal => {
+                Token::Global | Token::Nonlocal => {
+                    let head = self.take();
                     let start = head.location.start;
                     let variable = self.take_id()?;
                     let end = self.prev_pos().unwrap_or(start);
@@ -729,7 +819,7 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
 
                     let base = NodeBase::from_positions(start, end);
 
-                    let declaration = if scope == Token::Global {
+                    let declaration = if head.token == Token::Global {
                         Declaration::GlobalDecl(GlobalDecl { base, variable })
                     } else {
                         Declaration::NonLocalDecl(NonLocalDecl { base, variable })
@@ -738,20 +828,14 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     declarations.push(declaration);
                 }
                 _ => {
-                    let second = self.take();
-                    if second.token == Token::Colon {
-                        self.push_back(second);
-                        self.push_back(head);
-                        if let Some(var_def) = self.parse_var_def() {
-                            declarations.push(Declaration::VarDef(var_def));
-                        } else {
-                            self.skip_to_next_line();
-                        }
-                    } else {
-                        self.push_back(second);
-                        self.push_back(head);
+                    if *self.peek2() != Token::Colon {
                         break;
                     }
+                    if let Some(var_def) = self.parse_var_def() {
+                        declarations.push(Declaration::VarDef(var_def));
+                    } else {
+                        self.skip_to_next_line();
+                    }
                 }
             }
         }
@@ -768,17 +852,23 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         self.eat(Token::LeftPar)?;
 
         // Parse "typed_var,* )"
-        let token = self.take();
         let mut params = vec![];
-        if token.token != Token::RightPar {
-            self.push_back(token);
+        if *self.peek() == Token::RightPar {
+            self.take();
+        } else {
             loop {
                 let typed_var = self.parse_typed_var()?;
                 params.push(typed_var);
 
                 let token = self.take();
                 match token.token {
-                    Token::Comma => (),
+                    Token::Comma => {
+                        // Allow a trailing comma
+                        if *self.peek() == Token::RightPar {
+                            self.take();
+                            break;
+                        }
+                    }
                     Token::RightPar => break,
                     _ => {
                         self.errors.push(unexpected(token));
@@ -844,7 +934,9 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
             Token::None => Literal::NoneLiteral(NoneLiteral { base }),
             Token::True => Literal::BooleanLiteral(BooleanLiteral { base, value: true }),
             Token::False => Literal::BooleanLiteral(BooleanLiteral { base, value: false }),
-            Token::Number(value) => Literal::IntegerLiteral(IntegerLiteral { base, value }),
+            Token::Number(value) => {
+                Literal::IntegerLiteral(integer_literal(base, value, &mut self.errors))
+            }
             Token::StringLiteral(value) | Token::IdString(value) => {
                 Literal::StringLiteral(StringLiteral { base, value })
             }
@@ -863,6 +955,7 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
             base: NodeBase::from_positions(start, end),
             var: typed_var,
             value,
+            constant: false,
         })
     }
 
@@ -871,7 +964,42 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
 
         let token = self.take();
         match token.token {
-            Token::Identifier(class_name) | Token::IdString(class_name) => {
+            Token::Identifier(class_name)
+                if class_name == "Callable"
+                    && *self.peek() == Token::LeftSquare
+                    && *self.peek2() == Token::LeftSquare =>
+            {
+                self.parse_callable_type(start)
+            }
+            Token::Identifier(class_name) => {
+                let mut end = self.prev_pos().unwrap_or(start);
+                let class_type = TypeAnnotation::ClassType(ClassType {
+                    base: NodeBase::from_positions(start, end),
+                    class_name,
+                });
+
+                // Catch C-style array type "T[]" and recover as "[T]"
+                if *self.peek() != Token::LeftSquare {
+                    return Some(class_type);
+                }
+                end = self.take().location.end;
+                if *self.peek() == Token::RightSquare {
+                    end = self.take().location.end;
+                }
+                self.errors.push(CompilerError {
+                    base: NodeBase::from_positions(start, end),
+                    message: "array types are written as [T], not T[]".to_owned(),
+                    code: Some(explain::SYNTAX.to_owned()),
+                    syntax: true,
+                    friendly: None,
+                });
+
+                Some(TypeAnnotation::ListType(Box::new(ListType {
+                    base: NodeBase::from_positions(start, end),
+                    element_type: class_type,
+                })))
+            }
+            Token::IdString(class_name) => {
                 let end = self.prev_pos().unwrap_or(start);
 
                 Some(TypeAnnotation::ClassType(ClassType {
@@ -898,6 +1026,45 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         }
     }
 
+    // Parse the rest of "Callable[[type, ...], type]" after "Callable", where the return type
+    // can also be None (extension)
+    fn parse_callable_type(&mut self, start: Position) -> Option<TypeAnnotation> {
+        self.eat(Token::LeftSquare)?;
+        self.eat(Token::LeftSquare)?;
+
+        let mut parameters = vec![];
+        if *self.peek() != Token::RightSquare {
+            loop {
+                parameters.push(self.parse_type_annotation()?);
+                if *self.peek() != Token::Comma {
+                    break;
+                }
+                self.take();
+            }
+        }
+        self.eat(Token::RightSquare)?;
+        self.eat(Token::Comma)?;
+
+        let return_type = if *self.peek() == Token::None {
+            let token = self.take();
+            TypeAnnotation::ClassType(ClassType {
+                base: NodeBase::from_location(token.location),
+                class_name: "<None>".to_owned(),
+            })
+        } else {
+            self.parse_type_annotation()?
+        };
+        self.eat(Token::RightSquare)?;
+
+        let end = self.prev_pos().unwrap_or(start);
+
+        Some(TypeAnnotation::CallableType(Box::new(CallableType {
+            base: NodeBase::from_positions(start, end),
+            parameters,
+            return_type,
+        })))
+    }
+
     fn parse_typed_var(&mut self) -> Option<TypedVar> {
         let start = self.next_pos();
 
@@ -923,11 +1090,9 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
         let mut end = start; // end excludes EOF
 
         loop {
-            let head = self.take();
-            match head.token {
+            match self.peek() {
                 Token::Eof => break,
                 Token::Class => {
-                    self.push_back(head);
                     if let Some(class_def) = self.parse_class_def() {
                         declarations.push(Declaration::ClassDef(class_def));
                     } else {
@@ -937,7 +1102,6 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     end = self.prev_pos().unwrap_or(start);
                 }
                 Token::Def => {
-                    self.push_back(head);
                     if let Some(func_def) = self.parse_func_def() {
                         declarations.push(Declaration::FuncDef(func_def));
                     } else {
@@ -947,10 +1111,7 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
                     end = self.prev_pos().unwrap_or(start);
                 }
                 _ => {
-                    let second = self.take();
-                    if second.token == Token::Colon {
-                        self.push_back(second);
-                        self.push_back(head);
+                    if *self.peek2() == Token::Colon {
                         if let Some(var_def) = self.parse_var_def() {
                             declarations.push(Declaration::VarDef(var_def));
                         } else {
@@ -959,8 +1120,6 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
 
                         end = self.prev_pos().unwrap_or(start);
                     } else {
-                        self.push_back(second);
-                        self.push_back(head);
                         let stmt_list = self.parse_stmt_list();
 
                         statements = Some(stmt_list);
@@ -982,6 +1141,8 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
 
         let statements = statements.unwrap_or_default();
 
+        attach_comments(&mut declarations, &self.comments);
+
         Program {
             base: NodeBase::from_positions(start, end),
             declarations,
@@ -989,12 +1150,38 @@ impl<F: Iterator<Item = ComplexToken>> Parser<F> {
             errors: Errors {
                 base: NodeBase::new(0, 0, 0, 0),
                 errors: self.errors,
+                warnings: vec![],
             },
         }
     }
 }
 
-pub fn parse(get_token: impl Iterator<Item = ComplexToken>) -> Program {
-    let parser = Parser::new(get_token);
+// Attaches each block of consecutive full-line comments to the declaration
+// starting on the line right below it.
+fn attach_comments(declarations: &mut [Declaration], comments: &[(u32, String)]) {
+    for declaration in declarations {
+        let row = declaration.base().location.start.row;
+        let end = comments.partition_point(|(r, _)| *r < row);
+        let mut begin = end;
+        while begin > 0 && comments[begin - 1].0 + (end - begin + 1) as u32 == row {
+            begin -= 1;
+        }
+        declaration.base_mut().comments = comments[begin..end]
+            .iter()
+            .map(|(_, text)| text.clone())
+            .collect();
+
+        match declaration {
+            Declaration::ClassDef(class_def) => {
+                attach_comments(&mut class_def.declarations, comments)
+            }
+            Declaration::FuncDef(func_def) => attach_comments(&mut func_def.declarations, comments),
+            _ => (),
+        }
+    }
+}
+
+pub fn parse(get_token: impl Iterator<Item = ComplexToken>, class_const: bool) -> Program {
+    let parser = Parser::new(get_token, class_const);
     parser.parse_program()
 }
@@ -8,8 +8,8 @@ pub enum Token {
     Indent,
     Dedent,
     Badent,
-    Number(i32),
-    BadNumber,
+    // Raw value, range checked by the parser. Saturates instead of overflowing
+    Number(i64),
     Identifier(String),
     IdString(String),
     StringLiteral(String),
@@ -70,8 +70,16 @@ pub enum Token {
     Colon,
     Dot,
     Arrow,
+    // Extension: integer bitwise operators
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
 
     Unrecognized(String),
+    // Full-line comment text after '#'. Only produced when comments are kept
+    Comment(String),
     Eof,
 }
 
@@ -160,6 +168,23 @@ pub static OPERATORS: Lazy<HashMap<char, HashMap<char, Token>>> = Lazy::new(|| {
     .collect()
 });
 
+// OPERATORS plus the bitwise operators from the extension
+pub static BITWISE_OPERATORS: Lazy<HashMap<char, HashMap<char, Token>>> = Lazy::new(|| {
+    let mut operators = OPERATORS.clone();
+    operators.insert('&', vec![('\0', Token::BitAnd)].into_iter().collect());
+    operators.insert('|', vec![('\0', Token::BitOr)].into_iter().collect());
+    operators.insert('^', vec![('\0', Token::BitXor)].into_iter().collect());
+    operators
+        .get_mut(&'<')
+        .unwrap()
+        .insert('<', Token::ShiftLeft);
+    operators
+        .get_mut(&'>')
+        .unwrap()
+        .insert('>', Token::ShiftRight);
+    operators
+});
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct ComplexToken {
     pub token: Token,
@@ -0,0 +1,235 @@
+// Interactive mode.
+//
+// Reads one declaration or statement at a time, checks it along with all
+// declarations accepted before, and runs it with the interpreter so that
+// global variables, functions and classes persist across inputs.
+// A line ending with ':' starts a block, which ends at the next empty line.
+
+use crate::interp::{Interpreter, Value};
+use crate::node::*;
+use std::io::{BufRead, Write};
+
+// Whether the line ends with ':' ignoring comments
+fn opens_block(line: &str) -> bool {
+    let mut in_string = false;
+    let mut code = line;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => in_string = !in_string,
+            // The escaped character can't end the string
+            '\\' if in_string => {
+                chars.next();
+            }
+            '#' if !in_string => {
+                code = &line[..i];
+                break;
+            }
+            _ => (),
+        }
+    }
+    code.trim_end().ends_with(':')
+}
+
+// Python-style representation of a value. None is not shown
+fn repr(value: &Value) -> Option<String> {
+    Some(match value {
+        Value::None => return None,
+        Value::Int(i) => i.to_string(),
+        Value::Bool(true) => "True".to_owned(),
+        Value::Bool(false) => "False".to_owned(),
+        Value::Str(s) => format!("'{}'", String::from_utf8_lossy(s)),
+        Value::List(l) => {
+            let elements: Vec<_> = l
+                .borrow()
+                .iter()
+                .map(|element| repr(element).unwrap_or_else(|| "None".to_owned()))
+                .collect();
+            format!("[{}]", elements.join(", "))
+        }
+        Value::Object(o) => format!("<{} object>", o.class_name()),
+        Value::Func(f) => format!("<function {}>", f.name()),
+    })
+}
+
+fn print_messages(console: &mut dyn Write, messages: &[CompilerError], prefix: &str) {
+    for message in messages {
+        let start = message.base.location.start;
+        let _ = writeln!(
+            console,
+            "{}, {}: {}{}",
+            start.row,
+            start.col,
+            prefix,
+            message.display_message()
+        );
+    }
+}
+
+// Read lines until a complete declaration or statement
+fn read_source(interpreter: &mut Interpreter, console: &mut dyn Write) -> Option<String> {
+    let _ = write!(console, ">>> ");
+    let _ = console.flush();
+    let line = interpreter.read_line()?;
+    let mut source = line.clone() + "\n";
+    if opens_block(&line) {
+        loop {
+            let _ = write!(console, "... ");
+            let _ = console.flush();
+            match interpreter.read_line() {
+                Some(line) if !line.trim().is_empty() => {
+                    source += &line;
+                    source.push('\n');
+                }
+                _ => break,
+            }
+        }
+    }
+    Some(source)
+}
+
+pub fn run(
+    options: crate::parse::Options,
+    check_options: crate::check::Options,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    console: &mut dyn Write,
+) {
+    let mut interpreter = Interpreter::new(input, output);
+    // Declarations accepted so far, before type checking
+    let mut declarations: Vec<Declaration> = vec![];
+
+    while let Some(source) = read_source(&mut interpreter, console) {
+        if source.trim().is_empty() {
+            continue;
+        }
+
+        let ast = crate::parse::process_str(&source, options);
+        if !ast.errors.errors.is_empty() {
+            print_messages(console, &ast.errors.errors, "");
+            continue;
+        }
+
+        let new_declarations = ast.declarations.clone();
+        let mut ast = crate::check::check(
+            Program {
+                declarations: declarations
+                    .iter()
+                    .cloned()
+                    .chain(ast.declarations)
+                    .collect(),
+                ..ast
+            },
+            check_options,
+        );
+        crate::check::friendly(&mut ast);
+        print_messages(console, &ast.errors.errors, "");
+        print_messages(console, &ast.errors.warnings, "warning: ");
+        if !ast.errors.errors.is_empty() {
+            continue;
+        }
+
+        interpreter.declare(&ast.declarations[declarations.len()..]);
+        declarations.extend(new_declarations);
+
+        // A single expression shows its value
+        let result = if let [Stmt::ExprStmt(s)] = ast.statements.as_slice() {
+            interpreter.eval(&s.expr).map(|value| {
+                if let Some(text) = repr(&value) {
+                    let _ = writeln!(interpreter.output(), "{}", text);
+                }
+            })
+        } else {
+            interpreter.exec(&ast.statements)
+        };
+        if let Err(e) = result {
+            let _ = writeln!(console, "{} (error code {})", e, e.code);
+        }
+        let _ = interpreter.output().flush();
+    }
+    let _ = writeln!(console);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(lines: &str) -> (String, String) {
+        let mut input = lines.as_bytes();
+        let mut output = vec![];
+        let mut console = vec![];
+        run(
+            Default::default(),
+            Default::default(),
+            &mut input,
+            &mut output,
+            &mut console,
+        );
+        (
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(console).unwrap(),
+        )
+    }
+
+    #[test]
+    fn opens_block_ignores_comments() {
+        assert!(opens_block("while x: "));
+        assert!(opens_block("if x: # comment"));
+        assert!(!opens_block("print(\"a:\")"));
+        assert!(!opens_block("x = 1 # end:"));
+        assert!(opens_block("if s == \"#\":"));
+        assert!(!opens_block("s = \"\\\"\" # :"));
+        assert!(!opens_block("print(\"\\\"#:\")"));
+        assert!(opens_block("if s == \"\\\\\": # \""));
+    }
+
+    #[test]
+    fn scripted() {
+        let lines = "\
+x:int = 5
+def square(n:int) -> int:
+    return n * n
+
+class Counter(object):
+    n:int = 0
+    def inc(self:\"Counter\") -> int:
+        self.n = self.n + 1
+        return self.n
+
+c:Counter = None
+i:int = 0
+c = Counter()
+c.inc()
+c.inc()
+square(x) + 1
+x = x + 1
+print(square(x))
+for i in [1, 2]:
+    print(i)
+
+[x, 2]
+\"ab\" + \"c\"
+c
+y + 1
+x:int = 0
+x // 0
+print(x)
+input()
+typed line
+";
+        let (output, console) = session(lines);
+        assert_eq!(
+            output,
+            "1\n2\n26\n36\n1\n2\n[6, 2]\n'abc'\n<Counter object>\n6\n'typed line'\n"
+        );
+        assert_eq!(
+            console.replace(">>> ", "").replace("... ", ""),
+            "1, 1: [E-UNDEFINED-VAR] Not a variable: y\n\
+             1, 1: Cannot apply operator `+` on types `object` and `int`\n\
+             1, 1: Duplicate declaration of identifier in same scope: x\n\
+             1, 1: warning: Division by zero always fails at runtime\n\
+             Division by zero (error code 2)\n\
+             \n"
+        );
+    }
+}
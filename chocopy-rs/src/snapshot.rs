@@ -0,0 +1,125 @@
+// Support for tests that compare output against reference JSON files next to the test programs.
+//
+// A mismatch is shown as the first JSON path where the values differ. With
+// CHOCOPY_UPDATE_SNAPSHOTS=1, the reference files of mismatches are rewritten with the actual
+// output instead, except for those under test/original, which are output of the reference
+// compiler from the course.
+
+use crate::conformance::first_difference;
+use serde_json::Value;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+
+pub const UPDATE_VAR: &str = "CHOCOPY_UPDATE_SNAPSHOTS";
+
+// Fixtures from the original course project, never rewritten
+const ORIGINAL_DIR: &str = "test/original";
+
+// Files directly in the directory with names ending with the extension, in order
+pub fn files(dir: &str, extension: &str) -> Vec<PathBuf> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|f| f.unwrap().path())
+        .filter(|f| f.to_str().unwrap().ends_with(extension))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+// The path with the suffix added to its file name, as from `a.py` to `a.py.ast`
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap().to_owned();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+pub fn updatable(path: &Path) -> bool {
+    !path.starts_with(ORIGINAL_DIR)
+}
+
+pub struct Snapshots {
+    update: bool,
+    passed: bool,
+}
+
+impl Snapshots {
+    pub fn new() -> Snapshots {
+        Snapshots::with_update(std::env::var_os(UPDATE_VAR).is_some_and(|update| update == "1"))
+    }
+
+    pub fn with_update(update: bool) -> Snapshots {
+        Snapshots {
+            update,
+            passed: true,
+        }
+    }
+
+    pub fn start(&self, path: &Path) {
+        print!("Testing {} ---- ", path.display());
+        stdout().flush().unwrap();
+    }
+
+    // Compare the part of the reference and of the actual output that the test checks.
+    // On a mismatch, the whole `output` is written to the reference file if updates are requested.
+    pub fn compare(&mut self, reference: &Path, expected: &Value, actual: &Value, output: &Value) {
+        let difference = if let Some(difference) = first_difference(expected, actual, "") {
+            difference
+        } else {
+            println!("\x1b[32mOK\x1b[0m");
+            return;
+        };
+        if self.update && updatable(reference) {
+            let json = serde_json::to_string_pretty(output).unwrap() + "\n";
+            std::fs::write(reference, json).unwrap();
+            println!("\x1b[33mUpdated\x1b[0m");
+        } else {
+            self.fail(&format!("at {}", difference));
+        }
+    }
+
+    pub fn fail(&mut self, message: &str) {
+        println!("\x1b[31mError\x1b[0m {}", message);
+        self.passed = false;
+    }
+
+    pub fn passed(&self) -> bool {
+        self.passed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn update_flow() {
+        let reference = chocopy_rs_common::temp::temp_path(".ast.typed");
+        std::fs::write(&reference, "{\"kind\": \"Program\", \"errors\": []}").unwrap();
+        let expected: Value = serde_json::from_slice(&std::fs::read(&reference).unwrap()).unwrap();
+        let actual = json!({"kind": "Program", "errors": ["new"]});
+
+        let mut snapshots = Snapshots::with_update(false);
+        snapshots.compare(&reference, &expected, &actual, &actual);
+        assert!(!snapshots.passed());
+
+        // The reference is rewritten, and then matches
+        let mut snapshots = Snapshots::with_update(true);
+        snapshots.compare(&reference, &expected, &actual, &actual);
+        assert!(snapshots.passed());
+        let updated: Value = serde_json::from_slice(&std::fs::read(&reference).unwrap()).unwrap();
+        assert_eq!(updated, actual);
+        let mut snapshots = Snapshots::with_update(false);
+        snapshots.compare(&reference, &updated, &actual, &actual);
+        assert!(snapshots.passed());
+        std::fs::remove_file(reference).unwrap();
+
+        // Output of the reference compiler is kept as it is
+        let original = Path::new("test/original/pa2/no_such_file.py.ast.typed");
+        assert!(!updatable(original));
+        let mut snapshots = Snapshots::with_update(true);
+        snapshots.compare(original, &expected, &actual, &actual);
+        assert!(!snapshots.passed());
+        assert!(!original.exists());
+    }
+}
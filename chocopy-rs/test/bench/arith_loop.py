@@ -0,0 +1,15 @@
+# Integer arithmetic in a tight loop
+def checksum(n:int) -> int:
+    i:int = 0
+    s:int = 0
+    while i < n:
+        s = (s + (i % 1000) * (i % 1000) % 7 - i // 3) % 1000003
+        i = i + 1
+    return s
+
+print(checksum(3000000))
+
+#!
+#<->#
+#2964
+#<->#
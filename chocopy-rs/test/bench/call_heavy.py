@@ -0,0 +1,52 @@
+# Short calls between functions that are far apart in the source, for --reorder-functions
+def step(x:int) -> int:
+    return (x * 31 + 7) % 65521
+
+def report(l:[int]) -> str:
+    s:str = ""
+    x:int = 0
+    for x in l:
+        if x % 3 == 0:
+            s = s + "a"
+        elif x % 3 == 1:
+            s = s + "b"
+        else:
+            s = s + "c"
+    return s
+
+def mix(x:int, y:int) -> int:
+    return (step(x) + step(y)) % 65521
+
+def histogram(l:[int]) -> [int]:
+    h:[int] = None
+    x:int = 0
+    i:int = 0
+    h = [0, 0, 0, 0, 0, 0, 0, 0]
+    for x in l:
+        i = x % 8
+        h[i] = h[i] + 1
+        if h[i] > 100:
+            h[i] = h[i] - 100
+    return h
+
+def turn(acc:int, i:int) -> int:
+    return mix(acc, i) // 2 + mix(i, acc) // 2
+
+def run(n:int) -> int:
+    i:int = 0
+    acc:int = 1
+    while i < n:
+        acc = turn(acc, i)
+        i = i + 1
+    return acc
+
+print(run(3000000))
+print(report([1, 2, 3]))
+print(histogram([1, 9, 17, 4])[1])
+
+#!
+#<->#
+#40716
+#bca
+#3
+#<->#
@@ -0,0 +1,79 @@
+# Virtual calls over objects of many classes, for --group-prototypes
+class Shape(object):
+    n:int = 0
+    def area(self:"Shape") -> int:
+        return 0
+    def grow(self:"Shape", k:int) -> object:
+        self.n = (self.n + k) % 1000
+    def kind(self:"Shape") -> int:
+        return 0
+
+class Square(Shape):
+    def area(self:"Square") -> int:
+        return self.n * self.n
+    def kind(self:"Square") -> int:
+        return 1
+
+class Rect(Shape):
+    m:int = 3
+    def area(self:"Rect") -> int:
+        return self.n * self.m
+    def kind(self:"Rect") -> int:
+        return 2
+
+class Tri(Shape):
+    def area(self:"Tri") -> int:
+        return self.n * self.n // 2
+    def kind(self:"Tri") -> int:
+        return 3
+
+class Line(Shape):
+    def grow(self:"Line", k:int) -> object:
+        self.n = (self.n + 2 * k) % 1000
+    def kind(self:"Line") -> int:
+        return 4
+
+class Dot(Shape):
+    def grow(self:"Dot", k:int) -> object:
+        pass
+    def kind(self:"Dot") -> int:
+        return 5
+
+def make(i:int) -> Shape:
+    if i % 6 == 0:
+        return Square()
+    elif i % 6 == 1:
+        return Rect()
+    elif i % 6 == 2:
+        return Tri()
+    elif i % 6 == 3:
+        return Line()
+    elif i % 6 == 4:
+        return Dot()
+    return Shape()
+
+def run(shapes:[Shape], rounds:int) -> int:
+    acc:int = 0
+    r:int = 0
+    s:Shape = None
+    while r < rounds:
+        for s in shapes:
+            s.grow(r)
+            acc = (acc + s.area() + s.kind()) % 1000003
+        r = r + 1
+    return acc
+
+shapes:[Shape] = None
+i:int = 0
+shapes = []
+while i < 60:
+    shapes = shapes + [make(i)]
+    i = i + 1
+print(run(shapes, 30000))
+print(shapes[7].kind())
+
+#!
+#<->#
+#549963
+#2
+#<->#
@@ -0,0 +1,44 @@
+# List construction, iteration and in-place sorting
+def make(n:int) -> [int]:
+    l:[int] = None
+    i:int = 0
+    l = []
+    while i < n:
+        l = l + [(i * 7919) % 1009]
+        i = i + 1
+    return l
+
+def sort(l:[int]) -> object:
+    i:int = 0
+    j:int = 0
+    t:int = 0
+    while i < len(l):
+        j = len(l) - 1
+        while j > i:
+            if l[j] < l[j - 1]:
+                t = l[j]
+                l[j] = l[j - 1]
+                l[j - 1] = t
+            j = j - 1
+        i = i + 1
+
+def total(l:[int]) -> int:
+    s:int = 0
+    x:int = 0
+    for x in l:
+        s = s + x
+    return s
+
+l:[int] = None
+l = make(2000)
+sort(l)
+print(l[0])
+print(l[len(l) - 1])
+print(total(l))
+
+#!
+#<->#
+#0
+#1008
+#1008062
+#<->#
@@ -0,0 +1,21 @@
+# Deep recursion and many short calls
+def fib(n:int) -> int:
+    if n < 2:
+        return n
+    return fib(n - 1) + fib(n - 2)
+
+def ackermann(m:int, n:int) -> int:
+    if m == 0:
+        return n + 1
+    if n == 0:
+        return ackermann(m - 1, 1)
+    return ackermann(m - 1, ackermann(m, n - 1))
+
+print(fib(30))
+print(ackermann(2, 300))
+
+#!
+#<->#
+#832040
+#603
+#<->#
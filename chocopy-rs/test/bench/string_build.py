@@ -0,0 +1,27 @@
+# Repeated string concatenation and indexing
+def build(n:int) -> str:
+    s:str = ""
+    i:int = 0
+    while i < n:
+        s = s + "abcdefghij"[i % 10]
+        i = i + 1
+    return s
+
+def count(s:str, c:str) -> int:
+    k:int = 0
+    x:str = ""
+    for x in s:
+        if x == c:
+            k = k + 1
+    return k
+
+s:str = ""
+s = build(20000)
+print(len(s))
+print(count(s, "c"))
+
+#!
+#<->#
+#20000
+#2000
+#<->#
@@ -0,0 +1,26 @@
+class Item(object):
+    name:str = ""
+    price:int = 0
+
+    def total(self:"Item", count:int) -> int:
+        return self.price * count
+
+class Cart(object):
+    items:[Item] = None
+
+    def add(self:"Cart", item:Item) -> object:
+        self.items = self.items + [item]
+
+def checkout(cart:Cart, discount:int) -> int:
+    paid:int = 0
+    def pay(amount:int) -> object:
+        nonlocal paid
+        paid = paid + amount - discount
+    return paid
+
+cart:Cart = None
+item:Item = None
+cart = Cart()
+item = Item()
+cart.add(item)
+print(item.total(2))
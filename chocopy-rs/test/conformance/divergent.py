@@ -0,0 +1,3 @@
+x:int = 1
+x:int = 2
+print(x)
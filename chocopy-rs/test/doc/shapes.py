@@ -0,0 +1,33 @@
+# Geometry helpers
+
+# A shape with a name.
+# Subclasses override area().
+class Shape(object):
+    # Display name
+    name:str = "shape"
+    sides:int = 0 # not a doc comment
+
+    # Area in square units
+    def area(self:"Shape") -> int:
+        return 0
+
+class Square(Shape):
+    # Length of each side
+    size:int = 1
+
+    def area(self:"Square") -> int:
+        return self.size * self.size
+
+#Sum of the areas
+#  of all shapes
+def total(shapes:[Shape]) -> int:
+    s:int = 0
+    shape:Shape = None
+    for shape in shapes:
+        s = s + shape.area()
+    return s
+
+def describe(s:Shape, verbose:bool) -> object:
+    print(s.name)
+
+print(total([Shape(), Square()]))
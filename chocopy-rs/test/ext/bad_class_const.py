@@ -0,0 +1,12 @@
+class Counter(object):
+    CONST limit:int = 10
+    n:int = 0
+
+    def reset(self:"Counter") -> object:
+        self.limit = 0
+
+c:Counter = None
+c = Counter()
+Counter.limit = 5
+c.limit = c.limit + 1
+c.n = Counter.limit
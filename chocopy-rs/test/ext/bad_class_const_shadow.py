@@ -0,0 +1,10 @@
+class Base(object):
+    CONST size:int = 1
+
+class Derived(Base):
+    size:int = 2
+
+class Other(Base):
+    CONST size:int = 3
+
+print(Derived.size)
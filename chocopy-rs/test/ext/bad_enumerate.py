@@ -0,0 +1,14 @@
+x:int = 0
+s:str = ""
+b:bool = False
+
+for x, s in [1, 2]:
+    pass
+for s, x in enumerate([1, 2]):
+    pass
+for b, x in enumerate([1, 2]):
+    pass
+for x, s in enumerate("ab", 1):
+    pass
+for x, y in enumerate("ab"):
+    pass
@@ -0,0 +1,23 @@
+def f(x:int) -> int:
+    return x
+
+def outer() -> int:
+    def inner(x:int) -> int:
+        return x
+    h:Callable[[int], int] = None
+    h = inner
+    return h(0)
+
+g:Callable[[int], int] = None
+s:Callable[[str], int] = None
+o:object = None
+
+g = f
+s = f
+g = len
+o = object
+f = g
+g("x")
+g(1, 2)
+o(1)
+print(g.x)
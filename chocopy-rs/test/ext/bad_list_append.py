@@ -0,0 +1,6 @@
+x:[int] = None
+y:object = None
+x = append(x, True)
+x = append(x)
+y = append(y, 1)
+x = append([], "s")
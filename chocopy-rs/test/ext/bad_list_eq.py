@@ -0,0 +1,7 @@
+x:[[int]] = None
+y:[object] = None
+print(x == [[1], [2]])
+print(y == y)
+print([1] == [True])
+print([1] == None)
+print([1] == 1)
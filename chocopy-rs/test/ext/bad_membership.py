@@ -0,0 +1,20 @@
+class Point(object):
+    x:int = 0
+
+x:int = 0
+b:bool = False
+o:object = None
+ints:[int] = None
+objects:[object] = None
+
+b = "a" in ints
+b = 1 in objects
+b = o in ints
+b = 1 in "abc"
+b = "a" in 1
+b = None in ints
+x = count(ints, True)
+x = count(["a"], 1)
+x = count(ints)
+for x in ints in [ints]:
+    pass
@@ -0,0 +1,35 @@
+def hash(s:str) -> int:
+    h:int = 5381
+    c:str = ""
+    for c in s:
+        h = ((h << 5) + h) ^ len(c) * 97
+    return h & 65535
+
+x:int = 12
+y:int = 0
+n:int = 0
+
+y = -7
+
+print(x & 10)
+print(x | 3)
+print(x ^ 5)
+print(y & 255)
+print(y | 1)
+print(y ^ -1)
+print(1 << 0)
+print(1 << 31)
+print(1 << 32)
+print(y << 3)
+print(y >> 0)
+print(y >> 1)
+print(y >> 31)
+print(y >> 32)
+print(x >> 40)
+print(1 + 2 << 3)
+print(x & 6 == 4)
+print(1 | 2 ^ 3 & 4)
+print(-x >> 2)
+print(x << 2 - 1)
+print(hash("chocopy"))
+n = 1 << -1
@@ -0,0 +1,23 @@
+class Shape(object):
+    CONST sides:int = 4
+    CONST kind:str = "square"
+    CONST closed:bool = True
+    scale:int = 1
+
+    def perimeter(self:"Shape", length:int) -> int:
+        return self.sides * length * self.scale
+
+class Big(Shape):
+    CONST factor:int = 10
+
+    def __init__(self:"Big"):
+        self.scale = Big.factor
+
+s:Shape = None
+print(Shape.kind)
+print(Big.closed)
+s = Shape()
+print(s.perimeter(3))
+s = Big()
+print(s.perimeter(3))
+print(Big.sides + Big.factor)
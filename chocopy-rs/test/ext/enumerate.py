@@ -0,0 +1,35 @@
+def total(items:[int]) -> int:
+    i:int = 0
+    x:int = 0
+    s:int = 0
+    for i, x in enumerate(items):
+        s = s + i * x
+    return s
+
+i:int = 0
+c:str = ""
+w:str = ""
+o:object = None
+words:[str] = None
+
+for i, c in enumerate("abc"):
+    print(i)
+    print(c)
+words = ["zero", "one", "two"]
+for i, w in enumerate(words):
+    words[i] = w + "!"
+print(words[2])
+print(total([5, 6, 7]))
+
+# The index can be boxed into an object
+for o, w in enumerate(words):
+    print(o)
+print(i)
+
+# The single-variable form is unchanged
+for i in [4, 5]:
+    print(i)
+
+words = None
+for i, w in enumerate(words):
+    print(w)
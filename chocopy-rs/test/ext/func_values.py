@@ -0,0 +1,57 @@
+def square(x:int) -> int:
+    return x * x
+
+def negate(x:int) -> int:
+    return -x
+
+def shout(s:str) -> str:
+    return s + "!"
+
+def show(x:int):
+    print(x * 10)
+
+def apply(f:Callable[[int], int], x:int) -> int:
+    return f(x)
+
+def map_list(f:Callable[[int], int], items:[int]) -> [int]:
+    result:[int] = None
+    i:int = 0
+    result = []
+    for i in items:
+        result = result + [f(i)]
+    return result
+
+def each(f:Callable[[int], None], items:[int]):
+    i:int = 0
+    for i in items:
+        f(i)
+
+def twice(f:Callable[[str], str], s:str) -> str:
+    return f(f(s))
+
+def compose_apply(f:Callable[[int], int], g:Callable[[int], int], x:int) -> int:
+    def inner(y:int) -> int:
+        return f(g(y))
+    return inner(x)
+
+g:Callable[[int], int] = None
+o:object = None
+i:int = 0
+
+print(apply(square, 5))
+print(apply(negate, 5))
+for i in map_list(square, [1, 2, 3]):
+    print(i)
+print(twice(shout, "hi"))
+each(show, [1, 2])
+print(compose_apply(negate, square, 3))
+
+# Function values can be stored and compared by identity
+g = square
+print(g(7))
+o = g
+print(o is g)
+print(square is square)
+
+g = None
+print(g(1))
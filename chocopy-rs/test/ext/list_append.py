@@ -0,0 +1,61 @@
+class Point(object):
+    x:int = 0
+
+def squares(n:int) -> [int]:
+    result:[int] = None
+    i:int = 0
+    result = []
+    while i < n:
+        result = append(result, i * i)
+        i = i + 1
+    return result
+
+x:int = 0
+total:int = 0
+s:str = ""
+flags:[bool] = None
+words:[str] = None
+points:[Point] = None
+p:Point = None
+mixed:[object] = None
+before:[int] = None
+after:[int] = None
+
+for x in squares(100):
+    total = total + x
+print(total)
+print(len(squares(100)))
+
+flags = append(append([], True), False)
+print(flags[0])
+print(flags[1])
+
+words = ["a"]
+for x in [1, 2, 3]:
+    words = append(words, words[len(words) - 1] + "b")
+for s in words:
+    print(s)
+
+points = []
+for x in squares(5):
+    p = Point()
+    p.x = x
+    points = append(points, p)
+print(points[4].x)
+
+mixed = []
+mixed = append(mixed, 1)
+mixed = append(mixed, "two")
+mixed = append(mixed, None)
+print(len(mixed))
+
+# The original list is unchanged
+before = [1, 2]
+after = append(before, 3)
+after[0] = 5
+print(len(before))
+print(before[0])
+print(after[2])
+
+mixed = None
+mixed = append(mixed, 1)
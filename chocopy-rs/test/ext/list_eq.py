@@ -0,0 +1,25 @@
+def same(a:[int], b:[int]) -> bool:
+    return a == b
+
+x:[int] = None
+y:[int] = None
+s:[str] = None
+print([1, 2, 3] == [1, 2, 3])
+print([1, 2, 3] == [1, 2, 4])
+print([1, 2] == [1, 2, 3])
+print([1, 2, 3] != [1, 2])
+print([] == [1])
+print([True, False] == [True, False])
+print([True] != [False])
+print(["ab", "c"] == ["ab", "c"])
+print(["ab", "c"] == ["ab", "d"])
+print(["ab", ""] == ["a", "b"])
+print(x == y)
+x = [5]
+print(x == y)
+print(x == x)
+y = [5]
+print(same(x, y))
+s = ["", "x"]
+print(s == ["", "x"])
+print([[1], [2]][0] == [1])
@@ -0,0 +1,59 @@
+class Point(object):
+    x:int = 0
+
+def unique(items:[int]) -> [int]:
+    result:[int] = None
+    x:int = 0
+    result = []
+    for x in items:
+        if not x in result:
+            result = result + [x]
+    return result
+
+ints:[int] = None
+words:[str] = None
+points:[Point] = None
+nested:[[int]] = None
+p:Point = None
+q:Point = None
+s:str = "chocolate"
+
+ints = [3, 1, 4, 1, 5, 9, 2, 6]
+print(4 in ints)
+print(7 in ints)
+print(not 7 in ints)
+print(1 + 1 in ints)
+print(True in [False, False])
+print(False in [False, True])
+print(1 in [])
+
+words = ["cho", "co", "py"]
+print("co" in words)
+print("c" + "o" in words)
+print("coco" in words)
+print("" in words)
+
+print("late" in s)
+print("choc" in s)
+print("e" in s)
+print("" in s)
+print("coat" in s)
+print(s + "!" in s)
+
+p = Point()
+q = Point()
+points = [p, None]
+print(p in points)
+print(q in points)
+print(None in points)
+nested = [ints]
+print(ints in nested)
+print([3] in nested)
+
+print(count(ints, 1))
+print(count(ints, 8))
+print(count([], 0))
+print(len(unique(ints)))
+
+ints = None
+print(2 in ints)
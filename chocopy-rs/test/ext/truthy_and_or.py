@@ -0,0 +1,51 @@
+class A(object):
+    pass
+
+def loud(v:int) -> int:
+    print("evaluated")
+    return v
+
+x:object = None
+e:[int] = None
+l:[int] = None
+
+# int is true unless 0
+print(0 or 5)
+print(3 or 5)
+print(0 and 5)
+print(3 and 5)
+
+# bool mixed with other types gives object
+x = False or "b"
+print(x)
+x = True and 7
+print(x)
+x = True or 7
+print(x)
+
+# str is true unless empty
+print("" or "empty")
+print("a" or "empty")
+print("" and "x")
+print(len("a" and "bc"))
+
+# None is false
+x = None or 1
+print(x)
+print((None and "x") is None)
+
+# list is true unless empty
+e = []
+l = [1, 2]
+print(len(e or l))
+print(len(l or e))
+print(len(e and l))
+
+# Other objects are true
+print((A() or None) is None)
+print((None or A()) is None)
+
+# The right operand is not evaluated if the left one decides
+print(1 or loud(2))
+print(0 and loud(2))
+print(0 or loud(2))
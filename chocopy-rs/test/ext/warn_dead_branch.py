@@ -0,0 +1,31 @@
+x:int = 0
+
+def f(y:int) -> int:
+    if False:
+        return 1
+    return y
+
+if True:
+    x = 1
+else:
+    x = 2
+
+if x > 0:
+    x = 3
+elif True:
+    x = 4
+elif x < 0:
+    x = 5
+else:
+    x = 6
+
+if x > 0:
+    x = 7
+elif False:
+    x = 8
+
+while True:
+    if True:
+        x = 9
+    print(f(x))
+    x = x // 0
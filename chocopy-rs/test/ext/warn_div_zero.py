@@ -0,0 +1,8 @@
+x:int = 5
+
+def f(y:int) -> int:
+    return y % 0
+
+print(x // 0)
+print(x % 0)
+print(x // 2 + f(x))
@@ -0,0 +1,30 @@
+class A(object):
+    def name(self:"A") -> str:
+        return "A"
+
+class B(object):
+    def name(self:"B") -> str:
+        return "B"
+
+# Meant to inherit from A
+class C(B):
+    pass
+
+class Holder(object):
+    a:A = None
+
+    def set(self:"Holder", a:A) -> object:
+        self.a = a
+
+def describe(a:A) -> str:
+    return a.name()
+
+def make() -> A:
+    return C()
+
+a:A = None
+h:Holder = None
+a = C()
+describe(C())
+h = Holder()
+h.set(C())
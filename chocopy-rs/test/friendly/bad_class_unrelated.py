@@ -0,0 +1,14 @@
+class A(object):
+    x:int = 0
+
+class D(object):
+    y:int = 0
+
+def f(a:A) -> int:
+    return a.x
+
+a:A = None
+i:int = 0
+a = D()
+i = D()
+f(D())
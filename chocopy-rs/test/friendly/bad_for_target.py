@@ -0,0 +1,28 @@
+def numbers() -> [int]:
+    return [1, 2, 3]
+
+def f() -> object:
+    for f in "ab":
+        pass
+
+def g(s:str) -> object:
+    for c in s:
+        print(c)
+
+for x in numbers():
+    print(x)
+
+for y in "abc":
+    print(y)
+
+for z in [[1], [2]]:
+    pass
+
+for w in [undefined]:
+    pass
+
+for numbers in [1]:
+    pass
+
+for print in ["a"]:
+    pass
@@ -0,0 +1,37 @@
+class A(object):
+    def f(self:"A", x:int, y:[int]) -> object:
+        return None
+
+    def g(self:"A", x:int) -> int:
+        return x
+
+    def h(self:"A", x:int) -> int:
+        return x
+
+class B(A):
+    def __init__(self:"B"):
+        pass
+
+    # OK override
+    def f(self:"B", a:int, b:[int]) -> object:
+        return None
+
+    # Bad override
+    def g(self:"B", x:int, y:int) -> int:
+        return x
+
+class C(B):
+    # Bad override
+    def f(self:"C", x:int, y:[object]) -> object:
+        return None
+
+    # Bad override
+    def h(self:"C", x:int) -> bool:
+        return True
+
+class D(object):
+    # Bad override
+    def __init__(self:"D", x:int):
+        pass
+
+C()
@@ -0,0 +1,18 @@
+class Printer(object):
+    def print(self:"Printer", x:object) -> object:
+        print(x, x)
+
+def shadow() -> object:
+    def print(x:int, y:int) -> object:
+        pass
+    print(1, 2)
+    print(1)
+
+def inner() -> object:
+    def f() -> object:
+        print()
+    f()
+
+print()
+print("a", 1)
+print("b")
@@ -0,0 +1,6 @@
+x:int[] = None
+
+def f(y:bool[]) -> str[]:
+    return None
+
+x = [1, 2]
@@ -0,0 +1,2 @@
+x: int = 2147483647
+x = -2147483648
@@ -0,0 +1,5 @@
+x: int = 2147483648
+x = 2147483648
+x = -2147483649
+x = 123456789012345678901234567890
+x = -2147483648
@@ -0,0 +1,7 @@
+x: int = 0
+b: bool = False
+b = not not True
+x = - -1
+x = --x
+b = not -1
+b = not not not x == - - -x
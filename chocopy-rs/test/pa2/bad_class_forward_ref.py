@@ -0,0 +1,15 @@
+class A(object):
+    b:"B" = None
+    z:"Z" = None
+    def set_b(self:"A", b:"B") -> "B":
+        return b
+    def get_z(self:"A") -> "Z":
+        return None
+
+class B(A):
+    # The parameter type does not match, even though both classes are declared
+    def set_b(self:"B", b:"C") -> "B":
+        return self
+
+class C(object):
+    a:A = None
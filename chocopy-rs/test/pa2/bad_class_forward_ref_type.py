@@ -0,0 +1,17 @@
+class A(object):
+    b:"B" = None
+    def get_c(self:"A") -> "C":
+        return self.b
+
+class B(A):
+    c:"C" = None
+
+class C(object):
+    a:A = None
+
+a:A = None
+a = A()
+a.b = C()
+a.b.b.b = a
+a.b.c.a = a.b
+a.get_c().a = a.get_c()
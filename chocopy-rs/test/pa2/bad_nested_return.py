@@ -0,0 +1,10 @@
+def outer() -> str:
+    def inner() -> int:
+        def innermost() -> str:
+            return "z"
+        return "x"
+    def other() -> int:
+        return 1
+    return 2
+
+outer()
@@ -0,0 +1,10 @@
+class A(object):
+    pass
+
+a:A = None
+b:bool = False
+
+b = a == None
+b = None != a
+b = a == a
+b = a is None
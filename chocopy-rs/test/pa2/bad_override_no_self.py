@@ -0,0 +1,18 @@
+class A(object):
+    def f() -> int:
+        return 1
+
+    def g(self:"A") -> object:
+        return None
+
+class B(A):
+    # Inherits a method without parameters, which is already reported in A
+    def f() -> int:
+        return 2
+
+class C(B):
+    # Overrides a method of the grandparent. Return types can't be narrowed.
+    def g(self:"C") -> int:
+        return 1
+
+C().g()
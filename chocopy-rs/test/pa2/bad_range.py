@@ -0,0 +1,14 @@
+i:int = 0
+s:str = ""
+x:[int] = None
+for i in range("a"):
+    pass
+for i in range(1, 2, 3):
+    pass
+for i in range():
+    pass
+for s in range(3):
+    pass
+for i in range(1, True):
+    pass
+x = range(3)
@@ -0,0 +1,14 @@
+a:list = None
+b:List = None
+c:string = ""
+d:boolean = False
+e:integer = 0
+g:float = 0
+h:"None" = None
+i:Foo = None
+
+def f(x:[string]) -> integer:
+    y:boolean = True
+    return 0
+
+f(None)
@@ -0,0 +1,37 @@
+# Classes referring to classes declared later, in attribute types,
+# method parameter types and method return types
+class A(object):
+    b:"B" = None
+    bs:["B"] = None
+    def get_b(self:"A") -> "B":
+        return self.b
+    def set_b(self:"A", b:"B") -> "A":
+        self.b = b
+        return self
+    def first(self:"A", bs:["B"]) -> "B":
+        return bs[0]
+
+class B(A):
+    a:A = None
+    c:"C" = None
+    # Overrides with types of classes declared later
+    def set_b(self:"B", b:"B") -> "A":
+        self.a = b
+        return self
+    def to_c(self:"B", c:"C") -> "C":
+        return c
+
+class C(B):
+    def to_c(self:"C", c:"C") -> "C":
+        return self
+
+a:A = None
+b:B = None
+c:C = None
+a = A().set_b(B())
+b = a.get_b()
+c = C()
+b.c = c
+b = c.first([b, c])
+c = b.to_c(c).to_c(c)
+a = c.set_b(c)
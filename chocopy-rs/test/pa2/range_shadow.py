@@ -0,0 +1,6 @@
+def range(n:int) -> [int]:
+    return [n]
+
+i:int = 0
+for i in range(5):
+    print(i)
@@ -0,0 +1,76 @@
+# Two classes referring to each other, declared in either order,
+# forming a cyclic structure that is traversed
+class Left(object):
+    name:str = ""
+    right:"Right" = None
+    def next(self:"Left") -> "Right":
+        return self.right
+
+class Right(object):
+    name:str = ""
+    left:Left = None
+    def next(self:"Right") -> Left:
+        return self.left
+
+def ring(n:int) -> Left:
+    first:Left = None
+    left:Left = None
+    right:Right = None
+    i:int = 0
+    first = Left()
+    first.name = "L0"
+    left = first
+    while i < n:
+        right = Right()
+        right.name = "R" + str_int(i)
+        left.right = right
+        i = i + 1
+        if i < n:
+            left = Left()
+            left.name = "L" + str_int(i)
+            right.left = left
+    right.left = first
+    return first
+
+def str_int(i:int) -> str:
+    digits:str = "0123456789"
+    s:str = ""
+    if i == 0:
+        return "0"
+    while i > 0:
+        s = digits[i % 10] + s
+        i = i // 10
+    return s
+
+start:Left = None
+left:Left = None
+steps:int = 0
+start = ring(3)
+left = start
+while steps < 7:
+    print(left.name)
+    print(left.next().name)
+    left = left.next().next()
+    steps = steps + 1
+print(left is start)
+print(start.right.left.right.left.right.left is start)
+
+#!
+#<->#
+#L0
+#R0
+#L1
+#R1
+#L2
+#R2
+#L0
+#R0
+#L1
+#R1
+#L2
+#R2
+#L0
+#R0
+#False
+#True
+#<->#
@@ -0,0 +1,45 @@
+# Collecting garbage explicitly with collect(), while objects referenced
+# from the frames of the callers and from globals stay alive
+class Node(object):
+    value:int = 0
+    next:"Node" = None
+
+kept:Node = None
+
+def build(n:int) -> Node:
+    head:Node = None
+    node:Node = None
+    while n > 0:
+        node = Node()
+        node.value = n
+        node.next = head
+        head = node
+        n = n - 1
+    return head
+
+def total(head:Node) -> int:
+    sum:int = 0
+    while not (head is None):
+        sum = sum + head.value
+        head = head.next
+    return sum
+
+def check(local:Node) -> int:
+    garbage:Node = None
+    garbage = build(1000)
+    garbage = None
+    collect()
+    return total(local)
+
+kept = build(10)
+print(check(build(5)))
+collect()
+print(total(kept))
+print(collect() is None)
+
+#!
+#<->#
+#15
+#55
+#True
+#<->#
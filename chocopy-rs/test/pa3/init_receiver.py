@@ -0,0 +1,44 @@
+# Explicit __init__ calls on receivers of every kind of static type
+class A(object):
+    def __init__(self:"A"):
+        print("A.__init__")
+
+class B(A):
+    pass
+
+o:object = None
+a:A = None
+
+object().__init__()
+A().__init__()
+B().__init__()
+a = B()
+a.__init__()
+
+o = object()
+o.__init__()
+o = 1
+o.__init__()
+o = True
+o.__init__()
+o = "str"
+o.__init__()
+o = [1, 2]
+o.__init__()
+o = A()
+o.__init__()
+o = B()
+o.__init__()
+#!
+#<->#
+#A.__init__
+#A.__init__
+#A.__init__
+#A.__init__
+#A.__init__
+#A.__init__
+#A.__init__
+#A.__init__
+#A.__init__
+#A.__init__
+#<->#
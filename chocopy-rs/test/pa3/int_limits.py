@@ -0,0 +1,15 @@
+# The extreme int values written as literals
+x:int = 2147483647
+y:int = 0
+y = -2147483648
+print(x)
+print(y)
+print(-2147483648 + x)
+print(- -2147483647)
+#!
+#<->#
+#2147483647
+#-2147483648
+#-1
+#2147483647
+#<->#
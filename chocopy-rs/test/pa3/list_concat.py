@@ -0,0 +1,52 @@
+# Concatenation of lists with different element types, empty lists and None elements
+e:[int] = None
+m:[[int]] = None
+
+def show(l:[object]) -> object:
+    x:object = None
+    print(len(l))
+    for x in l:
+        if x is None:
+            print("None")
+        else:
+            print(x)
+
+e = []
+print(len(e + e))
+print((e + [1, 2])[1])
+print(([3, 4] + e)[0])
+show([None] + [None])
+show([None] + [1, 2])
+show([1] + [None, "a"])
+show(["b", None] + [True])
+show([True] + [5])
+m = [[1], None] + [[2, 3]]
+print(len(m[2]))
+print(m[1] is None)
+
+#!
+#<->#
+#0
+#2
+#3
+#2
+#None
+#None
+#3
+#None
+#1
+#2
+#3
+#1
+#None
+#a
+#3
+#b
+#None
+#True
+#2
+#True
+#5
+#2
+#True
+#<->#
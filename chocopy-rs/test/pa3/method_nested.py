@@ -0,0 +1,60 @@
+class Counter(object):
+    n:int = 0
+    name:str = "c"
+
+    def get(self:"Counter") -> int:
+        return self.n
+
+    def read(self:"Counter") -> int:
+        def inner() -> int:
+            return self.n * 10
+        return inner()
+
+    def call(self:"Counter") -> int:
+        def inner() -> int:
+            return self.get() + 1
+        return inner()
+
+    def bump(self:"Counter", k:int) -> object:
+        def inner(d:int) -> object:
+            self.n = self.n + d
+        inner(k)
+        inner(k)
+
+    def deep(self:"Counter", x:int) -> str:
+        y:int = 2
+        def mid(z:int) -> str:
+            def leaf() -> str:
+                self.n = self.n + x + y + z
+                return self.name
+            return leaf() + self.name
+        return mid(3)
+
+    def shadow(self:"Counter") -> int:
+        def inner(self:"Counter") -> int:
+            return self.n
+        other:Counter = None
+        other = Counter()
+        other.n = 100
+        return inner(other) + self.n
+
+c:Counter = None
+c = Counter()
+c.n = 4
+print(c.read())
+print(c.call())
+c.bump(3)
+print(c.n)
+print(c.deep(1))
+print(c.n)
+print(c.shadow())
+
+#!
+#<->#
+#40
+#5
+#10
+#cc
+#16
+#116
+#<->#
@@ -0,0 +1,65 @@
+# Assignments to variables of enclosing functions several levels up, and to
+# globals from nested functions. Recursion makes the static link chain differ
+# from the chain of callers.
+count:int = 0
+log:str = ""
+
+def outer(n:int) -> int:
+    total:int = 0
+    flag:bool = False
+    items:[int] = None
+    d:int = 0
+
+    def middle(k:int) -> int:
+        depth:int = 0
+        nonlocal items
+
+        def inner(j:int) -> object:
+            nonlocal depth
+
+            def innermost() -> object:
+                nonlocal total
+                nonlocal flag
+                global count
+                global log
+                total = total + j * k
+                flag = not flag
+                count = count + 1
+                log = log + "."
+
+            depth = depth + 1
+            innermost()
+            if j > 0:
+                inner(j - 1)
+
+        inner(k)
+        items = items + [depth]
+        return depth
+
+    items = []
+    while n > 0:
+        middle(n)
+        n = n - 1
+    for d in items:
+        print(d)
+    print(flag)
+    return total
+
+print(outer(3))
+print(outer(1))
+print(count)
+print(log)
+
+#!
+#<->#
+#4
+#3
+#2
+#True
+#25
+#2
+#False
+#1
+#11
+#...........
+#<->#
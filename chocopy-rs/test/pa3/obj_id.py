@@ -0,0 +1,31 @@
+# Object identities from obj_id, which stay the same while objects are
+# moved around and garbage is collected
+a:[int] = None
+b:[int] = None
+c:[int] = None
+garbage:[int] = None
+first:int = 0
+i:int = 0
+
+a = [1, 2]
+b = a
+c = [1, 2]
+print(obj_id(a) == obj_id(b))
+print(obj_id(a) == obj_id(c))
+print(obj_id(None))
+
+first = obj_id(a)
+while i < 1000:
+    garbage = [i, i]
+    i = i + 1
+print(obj_id(b) == first)
+print(obj_id(garbage) != first)
+
+#!
+#<->#
+#True
+#False
+#0
+#True
+#True
+#<->#
@@ -0,0 +1,64 @@
+# Printing objects through __str__, including through object-typed variables
+class Point(object):
+    x:int = 0
+    y:int = 0
+
+    def __init__(self:"Point"):
+        self.x = 1
+        self.y = 2
+
+    def __str__(self:"Point") -> str:
+        return "(" + self.coord(self.x) + ", " + self.coord(self.y) + ")"
+
+    def coord(self:"Point", v:int) -> str:
+        digits:str = "0123456789"
+        return digits[v]
+
+class Point3(Point):
+    z:int = 3
+
+    def __str__(self:"Point3") -> str:
+        return "(" + self.coord(self.x) + ", " + self.coord(self.y) + ", " + self.coord(self.z) + ")"
+
+class Plain(object):
+    pass
+
+class Hidden(Point):
+    # Point.__str__ calls this instead of Point.coord
+    def coord(self:"Hidden", v:int) -> str:
+        return "?"
+
+o:object = None
+p:Point = None
+
+print(Point())
+print(Point3())
+p = Point3()
+print(p)
+o = Point()
+print(o)
+print(p.__str__() + "!")
+p = Hidden()
+print(p)
+o = Hidden()
+print(o)
+o = 1
+print(o)
+o = "str"
+print(o)
+o = Plain()
+print(o)
+#!
+#<->#
+#(1, 2)
+#(1, 2, 3)
+#(1, 2, 3)
+#(1, 2)
+#(1, 2, 3)!
+#(?, ?)
+#(?, ?)
+#1
+#str
+#Invalid argument
+#Exited with error code 1
+#<->#
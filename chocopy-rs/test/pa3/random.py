@@ -0,0 +1,37 @@
+# Random numbers are the same in every run, and seed() restarts them
+def dice() -> int:
+    return randint(1, 6)
+
+def roll(n:int) -> object:
+    while n > 0:
+        print(dice())
+        n = n - 1
+
+roll(5)
+seed(2024)
+print(randint(-100, 100))
+print(randint(0, 1000000))
+
+# The numbers without calling seed() are those of seed(0)
+seed(0)
+roll(5)
+print(randint(7, 7))
+print(randint(2, 1))
+#!
+#<->#
+#1
+#2
+#4
+#3
+#5
+#-2
+#337216
+#1
+#2
+#4
+#3
+#5
+#7
+#Invalid argument
+#Exited with error code 1
+#<->#
@@ -0,0 +1,62 @@
+def total(n:int) -> int:
+    i:int = 0
+    s:int = 0
+    for i in range(n):
+        s = s + i
+    return s
+
+def count(n:int) -> int:
+    i:int = 0
+    c:int = 0
+    for i in range(n):
+        c = c + 1
+    return c
+
+def nested(a:int, b:int) -> int:
+    count:int = 0
+    def inner():
+        nonlocal count
+        j:int = 0
+        for j in range(a, b):
+            count = count + j
+    inner()
+    return count
+
+i:int = 42
+x:object = None
+print(total(10))
+print(count(1000000))
+print(nested(3, 7))
+for i in range(3, 6):
+    print(i)
+    i = i * 10
+print(i)
+for i in range(5, 5):
+    print("never")
+for i in range(7, 2):
+    print("never")
+for i in range(-3):
+    print("never")
+print(i)
+for x in range(-2, 1):
+    print(x)
+for i in range(len("abc")):
+    for i in range(i):
+        print(i)
+#!
+#<->#
+#45
+#1000000
+#18
+#3
+#4
+#5
+#50
+#50
+#-2
+#-1
+#0
+#0
+#0
+#1
+#<->#
@@ -0,0 +1,13 @@
+# Strings are UTF-8 bytes, so the second character of the input takes two
+# indices, and printing one of them alone shows U+FFFD
+s:str = ""
+t:str = ""
+c:str = ""
+s = input()
+print(s)
+print(len(s))
+print(s[1])
+print(s[1] + s[2])
+for c in s:
+    t = t + c
+print(t == s)